@@ -0,0 +1,114 @@
+mod common;
+
+use std::collections::HashMap;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use entrust::coder::CodecId;
+use entrust::histogram;
+use entrust::huffman::{ByteHuffman, HuffmanCode};
+
+const SIZES: [usize; 3] = [4 << 10, 64 << 10, 1 << 20];
+
+fn inputs(len: usize) -> [(&'static str, Vec<u8>); 2] {
+    [("text", common::corpus(len)), ("random", common::random_bytes(len))]
+}
+
+// Table construction alone, from counts already taken
+fn bench_build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    for (name, data) in inputs(64 << 10).iter() {
+        let mut freqs = HashMap::new();
+        for &b in data {
+            *freqs.entry(b).or_insert(0u64) += 1;
+        }
+        group.bench_with_input(BenchmarkId::new("huffman", name), &freqs, |b, freqs| {
+            b.iter(|| ByteHuffman::from_frequencies(freqs))
+        });
+        group.bench_with_input(BenchmarkId::new("length_limited", name), &freqs, |b, freqs| {
+            b.iter(|| HuffmanCode::with_max_length(freqs, 15))
+        });
+    }
+
+    // a large alphabet with Zipf-like counts, as word-level coding produces
+    let zipf: HashMap<u32, u64> = (0..4096u32).map(|sym| (sym, 1_000_000 / (sym as u64 + 1))).collect();
+    group.bench_function(BenchmarkId::new("huffman", "zipf4096"), |b| b.iter(|| HuffmanCode::from_frequencies(&zipf)));
+    group.bench_function(BenchmarkId::new("length_limited", "zipf4096"), |b| {
+        b.iter(|| HuffmanCode::with_max_length(&zipf, 15))
+    });
+    // encoding over an alphabet too big for the byte array goes through the map
+    let zipf_code = HuffmanCode::from_frequencies(&zipf);
+    let symbols: Vec<u32> = (0..65_536u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 20) % 4096 / (1 + i % 7)).collect();
+    group.bench_function(BenchmarkId::new("encode_map", "zipf4096"), |b| {
+        b.iter(|| zipf_code.encode_symbols_packed(&symbols))
+    });
+
+    // counting plus model building, per coder
+    let data = common::corpus(64 << 10);
+    for &codec in CodecId::ALL.iter() {
+        group.bench_function(BenchmarkId::new("train", codec), |b| b.iter(|| codec.train(&data)));
+    }
+    group.finish();
+}
+
+// The frequency pass of two-pass compression
+fn bench_count(c: &mut Criterion) {
+    let mut group = c.benchmark_group("count");
+    let len = 1 << 20;
+    group.throughput(Throughput::Bytes(len as u64));
+    let mut inputs = inputs(len).to_vec();
+    inputs.push(("runs", (0..len).map(|i| (i / 1000 % 4) as u8).collect()));
+    for (name, data) in inputs.iter() {
+        group.bench_with_input(BenchmarkId::new("count_bytes", name), data, |b, data| {
+            b.iter(|| histogram::count_bytes(data))
+        });
+        group.bench_with_input(BenchmarkId::new("naive", name), data, |b, data| {
+            b.iter(|| {
+                let mut counts = [0u64; 256];
+                for &byte in data.iter() {
+                    counts[byte as usize] += 1;
+                }
+                counts
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_encode(c: &mut Criterion) {
+    for &len in SIZES.iter() {
+        let mut group = c.benchmark_group(format!("encode_{}KiB", len >> 10));
+        group.throughput(Throughput::Bytes(len as u64));
+        group.sample_size(10);
+        for (name, data) in inputs(len).iter() {
+            for &codec in CodecId::ALL.iter() {
+                let coder = codec.train(data).unwrap();
+                group.bench_with_input(BenchmarkId::new(codec.name(), name), data, |b, data| {
+                    b.iter(|| coder.encode(data).unwrap())
+                });
+            }
+        }
+        group.finish();
+    }
+}
+
+fn bench_decode(c: &mut Criterion) {
+    for &len in SIZES.iter() {
+        let mut group = c.benchmark_group(format!("decode_{}KiB", len >> 10));
+        group.throughput(Throughput::Bytes(len as u64));
+        group.sample_size(10);
+        for (name, data) in inputs(len).iter() {
+            for &codec in CodecId::ALL.iter() {
+                let coder = codec.train(data).unwrap();
+                let bits = coder.encode(data).unwrap();
+                group.bench_with_input(BenchmarkId::new(codec.name(), name), &bits, |b, bits| {
+                    b.iter(|| coder.decode(bits).unwrap())
+                });
+            }
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_build, bench_count, bench_encode, bench_decode);
+criterion_main!(benches);
@@ -0,0 +1,34 @@
+// Inputs shared by the benchmarks
+
+// English-like text: words drawn with a skewed distribution
+pub fn corpus(len: usize) -> Vec<u8> {
+    let words = ["the", "of", "and", "a", "to", "in", "is", "you", "that", "it",
+                 "he", "was", "for", "on", "are", "as", "with", "his", "they", "compression"];
+    let mut rng = XorShift(2463534242);
+    let mut out = Vec::with_capacity(len + 16);
+    while out.len() < len {
+        let r = (rng.next() % 1000) as usize;
+        out.extend_from_slice(words[(r * r / 50_000).min(words.len() - 1)].as_bytes());
+        out.push(if r.is_multiple_of(11) { b'.' } else { b' ' });
+    }
+    out.truncate(len);
+    out
+}
+
+// Uniformly random bytes: all 256 symbols, nothing to gain
+#[allow(dead_code)]
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let mut rng = XorShift(0x9e3779b9);
+    (0..len).map(|_| rng.next() as u8).collect()
+}
+
+pub struct XorShift(pub u32);
+
+impl XorShift {
+    pub fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
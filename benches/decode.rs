@@ -0,0 +1,26 @@
+mod common;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use entrust::huffman::ByteHuffman;
+use entrust::table::TableDecoder;
+
+fn bench_decode(c: &mut Criterion) {
+    let data = common::corpus(1 << 20);
+    let code = ByteHuffman::new_bytes(&data);
+    let (packed, nbits) = code.encode_bytes_packed(&data);
+    let table = TableDecoder::new(&code).multi_symbol(false);
+    let multi = TableDecoder::new(&code);
+
+    let mut group = c.benchmark_group("decode_1MiB");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.sample_size(10);
+    group.bench_function("tree", |b| b.iter(|| code.decode_bytes_packed(&packed, nbits)));
+    group.bench_function("table", |b| b.iter(|| table.decode_packed(&packed, nbits)));
+    // several symbols per lookup where the codes are short
+    group.bench_function("table_multi", |b| b.iter(|| multi.decode_packed(&packed, nbits)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
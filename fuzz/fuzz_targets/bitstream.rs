@@ -0,0 +1,40 @@
+// Packed codewords and the stream decoder. The first byte says how much of
+// the rest to build a code from; what follows is decoded with it. The
+// training bytes have to round-trip, and the rest must decode or fail
+// cleanly. The same bytes are also read as a code table, as containers and
+// pipelines would, and whatever code that gives decodes the rest too.
+#![no_main]
+
+use std::io::Read;
+
+use entrust::huffman::ByteHuffman;
+use entrust::stream::HuffmanDecoder;
+use entrust::DecodeOptions;
+use libfuzzer_sys::fuzz_target;
+
+fn decode(code: ByteHuffman, bits: &[u8]) {
+    let _ = code.try_decode_bytes_packed(bits, bits.len() * 8);
+    let _ = code.decode_bytes_packed(bits, bits.len() * 8);
+    let limits = DecodeOptions::new().max_output_bytes(1 << 20);
+    let _ = HuffmanDecoder::new(bits, code).limits(limits).read_to_end(&mut Vec::new());
+}
+
+fuzz_target!(|data: &[u8]| {
+    let (&n, rest) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let (training, bits) = rest.split_at((n as usize).min(rest.len()));
+    if training.is_empty() {
+        return;
+    }
+    let code = ByteHuffman::new_bytes(training);
+    let (packed, nbits) = code.encode_bytes_packed(training);
+    assert_eq!(code.try_decode_bytes_packed(&packed, nbits).unwrap(), training);
+    decode(code, bits);
+
+    let limits = DecodeOptions::new().max_table_symbols(1 << 10).max_depth(64);
+    if let Ok(code) = ByteHuffman::from_table_with(training, &limits) {
+        decode(code, bits);
+    }
+});
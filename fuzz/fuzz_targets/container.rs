@@ -0,0 +1,27 @@
+// Whole containers, every codec and mode: decompressing must fail cleanly
+// or give exactly as many bytes as the header says, within the limits.
+#![no_main]
+
+use std::io::Cursor;
+
+use entrust::seekable::SeekableDecoder;
+use entrust::{decompress_from_slice_with, read_header, DecodeOptions};
+use libfuzzer_sys::fuzz_target;
+
+const MAX_OUTPUT: u64 = 1 << 24;
+
+fuzz_target!(|data: &[u8]| {
+    let limits = DecodeOptions::new().max_output_bytes(MAX_OUTPUT).max_table_symbols(1 << 10).max_depth(64);
+    let header = read_header(data);
+    if let Ok(out) = decompress_from_slice_with(data, &limits) {
+        assert_eq!(header.map(|h| h.len), Ok(out.len() as u64));
+    }
+
+    // the seekable decoder takes no limits; keep to what the header allows
+    if let Ok(mut reader) = SeekableDecoder::new(Cursor::new(data)) {
+        if reader.len() <= MAX_OUTPUT {
+            let len = reader.len();
+            let _ = reader.read_range(len / 2..len);
+        }
+    }
+});
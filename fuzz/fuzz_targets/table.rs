@@ -0,0 +1,24 @@
+// Code tables, as containers and pipelines read them: whatever from_table
+// or deserialize accepts has to write back out as the same code.
+#![no_main]
+
+use entrust::canonical::CanonicalHuffman;
+use entrust::huffman::ByteHuffman;
+use entrust::DecodeOptions;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let limits = DecodeOptions::new().max_table_symbols(1 << 10).max_depth(64);
+    if let Ok(code) = ByteHuffman::from_table_with(data, &limits) {
+        let again = ByteHuffman::from_table(&code.serialize_table()).expect("a written table reads back");
+        let mut lengths: Vec<(u8, usize)> = code.code_lengths().collect();
+        let mut again: Vec<(u8, usize)> = again.code_lengths().collect();
+        lengths.sort();
+        again.sort();
+        assert_eq!(lengths, again);
+    }
+    if let Ok(code) = CanonicalHuffman::deserialize(data) {
+        let again = CanonicalHuffman::deserialize(&code.serialize()).expect("a written table reads back");
+        assert_eq!(code.lengths(), again.lengths());
+    }
+});
@@ -0,0 +1,44 @@
+// The corpus the interop tests run over: generated samples that push the
+// encoder into each kind of block and the decoder through each kind of
+// match, entrust's own sources for real text, and whatever is in the
+// directory ENTRUST_CORPUS names.
+
+use std::fs;
+use std::path::Path;
+
+#[path = "../../benches/common/mod.rs"]
+mod common;
+
+pub fn corpus() -> Vec<(String, Vec<u8>)> {
+    let mut samples = vec![
+        ("empty".to_string(), Vec::new()),
+        ("one byte".to_string(), b"a".to_vec()),
+        ("text".to_string(), common::corpus(200_000)),
+        // nothing to match: stored blocks, and the 65535-byte limit on them
+        ("random".to_string(), common::random_bytes(150_000)),
+        ("random, block sized".to_string(), common::random_bytes(65_535)),
+        // the longest matches and runs overlapping their own output
+        ("zeros".to_string(), vec![0; 100_000]),
+        ("period 3".to_string(), b"abc".repeat(30_000)),
+        // matches at the far end of the 32K window
+        ("far matches".to_string(), common::random_bytes(32_000).repeat(2)),
+        ("all bytes".to_string(), (0..=255u8).cycle().take(10_000).collect()),
+    ];
+    samples.extend(files(Path::new(env!("CARGO_MANIFEST_DIR")).join("../src")));
+    if let Some(dir) = std::env::var_os("ENTRUST_CORPUS") {
+        samples.extend(files(dir));
+    }
+    samples
+}
+
+// The files directly in `dir`, by name, sorted
+fn files<P: AsRef<Path>>(dir: P) -> Vec<(String, Vec<u8>)> {
+    let mut found: Vec<(String, Vec<u8>)> = fs::read_dir(dir.as_ref())
+        .unwrap_or_else(|e| panic!("reading corpus {}: {}", dir.as_ref().display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .map(|path| (path.display().to_string(), fs::read(&path).unwrap()))
+        .collect();
+    found.sort();
+    found
+}
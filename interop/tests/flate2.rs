@@ -0,0 +1,75 @@
+use std::io::{Read, Write};
+
+use entrust::{deflate, gzip, zlib};
+use entrust::gzip::GzHeader;
+use entrust_interop::corpus;
+use flate2::read::{DeflateDecoder, GzDecoder, MultiGzDecoder, ZlibDecoder};
+use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+use flate2::{Compression, GzBuilder};
+
+const LEVELS: [u32; 4] = [0, 1, 6, 9];
+
+fn read_all<R: Read>(mut r: R) -> Vec<u8> {
+    let mut out = Vec::new();
+    r.read_to_end(&mut out).unwrap();
+    out
+}
+
+// Everything through one of flate2's encoders, and its finish
+fn encode<W: Write>(mut w: W, data: &[u8], finish: impl FnOnce(W) -> std::io::Result<Vec<u8>>) -> Vec<u8> {
+    w.write_all(data).unwrap();
+    finish(w).unwrap()
+}
+
+#[test]
+fn test_deflate() {
+    for (name, data) in corpus() {
+        let ours = deflate::deflate(&data);
+        assert!(read_all(DeflateDecoder::new(&ours[..])) == data, "flate2 inflating {}", name);
+        for &level in LEVELS.iter() {
+            let theirs = encode(DeflateEncoder::new(Vec::new(), Compression::new(level)), &data, |w| w.finish());
+            assert!(deflate::inflate(&theirs).unwrap() == data, "inflating {} from flate2 level {}", name, level);
+            // the stream's end is found to the byte, whatever follows it
+            let trailed = [&theirs[..], b"trailer"].concat();
+            assert_eq!(deflate::inflate_prefix(&trailed).unwrap().1, theirs.len(), "{}", name);
+        }
+    }
+}
+
+#[test]
+fn test_zlib() {
+    for (name, data) in corpus() {
+        let ours = zlib::compress(&data);
+        assert!(read_all(ZlibDecoder::new(&ours[..])) == data, "flate2 decompressing {}", name);
+        for &level in LEVELS.iter() {
+            let theirs = encode(ZlibEncoder::new(Vec::new(), Compression::new(level)), &data, |w| w.finish());
+            assert!(zlib::decompress(&theirs).unwrap() == data, "decompressing {} from flate2 level {}", name, level);
+        }
+    }
+}
+
+#[test]
+fn test_gzip() {
+    let header = GzHeader { mtime: 1_234_567_890, filename: Some(b"corpus.txt".to_vec()), comment: Some(b"hi".to_vec()) };
+    for (name, data) in corpus() {
+        let ours = gzip::compress(&data, &header);
+        let mut decoder = GzDecoder::new(&ours[..]);
+        assert!(read_all(&mut decoder) == data, "flate2 decompressing {}", name);
+        let read = decoder.header().unwrap();
+        assert_eq!((read.mtime(), read.filename(), read.comment()), (1_234_567_890, Some(&b"corpus.txt"[..]),
+                                                                      Some(&b"hi"[..])));
+
+        let theirs = encode(GzBuilder::new().filename("corpus.txt").mtime(7).write(Vec::new(), Compression::best()),
+                            &data, |w| w.finish());
+        let (read, unpacked) = gzip::decompress(&theirs).unwrap();
+        assert!(unpacked == data, "decompressing {} from flate2", name);
+        assert_eq!((read.mtime, read.filename), (7, Some(b"corpus.txt".to_vec())));
+    }
+
+    // members from each, concatenated, read by each
+    let (a, b) = (b"first member, ".to_vec(), b"second member".to_vec());
+    let theirs = encode(GzEncoder::new(Vec::new(), Compression::fast()), &b, |w| w.finish());
+    let joined = [gzip::compress(&a, &GzHeader::default()), theirs].concat();
+    assert_eq!(gzip::decompress(&joined).unwrap().1, [&a[..], &b[..]].concat());
+    assert_eq!(read_all(MultiGzDecoder::new(&joined[..])), [&a[..], &b[..]].concat());
+}
@@ -0,0 +1,45 @@
+#![cfg(feature = "libdeflate")]
+
+use entrust::{deflate, gzip, zlib};
+use entrust::gzip::GzHeader;
+use entrust_interop::corpus;
+use libdeflater::{CompressionLvl, Compressor, Decompressor};
+
+#[test]
+fn test_deflate() {
+    let mut decompressor = Decompressor::new();
+    for (name, data) in corpus() {
+        // libdeflate wants the output size up front
+        let mut out = vec![0; data.len()];
+        let n = decompressor.deflate_decompress(&deflate::deflate(&data), &mut out).unwrap();
+        assert!(n == data.len() && out == data, "libdeflate inflating {}", name);
+
+        for &level in [0, 1, 6, 12].iter() {
+            let mut compressor = Compressor::new(CompressionLvl::new(level).unwrap());
+            let mut theirs = vec![0; compressor.deflate_compress_bound(data.len())];
+            let n = compressor.deflate_compress(&data, &mut theirs).unwrap();
+            theirs.truncate(n);
+            assert!(deflate::inflate(&theirs).unwrap() == data, "inflating {} from libdeflate level {}", name, level);
+        }
+    }
+}
+
+#[test]
+fn test_wrappers() {
+    let mut decompressor = Decompressor::new();
+    let mut compressor = Compressor::new(CompressionLvl::best());
+    for (name, data) in corpus() {
+        let mut out = vec![0; data.len()];
+        decompressor.zlib_decompress(&zlib::compress(&data), &mut out).unwrap();
+        assert!(out == data, "libdeflate decompressing {} (zlib)", name);
+        decompressor.gzip_decompress(&gzip::compress(&data, &GzHeader::default()), &mut out).unwrap();
+        assert!(out == data, "libdeflate decompressing {} (gzip)", name);
+
+        let mut theirs = vec![0; compressor.zlib_compress_bound(data.len())];
+        let n = compressor.zlib_compress(&data, &mut theirs).unwrap();
+        assert!(zlib::decompress(&theirs[..n]).unwrap() == data, "decompressing {} from libdeflate (zlib)", name);
+        let mut theirs = vec![0; compressor.gzip_compress_bound(data.len())];
+        let n = compressor.gzip_compress(&data, &mut theirs).unwrap();
+        assert!(gzip::decompress(&theirs[..n]).unwrap().1 == data, "decompressing {} from libdeflate (gzip)", name);
+    }
+}
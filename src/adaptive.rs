@@ -0,0 +1,412 @@
+// Dynamic (adaptive) Huffman coding over bytes: encoder and decoder start
+// from the same empty tree and update it identically after every symbol,
+// so no pre-scan of the data and no transmitted table is needed. A symbol
+// seen for the first time is sent as the codeword of the NYT ("not yet
+// transmitted") leaf followed by its 8 raw bits.
+//
+// Both variants keep the sibling property: nodes are numbered so that
+// weights never decrease with the number and siblings are adjacent. FGK
+// only guarantees that; Vitter's algorithm additionally keeps, within a run
+// of equal weights, all leaves numbered below all internal nodes, which
+// bounds the code length much more tightly.
+
+use std::io::{self, Read, Write};
+
+use crate::bitio::{self, BitReader, BitWriter};
+
+const NONE: usize = usize::MAX;
+// 256 leaves + NYT + 256 internal nodes
+const MAX_NODES: usize = 2 * 256 + 1;
+
+pub trait AdaptiveCoder {
+    // Encode one symbol, then update the model
+    fn encode_symbol<W: Write>(&mut self, sym: u8, out: &mut BitWriter<W>) -> io::Result<()>;
+
+    // Decode one symbol and update the model; fails with UnexpectedEof if
+    // the input runs out before a whole symbol has been read.
+    fn decode_symbol<R: Read>(&mut self, input: &mut BitReader<R>) -> io::Result<u8>;
+
+    // One-shot helpers over packed MSB-first buffers
+    fn encode(&mut self, data: &[u8]) -> (Vec<u8>, usize) {
+        bitio::pack(|w| {
+            for &b in data {
+                self.encode_symbol(b, w)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn decode(&mut self, bytes: &[u8], nbits: usize) -> Vec<u8> {
+        let mut input = BitReader::new(bytes);
+        let mut ret = Vec::new();
+        while let Ok(b) = self.decode_symbol(&mut input) {
+            // a symbol running into the final byte's padding isn't real
+            if input.bits_read() as usize > nbits {
+                break;
+            }
+            ret.push(b);
+        }
+        ret
+    }
+}
+
+#[derive(Clone)]
+struct Node {
+    weight: u64,
+    parent: usize,
+    children: [usize; 2], // NONE for leaves
+    symbol: Option<u8>,   // None for internal nodes and NYT
+}
+
+// Tree shared by both update rules. Nodes live in `nodes` and never move;
+// their position in the sibling ordering is tracked separately so that
+// interchanging two subtrees only touches a handful of links.
+#[derive(Clone)]
+struct Tree {
+    nodes: Vec<Node>,
+    by_order: Vec<usize>, // order number -> node
+    order: Vec<usize>,    // node -> order number
+    leaf: [usize; 256],   // symbol -> node
+    nyt: usize,
+    root: usize,
+}
+
+impl Tree {
+
+    fn new() -> Self {
+        let mut by_order = vec![NONE; MAX_NODES];
+        by_order[MAX_NODES - 1] = 0;
+        Tree {
+            nodes: vec![Node { weight: 0, parent: NONE, children: [NONE; 2], symbol: None }],
+            by_order,
+            order: vec![MAX_NODES - 1],
+            leaf: [NONE; 256],
+            nyt: 0,
+            root: 0,
+        }
+    }
+
+    fn is_leaf(&self, n: usize) -> bool {
+        self.nodes[n].children[0] == NONE
+    }
+
+    fn path_to<W: Write>(&self, mut n: usize, out: &mut BitWriter<W>) -> io::Result<()> {
+        let mut path = Vec::new();
+        while n != self.root {
+            let parent = self.nodes[n].parent;
+            path.push(self.nodes[parent].children[1] == n);
+            n = parent;
+        }
+        for &bit in path.iter().rev() {
+            out.write_bit(bit)?;
+        }
+        Ok(())
+    }
+
+    fn encode<W: Write>(&self, sym: u8, out: &mut BitWriter<W>) -> io::Result<()> {
+        if self.leaf[sym as usize] != NONE {
+            self.path_to(self.leaf[sym as usize], out)
+        } else {
+            self.path_to(self.nyt, out)?;
+            out.write_bits(sym as u64, 8)
+        }
+    }
+
+    fn decode<R: Read>(&self, input: &mut BitReader<R>) -> io::Result<u8> {
+        let mut n = self.root;
+        while !self.is_leaf(n) {
+            n = self.nodes[n].children[input.read_bit()? as usize];
+        }
+        match self.nodes[n].symbol {
+            Some(sym) => Ok(sym),
+            None => Ok(input.read_bits(8)? as u8), // NYT
+        }
+    }
+
+    // Turn NYT into an internal node with a fresh NYT as its left child and
+    // a leaf for `sym` as its right child; returns the new leaf.
+    fn split_nyt(&mut self, sym: u8) -> usize {
+        let old = self.nyt;
+        let old_order = self.order[old];
+        let nyt = self.nodes.len();
+        let leaf = nyt + 1;
+        self.nodes.push(Node { weight: 0, parent: old, children: [NONE; 2], symbol: None });
+        self.nodes.push(Node { weight: 0, parent: old, children: [NONE; 2], symbol: Some(sym) });
+        self.nodes[old].children = [nyt, leaf];
+        self.order.push(old_order - 2);
+        self.order.push(old_order - 1);
+        self.by_order[old_order - 2] = nyt;
+        self.by_order[old_order - 1] = leaf;
+        self.leaf[sym as usize] = leaf;
+        self.nyt = nyt;
+        leaf
+    }
+
+    // Exchange the positions of two nodes (and the subtrees under them);
+    // neither may be an ancestor of the other.
+    fn swap(&mut self, a: usize, b: usize) {
+        let (pa, pb) = (self.nodes[a].parent, self.nodes[b].parent);
+        let ia = (self.nodes[pa].children[1] == a) as usize;
+        let ib = (self.nodes[pb].children[1] == b) as usize;
+        self.nodes[pa].children[ia] = b;
+        self.nodes[pb].children[ib] = a;
+        self.nodes[a].parent = pb;
+        self.nodes[b].parent = pa;
+        let (oa, ob) = (self.order[a], self.order[b]);
+        self.order.swap(a, b);
+        self.by_order[oa] = b;
+        self.by_order[ob] = a;
+    }
+
+    // Highest-numbered node for which `same_block` holds, scanning up from `n`
+    fn leader(&self, n: usize, same_block: impl Fn(&Tree, usize) -> bool) -> usize {
+        let mut leader = n;
+        for k in self.order[n] + 1..MAX_NODES {
+            let m = self.by_order[k];
+            if m == NONE || !same_block(self, m) {
+                break;
+            }
+            leader = m;
+        }
+        leader
+    }
+
+}
+
+// Faller–Gallager–Knuth: on each symbol, walk from its leaf to the root,
+// swapping each node with the highest-numbered node of equal weight
+// before incrementing it.
+#[derive(Clone)]
+pub struct Fgk {
+    tree: Tree,
+}
+
+impl Fgk {
+
+    pub fn new() -> Self {
+        Fgk { tree: Tree::new() }
+    }
+
+    fn update(&mut self, sym: u8) {
+        let t = &mut self.tree;
+        let mut n = if t.leaf[sym as usize] == NONE { t.split_nyt(sym) } else { t.leaf[sym as usize] };
+        loop {
+            let weight = t.nodes[n].weight;
+            let leader = t.leader(n, |t, m| t.nodes[m].weight == weight);
+            if leader != n && leader != t.nodes[n].parent {
+                t.swap(n, leader);
+            }
+            t.nodes[n].weight += 1;
+            if n == t.root {
+                break;
+            }
+            n = t.nodes[n].parent;
+        }
+    }
+
+}
+
+impl Default for Fgk {
+    fn default() -> Self {
+        Fgk::new()
+    }
+}
+
+impl AdaptiveCoder for Fgk {
+
+    fn encode_symbol<W: Write>(&mut self, sym: u8, out: &mut BitWriter<W>) -> io::Result<()> {
+        self.tree.encode(sym, out)?;
+        self.update(sym);
+        Ok(())
+    }
+
+    fn decode_symbol<R: Read>(&mut self, input: &mut BitReader<R>) -> io::Result<u8> {
+        let sym = self.tree.decode(input)?;
+        self.update(sym);
+        Ok(sym)
+    }
+
+}
+
+// Vitter's algorithm Λ: nodes are kept ordered by (weight, leaf before
+// internal node), and each increment slides the node just past the nodes
+// that would otherwise be out of order.
+#[derive(Clone)]
+pub struct Vitter {
+    tree: Tree,
+}
+
+impl Vitter {
+
+    pub fn new() -> Self {
+        Vitter { tree: Tree::new() }
+    }
+
+    fn update(&mut self, sym: u8) {
+        let t = &mut self.tree;
+        let mut leaf_to_increment = NONE;
+        let mut q;
+        if t.leaf[sym as usize] == NONE {
+            leaf_to_increment = t.split_nyt(sym);
+            q = t.nodes[leaf_to_increment].parent;
+        } else {
+            q = t.leaf[sym as usize];
+            let weight = t.nodes[q].weight;
+            let leader = t.leader(q, |t, m| t.is_leaf(m) && t.nodes[m].weight == weight);
+            if leader != q {
+                t.swap(q, leader);
+            }
+            // Incrementing a sibling of NYT first would slide it past its own parent
+            let parent = t.nodes[q].parent;
+            if parent != NONE && t.nodes[parent].children[0] == t.nyt {
+                leaf_to_increment = q;
+                q = parent;
+            }
+        }
+        while q != NONE {
+            q = self.slide_and_increment(q);
+        }
+        if leaf_to_increment != NONE {
+            self.slide_and_increment(leaf_to_increment);
+        }
+    }
+
+    // Returns the node to continue with: the new parent for a leaf, the
+    // former parent for an internal node.
+    fn slide_and_increment(&mut self, p: usize) -> usize {
+        let t = &mut self.tree;
+        let former_parent = t.nodes[p].parent;
+        let is_leaf = t.is_leaf(p);
+        let key = (t.nodes[p].weight + 1, !is_leaf);
+        loop {
+            let k = t.order[p] + 1;
+            if k >= MAX_NODES || t.by_order[k] == NONE {
+                break;
+            }
+            let next = t.by_order[k];
+            if (t.nodes[next].weight, !t.is_leaf(next)) >= key {
+                break;
+            }
+            t.swap(p, next);
+        }
+        t.nodes[p].weight += 1;
+        if is_leaf { t.nodes[p].parent } else { former_parent }
+    }
+
+}
+
+impl Default for Vitter {
+    fn default() -> Self {
+        Vitter::new()
+    }
+}
+
+impl AdaptiveCoder for Vitter {
+
+    fn encode_symbol<W: Write>(&mut self, sym: u8, out: &mut BitWriter<W>) -> io::Result<()> {
+        self.tree.encode(sym, out)?;
+        self.update(sym);
+        Ok(())
+    }
+
+    fn decode_symbol<R: Read>(&mut self, input: &mut BitReader<R>) -> io::Result<u8> {
+        let sym = self.tree.decode(input)?;
+        self.update(sym);
+        Ok(sym)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{AdaptiveCoder, Fgk, Vitter, Tree, NONE, MAX_NODES};
+    use crate::bitio::{BitReader, BitWriter};
+
+    fn samples() -> Vec<Vec<u8>> {
+        let mut lcg: u32 = 12345;
+        let noise: Vec<u8> = (0..2000).map(|_| {
+            lcg = lcg.wrapping_mul(1103515245).wrapping_add(12345);
+            (lcg >> 16) as u8
+        }).collect();
+        vec![b"".to_vec(), b"a".to_vec(), b"abracadabra".to_vec(),
+             b"the quick brown fox jumped over the lazy dog".repeat(20),
+             (0..=255u8).collect(), noise]
+    }
+
+    // Weights never decrease with the order number, siblings are adjacent,
+    // and every internal node weighs as much as its children together.
+    fn check_sibling_property(t: &Tree) {
+        let ordered: Vec<usize> = t.by_order.iter().cloned().filter(|&n| n != NONE).collect();
+        for w in ordered.windows(2) {
+            assert!(t.nodes[w[0]].weight <= t.nodes[w[1]].weight);
+        }
+        for (n, node) in t.nodes.iter().enumerate() {
+            if !t.is_leaf(n) {
+                let [l, r] = node.children;
+                assert_eq!(node.weight, t.nodes[l].weight + t.nodes[r].weight);
+                assert_eq!(t.order[l] + 1, t.order[r]);
+            }
+        }
+        assert_eq!(t.order[t.root], MAX_NODES - 1);
+    }
+
+    #[test]
+    fn test_fgk() {
+        for data in samples() {
+            let (packed, nbits) = Fgk::new().encode(&data);
+            assert_eq!(data, Fgk::new().decode(&packed, nbits));
+            let mut coder = Fgk::new();
+            coder.encode(&data);
+            check_sibling_property(&coder.tree);
+        }
+    }
+
+    #[test]
+    fn test_vitter() {
+        for data in samples() {
+            let (packed, nbits) = Vitter::new().encode(&data);
+            assert_eq!(data, Vitter::new().decode(&packed, nbits));
+            let mut coder = Vitter::new();
+            coder.encode(&data);
+            let t = &coder.tree;
+            check_sibling_property(t);
+            // within equal weights, leaves come before internal nodes
+            let ordered: Vec<usize> = t.by_order.iter().cloned().filter(|&n| n != NONE).collect();
+            for w in ordered.windows(2) {
+                if t.nodes[w[0]].weight == t.nodes[w[1]].weight {
+                    assert!(t.is_leaf(w[0]) || !t.is_leaf(w[1]));
+                }
+            }
+        }
+    }
+
+    fn stream<C: AdaptiveCoder>(mut encoder: C, mut decoder: C) {
+        // symbols can be fed one at a time through a shared bit stream
+        let text = b"dagoth ur was a hotep";
+        let mut writer = BitWriter::new(Vec::new());
+        for &b in text.iter() {
+            encoder.encode_symbol(b, &mut writer).unwrap();
+        }
+        let bytes = writer.into_inner().unwrap();
+        let mut reader = BitReader::new(&bytes[..]);
+        let decoded: Vec<u8> = (0..text.len()).map(|_| decoder.decode_symbol(&mut reader).unwrap()).collect();
+        assert_eq!(text.to_vec(), decoded);
+    }
+
+    #[test]
+    fn test_streaming() {
+        stream(Fgk::new(), Fgk::new());
+        stream(Vitter::new(), Vitter::new());
+    }
+
+    #[test]
+    fn test_compresses_skewed_input() {
+        let data = b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaabbbbbbbbbbcccd".repeat(10);
+        let (_, fgk_bits) = Fgk::new().encode(&data);
+        let (_, vitter_bits) = Vitter::new().encode(&data);
+        assert!(fgk_bits < data.len() * 8 / 3);
+        assert!(vitter_bits < data.len() * 8 / 3);
+    }
+
+}
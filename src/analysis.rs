@@ -0,0 +1,119 @@
+// Information-theoretic measures of a source, and of how well a frequency
+// table models it. A static code built from `model` costs roughly
+// `cross_entropy(model, data)` bits per symbol on `data`; the excess over
+// the data's own entropy is `kl_divergence(model, data)`.
+
+use std::collections::HashMap;
+
+use crate::huffman::Symbol;
+
+// Empirical entropy of `data`, in bits per symbol
+pub fn entropy<S: Symbol>(data: &[S]) -> f64 {
+    let counts = counts(data);
+    entropy_from_counts(counts.values().cloned(), data.len() as u64)
+}
+
+// Entropy of the distribution described by a frequency table
+pub fn entropy_of<S: Symbol>(freqs: &HashMap<S, u64>) -> f64 {
+    entropy_from_counts(freqs.values().cloned(), freqs.values().sum())
+}
+
+// Average bits per symbol of `data` under the distribution given by
+// `model`. Infinite if `data` contains a symbol the model gives zero
+// probability (so for any data under an empty model); zero for empty data.
+pub fn cross_entropy<S: Symbol>(model: &HashMap<S, u64>, data: &[S]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    // summed wide, as a large model's counts can add up past u64
+    let total: u128 = model.values().map(|&f| f as u128).sum();
+    if total == 0 {
+        return f64::INFINITY;
+    }
+    let n = data.len() as f64;
+    let mut bits = 0.0;
+    for (sym, count) in counts(data) {
+        let q = model.get(sym).map_or(0, |&f| f) as f64 / total as f64;
+        if q == 0.0 {
+            return f64::INFINITY;
+        }
+        bits -= count as f64 / n * q.log2();
+    }
+    bits
+}
+
+// Kullback-Leibler divergence D(data || model), in bits per symbol: the
+// cost of coding `data` with a model of `model` rather than of itself
+pub fn kl_divergence<S: Symbol>(model: &HashMap<S, u64>, data: &[S]) -> f64 {
+    let cross = cross_entropy(model, data);
+    if cross.is_infinite() {
+        return cross;
+    }
+    // Rounding can leave a tiny negative difference for identical distributions
+    (cross - entropy(data)).max(0.0)
+}
+
+pub(crate) fn entropy_from_counts<I: Iterator<Item=u64>>(counts: I, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    counts.filter(|&c| c > 0)
+        .map(|c| {
+            let p = c as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn counts<S: Symbol>(data: &[S]) -> HashMap<&S, u64> {
+    let mut counts = HashMap::new();
+    for sym in data {
+        *counts.entry(sym).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::HashMap;
+    use super::{cross_entropy, entropy, entropy_of, kl_divergence};
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert!(close(entropy(b"aaaabbcc"), 1.5));
+        assert!(close(entropy(b"abcdefgh"), 3.0));
+        assert!(close(entropy(b"zzzz"), 0.0));
+        assert!(close(entropy::<u8>(&[]), 0.0));
+
+        let freqs: HashMap<char, u64> = vec![('a', 2), ('b', 1), ('c', 1)].into_iter().collect();
+        assert!(close(entropy_of(&freqs), 1.5));
+    }
+
+    #[test]
+    fn test_model_fit() {
+        let data = b"aaaabbcc";
+        let exact: HashMap<u8, u64> = vec![(b'a', 4), (b'b', 2), (b'c', 2)].into_iter().collect();
+        assert!(close(cross_entropy(&exact, data), 1.5));
+        assert!(close(kl_divergence(&exact, data), 0.0));
+
+        // Uniform over four symbols costs two bits each
+        let uniform: HashMap<u8, u64> = vec![(b'a', 1), (b'b', 1), (b'c', 1), (b'd', 1)].into_iter().collect();
+        assert!(close(cross_entropy(&uniform, data), 2.0));
+        assert!(close(kl_divergence(&uniform, data), 0.5));
+
+        let missing: HashMap<u8, u64> = vec![(b'a', 1), (b'b', 1)].into_iter().collect();
+        assert_eq!(cross_entropy(&missing, data), f64::INFINITY);
+        assert_eq!(kl_divergence(&missing, data), f64::INFINITY);
+        assert_eq!(cross_entropy(&HashMap::new(), data), f64::INFINITY);
+
+        // counts that overflow a u64 sum: half and half, one bit each
+        let huge: HashMap<u8, u64> = vec![(b'a', u64::MAX), (b'b', u64::MAX)].into_iter().collect();
+        assert!(close(cross_entropy(&huge, b"ab"), 1.0));
+    }
+
+}
@@ -0,0 +1,469 @@
+// Several named files in one container, as tar and a compressor would
+// make together. The container's data is the archive stream:
+//
+//   magic        4 bytes  ARCHIVE_MAGIC
+//   version      u8       ARCHIVE_VERSION
+//   then for each entry:
+//   path_len     varint   length of the path that follows
+//   path         UTF-8, relative, components separated by '/'
+//   mode         varint   permission bits, as in st_mode & 0o7777
+//   mtime        varint   seconds since the Unix epoch
+//   size         varint   length of the contents that follow
+//   contents     size bytes
+//
+// The varints are unsigned LEB128 (codes::varint). Version 1 archives,
+// with a u16 path_len, u32 mode and u64 mtime and size, all big-endian,
+// are still read. Only files are stored; directories come back as needed
+// on extract. Paths that could land outside the target directory ("..",
+// absolute paths) are refused on the way in and out.
+//
+// The whole archive is held in memory.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::codes::varint::{self, VarintError};
+use crate::container::{self, CompressOptions, ContainerError};
+use crate::limits::DecodeOptions;
+
+pub const ARCHIVE_MAGIC: [u8; 4] = *b"ENTA";
+pub const ARCHIVE_VERSION: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub path: String,
+    pub size: u64,
+    pub mode: u32,
+    pub mtime: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Archive {
+    entries: Vec<Entry>,
+    contents: Vec<Vec<u8>>,
+    // where each path's entry is, so add() needn't search for it
+    index: HashMap<String, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveError {
+    Container(ContainerError),
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    // A path that's empty, absolute, or has an empty, "." or ".." component
+    BadPath(String),
+    // A varint field wider than 64 bits
+    Overflow,
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveError::Container(e) => write!(f, "{}", e),
+            ArchiveError::BadMagic => write!(f, "not an entrust archive"),
+            ArchiveError::UnsupportedVersion(v) => write!(f, "unsupported archive version {}", v),
+            ArchiveError::Truncated => write!(f, "archive is truncated"),
+            ArchiveError::BadPath(path) => write!(f, "bad path in archive: {:?}", path),
+            ArchiveError::Overflow => write!(f, "archive field overflows 64 bits"),
+        }
+    }
+}
+
+impl Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ArchiveError::Container(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ContainerError> for ArchiveError {
+    fn from(e: ContainerError) -> Self {
+        ArchiveError::Container(e)
+    }
+}
+
+impl From<VarintError> for ArchiveError {
+    fn from(e: VarintError) -> Self {
+        match e {
+            VarintError::Truncated => ArchiveError::Truncated,
+            VarintError::Overflow => ArchiveError::Overflow,
+        }
+    }
+}
+
+impl From<ArchiveError> for io::Error {
+    fn from(e: ArchiveError) -> Self {
+        match e {
+            ArchiveError::Container(e) => e.into(),
+            ArchiveError::Truncated => io::Error::new(io::ErrorKind::UnexpectedEof, e),
+            ArchiveError::BadPath(_) => io::Error::new(io::ErrorKind::InvalidInput, e),
+            _ => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+impl Archive {
+
+    pub fn new() -> Self {
+        Archive::default()
+    }
+
+    // Replaces any entry already at `path`
+    pub fn add(&mut self, path: &str, contents: Vec<u8>, mode: u32, mtime: u64) -> Result<(), ArchiveError> {
+        check_path(path)?;
+        let entry = Entry { path: path.to_string(), size: contents.len() as u64, mode: mode & 0o7777, mtime };
+        match self.index.get(path) {
+            Some(&i) => {
+                self.entries[i] = entry;
+                self.contents[i] = contents;
+            }
+            None => {
+                self.index.insert(path.to_string(), self.entries.len());
+                self.entries.push(entry);
+                self.contents.push(contents);
+            }
+        }
+        Ok(())
+    }
+
+    // The file at `source`, stored as `path` with its permissions and
+    // modification time
+    pub fn add_file<P: AsRef<Path>>(&mut self, source: P, path: &str) -> io::Result<()> {
+        let contents = fs::read(source.as_ref())?;
+        let meta = fs::metadata(source.as_ref())?;
+        let mtime = meta.modified()?.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        Ok(self.add(path, contents, file_mode(&meta), mtime)?)
+    }
+
+    // Every file under `dir`, recursively and in name order, stored under
+    // `prefix`; symbolic links aren't followed
+    pub fn add_dir<P: AsRef<Path>>(&mut self, dir: P, prefix: &str) -> io::Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let name = entry.file_name().into_string()
+                .map_err(|name| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} isn't UTF-8", name)))?;
+            let path = format!("{}/{}", prefix, name);
+            let kind = entry.file_type()?;
+            if kind.is_dir() {
+                self.add_dir(entry.path(), &path)?;
+            } else if kind.is_file() {
+                self.add_file(entry.path(), &path)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn get(&self, path: &str) -> Option<&[u8]> {
+        let &i = self.index.get(path)?;
+        Some(&self.contents[i])
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // The archive stream, before compression
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = ARCHIVE_MAGIC.to_vec();
+        out.push(ARCHIVE_VERSION);
+        for (entry, contents) in self.entries.iter().zip(&self.contents) {
+            varint::write_u64(&mut out, entry.path.len() as u64);
+            out.extend_from_slice(entry.path.as_bytes());
+            varint::write_u64(&mut out, entry.mode as u64);
+            varint::write_u64(&mut out, entry.mtime);
+            varint::write_u64(&mut out, entry.size);
+            out.extend_from_slice(contents);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Archive, ArchiveError> {
+        let mut rest = bytes;
+        if take(&mut rest, 4)? != ARCHIVE_MAGIC {
+            return Err(ArchiveError::BadMagic);
+        }
+        let version = take(&mut rest, 1)?[0];
+        if version != 1 && version != ARCHIVE_VERSION {
+            return Err(ArchiveError::UnsupportedVersion(version));
+        }
+        let mut archive = Archive::new();
+        while !rest.is_empty() {
+            let path_len = if version == 1 {
+                let b = take(&mut rest, 2)?;
+                u16::from_be_bytes([b[0], b[1]]) as u64
+            } else {
+                varint::read_u64(&mut rest)?
+            };
+            if path_len > rest.len() as u64 {
+                return Err(ArchiveError::Truncated);
+            }
+            let path = take(&mut rest, path_len as usize)?;
+            let path = std::str::from_utf8(path).map_err(|_| ArchiveError::BadPath(String::from_utf8_lossy(path).into()))?;
+            let (mode, mtime, size) = if version == 1 {
+                (take_u32(&mut rest)?, take_u64(&mut rest)?, take_u64(&mut rest)?)
+            } else {
+                let mode = varint::read_u64(&mut rest)?;
+                (mode as u32, varint::read_u64(&mut rest)?, varint::read_u64(&mut rest)?)
+            };
+            if size > rest.len() as u64 {
+                return Err(ArchiveError::Truncated);
+            }
+            let contents = take(&mut rest, size as usize)?;
+            archive.add(path, contents.to_vec(), mode, mtime)?;
+        }
+        Ok(archive)
+    }
+
+    pub fn compress(&self, options: &CompressOptions) -> Vec<u8> {
+        container::compress_with(&self.to_bytes(), options)
+    }
+
+    pub fn decompress(bytes: &[u8]) -> Result<Archive, ArchiveError> {
+        Archive::decompress_with(bytes, &DecodeOptions::new())
+    }
+
+    // decompress, failing with LimitExceeded rather than unpack more than
+    // `limits` allows (container::decompress_from_slice_with)
+    pub fn decompress_with(bytes: &[u8], limits: &DecodeOptions) -> Result<Archive, ArchiveError> {
+        Archive::from_bytes(&container::decompress_from_slice_with(bytes, limits)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, options: &CompressOptions) -> io::Result<()> {
+        fs::write(path, self.compress(options))
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Archive> {
+        Archive::open_with(path, &DecodeOptions::new())
+    }
+
+    pub fn open_with<P: AsRef<Path>>(path: P, limits: &DecodeOptions) -> io::Result<Archive> {
+        Ok(Archive::decompress_with(&fs::read(path)?, limits)?)
+    }
+
+    // Writes every entry under `dir`, creating directories as needed and
+    // restoring permissions (on Unix) and modification times. Existing
+    // files are overwritten. The setuid, setgid and sticky bits are kept in
+    // the entries but never restored: an archive from elsewhere shouldn't
+    // be able to hand out a setuid binary. A modification time too far off
+    // for the platform's clock is left as the time of extraction.
+    pub fn extract<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        for (entry, contents) in self.entries.iter().zip(&self.contents) {
+            let target = entry.path.split('/').fold(dir.as_ref().to_path_buf(), |path, part| path.join(part));
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&target, contents)?;
+            if let Some(mtime) = UNIX_EPOCH.checked_add(Duration::from_secs(entry.mtime)) {
+                File::options().write(true).open(&target)?.set_modified(mtime)?;
+            }
+            set_mode(&target, entry.mode)?;
+        }
+        Ok(())
+    }
+
+}
+
+// The entries of a compressed archive, without keeping their contents
+pub fn list(bytes: &[u8]) -> Result<Vec<Entry>, ArchiveError> {
+    Ok(Archive::decompress(bytes)?.entries)
+}
+
+fn check_path(path: &str) -> Result<(), ArchiveError> {
+    let bad = path.is_empty() || path.len() > u16::MAX as usize || path.contains(['\\', '\0', ':'])
+        || path.split('/').any(|part| part.is_empty() || part == "." || part == "..");
+    if bad {
+        return Err(ArchiveError::BadPath(path.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn file_mode(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(meta: &fs::Metadata) -> u32 {
+    if meta.permissions().readonly() { 0o444 } else { 0o644 }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+}
+
+// only whether the file is writable carries over
+#[cfg(not(unix))]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], ArchiveError> {
+    if bytes.len() < n {
+        return Err(ArchiveError::Truncated);
+    }
+    let (head, tail) = bytes.split_at(n);
+    *bytes = tail;
+    Ok(head)
+}
+
+fn take_u32(bytes: &mut &[u8]) -> Result<u32, ArchiveError> {
+    let b = take(bytes, 4)?;
+    Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn take_u64(bytes: &mut &[u8]) -> Result<u64, ArchiveError> {
+    let b = take(bytes, 8)?;
+    let mut arr = [0u8; 8];
+    arr.copy_from_slice(b);
+    Ok(u64::from_be_bytes(arr))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{list, Archive, ArchiveError, Entry};
+    use crate::container::{Codec, CompressOptions, ContainerError};
+    use crate::limits::{DecodeOptions, Limit, LimitExceeded};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::UNIX_EPOCH;
+
+    // Scratch directory in the system temp dir, removed on drop
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("entrust-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut archive = Archive::new();
+        archive.add("README", b"read me".to_vec(), 0o644, 1_700_000_000).unwrap();
+        archive.add("src/main.rs", b"fn main() {}\n".repeat(200), 0o100_755, 1_700_000_001).unwrap();
+        archive.add("empty", Vec::new(), 0o600, 0).unwrap();
+        archive.add("README", b"read me again".to_vec(), 0o644, 1_700_000_002).unwrap();
+        assert_eq!(archive.len(), 3);
+        assert_eq!(archive.entries()[1], Entry { path: "src/main.rs".into(), size: 2600, mode: 0o755, mtime: 1_700_000_001 });
+
+        let packed = archive.compress(&CompressOptions::new(Codec::Lzss));
+        assert!(packed.len() < archive.to_bytes().len() / 2);
+        let restored = Archive::decompress(&packed).unwrap();
+        assert_eq!(restored, archive);
+        assert_eq!(restored.get("README"), Some(&b"read me again"[..]));
+        assert_eq!(restored.get("src"), None);
+        assert_eq!(list(&packed).unwrap().iter().map(|e| e.path.as_str()).collect::<Vec<_>>(),
+                   vec!["README", "src/main.rs", "empty"]);
+
+        for bad in ["", "/etc/passwd", "a/../../b", "a//b", "./a", "a/", "C:\\x"].iter() {
+            assert_eq!(archive.add(bad, Vec::new(), 0, 0), Err(ArchiveError::BadPath(bad.to_string())));
+        }
+        let mut evil = Archive::new();
+        evil.add("abcd", b"x".to_vec(), 0o644, 0).unwrap();
+        let mut evil = evil.to_bytes();
+        evil[6..10].copy_from_slice(b"../x");
+        assert_eq!(Archive::from_bytes(&evil), Err(ArchiveError::BadPath("../x".into())));
+        let bytes = archive.to_bytes();
+        assert_eq!(Archive::from_bytes(&bytes[..bytes.len() - 1]), Err(ArchiveError::Truncated));
+        assert_eq!(Archive::decompress(b"ENTR"), Err(ArchiveError::Container(ContainerError::Truncated)));
+
+        let max = archive.to_bytes().len() as u64;
+        assert_eq!(Archive::decompress_with(&packed, &DecodeOptions::new().max_output_bytes(max)).unwrap(), archive);
+        assert_eq!(Archive::decompress_with(&packed, &DecodeOptions::new().max_output_bytes(max - 1)),
+                   Err(ArchiveError::Container(ContainerError::LimitExceeded(
+                       LimitExceeded { limit: Limit::OutputBytes, max: max - 1 }))));
+    }
+
+    #[test]
+    fn test_version_1() {
+        // written before lengths were varints
+        let mut bytes = b"ENTA\x01\x00\x05notes".to_vec();
+        bytes.extend_from_slice(&0o640u32.to_be_bytes());
+        bytes.extend_from_slice(&1_600_000_000u64.to_be_bytes());
+        bytes.extend_from_slice(&3u64.to_be_bytes());
+        bytes.extend_from_slice(b"abc");
+        let archive = Archive::from_bytes(&bytes).unwrap();
+        assert_eq!(archive.entries(), [Entry { path: "notes".into(), size: 3, mode: 0o640, mtime: 1_600_000_000 }]);
+        assert_eq!(archive.get("notes"), Some(&b"abc"[..]));
+
+        // the same entry in the current format, 13 bytes shorter
+        assert_eq!(archive.to_bytes().len(), bytes.len() - 13);
+        assert_eq!(Archive::from_bytes(&archive.to_bytes()).unwrap(), archive);
+        assert_eq!(Archive::from_bytes(b"ENTA\x02\x05notes\xff\xff\xff\xff\xff\xff\xff\xff\xff\x7f"),
+                   Err(ArchiveError::Overflow));
+    }
+
+    #[test]
+    fn test_files() {
+        let (source, target) = (TempDir::new("archive-src"), TempDir::new("archive-dst"));
+        fs::create_dir_all(source.0.join("docs/notes")).unwrap();
+        fs::write(source.0.join("a.txt"), b"alpha").unwrap();
+        fs::write(source.0.join("docs/notes/b.txt"), b"beta".repeat(100)).unwrap();
+
+        let mut archive = Archive::new();
+        archive.add_dir(&source.0, "tree").unwrap();
+        archive.add_file(source.0.join("a.txt"), "top.txt").unwrap();
+        let paths: Vec<&str> = archive.entries().iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["tree/a.txt", "tree/docs/notes/b.txt", "top.txt"]);
+
+        let file = target.0.join("out.entr");
+        archive.save(&file, &CompressOptions::default()).unwrap();
+        let restored = Archive::open(&file).unwrap();
+        assert_eq!(restored, archive);
+        restored.extract(&target.0).unwrap();
+        let extracted = target.0.join("tree/docs/notes/b.txt");
+        assert_eq!(fs::read(&extracted).unwrap(), b"beta".repeat(100));
+        let mtime = fs::metadata(&extracted).unwrap().modified().unwrap().duration_since(UNIX_EPOCH).unwrap();
+        assert_eq!(mtime.as_secs(), archive.entries()[1].mtime);
+
+        // a time past what SystemTime holds is left alone
+        let mut far = Archive::new();
+        far.add("far", b"future".to_vec(), 0o644, u64::MAX).unwrap();
+        far.extract(&target.0).unwrap();
+        assert_eq!(fs::read(target.0.join("far")).unwrap(), b"future");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&extracted).unwrap().permissions().mode() & 0o7777;
+            assert_eq!(mode, archive.entries()[1].mode);
+
+            let mut setuid = Archive::new();
+            setuid.add("run", b"#!/bin/sh\n".to_vec(), 0o4755, 0).unwrap();
+            setuid.extract(&target.0).unwrap();
+            assert_eq!(fs::metadata(target.0.join("run")).unwrap().permissions().mode() & 0o7777, 0o755);
+        }
+    }
+
+}
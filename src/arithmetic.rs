@@ -0,0 +1,276 @@
+// Binary arithmetic coding with 32-bit integer ranges (Witten, Neal and
+// Cleary, with the underflow handled by deferring bits). The coder narrows
+// [low, high] to each symbol's share of it, given as a cumulative count,
+// a count and a total; where those come from is up to a Model (see the
+// models module), which can change after every symbol as long as encoder
+// and decoder change it the same way.
+//
+// The decoder reads zeros past the end of its input, since the encoder
+// leaves off trailing bits that don't matter. Input that runs out long
+// before that is reported as truncated, but a short cut can go unnoticed;
+// callers should know how many symbols to expect.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::bitio::{self, BitReader, BitWriter};
+use crate::models::Model;
+
+const CODE_BITS: u32 = 32;
+const TOP: u64 = (1 << CODE_BITS) - 1;
+const HALF: u64 = 1 << (CODE_BITS - 1);
+const QUARTER: u64 = 1 << (CODE_BITS - 2);
+// A model's total must stay at or below this, so every symbol keeps a
+// nonempty range
+pub const MAX_TOTAL: u32 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithError {
+    // The input ended well before the symbols asked for
+    Truncated,
+    // The input led the decoder to a range no model could have given it
+    Corrupt,
+}
+
+impl fmt::Display for ArithError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArithError::Truncated => write!(f, "arithmetic coded data is truncated"),
+            ArithError::Corrupt => write!(f, "arithmetic coded data is corrupt"),
+        }
+    }
+}
+
+impl Error for ArithError {}
+
+impl From<ArithError> for io::Error {
+    fn from(e: ArithError) -> Self {
+        let kind = match e {
+            ArithError::Truncated => io::ErrorKind::UnexpectedEof,
+            ArithError::Corrupt => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+// Back from the io::Error the decoder's methods return; the only other
+// failure reading from a slice is running out of it
+pub(crate) fn from_io(e: io::Error) -> ArithError {
+    e.get_ref().and_then(|e| e.downcast_ref::<ArithError>()).copied().unwrap_or(ArithError::Truncated)
+}
+
+pub struct ArithmeticEncoder {
+    low: u64,
+    high: u64,
+    pending: u64, // opposite bits owed after the next one out
+}
+
+impl ArithmeticEncoder {
+
+    pub fn new() -> Self {
+        ArithmeticEncoder { low: 0, high: TOP, pending: 0 }
+    }
+
+    // Narrow to [low, low + freq) out of `total`. Panics unless freq > 0,
+    // low + freq <= total and total <= MAX_TOTAL
+    pub fn encode<W: Write>(&mut self, low: u32, freq: u32, total: u32, out: &mut BitWriter<W>) -> io::Result<()> {
+        assert!(freq > 0 && low + freq <= total && total <= MAX_TOTAL, "bad symbol range");
+        let range = self.high - self.low + 1;
+        self.high = self.low + range * (low + freq) as u64 / total as u64 - 1;
+        self.low += range * low as u64 / total as u64;
+        loop {
+            if self.high < HALF {
+                self.emit(false, out)?;
+            } else if self.low >= HALF {
+                self.emit(true, out)?;
+                self.low -= HALF;
+                self.high -= HALF;
+            } else if self.low >= QUARTER && self.high < HALF + QUARTER {
+                self.pending += 1;
+                self.low -= QUARTER;
+                self.high -= QUARTER;
+            } else {
+                break;
+            }
+            self.low *= 2;
+            self.high = self.high * 2 + 1;
+        }
+        Ok(())
+    }
+
+    // Encode `sym` as `model` sees it, then let the model learn from it
+    pub fn encode_symbol<M: Model + ?Sized, W: Write>(&mut self, model: &mut M, sym: u8, out: &mut BitWriter<W>)
+        -> io::Result<()> {
+        let (low, freq) = model.range(sym);
+        self.encode(low, freq, model.total(), out)?;
+        model.update(sym);
+        Ok(())
+    }
+
+    // Write the bits that pin down a value inside the final range. The
+    // writer isn't aligned or flushed.
+    pub fn finish<W: Write>(mut self, out: &mut BitWriter<W>) -> io::Result<()> {
+        self.pending += 1;
+        let bit = self.low >= QUARTER;
+        self.emit(bit, out)
+    }
+
+    fn emit<W: Write>(&mut self, bit: bool, out: &mut BitWriter<W>) -> io::Result<()> {
+        out.write_bit(bit)?;
+        for _ in 0..self.pending {
+            out.write_bit(!bit)?;
+        }
+        self.pending = 0;
+        Ok(())
+    }
+
+}
+
+impl Default for ArithmeticEncoder {
+    fn default() -> Self {
+        ArithmeticEncoder::new()
+    }
+}
+
+pub struct ArithmeticDecoder {
+    low: u64,
+    high: u64,
+    value: u64,
+    past_end: u32, // zeros supplied after the input ran out
+}
+
+impl ArithmeticDecoder {
+
+    pub fn new<R: Read>(input: &mut BitReader<R>) -> io::Result<Self> {
+        let mut dec = ArithmeticDecoder { low: 0, high: TOP, value: 0, past_end: 0 };
+        for _ in 0..CODE_BITS {
+            dec.value = dec.value * 2 + dec.next_bit(input)? as u64;
+        }
+        Ok(dec)
+    }
+
+    // Where the next symbol falls in [0, total); look it up in the model,
+    // then consume() its range. Fails with Corrupt if total is 0.
+    pub fn target(&self, total: u32) -> io::Result<u32> {
+        if total == 0 {
+            return Err(ArithError::Corrupt.into());
+        }
+        let range = self.high - self.low + 1;
+        Ok((((self.value - self.low + 1) * total as u64 - 1) / range) as u32)
+    }
+
+    // The same narrowing the encoder did for this range. Fails with
+    // Corrupt on a range the encoder would have refused.
+    pub fn consume<R: Read>(&mut self, low: u32, freq: u32, total: u32, input: &mut BitReader<R>) -> io::Result<()> {
+        if freq == 0 || low as u64 + freq as u64 > total as u64 || total > MAX_TOTAL {
+            return Err(ArithError::Corrupt.into());
+        }
+        let range = self.high - self.low + 1;
+        self.high = self.low + range * (low + freq) as u64 / total as u64 - 1;
+        self.low += range * low as u64 / total as u64;
+        loop {
+            if self.high < HALF {
+                // nothing to take off
+            } else if self.low >= HALF {
+                self.low -= HALF;
+                self.high -= HALF;
+                self.value -= HALF;
+            } else if self.low >= QUARTER && self.high < HALF + QUARTER {
+                self.low -= QUARTER;
+                self.high -= QUARTER;
+                self.value -= QUARTER;
+            } else {
+                break;
+            }
+            self.low *= 2;
+            self.high = self.high * 2 + 1;
+            self.value = self.value * 2 + self.next_bit(input)? as u64;
+        }
+        Ok(())
+    }
+
+    pub fn decode_symbol<M: Model + ?Sized, R: Read>(&mut self, model: &mut M, input: &mut BitReader<R>)
+        -> io::Result<u8> {
+        let total = model.total();
+        let (sym, low, freq) = model.find(self.target(total)?);
+        self.consume(low, freq, total, input)?;
+        model.update(sym);
+        Ok(sym)
+    }
+
+    fn next_bit<R: Read>(&mut self, input: &mut BitReader<R>) -> io::Result<bool> {
+        match input.read_bit() {
+            Ok(bit) => Ok(bit),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.past_end += 1;
+                if self.past_end > CODE_BITS {
+                    return Err(ArithError::Truncated.into());
+                }
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+}
+
+// Encode all of `data` with a fresh `model`
+pub fn encode<M: Model>(data: &[u8], mut model: M) -> Vec<u8> {
+    bitio::pack(|w| {
+        let mut enc = ArithmeticEncoder::new();
+        for &b in data {
+            enc.encode_symbol(&mut model, b, w)?;
+        }
+        enc.finish(w)
+    }).0
+}
+
+// Decode `len` symbols; `model` must start out as the encoder's did
+pub fn decode<M: Model>(bytes: &[u8], len: usize, mut model: M) -> Result<Vec<u8>, ArithError> {
+    let mut input = BitReader::new(bytes);
+    let mut dec = ArithmeticDecoder::new(&mut input).map_err(from_io)?;
+    let mut out = Vec::with_capacity(len.min(1 << 20));
+    for _ in 0..len {
+        out.push(dec.decode_symbol(&mut model, &mut input).map_err(from_io)?);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{decode, encode, from_io, ArithError, ArithmeticDecoder};
+    use crate::bitio::BitReader;
+    use crate::models::Order0;
+    use std::io;
+
+    #[test]
+    fn test_round_trip() {
+        let samples: Vec<Vec<u8>> = vec![Vec::new(), b"a".to_vec(), b"abracadabra".to_vec(),
+                                         (0..=255u8).collect(), vec![0; 10_000],
+                                         b"the quick brown fox jumped over the lazy dog".repeat(100)];
+        for data in samples {
+            let packed = encode(&data, Order0::new());
+            assert_eq!(decode(&packed, data.len(), Order0::new()).unwrap(), data);
+        }
+
+        // a long run costs next to nothing once the model has caught on
+        assert!(encode(&[7; 10_000], Order0::new()).len() < 100);
+        let packed = encode(b"abracadabra".repeat(50).as_slice(), Order0::new());
+        assert_eq!(decode(&packed[..packed.len() / 2], 550, Order0::new()), Err(ArithError::Truncated));
+    }
+
+    #[test]
+    fn test_bad_ranges() {
+        let mut input = BitReader::new(&[0x5a; 8][..]);
+        let mut dec = ArithmeticDecoder::new(&mut input).unwrap();
+        let corrupt = |e: io::Error| from_io(e) == ArithError::Corrupt;
+        assert!(dec.target(0).is_err_and(corrupt));
+        assert!(dec.consume(0, 1, 0, &mut input).is_err_and(corrupt));
+        assert!(dec.consume(3, 2, 4, &mut input).is_err_and(corrupt));
+        assert!(dec.consume(0, 0, 4, &mut input).is_err_and(corrupt));
+        assert!(dec.target(4).unwrap() < 4);
+    }
+
+}
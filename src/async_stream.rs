@@ -0,0 +1,221 @@
+// The streaming Huffman adapters of stream.rs for tokio: the same stream
+// layout, written through an AsyncWrite and read from an AsyncRead, so a
+// server can compress a response as it goes out without blocking its
+// runtime.
+//
+// Both are loops around a StreamingCoder, so neither holds more than
+// about CHUNK coded bytes. AsyncHuffmanEncoder only takes new input once
+// the bytes coded so far have gone to the writer, and then only as much as
+// codes to CHUNK bytes: a slow peer holds back the producer rather than
+// growing the buffer. AsyncHuffmanDecoder reads from its reader only when
+// the coder has nothing left to give. There is no Drop finishing the
+// stream here: call shutdown() (AsyncWriteExt) to write the trailer.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::huffman::ByteHuffman;
+use crate::stream::{Status, StreamingCoder, CHUNK};
+
+pub struct AsyncHuffmanEncoder<W> {
+    inner: W,
+    coder: StreamingCoder,
+    // Drained from the coder but not yet accepted by `inner`: out[pos..]
+    out: Vec<u8>,
+    pos: usize,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncHuffmanEncoder<W> {
+
+    pub fn new(inner: W, code: ByteHuffman) -> Self {
+        AsyncHuffmanEncoder { inner, coder: StreamingCoder::encoder(code), out: Vec::new(), pos: 0 }
+    }
+
+    pub fn code(&self) -> &ByteHuffman {
+        self.coder.code()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    // Coded bytes not yet written are lost unless shutdown() has completed
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn poll_dump(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            while self.pos < self.out.len() {
+                let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.out[self.pos..]))?;
+                if n == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                self.pos += n;
+            }
+            self.out.resize(CHUNK, 0);
+            let n = self.coder.drain(&mut self.out)?.written();
+            self.out.truncate(n);
+            self.pos = 0;
+            if n == 0 {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncHuffmanEncoder<W> {
+
+    // Bytes without a codeword fail with InvalidInput, as for HuffmanEncoder
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_dump(cx))?;
+        Poll::Ready(Ok(this.coder.feed(buf)?))
+    }
+
+    // Bits of a partial byte stay buffered, as for HuffmanEncoder
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_dump(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.coder.finish()?;
+        ready!(this.poll_dump(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+
+}
+
+pub struct AsyncHuffmanDecoder<R> {
+    inner: R,
+    coder: StreamingCoder,
+}
+
+impl<R: AsyncRead + Unpin> AsyncHuffmanDecoder<R> {
+
+    pub fn new(inner: R, code: ByteHuffman) -> Self {
+        AsyncHuffmanDecoder { inner, coder: StreamingCoder::decoder(code) }
+    }
+
+    pub fn code(&self) -> &ByteHuffman {
+        self.coder.code()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    // Compressed bytes already pulled from the reader are lost
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncHuffmanDecoder<R> {
+
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, out: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if out.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        let mut chunk = [0u8; CHUNK];
+        loop {
+            match this.coder.drain(out.initialize_unfilled())? {
+                Status::NeedsInput(0) => {}
+                status => {
+                    out.advance(status.written());
+                    return Poll::Ready(Ok(()));
+                }
+            }
+            let mut input = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut input))?;
+            if input.filled().is_empty() {
+                this.coder.finish()?;
+            } else {
+                this.coder.feed(input.filled())?;
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{AsyncHuffmanDecoder, AsyncHuffmanEncoder};
+    use crate::huffman::ByteHuffman;
+    use crate::stream::HuffmanEncoder;
+    use std::io::{self, Write};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_encoder() {
+        let data = b"the quick brown fox jumped over the lazy dog".repeat(500);
+        let code = ByteHuffman::new_bytes(&data);
+        let mut encoder = AsyncHuffmanEncoder::new(Vec::new(), code.clone());
+        for chunk in data.chunks(7) {
+            encoder.write_all(chunk).await.unwrap();
+        }
+        encoder.shutdown().await.unwrap();
+
+        // the same bytes as the blocking encoder
+        let mut expected = HuffmanEncoder::new(Vec::new(), code);
+        expected.write_all(&data).unwrap();
+        assert_eq!(encoder.into_inner(), expected.finish().unwrap());
+
+        // one write takes no more than codes to a CHUNK
+        let mut encoder = AsyncHuffmanEncoder::new(Vec::new(), ByteHuffman::new_bytes(&data));
+        let n = encoder.write(&data).await.unwrap();
+        assert!(n > 0 && n < data.len());
+
+        let mut encoder = AsyncHuffmanEncoder::new(Vec::new(), ByteHuffman::new_bytes(b"abc"));
+        assert_eq!(encoder.write(b"abxc").await.unwrap(), 2);
+        assert_eq!(encoder.write(b"xc").await.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        encoder.shutdown().await.unwrap();
+        assert!(encoder.write(b"a").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251 * (i % 7)) as u8 % 16).collect();
+        let code = ByteHuffman::new_bytes(&data);
+
+        // a pipe with a small buffer: the writer has to wait on the reader
+        let (tx, rx) = tokio::io::duplex(64);
+        let writer = {
+            let (data, code) = (data.clone(), code.clone());
+            tokio::spawn(async move {
+                let mut encoder = AsyncHuffmanEncoder::new(tx, code);
+                encoder.write_all(&data).await.unwrap();
+                encoder.shutdown().await.unwrap();
+            })
+        };
+        let mut decoded = Vec::new();
+        AsyncHuffmanDecoder::new(rx, code.clone()).read_to_end(&mut decoded).await.unwrap();
+        writer.await.unwrap();
+        assert!(decoded == data);
+
+        let mut sink = Vec::new();
+        let err = AsyncHuffmanDecoder::new(&b""[..], code.clone()).read_to_end(&mut sink).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = AsyncHuffmanDecoder::new(&[9u8][..], code).read_to_end(&mut sink).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+}
@@ -0,0 +1,475 @@
+// Command-line front end to the container format and profiles:
+//
+//   entrust compress [-l LEVEL] [-b BLOCK_SIZE] [-c CHECKSUM] [INPUT [OUTPUT]]
+//   entrust decompress [INPUT [OUTPUT]]
+//   entrust inspect [-t TABLE] [-n NBITS] [INPUT]
+//   entrust train [-n NAME] PATH...
+//   entrust archive create [-l LEVEL] [-c CHECKSUM] ARCHIVE PATH...
+//   entrust archive extract ARCHIVE [DIR]
+//   entrust archive list ARCHIVE
+//   entrust eval [-f csv|json] [-r RUNS] [-m METHOD,...] DIR
+//
+// A missing or "-" INPUT/OUTPUT is stdin/stdout. inspect describes a
+// container (or, given anything else, the code that would be built for
+// it); with -t or -n it dumps INPUT as a bit stream instead, split into
+// the codewords of TABLE (a ByteHuffman serialize_table file) if given.
+// NBITS is how many of its bits count, by default all of them. train
+// prints a byte profile of the files and directories given, as a Rust
+// table like those in src/profiles/tables.rs. archive create stores each
+// PATH (a directory with everything under it) by its last component,
+// LZSS compressed; extract writes the files under DIR, by default the
+// current directory. eval runs every codec (or the METHODs named, as
+// eval::Method::name gives them) over each file under DIR and prints the
+// sizes and speeds, best of RUNS, as CSV (the default) or JSON.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+
+use entrust::archive::{self, Archive};
+use entrust::checksum::ChecksumKind;
+use entrust::container::{self, Codec, CompressOptions, ECC_MAGIC, MAGIC};
+use entrust::eval::{Evaluation, Method};
+use entrust::huffman::ByteHuffman;
+use entrust::inspect as bitdump;
+use entrust::Bits;
+use entrust::profiles::ProfileBuilder;
+use entrust::stats::CompressionReport;
+
+const USAGE: &str = "usage:
+    entrust compress [-l 1-9] [-b BLOCK_SIZE] [-c none|crc16|crc32|crc32c|adler32|xxhash64] [-e PARITY]
+                     [INPUT [OUTPUT]]
+    entrust decompress [INPUT [OUTPUT]]
+    entrust inspect [-t TABLE] [-n NBITS] [INPUT]
+    entrust train [-n NAME] PATH...
+    entrust archive create [-l 1-9] [-c CHECKSUM] ARCHIVE PATH...
+    entrust archive extract ARCHIVE [DIR]
+    entrust archive list ARCHIVE
+    entrust eval [-f csv|json] [-r RUNS] [-m METHOD,...] DIR";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Compress { options: CompressOptions, input: Option<String>, output: Option<String> },
+    Decompress { input: Option<String>, output: Option<String> },
+    Inspect { input: Option<String>, table: Option<String>, nbits: Option<usize> },
+    Train { name: String, paths: Vec<String> },
+    ArchiveCreate { options: CompressOptions, archive: String, paths: Vec<String> },
+    ArchiveExtract { archive: String, dir: Option<String> },
+    ArchiveList { archive: String },
+    Eval { dir: String, json: bool, runs: u32, methods: Option<Vec<Method>> },
+}
+
+#[derive(Debug, PartialEq)]
+struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\n{}", self.0, USAGE)
+    }
+}
+
+fn usage<T>(msg: &str) -> Result<T, UsageError> {
+    Err(UsageError(msg.to_string()))
+}
+
+fn parse_args(args: &[String]) -> Result<Command, UsageError> {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => return usage("missing command"),
+    };
+    // archive's actions parse as commands of their own
+    let (command, rest) = match (command, rest.split_first()) {
+        ("archive", Some((action, rest))) => (format!("archive {}", action), rest),
+        ("archive", None) => return usage("archive needs create, extract or list"),
+        _ => (command.to_string(), rest),
+    };
+    let command = command.as_str();
+
+    // Flags taking a value, then positional arguments
+    let mut flags: Vec<(&str, &str)> = Vec::new();
+    let mut positional: Vec<String> = Vec::new();
+    let mut iter = rest.iter();
+    while let Some(arg) = iter.next() {
+        if arg.len() > 1 && arg.starts_with('-') {
+            match iter.next() {
+                Some(value) => flags.push((arg, value)),
+                None => return usage(&format!("{} needs a value", arg)),
+            }
+        } else {
+            positional.push(arg.clone());
+        }
+    }
+    let allowed: &[&str] = match command {
+        "compress" => &["-l", "-b", "-c", "-e"],
+        "inspect" => &["-t", "-n"],
+        "train" => &["-n"],
+        "archive create" => &["-l", "-c"],
+        "eval" => &["-f", "-r", "-m"],
+        _ => &[],
+    };
+    if let Some((flag, _)) = flags.iter().find(|(flag, _)| !allowed.contains(flag)) {
+        return usage(&format!("unknown option {} for {}", flag, command));
+    }
+    let max_positional = match command {
+        "compress" | "decompress" | "archive extract" => 2,
+        "inspect" | "archive list" | "eval" => 1,
+        _ => usize::MAX,
+    };
+    if positional.len() > max_positional {
+        return usage(&format!("too many arguments for {}", command));
+    }
+    let mut positional = positional.into_iter();
+
+    Ok(match command {
+        "compress" => {
+            let options = compress_options(CompressOptions::new(Codec::Huffman), &mut flags)?;
+            Command::Compress { options, input: positional.next(), output: positional.next() }
+        }
+        "decompress" => Command::Decompress { input: positional.next(), output: positional.next() },
+        "inspect" => {
+            let mut table = None;
+            let mut nbits = None;
+            for &(flag, value) in &flags {
+                match flag {
+                    "-t" => table = Some(value.to_string()),
+                    _ => match value.parse::<usize>() {
+                        Ok(n) => nbits = Some(n),
+                        Err(_) => return usage(&format!("bad bit count {}", value)),
+                    },
+                }
+            }
+            Command::Inspect { input: positional.next(), table, nbits }
+        }
+        "train" => {
+            let paths: Vec<String> = positional.collect();
+            if paths.is_empty() {
+                return usage("train needs at least one file or directory");
+            }
+            let name = flags.last().map_or("CUSTOM", |&(_, name)| name).to_string();
+            Command::Train { name, paths }
+        }
+        "archive create" => {
+            let options = compress_options(CompressOptions::new(Codec::Lzss), &mut flags)?;
+            let archive = positional.next();
+            let paths: Vec<String> = positional.collect();
+            match archive {
+                Some(archive) if !paths.is_empty() => Command::ArchiveCreate { options, archive, paths },
+                _ => return usage("archive create needs an archive and at least one file or directory"),
+            }
+        }
+        "archive extract" | "archive list" => {
+            let archive = match positional.next() {
+                Some(archive) => archive,
+                None => return usage(&format!("{} needs an archive", command)),
+            };
+            match command {
+                "archive list" => Command::ArchiveList { archive },
+                _ => Command::ArchiveExtract { archive, dir: positional.next() },
+            }
+        }
+        "eval" => {
+            let (mut json, mut runs, mut methods) = (false, 1, None);
+            for &(flag, value) in &flags {
+                match flag {
+                    "-f" => match value {
+                        "csv" | "json" => json = value == "json",
+                        _ => return usage(&format!("unknown format {}", value)),
+                    },
+                    "-r" => match value.parse::<u32>() {
+                        Ok(n) if n > 0 => runs = n,
+                        _ => return usage(&format!("bad run count {}", value)),
+                    },
+                    _ => {
+                        let named: Option<Vec<Method>> = value.split(',').map(Method::from_name).collect();
+                        match named {
+                            Some(named) => methods = Some(named),
+                            None => return usage(&format!("unknown method in {}", value)),
+                        }
+                    }
+                }
+            }
+            match positional.next() {
+                Some(dir) => Command::Eval { dir, json, runs, methods },
+                None => return usage("eval needs a corpus directory"),
+            }
+        }
+        _ => return usage(&format!("unknown command {}", command)),
+    })
+}
+
+// Applies -l, -b, -c and -e to `options`
+fn compress_options(mut options: CompressOptions, flags: &mut [(&str, &str)]) -> Result<CompressOptions, UsageError> {
+    // the level first, so it doesn't undo an explicit block size
+    flags.sort_by_key(|&(flag, _)| flag != "-l");
+    for &(flag, value) in flags.iter() {
+        options = match flag {
+            "-l" => match value.parse::<u8>() {
+                Ok(level) if (1..=9).contains(&level) => options.level(level),
+                _ => return usage(&format!("bad level {}", value)),
+            },
+            "-b" => match value.parse::<u32>() {
+                Ok(size) if size > 0 => options.block_size(size as usize),
+                _ => return usage(&format!("bad block size {}", value)),
+            },
+            "-e" => match value.parse::<u8>() {
+                Ok(parity) if parity < 255 => options.ecc(parity),
+                _ => return usage(&format!("bad parity count {}", value)),
+            },
+            _ => match ChecksumKind::from_name(value) {
+                Some(kind) => options.checksum(kind),
+                None => return usage(&format!("unknown checksum {}", value)),
+            },
+        };
+    }
+    Ok(options)
+}
+
+fn read_input(input: &Option<String>) -> io::Result<Vec<u8>> {
+    match input.as_deref() {
+        None | Some("-") => {
+            let mut data = Vec::new();
+            io::stdin().read_to_end(&mut data)?;
+            Ok(data)
+        }
+        Some(path) => fs::read(path),
+    }
+}
+
+fn write_output(output: &Option<String>, data: &[u8]) -> io::Result<()> {
+    match output.as_deref() {
+        None | Some("-") => {
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+            out.write_all(data)?;
+            out.flush()
+        }
+        Some(path) => fs::write(path, data),
+    }
+}
+
+fn inspect(data: &[u8], out: &mut dyn Write) -> entrust::Result<()> {
+    if data.starts_with(&MAGIC) || data.starts_with(&ECC_MAGIC) {
+        let header = container::read_header(data)?;
+        writeln!(out, "container version {}", header.version)?;
+        let mode = match (header.blocked, header.seekable) {
+            (_, true) => " (blocks, seekable)",
+            (true, false) => " (blocks)",
+            _ => "",
+        };
+        writeln!(out, "codec:      {:?}{}", header.codec, mode)?;
+        writeln!(out, "checksum:   {}", header.checksum.name())?;
+        if header.ecc > 0 {
+            writeln!(out, "ecc:        Reed-Solomon, {} parity bytes per 255", header.ecc)?;
+        }
+        writeln!(out, "original:   {} bytes", header.len)?;
+        writeln!(out, "compressed: {} bytes", data.len())?;
+        if header.len > 0 {
+            writeln!(out, "ratio:      {:.4}", data.len() as f64 / header.len as f64)?;
+        }
+        let decoded = container::decompress_from_slice(data)?;
+        writeln!(out, "contents:   ok, {} bytes", decoded.len())?;
+        return Ok(());
+    }
+
+    let code = ByteHuffman::try_new_bytes(data)?;
+    let report = CompressionReport::for_bytes(&code, data)?;
+    writeln!(out, "{} bytes, {} distinct", data.len(), report.code_lengths.len())?;
+    writeln!(out, "entropy:        {:.4} bits/byte", report.entropy)?;
+    writeln!(out, "average length: {:.4} bits/byte", report.average_length)?;
+    writeln!(out, "efficiency:     {:.4}", report.efficiency)?;
+    writeln!(out, "ratio:          {:.4}", report.ratio)?;
+    writeln!(out, "\nbyte   count  codeword")?;
+    for (b, _) in code.code_lengths() {
+        let shown = if b.is_ascii_graphic() || b == b' ' { format!("{:?}", b as char) } else { format!("{:#04x}", b) };
+        let codeword = code.code_for(&b).map(|bits| bits.to_bit_string()).unwrap_or_default();
+        writeln!(out, "{:<6} {:>6}  {}", shown, code.frequencies()[&b], codeword)?;
+    }
+    Ok(())
+}
+
+// The first `nbits` bits of `data` (all of them if None), laid out by
+// inspect::dump, or split into codewords if there's a code
+fn inspect_bits(data: &[u8], code: Option<&ByteHuffman>, nbits: Option<usize>, out: &mut dyn Write)
+    -> entrust::Result<()> {
+    let nbits = nbits.unwrap_or(data.len() * 8);
+    if nbits > data.len() * 8 {
+        let msg = format!("{} bits asked for, the input has {}", nbits, data.len() * 8);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into());
+    }
+    let bits = Bits::new(data.to_vec(), nbits);
+    match code {
+        Some(code) => out.write_all(bitdump::dump_with(&bits, code).as_bytes())?,
+        None => out.write_all(bitdump::dump(&bits).as_bytes())?,
+    }
+    Ok(())
+}
+
+fn run(command: Command) -> entrust::Result<()> {
+    match command {
+        Command::Compress { options, input, output } => {
+            let data = read_input(&input)?;
+            write_output(&output, &container::compress_with(&data, &options))?;
+        }
+        Command::Decompress { input, output } => {
+            let data = read_input(&input)?;
+            write_output(&output, &container::decompress_from_slice(&data)?)?;
+        }
+        Command::Inspect { input, table, nbits } => {
+            let data = read_input(&input)?;
+            let stdout = io::stdout();
+            if table.is_none() && nbits.is_none() {
+                inspect(&data, &mut stdout.lock())?;
+            } else {
+                let code = match table {
+                    Some(path) => Some(ByteHuffman::from_table(&fs::read(path)?)?),
+                    None => None,
+                };
+                inspect_bits(&data, code.as_ref(), nbits, &mut stdout.lock())?;
+            }
+        }
+        Command::Train { name, paths } => {
+            let mut builder = ProfileBuilder::new();
+            for path in &paths {
+                if fs::metadata(path)?.is_dir() {
+                    builder.add_dir(path)?;
+                } else {
+                    builder.add_file(path)?;
+                }
+            }
+            print!("{}", builder.to_rust(&name));
+        }
+        Command::ArchiveCreate { options, archive, paths } => {
+            let mut contents = Archive::new();
+            for path in &paths {
+                let name = match Path::new(path).file_name().and_then(|name| name.to_str()) {
+                    Some(name) => name,
+                    None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("can't archive {}", path)).into()),
+                };
+                if fs::metadata(path)?.is_dir() {
+                    contents.add_dir(path, name)?;
+                } else {
+                    contents.add_file(path, name)?;
+                }
+            }
+            contents.save(&archive, &options)?;
+        }
+        Command::ArchiveExtract { archive, dir } => {
+            Archive::open(&archive)?.extract(dir.as_deref().unwrap_or("."))?;
+        }
+        Command::ArchiveList { archive } => {
+            for entry in archive::list(&fs::read(&archive)?)? {
+                println!("{:04o} {:>12} {:>12} {}", entry.mode, entry.size, entry.mtime, entry.path);
+            }
+        }
+        Command::Eval { dir, json, runs, methods } => {
+            let mut eval = Evaluation::new().runs(runs);
+            if let Some(methods) = methods {
+                eval = eval.methods(&methods);
+            }
+            let report = eval.run_dir(&dir)?;
+            print!("{}", if json { report.to_json() } else { report.to_csv() });
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match parse_args(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("entrust: {}", e);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(command) {
+        eprintln!("entrust: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{inspect, inspect_bits, parse_args, Command};
+    use entrust::huffman::ByteHuffman;
+    use entrust::checksum::ChecksumKind;
+    use entrust::coder::CodecId;
+    use entrust::container::{self, Codec, CompressOptions};
+    use entrust::eval::Method;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = CompressOptions::new(Codec::Huffman).block_size(4096).checksum(ChecksumKind::XxHash64);
+        assert_eq!(parse_args(&args("compress -b 4096 in -c xxhash64 out")),
+                   Ok(Command::Compress { options, input: Some("in".into()), output: Some("out".into()) }));
+        let options = CompressOptions::new(Codec::Huffman).level(9).block_size(4096).ecc(16);
+        assert_eq!(parse_args(&args("compress -b 4096 -e 16 -l 9")),
+                   Ok(Command::Compress { options, input: None, output: None }));
+        assert_eq!(parse_args(&args("decompress -")), Ok(Command::Decompress { input: Some("-".into()), output: None }));
+        assert_eq!(parse_args(&args("train a b")),
+                   Ok(Command::Train { name: "CUSTOM".into(), paths: vec!["a".into(), "b".into()] }));
+        let options = CompressOptions::new(Codec::Lzss).level(9);
+        assert_eq!(parse_args(&args("archive create -l 9 out.entr a b")),
+                   Ok(Command::ArchiveCreate { options, archive: "out.entr".into(), paths: vec!["a".into(), "b".into()] }));
+        assert_eq!(parse_args(&args("archive extract out.entr")),
+                   Ok(Command::ArchiveExtract { archive: "out.entr".into(), dir: None }));
+        assert_eq!(parse_args(&args("archive list out.entr")), Ok(Command::ArchiveList { archive: "out.entr".into() }));
+        assert_eq!(parse_args(&args("inspect -t table -n 37 in")),
+                   Ok(Command::Inspect { input: Some("in".into()), table: Some("table".into()), nbits: Some(37) }));
+        assert_eq!(parse_args(&args("eval corpus")),
+                   Ok(Command::Eval { dir: "corpus".into(), json: false, runs: 1, methods: None }));
+        assert_eq!(parse_args(&args("eval -f json -r 5 -m rans,container-lzss corpus")),
+                   Ok(Command::Eval { dir: "corpus".into(), json: true, runs: 5,
+                                      methods: Some(vec![Method::Coder(CodecId::Rans), Method::Container(Codec::Lzss)]) }));
+
+        for bad in ["", "frobnicate", "compress -b 0", "compress -c md5", "compress -l 0", "compress -l x", "compress -b", "decompress -b 1",
+                    "inspect a b", "inspect -n x", "inspect -l 3", "compress a b c", "train", "train -n X",
+                    "archive", "archive pack x y", "archive create x", "archive create -b 10 x y", "archive list",
+                    "archive list x y", "archive extract a b c", "eval", "eval -f xml d", "eval -r 0 d",
+                    "eval -m zip d", "eval a b"].iter() {
+            assert!(parse_args(&args(bad)).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_inspect() {
+        let data = b"she sells sea shells by the sea shore".repeat(4);
+        let mut out = Vec::new();
+        inspect(&data, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("148 bytes, 11 distinct\n"));
+        assert!(text.contains("\n' '        28  "));
+
+        let mut out = Vec::new();
+        inspect(&container::compress_to_vec(&data, Codec::Huffman), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("original:   148 bytes"));
+        assert!(text.contains("contents:   ok, 148 bytes"));
+        assert!(inspect(b"", &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_inspect_bits() {
+        let code = ByteHuffman::new_bytes(b"aaab");
+        let (packed, nbits) = code.encode_bytes_packed(b"aab");
+        let mut out = Vec::new();
+        inspect_bits(&packed, Some(&code), Some(nbits), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.ends_with("3 bits, 3 symbols\n"), "{}", text);
+        assert!(text.contains("  98\n"));
+
+        let mut out = Vec::new();
+        inspect_bits(&[0xa5], None, None, &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("10100101"));
+        assert!(inspect_bits(&[0xa5], None, Some(9), &mut Vec::new()).is_err());
+    }
+
+}
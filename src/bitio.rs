@@ -0,0 +1,501 @@
+// Bit-level I/O shared by the coders in this crate. Fields of up to 64
+// bits are written to / read from any byte sink or source; BitOrder says
+// how bits are packed into each byte.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::iter::FromIterator;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOrder {
+    // First bit goes to the most significant bit of each byte, and fields
+    // are written most significant bit first (JPEG, this crate's default).
+    MsbFirst,
+    // First bit goes to the least significant bit of each byte, and fields
+    // are written least significant bit first (DEFLATE).
+    LsbFirst,
+}
+
+pub struct BitWriter<W: Write> {
+    inner: W,
+    order: BitOrder,
+    cur: u8,    // partially filled byte
+    used: u32,  // bits of `cur` in use
+    written: u64,
+}
+
+impl<W: Write> BitWriter<W> {
+
+    pub fn new(inner: W) -> Self {
+        BitWriter::with_order(inner, BitOrder::MsbFirst)
+    }
+
+    pub fn with_order(inner: W, order: BitOrder) -> Self {
+        BitWriter { inner, order, cur: 0, used: 0, written: 0 }
+    }
+
+    pub fn write_bit(&mut self, bit: bool) -> io::Result<()> {
+        let shift = match self.order {
+            BitOrder::MsbFirst => 7 - self.used,
+            BitOrder::LsbFirst => self.used,
+        };
+        self.cur |= (bit as u8) << shift;
+        self.used += 1;
+        self.written += 1;
+        if self.used == 8 {
+            self.inner.write_all(&[self.cur])?;
+            self.cur = 0;
+            self.used = 0;
+        }
+        Ok(())
+    }
+
+    // Write the low `n` bits of `value` (n <= 64)
+    pub fn write_bits(&mut self, value: u64, n: u32) -> io::Result<()> {
+        assert!(n <= 64, "can't write a {}-bit field", n);
+        match self.order {
+            BitOrder::MsbFirst => {
+                for i in (0..n).rev() {
+                    self.write_bit((value >> i) & 1 == 1)?;
+                }
+            }
+            BitOrder::LsbFirst => {
+                for i in 0..n {
+                    self.write_bit((value >> i) & 1 == 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Zero-pad to the next byte boundary
+    pub fn align(&mut self) -> io::Result<()> {
+        while self.used != 0 {
+            self.write_bit(false)?;
+        }
+        Ok(())
+    }
+
+    pub fn is_aligned(&self) -> bool {
+        self.used == 0
+    }
+
+    // Total bits written, including those still waiting in a partial byte
+    // and any padding align() (or flush, or into_inner) has added
+    pub fn bits_written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn order(&self) -> BitOrder {
+        self.order
+    }
+
+    // Align and flush the underlying writer
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.align()?;
+        self.inner.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    // Align and hand back the underlying writer
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.align()?;
+        Ok(self.inner)
+    }
+
+}
+
+pub struct BitReader<R: Read> {
+    inner: R,
+    order: BitOrder,
+    cur: u8,
+    left: u32,  // unread bits in `cur`
+    read: u64,
+}
+
+impl<R: Read> BitReader<R> {
+
+    pub fn new(inner: R) -> Self {
+        BitReader::with_order(inner, BitOrder::MsbFirst)
+    }
+
+    pub fn with_order(inner: R, order: BitOrder) -> Self {
+        BitReader { inner, order, cur: 0, left: 0, read: 0 }
+    }
+
+    // Fails with UnexpectedEof once the underlying reader is exhausted
+    pub fn read_bit(&mut self) -> io::Result<bool> {
+        if self.left == 0 {
+            let mut byte = [0u8];
+            self.inner.read_exact(&mut byte)?;
+            self.cur = byte[0];
+            self.left = 8;
+        }
+        let bit = match self.order {
+            BitOrder::MsbFirst => {
+                let bit = self.cur & 0x80 != 0;
+                self.cur <<= 1;
+                bit
+            }
+            BitOrder::LsbFirst => {
+                let bit = self.cur & 1 != 0;
+                self.cur >>= 1;
+                bit
+            }
+        };
+        self.left -= 1;
+        self.read += 1;
+        Ok(bit)
+    }
+
+    // Read an `n`-bit field (n <= 64), the counterpart of write_bits
+    pub fn read_bits(&mut self, n: u32) -> io::Result<u64> {
+        assert!(n <= 64, "can't read a {}-bit field", n);
+        let mut value = 0u64;
+        for i in 0..n {
+            let bit = self.read_bit()? as u64;
+            match self.order {
+                BitOrder::MsbFirst => value = (value << 1) | bit,
+                BitOrder::LsbFirst => value |= bit << i,
+            }
+        }
+        Ok(value)
+    }
+
+    // Skip the rest of the current byte
+    pub fn align(&mut self) {
+        self.read += self.left as u64;
+        self.left = 0;
+    }
+
+    pub fn is_aligned(&self) -> bool {
+        self.left == 0
+    }
+
+    pub fn bits_read(&self) -> u64 {
+        self.read
+    }
+
+    pub fn order(&self) -> BitOrder {
+        self.order
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    // Any bits left in a partially read byte are dropped
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+}
+
+// A bit string packed MSB-first into bytes, with the number of bits that
+// count. Padding bits in the last byte are always zero, so equal bit
+// strings compare equal. It's what the encoders in this crate hand back and
+// the decoders take, and what codewords are made of.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Bits {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Bits {
+
+    // Bytes past the ones `len` needs are dropped and padding is cleared.
+    // Panics if `len` needs more bytes than given.
+    pub fn new(mut bytes: Vec<u8>, len: usize) -> Self {
+        assert!(len <= bytes.len() * 8, "bit length exceeds the bytes given");
+        bytes.truncate(len.div_ceil(8));
+        if !len.is_multiple_of(8) {
+            *bytes.last_mut().unwrap() &= 0xff << (8 - len % 8);
+        }
+        Bits { bytes, len }
+    }
+
+    // Room for `nbits` bits before reallocating
+    pub fn with_capacity(nbits: usize) -> Self {
+        Bits { bytes: Vec::with_capacity(nbits.div_ceil(8)), len: 0 }
+    }
+
+    // None unless every character is '0' or '1'
+    pub fn from_bit_string(s: &str) -> Option<Self> {
+        s.chars().map(|x| match x {
+            '0' => Some(false),
+            '1' => Some(true),
+            _ => None,
+        }).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, bit: bool) {
+        if self.len.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> (self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    // Whole bytes are shifted in at once
+    pub fn append(&mut self, other: &Bits) {
+        let shift = self.len % 8;
+        if shift == 0 {
+            self.bytes.extend_from_slice(&other.bytes);
+        } else {
+            for &b in &other.bytes {
+                *self.bytes.last_mut().unwrap() |= b >> shift;
+                self.bytes.push(b << (8 - shift));
+            }
+            self.bytes.truncate((self.len + other.len).div_ceil(8));
+        }
+        self.len += other.len;
+    }
+
+    pub fn iter(&self) -> BitsIter<'_> {
+        BitsIter { bits: self, pos: 0 }
+    }
+
+    // A copy of the bits in `range`. Panics if it reaches past len()
+    pub fn slice(&self, range: Range<usize>) -> Bits {
+        assert!(range.start <= range.end && range.end <= self.len, "bit range out of bounds");
+        if range.start.is_multiple_of(8) {
+            return Bits::new(self.bytes[range.start / 8..range.end.div_ceil(8)].to_vec(), range.len());
+        }
+        range.map(|i| self.get(i)).collect()
+    }
+
+    // Panics unless i < len()
+    pub fn get(&self, i: usize) -> bool {
+        assert!(i < self.len, "bit index out of range");
+        self.bytes[i / 8] & (0x80 >> (i % 8)) != 0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    // The packed bytes and bit count, the form the `_packed` methods use
+    pub fn into_parts(self) -> (Vec<u8>, usize) {
+        (self.bytes, self.len)
+    }
+
+    // As '0'/'1' characters
+    pub fn to_bit_string(&self) -> String {
+        self.iter().map(|x| if x { '1' } else { '0' }).collect()
+    }
+
+}
+
+impl From<Vec<u8>> for Bits {
+    fn from(bytes: Vec<u8>) -> Self {
+        let len = bytes.len() * 8;
+        Bits { bytes, len }
+    }
+}
+
+impl FromIterator<bool> for Bits {
+    fn from_iter<I: IntoIterator<Item=bool>>(bits: I) -> Self {
+        let mut ret = Bits::default();
+        ret.extend(bits);
+        ret
+    }
+}
+
+impl Extend<bool> for Bits {
+    fn extend<I: IntoIterator<Item=bool>>(&mut self, bits: I) {
+        for bit in bits {
+            self.push(bit);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Bits {
+    type Item = bool;
+    type IntoIter = BitsIter<'a>;
+
+    fn into_iter(self) -> BitsIter<'a> {
+        self.iter()
+    }
+}
+
+// As '0'/'1' characters, like to_bit_string
+impl fmt::Display for Bits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(&self.to_bit_string())
+    }
+}
+
+pub struct BitsIter<'a> {
+    bits: &'a Bits,
+    pos: usize,
+}
+
+impl Iterator for BitsIter<'_> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        if self.pos == self.bits.len {
+            return None;
+        }
+        self.pos += 1;
+        Some(self.bits.get(self.pos - 1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.bits.len - self.pos;
+        (left, Some(left))
+    }
+}
+
+impl ExactSizeIterator for BitsIter<'_> {}
+
+// Concatenate bit strings into MSB-first packed bytes, returning them and
+// the number of meaningful bits. Whole bytes of each part are shifted in at
+// once, which is what makes the packed encoders cheap.
+pub(crate) fn concat<'a, I: IntoIterator<Item=&'a Bits>>(parts: I) -> (Vec<u8>, usize) {
+    let mut out = Vec::new();
+    let mut acc = 0u64; // pending bits, right-aligned
+    let mut pending = 0u32;
+    let mut nbits = 0;
+    for part in parts {
+        let full = part.len / 8;
+        for &b in &part.bytes[..full] {
+            acc = (acc << 8) | b as u64;
+            out.push((acc >> pending) as u8);
+        }
+        let rest = (part.len % 8) as u32;
+        if rest > 0 {
+            acc = (acc << rest) | (part.bytes[full] >> (8 - rest)) as u64;
+            pending += rest;
+            if pending >= 8 {
+                pending -= 8;
+                out.push((acc >> pending) as u8);
+            }
+        }
+        acc &= (1 << pending) - 1;
+        nbits += part.len;
+    }
+    if pending > 0 {
+        out.push((acc << (8 - pending)) as u8);
+    }
+    (out, nbits)
+}
+
+// Run `f` against a fresh MSB-first writer over a Vec, returning the packed
+// bytes and the number of meaningful bits in them.
+pub(crate) fn pack<F>(f: F) -> (Vec<u8>, usize)
+    where F: FnOnce(&mut BitWriter<&mut Vec<u8>>) -> io::Result<()> {
+    let mut out = Vec::new();
+    let mut writer = BitWriter::new(&mut out);
+    f(&mut writer).expect("writing to a Vec can't fail");
+    let nbits = writer.bits_written() as usize;
+    writer.align().expect("writing to a Vec can't fail");
+    (out, nbits)
+}
+
+// The first `nbits` bits of an MSB-first packed buffer
+pub(crate) fn unpack(bytes: &[u8], nbits: usize) -> impl Iterator<Item=bool> + '_ {
+    let mut reader = BitReader::new(bytes);
+    (0..nbits).map_while(move |_| reader.read_bit().ok())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{concat, BitOrder, BitReader, BitWriter, Bits};
+    use std::io;
+
+    #[test]
+    fn test_bit_packing() {
+        let mut msb = BitWriter::new(Vec::new());
+        msb.write_bits(0b101, 3).unwrap();
+        msb.write_bits(0b11, 2).unwrap();
+        assert_eq!(msb.bits_written(), 5);
+        assert_eq!(msb.into_inner().unwrap(), vec![0b1011_1000]);
+
+        let mut lsb = BitWriter::with_order(Vec::new(), BitOrder::LsbFirst);
+        lsb.write_bits(0b101, 3).unwrap();
+        lsb.write_bits(0b11, 2).unwrap();
+        assert_eq!(lsb.into_inner().unwrap(), vec![0b0001_1101]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let fields: Vec<(u64, u32)> = vec![(1, 1), (0, 3), (0x1234, 13), (u64::MAX, 64),
+                                           (0xab, 8), (5, 7), (0, 0), (0x1_0000_0001, 33)];
+        for &order in [BitOrder::MsbFirst, BitOrder::LsbFirst].iter() {
+            let mut writer = BitWriter::with_order(Vec::new(), order);
+            for &(value, n) in &fields {
+                writer.write_bits(value, n).unwrap();
+            }
+            writer.align().unwrap();
+            assert!(writer.is_aligned());
+            writer.write_bits(0x5a, 8).unwrap();
+            let bytes = writer.into_inner().unwrap();
+
+            let mut reader = BitReader::with_order(&bytes[..], order);
+            for &(value, n) in &fields {
+                assert_eq!(reader.read_bits(n).unwrap(), value);
+            }
+            reader.align();
+            assert_eq!(reader.read_bits(8).unwrap(), 0x5a);
+            assert_eq!(reader.bits_read() as usize, bytes.len() * 8);
+            assert_eq!(reader.read_bit().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn test_bits() {
+        let bits = Bits::from_bit_string("1011001").unwrap();
+        assert_eq!((bits.as_bytes(), bits.len()), (&[0b1011_0010][..], 7));
+        assert_eq!(bits.to_bit_string(), "1011001");
+        assert_eq!(Bits::from_bit_string("10x"), None);
+        // padding doesn't take part in comparisons
+        assert_eq!(Bits::new(vec![0b1011_0011, 0xff], 7), bits);
+
+        let parts: Vec<Bits> = ["101", "", "11110000111", "0", "1100110011001100110"].iter()
+            .map(|s| Bits::from_bit_string(s).unwrap())
+            .collect();
+        let (packed, nbits) = concat(&parts);
+        let joined: String = parts.iter().map(|b| b.to_bit_string()).collect();
+        assert_eq!(Bits::new(packed.clone(), nbits).to_bit_string(), joined);
+
+        let mut appended = Bits::with_capacity(nbits);
+        for part in &parts {
+            appended.append(part);
+        }
+        assert_eq!(appended.to_string(), joined);
+        assert_eq!(appended.iter().len(), nbits);
+        for (start, end) in [(0, 0), (0, 14), (3, 20), (8, 33), (33, 34)] {
+            assert_eq!(appended.slice(start..end).to_string(), joined[start..end]);
+        }
+        assert_eq!(appended.into_parts(), (packed, nbits));
+    }
+
+}
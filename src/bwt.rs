@@ -0,0 +1,263 @@
+// Burrows-Wheeler transform. The forward transform sorts the suffixes of
+// the input plus an end-of-text sentinel (smaller than every byte) and
+// outputs the byte before each one; bytes followed by similar contexts end
+// up together, which move-to-front and run-length coding then exploit. The
+// sentinel itself isn't output: its row is returned as the primary index,
+// which the inverse needs.
+//
+// Suffixes are sorted in linear time with SA-IS (Nong, Zhang & Chan,
+// "Two Efficient Algorithms for Linear Time Suffix Array Construction").
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BwtError {
+    // The primary index is past the end of the transformed data
+    InvalidPrimaryIndex,
+}
+
+impl fmt::Display for BwtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BwtError::InvalidPrimaryIndex => write!(f, "BWT primary index out of range"),
+        }
+    }
+}
+
+impl Error for BwtError {}
+
+// Starting positions of the suffixes of `data` in sorted order
+pub fn suffix_array(data: &[u8]) -> Vec<usize> {
+    let mut sa = sentinel_suffix_array(data);
+    sa.remove(0);
+    sa
+}
+
+// Suffix array of data plus sentinel; entry 0 is always the sentinel's
+fn sentinel_suffix_array(data: &[u8]) -> Vec<usize> {
+    let s: Vec<u32> = data.iter().map(|&b| b as u32 + 1).chain(std::iter::once(0)).collect();
+    sais(&s, 257)
+}
+
+// The transformed bytes and the primary index (in 0..=data.len())
+pub fn forward(data: &[u8]) -> (Vec<u8>, usize) {
+    let mut out = Vec::with_capacity(data.len());
+    let mut primary = 0;
+    for (row, &p) in sentinel_suffix_array(data).iter().enumerate() {
+        if p == 0 {
+            primary = row;
+        } else {
+            out.push(data[p - 1]);
+        }
+    }
+    (out, primary)
+}
+
+pub fn inverse(bwt: &[u8], primary: usize) -> Result<Vec<u8>, BwtError> {
+    let n = bwt.len();
+    if primary > n {
+        return Err(BwtError::InvalidPrimaryIndex);
+    }
+    // Last column of the sorted rows, with the sentinel put back (None)
+    let last = |row: usize| match row.cmp(&primary) {
+        std::cmp::Ordering::Less => Some(bwt[row]),
+        std::cmp::Ordering::Equal => None,
+        std::cmp::Ordering::Greater => Some(bwt[row - 1]),
+    };
+
+    // The sentinel's row comes first in the sorted rows, then each byte's
+    let mut starts = [0usize; 256];
+    let mut total = 1;
+    let mut counts = [0usize; 256];
+    for &b in bwt {
+        counts[b as usize] += 1;
+    }
+    for (start, &count) in starts.iter_mut().zip(counts.iter()) {
+        *start = total;
+        total += count;
+    }
+
+    // LF mapping: the row whose first byte is this row's last byte
+    let mut lf = vec![0usize; n + 1];
+    for (row, next) in lf.iter_mut().enumerate() {
+        if let Some(b) = last(row) {
+            *next = starts[b as usize];
+            starts[b as usize] += 1;
+        }
+    }
+
+    // The sentinel's row ends with the last byte of the input; walk back
+    let mut out = vec![0u8; n];
+    let mut row = 0;
+    for k in (0..n).rev() {
+        out[k] = last(row).ok_or(BwtError::InvalidPrimaryIndex)?;
+        row = lf[row];
+    }
+    Ok(out)
+}
+
+const EMPTY: usize = usize::MAX;
+
+// SA-IS over `s`, whose last element is a unique smallest sentinel and
+// whose values are all below `k`
+fn sais(s: &[u32], k: usize) -> Vec<usize> {
+    let n = s.len();
+    if n == 1 {
+        return vec![0];
+    }
+
+    // S-type suffixes are smaller than the suffix after them, L-type larger
+    let mut stype = vec![false; n];
+    stype[n - 1] = true;
+    for i in (0..n - 1).rev() {
+        stype[i] = s[i] < s[i + 1] || (s[i] == s[i + 1] && stype[i + 1]);
+    }
+    let is_lms = |i: usize| i > 0 && stype[i] && !stype[i - 1];
+
+    let mut bucket_sizes = vec![0usize; k];
+    for &c in s {
+        bucket_sizes[c as usize] += 1;
+    }
+
+    // Sort the LMS substrings by inducing from their arbitrarily ordered
+    // starting positions
+    let lms: Vec<usize> = (1..n).filter(|&i| is_lms(i)).collect();
+    let mut sa = vec![EMPTY; n];
+    place_lms(s, &mut sa, &bucket_sizes, lms.iter().cloned());
+    induce(s, &mut sa, &stype, &bucket_sizes);
+
+    // Name each LMS substring by its rank, equal substrings sharing a name
+    let mut names = vec![EMPTY; n];
+    let mut name = 0;
+    let mut prev: Option<usize> = None;
+    for &p in sa.iter().filter(|&&p| is_lms(p)) {
+        if let Some(q) = prev {
+            if !lms_substrings_equal(s, &stype, q, p) {
+                name += 1;
+            }
+        }
+        names[p] = name;
+        prev = Some(p);
+    }
+
+    // Sort the LMS suffixes: directly if the names are all distinct,
+    // otherwise by recursing on the string of names
+    let reduced: Vec<u32> = lms.iter().map(|&p| names[p] as u32).collect();
+    let order: Vec<usize> = if name + 1 == lms.len() {
+        let mut order = vec![0; lms.len()];
+        for (i, &r) in reduced.iter().enumerate() {
+            order[r as usize] = i;
+        }
+        order
+    } else {
+        sais(&reduced, name + 1)
+    };
+
+    sa.iter_mut().for_each(|p| *p = EMPTY);
+    place_lms(s, &mut sa, &bucket_sizes, order.iter().rev().map(|&i| lms[i]));
+    induce(s, &mut sa, &stype, &bucket_sizes);
+    sa
+}
+
+// Put LMS positions at the ends of their buckets, each in front of those
+// placed before it
+fn place_lms<I: Iterator<Item=usize>>(s: &[u32], sa: &mut [usize], bucket_sizes: &[usize], positions: I) {
+    let mut tails = bucket_ends(bucket_sizes);
+    for p in positions {
+        let c = s[p] as usize;
+        tails[c] -= 1;
+        sa[tails[c]] = p;
+    }
+}
+
+// Induce the L-type suffixes from the left, then the S-type from the right
+fn induce(s: &[u32], sa: &mut [usize], stype: &[bool], bucket_sizes: &[usize]) {
+    let mut heads: Vec<usize> = bucket_ends(bucket_sizes).iter().zip(bucket_sizes)
+        .map(|(end, size)| end - size)
+        .collect();
+    for i in 0..sa.len() {
+        if sa[i] != EMPTY && sa[i] > 0 && !stype[sa[i] - 1] {
+            let j = sa[i] - 1;
+            sa[heads[s[j] as usize]] = j;
+            heads[s[j] as usize] += 1;
+        }
+    }
+    let mut tails = bucket_ends(bucket_sizes);
+    for i in (0..sa.len()).rev() {
+        if sa[i] != EMPTY && sa[i] > 0 && stype[sa[i] - 1] {
+            let j = sa[i] - 1;
+            tails[s[j] as usize] -= 1;
+            sa[tails[s[j] as usize]] = j;
+        }
+    }
+}
+
+fn bucket_ends(bucket_sizes: &[usize]) -> Vec<usize> {
+    bucket_sizes.iter()
+        .scan(0, |end, size| {
+            *end += size;
+            Some(*end)
+        })
+        .collect()
+}
+
+// LMS substrings run from one LMS position to the next, inclusive
+fn lms_substrings_equal(s: &[u32], stype: &[bool], a: usize, b: usize) -> bool {
+    let is_lms = |i: usize| i > 0 && stype[i] && !stype[i - 1];
+    let n = s.len();
+    for d in 0.. {
+        if a + d == n || b + d == n || s[a + d] != s[b + d] || stype[a + d] != stype[b + d] {
+            return false;
+        }
+        if d > 0 && (is_lms(a + d) || is_lms(b + d)) {
+            return is_lms(a + d) && is_lms(b + d);
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{forward, inverse, suffix_array, BwtError};
+
+    fn samples() -> Vec<Vec<u8>> {
+        let mut x = 0x2545f491u32;
+        let noise: Vec<u8> = (0..5000).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            (x % 4) as u8
+        }).collect();
+        vec![b"".to_vec(), b"a".to_vec(), b"banana".to_vec(), b"mississippi".to_vec(),
+             vec![b'z'; 1000], b"abababababababababab".to_vec(), (0..=255u8).rev().collect(),
+             b"the quick brown fox jumped over the lazy dog".repeat(30), noise]
+    }
+
+    #[test]
+    fn test_suffix_array() {
+        for data in samples() {
+            let mut naive: Vec<usize> = (0..data.len()).collect();
+            naive.sort_by_key(|&i| &data[i..]);
+            assert_eq!(suffix_array(&data), naive);
+        }
+    }
+
+    #[test]
+    fn test_banana() {
+        // sorted rows: $, a$, ana$, anana$, banana$, na$, nana$
+        assert_eq!(forward(b"banana"), (b"annbaa".to_vec(), 4));
+        assert_eq!(inverse(b"annbaa", 4).unwrap(), b"banana");
+        assert_eq!(inverse(b"annbaa", 7), Err(BwtError::InvalidPrimaryIndex));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for data in samples() {
+            let (bwt, primary) = forward(&data);
+            assert_eq!(inverse(&bwt, primary).unwrap(), data);
+        }
+    }
+
+}
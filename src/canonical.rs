@@ -0,0 +1,378 @@
+use std::sync::Arc;
+
+use crate::bitio::{self, Bits};
+use crate::hash::FxHashMap;
+use crate::huffman::{DecodeError, EncodeError, Symbol, TableError};
+
+// A canonical Huffman code is fully determined by the codeword length of
+// each symbol: symbols are ordered by (length, symbol), the first gets the
+// all-zeros codeword and each next one gets the previous codeword plus one,
+// shifted left whenever the length grows. This is the convention DEFLATE
+// (RFC 1951, 3.2.2) and JPEG use, so only the lengths need transmitting.
+#[derive(Clone)]
+pub struct CanonicalHuffman {
+    // Symbols in canonical order
+    symbols: Arc<[char]>,
+    // counts[len] is the number of codewords of length `len` (counts[0] == 0)
+    counts: Vec<u32>,
+    // shared between clones, like HuffmanCode's tables
+    code: Arc<FxHashMap<char, Bits>>,
+}
+
+impl CanonicalHuffman {
+
+    pub fn from_lengths(lengths: &[(char, u8)]) -> Result<Self, TableError> {
+        let mut sorted: Vec<(u8, char)> = lengths.iter()
+            .filter(|(_, len)| *len > 0)
+            .map(|&(ch, len)| (len, ch))
+            .collect();
+        sorted.sort();
+        let mut counts = vec![0u32; sorted.last().map_or(0, |&(len, _)| len as usize)];
+        for &(len, _) in &sorted {
+            counts[len as usize - 1] += 1;
+        }
+        let symbols: Vec<char> = sorted.into_iter().map(|(_, ch)| ch).collect();
+        CanonicalHuffman::from_counts(&counts, &symbols)
+    }
+
+    // From the number of codewords of each length 1, 2, ... and the symbols
+    // in codeword order, the way a JPEG DHT segment lists them (BITS and
+    // HUFFVAL). Unlike from_lengths, symbols of one length keep the order
+    // given rather than being sorted.
+    pub fn from_counts(counts: &[u32], symbols: &[char]) -> Result<Self, TableError> {
+        let mut counts: Vec<u32> = std::iter::once(0).chain(counts.iter().cloned()).collect();
+        while counts.len() > 1 && counts[counts.len() - 1] == 0 {
+            counts.pop();
+        }
+        let total: u64 = counts.iter().map(|&n| n as u64).sum();
+        if total > symbols.len() as u64 {
+            return Err(TableError::Truncated);
+        }
+        if total < symbols.len() as u64 {
+            return Err(TableError::InvalidSymbol);
+        }
+        if !satisfies_kraft(&counts) {
+            return Err(TableError::Oversubscribed);
+        }
+
+        let mut code = FxHashMap::default();
+        let mut codeword: Vec<u8> = Vec::new(); // big-endian bits of the next codeword
+        let mut next = symbols.iter();
+        for (len, &count) in counts.iter().enumerate() {
+            codeword.resize(len, 0);
+            for _ in 0..count {
+                let ch = *next.next().expect("counted above");
+                if code.insert(ch, codeword.iter().map(|&b| b == 1).collect()).is_some() {
+                    return Err(TableError::DuplicateSymbol(ch));
+                }
+                increment(&mut codeword);
+            }
+        }
+
+        Ok(CanonicalHuffman {
+            symbols: symbols.into(),
+            counts,
+            code: Arc::new(code),
+        })
+    }
+
+    // Number of codewords of each length 1, 2, ..., up to the longest
+    pub fn counts(&self) -> &[u32] {
+        &self.counts[1..]
+    }
+
+    // Symbols in codeword order
+    pub fn symbols(&self) -> &[char] {
+        &self.symbols
+    }
+
+    // (symbol, codeword length) pairs in canonical order
+    pub fn lengths(&self) -> Vec<(char, u8)> {
+        self.symbols.iter().map(|ch| (*ch, self.code[ch].len() as u8)).collect()
+    }
+
+    pub fn codeword(&self, ch: char) -> Option<&Bits> {
+        self.code.get(&ch)
+    }
+
+    // Panics on a character without a codeword; see try_encode_bits
+    pub fn encode_bits(&self, s: &str) -> Bits {
+        self.try_encode_bits(s).expect("character has no codeword")
+    }
+
+    pub fn try_encode_bits(&self, s: &str) -> Result<Bits, EncodeError> {
+        let mut ret = Bits::default();
+        for (pos, ch) in s.chars().enumerate() {
+            ret.append(self.code.get(&ch).ok_or(EncodeError { symbol: ch, pos })?);
+        }
+        Ok(ret)
+    }
+
+    pub fn decode_bits(&self, bits: &Bits) -> String {
+        self.decode_stream(bits.iter())
+    }
+
+    pub fn try_decode_bits(&self, bits: &Bits) -> Result<String, DecodeError> {
+        self.try_decode_stream(bits.iter())
+    }
+
+    // The same as '0'/'1' strings.
+    // Panics on a character without a codeword; see try_encode_string
+    pub fn encode_string(&self, s: &str) -> String {
+        self.try_encode_string(s).expect("character has no codeword")
+    }
+
+    pub fn try_encode_string(&self, s: &str) -> Result<String, EncodeError> {
+        Ok(self.try_encode_bits(s)?.to_bit_string())
+    }
+
+    pub fn decode_string(&self, s: &str) -> String {
+        self.decode_stream(s.chars().map(|x| x != '0'))
+    }
+
+    pub fn try_decode_string(&self, s: &str) -> Result<String, DecodeError> {
+        if let Some(pos) = s.chars().position(|x| x != '0' && x != '1') {
+            return Err(DecodeError::InvalidBitstream { pos });
+        }
+        self.try_decode_stream(s.chars().map(|x| x == '1'))
+    }
+
+    // Panics on a character without a codeword; see try_encode_packed
+    pub fn encode_packed(&self, s: &str) -> (Vec<u8>, usize) {
+        self.try_encode_packed(s).expect("character has no codeword")
+    }
+
+    pub fn try_encode_packed(&self, s: &str) -> Result<(Vec<u8>, usize), EncodeError> {
+        if let Some((pos, ch)) = s.chars().enumerate().find(|(_, ch)| !self.code.contains_key(ch)) {
+            return Err(EncodeError { symbol: ch, pos });
+        }
+        Ok(bitio::concat(s.chars().map(|ch| &self.code[&ch])))
+    }
+
+    pub fn decode_packed(&self, bytes: &[u8], nbits: usize) -> String {
+        self.decode_stream(bitio::unpack(bytes, nbits))
+    }
+
+    pub fn try_decode_packed(&self, bytes: &[u8], nbits: usize) -> Result<String, DecodeError> {
+        let ret = self.try_decode_stream(bitio::unpack(bytes, nbits))?;
+        if nbits > bytes.len() * 8 {
+            return Err(DecodeError::TruncatedCodeword { pos: bytes.len() * 8 });
+        }
+        Ok(ret)
+    }
+
+    // Compact table: the maximum length as one byte, then for each length
+    // 1..=max the number of codewords of that length (u32, big-endian),
+    // then the symbols in canonical order as UTF-8.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = vec![(self.counts.len() - 1) as u8];
+        for count in &self.counts[1..] {
+            out.extend_from_slice(&count.to_be_bytes());
+        }
+        let symbols: String = self.symbols.iter().collect();
+        out.extend_from_slice(symbols.as_bytes());
+        out
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, TableError> {
+        let (&max_len, mut rest) = bytes.split_first().ok_or(TableError::Truncated)?;
+        let mut counts = Vec::new();
+        for _ in 0..max_len {
+            if rest.len() < 4 {
+                return Err(TableError::Truncated);
+            }
+            let (count, tail) = rest.split_at(4);
+            counts.push(u32::from_be_bytes([count[0], count[1], count[2], count[3]]));
+            rest = tail;
+        }
+        let symbols: Vec<char> = std::str::from_utf8(rest).map_err(|_| TableError::InvalidSymbol)?.chars().collect();
+        CanonicalHuffman::from_counts(&counts, &symbols)
+    }
+
+    fn decode_stream<I: Iterator<Item=bool>>(&self, bits: I) -> String {
+        // Rather than the codeword value itself (which may not fit in any
+        // integer for deep codes), track its offset from the first codeword
+        // of the current length; for a valid prefix it stays below the
+        // alphabet size.
+        let mut ret = "".to_string();
+        let mut len = 0;
+        let mut offset = 0usize;
+        let mut index = 0usize;
+
+        for x in bits {
+            len += 1;
+            if len >= self.counts.len() {
+                break; // no codeword has this prefix
+            }
+            offset = 2 * offset + x as usize;
+            let count = self.counts[len] as usize;
+            if offset < count {
+                ret.push(self.symbols[index + offset]);
+                len = 0;
+                offset = 0;
+                index = 0;
+            } else {
+                offset -= count;
+                index += count;
+            }
+        }
+        ret
+    }
+
+    // As decode_bits, but a prefix no codeword has, or input ending inside
+    // a codeword, is an error
+    fn try_decode_stream<I: Iterator<Item=bool>>(&self, bits: I) -> Result<String, DecodeError> {
+        let mut ret = "".to_string();
+        let mut len = 0;
+        let mut offset = 0usize;
+        let mut index = 0usize;
+        let mut start = 0;
+
+        for (pos, x) in bits.enumerate() {
+            len += 1;
+            if len >= self.counts.len() {
+                return Err(DecodeError::InvalidBitstream { pos: start });
+            }
+            offset = 2 * offset + x as usize;
+            let count = self.counts[len] as usize;
+            if offset < count {
+                ret.push(self.symbols[index + offset]);
+                len = 0;
+                offset = 0;
+                index = 0;
+                start = pos + 1;
+            } else {
+                offset -= count;
+                index += count;
+            }
+        }
+        if len > 0 {
+            return Err(DecodeError::TruncatedCodeword { pos: start });
+        }
+        Ok(ret)
+    }
+
+}
+
+// Put (symbol, length) pairs in canonical order and assign their codewords.
+// Zero lengths mark unused symbols and are dropped; None if the lengths
+// violate the Kraft inequality.
+pub(crate) fn canonical_codewords<S: Symbol>(lengths: &[(S, u8)]) -> Option<Vec<(S, Bits)>> {
+    let mut sorted: Vec<(u8, &S)> = lengths.iter()
+        .filter(|(_, len)| *len > 0)
+        .map(|(sym, len)| (*len, sym))
+        .collect();
+    sorted.sort();
+
+    let max_len = sorted.last().map_or(0, |&(len, _)| len as usize);
+    let mut counts = vec![0u32; max_len + 1];
+    for &(len, _) in &sorted {
+        counts[len as usize] += 1;
+    }
+    if !satisfies_kraft(&counts) {
+        return None;
+    }
+
+    let mut codeword: Vec<u8> = Vec::new(); // big-endian bits of the next codeword
+    let mut assigned = Vec::with_capacity(sorted.len());
+    for (len, sym) in sorted {
+        codeword.resize(len as usize, 0);
+        assigned.push((sym.clone(), codeword.iter().map(|&b| b == 1).collect()));
+        increment(&mut codeword);
+    }
+    Some(assigned)
+}
+
+// Kraft: sum of 2^-len over all codewords must not exceed one. Track the
+// number of still-unused codewords at each length. Once that would pass
+// 2^64 the code is fine: there are fewer symbols than that to use them up.
+fn satisfies_kraft(counts: &[u32]) -> bool {
+    let mut left: u64 = 1;
+    for &count in &counts[1..] {
+        left = match left.checked_mul(2) {
+            Some(doubled) => doubled,
+            None => return true,
+        };
+        if left < count as u64 {
+            return false;
+        }
+        left -= count as u64;
+    }
+    true
+}
+
+fn increment(bits: &mut [u8]) {
+    for b in bits.iter_mut().rev() {
+        if *b == 0 {
+            *b = 1;
+            return;
+        }
+        *b = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::CanonicalHuffman;
+    use crate::huffman::{DecodeError, HuffmanCode, TableError};
+
+    #[test]
+    fn test_rfc1951_example() {
+        // RFC 1951, 3.2.2: lengths (3, 3, 3, 3, 3, 2, 4, 4) for ABCDEFGH
+        let lengths: Vec<(char, u8)> = "ABCDEFGH".chars().zip(vec![3, 3, 3, 3, 3, 2, 4, 4]).collect();
+        let canonical = CanonicalHuffman::from_lengths(&lengths).unwrap();
+        let expected = [('A', "010"), ('B', "011"), ('C', "100"), ('D', "101"),
+                        ('E', "110"), ('F', "00"), ('G', "1110"), ('H', "1111")];
+        for (ch, codeword) in expected.iter() {
+            assert_eq!(canonical.codeword(*ch).map(|c| c.to_bit_string()), Some(codeword.to_string()));
+        }
+        let s = "HEADBADGECAFE";
+        assert_eq!(s, canonical.decode_string(&canonical.encode_string(s)));
+        let (packed, nbits) = canonical.encode_packed(s);
+        assert_eq!(s, canonical.decode_packed(&packed, nbits));
+
+        let err = canonical.try_encode_packed("ABZ").unwrap_err();
+        assert_eq!((err.symbol, err.pos), ('Z', 2));
+        assert_eq!(canonical.try_encode_string("Z").unwrap_err().pos, 0);
+
+        let bits = canonical.encode_string(s);
+        assert_eq!(canonical.try_decode_string(&bits).unwrap(), s);
+        assert_eq!(canonical.try_decode_string(&bits[..bits.len() - 1]),
+                   Err(DecodeError::TruncatedCodeword { pos: bits.len() - 3 }));
+        assert_eq!(canonical.try_decode_packed(&packed, nbits + 8), Err(DecodeError::TruncatedCodeword { pos: packed.len() * 8 }));
+        // incomplete code: nothing starts with 1
+        let partial = CanonicalHuffman::from_lengths(&[('a', 2), ('b', 2)]).unwrap();
+        assert_eq!(partial.try_decode_string("01100"), Err(DecodeError::InvalidBitstream { pos: 2 }));
+        assert_eq!(partial.try_decode_string("01x"), Err(DecodeError::InvalidBitstream { pos: 2 }));
+    }
+
+    #[test]
+    fn test_serialize() {
+        let s = "dagoth ur was a hotep, ünïcödé too";
+        let canonical = HuffmanCode::new(s).to_canonical();
+        let table = canonical.serialize();
+        let restored = CanonicalHuffman::deserialize(&table).unwrap();
+        assert_eq!(canonical.lengths(), restored.lengths());
+        assert_eq!(s, restored.decode_string(&canonical.encode_string(s)));
+
+        assert_eq!(CanonicalHuffman::deserialize(&table[..3]).err(), Some(TableError::Truncated));
+    }
+
+    #[test]
+    fn test_invalid_lengths() {
+        let lengths = vec![('a', 1), ('b', 1), ('c', 1)];
+        assert_eq!(CanonicalHuffman::from_lengths(&lengths).err(), Some(TableError::Oversubscribed));
+        let lengths = vec![('a', 1), ('a', 2)];
+        assert_eq!(CanonicalHuffman::from_lengths(&lengths).err(), Some(TableError::DuplicateSymbol('a')));
+
+        // past 64 bits the free codewords outnumber anything a u64 counts
+        let mut counts = vec![0; 100];
+        counts[0] = 1;
+        counts[99] = 1;
+        assert!(CanonicalHuffman::from_counts(&counts, &['a', 'b']).is_ok());
+        counts[0] = 3;
+        assert_eq!(CanonicalHuffman::from_counts(&counts, &['a', 'b', 'c', 'd']).err(), Some(TableError::Oversubscribed));
+    }
+
+}
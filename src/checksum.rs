@@ -0,0 +1,613 @@
+// Checksums for detecting corrupted data: CRC-16/CCITT (as in XMODEM-style
+// framing and Bluetooth), CRC-32 (IEEE, as in gzip, PNG and Ethernet),
+// CRC-32C (Castagnoli, as in iSCSI and ext4), Adler-32 (zlib) and xxHash64.
+// Each comes as a one-shot function and a running form fed with update();
+// the running forms all implement Checksum, for framing code that takes
+// whichever the format calls for.
+//
+// The container records one of these for the whole input and, in block
+// mode, one per block; ChecksumKind names them there.
+
+const CRC16_TABLE: [u16; 256] = crc16_table(0x1021);
+const CRC32_TABLE: [u32; 256] = crc_table(0xEDB8_8320);
+const CRC32C_TABLE: [u32; 256] = crc_table(0x82F6_3B78);
+
+// Byte-at-a-time table for a reflected 32-bit CRC polynomial
+const fn crc_table(poly: u32) -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ poly } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+// Byte-at-a-time table for an unreflected (MSB-first) 16-bit CRC polynomial
+const fn crc16_table(poly: u16) -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ poly } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn crc_update(table: &[u32; 256], mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc = table[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+// A running checksum. finalize() gives the checksum of everything passed
+// to update() so far, and more can still follow.
+pub trait Checksum {
+    type Output;
+
+    fn update(&mut self, data: &[u8]);
+
+    fn finalize(&self) -> Self::Output;
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = Crc16::new();
+    crc.update(data);
+    crc.finish()
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = Crc32c::new();
+    crc.update(data);
+    crc.finish()
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut adler = Adler32::new();
+    adler.update(data);
+    adler.finish()
+}
+
+pub fn xxhash64(data: &[u8], seed: u64) -> u64 {
+    let mut hash = XxHash64::with_seed(seed);
+    hash.update(data);
+    hash.finish()
+}
+
+// CRC-16/CCITT in its most common form (CCITT-FALSE, a.k.a. IBM-3740):
+// polynomial 0x1021, initial value 0xFFFF, MSB-first, no final xor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc16 {
+    state: u16,
+}
+
+impl Crc16 {
+
+    pub fn new() -> Self {
+        Crc16 { state: 0xffff }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.state = CRC16_TABLE[((self.state >> 8) as u8 ^ b) as usize] ^ (self.state << 8);
+        }
+    }
+
+    pub fn finish(&self) -> u16 {
+        self.state
+    }
+
+}
+
+impl Default for Crc16 {
+    fn default() -> Self {
+        Crc16::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+
+    pub fn new() -> Self {
+        Crc32 { state: !0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.state = crc_update(&CRC32_TABLE, self.state, data);
+    }
+
+    // The checksum of everything so far; more data can still follow
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc32c {
+    state: u32,
+}
+
+impl Crc32c {
+
+    pub fn new() -> Self {
+        Crc32c { state: !0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.state = crc_update(&CRC32C_TABLE, self.state, data);
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+
+}
+
+impl Default for Crc32c {
+    fn default() -> Self {
+        Crc32c::new()
+    }
+}
+
+const ADLER_MOD: u32 = 65521;
+// Most bytes that can be summed before `b` might overflow a u32 (zlib's NMAX)
+const ADLER_NMAX: usize = 5552;
+
+// Adler-32 (RFC 1950): a, the byte sum plus one, and b, the sum of the a's,
+// both mod 65521, as b << 16 | a
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+
+    pub fn new() -> Self {
+        Adler32 { a: 1, b: 0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(ADLER_NMAX) {
+            for &byte in chunk {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= ADLER_MOD;
+            self.b %= ADLER_MOD;
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.b << 16 | self.a
+    }
+
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Adler32::new()
+    }
+}
+
+const PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME64_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+// xxHash64 (Yann Collet). Input is consumed in 32-byte stripes by four
+// accumulators; a partial stripe waits in `buf` until more data arrives
+// or finish() folds it in.
+#[derive(Debug, Clone)]
+pub struct XxHash64 {
+    seed: u64,
+    acc: [u64; 4],
+    buf: [u8; 32],
+    buffered: usize,
+    total: u64,
+}
+
+impl XxHash64 {
+
+    pub fn new() -> Self {
+        XxHash64::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        XxHash64 {
+            seed,
+            acc: [seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+                  seed.wrapping_add(PRIME64_2),
+                  seed,
+                  seed.wrapping_sub(PRIME64_1)],
+            buf: [0; 32],
+            buffered: 0,
+            total: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+        if self.buffered > 0 {
+            let n = data.len().min(32 - self.buffered);
+            self.buf[self.buffered..self.buffered + n].copy_from_slice(&data[..n]);
+            self.buffered += n;
+            data = &data[n..];
+            if self.buffered < 32 {
+                return;
+            }
+            let stripe = self.buf;
+            self.stripe(&stripe);
+            self.buffered = 0;
+        }
+        let mut stripes = data.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    pub fn finish(&self) -> u64 {
+        let mut h = if self.total >= 32 {
+            let [a, b, c, d] = self.acc;
+            let mut h = a.rotate_left(1)
+                .wrapping_add(b.rotate_left(7))
+                .wrapping_add(c.rotate_left(12))
+                .wrapping_add(d.rotate_left(18));
+            for &acc in &self.acc {
+                h = (h ^ round(0, acc)).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+            }
+            h
+        } else {
+            self.seed.wrapping_add(PRIME64_5)
+        };
+        h = h.wrapping_add(self.total);
+
+        let mut tail = &self.buf[..self.buffered];
+        while tail.len() >= 8 {
+            h ^= round(0, read_u64(tail));
+            h = h.rotate_left(27).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+            tail = &tail[8..];
+        }
+        if tail.len() >= 4 {
+            h ^= (read_u32(tail) as u64).wrapping_mul(PRIME64_1);
+            h = h.rotate_left(23).wrapping_mul(PRIME64_2).wrapping_add(PRIME64_3);
+            tail = &tail[4..];
+        }
+        for &b in tail {
+            h ^= (b as u64).wrapping_mul(PRIME64_5);
+            h = h.rotate_left(11).wrapping_mul(PRIME64_1);
+        }
+
+        h ^= h >> 33;
+        h = h.wrapping_mul(PRIME64_2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(PRIME64_3);
+        h ^ (h >> 32)
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (i, acc) in self.acc.iter_mut().enumerate() {
+            *acc = round(*acc, read_u64(&stripe[8 * i..]));
+        }
+    }
+
+}
+
+impl Default for XxHash64 {
+    fn default() -> Self {
+        XxHash64::new()
+    }
+}
+
+fn round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME64_2)).rotate_left(31).wrapping_mul(PRIME64_1)
+}
+
+fn read_u64(b: &[u8]) -> u64 {
+    let mut arr = [0u8; 8];
+    arr.copy_from_slice(&b[..8]);
+    u64::from_le_bytes(arr)
+}
+
+fn read_u32(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+// The checksum a container records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChecksumKind {
+    None,
+    Crc32,
+    Crc32c,
+    XxHash64, // seed 0
+    Adler32,
+    Crc16,
+}
+
+impl ChecksumKind {
+
+    pub const ALL: [ChecksumKind; 6] = [ChecksumKind::None, ChecksumKind::Crc32, ChecksumKind::Crc32c,
+                                        ChecksumKind::XxHash64, ChecksumKind::Adler32, ChecksumKind::Crc16];
+
+    pub fn id(self) -> u8 {
+        match self {
+            ChecksumKind::None => 0,
+            ChecksumKind::Crc32 => 1,
+            ChecksumKind::Crc32c => 2,
+            ChecksumKind::XxHash64 => 3,
+            ChecksumKind::Adler32 => 4,
+            ChecksumKind::Crc16 => 5,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<ChecksumKind> {
+        ChecksumKind::ALL.iter().cloned().find(|k| k.id() == id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumKind::None => "none",
+            ChecksumKind::Crc32 => "crc32",
+            ChecksumKind::Crc32c => "crc32c",
+            ChecksumKind::XxHash64 => "xxhash64",
+            ChecksumKind::Adler32 => "adler32",
+            ChecksumKind::Crc16 => "crc16",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ChecksumKind> {
+        ChecksumKind::ALL.iter().cloned().find(|k| k.name() == name)
+    }
+
+    // Bytes the checksum takes up when stored
+    pub fn size(self) -> usize {
+        match self {
+            ChecksumKind::None => 0,
+            ChecksumKind::Crc16 => 2,
+            ChecksumKind::Crc32 | ChecksumKind::Crc32c | ChecksumKind::Adler32 => 4,
+            ChecksumKind::XxHash64 => 8,
+        }
+    }
+
+    pub fn is_none(self) -> bool {
+        self == ChecksumKind::None
+    }
+
+    pub fn compute(self, data: &[u8]) -> u64 {
+        let mut digest = self.digest();
+        digest.update(data);
+        digest.finish()
+    }
+
+    pub fn digest(self) -> Digest {
+        Digest(match self {
+            ChecksumKind::None => DigestState::None,
+            ChecksumKind::Crc32 => DigestState::Crc32(Crc32::new()),
+            ChecksumKind::Crc32c => DigestState::Crc32c(Crc32c::new()),
+            ChecksumKind::XxHash64 => DigestState::XxHash64(XxHash64::new()),
+            ChecksumKind::Adler32 => DigestState::Adler32(Adler32::new()),
+            ChecksumKind::Crc16 => DigestState::Crc16(Crc16::new()),
+        })
+    }
+
+}
+
+// A running checksum of any kind; narrower ones are widened to u64
+#[derive(Debug, Clone)]
+pub struct Digest(DigestState);
+
+#[derive(Debug, Clone)]
+enum DigestState {
+    None,
+    Crc32(Crc32),
+    Crc32c(Crc32c),
+    XxHash64(XxHash64),
+    Adler32(Adler32),
+    Crc16(Crc16),
+}
+
+impl Digest {
+
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.0 {
+            DigestState::None => {}
+            DigestState::Crc32(c) => c.update(data),
+            DigestState::Crc32c(c) => c.update(data),
+            DigestState::XxHash64(h) => h.update(data),
+            DigestState::Adler32(a) => a.update(data),
+            DigestState::Crc16(c) => c.update(data),
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        match &self.0 {
+            DigestState::None => 0,
+            DigestState::Crc32(c) => c.finish() as u64,
+            DigestState::Crc32c(c) => c.finish() as u64,
+            DigestState::XxHash64(h) => h.finish(),
+            DigestState::Adler32(a) => a.finish() as u64,
+            DigestState::Crc16(c) => c.finish() as u64,
+        }
+    }
+
+}
+
+impl Checksum for Crc16 {
+    type Output = u16;
+
+    fn update(&mut self, data: &[u8]) {
+        Crc16::update(self, data)
+    }
+
+    fn finalize(&self) -> u16 {
+        self.finish()
+    }
+}
+
+impl Checksum for Crc32 {
+    type Output = u32;
+
+    fn update(&mut self, data: &[u8]) {
+        Crc32::update(self, data)
+    }
+
+    fn finalize(&self) -> u32 {
+        self.finish()
+    }
+}
+
+impl Checksum for Crc32c {
+    type Output = u32;
+
+    fn update(&mut self, data: &[u8]) {
+        Crc32c::update(self, data)
+    }
+
+    fn finalize(&self) -> u32 {
+        self.finish()
+    }
+}
+
+impl Checksum for Adler32 {
+    type Output = u32;
+
+    fn update(&mut self, data: &[u8]) {
+        Adler32::update(self, data)
+    }
+
+    fn finalize(&self) -> u32 {
+        self.finish()
+    }
+}
+
+impl Checksum for XxHash64 {
+    type Output = u64;
+
+    fn update(&mut self, data: &[u8]) {
+        XxHash64::update(self, data)
+    }
+
+    fn finalize(&self) -> u64 {
+        self.finish()
+    }
+}
+
+impl Checksum for Digest {
+    type Output = u64;
+
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data)
+    }
+
+    fn finalize(&self) -> u64 {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{adler32, crc16, crc32, crc32c, xxhash64, Adler32, Checksum, ChecksumKind, Crc16, Crc32, XxHash64};
+
+    #[test]
+    fn test_check_values() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc16(b""), 0xFFFF);
+        assert_eq!(crc16(b"A"), 0xB915);
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        assert_eq!(crc32c(&[0u8; 32]), 0x8A91_36AA);
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(&[0xff; 100_000]), 0x149A_302C);
+        assert_eq!(xxhash64(b"", 0), 0xEF46_DB37_51D8_E999);
+        assert_eq!(xxhash64(b"a", 0), 0xD24E_C4F1_A98C_6E5B);
+        assert_eq!(xxhash64(b"abc", 0), 0x44BC_2CF5_AD77_0999);
+        assert_eq!(xxhash64(b"Nobody inspects the spammish repetition", 0), 0xFBCE_A83C_8A37_8BF1);
+    }
+
+    #[test]
+    fn test_incremental() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 31 % 251) as u8).collect();
+        for seed in [0, 1, u64::MAX].iter() {
+            let expected = xxhash64(&data, *seed);
+            for split in [1, 7, 31, 32, 33, 100].iter() {
+                let mut hash = XxHash64::with_seed(*seed);
+                for chunk in data.chunks(*split) {
+                    hash.update(chunk);
+                }
+                assert_eq!(hash.finish(), expected);
+            }
+        }
+        let mut adler = Adler32::new();
+        for chunk in data.chunks(7) {
+            adler.update(chunk);
+        }
+        assert_eq!(adler.finish(), adler32(&data));
+        for kind in ChecksumKind::ALL.iter() {
+            let mut digest = kind.digest();
+            digest.update(&data[..500]);
+            digest.update(&data[500..]);
+            assert_eq!(digest.finish(), kind.compute(&data));
+            assert_eq!(ChecksumKind::from_id(kind.id()), Some(*kind));
+            assert_eq!(ChecksumKind::from_name(kind.name()), Some(*kind));
+        }
+        assert_eq!(ChecksumKind::Crc32.compute(b"123456789"), 0xCBF4_3926);
+        assert_eq!(ChecksumKind::Crc16.compute(b"123456789"), 0x29B1);
+    }
+
+    // Framing code written against the trait
+    fn frame<C: Checksum>(mut check: C, parts: &[&[u8]]) -> C::Output {
+        for part in parts {
+            check.update(part);
+        }
+        check.finalize()
+    }
+
+    #[test]
+    fn test_checksum_trait() {
+        let parts: [&[u8]; 3] = [b"1234", b"", b"56789"];
+        assert_eq!(frame(Crc16::new(), &parts), 0x29B1);
+        assert_eq!(frame(Crc32::new(), &parts), 0xCBF4_3926);
+        assert_eq!(frame(Adler32::new(), &parts), adler32(b"123456789"));
+        assert_eq!(frame(XxHash64::new(), &parts), xxhash64(b"123456789", 0));
+        assert_eq!(frame(ChecksumKind::Crc32c.digest(), &parts), crc32c(b"123456789") as u64);
+    }
+
+}
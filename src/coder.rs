@@ -0,0 +1,544 @@
+// One interface over the crate's byte entropy coders, so callers can swap
+// algorithms or pick one at runtime by CodecId.
+//
+// A Coder holds whatever model it needs (a code table, scaled frequencies,
+// an adaptive model's starting state); encoder and decoder must use the
+// same one. Its output holds everything else: rANS, FSE and the arithmetic
+// coder record the symbol count in front of their stream, as a u64
+// big-endian.
+//
+// encode_with_scratch() keeps a coder's temporaries in a caller's Scratch
+// (see scratch.rs), and decode_into() writes into the caller's buffer
+// instead of a new Vec, so a hot loop can reuse both. max_decoded_len()
+// says how big that buffer must be: the exact count for rANS, FSE and the
+// arithmetic coder, and
+// for the bit-at-a-time coders one byte per bit (or per shortest codeword,
+// for a Huffman code) of input.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::adaptive::{AdaptiveCoder, Fgk, Vitter};
+use crate::arithmetic::{self, ArithmeticDecoder};
+use crate::bitio::{self, BitReader, BitWriter, Bits};
+use crate::fse::Fse;
+use crate::huffman::{BuildError, ByteHuffman, EncodeError};
+use crate::models::{Context, ContextModel, Model, Order0};
+use crate::rans::Rans;
+use crate::scratch::Scratch;
+use crate::shannon_fano::ShannonFano;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoderError {
+    // A byte the coder's model has no code for
+    Encode(EncodeError<u8>),
+    // No model could be built for the training data
+    Build(BuildError),
+    // The bits don't decode cleanly
+    Corrupt,
+    // decode_into's buffer can't hold all of the output
+    BufferTooSmall,
+}
+
+impl fmt::Display for CoderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CoderError::Encode(e) => write!(f, "{}", e),
+            CoderError::Build(e) => write!(f, "{}", e),
+            CoderError::Corrupt => write!(f, "encoded data is corrupt"),
+            CoderError::BufferTooSmall => write!(f, "output buffer is too small"),
+        }
+    }
+}
+
+impl Error for CoderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CoderError::Encode(e) => Some(e),
+            CoderError::Build(e) => Some(e),
+            CoderError::Corrupt | CoderError::BufferTooSmall => None,
+        }
+    }
+}
+
+impl From<EncodeError<u8>> for CoderError {
+    fn from(e: EncodeError<u8>) -> Self {
+        CoderError::Encode(e)
+    }
+}
+
+impl From<BuildError> for CoderError {
+    fn from(e: BuildError) -> Self {
+        CoderError::Build(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, CoderError>;
+
+// Send + Sync, so one trained coder (say an Arc<dyn Coder>) can serve
+// every thread of a server
+pub trait Coder: Send + Sync {
+    fn encode(&self, data: &[u8]) -> Result<Bits>;
+    // encode() with its temporaries in `scratch`, for coders that have any
+    fn encode_with_scratch(&self, data: &[u8], _scratch: &mut Scratch) -> Result<Bits> {
+        self.encode(data)
+    }
+    fn decode(&self, bits: &Bits) -> Result<Vec<u8>>;
+    // Returns the number of bytes written to the front of `out`. On error
+    // the rest of `out` may have been written to as well.
+    fn decode_into(&self, bits: &Bits, out: &mut [u8]) -> Result<usize>;
+    // A buffer this long is always enough for decode_into
+    fn max_decoded_len(&self, bits: &Bits) -> usize;
+}
+
+// Symbol-at-a-time coding over the crate's bit streams. Prefix codes and
+// the adaptive coders can work this way; rANS and FSE encode back to front
+// and can't.
+pub trait SymbolCoder {
+    fn write_symbol<W: Write>(&mut self, sym: u8, out: &mut BitWriter<W>) -> io::Result<()>;
+    fn read_symbol<R: Read>(&mut self, input: &mut BitReader<R>) -> io::Result<u8>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecId {
+    Huffman,
+    ShannonFano,
+    Rans,
+    Fse,
+    Fgk,
+    Vitter,
+    Arithmetic,
+}
+
+impl CodecId {
+
+    pub const ALL: [CodecId; 7] = [CodecId::Huffman, CodecId::ShannonFano, CodecId::Rans,
+                                   CodecId::Fse, CodecId::Fgk, CodecId::Vitter, CodecId::Arithmetic];
+
+    pub fn id(self) -> u8 {
+        match self {
+            CodecId::Huffman => 1,
+            CodecId::ShannonFano => 2,
+            CodecId::Rans => 3,
+            CodecId::Fse => 4,
+            CodecId::Fgk => 5,
+            CodecId::Vitter => 6,
+            CodecId::Arithmetic => 7,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<CodecId> {
+        CodecId::ALL.iter().cloned().find(|c| c.id() == id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CodecId::Huffman => "huffman",
+            CodecId::ShannonFano => "shannon-fano",
+            CodecId::Rans => "rans",
+            CodecId::Fse => "fse",
+            CodecId::Fgk => "fgk",
+            CodecId::Vitter => "vitter",
+            CodecId::Arithmetic => "arithmetic",
+        }
+    }
+
+    // A coder with its model fitted to `data`; the adaptive coders (and
+    // the arithmetic coder, over an Order0 model) start empty and ignore it
+    pub fn train(self, data: &[u8]) -> Result<Box<dyn Coder>> {
+        Ok(match self {
+            CodecId::Huffman => Box::new(ByteHuffman::try_new_bytes(data)?),
+            CodecId::ShannonFano => Box::new(ShannonFano::try_from_symbols(data.iter().cloned())?),
+            CodecId::Rans => Box::new(Rans::try_new(data)?),
+            CodecId::Fse => Box::new(Fse::try_new(data)?),
+            CodecId::Fgk => Box::new(Fgk::new()),
+            CodecId::Vitter => Box::new(Vitter::new()),
+            CodecId::Arithmetic => Box::new(Order0::new()),
+        })
+    }
+
+}
+
+impl fmt::Display for CodecId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+fn encode_symbols<F>(data: &[u8], mut write: F) -> Bits
+    where F: FnMut(u8, &mut BitWriter<&mut Vec<u8>>) -> io::Result<()> {
+    let (bytes, nbits) = bitio::pack(|w| {
+        for &b in data {
+            write(b, w)?;
+        }
+        Ok(())
+    });
+    Bits::new(bytes, nbits)
+}
+
+// Read symbols until exactly `bits.len()` bits are used up
+fn decode_symbols<F>(bits: &Bits, mut read: F) -> Result<Vec<u8>>
+    where F: FnMut(&mut BitReader<&[u8]>) -> io::Result<u8> {
+    let mut input = BitReader::new(bits.as_bytes());
+    let mut out = Vec::new();
+    while (input.bits_read() as usize) < bits.len() {
+        out.push(read(&mut input).map_err(|_| CoderError::Corrupt)?);
+        if input.bits_read() as usize > bits.len() {
+            return Err(CoderError::Corrupt);
+        }
+    }
+    Ok(out)
+}
+
+fn decode_symbols_into<F>(bits: &Bits, out: &mut [u8], mut read: F) -> Result<usize>
+    where F: FnMut(&mut BitReader<&[u8]>) -> io::Result<u8> {
+    let mut input = BitReader::new(bits.as_bytes());
+    let mut n = 0;
+    while (input.bits_read() as usize) < bits.len() {
+        let slot = out.get_mut(n).ok_or(CoderError::BufferTooSmall)?;
+        *slot = read(&mut input).map_err(|_| CoderError::Corrupt)?;
+        n += 1;
+        if input.bits_read() as usize > bits.len() {
+            return Err(CoderError::Corrupt);
+        }
+    }
+    Ok(n)
+}
+
+fn check_known<F: Fn(u8) -> bool>(data: &[u8], known: F) -> Result<()> {
+    match data.iter().position(|&b| !known(b)) {
+        Some(pos) => Err(EncodeError { symbol: data[pos], pos }.into()),
+        None => Ok(()),
+    }
+}
+
+impl Coder for ByteHuffman {
+
+    fn encode(&self, data: &[u8]) -> Result<Bits> {
+        let (bytes, nbits) = self.try_encode_bytes_packed(data)?;
+        Ok(Bits::new(bytes, nbits))
+    }
+
+    fn decode(&self, bits: &Bits) -> Result<Vec<u8>> {
+        decode_symbols(bits, |r| self.read_symbol(r))
+    }
+
+    fn decode_into(&self, bits: &Bits, out: &mut [u8]) -> Result<usize> {
+        decode_symbols_into(bits, out, |r| self.read_symbol(r))
+    }
+
+    fn max_decoded_len(&self, bits: &Bits) -> usize {
+        bits.len() / self.code_lengths().map(|(_, len)| len).min().unwrap_or(1).max(1)
+    }
+
+}
+
+impl SymbolCoder for ByteHuffman {
+
+    fn write_symbol<W: Write>(&mut self, sym: u8, out: &mut BitWriter<W>) -> io::Result<()> {
+        self.write_byte(sym, out)
+    }
+
+    fn read_symbol<R: Read>(&mut self, input: &mut BitReader<R>) -> io::Result<u8> {
+        ByteHuffman::read_symbol(self, input)
+    }
+
+}
+
+impl Coder for ShannonFano<u8> {
+
+    fn encode(&self, data: &[u8]) -> Result<Bits> {
+        check_known(data, |b| self.frequencies().contains_key(&b))?;
+        Ok(encode_symbols(data, |b, w| ShannonFano::write_symbol(self, &b, w)))
+    }
+
+    fn decode(&self, bits: &Bits) -> Result<Vec<u8>> {
+        decode_symbols(bits, |r| ShannonFano::read_symbol(self, r))
+    }
+
+    fn decode_into(&self, bits: &Bits, out: &mut [u8]) -> Result<usize> {
+        decode_symbols_into(bits, out, |r| ShannonFano::read_symbol(self, r))
+    }
+
+    fn max_decoded_len(&self, bits: &Bits) -> usize {
+        bits.len()
+    }
+
+}
+
+impl SymbolCoder for ShannonFano<u8> {
+
+    fn write_symbol<W: Write>(&mut self, sym: u8, out: &mut BitWriter<W>) -> io::Result<()> {
+        ShannonFano::write_symbol(self, &sym, out)
+    }
+
+    fn read_symbol<R: Read>(&mut self, input: &mut BitReader<R>) -> io::Result<u8> {
+        ShannonFano::read_symbol(self, input)
+    }
+
+}
+
+// Symbol count, then the coder's own bytes
+fn with_count(count: usize, bytes: Vec<u8>) -> Bits {
+    let mut out = (count as u64).to_be_bytes().to_vec();
+    out.extend(bytes);
+    Bits::from(out)
+}
+
+fn split_count(bits: &Bits) -> Result<(usize, &[u8])> {
+    let bytes = bits.as_bytes();
+    if bytes.len() < 8 || bits.len() != bytes.len() * 8 {
+        return Err(CoderError::Corrupt);
+    }
+    let (count, rest) = bytes.split_at(8);
+    let mut arr = [0u8; 8];
+    arr.copy_from_slice(count);
+    Ok((u64::from_be_bytes(arr) as usize, rest))
+}
+
+impl Coder for Rans {
+
+    fn encode(&self, data: &[u8]) -> Result<Bits> {
+        Ok(with_count(data.len(), Rans::encode(self, data)?))
+    }
+
+    fn encode_with_scratch(&self, data: &[u8], scratch: &mut Scratch) -> Result<Bits> {
+        Ok(with_count(data.len(), Rans::encode_with_scratch(self, data, scratch)?))
+    }
+
+    fn decode(&self, bits: &Bits) -> Result<Vec<u8>> {
+        let (count, rest) = split_count(bits)?;
+        Rans::decode(self, rest, count).map_err(|_| CoderError::Corrupt)
+    }
+
+    fn decode_into(&self, bits: &Bits, out: &mut [u8]) -> Result<usize> {
+        let (count, rest) = split_count(bits)?;
+        let out = out.get_mut(..count).ok_or(CoderError::BufferTooSmall)?;
+        Rans::decode_into(self, rest, out).map_err(|_| CoderError::Corrupt)?;
+        Ok(count)
+    }
+
+    fn max_decoded_len(&self, bits: &Bits) -> usize {
+        split_count(bits).map_or(0, |(count, _)| count)
+    }
+
+}
+
+impl Coder for Fse {
+
+    fn encode(&self, data: &[u8]) -> Result<Bits> {
+        Ok(with_count(data.len(), Fse::encode(self, data)?))
+    }
+
+    fn encode_with_scratch(&self, data: &[u8], scratch: &mut Scratch) -> Result<Bits> {
+        Ok(with_count(data.len(), Fse::encode_with_scratch(self, data, scratch)?))
+    }
+
+    fn decode(&self, bits: &Bits) -> Result<Vec<u8>> {
+        let (count, rest) = split_count(bits)?;
+        Fse::decode(self, rest, count).map_err(|_| CoderError::Corrupt)
+    }
+
+    fn decode_into(&self, bits: &Bits, out: &mut [u8]) -> Result<usize> {
+        let (count, rest) = split_count(bits)?;
+        let out = out.get_mut(..count).ok_or(CoderError::BufferTooSmall)?;
+        Fse::decode_into(self, rest, out).map_err(|_| CoderError::Corrupt)?;
+        Ok(count)
+    }
+
+    fn max_decoded_len(&self, bits: &Bits) -> usize {
+        split_count(bits).map_or(0, |(count, _)| count)
+    }
+
+}
+
+impl<T: AdaptiveCoder> SymbolCoder for T {
+
+    fn write_symbol<W: Write>(&mut self, sym: u8, out: &mut BitWriter<W>) -> io::Result<()> {
+        self.encode_symbol(sym, out)
+    }
+
+    fn read_symbol<R: Read>(&mut self, input: &mut BitReader<R>) -> io::Result<u8> {
+        self.decode_symbol(input)
+    }
+
+}
+
+// The adaptive coders start every message from the model they hold
+// (normally an empty one), leaving it untouched.
+impl Coder for Fgk {
+
+    fn encode(&self, data: &[u8]) -> Result<Bits> {
+        let mut model = self.clone();
+        Ok(encode_symbols(data, |b, w| model.encode_symbol(b, w)))
+    }
+
+    fn decode(&self, bits: &Bits) -> Result<Vec<u8>> {
+        let mut model = self.clone();
+        decode_symbols(bits, |r| model.decode_symbol(r))
+    }
+
+    fn decode_into(&self, bits: &Bits, out: &mut [u8]) -> Result<usize> {
+        let mut model = self.clone();
+        decode_symbols_into(bits, out, |r| model.decode_symbol(r))
+    }
+
+    fn max_decoded_len(&self, bits: &Bits) -> usize {
+        bits.len()
+    }
+
+}
+
+impl Coder for Vitter {
+
+    fn encode(&self, data: &[u8]) -> Result<Bits> {
+        let mut model = self.clone();
+        Ok(encode_symbols(data, |b, w| model.encode_symbol(b, w)))
+    }
+
+    fn decode(&self, bits: &Bits) -> Result<Vec<u8>> {
+        let mut model = self.clone();
+        decode_symbols(bits, |r| model.decode_symbol(r))
+    }
+
+    fn decode_into(&self, bits: &Bits, out: &mut [u8]) -> Result<usize> {
+        let mut model = self.clone();
+        decode_symbols_into(bits, out, |r| model.decode_symbol(r))
+    }
+
+    fn max_decoded_len(&self, bits: &Bits) -> usize {
+        bits.len()
+    }
+
+}
+
+fn decode_arithmetic_into<M: Model>(bits: &Bits, out: &mut [u8], mut model: M) -> Result<usize> {
+    let (count, rest) = split_count(bits)?;
+    let out = out.get_mut(..count).ok_or(CoderError::BufferTooSmall)?;
+    let mut input = BitReader::new(rest);
+    let mut dec = ArithmeticDecoder::new(&mut input).map_err(|_| CoderError::Corrupt)?;
+    for slot in out.iter_mut() {
+        *slot = dec.decode_symbol(&mut model, &mut input).map_err(|_| CoderError::Corrupt)?;
+    }
+    Ok(count)
+}
+
+// The arithmetic coder's models are adaptive too: every message starts
+// from the one held.
+impl Coder for Order0 {
+
+    fn encode(&self, data: &[u8]) -> Result<Bits> {
+        Ok(with_count(data.len(), arithmetic::encode(data, self.clone())))
+    }
+
+    fn decode(&self, bits: &Bits) -> Result<Vec<u8>> {
+        let (count, rest) = split_count(bits)?;
+        arithmetic::decode(rest, count, self.clone()).map_err(|_| CoderError::Corrupt)
+    }
+
+    fn decode_into(&self, bits: &Bits, out: &mut [u8]) -> Result<usize> {
+        decode_arithmetic_into(bits, out, self.clone())
+    }
+
+    fn max_decoded_len(&self, bits: &Bits) -> usize {
+        split_count(bits).map_or(0, |(count, _)| count)
+    }
+
+}
+
+impl<C: Context + Clone + Send + Sync> Coder for ContextModel<C> {
+
+    fn encode(&self, data: &[u8]) -> Result<Bits> {
+        Ok(with_count(data.len(), arithmetic::encode(data, self.clone())))
+    }
+
+    fn decode(&self, bits: &Bits) -> Result<Vec<u8>> {
+        let (count, rest) = split_count(bits)?;
+        arithmetic::decode(rest, count, self.clone()).map_err(|_| CoderError::Corrupt)
+    }
+
+    fn decode_into(&self, bits: &Bits, out: &mut [u8]) -> Result<usize> {
+        decode_arithmetic_into(bits, out, self.clone())
+    }
+
+    fn max_decoded_len(&self, bits: &Bits) -> usize {
+        split_count(bits).map_or(0, |(count, _)| count)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{CodecId, Coder, CoderError, SymbolCoder};
+    use crate::adaptive::Vitter;
+    use crate::bitio::{BitReader, BitWriter, Bits};
+    use crate::huffman::{ByteHuffman, EncodeError};
+    use crate::models::Order1;
+
+    #[test]
+    fn test_round_trip() {
+        let text = b"the quick brown fox jumped over the lazy dog".repeat(20);
+        for codec in CodecId::ALL.iter().cloned() {
+            assert_eq!(CodecId::from_id(codec.id()), Some(codec));
+            let coder = codec.train(&text).unwrap();
+            for data in [&text[..], b"", b"fox"] {
+                let bits = coder.encode(data).unwrap();
+                assert_eq!(coder.decode(&bits).unwrap(), data, "{}", codec);
+            }
+            assert!(coder.encode(&text).unwrap().len() < text.len() * 6, "{}", codec);
+
+            // one buffer for every message
+            let mut buf = vec![0u8; text.len()];
+            for data in [&text[..], b"", b"fox"] {
+                let bits = coder.encode(data).unwrap();
+                assert!(coder.max_decoded_len(&bits) >= data.len(), "{}", codec);
+                let n = coder.decode_into(&bits, &mut buf).unwrap();
+                assert_eq!(&buf[..n], data, "{}", codec);
+            }
+            let bits = coder.encode(&text).unwrap();
+            assert_eq!(coder.decode_into(&bits, &mut buf[..100]), Err(CoderError::BufferTooSmall), "{}", codec);
+        }
+        assert_eq!(CodecId::from_id(0), None);
+        assert!(CodecId::Rans.train(b"").is_err());
+
+        // any arithmetic model will do, and a cut stream is noticed
+        let order1 = Order1::default();
+        let bits = order1.encode(&text).unwrap();
+        assert_eq!(order1.decode(&bits).unwrap(), text);
+        assert!(bits.len() < CodecId::Arithmetic.train(&text).unwrap().encode(&text).unwrap().len());
+        let cut = Bits::from(bits.as_bytes()[..bits.as_bytes().len() / 2].to_vec());
+        assert_eq!(order1.decode(&cut), Err(CoderError::Corrupt));
+    }
+
+    #[test]
+    fn test_errors() {
+        for codec in &[CodecId::Huffman, CodecId::ShannonFano, CodecId::Rans, CodecId::Fse] {
+            let coder = codec.train(b"abc").unwrap();
+            assert_eq!(coder.encode(b"abd").unwrap_err(),
+                       CoderError::Encode(EncodeError { symbol: b'd', pos: 2 }), "{}", codec);
+            assert_eq!(coder.decode(&Bits::new(vec![0xff], 3)), Err(CoderError::Corrupt), "{}", codec);
+        }
+    }
+
+    // Static and adaptive coders stream through the same interface
+    fn stream<C: SymbolCoder>(mut encoder: C, mut decoder: C, data: &[u8]) -> Vec<u8> {
+        let mut w = BitWriter::new(Vec::new());
+        for &b in data {
+            encoder.write_symbol(b, &mut w).unwrap();
+        }
+        let bytes = w.into_inner().unwrap();
+        let mut r = BitReader::new(&bytes[..]);
+        data.iter().map(|_| decoder.read_symbol(&mut r).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_symbol_coder() {
+        let data = b"abracadabra";
+        let code = ByteHuffman::new_bytes(data);
+        assert_eq!(stream(code.clone(), code, data), data);
+        assert_eq!(stream(Vitter::new(), Vitter::new(), data), data);
+    }
+
+}
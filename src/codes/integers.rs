@@ -0,0 +1,210 @@
+// Small integer codes over the crate's bit streams, meant to be combined:
+// Golomb codes are unary quotients followed by truncated binary
+// remainders, Elias gamma is order-0 Exp-Golomb shifted by one, and so on.
+//
+// - unary: n one bits, then a zero
+// - truncated binary: n in 0..m using floor(log2 m) or one more bits,
+//   wasting no codewords when m isn't a power of two
+// - Exp-Golomb of order k (H.264, Dirac): the binary form of n + 2^k,
+//   preceded by as many zeros as it has bits beyond k + 1
+// - Golomb with modulus m, and Rice (m = 2^k)
+//
+// Values that can't be coded fail with InvalidInput; readers fail with
+// InvalidData on codewords of values that don't fit a u64.
+
+use std::io::{self, Read, Write};
+
+use crate::bitio::{BitReader, BitWriter};
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.to_string())
+}
+
+fn too_big() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "codeword overflows u64")
+}
+
+fn bit_length(n: u64) -> u32 {
+    64 - n.leading_zeros()
+}
+
+pub fn write_unary<W: Write>(w: &mut BitWriter<W>, n: u64) -> io::Result<()> {
+    let mut left = n;
+    while left >= 64 {
+        w.write_bits(u64::MAX, 64)?;
+        left -= 64;
+    }
+    w.write_bits((1 << left) - 1, left as u32)?;
+    w.write_bit(false)
+}
+
+pub fn read_unary<R: Read>(r: &mut BitReader<R>) -> io::Result<u64> {
+    let mut n = 0u64;
+    while r.read_bit()? {
+        n = n.checked_add(1).ok_or_else(too_big)?;
+    }
+    Ok(n)
+}
+
+pub fn write_truncated_binary<W: Write>(w: &mut BitWriter<W>, n: u64, m: u64) -> io::Result<()> {
+    if n >= m {
+        return Err(invalid_input("truncated binary value out of range"));
+    }
+    let (k, short) = truncated_binary_split(m);
+    if n < short {
+        w.write_bits(n, k)
+    } else {
+        // in 64 + 1 bits when m > 2^63; only happens for n >= short
+        let v = n as u128 + short as u128;
+        w.write_bits((v >> 1) as u64, k)?;
+        w.write_bit(v & 1 == 1)
+    }
+}
+
+pub fn read_truncated_binary<R: Read>(r: &mut BitReader<R>, m: u64) -> io::Result<u64> {
+    if m == 0 {
+        return Err(invalid_input("truncated binary range is empty"));
+    }
+    let (k, short) = truncated_binary_split(m);
+    let n = r.read_bits(k)?;
+    if n < short {
+        return Ok(n);
+    }
+    let v = ((n as u128) << 1) | r.read_bit()? as u128;
+    Ok((v - short as u128) as u64)
+}
+
+// The codeword length for the first values, and how many of them (u) get
+// it; the remaining m - u values take one bit more
+fn truncated_binary_split(m: u64) -> (u32, u64) {
+    let k = bit_length(m) - 1;
+    let short = ((2u128 << k) - m as u128) as u64;
+    (k, short)
+}
+
+pub fn write_exp_golomb<W: Write>(w: &mut BitWriter<W>, n: u64, k: u32) -> io::Result<()> {
+    if k >= 64 {
+        return Err(invalid_input("Exp-Golomb order must be below 64"));
+    }
+    let v = n.checked_add(1 << k).ok_or_else(|| invalid_input("Exp-Golomb value overflows u64"))?;
+    let len = bit_length(v);
+    w.write_bits(0, len - k - 1)?;
+    w.write_bits(v, len)
+}
+
+pub fn read_exp_golomb<R: Read>(r: &mut BitReader<R>, k: u32) -> io::Result<u64> {
+    if k >= 64 {
+        return Err(invalid_input("Exp-Golomb order must be below 64"));
+    }
+    let mut zeros = 0;
+    while !r.read_bit()? {
+        zeros += 1;
+        if zeros + k > 63 {
+            return Err(too_big());
+        }
+    }
+    let rest = zeros + k;
+    let v = 1 << rest | r.read_bits(rest)?;
+    Ok(v - (1 << k))
+}
+
+pub fn write_golomb<W: Write>(w: &mut BitWriter<W>, n: u64, m: u64) -> io::Result<()> {
+    if m == 0 {
+        return Err(invalid_input("Golomb modulus must be positive"));
+    }
+    write_unary(w, n / m)?;
+    write_truncated_binary(w, n % m, m)
+}
+
+pub fn read_golomb<R: Read>(r: &mut BitReader<R>, m: u64) -> io::Result<u64> {
+    let q = read_unary(r)?;
+    let rem = read_truncated_binary(r, m)?;
+    q.checked_mul(m).and_then(|v| v.checked_add(rem)).ok_or_else(too_big)
+}
+
+pub fn write_rice<W: Write>(w: &mut BitWriter<W>, n: u64, k: u32) -> io::Result<()> {
+    if k >= 64 {
+        return Err(invalid_input("Rice parameter must be below 64"));
+    }
+    write_golomb(w, n, 1 << k)
+}
+
+pub fn read_rice<R: Read>(r: &mut BitReader<R>, k: u32) -> io::Result<u64> {
+    if k >= 64 {
+        return Err(invalid_input("Rice parameter must be below 64"));
+    }
+    read_golomb(r, 1 << k)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::bitio::{self, BitReader};
+
+    fn bits<F>(f: F) -> String
+        where F: FnOnce(&mut BitWriter<&mut Vec<u8>>) -> io::Result<()> {
+        let (bytes, nbits) = bitio::pack(f);
+        bitio::unpack(&bytes, nbits).map(|b| if b { '1' } else { '0' }).collect()
+    }
+
+    #[test]
+    fn test_codewords() {
+        assert_eq!(bits(|w| write_unary(w, 0)), "0");
+        assert_eq!(bits(|w| write_unary(w, 3)), "1110");
+        assert_eq!(bits(|w| write_unary(w, 70)).len(), 71);
+
+        // m = 5: 0-2 in two bits, 3-4 in three
+        let tb: Vec<String> = (0..5).map(|n| bits(|w| write_truncated_binary(w, n, 5))).collect();
+        assert_eq!(tb, vec!["00", "01", "10", "110", "111"]);
+        assert_eq!(bits(|w| write_truncated_binary(w, 5, 8)), "101");
+
+        let eg0: Vec<String> = (0..5).map(|n| bits(|w| write_exp_golomb(w, n, 0))).collect();
+        assert_eq!(eg0, vec!["1", "010", "011", "00100", "00101"]);
+        assert_eq!(bits(|w| write_exp_golomb(w, 3, 2)), "111");
+        assert_eq!(bits(|w| write_exp_golomb(w, 4, 2)), "01000");
+
+        assert_eq!(bits(|w| write_golomb(w, 7, 3)), "11010");
+        assert_eq!(bits(|w| write_rice(w, 9, 2)), "11001");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let values: Vec<u64> = (0..300).chain([1 << 40, u64::MAX - 1]).collect();
+        for &m in &[1u64, 3, 8, 1000, u64::MAX] {
+            let small: Vec<u64> = values.iter().map(|&n| n % m).collect();
+            let (bytes, _) = bitio::pack(|w| {
+                for &n in &small {
+                    write_truncated_binary(w, n, m)?;
+                    write_exp_golomb(w, n >> 4, 3)?;
+                    write_golomb(w, n % 5000, m.min(1 << 20))?;
+                }
+                Ok(())
+            });
+            let mut r = BitReader::new(&bytes[..]);
+            for &n in &small {
+                assert_eq!(read_truncated_binary(&mut r, m).unwrap(), n);
+                assert_eq!(read_exp_golomb(&mut r, 3).unwrap(), n >> 4);
+                assert_eq!(read_golomb(&mut r, m.min(1 << 20)).unwrap(), n % 5000);
+            }
+        }
+        let (bytes, _) = bitio::pack(|w| write_rice(w, 1234, 5));
+        assert_eq!(read_rice(&mut BitReader::new(&bytes[..]), 5).unwrap(), 1234);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut w = BitWriter::new(Vec::new());
+        let invalid = io::ErrorKind::InvalidInput;
+        assert_eq!(write_truncated_binary(&mut w, 5, 5).unwrap_err().kind(), invalid);
+        assert_eq!(write_exp_golomb(&mut w, u64::MAX, 1).unwrap_err().kind(), invalid);
+        assert_eq!(write_golomb(&mut w, 1, 0).unwrap_err().kind(), invalid);
+
+        let zeros = [0u8; 10];
+        assert_eq!(read_exp_golomb(&mut BitReader::new(&zeros[..]), 0).unwrap_err().kind(),
+                   io::ErrorKind::InvalidData);
+        assert_eq!(read_unary(&mut BitReader::new(&[0xffu8][..])).unwrap_err().kind(),
+                   io::ErrorKind::UnexpectedEof);
+    }
+
+}
@@ -0,0 +1,3 @@
+// Building blocks for variable-length codes
+pub mod integers;
+pub mod varint;
@@ -0,0 +1,221 @@
+// Byte-aligned variable-length integers, for lengths and counts in headers
+// where most values are small but any u64 has to fit:
+//
+// - LEB128 (DWARF, WebAssembly, protobuf): seven bits per byte, least
+//   significant group first, the top bit set on every byte but the last
+// - signed LEB128: the same, with the last byte's bit 6 as the sign,
+//   extended through the unused high bits
+// - prefix varint: the number of leading one bits in the first byte says
+//   how many bytes follow, so a reader knows the length up front. The
+//   value is big-endian in the bits after the prefix; a first byte of 0xff
+//   is followed by all 64 bits.
+//
+// Readers take from the front of a slice and leave it just past the value.
+// Overlong LEB128 encodings (0x80 0x00 for 0) are accepted; values that
+// don't fit 64 bits aren't.
+
+use std::error::Error;
+use std::fmt;
+
+// Longest LEB128 encoding of a 64-bit value
+pub const MAX_LEN: usize = 10;
+// Longest prefix varint
+pub const MAX_PREFIX_LEN: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarintError {
+    // The bytes end partway through a value
+    Truncated,
+    // An encoding of more than 64 bits
+    Overflow,
+}
+
+impl fmt::Display for VarintError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VarintError::Truncated => write!(f, "varint is truncated"),
+            VarintError::Overflow => write!(f, "varint overflows 64 bits"),
+        }
+    }
+}
+
+impl Error for VarintError {}
+
+pub fn len_u64(v: u64) -> usize {
+    (64 - v.leading_zeros() as usize).div_ceil(7).max(1)
+}
+
+pub fn len_i64(v: i64) -> usize {
+    // the magnitude's bits plus a sign bit
+    let bits = 65 - if v < 0 { (!v).leading_zeros() } else { v.leading_zeros() } as usize;
+    bits.div_ceil(7)
+}
+
+pub fn len_prefix(v: u64) -> usize {
+    match len_u64(v) {
+        n if n > 8 => MAX_PREFIX_LEN,
+        n => n,
+    }
+}
+
+pub fn write_u64(out: &mut Vec<u8>, v: u64) {
+    let mut v = v;
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+pub fn read_u64(bytes: &mut &[u8]) -> Result<u64, VarintError> {
+    let mut v = 0u64;
+    let mut shift = 0;
+    loop {
+        let b = take_byte(bytes)?;
+        if shift == 63 && b > 1 {
+            return Err(VarintError::Overflow);
+        }
+        v |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+        shift += 7;
+    }
+}
+
+pub fn write_i64(out: &mut Vec<u8>, v: i64) {
+    let mut v = v;
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        // done once what's left is all sign, and bit 6 agrees with it
+        if (v == 0 && b & 0x40 == 0) || (v == -1 && b & 0x40 != 0) {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+pub fn read_i64(bytes: &mut &[u8]) -> Result<i64, VarintError> {
+    let mut v = 0i64;
+    let mut shift = 0;
+    loop {
+        let b = take_byte(bytes)?;
+        // the tenth byte holds bit 63 and nothing but its sign extension
+        if shift == 63 && b != 0 && b != 0x7f {
+            return Err(VarintError::Overflow);
+        }
+        v |= ((b & 0x7f) as i64) << shift;
+        shift += 7;
+        if b & 0x80 == 0 {
+            if shift < 64 && b & 0x40 != 0 {
+                v |= -1 << shift;
+            }
+            return Ok(v);
+        }
+    }
+}
+
+pub fn write_prefix(out: &mut Vec<u8>, v: u64) {
+    let len = len_prefix(v);
+    if len == MAX_PREFIX_LEN {
+        out.push(0xff);
+        out.extend_from_slice(&v.to_be_bytes());
+        return;
+    }
+    let extra = len - 1;
+    let tag = !(0xffu8 >> extra);
+    out.push(tag | (v >> (8 * extra)) as u8);
+    for i in (0..extra).rev() {
+        out.push((v >> (8 * i)) as u8);
+    }
+}
+
+pub fn read_prefix(bytes: &mut &[u8]) -> Result<u64, VarintError> {
+    let first = take_byte(bytes)?;
+    let extra = first.leading_ones() as usize;
+    if bytes.len() < extra {
+        return Err(VarintError::Truncated);
+    }
+    let (rest, tail) = bytes.split_at(extra);
+    *bytes = tail;
+    let high = if extra == 8 { 0 } else { (first & (0x7f >> extra)) as u64 };
+    Ok(rest.iter().fold(high, |v, &b| v << 8 | b as u64))
+}
+
+fn take_byte(bytes: &mut &[u8]) -> Result<u8, VarintError> {
+    let (&b, rest) = bytes.split_first().ok_or(VarintError::Truncated)?;
+    *bytes = rest;
+    Ok(b)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{len_i64, len_prefix, len_u64, read_i64, read_prefix, read_u64, write_i64, write_prefix, write_u64,
+                VarintError, MAX_LEN};
+
+    // Every value with its encoding, and each strict prefix of it reported
+    // as truncated
+    fn check<T: Copy + PartialEq + std::fmt::Debug>(v: T, len: usize, write: fn(&mut Vec<u8>, T),
+                                                     read: fn(&mut &[u8]) -> Result<T, VarintError>) {
+        let mut out = vec![0xaa];
+        write(&mut out, v);
+        out.push(0x55);
+        assert_eq!(out.len(), len + 2, "{:?}", v);
+        let mut rest = &out[1..];
+        assert_eq!(read(&mut rest), Ok(v));
+        assert_eq!(rest, [0x55]);
+        for cut in 1..=len {
+            assert_eq!(read(&mut &out[1..cut]), Err(VarintError::Truncated), "{:?}", v);
+        }
+    }
+
+    #[test]
+    fn test_boundaries() {
+        check(0, 1, write_u64, read_u64);
+        check(u64::MAX, MAX_LEN, write_u64, read_u64);
+        check(0, 1, write_prefix, read_prefix);
+        check(u64::MAX, 9, write_prefix, read_prefix);
+        for k in 1..=9 {
+            // 2^7k is the first value needing another byte
+            let edge = 1u64 << (7 * k);
+            for (v, len) in [(edge - 1, k), (edge, k + 1), (edge + 1, k + 1)] {
+                assert_eq!((len_u64(v), len_prefix(v)), (len, len.min(9)));
+                check(v, len, write_u64, read_u64);
+                check(v, len.min(9), write_prefix, read_prefix);
+            }
+            // a signed byte holds 6 bits and the sign
+            let edge = 1i64 << (7 * k - 1);
+            for (v, len) in [(edge - 1, k), (edge, k + 1), (-edge, k), (-edge - 1, k + 1)] {
+                assert_eq!(len_i64(v), len);
+                check(v, len, write_i64, read_i64);
+            }
+        }
+        for v in [0, -1, 63, 64, -64, -65, i64::MAX, i64::MIN] {
+            check(v, len_i64(v), write_i64, read_i64);
+        }
+        assert_eq!((len_i64(i64::MAX), len_i64(i64::MIN)), (MAX_LEN, MAX_LEN));
+    }
+
+    #[test]
+    fn test_encodings() {
+        let mut out = Vec::new();
+        write_u64(&mut out, 624_485);
+        write_i64(&mut out, -123_456);
+        write_prefix(&mut out, 300);
+        write_prefix(&mut out, 1 << 56);
+        assert_eq!(out[..8], [0xe5, 0x8e, 0x26, 0xc0, 0xbb, 0x78, 0x81, 0x2c]);
+        assert_eq!(out[8..], [0xff, 1, 0, 0, 0, 0, 0, 0, 0]);
+
+        assert_eq!(read_u64(&mut &[0x80, 0x00][..]), Ok(0));
+        assert_eq!(read_u64(&mut &[0xff; 9].iter().chain(&[0x02]).cloned().collect::<Vec<_>>()[..]),
+                   Err(VarintError::Overflow));
+        assert_eq!(read_u64(&mut &[0xff; 11][..]), Err(VarintError::Overflow));
+        assert_eq!(read_i64(&mut &[0x80; 9].iter().chain(&[0x7e]).cloned().collect::<Vec<_>>()[..]),
+                   Err(VarintError::Overflow));
+        assert_eq!(read_i64(&mut &[0xff; 11][..]), Err(VarintError::Overflow));
+    }
+
+}
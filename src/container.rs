@@ -0,0 +1,1860 @@
+// Self-describing compressed container. Everything needed to decompress
+// travels with the data:
+//
+//   magic        4 bytes  "ENTR"
+//   version      u8       FORMAT_VERSION
+//   codec        u8       Codec id
+//   check        u8       ChecksumKind id
+//   length       u64      length of the original data
+//   table_len    u32      length of the code table that follows
+//   table        table_len bytes (HuffmanCode::serialize_table)
+//   payload_len  u64      length of the packed codewords that follow
+//   payload      payload_len bytes (for Huffman, MSB-first and zero-padded)
+//   checksum     0, 2, 4 or 8 bytes (ChecksumKind::size) over the original data
+//
+// All integers are big-endian. Version 1 had no check byte and always
+// ended with a CRC-32; it can still be read.
+//
+// In block mode (CompressOptions::block_size) the BLOCKED bit is set in the
+// codec byte, the top-level table is empty (table_len 0) and the payload is
+// a run of blocks, each with its own code:
+//
+//   block_len    varint   number of original bytes in the block
+//   table_len    varint
+//   table        table_len bytes
+//   payload_len  varint
+//   payload      payload_len bytes
+//   checksum     over the block's original bytes, of the same kind
+//
+// so a corrupt block is caught as soon as it's decoded. The varints are
+// unsigned LEB128 (codes::varint); version 2 wrote these three as u32s.
+// The lengths in the container header stay fixed width, as compress_file
+// and seekable::append patch them in place once the blocks are written.
+//
+// The BwtPipeline codec has no table (table_len 0); each payload is the
+// output of Pipeline::bwt() on the block, stage list and all. The Lzss codec
+// Huffman codes the block's LZSS token bytes (lzss::Lzss::encode): the
+// table is for those, and the payload is their count as a u64, then their
+// codewords. The Ppm codec has no table either; its payload is the
+// ppm::Ppm output. All three default to
+// block mode, as their memory use grows with the block.
+//
+// CompressOptions::level sets the block size and, for Lzss, the match
+// finder and parse, for Ppm the context order (which its payload carries);
+// the level itself isn't recorded, as decoding doesn't depend on it.
+// With the `parallel` feature, block mode encodes and decodes its blocks
+// on rayon's thread pool; the output is the same byte for byte.
+//
+// A seekable container (CompressOptions::seekable) is in block mode with
+// the SEEKABLE bit also set in the codec byte, and ends with a seek table
+// after the checksum:
+//
+//   frame_offset varint   where the block's frame starts in the container
+//   data_offset  varint   where its bytes start in the original data
+//     ...                 once per block
+//   size         u32      length of the entries above, in bytes
+//   magic        4 bytes  SEEK_MAGIC
+//
+// so crate::seekable::SeekableDecoder can find it from the end of the file
+// and go straight to the block holding any position. In version 2 the
+// offsets were u64s, and in place of the size came the number of blocks.
+// Its checksum is over the blocks' checksums (each ChecksumKind::size
+// bytes) rather than over the data, so blocks can be appended
+// (crate::seekable::append) without reading back what's there;
+// the blocks' own checksums still cover every byte.
+//
+// With CompressOptions::ecc the whole container, built as above, is
+// wrapped for forward error correction:
+//
+//   magic        4 bytes  ECC_MAGIC
+//   protected    crate::ecc::reed_solomon::ReedSolomon::protect output
+//
+// Decoding repairs what it can before reading the container inside. The
+// magic is still recognized with one byte of it damaged.
+//
+// compress_file and decompress_file write and read the same format a block
+// at a time, so file size isn't limited by memory. Error-corrected
+// containers are the exception: they are built and read whole. With the
+// `mmap` feature, compress_mmap maps the input file instead of reading it;
+// it's unsafe, as nothing may change the file while it's mapped.
+// Their *_with_progress versions report how far they've got, and their
+// *_cancellable versions can also be stopped partway (progress.rs).
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::bitio::{BitReader, BitWriter};
+use crate::checksum::ChecksumKind;
+use crate::codes::varint::{self, VarintError};
+use crate::ecc::reed_solomon::{self, ReedSolomon, RsError};
+use crate::histogram;
+use crate::huffman::{ByteHuffman, TableError};
+use crate::limits::{DecodeOptions, Limit, LimitExceeded};
+use crate::lzss::{self, Lzss};
+#[cfg(feature = "mmap")]
+use crate::mmap::Mmap;
+use crate::pipeline::{Pipeline, PipelineError};
+use crate::ppm::{Ppm, PpmError};
+use crate::progress::{Counter, Monitor, Progress, Stage};
+
+pub const MAGIC: [u8; 4] = *b"ENTR";
+pub const FORMAT_VERSION: u8 = 3;
+pub const BLOCKED: u8 = 0x80;
+pub const SEEKABLE: u8 = 0x40;
+pub const SEEK_MAGIC: [u8; 4] = *b"ESEK";
+pub const ECC_MAGIC: [u8; 4] = *b"EFEC";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Codec {
+    Huffman,     // static byte-oriented Huffman code built from the data
+    BwtPipeline, // BWT, move-to-front, run-length and Huffman, as bzip2
+    Lzss,        // LZSS tokens, Huffman coded
+    Ppm,         // PPM context modelling, arithmetic coded; for text
+}
+
+// bzip2's largest (-9) block
+pub const BWT_BLOCK_SIZE: usize = 900_000;
+pub const LZSS_BLOCK_SIZE: usize = 1 << 20;
+pub const PPM_BLOCK_SIZE: usize = 1 << 20;
+pub const DEFAULT_LEVEL: u8 = 6;
+// for seekable containers that don't set a block size
+pub const SEEKABLE_BLOCK_SIZE: usize = 1 << 20;
+
+impl Codec {
+
+    pub const ALL: [Codec; 4] = [Codec::Huffman, Codec::BwtPipeline, Codec::Lzss, Codec::Ppm];
+
+    pub fn id(self) -> u8 {
+        match self {
+            Codec::Huffman => 1,
+            Codec::BwtPipeline => 2,
+            Codec::Lzss => 3,
+            Codec::Ppm => 4,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Codec> {
+        Codec::ALL.iter().cloned().find(|c| c.id() == id)
+    }
+
+    // The level CompressOptions::new starts out at: DEFAULT_LEVEL, but 9
+    // for BwtPipeline, bzip2's default
+    pub fn default_level(self) -> u8 {
+        match self {
+            Codec::BwtPipeline => 9,
+            _ => DEFAULT_LEVEL,
+        }
+    }
+
+    // Block size for a compression level. Huffman rebuilds its table more
+    // often from level 7 up; BwtPipeline takes 100K blocks times the level,
+    // as bzip2 does. Panics unless 1 <= level <= 9.
+    pub fn block_size(self, level: u8) -> Option<usize> {
+        assert!((1..=9).contains(&level), "level must be in 1..=9");
+        match self {
+            Codec::Huffman => match level {
+                1..=6 => None,
+                7 => Some(256 * 1024),
+                8 => Some(64 * 1024),
+                _ => Some(16 * 1024),
+            },
+            Codec::BwtPipeline => Some(BWT_BLOCK_SIZE / 9 * level as usize),
+            Codec::Lzss => Some(LZSS_BLOCK_SIZE),
+            Codec::Ppm => Some(PPM_BLOCK_SIZE),
+        }
+    }
+
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContainerError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnknownCodec(u8),
+    UnknownChecksum(u8),
+    Truncated,
+    Table(TableError),
+    CorruptPayload,
+    ChecksumMismatch,
+    NotSeekable,
+    BadSeekTable,
+    // Appending with a codec or checksum other than the container's
+    OptionsMismatch,
+    // An error-corrected container too damaged to repair
+    Ecc(RsError),
+    // Decoding would go past a DecodeOptions limit
+    LimitExceeded(LimitExceeded),
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContainerError::BadMagic => write!(f, "not an entrust container"),
+            ContainerError::UnsupportedVersion(v) => write!(f, "unsupported container version {}", v),
+            ContainerError::UnknownCodec(id) => write!(f, "unknown codec id {}", id),
+            ContainerError::UnknownChecksum(id) => write!(f, "unknown checksum id {}", id),
+            ContainerError::Truncated => write!(f, "container is truncated"),
+            ContainerError::Table(e) => write!(f, "invalid code table: {}", e),
+            ContainerError::CorruptPayload => write!(f, "compressed payload is corrupt"),
+            ContainerError::ChecksumMismatch => write!(f, "checksum mismatch"),
+            ContainerError::NotSeekable => write!(f, "container has no seek table"),
+            ContainerError::BadSeekTable => write!(f, "seek table is corrupt"),
+            ContainerError::OptionsMismatch => write!(f, "options don't match the container's codec and checksum"),
+            ContainerError::Ecc(e) => write!(f, "error correction failed: {}", e),
+            ContainerError::LimitExceeded(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ContainerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ContainerError::Table(e) => Some(e),
+            ContainerError::Ecc(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<TableError> for ContainerError {
+    fn from(e: TableError) -> Self {
+        match e {
+            TableError::LimitExceeded(e) => ContainerError::LimitExceeded(e),
+            e => ContainerError::Table(e),
+        }
+    }
+}
+
+impl From<LimitExceeded> for ContainerError {
+    fn from(e: LimitExceeded) -> Self {
+        ContainerError::LimitExceeded(e)
+    }
+}
+
+impl From<RsError> for ContainerError {
+    fn from(e: RsError) -> Self {
+        ContainerError::Ecc(e)
+    }
+}
+
+impl From<VarintError> for ContainerError {
+    fn from(e: VarintError) -> Self {
+        match e {
+            VarintError::Truncated => ContainerError::Truncated,
+            VarintError::Overflow => ContainerError::CorruptPayload,
+        }
+    }
+}
+
+// For the file functions; the ContainerError is kept as the inner error
+impl From<ContainerError> for io::Error {
+    fn from(e: ContainerError) -> Self {
+        let kind = match e {
+            ContainerError::Truncated => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+// Deserialized through RawCompressOptions, so that options read from
+// JSON and the like get the checks the builders make
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "RawCompressOptions"))]
+pub struct CompressOptions {
+    codec: Codec,
+    block_size: Option<usize>,
+    checksum: ChecksumKind,
+    level: u8,
+    seekable: bool,
+    ecc: u8,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct RawCompressOptions {
+    codec: Codec,
+    block_size: Option<usize>,
+    checksum: ChecksumKind,
+    #[serde(default = "default_level")]
+    level: u8,
+    #[serde(default)]
+    seekable: bool,
+    #[serde(default)]
+    ecc: u8,
+}
+
+#[cfg(feature = "serde")]
+fn default_level() -> u8 {
+    DEFAULT_LEVEL
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawCompressOptions> for CompressOptions {
+    type Error = &'static str;
+
+    fn try_from(raw: RawCompressOptions) -> Result<Self, Self::Error> {
+        let RawCompressOptions { codec, block_size, checksum, level, seekable, ecc } = raw;
+        if block_size.is_some_and(|size| size == 0 || size as u64 > u32::MAX as u64) {
+            return Err("block size must be in 1..=u32::MAX");
+        }
+        if !(1..=9).contains(&level) {
+            return Err("level must be in 1..=9");
+        }
+        if ecc == 255 {
+            return Err("parity must be in 0..=254");
+        }
+        Ok(CompressOptions { codec, block_size, checksum, level, seekable, ecc })
+    }
+}
+
+impl CompressOptions {
+
+    // Checked with CRC-32, at the codec's default level: for Huffman, one
+    // code for the whole input
+    pub fn new(codec: Codec) -> Self {
+        let level = codec.default_level();
+        CompressOptions { codec, block_size: codec.block_size(level), checksum: ChecksumKind::Crc32, level,
+                          seekable: false, ecc: 0 }
+    }
+
+    // Compression level from 1 (fastest) to 9 (smallest), as in gzip and
+    // zstd: sets the block size (Codec::block_size) and, for Lzss, the
+    // match finder and parse (Lzss::level). A block size set before this is
+    // replaced. Panics unless 1 <= level <= 9.
+    pub fn level(mut self, level: u8) -> Self {
+        self.block_size = self.codec.block_size(level);
+        self.level = level;
+        self
+    }
+
+    // Split the input into blocks of `size` bytes (the last may be shorter),
+    // each with a code of its own. Worth it when the symbol distribution
+    // drifts through the input by more than a table costs per block.
+    // Panics unless 0 < size <= u32::MAX.
+    pub fn block_size(mut self, size: usize) -> Self {
+        assert!(size > 0 && size as u64 <= u32::MAX as u64, "block size must be in 1..=u32::MAX");
+        self.block_size = Some(size);
+        self
+    }
+
+    // Also applies to each block in block mode. ChecksumKind::None saves
+    // the bytes but leaves corruption to be noticed (or not) by the decoder.
+    pub fn checksum(mut self, kind: ChecksumKind) -> Self {
+        self.checksum = kind;
+        self
+    }
+
+    // Write a seek table, for crate::seekable. This needs block mode: with
+    // no block size set, blocks are SEEKABLE_BLOCK_SIZE. Smaller blocks
+    // cost a little compression and make random reads cheaper.
+    pub fn seekable(mut self, seekable: bool) -> Self {
+        self.seekable = seekable;
+        self
+    }
+
+    // Wrap the container in Reed-Solomon codewords with `parity` parity
+    // bytes each (0, the default, for none), so up to parity / 2 damaged
+    // bytes in every 255 can be repaired; 32 costs about 14% in size. An
+    // error-corrected container isn't seekable by crate::seekable.
+    // Panics if parity is 255.
+    pub fn ecc(mut self, parity: u8) -> Self {
+        assert!(parity < 255, "parity must be in 0..=254");
+        self.ecc = parity;
+        self
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub fn get_checksum(&self) -> ChecksumKind {
+        self.checksum
+    }
+
+    pub fn get_block_size(&self) -> Option<usize> {
+        match self.block_size {
+            None if self.seekable => Some(SEEKABLE_BLOCK_SIZE),
+            size => size,
+        }
+    }
+
+    pub fn is_seekable(&self) -> bool {
+        self.seekable
+    }
+
+    pub fn get_level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn get_ecc(&self) -> u8 {
+        self.ecc
+    }
+
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        CompressOptions::new(Codec::Huffman)
+    }
+}
+
+pub fn compress_to_vec(data: &[u8], codec: Codec) -> Vec<u8> {
+    compress_with(data, &CompressOptions::new(codec))
+}
+
+pub fn compress_with(data: &[u8], options: &CompressOptions) -> Vec<u8> {
+    let check = options.checksum;
+    let mut frames = Vec::new();
+    let (table, payload) = match options.get_block_size() {
+        None => encode_block(data, options),
+        Some(size) => {
+            let blocks: Vec<&[u8]> = data.chunks(size).collect();
+            #[cfg(feature = "parallel")]
+            let encoded: Vec<Vec<u8>> = blocks.par_iter().map(|b| encode_frame(b, options, true)).collect();
+            #[cfg(not(feature = "parallel"))]
+            let encoded: Vec<Vec<u8>> = blocks.iter().map(|b| encode_frame(b, options, true)).collect();
+            frames = encoded.iter().zip(&blocks).map(|(f, b)| (f.len() as u64, b.len() as u64)).collect();
+            (Vec::new(), encoded.concat())
+        }
+    };
+
+    let mut out = Vec::with_capacity(35 + table.len() + payload.len());
+    out.extend_from_slice(&header(options, data.len() as u64));
+    out.extend_from_slice(&(table.len() as u32).to_be_bytes());
+    out.extend_from_slice(&table);
+    out.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    let start = out.len() as u64;
+    out.extend_from_slice(&payload);
+    if options.seekable {
+        let checksum = check.compute(&frame_checksums(&payload, &frames, check));
+        put_checksum(&mut out, check, checksum);
+        out.extend(seek_table(&seek_entries(&frames, start, 0), true));
+    } else {
+        put_checksum(&mut out, check, check.compute(data));
+    }
+    if options.ecc > 0 {
+        let mut wrapped = ECC_MAGIC.to_vec();
+        wrapped.extend(ReedSolomon::new(options.ecc as usize).protect(&out));
+        return wrapped;
+    }
+    out
+}
+
+// Everything ahead of the table length
+fn header(options: &CompressOptions, len: u64) -> Vec<u8> {
+    let mut codec_id = options.codec.id();
+    if options.get_block_size().is_some() {
+        codec_id |= BLOCKED;
+    }
+    if options.seekable {
+        codec_id |= SEEKABLE;
+    }
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&[FORMAT_VERSION, codec_id, options.checksum.id()]);
+    out.extend_from_slice(&len.to_be_bytes());
+    out
+}
+
+// (table, payload) for one block
+fn encode_block(data: &[u8], options: &CompressOptions) -> (Vec<u8>, Vec<u8>) {
+    match options.codec {
+        Codec::Huffman => {
+            if data.is_empty() {
+                (0u32.to_be_bytes().to_vec(), Vec::new())
+            } else {
+                let code = ByteHuffman::new_bytes(data);
+                (code.serialize_table(), code.encode_bytes_packed(data).0)
+            }
+        }
+        Codec::BwtPipeline => (Vec::new(), Pipeline::bwt().compress(data)),
+        Codec::Lzss => {
+            // never empty: the tokens start with a header byte
+            let tokens = Lzss::level(options.level).encode(data);
+            let code = ByteHuffman::new_bytes(&tokens);
+            let mut payload = (tokens.len() as u64).to_be_bytes().to_vec();
+            payload.extend(code.encode_bytes_packed(&tokens).0);
+            (code.serialize_table(), payload)
+        }
+        Codec::Ppm => (Vec::new(), Ppm::level(options.level).compress(data)),
+    }
+}
+
+// One block in block mode, with its header and checksum; its lengths as
+// varints, or as u32s for a version 2 container
+pub(crate) fn encode_frame(data: &[u8], options: &CompressOptions, varints: bool) -> Vec<u8> {
+    let check = options.checksum;
+    let (table, packed) = encode_block(data, options);
+    let mut out = Vec::with_capacity(20 + table.len() + packed.len());
+    put_len(&mut out, data.len() as u64, varints);
+    put_len(&mut out, table.len() as u64, varints);
+    out.extend_from_slice(&table);
+    put_len(&mut out, packed.len() as u64, varints);
+    out.extend_from_slice(&packed);
+    put_checksum(&mut out, check, check.compute(data));
+    out
+}
+
+fn put_len(out: &mut Vec<u8>, len: u64, varint: bool) {
+    if varint {
+        varint::write_u64(out, len);
+    } else {
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+pub(crate) fn put_checksum(out: &mut Vec<u8>, check: ChecksumKind, value: u64) {
+    out.extend_from_slice(&value.to_be_bytes()[8 - check.size()..]);
+}
+
+// The checksum bytes at the end of each frame in `payload`, given the
+// frames' (frame length, block length)s, for a seekable container's checksum
+fn frame_checksums(payload: &[u8], frames: &[(u64, u64)], check: ChecksumKind) -> Vec<u8> {
+    let mut end = 0;
+    let mut out = Vec::with_capacity(frames.len() * check.size());
+    for &(frame_len, _) in frames {
+        end += frame_len as usize;
+        out.extend_from_slice(&payload[end - check.size()..end]);
+    }
+    out
+}
+
+// The seek table for (frame offset, data offset) entries, as varints or
+// as the u64s of a version 2 container
+pub(crate) fn seek_table(entries: &[(u64, u64)], varints: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(16 * entries.len() + 8);
+    for &(frame_offset, data_offset) in entries {
+        if varints {
+            varint::write_u64(&mut out, frame_offset);
+            varint::write_u64(&mut out, data_offset);
+        } else {
+            out.extend_from_slice(&frame_offset.to_be_bytes());
+            out.extend_from_slice(&data_offset.to_be_bytes());
+        }
+    }
+    let size = if varints { out.len() } else { entries.len() };
+    out.extend_from_slice(&(size as u32).to_be_bytes());
+    out.extend_from_slice(&SEEK_MAGIC);
+    out
+}
+
+// Entries for frames of the given (frame length, block length)s, the first
+// at `frame_offset` in the container and `data_offset` in the data
+pub(crate) fn seek_entries(frames: &[(u64, u64)], mut frame_offset: u64, mut data_offset: u64) -> Vec<(u64, u64)> {
+    frames.iter().map(|&(frame_len, block_len)| {
+        let entry = (frame_offset, data_offset);
+        frame_offset += frame_len;
+        data_offset += block_len;
+        entry
+    }).collect()
+}
+
+// What the version, codec and checksum bytes say
+#[derive(Clone, Copy)]
+pub(crate) struct Format {
+    pub(crate) codec: Codec,
+    pub(crate) blocked: bool,
+    pub(crate) seekable: bool,
+    // for the whole input, and for each block
+    pub(crate) check: ChecksumKind,
+    pub(crate) block_check: ChecksumKind,
+    // frame and seek table lengths are varints (from version 3)
+    pub(crate) varints: bool,
+}
+
+impl Format {
+
+    // Version 1 has no checksum byte; it always ends with a CRC-32 and
+    // its blocks carry none
+    fn parse(version: u8, codec_id: u8, check_id: Option<u8>) -> Result<Format, ContainerError> {
+        let codec = Codec::from_id(codec_id & !(BLOCKED | SEEKABLE)).ok_or(ContainerError::UnknownCodec(codec_id))?;
+        let blocked = codec_id & BLOCKED != 0;
+        let seekable = codec_id & SEEKABLE != 0;
+        // seek tables came after version 1, and need blocks
+        if seekable && (version == 1 || !blocked) {
+            return Err(ContainerError::UnknownCodec(codec_id));
+        }
+        match (version, check_id) {
+            (1, _) => Ok(Format { codec, blocked, seekable, check: ChecksumKind::Crc32,
+                                  block_check: ChecksumKind::None, varints: false }),
+            (2..=3, Some(id)) => {
+                let check = ChecksumKind::from_id(id).ok_or(ContainerError::UnknownChecksum(id))?;
+                Ok(Format { codec, blocked, seekable, check, block_check: check, varints: version >= 3 })
+            }
+            _ => Err(ContainerError::UnsupportedVersion(version)),
+        }
+    }
+
+}
+
+// The fixed fields at the front of a container, for looking at one
+// without decompressing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Header {
+    pub version: u8,
+    pub codec: Codec,
+    pub blocked: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub seekable: bool,
+    pub checksum: ChecksumKind,
+    pub len: u64, // length of the original data
+    // Parity bytes per error correction codeword, 0 for none
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ecc: u8,
+}
+
+// An error-corrected container is repaired, all of it, before its header
+// is read
+pub fn read_header(bytes: &[u8]) -> Result<Header, ContainerError> {
+    if is_protected(bytes) {
+        let mut rest = &reed_solomon::recover(&bytes[4..])?[..];
+        let mut header = parse_header(&mut rest)?.0;
+        header.ecc = reed_solomon::read_parity(&bytes[4..])? as u8;
+        return Ok(header);
+    }
+    let mut rest = bytes;
+    Ok(parse_header(&mut rest)?.0)
+}
+
+// Starts with ECC_MAGIC, give or take a byte
+fn is_protected(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && bytes[..4].iter().zip(&ECC_MAGIC).filter(|(a, b)| a != b).count() <= 1
+}
+
+fn parse_header(rest: &mut &[u8]) -> Result<(Header, Format), ContainerError> {
+    if take(rest, 4)? != MAGIC {
+        return Err(ContainerError::BadMagic);
+    }
+    let version = take(rest, 1)?[0];
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(ContainerError::UnsupportedVersion(version));
+    }
+    let codec_id = take(rest, 1)?[0];
+    let check_id = if version >= 2 { Some(take(rest, 1)?[0]) } else { None };
+    let format = Format::parse(version, codec_id, check_id)?;
+    let len = take_u64(rest)?;
+    let header = Header { version, codec: format.codec, blocked: format.blocked, seekable: format.seekable,
+                          checksum: format.check, len, ecc: 0 };
+    Ok((header, format))
+}
+
+pub fn decompress_from_slice(bytes: &[u8]) -> Result<Vec<u8>, ContainerError> {
+    decompress_from_slice_with(bytes, &DecodeOptions::new())
+}
+
+// decompress_from_slice, failing with LimitExceeded before decoding
+// anything if the container holds more than `limits` allows. Its length
+// is checked against max_output_bytes up front, as the blocks have to add
+// up to it; every code table against the table limits.
+pub fn decompress_from_slice_with(bytes: &[u8], limits: &DecodeOptions) -> Result<Vec<u8>, ContainerError> {
+    let recovered;
+    let mut rest = if is_protected(bytes) {
+        recovered = reed_solomon::recover(&bytes[4..])?;
+        &recovered[..]
+    } else {
+        bytes
+    };
+    let (Header { len, .. }, format) = parse_header(&mut rest)?;
+    limits.check_output(len)?;
+    let table_len = take_u32(&mut rest)? as usize;
+    let table = take(&mut rest, table_len)?;
+    let payload_len = take_u64(&mut rest)?;
+    if payload_len > rest.len() as u64 {
+        return Err(ContainerError::Truncated);
+    }
+    let payload = take(&mut rest, payload_len as usize)?;
+    let checksum = take_checksum(&mut rest, format.check)?;
+
+    let (data, computed) = if format.blocked {
+        let blocks = block_index(payload, len, format)?;
+        let data = decode_blocks(format, &blocks, len, limits)?;
+        let computed = if format.seekable {
+            let mut digest = format.check.digest();
+            for block in &blocks {
+                digest.update(&block.checksum.to_be_bytes()[8 - format.check.size()..]);
+            }
+            digest.finish()
+        } else {
+            format.check.compute(&data)
+        };
+        (data, computed)
+    } else {
+        let data = decode_block(format.codec, table, payload, len, limits)?;
+        let computed = format.check.compute(&data);
+        (data, computed)
+    };
+    if computed != checksum {
+        return Err(ContainerError::ChecksumMismatch);
+    }
+    Ok(data)
+}
+
+// Where one block of a block-mode payload sits
+struct BlockRef<'a> {
+    len: u64,
+    table: &'a [u8],
+    packed: &'a [u8],
+    checksum: u64,
+}
+
+// The frame index: every block's header, found by walking the lengths, so
+// the blocks can be decoded independently of each other
+fn block_index(mut payload: &[u8], len: u64, format: Format) -> Result<Vec<BlockRef<'_>>, ContainerError> {
+    let mut blocks = Vec::new();
+    let mut total = 0u64;
+    while !payload.is_empty() {
+        let block = parse_frame(&mut payload, format)?;
+        if block.len > len - total {
+            return Err(ContainerError::CorruptPayload);
+        }
+        total += block.len;
+        blocks.push(block);
+    }
+    if total != len {
+        return Err(ContainerError::Truncated);
+    }
+    Ok(blocks)
+}
+
+fn parse_frame<'a>(payload: &mut &'a [u8], format: Format) -> Result<BlockRef<'a>, ContainerError> {
+    let len = take_len(payload, format.varints)?;
+    let table_len = take_len(payload, format.varints)?;
+    let table = take_bounded(payload, table_len)?;
+    let payload_len = take_len(payload, format.varints)?;
+    let packed = take_bounded(payload, payload_len)?;
+    let checksum = take_checksum(payload, format.block_check)?;
+    if len == 0 {
+        return Err(ContainerError::CorruptPayload);
+    }
+    Ok(BlockRef { len, table, packed, checksum })
+}
+
+fn decode_blocks(format: Format, blocks: &[BlockRef], len: u64, limits: &DecodeOptions)
+    -> Result<Vec<u8>, ContainerError> {
+    let decode = |b: &BlockRef| decode_checked(format, b, limits);
+    #[cfg(feature = "parallel")]
+    let decoded: Result<Vec<Vec<u8>>, ContainerError> = blocks.par_iter().map(decode).collect();
+    #[cfg(not(feature = "parallel"))]
+    let decoded: Result<Vec<Vec<u8>>, ContainerError> = blocks.iter().map(decode).collect();
+    // don't trust `len` with a huge up-front allocation
+    let mut data = Vec::with_capacity(len.min(1 << 20) as usize);
+    for block in decoded? {
+        data.extend_from_slice(&block);
+    }
+    Ok(data)
+}
+
+fn decode_checked(format: Format, block: &BlockRef, limits: &DecodeOptions) -> Result<Vec<u8>, ContainerError> {
+    let data = decode_block(format.codec, block.table, block.packed, block.len, limits)?;
+    if format.block_check.compute(&data) != block.checksum {
+        return Err(ContainerError::ChecksumMismatch);
+    }
+    Ok(data)
+}
+
+// A block-mode frame on its own: exactly one frame, whose bytes are
+// checked against its checksum. Its length is checked against
+// max_output_bytes before it's decoded.
+pub(crate) fn decode_frame(format: Format, mut frame: &[u8], limits: &DecodeOptions)
+    -> Result<Vec<u8>, ContainerError> {
+    let block = parse_frame(&mut frame, format)?;
+    if !frame.is_empty() {
+        return Err(ContainerError::CorruptPayload);
+    }
+    limits.check_output(block.len)?;
+    decode_checked(format, &block, limits)
+}
+
+fn decode_block(codec: Codec, table: &[u8], payload: &[u8], len: u64, limits: &DecodeOptions)
+    -> Result<Vec<u8>, ContainerError> {
+    match codec {
+        Codec::Huffman => decode_huffman(table, payload, len, limits),
+        Codec::BwtPipeline => {
+            // as for Lzss, stop at the block's length. The stages before
+            // carry the 8-byte BWT primary index, and the run-length codes
+            // Huffman gives back can be three times that long. A table over
+            // the caller's limits is still theirs to hear about.
+            let stages = limits.max_output_bytes(len.saturating_add(8).saturating_mul(3));
+            let pipeline = Pipeline::bwt().limits(limits.max_output_bytes(len)).stage_limits(stages);
+            let data = pipeline.decompress_with(payload).map_err(|e| match e {
+                PipelineError::LimitExceeded(e) if e.limit != Limit::OutputBytes => ContainerError::LimitExceeded(e),
+                _ => ContainerError::CorruptPayload,
+            })?;
+            if data.len() as u64 != len {
+                return Err(ContainerError::CorruptPayload);
+            }
+            Ok(data)
+        }
+        Codec::Lzss => {
+            let mut payload = payload;
+            let count = take_u64(&mut payload)?;
+            let tokens = decode_huffman(table, payload, count, limits)?;
+            // the tokens can stand for far more than their size; stop them
+            // at the block's length rather than check it afterwards
+            let data = lzss::decode_with(&tokens, &limits.max_output_bytes(len))
+                .map_err(|_| ContainerError::CorruptPayload)?;
+            if data.len() as u64 != len {
+                return Err(ContainerError::CorruptPayload);
+            }
+            Ok(data)
+        }
+        Codec::Ppm => {
+            let len = usize::try_from(len).map_err(|_| ContainerError::CorruptPayload)?;
+            Ppm::decompress(payload, len).map_err(|e| match e {
+                PpmError::Truncated => ContainerError::Truncated,
+                _ => ContainerError::CorruptPayload,
+            })
+        }
+    }
+}
+
+fn decode_huffman(table: &[u8], payload: &[u8], len: u64, limits: &DecodeOptions)
+    -> Result<Vec<u8>, ContainerError> {
+    let code = ByteHuffman::from_table_with(table, limits)?;
+    let mut reader = BitReader::new(payload);
+    // don't trust `len` with a huge up-front allocation
+    let mut data = Vec::with_capacity(len.min(1 << 20) as usize);
+    for _ in 0..len {
+        data.push(code.read_symbol(&mut reader).map_err(|_| ContainerError::CorruptPayload)?);
+    }
+    Ok(data)
+}
+
+const FILE_BLOCK: usize = 64 * 1024;
+
+pub fn compress_file<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q, codec: Codec) -> io::Result<()> {
+    compress_file_with(input, output, &CompressOptions::new(codec))
+}
+
+// Without a block size, two passes over `input`: the first counts bytes
+// and takes the checksum, the second encodes into `output` as it reads.
+// Fails if the input changes between the passes. In block mode it's one
+// pass, a block at a time. Either way memory use doesn't depend on the
+// file size.
+pub fn compress_file_with<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q, options: &CompressOptions)
+    -> io::Result<()>
+{
+    compress_file_with_progress(input, output, options, |_| {})
+}
+
+// compress_file_with, calling `progress` after every FILE_BLOCK of input
+// in each pass (or after every block in block mode), then once more with
+// Stage::Done
+pub fn compress_file_with_progress<P, Q, F>(input: P, output: Q, options: &CompressOptions, mut progress: F)
+    -> io::Result<()>
+    where P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(Progress)
+{
+    compress_file_monitored(input.as_ref(), output.as_ref(), options, &mut Monitor::new(&mut progress, None))
+}
+
+// compress_file_with_progress, stopping with a progress::Cancelled error
+// at the next report after `cancel` is set. The output is written to a
+// temporary file beside `output` and only renamed into place once it's
+// complete, so on any error, cancellation included, `output` is untouched.
+pub fn compress_file_cancellable<P, Q, F>(input: P, output: Q, options: &CompressOptions, cancel: &AtomicBool,
+                                          mut progress: F) -> io::Result<()>
+    where P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(Progress)
+{
+    let mut monitor = Monitor::new(&mut progress, Some(cancel));
+    through_temp(output.as_ref(), |temp| compress_file_monitored(input.as_ref(), temp, options, &mut monitor))
+}
+
+fn compress_file_monitored(input: &Path, output: &Path, options: &CompressOptions, monitor: &mut Monitor)
+    -> io::Result<()>
+{
+    let check = options.checksum;
+    if options.ecc > 0 {
+        let data = std::fs::read(input)?;
+        let packed = compress_with(&data, options);
+        std::fs::write(output, &packed)?;
+        let consumed = data.len() as u64;
+        let produced = packed.len() as u64;
+        return monitor.report(Progress { stage: Stage::Done, consumed, total: Some(consumed), produced });
+    }
+    if let Some(size) = options.get_block_size() {
+        let input = File::open(input)?;
+        let total = Some(input.metadata()?.len());
+        return compress_blocks(input, total, output, options, size, monitor);
+    }
+    let total = Some(std::fs::metadata(input)?.len());
+    let (counts, len, checksum) = scan_file(input, check, &mut |consumed| {
+        monitor.report(Progress { stage: Stage::Scanning, consumed, total, produced: 0 })
+    })?;
+
+    let mut out = BufWriter::new(Counter::new(File::create(output)?));
+    out.write_all(&header(options, len))?;
+
+    match options.codec {
+        Codec::Huffman => {
+            write_huffman(&mut out, &counts, |code, out| {
+                let start = written(out);
+                encode_file(input, code, out, len, check, checksum, &mut |consumed, produced| {
+                    monitor.report(Progress { stage: Stage::Encoding, consumed, total, produced: start + produced })
+                })
+            })?;
+        }
+        Codec::BwtPipeline | Codec::Lzss | Codec::Ppm => {
+            // these only go without blocks when a deserialized CompressOptions
+            // says so; the whole file is encoded at once
+            let data = std::fs::read(input)?;
+            if data.len() as u64 != len || check.compute(&data) != checksum {
+                return Err(io::Error::other("input file changed during compression"));
+            }
+            let (table, payload) = encode_block(&data, options);
+            out.write_all(&(table.len() as u32).to_be_bytes())?;
+            out.write_all(&table)?;
+            out.write_all(&(payload.len() as u64).to_be_bytes())?;
+            out.write_all(&payload)?;
+            monitor.report(Progress { stage: Stage::Encoding, consumed: len, total, produced: written(&out) })?;
+        }
+    }
+
+    let mut trailer = Vec::new();
+    put_checksum(&mut trailer, check, checksum);
+    out.write_all(&trailer)?;
+    out.flush()?;
+    monitor.report(Progress { stage: Stage::Done, consumed: len, total, produced: out.get_ref().count() })
+}
+
+/// compress_file_with, with `input` memory-mapped (see mmap.rs) for the
+/// counting and encoding passes instead of read through a buffer twice.
+/// Blocks are encoded from the map and written one at a time, as
+/// compress_file_with does; only an error-corrected container is built
+/// whole in memory first. The output is the same either way.
+///
+/// # Safety
+///
+/// Nothing, in this process or any other, may truncate or write to `input`
+/// until this returns. The map points straight at the file's pages: a read
+/// past a new end of the file kills the process with SIGBUS, and a write
+/// changes bytes the encoder has already counted, behind a shared slice.
+/// compress_file_with is the safe way to compress a file that may change.
+#[cfg(feature = "mmap")]
+pub unsafe fn compress_mmap<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q, options: &CompressOptions)
+    -> io::Result<()>
+{
+    // SAFETY: our caller promised the file stays as it is
+    let data = unsafe { Mmap::open(input.as_ref())? };
+    if options.ecc > 0 {
+        return std::fs::write(output, compress_with(&data, options));
+    }
+    if let Some(size) = options.get_block_size() {
+        let mut ignore = |_| {};
+        let mut monitor = Monitor::new(&mut ignore, None);
+        return compress_blocks(&data[..], Some(data.len() as u64), output.as_ref(), options, size, &mut monitor);
+    }
+    let check = options.checksum;
+    let checksum = check.compute(&data);
+    let mut out = BufWriter::new(File::create(output)?);
+    out.write_all(&header(options, data.len() as u64))?;
+    match options.codec {
+        Codec::Huffman => {
+            write_huffman(&mut out, &histogram::count_bytes(&data), |code, out| {
+                let mut bits = BitWriter::new(out);
+                for &b in data.iter() {
+                    code.write_byte(b, &mut bits)?;
+                }
+                bits.align()?;
+                bits.flush()
+            })?;
+        }
+        Codec::BwtPipeline | Codec::Lzss | Codec::Ppm => {
+            // unblocked only from a deserialized CompressOptions, as above
+            let (table, payload) = encode_block(&data, options);
+            out.write_all(&(table.len() as u32).to_be_bytes())?;
+            out.write_all(&table)?;
+            out.write_all(&(payload.len() as u64).to_be_bytes())?;
+            out.write_all(&payload)?;
+        }
+    }
+    let mut trailer = Vec::new();
+    put_checksum(&mut trailer, check, checksum);
+    out.write_all(&trailer)?;
+    out.flush()
+}
+
+// Table and payload of a whole-input Huffman container from byte counts;
+// `encode` writes the packed codewords of every byte, in order
+fn write_huffman<W: Write, F>(out: &mut W, counts: &[u64; 256], encode: F) -> io::Result<()>
+    where F: FnOnce(&ByteHuffman, &mut W) -> io::Result<()> {
+    let freqs: HashMap<u8, u64> = (0..=255u8)
+        .filter(|&b| counts[b as usize] > 0)
+        .map(|b| (b, counts[b as usize]))
+        .collect();
+    if freqs.is_empty() {
+        // an empty table, as compress_to_vec writes
+        out.write_all(&4u32.to_be_bytes())?;
+        out.write_all(&0u32.to_be_bytes())?;
+        out.write_all(&0u64.to_be_bytes())?;
+        return Ok(());
+    }
+    let code = ByteHuffman::from_frequencies(&freqs);
+    let table = code.serialize_table();
+    out.write_all(&(table.len() as u32).to_be_bytes())?;
+    out.write_all(&table)?;
+    let nbits: u64 = freqs.iter().map(|(b, &f)| f * code.codewords()[b].len() as u64).sum();
+    out.write_all(&nbits.div_ceil(8).to_be_bytes())?;
+    encode(&code, out)
+}
+
+// The payload length goes ahead of the blocks, so the output is written
+// with a placeholder there and patched at the end. `input` is a file, or
+// a memory map read as a slice; `total` is its length, for progress.
+fn compress_blocks<R: Read>(mut input: R, total: Option<u64>, output: &Path, options: &CompressOptions, size: usize,
+                            monitor: &mut Monitor) -> io::Result<()> {
+    let check = options.checksum;
+    let mut out = BufWriter::new(Counter::new(File::create(output)?));
+    let header = header(options, 0);
+    out.write_all(&header)?;
+    out.write_all(&0u32.to_be_bytes())?;
+    out.write_all(&0u64.to_be_bytes())?;
+
+    let mut digest = check.digest();
+    let mut len = 0u64;
+    let mut payload_len = 0u64;
+    let mut frames = Vec::new();
+    let mut block = Vec::with_capacity(size.min(1 << 24));
+    loop {
+        block.clear();
+        if (&mut input).take(size as u64).read_to_end(&mut block)? == 0 {
+            break;
+        }
+        let frame = encode_frame(&block, options, true);
+        if options.seekable {
+            digest.update(&frame[frame.len() - check.size()..]);
+        } else {
+            digest.update(&block);
+        }
+        len += block.len() as u64;
+        payload_len += frame.len() as u64;
+        frames.push((frame.len() as u64, block.len() as u64));
+        out.write_all(&frame)?;
+        monitor.report(Progress { stage: Stage::Encoding, consumed: len, total, produced: written(&out) })?;
+    }
+    let mut trailer = Vec::new();
+    put_checksum(&mut trailer, check, digest.finish());
+    if options.seekable {
+        trailer.extend(seek_table(&seek_entries(&frames, header.len() as u64 + 12, 0), true));
+    }
+    out.write_all(&trailer)?;
+
+    let mut out = out.into_inner().map_err(|e| e.into_error())?;
+    // before patching, which writes over bytes already counted
+    let produced = out.count();
+    out.seek(SeekFrom::Start(header.len() as u64 - 8))?;
+    out.write_all(&len.to_be_bytes())?;
+    out.seek(SeekFrom::Current(4))?;
+    out.write_all(&payload_len.to_be_bytes())?;
+    out.flush()?;
+    monitor.report(Progress { stage: Stage::Done, consumed: len, total, produced })
+}
+
+// Run `write` on a temporary path beside `output`, then move the result to
+// `output`; on failure the temporary file is removed instead
+fn through_temp<F>(output: &Path, write: F) -> io::Result<()>
+    where F: FnOnce(&Path) -> io::Result<()> {
+    let name = output.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp = output.with_file_name(temp_name);
+    match write(&temp) {
+        Ok(()) => std::fs::rename(&temp, output),
+        Err(e) => {
+            let _ = std::fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+// Bytes written to a counted file so far, buffered or not
+fn written<W: Write>(out: &BufWriter<Counter<W>>) -> u64 {
+    out.get_ref().count() + out.buffer().len() as u64
+}
+
+// Bytes taken from a counted file so far, not counting what's buffered
+fn read_so_far<R: Read>(input: &BufReader<Counter<R>>) -> u64 {
+    input.get_ref().count() - input.buffer().len() as u64
+}
+
+// Reads the container header, then decodes the payload a block at a time
+// into `output`. Format errors come back as InvalidData (UnexpectedEof for
+// a truncated file) wrapping a ContainerError; on any error `output` may
+// hold partial data, though in block mode no block is written before its
+// checksum has been checked.
+pub fn decompress_file<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q) -> io::Result<()> {
+    decompress_file_with_progress(input, output, |_| {})
+}
+
+// decompress_file, with `limits` checked as decompress_from_slice_with
+// does, before any output is written
+pub fn decompress_file_with<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q, limits: &DecodeOptions)
+    -> io::Result<()>
+{
+    decompress_file_monitored(input.as_ref(), output.as_ref(), limits, &mut Monitor::new(&mut |_| {}, None))
+}
+
+// decompress_file, calling `progress` after every block written (every
+// FILE_BLOCK bytes without blocks), then once more with Stage::Done.
+// `consumed` and `total` count compressed bytes.
+pub fn decompress_file_with_progress<P, Q, F>(input: P, output: Q, mut progress: F) -> io::Result<()>
+    where P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(Progress)
+{
+    let mut monitor = Monitor::new(&mut progress, None);
+    decompress_file_monitored(input.as_ref(), output.as_ref(), &DecodeOptions::new(), &mut monitor)
+}
+
+// decompress_file_with_progress under `limits`, as decompress_file_with,
+// stopping at the next report after `cancel` is set; as with
+// compress_file_cancellable, `output` is only written once the whole file
+// has decoded and checked out
+pub fn decompress_file_cancellable<P, Q, F>(input: P, output: Q, limits: &DecodeOptions, cancel: &AtomicBool,
+                                            mut progress: F) -> io::Result<()>
+    where P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(Progress)
+{
+    let mut monitor = Monitor::new(&mut progress, Some(cancel));
+    through_temp(output.as_ref(), |temp| decompress_file_monitored(input.as_ref(), temp, limits, &mut monitor))
+}
+
+fn decompress_file_monitored(input: &Path, output: &Path, limits: &DecodeOptions, monitor: &mut Monitor)
+    -> io::Result<()>
+{
+    let file = File::open(input)?;
+    let total = Some(file.metadata()?.len());
+    let mut input = BufReader::new(Counter::new(file));
+    if is_protected(input.fill_buf()?) {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let data = decompress_from_slice_with(&bytes, limits)?;
+        std::fs::write(output, &data)?;
+        let (consumed, produced) = (bytes.len() as u64, data.len() as u64);
+        return monitor.report(Progress { stage: Stage::Done, consumed, total, produced });
+    }
+    let Prelude { format, len, table, payload_len } = read_prelude(&mut input)?;
+    limits.check_output(len).map_err(ContainerError::from)?;
+
+    let mut out = BufWriter::new(Counter::new(File::create(output)?));
+    let mut digest = format.check.digest();
+    let mut payload = (&mut input).take(payload_len);
+    if format.blocked {
+        // each block is decoded whole, so memory is bounded by the block size
+        let mut left = len;
+        while payload.limit() > 0 {
+            let block_len = read_len(&mut payload, format.varints)?;
+            let table_len = read_len(&mut payload, format.varints)?;
+            let table = read_exact_vec(&mut payload, table_len)?;
+            let packed_len = read_len(&mut payload, format.varints)?;
+            let packed = read_exact_vec(&mut payload, packed_len)?;
+            let checksum = read_checksum(&mut payload, format.block_check)?;
+            if block_len == 0 || block_len > left {
+                return Err(ContainerError::CorruptPayload.into());
+            }
+            let block = decode_block(format.codec, &table, &packed, block_len, limits)?;
+            if format.block_check.compute(&block) != checksum {
+                return Err(ContainerError::ChecksumMismatch.into());
+            }
+            left -= block_len;
+            if format.seekable {
+                digest.update(&checksum.to_be_bytes()[8 - format.check.size()..]);
+            } else {
+                digest.update(&block);
+            }
+            out.write_all(&block)?;
+            let consumed = read_so_far(payload.get_ref());
+            monitor.report(Progress { stage: Stage::Decoding, consumed, total, produced: written(&out) })?;
+        }
+        if left > 0 {
+            return Err(ContainerError::Truncated.into());
+        }
+    } else {
+        match format.codec {
+            Codec::Huffman if len > 0 => {
+                let code = ByteHuffman::from_table_with(&table, limits).map_err(ContainerError::from)?;
+                let mut reader = BitReader::new(&mut payload);
+                let mut block = Vec::with_capacity(FILE_BLOCK);
+                let mut left = len;
+                while left > 0 {
+                    block.clear();
+                    for _ in 0..left.min(FILE_BLOCK as u64) {
+                        block.push(code.read_symbol(&mut reader).map_err(|e| match e.kind() {
+                            io::ErrorKind::UnexpectedEof => e,
+                            _ => ContainerError::CorruptPayload.into(),
+                        })?);
+                    }
+                    left -= block.len() as u64;
+                    digest.update(&block);
+                    out.write_all(&block)?;
+                    let consumed = read_so_far(reader.get_ref().get_ref());
+                    monitor.report(Progress { stage: Stage::Decoding, consumed, total, produced: written(&out) })?;
+                }
+            }
+            Codec::Huffman => {}
+            Codec::BwtPipeline | Codec::Lzss | Codec::Ppm => {
+                let packed = read_exact_vec(&mut payload, payload_len)?;
+                let data = decode_block(format.codec, &table, &packed, len, limits)?;
+                digest.update(&data);
+                out.write_all(&data)?;
+                let consumed = read_so_far(payload.get_ref());
+                monitor.report(Progress { stage: Stage::Decoding, consumed, total, produced: written(&out) })?;
+            }
+        }
+        // skip whatever of the payload the decoder didn't need
+        io::copy(&mut payload, &mut io::sink())?;
+    }
+
+    if read_checksum(&mut input, format.check)? != digest.finish() {
+        return Err(ContainerError::ChecksumMismatch.into());
+    }
+    out.flush()?;
+    let consumed = read_so_far(&input);
+    monitor.report(Progress { stage: Stage::Done, consumed, total, produced: out.get_ref().count() })
+}
+
+// A container up to its payload, as read from a stream
+pub(crate) struct Prelude {
+    pub(crate) format: Format,
+    pub(crate) len: u64,
+    pub(crate) table: Vec<u8>,
+    pub(crate) payload_len: u64,
+}
+
+pub(crate) fn read_prelude(input: &mut impl Read) -> io::Result<Prelude> {
+    if read_array::<4>(input)? != MAGIC {
+        return Err(ContainerError::BadMagic.into());
+    }
+    let [version, codec_id] = read_array::<2>(input)?;
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(ContainerError::UnsupportedVersion(version).into());
+    }
+    let check_id = if version >= 2 { Some(read_array::<1>(input)?[0]) } else { None };
+    let format = Format::parse(version, codec_id, check_id)?;
+    let len = u64::from_be_bytes(read_array(input)?);
+    let table_len = u32::from_be_bytes(read_array(input)?) as u64;
+    let table = read_exact_vec(input, table_len)?;
+    let payload_len = u64::from_be_bytes(read_array(input)?);
+    Ok(Prelude { format, len, table, payload_len })
+}
+
+// Byte counts, length and checksum of a file; `progress` gets the bytes
+// read so far after each block
+fn scan_file(path: &Path, check: ChecksumKind, progress: &mut dyn FnMut(u64) -> io::Result<()>)
+    -> io::Result<([u64; 256], u64, u64)>
+{
+    let mut input = File::open(path)?;
+    let mut counts = [0u64; 256];
+    let mut len = 0u64;
+    let mut digest = check.digest();
+    let mut block = vec![0u8; FILE_BLOCK];
+    loop {
+        let n = match input.read(&mut block) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        histogram::add_bytes(&mut counts, &block[..n]);
+        len += n as u64;
+        digest.update(&block[..n]);
+        progress(len)?;
+    }
+    Ok((counts, len, digest.finish()))
+}
+
+// `progress` gets the bytes read and whole bytes encoded so far after each
+// block
+fn encode_file<W: Write>(path: &Path, code: &ByteHuffman, out: W, len: u64, check: ChecksumKind, checksum: u64,
+                         progress: &mut dyn FnMut(u64, u64) -> io::Result<()>) -> io::Result<()>
+{
+    let changed = || io::Error::other("input file changed during compression");
+    let mut input = File::open(path)?;
+    let mut bits = BitWriter::new(out);
+    let mut block = vec![0u8; FILE_BLOCK];
+    let mut seen = 0u64;
+    // with no checksum at all, this at least catches changes to the length
+    // or to a byte the code doesn't cover
+    let mut digest = check.digest();
+    loop {
+        let n = match input.read(&mut block) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        seen += n as u64;
+        if seen > len {
+            return Err(changed());
+        }
+        for b in &block[..n] {
+            code.write_byte(*b, &mut bits).map_err(|_| changed())?;
+        }
+        digest.update(&block[..n]);
+        progress(seen, bits.bits_written() / 8)?;
+    }
+    if seen != len || digest.finish() != checksum {
+        return Err(changed());
+    }
+    bits.align()?;
+    bits.flush()
+}
+
+// Exactly `len` bytes, without trusting `len` for the allocation
+pub(crate) fn read_exact_vec(input: &mut impl Read, len: u64) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    if input.take(len).read_to_end(&mut buf)? as u64 != len {
+        return Err(ContainerError::Truncated.into());
+    }
+    Ok(buf)
+}
+
+pub(crate) fn read_array<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+// A frame length: a varint, or a u32 before version 3
+fn read_len(input: &mut impl Read, varint: bool) -> io::Result<u64> {
+    if !varint {
+        return Ok(u32::from_be_bytes(read_array(input)?) as u64);
+    }
+    let mut buf = Vec::with_capacity(varint::MAX_LEN);
+    loop {
+        let [b] = read_array(input)?;
+        buf.push(b);
+        if b & 0x80 == 0 || buf.len() == varint::MAX_LEN {
+            break;
+        }
+    }
+    Ok(varint::read_u64(&mut &buf[..]).map_err(ContainerError::from)?)
+}
+
+fn read_checksum(input: &mut impl Read, check: ChecksumKind) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    input.read_exact(&mut buf[8 - check.size()..])?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], ContainerError> {
+    if bytes.len() < n {
+        return Err(ContainerError::Truncated);
+    }
+    let (head, tail) = bytes.split_at(n);
+    *bytes = tail;
+    Ok(head)
+}
+
+// `n` bytes, where `n` came from the input and may not fit a usize
+fn take_bounded<'a>(bytes: &mut &'a [u8], n: u64) -> Result<&'a [u8], ContainerError> {
+    if n > bytes.len() as u64 {
+        return Err(ContainerError::Truncated);
+    }
+    take(bytes, n as usize)
+}
+
+fn take_len(bytes: &mut &[u8], varint: bool) -> Result<u64, ContainerError> {
+    if varint {
+        Ok(varint::read_u64(bytes)?)
+    } else {
+        Ok(take_u32(bytes)? as u64)
+    }
+}
+
+fn take_u32(bytes: &mut &[u8]) -> Result<u32, ContainerError> {
+    let b = take(bytes, 4)?;
+    Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn take_u64(bytes: &mut &[u8]) -> Result<u64, ContainerError> {
+    let b = take(bytes, 8)?;
+    let mut arr = [0u8; 8];
+    arr.copy_from_slice(b);
+    Ok(u64::from_be_bytes(arr))
+}
+
+fn take_checksum(bytes: &mut &[u8], check: ChecksumKind) -> Result<u64, ContainerError> {
+    let b = take(bytes, check.size())?;
+    let mut arr = [0u8; 8];
+    arr[8 - b.len()..].copy_from_slice(b);
+    Ok(u64::from_be_bytes(arr))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{compress_to_vec, compress_with, decompress_from_slice, compress_file, compress_file_with,
+                compress_file_with_progress, compress_file_cancellable, decompress_file, decompress_file_with_progress,
+                decompress_file_cancellable, decompress_from_slice_with, decompress_file_with, read_header, Codec,
+                CompressOptions, ContainerError, FORMAT_VERSION, FILE_BLOCK, decode_block, encode_frame, header,
+                put_checksum, frame_checksums, seek_table, seek_entries};
+    use crate::seekable::{self, SeekableDecoder};
+    use crate::huffman::ByteHuffman;
+    use crate::lzss::{self, Token};
+    use crate::limits::{DecodeOptions, Limit, LimitExceeded};
+    use crate::pipeline::Pipeline;
+    use crate::progress::{self, Progress, Stage};
+    use crate::ecc::reed_solomon::RsError;
+    use crate::checksum::ChecksumKind;
+    use std::fs;
+    use std::io;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // Scratch file in the system temp dir, removed on drop
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            TempPath(std::env::temp_dir().join(format!("entrust-{}-{}", std::process::id(), name)))
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let samples: Vec<Vec<u8>> = vec![b"".to_vec(), b"a".to_vec(), b"aaaaaaaa".to_vec(),
+                                         b"dagoth ur was a hotep".repeat(40), (0..=255u8).collect()];
+        for data in samples {
+            let packed = compress_to_vec(&data, Codec::Huffman);
+            assert_eq!(&packed[..4], b"ENTR");
+            assert_eq!(decompress_from_slice(&packed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let data = b"the quick brown fox jumped over the lazy dog".repeat(4);
+        let packed = compress_to_vec(&data, Codec::Huffman);
+
+        assert_eq!(decompress_from_slice(b"ENTX"), Err(ContainerError::BadMagic));
+        assert_eq!(decompress_from_slice(&packed[..packed.len() - 1]), Err(ContainerError::Truncated));
+
+        let mut bumped = packed.clone();
+        bumped[4] = FORMAT_VERSION + 1;
+        assert_eq!(decompress_from_slice(&bumped), Err(ContainerError::UnsupportedVersion(FORMAT_VERSION + 1)));
+
+        let mut unknown = packed.clone();
+        unknown[5] = 0xee;
+        assert_eq!(decompress_from_slice(&unknown), Err(ContainerError::UnknownCodec(0xee)));
+
+        let mut unknown = packed.clone();
+        unknown[6] = 0xee;
+        assert_eq!(decompress_from_slice(&unknown), Err(ContainerError::UnknownChecksum(0xee)));
+
+        // flip a bit in the payload's first byte (just past the table)
+        let table_len = u32::from_be_bytes([packed[15], packed[16], packed[17], packed[18]]) as usize;
+        let mut flipped = packed.clone();
+        flipped[19 + table_len + 8] ^= 0x10;
+        assert!(decompress_from_slice(&flipped).is_err());
+
+        // LZSS tokens for 4MB in a block of 10 bytes stop at the 10th
+        let mut tokens = vec![Token::Literal(0)];
+        tokens.extend(std::iter::repeat_n(Token::Match { offset: 1, length: 1 << 16 }, 64));
+        let tokens = lzss::serialize(&tokens, 3);
+        let code = ByteHuffman::new_bytes(&tokens);
+        let mut payload = (tokens.len() as u64).to_be_bytes().to_vec();
+        payload.extend(code.encode_bytes_packed(&tokens).0);
+        let table = code.serialize_table();
+        assert_eq!(decode_block(Codec::Lzss, &table, &payload, 10, &DecodeOptions::new()),
+                   Err(ContainerError::CorruptPayload));
+
+        // likewise a pipeline block, while its code tables answer to the caller's limits
+        let payload = Pipeline::bwt().compress(&[0; 100_000]);
+        assert_eq!(decode_block(Codec::BwtPipeline, &[], &payload, 10, &DecodeOptions::new()),
+                   Err(ContainerError::CorruptPayload));
+        assert_eq!(decode_block(Codec::BwtPipeline, &[], &payload, 100_000, &DecodeOptions::new().max_table_symbols(0)),
+                   Err(ContainerError::LimitExceeded(LimitExceeded { limit: Limit::TableSymbols, max: 0 })));
+    }
+
+    #[test]
+    fn test_checksums() {
+        let data = b"the quick brown fox jumped over the lazy dog".repeat(40);
+        for &kind in ChecksumKind::ALL.iter() {
+            let options = CompressOptions::new(Codec::Huffman).checksum(kind);
+            let packed = compress_with(&data, &options);
+            assert_eq!(packed[6], kind.id());
+            assert_eq!(decompress_from_slice(&packed).unwrap(), data);
+            if kind.is_none() {
+                continue;
+            }
+            let mut corrupt = packed.clone();
+            *corrupt.last_mut().unwrap() ^= 1;
+            assert_eq!(decompress_from_slice(&corrupt), Err(ContainerError::ChecksumMismatch));
+
+            // a damaged block is caught by its own checksum, here the last one
+            let blocked = compress_with(&data, &options.block_size(500));
+            assert_eq!(decompress_from_slice(&blocked).unwrap(), data);
+            let mut corrupt = blocked.clone();
+            let at = corrupt.len() - kind.size() - 2;
+            corrupt[at] ^= 1;
+            assert_eq!(decompress_from_slice(&corrupt), Err(ContainerError::ChecksumMismatch));
+        }
+
+        // version 1: no check byte, a CRC-32 at the end
+        let mut v1 = compress_to_vec(&data, Codec::Huffman);
+        v1.remove(6);
+        v1[4] = 1;
+        assert_eq!(decompress_from_slice(&v1).unwrap(), data);
+
+        // version 2: fixed-width frame lengths and seek table entries
+        let options = CompressOptions::new(Codec::Huffman).block_size(500).seekable(true);
+        let check = options.get_checksum();
+        let blocks: Vec<&[u8]> = data.chunks(500).collect();
+        let encoded: Vec<Vec<u8>> = blocks.iter().map(|b| encode_frame(b, &options, false)).collect();
+        let frames: Vec<(u64, u64)> = encoded.iter().zip(&blocks).map(|(f, b)| (f.len() as u64, b.len() as u64)).collect();
+        let payload = encoded.concat();
+        let mut v2 = header(&options, data.len() as u64);
+        v2[4] = 2;
+        v2.extend_from_slice(&0u32.to_be_bytes());
+        v2.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+        let start = v2.len() as u64;
+        v2.extend_from_slice(&payload);
+        put_checksum(&mut v2, check, check.compute(&frame_checksums(&payload, &frames, check)));
+        v2.extend(seek_table(&seek_entries(&frames, start, 0), false));
+        assert_eq!(decompress_from_slice(&v2).unwrap(), data);
+        // appending keeps to the container's version
+        let mut file = io::Cursor::new(v2);
+        seekable::append(&mut file, b"more", &options).unwrap();
+        assert_eq!(file.get_ref()[4], 2);
+        let mut reader = SeekableDecoder::new(&mut file).unwrap();
+        assert_eq!(reader.read_range(1750..1764).unwrap(), b"e lazy dogmore");
+    }
+
+    #[test]
+    fn test_files() {
+        let (plain, packed, unpacked) = (TempPath::new("plain"), TempPath::new("packed"), TempPath::new("unpacked"));
+        let mut noise = Vec::new();
+        let mut x = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..3 * FILE_BLOCK + 17 {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            noise.push((x % 7) as u8 * (x % 5) as u8);
+        }
+        let samples: Vec<Vec<u8>> = vec![b"".to_vec(), b"aaaaaaaa".to_vec(), noise];
+        for data in samples {
+            fs::write(&plain.0, &data).unwrap();
+            compress_file(&plain.0, &packed.0, Codec::Huffman).unwrap();
+            // same bytes as the in-memory version
+            let packed_bytes = fs::read(&packed.0).unwrap();
+            assert_eq!(packed_bytes, compress_to_vec(&data, Codec::Huffman));
+            decompress_file(&packed.0, &unpacked.0).unwrap();
+            assert_eq!(fs::read(&unpacked.0).unwrap(), data);
+        }
+
+        let packed_bytes = fs::read(&packed.0).unwrap();
+        fs::write(&packed.0, &packed_bytes[..packed_bytes.len() - 5]).unwrap();
+        let err = decompress_file(&packed.0, &unpacked.0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut corrupt = packed_bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        fs::write(&packed.0, &corrupt).unwrap();
+        let err = decompress_file(&packed.0, &unpacked.0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let inner = err.get_ref().unwrap().downcast_ref::<ContainerError>();
+        assert_eq!(inner, Some(&ContainerError::ChecksumMismatch));
+
+        assert!(compress_file("/nonexistent/entrust/input", &packed.0, Codec::Huffman).is_err());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap() {
+        let (plain, mapped, read) = (TempPath::new("mmap-plain"), TempPath::new("mmap-out"), TempPath::new("mmap-read"));
+        let mut data = b"the rain in spain stays mainly in the plain; ".repeat(3000);
+        data.extend((0..FILE_BLOCK as u32).map(|i| (i * i % 13) as u8));
+        let options = [CompressOptions::new(Codec::Huffman), CompressOptions::new(Codec::Huffman).block_size(5000),
+                       CompressOptions::new(Codec::Lzss).checksum(ChecksumKind::None),
+                       CompressOptions { block_size: None, ..CompressOptions::new(Codec::Ppm) },
+                       CompressOptions::new(Codec::Huffman).block_size(5000).seekable(true),
+                       CompressOptions::new(Codec::BwtPipeline).ecc(16)];
+        // SAFETY: the files are this test's own, and nothing else touches them
+        for data in [&b""[..], b"x", &data] {
+            fs::write(&plain.0, data).unwrap();
+            for options in options.iter() {
+                unsafe { super::compress_mmap(&plain.0, &mapped.0, options).unwrap() };
+                compress_file_with(&plain.0, &read.0, options).unwrap();
+                assert!(fs::read(&mapped.0).unwrap() == fs::read(&read.0).unwrap());
+                assert!(decompress_from_slice(&fs::read(&mapped.0).unwrap()).unwrap() == data);
+            }
+        }
+        assert!(unsafe { super::compress_mmap("/nonexistent/entrust/input", &mapped.0, &options[0]) }.is_err());
+    }
+
+    #[test]
+    fn test_progress() {
+        let (plain, packed, unpacked) = (TempPath::new("pr-plain"), TempPath::new("pr-packed"),
+                                         TempPath::new("pr-unpacked"));
+        let data: Vec<u8> = (0..5 * FILE_BLOCK as u32 / 2).map(|i| (i % 251 % 19) as u8).collect();
+        fs::write(&plain.0, &data).unwrap();
+        let len = data.len() as u64;
+        for options in [CompressOptions::new(Codec::Huffman), CompressOptions::new(Codec::Huffman).block_size(50000)] {
+            let mut seen = Vec::new();
+            compress_file_with_progress(&plain.0, &packed.0, &options, |p| seen.push(p)).unwrap();
+            let size = fs::metadata(&packed.0).unwrap().len();
+            // stages in order, counts never going back within one
+            assert!(seen.windows(2).all(|w| w[0].stage < w[1].stage
+                || (w[0].stage == w[1].stage && w[0].consumed <= w[1].consumed && w[0].produced <= w[1].produced)));
+            assert!(seen.iter().all(|p| p.total == Some(len) && p.produced <= size));
+            let scans = seen.iter().filter(|p| p.stage == Stage::Scanning).count();
+            assert_eq!(scans, if options.get_block_size().is_some() { 0 } else { 3 });
+            assert_eq!(seen.iter().rfind(|p| p.stage == Stage::Encoding).unwrap().consumed, len);
+            let done = Progress { stage: Stage::Done, consumed: len, total: Some(len), produced: size };
+            assert_eq!(seen.last(), Some(&done));
+
+            let mut seen = Vec::new();
+            decompress_file_with_progress(&packed.0, &unpacked.0, |p| seen.push(p)).unwrap();
+            assert_eq!(fs::read(&unpacked.0).unwrap(), data);
+            assert!(seen.len() > 2 && seen[..seen.len() - 1].iter().all(|p| p.stage == Stage::Decoding));
+            let done = Progress { stage: Stage::Done, consumed: size, total: Some(size), produced: len };
+            assert_eq!(seen.last(), Some(&done));
+        }
+    }
+
+    #[test]
+    fn test_cancel() {
+        let (plain, packed, unpacked) = (TempPath::new("cancel-plain"), TempPath::new("cancel-packed"),
+                                         TempPath::new("cancel-unpacked"));
+        let data = b"abcdefgh".repeat(FILE_BLOCK);
+        fs::write(&plain.0, &data).unwrap();
+        let cancel = AtomicBool::new(false);
+        let temp_files = || fs::read_dir(std::env::temp_dir()).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().contains("cancel-packed.")).count();
+
+        for options in [CompressOptions::new(Codec::Huffman), CompressOptions::new(Codec::Huffman).block_size(50000)] {
+            fs::write(&packed.0, b"old").unwrap();
+            let mut reports = 0;
+            let err = compress_file_cancellable(&plain.0, &packed.0, &options, &cancel, |_| {
+                reports += 1;
+                cancel.store(reports == 3, Ordering::Relaxed);
+            }).unwrap_err();
+            assert!(progress::is_cancelled(&err));
+            assert!(matches!(crate::Error::from(err), crate::Error::Cancelled));
+            assert_eq!(reports, 3);
+            // the old output is still there, and nothing is left beside it
+            assert_eq!(fs::read(&packed.0).unwrap(), b"old");
+            assert_eq!(temp_files(), 0);
+
+            cancel.store(false, Ordering::Relaxed);
+            compress_file_cancellable(&plain.0, &packed.0, &options, &cancel, |_| {}).unwrap();
+            assert_eq!(fs::read(&packed.0).unwrap(), compress_with(&data, &options));
+            assert_eq!(temp_files(), 0);
+        }
+
+        let limits = DecodeOptions::new();
+        let err = decompress_file_cancellable(&packed.0, &unpacked.0, &limits, &cancel,
+                                              |_| cancel.store(true, Ordering::Relaxed)).unwrap_err();
+        assert!(progress::is_cancelled(&err));
+        assert!(!unpacked.0.exists());
+        cancel.store(false, Ordering::Relaxed);
+        let small = limits.max_output_bytes(data.len() as u64 - 1);
+        let err = decompress_file_cancellable(&packed.0, &unpacked.0, &small, &cancel, |_| {}).unwrap_err();
+        assert!(matches!(crate::Error::from(err), crate::Error::LimitExceeded(_)));
+        assert!(!unpacked.0.exists());
+        decompress_file_cancellable(&packed.0, &unpacked.0, &limits, &cancel, |_| {}).unwrap();
+        assert!(fs::read(&unpacked.0).unwrap() == data);
+    }
+
+    #[test]
+    fn test_limits() {
+        let data = b"the rain in spain".repeat(200);
+        let over = |limit, max| ContainerError::LimitExceeded(LimitExceeded { limit, max });
+        for options in [CompressOptions::new(Codec::Huffman), CompressOptions::new(Codec::Lzss).block_size(1000)] {
+            let packed = compress_with(&data, &options);
+            let limits = DecodeOptions::new().max_output_bytes(data.len() as u64);
+            assert!(decompress_from_slice_with(&packed, &limits).unwrap() == data);
+            assert_eq!(decompress_from_slice_with(&packed, &limits.max_output_bytes(3399)),
+                       Err(over(Limit::OutputBytes, 3399)));
+            // "the rain spi" has 10 distinct bytes
+            assert_eq!(decompress_from_slice_with(&packed, &limits.max_table_symbols(9)),
+                       Err(over(Limit::TableSymbols, 9)));
+            assert_eq!(decompress_from_slice_with(&packed, &limits.max_depth(1)), Err(over(Limit::Depth, 1)));
+        }
+
+        // a header claiming a huge length is turned down before any decoding
+        let mut packed = compress_to_vec(&data, Codec::Huffman);
+        packed[7..15].copy_from_slice(&u64::MAX.to_be_bytes());
+        let limits = DecodeOptions::new().max_output_bytes(1 << 30);
+        assert_eq!(decompress_from_slice_with(&packed, &limits), Err(over(Limit::OutputBytes, 1 << 30)));
+
+        let (packed, unpacked) = (TempPath::new("limits-packed"), TempPath::new("limits-unpacked"));
+        fs::write(&packed.0, compress_to_vec(&data, Codec::Huffman)).unwrap();
+        let err = decompress_file_with(&packed.0, &unpacked.0, &limits.max_output_bytes(100)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(crate::Error::from(err), crate::Error::LimitExceeded(_)));
+        decompress_file_with(&packed.0, &unpacked.0, &limits).unwrap();
+        assert!(fs::read(&unpacked.0).unwrap() == data);
+    }
+
+    #[test]
+    fn test_blocks() {
+        // a distribution that changes halfway through
+        let mut data = b"GET /index.html 200 ".repeat(400);
+        data.extend((0..8000u32).map(|i| (i * 7 % 11) as u8));
+        let whole = compress_to_vec(&data, Codec::Huffman);
+        let options = CompressOptions::new(Codec::Huffman).block_size(4000);
+        let blocked = compress_with(&data, &options);
+        assert_eq!(blocked[5], Codec::Huffman.id() | super::BLOCKED);
+        assert!(blocked.len() < whole.len());
+        assert_eq!(decompress_from_slice(&blocked).unwrap(), data);
+
+        for data in [&b""[..], b"a", b"abcabcab"].iter() {
+            let blocked = compress_with(data, &CompressOptions::default().block_size(3));
+            assert_eq!(decompress_from_slice(&blocked).unwrap(), *data);
+        }
+
+        // the file decoder reads block mode too
+        let (packed, unpacked) = (TempPath::new("blocks"), TempPath::new("blocks-out"));
+        fs::write(&packed.0, &blocked).unwrap();
+        decompress_file(&packed.0, &unpacked.0).unwrap();
+        assert_eq!(fs::read(&unpacked.0).unwrap(), data);
+
+        // a block claiming more bytes than the container holds
+        let mut lying = blocked.clone();
+        lying[28] = 0xff;
+        assert_eq!(decompress_from_slice(&lying), Err(ContainerError::CorruptPayload));
+    }
+
+    #[test]
+    fn test_file_blocks() {
+        let (plain, packed, unpacked) = (TempPath::new("fb-plain"), TempPath::new("fb-packed"),
+                                         TempPath::new("fb-unpacked"));
+        let mut data = b"abcd".repeat(FILE_BLOCK / 2);
+        data.extend(b"wxyz".repeat(FILE_BLOCK / 3));
+        fs::write(&plain.0, &data).unwrap();
+        let options = CompressOptions::new(Codec::Huffman).block_size(FILE_BLOCK).checksum(ChecksumKind::XxHash64);
+        for options in [options, options.seekable(true)].iter() {
+            compress_file_with(&plain.0, &packed.0, options).unwrap();
+            assert_eq!(fs::read(&packed.0).unwrap(), compress_with(&data, options));
+            decompress_file(&packed.0, &unpacked.0).unwrap();
+            assert_eq!(fs::read(&unpacked.0).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_bwt_pipeline() {
+        let mut text = b"It is a truth universally acknowledged, that a single man in possession \
+                         of a good fortune, must be in want of a wife. ".repeat(300);
+        text.extend((0..5000u32).map(|i| (i * 7 % 251) as u8));
+        let options = CompressOptions::new(Codec::BwtPipeline);
+        assert_eq!(options.get_block_size(), Some(super::BWT_BLOCK_SIZE));
+        let packed = compress_to_vec(&text, Codec::BwtPipeline);
+        assert_eq!(packed[5], Codec::BwtPipeline.id() | super::BLOCKED);
+        assert_eq!(decompress_from_slice(&packed).unwrap(), text);
+        assert!(packed.len() < compress_to_vec(&text, Codec::Huffman).len() / 4);
+
+        let (plain, file, unpacked) = (TempPath::new("bwt-plain"), TempPath::new("bwt-packed"),
+                                       TempPath::new("bwt-unpacked"));
+        fs::write(&plain.0, &text).unwrap();
+        let small = options.block_size(4096);
+        let whole = CompressOptions { block_size: None, ..options };
+        for options in [small, whole].iter() {
+            compress_file_with(&plain.0, &file.0, options).unwrap();
+            assert_eq!(fs::read(&file.0).unwrap(), compress_with(&text, options));
+            decompress_file(&file.0, &unpacked.0).unwrap();
+            assert_eq!(fs::read(&unpacked.0).unwrap(), text);
+        }
+        assert_eq!(decompress_from_slice(&compress_with(b"", &whole)).unwrap(), b"");
+
+        let mut corrupt = compress_with(&text, &whole);
+        let at = corrupt.len() - 10;
+        corrupt[at] ^= 0x40;
+        assert!(decompress_from_slice(&corrupt).is_err());
+
+        // without runs to take out, the run-length stage only adds escapes
+        let mut x = 0x9e37_79b9u32;
+        for &len in [1, 100, 257, 1000, 20_000].iter() {
+            let noise: Vec<u8> = (0..len).map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            }).collect();
+            let packed = compress_to_vec(&noise, Codec::BwtPipeline);
+            assert_eq!(decompress_from_slice(&packed).unwrap(), noise, "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn test_levels() {
+        let mut data = b"GET /index.html HTTP/1.1 200 1043\nGET /style.css HTTP/1.1 304 0\n".repeat(200);
+        data.extend((0..3000u32).map(|i| (i * i % 251) as u8));
+        let huffman = compress_to_vec(&data, Codec::Huffman).len();
+        for &codec in Codec::ALL.iter() {
+            for level in 1..=9 {
+                let options = CompressOptions::new(codec).level(level);
+                assert_eq!((options.get_level(), options.get_block_size()), (level, codec.block_size(level)));
+                let packed = compress_with(&data, &options);
+                assert_eq!(decompress_from_slice(&packed).unwrap(), data);
+                if codec == Codec::Lzss || codec == Codec::Ppm {
+                    assert!(packed.len() < huffman / 4);
+                }
+            }
+        }
+        assert_eq!(CompressOptions::new(Codec::Lzss).get_level(), super::DEFAULT_LEVEL);
+        assert_eq!(CompressOptions::new(Codec::BwtPipeline).level(1).get_block_size(), Some(100_000));
+        assert_eq!(CompressOptions::new(Codec::Huffman).block_size(10).level(5).get_block_size(), None);
+
+        let (plain, file, unpacked) = (TempPath::new("lv-plain"), TempPath::new("lv-packed"),
+                                       TempPath::new("lv-unpacked"));
+        fs::write(&plain.0, &data).unwrap();
+        let blocked = CompressOptions::new(Codec::Lzss).level(9).block_size(5000);
+        let whole = CompressOptions { block_size: None, ..blocked };
+        for options in [blocked, whole].iter() {
+            compress_file_with(&plain.0, &file.0, options).unwrap();
+            assert_eq!(fs::read(&file.0).unwrap(), compress_with(&data, options));
+            decompress_file(&file.0, &unpacked.0).unwrap();
+            assert_eq!(fs::read(&unpacked.0).unwrap(), data);
+        }
+        assert!(decompress_from_slice(&compress_with(&data, &whole)[..60]).is_err());
+    }
+
+    #[test]
+    fn test_ecc() {
+        let data = b"Reed and Solomon, 1960: Polynomial Codes over Certain Finite Fields. ".repeat(300);
+        let options = CompressOptions::new(Codec::Lzss).block_size(4000).ecc(32);
+        let packed = compress_with(&data, &options);
+        let plain = compress_with(&data, &options.ecc(0));
+        // magic and header codeword, then 32 parity bytes per 223
+        assert_eq!(packed.len(), 4 + 17 + plain.len() + plain.len().div_ceil(223) * 32);
+        assert_eq!(read_header(&packed).unwrap().ecc, 32);
+        assert_eq!(read_header(&plain).unwrap().ecc, 0);
+
+        // a damaged magic byte, then 16 bad bytes in every codeword
+        let mut damaged = packed.clone();
+        damaged[1] = b'X';
+        for start in (40..damaged.len()).step_by(255) {
+            for b in &mut damaged[start..(start + 16).min(packed.len())] {
+                *b ^= 0xa5;
+            }
+        }
+        assert_eq!(decompress_from_slice(&damaged).unwrap(), data);
+        damaged[50..70].iter_mut().for_each(|b| *b = !*b);
+        assert_eq!(decompress_from_slice(&damaged), Err(ContainerError::Ecc(RsError::TooManyErrors)));
+
+        let (file, unpacked) = (TempPath::new("ecc-packed"), TempPath::new("ecc-unpacked"));
+        fs::write(&file.0, &packed).unwrap();
+        decompress_file(&file.0, &unpacked.0).unwrap();
+        assert_eq!(fs::read(&unpacked.0).unwrap(), data);
+    }
+
+}
@@ -0,0 +1,614 @@
+// DEFLATE (RFC 1951): LZ77 matches and literals, entropy coded with
+// canonical Huffman codes. The encoder finds matches with the lz77 module,
+// cuts the result into blocks and sends each one stored, with the fixed
+// codes or with its own length-limited dynamic codes, whichever is
+// smallest. Output is a raw DEFLATE stream, without a zlib or gzip wrapper.
+//
+// Bits are packed LSB-first, with Huffman codewords sent starting from
+// their most significant bit (RFC 1951, 3.1.1).
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::bitio::{BitOrder, BitReader, BitWriter};
+use crate::canonical::canonical_codewords;
+use crate::huffman::HuffmanCode;
+use crate::limits::{DecodeOptions, LimitExceeded};
+use crate::lz77::Lz77;
+
+const END_OF_BLOCK: usize = 256;
+// Literal/length codes 286 and 287 and distance codes 30 and 31 never occur
+const LITLEN_CODES: usize = 286;
+const DIST_CODES: usize = 30;
+const MAX_CODE_LEN: u8 = 15;
+const MAX_CL_CODE_LEN: u8 = 7;
+// Tokens per block; each block gets codes fitted to its own statistics
+const BLOCK_TOKENS: usize = 1 << 14;
+const MAX_STORED: usize = u16::MAX as usize;
+
+// Base lengths and extra bits for length codes 257..=285
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+                                35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+                                3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                              257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+                              8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+                              7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// Order the code length code lengths are sent in
+const CL_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeflateError {
+    // The stream ends before its final block does
+    Truncated,
+    // Block type 3 is reserved
+    InvalidBlockType,
+    // A stored block's length and its complement disagree
+    StoredLengthMismatch,
+    // A dynamic block's code lengths don't describe a usable code
+    InvalidCodeLengths,
+    // A bit sequence that is no codeword, or a reserved length or distance code
+    InvalidSymbol,
+    // A match reaches back before the start of the output
+    DistanceTooFar,
+    // More output than DecodeOptions::max_output_bytes
+    LimitExceeded(LimitExceeded),
+}
+
+impl fmt::Display for DeflateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeflateError::Truncated => write!(f, "DEFLATE stream is truncated"),
+            DeflateError::InvalidBlockType => write!(f, "reserved block type"),
+            DeflateError::StoredLengthMismatch => write!(f, "stored block length check failed"),
+            DeflateError::InvalidCodeLengths => write!(f, "invalid Huffman code lengths"),
+            DeflateError::InvalidSymbol => write!(f, "invalid Huffman code or symbol"),
+            DeflateError::DistanceTooFar => write!(f, "match distance reaches before start of output"),
+            DeflateError::LimitExceeded(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for DeflateError {}
+
+impl From<LimitExceeded> for DeflateError {
+    fn from(e: LimitExceeded) -> Self {
+        DeflateError::LimitExceeded(e)
+    }
+}
+
+impl From<io::Error> for DeflateError {
+    // The only way reading from a slice fails
+    fn from(_: io::Error) -> Self {
+        DeflateError::Truncated
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut tokens = Vec::new();
+    for triple in Lz77::default().compress(data) {
+        if triple.length > 0 {
+            tokens.push(Token::Match { length: triple.length, distance: triple.offset });
+        }
+        tokens.push(Token::Literal(triple.literal));
+    }
+
+    let mut w = BitWriter::with_order(Vec::new(), BitOrder::LsbFirst);
+    let write = |w: &mut BitWriter<Vec<u8>>| -> io::Result<()> {
+        if tokens.is_empty() {
+            return write_block(w, &[], &[], true);
+        }
+        let mut start = 0;
+        let blocks: Vec<&[Token]> = tokens.chunks(BLOCK_TOKENS).collect();
+        for (i, block) in blocks.iter().enumerate() {
+            let len: usize = block.iter().map(token_len).sum();
+            write_block(w, block, &data[start..start + len], i + 1 == blocks.len())?;
+            start += len;
+        }
+        Ok(())
+    };
+    write(&mut w).expect("writing to a Vec cannot fail");
+    w.into_inner().expect("writing to a Vec cannot fail")
+}
+
+fn token_len(token: &Token) -> usize {
+    match *token {
+        Token::Literal(_) => 1,
+        Token::Match { length, .. } => length as usize,
+    }
+}
+
+// Symbol, extra bits and their count for a match length or distance
+fn length_code(length: u16) -> (usize, u16, u8) {
+    let i = LENGTH_BASE.iter().rposition(|&b| b <= length).unwrap();
+    (257 + i, length - LENGTH_BASE[i], LENGTH_EXTRA[i])
+}
+
+fn dist_code(distance: u16) -> (usize, u16, u8) {
+    let i = DIST_BASE.iter().rposition(|&b| b <= distance).unwrap();
+    (i, distance - DIST_BASE[i], DIST_EXTRA[i])
+}
+
+fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let mut litlen = vec![8u8; 288];
+    litlen[144..256].iter_mut().for_each(|l| *l = 9);
+    litlen[256..280].iter_mut().for_each(|l| *l = 7);
+    (litlen, vec![5u8; 30])
+}
+
+// Length-limited Huffman code lengths for an alphabet of `freqs.len()`
+// symbols, zero for unused ones. Like zlib, make sure at least two
+// symbols get a codeword, since some decoders reject a lone one-bit code.
+fn huffman_lengths(freqs: &[u64], max_len: u8) -> Vec<u8> {
+    let mut used: HashMap<u16, u64> = freqs.iter().enumerate()
+        .filter(|(_, &f)| f > 0)
+        .map(|(sym, &f)| (sym as u16, f))
+        .collect();
+    for sym in 0..freqs.len() {
+        if used.len() >= 2 {
+            break;
+        }
+        used.entry(sym as u16).or_insert(1);
+    }
+    let code = HuffmanCode::with_max_length(&used, max_len).expect("alphabet fits the length limit");
+    let mut lengths = vec![0u8; freqs.len()];
+    for (sym, codeword) in code.codewords() {
+        lengths[*sym as usize] = codeword.len() as u8;
+    }
+    lengths
+}
+
+// Codeword of each symbol as (bits in transmission order, length)
+fn codes_from_lengths(lengths: &[u8]) -> Vec<(u64, u8)> {
+    let pairs: Vec<(usize, u8)> = lengths.iter().cloned().enumerate().collect();
+    let mut codes = vec![(0, 0); lengths.len()];
+    for (sym, codeword) in canonical_codewords(&pairs).expect("code lengths satisfy the Kraft inequality") {
+        // first bit sent goes in the least significant position
+        let bits = codeword.iter().enumerate().fold(0, |acc, (i, x)| acc | (x as u64) << i);
+        codes[sym] = (bits, codeword.len() as u8);
+    }
+    codes
+}
+
+// Run-length code the concatenated code lengths with symbols 16 (repeat
+// the previous length 3-6 times), 17 (3-10 zeros) and 18 (11-138 zeros)
+fn rle_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let len = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|&&l| l == len).count();
+        i += run;
+        if len == 0 {
+            while run >= 11 {
+                let n = run.min(138);
+                out.push((18, (n - 11) as u8));
+                run -= n;
+            }
+            if run >= 3 {
+                out.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            out.push((len, 0));
+            run -= 1;
+            while run >= 3 {
+                let n = run.min(6);
+                out.push((16, (n - 3) as u8));
+                run -= n;
+            }
+        }
+        out.extend(std::iter::repeat_n((len, 0), run));
+    }
+    out
+}
+
+fn cl_extra_bits(sym: u8) -> u8 {
+    match sym {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+// Everything a dynamic block header sends
+struct DynamicHeader {
+    litlen: Vec<u8>,
+    dist: Vec<u8>,
+    cl_lengths: Vec<u8>,
+    rle: Vec<(u8, u8)>,
+    // Number of code length code lengths sent
+    hclen: usize,
+}
+
+impl DynamicHeader {
+
+    fn new(litlen_freqs: &[u64], dist_freqs: &[u64]) -> Self {
+        let mut litlen = huffman_lengths(litlen_freqs, MAX_CODE_LEN);
+        let mut dist = huffman_lengths(dist_freqs, MAX_CODE_LEN);
+        let nlit = litlen.iter().rposition(|&l| l > 0).map_or(0, |i| i + 1).max(257);
+        let ndist = dist.iter().rposition(|&l| l > 0).map_or(0, |i| i + 1).max(1);
+        litlen.truncate(nlit);
+        dist.truncate(ndist);
+
+        let rle = rle_lengths(&[&litlen[..], &dist[..]].concat());
+        let mut cl_freqs = [0u64; 19];
+        for &(sym, _) in &rle {
+            cl_freqs[sym as usize] += 1;
+        }
+        let cl_lengths = huffman_lengths(&cl_freqs, MAX_CL_CODE_LEN);
+        let hclen = CL_ORDER.iter().rposition(|&s| cl_lengths[s] > 0).map_or(0, |i| i + 1).max(4);
+        DynamicHeader { litlen, dist, cl_lengths, rle, hclen }
+    }
+
+    fn cost(&self) -> u64 {
+        let rle: u64 = self.rle.iter()
+            .map(|&(sym, _)| (self.cl_lengths[sym as usize] + cl_extra_bits(sym)) as u64)
+            .sum();
+        14 + 3 * self.hclen as u64 + rle
+    }
+
+    fn write<W: io::Write>(&self, w: &mut BitWriter<W>) -> io::Result<()> {
+        w.write_bits((self.litlen.len() - 257) as u64, 5)?;
+        w.write_bits((self.dist.len() - 1) as u64, 5)?;
+        w.write_bits((self.hclen - 4) as u64, 4)?;
+        for &sym in &CL_ORDER[..self.hclen] {
+            w.write_bits(self.cl_lengths[sym] as u64, 3)?;
+        }
+        let cl_codes = codes_from_lengths(&self.cl_lengths);
+        for &(sym, extra) in &self.rle {
+            let (bits, len) = cl_codes[sym as usize];
+            w.write_bits(bits, len as u32)?;
+            w.write_bits(extra as u64, cl_extra_bits(sym) as u32)?;
+        }
+        Ok(())
+    }
+
+}
+
+// Bits the tokens take under the given code lengths
+fn data_cost(tokens: &[Token], litlen: &[u8], dist: &[u8]) -> u64 {
+    let mut bits = litlen[END_OF_BLOCK] as u64;
+    for token in tokens {
+        bits += match *token {
+            Token::Literal(b) => litlen[b as usize] as u64,
+            Token::Match { length, distance } => {
+                let (lsym, _, lextra) = length_code(length);
+                let (dsym, _, dextra) = dist_code(distance);
+                (litlen[lsym] + lextra + dist[dsym] + dextra) as u64
+            }
+        };
+    }
+    bits
+}
+
+// `raw` is the input the tokens stand for, sent as is if that's smallest
+fn write_block<W: io::Write>(w: &mut BitWriter<W>, tokens: &[Token], raw: &[u8], last: bool)
+    -> io::Result<()> {
+    let mut litlen_freqs = [0u64; LITLEN_CODES];
+    let mut dist_freqs = [0u64; DIST_CODES];
+    litlen_freqs[END_OF_BLOCK] = 1;
+    for token in tokens {
+        match *token {
+            Token::Literal(b) => litlen_freqs[b as usize] += 1,
+            Token::Match { length, distance } => {
+                litlen_freqs[length_code(length).0] += 1;
+                dist_freqs[dist_code(distance).0] += 1;
+            }
+        }
+    }
+
+    let dynamic = DynamicHeader::new(&litlen_freqs, &dist_freqs);
+    let (fixed_litlen, fixed_dist) = fixed_lengths();
+    let dynamic_cost = dynamic.cost() + data_cost(tokens, &dynamic.litlen, &dynamic.dist);
+    let fixed_cost = data_cost(tokens, &fixed_litlen, &fixed_dist);
+    // worst-case alignment, then LEN and NLEN for each chunk
+    let stored_cost = (raw.len().div_ceil(MAX_STORED).max(1) * (7 + 32) + 8 * raw.len()) as u64;
+
+    if stored_cost < fixed_cost.min(dynamic_cost) {
+        let chunks: Vec<&[u8]> = raw.chunks(MAX_STORED).collect();
+        for (i, chunk) in chunks.iter().enumerate() {
+            w.write_bits((last && i + 1 == chunks.len()) as u64, 1)?;
+            w.write_bits(0, 2)?;
+            w.align()?;
+            w.write_bits(chunk.len() as u64, 16)?;
+            w.write_bits(!(chunk.len() as u16) as u64, 16)?;
+            for &b in chunk.iter() {
+                w.write_bits(b as u64, 8)?;
+            }
+        }
+        return Ok(());
+    }
+
+    w.write_bits(last as u64, 1)?;
+    let (litlen, dist) = if fixed_cost <= dynamic_cost {
+        w.write_bits(1, 2)?;
+        (fixed_litlen, fixed_dist)
+    } else {
+        w.write_bits(2, 2)?;
+        dynamic.write(w)?;
+        (dynamic.litlen, dynamic.dist)
+    };
+    write_tokens(w, tokens, &codes_from_lengths(&litlen), &codes_from_lengths(&dist))
+}
+
+fn write_tokens<W: io::Write>(w: &mut BitWriter<W>, tokens: &[Token],
+                              litlen: &[(u64, u8)], dist: &[(u64, u8)]) -> io::Result<()> {
+    let mut put = |(bits, len): (u64, u8)| w.write_bits(bits, len as u32);
+    for token in tokens {
+        match *token {
+            Token::Literal(b) => put(litlen[b as usize])?,
+            Token::Match { length, distance } => {
+                let (lsym, lbits, lextra) = length_code(length);
+                put(litlen[lsym])?;
+                put((lbits as u64, lextra))?;
+                let (dsym, dbits, dextra) = dist_code(distance);
+                put(dist[dsym])?;
+                put((dbits as u64, dextra))?;
+            }
+        }
+    }
+    put(litlen[END_OF_BLOCK])
+}
+
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, DeflateError> {
+    Ok(inflate_prefix(data)?.0)
+}
+
+// inflate, stopping with LimitExceeded before the output passes
+// max_output_bytes. A DEFLATE stream can expand over a thousandfold, so
+// this is the one to use on untrusted input.
+pub fn inflate_with(data: &[u8], limits: &DecodeOptions) -> Result<Vec<u8>, DeflateError> {
+    Ok(inflate_prefix_with(data, limits)?.0)
+}
+
+// Inflate the DEFLATE stream at the start of `data`, returning the output
+// and the number of bytes the stream took (its last byte included, padding
+// and all); whatever follows, such as a gzip or zlib trailer, is left alone.
+pub fn inflate_prefix(data: &[u8]) -> Result<(Vec<u8>, usize), DeflateError> {
+    inflate_prefix_with(data, &DecodeOptions::new())
+}
+
+pub fn inflate_prefix_with(data: &[u8], limits: &DecodeOptions) -> Result<(Vec<u8>, usize), DeflateError> {
+    let mut r = BitReader::with_order(data, BitOrder::LsbFirst);
+    let mut out = Vec::new();
+    loop {
+        let last = r.read_bit()?;
+        match r.read_bits(2)? {
+            0 => inflate_stored(&mut r, &mut out, limits)?,
+            1 => {
+                let (litlen, dist) = fixed_lengths();
+                inflate_codes(&mut r, &mut out, &Decoder::new(&litlen)?, &Decoder::new(&dist)?, limits)?;
+            }
+            2 => {
+                let (litlen, dist) = read_dynamic_header(&mut r)?;
+                inflate_codes(&mut r, &mut out, &litlen, &dist, limits)?;
+            }
+            _ => return Err(DeflateError::InvalidBlockType),
+        }
+        if last {
+            return Ok((out, (r.bits_read() as usize).div_ceil(8)));
+        }
+    }
+}
+
+fn inflate_stored(r: &mut BitReader<&[u8]>, out: &mut Vec<u8>, limits: &DecodeOptions)
+    -> Result<(), DeflateError> {
+    r.align();
+    let len = r.read_bits(16)? as u16;
+    let nlen = r.read_bits(16)? as u16;
+    if len != !nlen {
+        return Err(DeflateError::StoredLengthMismatch);
+    }
+    limits.check_output(out.len() as u64 + len as u64)?;
+    for _ in 0..len {
+        out.push(r.read_bits(8)? as u8);
+    }
+    Ok(())
+}
+
+fn read_dynamic_header(r: &mut BitReader<&[u8]>) -> Result<(Decoder, Decoder), DeflateError> {
+    let nlit = r.read_bits(5)? as usize + 257;
+    let ndist = r.read_bits(5)? as usize + 1;
+    let hclen = r.read_bits(4)? as usize + 4;
+    if nlit > LITLEN_CODES || ndist > DIST_CODES {
+        return Err(DeflateError::InvalidCodeLengths);
+    }
+    let mut cl_lengths = [0u8; 19];
+    for &sym in &CL_ORDER[..hclen] {
+        cl_lengths[sym] = r.read_bits(3)? as u8;
+    }
+    let cl = Decoder::new(&cl_lengths)?;
+
+    let mut lengths = Vec::with_capacity(nlit + ndist);
+    while lengths.len() < nlit + ndist {
+        let (len, repeat) = match cl.decode(r)? {
+            sym @ 0..=15 => (sym as u8, 1),
+            16 => (*lengths.last().ok_or(DeflateError::InvalidCodeLengths)?, 3 + r.read_bits(2)?),
+            17 => (0, 3 + r.read_bits(3)?),
+            _ => (0, 11 + r.read_bits(7)?),
+        };
+        if lengths.len() + repeat as usize > nlit + ndist {
+            return Err(DeflateError::InvalidCodeLengths);
+        }
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths[END_OF_BLOCK] == 0 {
+        return Err(DeflateError::InvalidCodeLengths);
+    }
+    Ok((Decoder::new(&lengths[..nlit])?, Decoder::new(&lengths[nlit..])?))
+}
+
+fn inflate_codes(r: &mut BitReader<&[u8]>, out: &mut Vec<u8>, litlen: &Decoder, dist: &Decoder,
+                 limits: &DecodeOptions) -> Result<(), DeflateError> {
+    loop {
+        let sym = litlen.decode(r)?;
+        if sym < END_OF_BLOCK {
+            limits.check_output(out.len() as u64 + 1)?;
+            out.push(sym as u8);
+            continue;
+        }
+        if sym == END_OF_BLOCK {
+            return Ok(());
+        }
+        let i = sym - 257;
+        if i >= LENGTH_BASE.len() {
+            return Err(DeflateError::InvalidSymbol);
+        }
+        let length = LENGTH_BASE[i] as usize + r.read_bits(LENGTH_EXTRA[i] as u32)? as usize;
+        let d = dist.decode(r)?;
+        if d >= DIST_CODES {
+            return Err(DeflateError::InvalidSymbol);
+        }
+        let distance = DIST_BASE[d] as usize + r.read_bits(DIST_EXTRA[d] as u32)? as usize;
+        if distance > out.len() {
+            return Err(DeflateError::DistanceTooFar);
+        }
+        limits.check_output((out.len() + length) as u64)?;
+        let start = out.len() - distance;
+        for k in 0..length {
+            let b = out[start + k];
+            out.push(b);
+        }
+    }
+}
+
+// Canonical decoding one bit at a time, as in zlib's puff.c: within each
+// length the codewords are consecutive, so track the first codeword of the
+// current length and how many symbols shorter codewords used up.
+struct Decoder {
+    // counts[len] is the number of codewords of length `len`
+    counts: [u16; MAX_CODE_LEN as usize + 1],
+    // Symbols in canonical order
+    symbols: Vec<u16>,
+}
+
+impl Decoder {
+
+    // Incomplete codes are accepted (a single distance code is common);
+    // reading one of the missing codewords is an error.
+    fn new(lengths: &[u8]) -> Result<Self, DeflateError> {
+        let mut counts = [0u16; MAX_CODE_LEN as usize + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = 2 * left - count as i32;
+            if left < 0 {
+                return Err(DeflateError::InvalidCodeLengths);
+            }
+        }
+        let mut symbols: Vec<(u8, u16)> = lengths.iter().enumerate()
+            .filter(|(_, &len)| len > 0)
+            .map(|(sym, &len)| (len, sym as u16))
+            .collect();
+        symbols.sort();
+        Ok(Decoder { counts, symbols: symbols.into_iter().map(|(_, sym)| sym).collect() })
+    }
+
+    fn decode(&self, r: &mut BitReader<&[u8]>) -> Result<usize, DeflateError> {
+        let mut code = 0usize;
+        let mut first = 0usize;
+        let mut index = 0usize;
+        for &count in &self.counts[1..] {
+            code |= r.read_bit()? as usize;
+            let count = count as usize;
+            if code < first + count {
+                return Ok(self.symbols[index + code - first] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(DeflateError::InvalidSymbol)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{deflate, inflate, inflate_with, rle_lengths, DeflateError};
+    use crate::limits::{DecodeOptions, Limit, LimitExceeded};
+
+    #[test]
+    fn test_zlib_streams() {
+        // Produced by zlib's deflate (raw, level 9 and level 0)
+        let fixed = [203, 72, 205, 201, 201, 87, 200, 64, 39, 117, 20, 202, 243, 139, 114, 82, 20,
+                     185, 50, 104, 44, 15, 0];
+        assert_eq!(inflate(&fixed).unwrap(), b"hello hello hello hello, world!\n".repeat(4));
+        let stored = [1, 6, 0, 249, 255, 115, 116, 111, 114, 101, 100];
+        assert_eq!(inflate(&stored).unwrap(), b"stored");
+
+        assert_eq!(deflate(b""), vec![3, 0]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut x = 0x9e3779b9u32;
+        let noise: Vec<u8> = (0..200_000).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        }).collect();
+        let text = b"It is a truth universally acknowledged, that a single man in possession \
+                     of a good fortune, must be in want of a wife. ".repeat(2000);
+        let samples = vec![b"".to_vec(), b"a".to_vec(), b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_vec(),
+                           (0..=255u8).collect(), text.clone(), noise.clone()];
+        for data in samples {
+            assert_eq!(inflate(&deflate(&data)).unwrap(), data);
+        }
+
+        assert!(deflate(&text).len() < text.len() / 50);
+        // incompressible input goes out stored, at a few bytes per block
+        assert!(deflate(&noise).len() < noise.len() + 100);
+    }
+
+    #[test]
+    fn test_rle_lengths() {
+        let lengths = [[8u8; 10].as_ref(), &[0; 150], &[0; 4], &[5, 5]].concat();
+        assert_eq!(rle_lengths(&lengths),
+                   vec![(8, 0), (16, 3), (16, 0), (18, 127), (18, 5), (5, 0), (5, 0)]);
+    }
+
+    #[test]
+    fn test_corrupt_streams() {
+        let data = b"the quick brown fox jumped over the lazy dog".repeat(20);
+        let compressed = deflate(&data);
+        assert_eq!(inflate(&compressed[..compressed.len() / 2]), Err(DeflateError::Truncated));
+        assert_eq!(inflate(&[0x07]), Err(DeflateError::InvalidBlockType));
+        assert_eq!(inflate(&[1, 6, 0, 0, 0]), Err(DeflateError::StoredLengthMismatch));
+        // fixed block opening with a match at distance 1
+        assert_eq!(inflate(&[0x03, 0x02]), Err(DeflateError::DistanceTooFar));
+    }
+
+    #[test]
+    fn test_limits() {
+        // a megabyte of zeros is a few kilobytes of DEFLATE
+        let zeros = vec![0u8; 1 << 20];
+        let bomb = deflate(&zeros);
+        assert!(bomb.len() * 100 < zeros.len());
+        let limits = DecodeOptions::new().max_output_bytes(1 << 16);
+        let over = LimitExceeded { limit: Limit::OutputBytes, max: 1 << 16 };
+        assert_eq!(inflate_with(&bomb, &limits), Err(DeflateError::LimitExceeded(over)));
+        assert_eq!(inflate_with(&bomb, &limits.max_output_bytes(1 << 20)).unwrap().len(), 1 << 20);
+        // stored blocks too
+        assert_eq!(inflate_with(&[1, 5, 0, 0xfa, 0xff, 1, 2, 3, 4, 5], &limits.max_output_bytes(4)).unwrap_err(),
+                   DeflateError::LimitExceeded(LimitExceeded { limit: Limit::OutputBytes, max: 4 }));
+    }
+
+}
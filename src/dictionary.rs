@@ -0,0 +1,159 @@
+// Preset dictionaries for the LZ coders. A small message has little
+// history of its own for matches to come from; seeding the window with
+// bytes typical of such messages (field names, boilerplate, common values)
+// gives it some. Encoder and decoder need the same dictionary, which is
+// told apart by id(), the Adler-32 of its bytes as in zlib's DICTID.
+//
+// train() builds one from sample messages, after zstd's COVER algorithm:
+// every d-mer (DMER bytes) is scored by how many samples contain it, and
+// the segments of SEGMENT bytes with the highest total score are picked
+// one at a time, each pick zeroing the scores of the d-mers it covers so
+// the next one brings something new. The best segments go last, where
+// matches into them are shortest.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::checksum::adler32;
+
+const DMER: usize = 6;
+const SEGMENT: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dictionary {
+    bytes: Vec<u8>,
+}
+
+impl Dictionary {
+
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Dictionary { bytes }
+    }
+
+    // At most `size` bytes of the substrings that recur across `samples`.
+    // Shorter (possibly empty) when there isn't that much worth having:
+    // a d-mer has to appear in at least two samples to count.
+    pub fn train<S: AsRef<[u8]>>(samples: &[S], size: usize) -> Self {
+        let samples: Vec<&[u8]> = samples.iter().map(|s| s.as_ref()).filter(|s| s.len() >= DMER).collect();
+        let mut scores: HashMap<&[u8], u64> = HashMap::new();
+        for sample in &samples {
+            let distinct: HashSet<&[u8]> = sample.windows(DMER).collect();
+            for dmer in distinct {
+                *scores.entry(dmer).or_insert(0) += 1;
+            }
+        }
+        scores.retain(|_, n| *n >= 2);
+
+        let mut picked: Vec<&[u8]> = Vec::new();
+        let mut total = 0;
+        while total < size {
+            let best = best_segment(&samples, &scores, (size - total).min(SEGMENT));
+            let segment = match best {
+                Some(segment) => segment,
+                None => break,
+            };
+            for dmer in segment.windows(DMER) {
+                scores.remove(dmer);
+            }
+            total += segment.len();
+            picked.push(segment);
+        }
+        Dictionary { bytes: picked.iter().rev().flat_map(|s| s.iter().cloned()).collect() }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn id(&self) -> u32 {
+        adler32(&self.bytes)
+    }
+
+}
+
+impl From<Vec<u8>> for Dictionary {
+    fn from(bytes: Vec<u8>) -> Self {
+        Dictionary::new(bytes)
+    }
+}
+
+// The window of `len` bytes (shorter at the end of a sample) whose d-mers
+// score highest, if any scores at all
+fn best_segment<'a>(samples: &[&'a [u8]], scores: &HashMap<&[u8], u64>, len: usize) -> Option<&'a [u8]> {
+    let mut best: Option<(u64, &[u8])> = None;
+    // a segment shorter than a d-mer covers none
+    let len = len.max(DMER);
+    for sample in samples {
+        let dmer_scores: Vec<u64> = sample.windows(DMER).map(|d| scores.get(d).cloned().unwrap_or(0)).collect();
+        // the d-mers starting at i..i + len - DMER + 1, summed as a sliding window
+        let span = (len - DMER + 1).min(dmer_scores.len());
+        let mut sum: u64 = dmer_scores[..span].iter().sum();
+        for i in 0..=dmer_scores.len() - span {
+            if i > 0 {
+                sum = sum - dmer_scores[i - 1] + dmer_scores[i + span - 1];
+            }
+            if sum > 0 && best.is_none_or(|(score, _)| sum > score) {
+                best = Some((sum, &sample[i..(i + len).min(sample.len())]));
+            }
+        }
+    }
+    best.map(|(_, segment)| segment)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::Dictionary;
+    use crate::lzss::{self, Lzss};
+
+    fn messages() -> Vec<Vec<u8>> {
+        (0..200u32).map(|i| format!(
+            r#"{{"id":{},"user":{{"name":"user{}","email":"user{}@example.com"}},"status":"{}","items":[{}]}}"#,
+            i * 7919 % 10007, i, i, ["active", "pending", "closed"][i as usize % 3], i % 5
+        ).into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_train() {
+        let messages = messages();
+        let dict = Dictionary::train(&messages[..150], 1024);
+        assert!(dict.len() <= 1024 && !dict.is_empty());
+        let text = String::from_utf8_lossy(dict.as_bytes());
+        assert!(text.contains("@example.com"));
+        assert!(text.contains("\"status\":\""));
+
+        // nothing recurs: no dictionary
+        assert!(Dictionary::train(&[b"abcdefgh", b"ijklmnop"], 100).is_empty());
+        assert!(Dictionary::train::<&[u8]>(&[], 100).is_empty());
+    }
+
+    #[test]
+    fn test_compress_with_dict() {
+        let messages = messages();
+        let dict = Dictionary::train(&messages[..150], 2048);
+        let lz = Lzss::level(6);
+        let (mut plain, mut with_dict) = (0, 0);
+        for message in &messages[150..] {
+            let packed = lz.encode_with_dict(message, &dict);
+            assert_eq!(lzss::decode_with_dict(&packed, &dict).unwrap(), *message);
+            plain += lz.encode(message).len();
+            with_dict += packed.len();
+        }
+        assert!(with_dict < plain / 2);
+
+        // a different dictionary gives different bytes, if it decodes at all
+        let packed = lz.encode_with_dict(&messages[199], &dict);
+        let other = Dictionary::new(vec![b'x'; dict.len()]);
+        assert_ne!(dict.id(), other.id());
+        assert_ne!(lzss::decode_with_dict(&packed, &other).ok().as_ref(), Some(&messages[199]));
+        assert_eq!(lz.encode_with_dict(b"", &dict), lz.encode(b""));
+    }
+
+}
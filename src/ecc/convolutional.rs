@@ -0,0 +1,240 @@
+// Rate 1/n convolutional codes with a hard-decision Viterbi decoder.
+//
+// The encoder keeps the last K - 1 input bits (K, the constraint length,
+// counts the current bit too); for each input bit it sends one bit per
+// generator polynomial, the parity of the polynomial's taps over those K
+// bits. Polynomials are written the usual way, in octal with the most
+// significant bit tapping the current input: Convolutional::nasa() is
+// K = 7 with 171 and 133, as flown on Voyager and used in 802.11.
+//
+// Every message is followed by K - 1 zero bits to bring the encoder back
+// to state 0, so the decoder knows where the best path has to end. The
+// decoder keeps one decision bit per state per step, a cost of
+// (8 * len + K - 1) * 2^(K - 1) / 8 bytes of memory for `len` bytes of data.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::bitio::{BitReader, BitWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvError {
+    Truncated,
+    // A received bit count that isn't a whole number of steps, tail
+    // included
+    BadLength,
+}
+
+impl fmt::Display for ConvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConvError::Truncated => write!(f, "convolutionally coded data is truncated"),
+            ConvError::BadLength => write!(f, "received bits don't match the code's rate"),
+        }
+    }
+}
+
+impl Error for ConvError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Convolutional {
+    k: u32,
+    polys: Vec<u32>,
+    // Output bits (first polynomial highest) for every value of the K-bit
+    // register, current input bit highest
+    outputs: Vec<u8>,
+}
+
+impl Convolutional {
+
+    // Panics unless 2 <= k <= 16, there are 1 to 8 polynomials, and each
+    // is nonzero and fits in k bits
+    pub fn new(k: u32, polys: &[u32]) -> Self {
+        assert!((2..=16).contains(&k), "constraint length must be in 2..=16");
+        assert!(!polys.is_empty() && polys.len() <= 8, "need 1 to 8 generator polynomials");
+        assert!(polys.iter().all(|&p| p != 0 && p < 1 << k), "generator polynomial out of range");
+        let outputs = (0..1u32 << k).map(|reg| {
+            polys.iter().fold(0u8, |out, &p| out << 1 | ((reg & p).count_ones() & 1) as u8)
+        }).collect();
+        Convolutional { k, polys: polys.to_vec(), outputs }
+    }
+
+    // K = 7, rate 1/2, polynomials 171 and 133 (octal)
+    pub fn nasa() -> Self {
+        Convolutional::new(7, &[0o171, 0o133])
+    }
+
+    pub fn constraint_length(&self) -> u32 {
+        self.k
+    }
+
+    pub fn polynomials(&self) -> &[u32] {
+        &self.polys
+    }
+
+    // Output bits per input bit
+    pub fn outputs(&self) -> usize {
+        self.polys.len()
+    }
+
+    // Coded bits for `len` bytes of data, tail included
+    pub fn encoded_bits(&self, len: usize) -> usize {
+        (8 * len + self.k as usize - 1) * self.outputs()
+    }
+
+    pub fn encode_bits(&self, bits: &[bool]) -> Vec<bool> {
+        let tail = std::iter::repeat_n(false, self.k as usize - 1);
+        let n = self.outputs();
+        let mut state = 0u32;
+        let mut out = Vec::with_capacity((bits.len() + tail.len()) * n);
+        for bit in bits.iter().cloned().chain(tail) {
+            let reg = (bit as u32) << (self.k - 1) | state;
+            let symbol = self.outputs[reg as usize];
+            out.extend((0..n).rev().map(|i| symbol >> i & 1 == 1));
+            state = reg >> 1;
+        }
+        out
+    }
+
+    // The most likely input for `received`, and the number of bits of
+    // `received` it disagrees with (the errors corrected, if the channel
+    // wasn't too noisy for the code)
+    pub fn decode_bits(&self, received: &[bool]) -> Result<(Vec<bool>, u32), ConvError> {
+        let n = self.outputs();
+        let tail = self.k as usize - 1;
+        if !received.len().is_multiple_of(n) || received.len() / n < tail {
+            return Err(ConvError::BadLength);
+        }
+        let steps = received.len() / n;
+        let states = 1usize << (self.k - 1);
+        let mask = states - 1;
+        let words = states.div_ceil(64);
+
+        // the encoder starts in state 0
+        let mut metrics = vec![u32::MAX; states];
+        metrics[0] = 0;
+        let mut next = vec![u32::MAX; states];
+        // bit s of step t: state s was entered from its predecessor ending in 1
+        let mut decisions = vec![0u64; steps * words];
+        for (t, symbol) in received.chunks(n).enumerate() {
+            let symbol = symbol.iter().fold(0u8, |acc, &b| acc << 1 | b as u8);
+            for (s, metric) in next.iter_mut().enumerate() {
+                let bit = s >> (self.k - 2);
+                let p0 = (s << 1) & mask;
+                let branch = |p: usize| {
+                    let reg = bit << (self.k - 1) | p;
+                    metrics[p].saturating_add((self.outputs[reg] ^ symbol).count_ones())
+                };
+                let (m0, m1) = (branch(p0), branch(p0 | 1));
+                if m1 < m0 {
+                    *metric = m1;
+                    decisions[t * words + s / 64] |= 1 << (s % 64);
+                } else {
+                    *metric = m0;
+                }
+            }
+            std::mem::swap(&mut metrics, &mut next);
+        }
+
+        // trace back from state 0, where the tail left the encoder
+        let mut bits = vec![false; steps];
+        let mut state = 0;
+        for t in (0..steps).rev() {
+            bits[t] = state >> (self.k - 2) & 1 == 1;
+            let d = decisions[t * words + state / 64] >> (state % 64) & 1;
+            state = (state << 1) & mask | d as usize;
+        }
+        bits.truncate(steps - tail);
+        Ok((bits, metrics[0]))
+    }
+
+    pub fn write_bytes<W: Write>(&self, data: &[u8], out: &mut BitWriter<W>) -> io::Result<()> {
+        for bit in self.encode_bits(&to_bits(data)) {
+            out.write_bit(bit)?;
+        }
+        Ok(())
+    }
+
+    // Read the encoding of `len` bytes and decode it, with the number of
+    // bit errors corrected
+    pub fn read_bytes<R: Read>(&self, input: &mut BitReader<R>, len: usize) -> io::Result<(Vec<u8>, u32)> {
+        let received = (0..self.encoded_bits(len)).map(|_| input.read_bit()).collect::<io::Result<Vec<bool>>>()?;
+        let (bits, errors) = self.decode_bits(&received).expect("read a whole number of steps");
+        Ok((from_bits(&bits), errors))
+    }
+
+    // write_bytes() into a Vec, zero-padded to a whole byte
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = BitWriter::new(Vec::with_capacity(self.encoded_bits(data.len()).div_ceil(8)));
+        self.write_bytes(data, &mut out).expect("writing to a Vec can't fail");
+        out.into_inner().expect("writing to a Vec can't fail")
+    }
+
+    // The counterpart of encode(), for `len` bytes of data
+    pub fn decode(&self, bytes: &[u8], len: usize) -> Result<(Vec<u8>, u32), ConvError> {
+        if bytes.len() * 8 < self.encoded_bits(len) {
+            return Err(ConvError::Truncated);
+        }
+        let received: Vec<bool> = to_bits(bytes).into_iter().take(self.encoded_bits(len)).collect();
+        let (bits, errors) = self.decode_bits(&received)?;
+        Ok((from_bits(&bits), errors))
+    }
+
+}
+
+// MSB first, as bitio writes
+fn to_bits(data: &[u8]) -> Vec<bool> {
+    data.iter().flat_map(|&b| (0..8).rev().map(move |i| b >> i & 1 == 1)).collect()
+}
+
+fn from_bits(bits: &[bool]) -> Vec<u8> {
+    bits.chunks(8).map(|byte| byte.iter().fold(0u8, |acc, &b| acc << 1 | b as u8)).collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{from_bits, to_bits, ConvError, Convolutional};
+
+    #[test]
+    fn test_encode() {
+        // the textbook K = 3 (7, 5) code: 1011 -> 11 10 00 01 01 11
+        let code = Convolutional::new(3, &[0o7, 0o5]);
+        let bits = |s: &str| s.chars().filter(|c| !c.is_whitespace()).map(|c| c == '1').collect::<Vec<bool>>();
+        assert_eq!(code.encode_bits(&bits("1011")), bits("11 10 00 01 01 11"));
+        assert_eq!(code.decode_bits(&bits("11 10 00 01 01 11")), Ok((bits("1011"), 0)));
+        // one error
+        assert_eq!(code.decode_bits(&bits("11 10 01 01 01 11")), Ok((bits("1011"), 1)));
+        assert_eq!(code.decode_bits(&bits("11 10 0")), Err(ConvError::BadLength));
+        assert_eq!(from_bits(&to_bits(b"ok")), b"ok");
+    }
+
+    #[test]
+    fn test_viterbi() {
+        let code = Convolutional::nasa();
+        let data = b"Voyager 1, 1977. Still talking.".to_vec();
+        let packed = code.encode(&data);
+        assert_eq!(packed.len(), code.encoded_bits(data.len()).div_ceil(8));
+        assert_eq!(code.decode(&packed, data.len()), Ok((data.clone(), 0)));
+
+        // a flipped bit in every 20, well inside what free distance 10 covers
+        let mut noisy = packed.clone();
+        let mut flipped = 0;
+        for bit in (7..code.encoded_bits(data.len())).step_by(20) {
+            noisy[bit / 8] ^= 0x80 >> (bit % 8);
+            flipped += 1;
+        }
+        assert_eq!(code.decode(&noisy, data.len()), Ok((data.clone(), flipped)));
+        assert_eq!(code.decode(&packed[..10], data.len()), Err(ConvError::Truncated));
+
+        // a rate 1/3 code with a longer register
+        let code = Convolutional::new(9, &[0o557, 0o663, 0o711]);
+        let mut noisy = code.encode(&data);
+        for byte in noisy.iter_mut().step_by(5) {
+            *byte ^= 0x10;
+        }
+        assert_eq!(code.decode(&noisy, data.len()).unwrap().0, data);
+    }
+
+}
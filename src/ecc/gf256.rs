@@ -0,0 +1,88 @@
+// Arithmetic in GF(2^8) with the polynomial x^8 + x^4 + x^3 + x^2 + 1
+// (0x11d) and generator 2, as in QR codes, CDs and most Reed-Solomon
+// implementations. Addition and subtraction are both xor; multiplication
+// and division go through log and antilog tables.
+
+const POLY: u16 = 0x11d;
+
+// EXP is doubled so a sum of two logs indexes it without a reduction
+const TABLES: ([u8; 512], [u8; 256]) = tables();
+const EXP: [u8; 512] = TABLES.0;
+const LOG: [u8; 256] = TABLES.1;
+
+const fn tables() -> ([u8; 512], [u8; 256]) {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= POLY;
+        }
+        i += 1;
+    }
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+    (exp, log)
+}
+
+pub fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    EXP[LOG[a as usize] as usize + LOG[b as usize] as usize]
+}
+
+// Panics if b is 0
+pub fn div(a: u8, b: u8) -> u8 {
+    assert!(b != 0, "division by zero in GF(256)");
+    if a == 0 {
+        return 0;
+    }
+    EXP[LOG[a as usize] as usize + 255 - LOG[b as usize] as usize]
+}
+
+// Panics if a is 0
+pub fn inv(a: u8) -> u8 {
+    div(1, a)
+}
+
+// The generator 2 to the power `n`, which may be negative
+pub fn exp(n: i32) -> u8 {
+    EXP[n.rem_euclid(255) as usize]
+}
+
+// Panics if a is 0
+pub fn log(a: u8) -> u8 {
+    assert!(a != 0, "log of zero in GF(256)");
+    LOG[a as usize]
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{div, exp, inv, log, mul};
+
+    #[test]
+    fn test_field() {
+        for a in 1..=255u8 {
+            assert_eq!(mul(a, inv(a)), 1);
+            assert_eq!(exp(log(a) as i32), a);
+            for b in [1u8, 2, 3, 0x53, 0xca, 255].iter() {
+                assert_eq!(div(mul(a, *b), *b), a);
+                assert_eq!(mul(a, *b), mul(*b, a));
+            }
+            assert_eq!(mul(a, 0), 0);
+        }
+        // x * x^7 wraps around the polynomial
+        assert_eq!(mul(2, 0x80), 0x1d);
+        assert_eq!(exp(-1), inv(2));
+        assert_eq!(exp(255), 1);
+    }
+
+}
@@ -0,0 +1,217 @@
+// Hamming codes over bit streams. Each 4-bit nibble of data becomes a
+// codeword that survives one flipped bit:
+//
+//   Hamming::H74     (7,4): parity bits at positions 1, 2 and 4 of the
+//                    codeword (MSB first), data at 3, 5, 6 and 7; the
+//                    syndrome of a damaged codeword is the position of the
+//                    bad bit. Corrects any single error.
+//   Hamming::Secded  (8,4), "extended": the (7,4) codeword and a parity bit
+//                    over all seven. Single error correction, double error
+//                    detection: two flipped bits are reported rather than
+//                    "corrected" into a third.
+//
+// Bytes go high nibble first. The code rate is 4/7 or 1/2, so this is for
+// noisy channels, not for storage that a checksum and a retry can cover.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::bitio::{BitReader, BitWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HammingError {
+    Truncated,
+    // Two bits of SECDED codeword `index` (counting from 0) are wrong
+    DoubleError { index: usize },
+}
+
+impl fmt::Display for HammingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HammingError::Truncated => write!(f, "Hamming coded data is truncated"),
+            HammingError::DoubleError { index } => write!(f, "uncorrectable double error in codeword {}", index),
+        }
+    }
+}
+
+impl Error for HammingError {}
+
+impl From<HammingError> for io::Error {
+    fn from(e: HammingError) -> Self {
+        let kind = match e {
+            HammingError::Truncated => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hamming {
+    H74,
+    Secded,
+}
+
+impl Hamming {
+
+    pub fn codeword_bits(self) -> u32 {
+        match self {
+            Hamming::H74 => 7,
+            Hamming::Secded => 8,
+        }
+    }
+
+    // The codeword for the low 4 bits of `nibble`, in the low
+    // codeword_bits() bits
+    pub fn encode_nibble(self, nibble: u8) -> u8 {
+        let d = |i: u32| (nibble >> (3 - i)) & 1;
+        let (d1, d2, d3, d4) = (d(0), d(1), d(2), d(3));
+        let p1 = d1 ^ d2 ^ d4;
+        let p2 = d1 ^ d3 ^ d4;
+        let p4 = d2 ^ d3 ^ d4;
+        let word = p1 << 6 | p2 << 5 | d1 << 4 | p4 << 3 | d2 << 2 | d3 << 1 | d4;
+        match self {
+            Hamming::H74 => word,
+            Hamming::Secded => word << 1 | (word.count_ones() & 1) as u8,
+        }
+    }
+
+    // The nibble in `word`, and whether a bit had to be corrected. Only
+    // Secded can fail (with index 0); H74 takes any word for at most one
+    // error away from a codeword.
+    pub fn decode_codeword(self, word: u8) -> Result<(u8, bool), HammingError> {
+        let (word, overall) = match self {
+            Hamming::H74 => (word & 0x7f, None),
+            Hamming::Secded => (word >> 1, Some(word.count_ones() & 1 == 1)),
+        };
+        // bit at position p (1-based, MSB first) of the 7-bit word
+        let bit = |p: u32| (word >> (7 - p)) & 1;
+        let syndrome = (bit(1) ^ bit(3) ^ bit(5) ^ bit(7))
+                     | (bit(2) ^ bit(3) ^ bit(6) ^ bit(7)) << 1
+                     | (bit(4) ^ bit(5) ^ bit(6) ^ bit(7)) << 2;
+        let corrected = match (syndrome, overall) {
+            (0, None) | (0, Some(false)) => return Ok((nibble_of(word), false)),
+            // only the overall parity bit itself is wrong
+            (0, Some(true)) => word,
+            (_, Some(false)) => return Err(HammingError::DoubleError { index: 0 }),
+            (s, _) => word ^ (1 << (7 - s)),
+        };
+        Ok((nibble_of(corrected), true))
+    }
+
+    pub fn write_bytes<W: Write>(self, data: &[u8], out: &mut BitWriter<W>) -> io::Result<()> {
+        for &b in data {
+            out.write_bits(self.encode_nibble(b >> 4) as u64, self.codeword_bits())?;
+            out.write_bits(self.encode_nibble(b & 0xf) as u64, self.codeword_bits())?;
+        }
+        Ok(())
+    }
+
+    // Read `len` bytes' worth of codewords, correcting as it goes; also
+    // returns how many codewords needed it. A double error comes back as
+    // InvalidData wrapping a HammingError, its index counted from where
+    // this call started.
+    pub fn read_bytes<R: Read>(self, input: &mut BitReader<R>, len: usize) -> io::Result<(Vec<u8>, usize)> {
+        let mut out = Vec::with_capacity(len);
+        let mut fixed = 0;
+        for i in 0..len {
+            let mut byte = 0;
+            for half in 0..2 {
+                let word = input.read_bits(self.codeword_bits())? as u8;
+                let (nibble, corrected) = self.decode_codeword(word)
+                    .map_err(|_| HammingError::DoubleError { index: 2 * i + half })?;
+                byte = byte << 4 | nibble;
+                fixed += corrected as usize;
+            }
+            out.push(byte);
+        }
+        Ok((out, fixed))
+    }
+
+    // write_bytes() into a Vec, zero-padded to a whole byte
+    pub fn encode(self, data: &[u8]) -> Vec<u8> {
+        let mut out = BitWriter::new(Vec::with_capacity(data.len() * 2));
+        self.write_bytes(data, &mut out).expect("writing to a Vec can't fail");
+        out.into_inner().expect("writing to a Vec can't fail")
+    }
+
+    // The counterpart of encode(), for `len` bytes of data
+    pub fn decode(self, bytes: &[u8], len: usize) -> Result<(Vec<u8>, usize), HammingError> {
+        self.read_bytes(&mut BitReader::new(bytes), len).map_err(|e| match e.into_inner() {
+            Some(inner) => *inner.downcast::<HammingError>().expect("only HammingErrors besides EOF"),
+            None => HammingError::Truncated,
+        })
+    }
+
+}
+
+// Data bits sit at positions 3, 5, 6 and 7
+fn nibble_of(word: u8) -> u8 {
+    (word >> 4 & 1) << 3 | (word >> 2 & 1) << 2 | (word >> 1 & 1) << 1 | word & 1
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Hamming, HammingError};
+    use crate::bitio::{BitReader, BitWriter};
+
+    #[test]
+    fn test_codewords() {
+        // the textbook (7,4) codewords: 0b1011 -> 0110011
+        assert_eq!(Hamming::H74.encode_nibble(0b1011), 0b0110011);
+        assert_eq!(Hamming::Secded.encode_nibble(0b1011), 0b01100110);
+        for code in [Hamming::H74, Hamming::Secded].iter() {
+            let bits = code.codeword_bits();
+            for nibble in 0..16u8 {
+                let word = code.encode_nibble(nibble);
+                assert_eq!(code.decode_codeword(word), Ok((nibble, false)));
+                for i in 0..bits {
+                    assert_eq!(code.decode_codeword(word ^ 1 << i), Ok((nibble, true)));
+                }
+                // two errors: H74 goes wrong, Secded notices
+                for i in 0..bits {
+                    for j in 0..i {
+                        let damaged = word ^ 1 << i ^ 1 << j;
+                        match code {
+                            Hamming::H74 => assert_ne!(code.decode_codeword(damaged).unwrap().0, nibble),
+                            Hamming::Secded => assert_eq!(code.decode_codeword(damaged),
+                                                          Err(HammingError::DoubleError { index: 0 })),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_streams() {
+        let data = b"noisy channel".to_vec();
+        let packed = Hamming::H74.encode(&data);
+        assert_eq!(packed.len(), (data.len() * 14).div_ceil(8));
+
+        // one flipped bit in every codeword
+        let mut damaged = packed.clone();
+        for word in 0..data.len() * 2 {
+            let bit = word * 7 + word % 7;
+            damaged[bit / 8] ^= 0x80 >> (bit % 8);
+        }
+        assert_eq!(Hamming::H74.decode(&damaged, data.len()), Ok((data.clone(), data.len() * 2)));
+        assert_eq!(Hamming::H74.decode(&packed, data.len() + 1), Err(HammingError::Truncated));
+
+        // behind other fields in the same bit stream
+        let mut out = BitWriter::new(Vec::new());
+        out.write_bits(0b101, 3).unwrap();
+        Hamming::Secded.write_bytes(&data, &mut out).unwrap();
+        let mut bytes = out.into_inner().unwrap();
+        // stream bits 30 and 31, both in the fourth codeword
+        bytes[3] ^= 0b11;
+        let mut input = BitReader::new(&bytes[..]);
+        assert_eq!(input.read_bits(3).unwrap(), 0b101);
+        let err = Hamming::Secded.read_bytes(&mut input, data.len()).unwrap_err();
+        assert_eq!(err.get_ref().unwrap().downcast_ref::<HammingError>(),
+                   Some(&HammingError::DoubleError { index: 3 }));
+    }
+
+}
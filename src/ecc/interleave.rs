@@ -0,0 +1,222 @@
+// Interleavers spread neighbouring symbols apart before a channel and put
+// them back after it, so a burst of errors lands as scattered single
+// errors, one or two per codeword, where the error correcting code can
+// deal with them.
+//
+// BlockInterleaver writes `rows` x `cols` symbols into a matrix by rows and
+// reads them out by columns: two symbols next to each other on the
+// channel were `cols` apart before. A short last block is read the same
+// way with the missing cells skipped, so the output is always exactly as
+// long as the input.
+//
+// ConvolutionalInterleaver (Forney's) deals symbols round-robin to
+// `branches` delay lines of 0, delay, 2 * delay, ... symbols; the
+// deinterleaver's delays run the other way, so every symbol comes out
+// latency() symbols late. It spreads bursts as well as a block interleaver
+// at about half the memory and delay, and works on an unbounded stream.
+//
+// Both work on any symbol: bytes for Reed-Solomon, bits from bitio for the
+// Hamming and convolutional codes.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use crate::bitio::{BitReader, BitWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockInterleaver {
+    rows: usize,
+    cols: usize,
+}
+
+impl BlockInterleaver {
+
+    // Panics unless rows and cols are both nonzero
+    pub fn new(rows: usize, cols: usize) -> Self {
+        assert!(rows > 0 && cols > 0, "interleaver needs at least one row and column");
+        BlockInterleaver { rows, cols }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn interleave<T: Clone>(&self, data: &[T]) -> Vec<T> {
+        let mut out = Vec::with_capacity(data.len());
+        for block in data.chunks(self.rows.saturating_mul(self.cols)) {
+            out.extend(self.order(block.len()).map(|i| block[i].clone()));
+        }
+        out
+    }
+
+    pub fn deinterleave<T: Clone>(&self, data: &[T]) -> Vec<T> {
+        let mut out = data.to_vec();
+        let mut start = 0;
+        for block in data.chunks(self.rows.saturating_mul(self.cols)) {
+            for (x, i) in block.iter().zip(self.order(block.len())) {
+                out[start + i] = x.clone();
+            }
+            start += block.len();
+        }
+        out
+    }
+
+    // Interleave `bits` onto a bit stream
+    pub fn write_bits<W: Write>(&self, bits: &[bool], out: &mut BitWriter<W>) -> io::Result<()> {
+        for bit in self.interleave(bits) {
+            out.write_bit(bit)?;
+        }
+        Ok(())
+    }
+
+    // Read `n` interleaved bits and put them back in order
+    pub fn read_bits<R: Read>(&self, input: &mut BitReader<R>, n: usize) -> io::Result<Vec<bool>> {
+        let bits = (0..n).map(|_| input.read_bit()).collect::<io::Result<Vec<bool>>>()?;
+        Ok(self.deinterleave(&bits))
+    }
+
+    // Positions in a block of `len` symbols, in the order they're sent.
+    // Only the cells under `len` are visited, so a huge matrix around a
+    // few symbols costs no more than a small one.
+    fn order(&self, len: usize) -> impl Iterator<Item=usize> {
+        let (rows, cols) = (self.rows, self.cols);
+        (0..cols.min(len)).flat_map(move |c| (0..rows.min((len - c - 1) / cols + 1)).map(move |r| r * cols + c))
+    }
+
+}
+
+#[derive(Debug, Clone)]
+pub struct ConvolutionalInterleaver<T> {
+    lines: Vec<VecDeque<T>>,
+    next: usize,
+    delay: usize,
+}
+
+impl<T: Clone + Default> ConvolutionalInterleaver<T> {
+
+    // Line i delays by i * delay symbols. Panics if branches is 0.
+    pub fn new(branches: usize, delay: usize) -> Self {
+        ConvolutionalInterleaver::with_delays(branches, delay, |i| i * delay)
+    }
+
+    // The inverse of new(branches, delay): line i delays by
+    // (branches - 1 - i) * delay
+    pub fn deinterleaver(branches: usize, delay: usize) -> Self {
+        ConvolutionalInterleaver::with_delays(branches, delay, |i| (branches - 1 - i) * delay)
+    }
+
+    fn with_delays(branches: usize, delay: usize, line_delay: impl Fn(usize) -> usize) -> Self {
+        assert!(branches > 0, "interleaver needs at least one branch");
+        let lines = (0..branches).map(|i| std::iter::repeat_n(T::default(), line_delay(i)).collect()).collect();
+        ConvolutionalInterleaver { lines, next: 0, delay }
+    }
+
+    pub fn branches(&self) -> usize {
+        self.lines.len()
+    }
+
+    // Symbols between going into an interleaver and coming out of the
+    // matching deinterleaver
+    pub fn latency(&self) -> usize {
+        self.lines.len() * (self.lines.len() - 1) * self.delay
+    }
+
+    // Put one symbol in and take one out; until the lines have filled,
+    // what comes out is T::default()
+    pub fn push(&mut self, x: T) -> T {
+        let branches = self.lines.len();
+        let line = &mut self.lines[self.next];
+        self.next = (self.next + 1) % branches;
+        line.push_back(x);
+        line.pop_front().expect("a line is never empty after a push")
+    }
+
+}
+
+// All of `data` through a fresh convolutional interleaver, followed by
+// enough padding to flush it: latency() symbols longer than `data`
+pub fn interleave_convolutional<T: Clone + Default>(data: &[T], branches: usize, delay: usize) -> Vec<T> {
+    let mut interleaver = ConvolutionalInterleaver::new(branches, delay);
+    let padding = std::iter::repeat_n(T::default(), interleaver.latency());
+    data.iter().cloned().chain(padding).map(|x| interleaver.push(x)).collect()
+}
+
+// The inverse of interleave_convolutional; None if `data` is shorter than
+// the latency
+pub fn deinterleave_convolutional<T: Clone + Default>(data: &[T], branches: usize, delay: usize) -> Option<Vec<T>> {
+    let mut deinterleaver = ConvolutionalInterleaver::deinterleaver(branches, delay);
+    let latency = deinterleaver.latency();
+    if data.len() < latency {
+        return None;
+    }
+    Some(data.iter().map(|x| deinterleaver.push(x.clone())).skip(latency).collect())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{deinterleave_convolutional, interleave_convolutional, BlockInterleaver, ConvolutionalInterleaver};
+    use crate::bitio::{BitReader, BitWriter};
+    use crate::ecc::hamming::Hamming;
+
+    #[test]
+    fn test_block() {
+        let block = BlockInterleaver::new(3, 4);
+        let data: Vec<u32> = (0..12).collect();
+        assert_eq!(block.interleave(&data), vec![0, 4, 8, 1, 5, 9, 2, 6, 10, 3, 7, 11]);
+        for len in [0, 1, 11, 12, 13, 30].iter() {
+            let data: Vec<u32> = (0..*len).collect();
+            let interleaved = block.interleave(&data);
+            assert_eq!(interleaved.len(), data.len());
+            assert_eq!(block.deinterleave(&interleaved), data);
+        }
+        // short last block: 12..17 as a 3x4 matrix with the bottom row cut
+        assert_eq!(block.interleave(&(0..17).collect::<Vec<u32>>())[12..], [12, 16, 13, 14, 15]);
+        // as wide and tall as a pipeline header allows, around five bytes
+        let huge = BlockInterleaver::new(u32::MAX as usize, u32::MAX as usize);
+        assert_eq!(huge.deinterleave(b"hello"), b"hello");
+        assert_eq!(BlockInterleaver::new(2, u32::MAX as usize).interleave(b"hello"), b"hello");
+        assert_eq!(BlockInterleaver::new(u32::MAX as usize, 2).interleave(b"hello"), b"hloel");
+
+        // a burst of 7 bits across Hamming codewords that can take one each
+        let data = b"burst".to_vec();
+        let coded = Hamming::H74.encode(&data);
+        let bits: Vec<bool> = (0..data.len() * 14).map(|i| coded[i / 8] >> (7 - i % 8) & 1 == 1).collect();
+        let block = BlockInterleaver::new(data.len() * 2, 7);
+        let mut out = BitWriter::new(Vec::new());
+        block.write_bits(&bits, &mut out).unwrap();
+        let mut channel = out.into_inner().unwrap();
+        channel[2] ^= 0xfe;
+        let received = block.read_bits(&mut BitReader::new(&channel[..]), bits.len()).unwrap();
+        let mut repacked = BitWriter::new(Vec::new());
+        for bit in received {
+            repacked.write_bit(bit).unwrap();
+        }
+        let repacked = repacked.into_inner().unwrap();
+        assert_eq!(Hamming::H74.decode(&repacked, data.len()), Ok((data, 7)));
+    }
+
+    #[test]
+    fn test_convolutional() {
+        let data: Vec<u8> = (1..=100).collect();
+        let interleaved = interleave_convolutional(&data, 4, 2);
+        assert_eq!(interleaved.len(), data.len() + 4 * 3 * 2);
+        assert_eq!(deinterleave_convolutional(&interleaved, 4, 2), Some(data.clone()));
+        assert_eq!(deinterleave_convolutional(&interleaved[..5], 4, 2), None);
+
+        // neighbours on the channel were at least `branches` apart going in
+        let positions: Vec<u8> = interleaved.iter().cloned().filter(|&x| x > 0).collect();
+        for pair in positions.windows(2) {
+            assert!(pair[0].abs_diff(pair[1]) >= 4);
+        }
+
+        let mut interleaver = ConvolutionalInterleaver::new(3, 1);
+        let out: Vec<u8> = (1..=6).map(|x| interleaver.push(x)).collect();
+        assert_eq!(out, [1, 0, 0, 4, 2, 0]);
+    }
+
+}
@@ -0,0 +1,10 @@
+// Channel coding: redundancy added back on purpose, so data that has
+// crossed a noisy channel or sat on a failing disk can be repaired rather
+// than just found corrupt by a checksum. Compressed data needs it more
+// than most, as one bad bit can wreck everything decoded after it.
+
+pub mod convolutional;
+pub mod gf256;
+pub mod hamming;
+pub mod interleave;
+pub mod reed_solomon;
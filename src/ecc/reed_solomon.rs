@@ -0,0 +1,358 @@
+// Reed-Solomon codes over GF(256). A codeword is up to 255 bytes: the data,
+// then `parity` bytes computed from it, and any `parity / 2` corrupt bytes
+// anywhere in it can be corrected. Data shorter than 255 - parity bytes
+// makes a shortened codeword, as if padded with leading zeros.
+//
+// The generator polynomial has roots 2^0 .. 2^(parity - 1). Decoding is
+// the textbook route: syndromes, Berlekamp-Massey for the error locator,
+// a Chien search for its roots and Forney's formula for the magnitudes.
+//
+// protect() and recover() handle data of any length, as a run of
+// codewords behind a header giving the parity count and the data length:
+//
+//   header       17 bytes: a codeword with HEADER_PARITY parity bytes over
+//                parity u8 and length u64 (big-endian)
+//   codewords    the data in pieces of 255 - parity bytes (the last may be
+//                shorter), each followed by its parity bytes
+//
+// This is the post-stage of the pipeline (pipeline::ReedSolomon) and of
+// the container (CompressOptions::ecc).
+
+use std::error::Error;
+use std::fmt;
+
+use super::gf256;
+
+pub const HEADER_PARITY: usize = 8;
+const HEADER_LEN: usize = 9 + HEADER_PARITY;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RsError {
+    Truncated,
+    // More corrupt bytes in a codeword than its parity can correct
+    TooManyErrors,
+    // A header that decodes but doesn't describe the bytes after it
+    BadHeader,
+}
+
+impl fmt::Display for RsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RsError::Truncated => write!(f, "error-corrected data is truncated"),
+            RsError::TooManyErrors => write!(f, "too many errors to correct"),
+            RsError::BadHeader => write!(f, "invalid error correction header"),
+        }
+    }
+}
+
+impl Error for RsError {}
+
+#[derive(Debug, Clone)]
+pub struct ReedSolomon {
+    parity: usize,
+    // Highest power first, leading coefficient 1
+    generator: Vec<u8>,
+}
+
+impl ReedSolomon {
+
+    // Panics unless 0 < parity < 255
+    pub fn new(parity: usize) -> Self {
+        assert!(parity > 0 && parity < 255, "parity must be in 1..=254");
+        let mut generator = vec![1u8];
+        for i in 0..parity {
+            // times (x + 2^i)
+            let root = gf256::exp(i as i32);
+            let mut next = generator.clone();
+            next.push(0);
+            for (j, &g) in generator.iter().enumerate() {
+                next[j + 1] ^= gf256::mul(g, root);
+            }
+            generator = next;
+        }
+        ReedSolomon { parity, generator }
+    }
+
+    pub fn parity(&self) -> usize {
+        self.parity
+    }
+
+    // Most data bytes a codeword can hold
+    pub fn max_data_len(&self) -> usize {
+        255 - self.parity
+    }
+
+    // The parity bytes for `data`. Panics if data is longer than
+    // max_data_len().
+    pub fn parity_of(&self, data: &[u8]) -> Vec<u8> {
+        assert!(data.len() <= self.max_data_len(), "data too long for one codeword");
+        // the remainder of data * x^parity divided by the generator
+        let mut rem = vec![0u8; self.parity];
+        for &b in data {
+            let coef = b ^ rem[0];
+            rem.rotate_left(1);
+            rem[self.parity - 1] = 0;
+            if coef != 0 {
+                for (r, &g) in rem.iter_mut().zip(&self.generator[1..]) {
+                    *r ^= gf256::mul(g, coef);
+                }
+            }
+        }
+        rem
+    }
+
+    // `data` followed by its parity bytes. Panics if data is longer than
+    // max_data_len().
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = data.to_vec();
+        out.extend(self.parity_of(data));
+        out
+    }
+
+    // Correct `codeword` (data then parity) in place, returning how many
+    // bytes were wrong. On TooManyErrors it is left as it was. Panics if
+    // the codeword is longer than 255 bytes or shorter than the parity.
+    pub fn decode(&self, codeword: &mut [u8]) -> Result<usize, RsError> {
+        assert!(codeword.len() <= 255 && codeword.len() >= self.parity, "not a codeword length");
+        let syndromes = self.syndromes(codeword);
+        if syndromes.iter().all(|&s| s == 0) {
+            return Ok(0);
+        }
+        let locator = berlekamp_massey(&syndromes);
+        let errors = locator.len() - 1;
+        if 2 * errors > self.parity {
+            return Err(RsError::TooManyErrors);
+        }
+
+        // Chien search: byte k is the coefficient of x^(n - 1 - k), so an
+        // error there makes 2^-(n - 1 - k) a root of the locator
+        let n = codeword.len();
+        let positions: Vec<usize> = (0..n).filter(|&k| eval_low(&locator, gf256::exp(-((n - 1 - k) as i32))) == 0)
+                                          .collect();
+        if positions.len() != errors {
+            return Err(RsError::TooManyErrors);
+        }
+
+        // Forney: with the first root 2^0 the magnitude at locator X is
+        // X * omega(1/X) / locator'(1/X), where omega = S * locator mod x^parity
+        let mut omega = vec![0u8; self.parity];
+        for (i, &s) in syndromes.iter().enumerate() {
+            for (j, &l) in locator.iter().enumerate().take(self.parity - i) {
+                omega[i + j] ^= gf256::mul(s, l);
+            }
+        }
+        // the formal derivative keeps the odd powers
+        let derivative: Vec<u8> = locator.iter().enumerate().skip(1)
+                                         .map(|(i, &l)| if i % 2 == 1 { l } else { 0 }).collect();
+        let mut corrected = codeword.to_vec();
+        for &k in &positions {
+            let x = gf256::exp((n - 1 - k) as i32);
+            let x_inv = gf256::inv(x);
+            let denominator = eval_low(&derivative, x_inv);
+            if denominator == 0 {
+                return Err(RsError::TooManyErrors);
+            }
+            corrected[k] ^= gf256::mul(x, gf256::div(eval_low(&omega, x_inv), denominator));
+        }
+        // past half the parity the locator can point at the wrong bytes
+        if self.syndromes(&corrected).iter().any(|&s| s != 0) {
+            return Err(RsError::TooManyErrors);
+        }
+        codeword.copy_from_slice(&corrected);
+        Ok(errors)
+    }
+
+    // The data as a header and a run of codewords (see the top of the file)
+    pub fn protect(&self, data: &[u8]) -> Vec<u8> {
+        let chunks = data.len().div_ceil(self.max_data_len());
+        let mut out = Vec::with_capacity(HEADER_LEN + data.len() + chunks * self.parity);
+        let mut header = vec![self.parity as u8];
+        header.extend_from_slice(&(data.len() as u64).to_be_bytes());
+        out.extend(ReedSolomon::new(HEADER_PARITY).encode(&header));
+        for chunk in data.chunks(self.max_data_len()) {
+            out.extend_from_slice(chunk);
+            out.extend(self.parity_of(chunk));
+        }
+        out
+    }
+
+    // Evaluations at the generator's roots; all zero for a codeword
+    fn syndromes(&self, codeword: &[u8]) -> Vec<u8> {
+        (0..self.parity).map(|i| {
+            let x = gf256::exp(i as i32);
+            codeword.iter().fold(0, |acc, &c| gf256::mul(acc, x) ^ c)
+        }).collect()
+    }
+
+}
+
+// Undo protect(), correcting what can be corrected. The parity count comes
+// from the header.
+pub fn recover(bytes: &[u8]) -> Result<Vec<u8>, RsError> {
+    recover_counting(bytes).map(|(data, _)| data)
+}
+
+// recover(), also giving the number of bytes corrected
+pub fn recover_counting(bytes: &[u8]) -> Result<(Vec<u8>, usize), RsError> {
+    let (parity, len, mut fixed) = read_header(bytes)?;
+    let rs = ReedSolomon::new(parity);
+    let body = &bytes[HEADER_LEN..];
+    let chunks = len.div_ceil(rs.max_data_len() as u64);
+    let expected = chunks.checked_mul(parity as u64).and_then(|p| p.checked_add(len)).ok_or(RsError::BadHeader)?;
+    if (body.len() as u64) < expected {
+        return Err(RsError::Truncated);
+    }
+    if body.len() as u64 > expected {
+        return Err(RsError::BadHeader);
+    }
+    let mut data = Vec::with_capacity(len as usize);
+    for codeword in body.chunks(255) {
+        let mut codeword = codeword.to_vec();
+        fixed += rs.decode(&mut codeword)?;
+        data.extend_from_slice(&codeword[..codeword.len() - parity]);
+    }
+    Ok((data, fixed))
+}
+
+// The parity count protect() was called with
+pub fn read_parity(bytes: &[u8]) -> Result<usize, RsError> {
+    read_header(bytes).map(|(parity, _, _)| parity)
+}
+
+// Parity count, data length and bytes corrected in the header
+fn read_header(bytes: &[u8]) -> Result<(usize, u64, usize), RsError> {
+    if bytes.len() < HEADER_LEN {
+        return Err(RsError::Truncated);
+    }
+    let mut header = bytes[..HEADER_LEN].to_vec();
+    let fixed = ReedSolomon::new(HEADER_PARITY).decode(&mut header)?;
+    let parity = header[0] as usize;
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&header[1..9]);
+    if parity == 0 || parity == 255 {
+        return Err(RsError::BadHeader);
+    }
+    Ok((parity, u64::from_be_bytes(len), fixed))
+}
+
+// Berlekamp-Massey: the shortest LFSR generating the syndromes, which is
+// the error locator. Lowest power first, constant term 1, no trailing zeros.
+fn berlekamp_massey(syndromes: &[u8]) -> Vec<u8> {
+    let mut locator = vec![1u8];
+    let mut prev = vec![1u8];
+    let mut degree = 0;
+    // steps since `prev` was last replaced, and its discrepancy then
+    let mut shift = 1;
+    let mut prev_discrepancy = 1u8;
+    for n in 0..syndromes.len() {
+        let mut d = syndromes[n];
+        for i in 1..=degree.min(locator.len() - 1) {
+            d ^= gf256::mul(locator[i], syndromes[n - i]);
+        }
+        if d == 0 {
+            shift += 1;
+            continue;
+        }
+        let scale = gf256::div(d, prev_discrepancy);
+        let mut next = locator.clone();
+        next.resize(next.len().max(prev.len() + shift), 0);
+        for (i, &p) in prev.iter().enumerate() {
+            next[i + shift] ^= gf256::mul(scale, p);
+        }
+        if 2 * degree <= n {
+            prev = std::mem::replace(&mut locator, next);
+            degree = n + 1 - degree;
+            prev_discrepancy = d;
+            shift = 1;
+        } else {
+            locator = next;
+            shift += 1;
+        }
+    }
+    locator.truncate(degree + 1);
+    while locator.len() > 1 && *locator.last().unwrap() == 0 {
+        locator.pop();
+    }
+    locator
+}
+
+// Evaluate a lowest-power-first polynomial
+fn eval_low(poly: &[u8], x: u8) -> u8 {
+    poly.iter().rev().fold(0, |acc, &c| gf256::mul(acc, x) ^ c)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{read_parity, recover, recover_counting, ReedSolomon, RsError};
+
+    #[test]
+    fn test_codeword() {
+        let rs = ReedSolomon::new(10);
+        let data: Vec<u8> = (0..200u32).map(|i| (i * 37 % 256) as u8).collect();
+        let codeword = rs.encode(&data);
+        assert_eq!(codeword.len(), 210);
+
+        // up to parity / 2 errors anywhere, data or parity
+        for errors in 0..=5 {
+            for start in [0usize, 3, 100, 199, 205].iter() {
+                let mut corrupt = codeword.clone();
+                for e in 0..errors {
+                    corrupt[(start + e * 41) % codeword.len()] ^= 0x5a + e as u8;
+                }
+                assert_eq!(rs.decode(&mut corrupt), Ok(errors));
+                assert_eq!(corrupt, codeword);
+            }
+        }
+
+        // a shortened codeword works the same way
+        let mut short = rs.encode(b"hi");
+        short[0] = b'H';
+        short[11] ^= 1;
+        assert_eq!(rs.decode(&mut short), Ok(2));
+        assert_eq!(&short[..2], b"hi");
+
+        // one too many is caught (or at least never silently accepted as
+        // the original)
+        let mut corrupt = codeword.clone();
+        for e in 0..6 {
+            corrupt[e * 30] ^= 0xff;
+        }
+        let before = corrupt.clone();
+        match rs.decode(&mut corrupt) {
+            Err(RsError::TooManyErrors) => assert_eq!(corrupt, before),
+            Ok(_) => assert_ne!(corrupt, codeword),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn test_protect() {
+        let data = b"the lazy dog slept through the whole thing".repeat(40);
+        let rs = ReedSolomon::new(16);
+        let protected = rs.protect(&data);
+        assert_eq!(recover(&protected).unwrap(), data);
+        assert_eq!(read_parity(&protected), Ok(16));
+        assert_eq!(recover(&ReedSolomon::new(2).protect(b"")).unwrap(), b"");
+
+        // a burst of 8 in every codeword, and some damage to the header
+        let mut damaged = protected.clone();
+        for start in (30..damaged.len()).step_by(255) {
+            for b in &mut damaged[start..(start + 8).min(protected.len())] {
+                *b = !*b;
+            }
+        }
+        damaged[0] ^= 1;
+        damaged[16] ^= 0x80;
+        let (recovered, fixed) = recover_counting(&damaged).unwrap();
+        assert_eq!(recovered, data);
+        assert!(fixed > 8 * 6);
+
+        assert_eq!(recover(&protected[..10]), Err(RsError::Truncated));
+        assert_eq!(recover(&protected[..protected.len() - 1]), Err(RsError::Truncated));
+        let mut longer = protected.clone();
+        longer.push(0);
+        assert_eq!(recover(&longer), Err(RsError::BadHeader));
+    }
+
+}
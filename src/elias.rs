@@ -0,0 +1,183 @@
+// Elias's universal codes for positive integers (Elias, "Universal
+// codeword sets and representations of the integers", 1975). None needs
+// to know the range of values in advance, and small values get short
+// codewords:
+//
+// - gamma: floor(log2 n) zeros, then n in binary. 2 log2 n + 1 bits.
+// - delta: gamma code of the bit length of n, then n in binary without
+//   its leading one. Shorter than gamma from n = 32 on.
+// - omega: n in binary, preceded recursively by the omega code of its
+//   length minus one, ending in a zero. Shortest of the three for big n.
+//
+// Zero has no codeword; code n + 1 to include it.
+
+use std::io::{self, Read, Write};
+
+use crate::bitio::{self, BitReader, BitWriter};
+use crate::codes::integers;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Elias {
+    Gamma,
+    Delta,
+    Omega,
+}
+
+impl Elias {
+
+    // Codeword length of `n` in bits; panics if n is zero
+    pub fn len(self, n: u64) -> u32 {
+        assert!(n > 0, "Elias codes start at 1");
+        let bits = bit_length(n);
+        match self {
+            Elias::Gamma => 2 * bits - 1,
+            Elias::Delta => Elias::Gamma.len(bits as u64) + bits - 1,
+            Elias::Omega => {
+                let mut len = 1;
+                let mut n = n;
+                while n > 1 {
+                    len += bit_length(n);
+                    n = bit_length(n) as u64 - 1;
+                }
+                len
+            }
+        }
+    }
+
+    // Fails with InvalidInput for zero
+    pub fn write<W: Write>(self, n: u64, w: &mut BitWriter<W>) -> io::Result<()> {
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Elias codes start at 1"));
+        }
+        let bits = bit_length(n);
+        match self {
+            // order-0 Exp-Golomb counts from zero
+            Elias::Gamma => integers::write_exp_golomb(w, n - 1, 0),
+            Elias::Delta => {
+                Elias::Gamma.write(bits as u64, w)?;
+                w.write_bits(n, bits - 1)
+            }
+            Elias::Omega => {
+                let mut groups = Vec::new();
+                let mut n = n;
+                while n > 1 {
+                    groups.push(n);
+                    n = bit_length(n) as u64 - 1;
+                }
+                for &g in groups.iter().rev() {
+                    w.write_bits(g, bit_length(g))?;
+                }
+                w.write_bit(false)
+            }
+        }
+    }
+
+    // Fails with InvalidData for codewords of values beyond u64
+    pub fn read<R: Read>(self, r: &mut BitReader<R>) -> io::Result<u64> {
+        let too_big = || io::Error::new(io::ErrorKind::InvalidData, "Elias codeword overflows u64");
+        match self {
+            Elias::Gamma => Ok(integers::read_exp_golomb(r, 0)? + 1),
+            Elias::Delta => {
+                let bits = Elias::Gamma.read(r)?;
+                if bits > 64 {
+                    return Err(too_big());
+                }
+                let rest = bits as u32 - 1;
+                Ok(1 << rest | r.read_bits(rest)?)
+            }
+            Elias::Omega => {
+                let mut n = 1u64;
+                while r.read_bit()? {
+                    if n > 63 {
+                        return Err(too_big());
+                    }
+                    n = 1 << n | r.read_bits(n as u32)?;
+                }
+                Ok(n)
+            }
+        }
+    }
+
+    // Packed MSB-first, with the number of bits used; panics on zero
+    pub fn encode<I: IntoIterator<Item=u64>>(self, values: I) -> (Vec<u8>, usize) {
+        bitio::pack(|w| {
+            for n in values {
+                assert!(n > 0, "Elias codes start at 1");
+                self.write(n, w)?;
+            }
+            Ok(())
+        })
+    }
+
+    // Every value in the first `nbits` bits of `bytes`. A codeword cut
+    // short by the end is an UnexpectedEof error.
+    pub fn decode(self, bytes: &[u8], nbits: usize) -> io::Result<Vec<u64>> {
+        let mut r = BitReader::new(bytes);
+        let mut values = Vec::new();
+        while (r.bits_read() as usize) < nbits {
+            values.push(self.read(&mut r)?);
+            if r.bits_read() as usize > nbits {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "codeword runs past the end"));
+            }
+        }
+        Ok(values)
+    }
+
+}
+
+fn bit_length(n: u64) -> u32 {
+    64 - n.leading_zeros()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::Elias;
+    use crate::bitio::{self, BitWriter};
+    use std::io;
+
+    fn codeword(code: Elias, n: u64) -> String {
+        let (bytes, nbits) = code.encode(vec![n]);
+        bitio::unpack(&bytes, nbits).map(|b| if b { '1' } else { '0' }).collect()
+    }
+
+    #[test]
+    fn test_codewords() {
+        let gamma = [(1, "1"), (2, "010"), (3, "011"), (4, "00100"), (9, "0001001")];
+        let delta = [(1, "1"), (2, "0100"), (3, "0101"), (4, "01100"), (17, "001010001")];
+        let omega = [(1, "0"), (2, "100"), (3, "110"), (4, "101000"), (16, "10100100000"),
+                     (100, "1011011001000")];
+        for (code, table) in [(Elias::Gamma, &gamma[..]), (Elias::Delta, &delta[..]), (Elias::Omega, &omega[..])] {
+            for &(n, expected) in table {
+                assert_eq!(codeword(code, n), expected);
+                assert_eq!(code.len(n) as usize, expected.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let values: Vec<u64> = (1..1000).chain([1 << 32, u64::MAX - 1, u64::MAX]).collect();
+        for code in [Elias::Gamma, Elias::Delta, Elias::Omega] {
+            let (bytes, nbits) = code.encode(values.iter().cloned());
+            assert_eq!(nbits as u64, values.iter().map(|&n| code.len(n) as u64).sum::<u64>());
+            assert_eq!(code.decode(&bytes, nbits).unwrap(), values);
+        }
+        assert!(Elias::Delta.len(1 << 20) < Elias::Gamma.len(1 << 20));
+        assert!(Elias::Omega.len(1 << 15) < Elias::Delta.len(1 << 15));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut w = BitWriter::new(Vec::new());
+        assert_eq!(Elias::Gamma.write(0, &mut w).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        // gamma of 4 with its last bit cut off
+        let err = Elias::Gamma.decode(&[0b0010_0000], 4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        // 64 leading zeros can't be followed by anything that fits
+        let err = Elias::Gamma.decode(&[0; 9], 72).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+}
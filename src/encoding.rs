@@ -0,0 +1,577 @@
+// Binary-to-text encodings, for getting compressed output through channels
+// that only carry text (JSON, email, URLs, terminals):
+//
+// - Base64, Base32 and Base16 as in RFC 4648, along with the URL-safe
+//   Base64 alphabet and Base32's "extended hex" one
+// - Ascii85 (btoa, Adobe): four bytes in five characters, and 'z' for four
+//   zero bytes; bare, without Adobe's <~ ~> delimiters
+//
+// Encoders pad with '=' where RFC 4648 does, except Base64Url, which is
+// usually used without. Decoders skip ASCII whitespace (the line breaks of
+// MIME or PEM), take padding or its absence, read Base32 and Base16 in
+// either case, and refuse anything else that isn't the canonical encoding
+// of some bytes: stray characters, padding in the middle, set bits past
+// the last byte.
+//
+// EncodeWriter and DecodeReader do the same a chunk at a time.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64_URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const BASE32_HEX: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+const BASE16: &[u8; 16] = b"0123456789ABCDEF";
+// Ascii85 digits are '!' + 0..85, not a lookup
+const ASCII85_FIRST: u8 = b'!';
+
+// How much DecodeReader asks of its reader at a time
+const CHUNK: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Base64,
+    Base64Url,
+    Base32,
+    Base32Hex,
+    Base16,
+    Ascii85,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodingError {
+    // A byte outside the alphabet or out of place; `pos` counts bytes from
+    // the start of the text
+    InvalidChar { byte: u8, pos: usize },
+    // '=' where there can't be padding, or the wrong amount of it
+    BadPadding { pos: usize },
+    // The text stops partway through a group
+    Truncated,
+    // An Ascii85 group over 2^32 - 1, ending at `pos`
+    Overflow { pos: usize },
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodingError::InvalidChar { byte, pos } => {
+                write!(f, "invalid character '{}' at position {}", byte.escape_ascii(), pos)
+            }
+            EncodingError::BadPadding { pos } => write!(f, "bad padding at position {}", pos),
+            EncodingError::Truncated => write!(f, "encoded text is truncated"),
+            EncodingError::Overflow { pos } => write!(f, "Ascii85 group ending at position {} overflows", pos),
+        }
+    }
+}
+
+impl Error for EncodingError {}
+
+impl From<EncodingError> for io::Error {
+    fn from(e: EncodingError) -> Self {
+        match e {
+            EncodingError::Truncated => io::Error::new(io::ErrorKind::UnexpectedEof, e),
+            _ => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+impl Encoding {
+
+    pub const ALL: [Encoding; 6] = [Encoding::Base64, Encoding::Base64Url, Encoding::Base32, Encoding::Base32Hex,
+                                    Encoding::Base16, Encoding::Ascii85];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Base64 => "base64",
+            Encoding::Base64Url => "base64url",
+            Encoding::Base32 => "base32",
+            Encoding::Base32Hex => "base32hex",
+            Encoding::Base16 => "base16",
+            Encoding::Ascii85 => "ascii85",
+        }
+    }
+
+    // Characters encode() produces for `n` bytes; for Ascii85 an upper
+    // bound, as runs of zeros come out shorter
+    pub fn encoded_len(self, n: usize) -> usize {
+        let (bytes, chars) = self.group();
+        n / bytes * chars + match n % bytes {
+            0 => 0,
+            _ if self.pads() => chars,
+            rest => self.partial_chars(rest),
+        }
+    }
+
+    pub fn encode(self, data: &[u8]) -> String {
+        let mut out = Vec::with_capacity(self.encoded_len(data.len()));
+        for chunk in data.chunks(self.group().0) {
+            self.encode_group(chunk, &mut out);
+        }
+        String::from_utf8(out).expect("alphabets are ASCII")
+    }
+
+    pub fn decode<T: AsRef<[u8]>>(self, text: T) -> Result<Vec<u8>, EncodingError> {
+        let mut decoder = Decoder::new(self);
+        let mut out = Vec::with_capacity(text.as_ref().len() / self.group().1 * self.group().0);
+        for &c in text.as_ref() {
+            decoder.push(c, &mut out)?;
+        }
+        decoder.finish(&mut out)?;
+        Ok(out)
+    }
+
+    fn alphabet(self) -> &'static [u8] {
+        match self {
+            Encoding::Base64 => BASE64,
+            Encoding::Base64Url => BASE64_URL,
+            Encoding::Base32 => BASE32,
+            Encoding::Base32Hex => BASE32_HEX,
+            Encoding::Base16 => BASE16,
+            Encoding::Ascii85 => &[],
+        }
+    }
+
+    // Bytes in and characters out per group
+    fn group(self) -> (usize, usize) {
+        match self {
+            Encoding::Base64 | Encoding::Base64Url => (3, 4),
+            Encoding::Base32 | Encoding::Base32Hex => (5, 8),
+            Encoding::Base16 => (1, 2),
+            Encoding::Ascii85 => (4, 5),
+        }
+    }
+
+    fn pads(self) -> bool {
+        matches!(self, Encoding::Base64 | Encoding::Base32 | Encoding::Base32Hex)
+    }
+
+    // Bits per character, for the power-of-two bases
+    fn bits(self) -> usize {
+        self.alphabet().len().trailing_zeros() as usize
+    }
+
+    // Characters for the `n` bytes of a last, partial group, unpadded
+    fn partial_chars(self, n: usize) -> usize {
+        match self {
+            Encoding::Ascii85 => n + 1,
+            _ => (8 * n).div_ceil(self.bits()),
+        }
+    }
+
+    // `chunk` is a whole group, or the last one
+    fn encode_group(self, chunk: &[u8], out: &mut Vec<u8>) {
+        let (bytes, chars) = self.group();
+        if self == Encoding::Ascii85 {
+            if *chunk == [0u8; 4] {
+                out.push(b'z');
+                return;
+            }
+            let mut word = [0u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            let mut v = u32::from_be_bytes(word);
+            let mut digits = [0u8; 5];
+            for d in digits.iter_mut().rev() {
+                *d = ASCII85_FIRST + (v % 85) as u8;
+                v /= 85;
+            }
+            out.extend_from_slice(&digits[..chunk.len() + 1]);
+            return;
+        }
+        let k = self.bits();
+        // the group left-aligned in its bits, zeros after a short chunk
+        let acc = chunk.iter().fold(0u64, |acc, &b| acc << 8 | b as u64) << (8 * (bytes - chunk.len()));
+        let alphabet = self.alphabet();
+        let n = self.partial_chars(chunk.len());
+        for i in 1..=n {
+            out.push(alphabet[(acc >> (8 * bytes - k * i)) as usize & (alphabet.len() - 1)]);
+        }
+        if self.pads() {
+            out.resize(out.len() + chars - n, b'=');
+        }
+    }
+
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Decoding a character at a time, which is what lets DecodeReader take its
+// input in arbitrary pieces
+struct Decoder {
+    encoding: Encoding,
+    values: [u8; 256], // 0xff outside the alphabet
+    group: [u8; 8],
+    n: usize,
+    padding: usize,
+    ended: bool, // padding has closed the text
+    pos: usize,  // of the next character
+    last: (u8, usize), // the group's last character and its position
+}
+
+impl Decoder {
+
+    fn new(encoding: Encoding) -> Self {
+        let mut values = [0xff; 256];
+        if encoding == Encoding::Ascii85 {
+            for v in 0..85 {
+                values[(ASCII85_FIRST + v) as usize] = v;
+            }
+        }
+        let any_case = matches!(encoding, Encoding::Base32 | Encoding::Base32Hex | Encoding::Base16);
+        for (v, &c) in encoding.alphabet().iter().enumerate() {
+            values[c as usize] = v as u8;
+            if any_case {
+                values[c.to_ascii_lowercase() as usize] = v as u8;
+            }
+        }
+        Decoder { encoding, values, group: [0; 8], n: 0, padding: 0, ended: false, pos: 0, last: (0, 0) }
+    }
+
+    fn push(&mut self, c: u8, out: &mut Vec<u8>) -> Result<(), EncodingError> {
+        let pos = self.pos;
+        self.pos += 1;
+        if c.is_ascii_whitespace() {
+            return Ok(());
+        }
+        let chars = self.encoding.group().1;
+        let pads = matches!(self.encoding, Encoding::Base64 | Encoding::Base64Url | Encoding::Base32 |
+                                           Encoding::Base32Hex);
+        if c == b'=' && pads {
+            // padding fills out a partial group, and nothing comes after it
+            if self.n == 0 || self.ended {
+                return Err(EncodingError::BadPadding { pos });
+            }
+            self.padding += 1;
+            if self.n + self.padding == chars {
+                self.flush(out)?;
+                self.ended = true;
+            }
+            return Ok(());
+        }
+        if self.padding > 0 || self.ended {
+            return Err(EncodingError::InvalidChar { byte: c, pos });
+        }
+        if c == b'z' && self.encoding == Encoding::Ascii85 && self.n == 0 {
+            out.extend_from_slice(&[0; 4]);
+            return Ok(());
+        }
+        let v = self.values[c as usize];
+        if v == 0xff {
+            return Err(EncodingError::InvalidChar { byte: c, pos });
+        }
+        self.group[self.n] = v;
+        self.n += 1;
+        self.last = (c, pos);
+        if self.n == chars {
+            self.flush(out)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> Result<(), EncodingError> {
+        if self.padding > 0 {
+            return Err(EncodingError::BadPadding { pos: self.pos });
+        }
+        self.flush(out)
+    }
+
+    // Decode the group so far, whole or the last one
+    fn flush(&mut self, out: &mut Vec<u8>) -> Result<(), EncodingError> {
+        let n = self.n;
+        self.n = 0;
+        self.padding = 0;
+        if n == 0 {
+            return Ok(());
+        }
+        if self.encoding == Encoding::Ascii85 {
+            if n == 1 {
+                return Err(EncodingError::Truncated);
+            }
+            // a short group was cut from one padded with zero bytes; the
+            // highest digits round it back up
+            let v = (0..5).fold(0u64, |v, i| v * 85 + if i < n { self.group[i] } else { 84 } as u64);
+            if v > u32::MAX as u64 {
+                return Err(EncodingError::Overflow { pos: self.last.1 });
+            }
+            out.extend_from_slice(&(v as u32).to_be_bytes()[..n - 1]);
+            return Ok(());
+        }
+        let k = self.encoding.bits();
+        let spare = n * k % 8;
+        if spare >= k {
+            // a whole character more than the bytes before it need
+            return Err(EncodingError::Truncated);
+        }
+        let acc = self.group[..n].iter().fold(0u64, |acc, &v| acc << k | v as u64);
+        if acc & ((1 << spare) - 1) != 0 {
+            let (byte, pos) = self.last;
+            return Err(EncodingError::InvalidChar { byte, pos });
+        }
+        let acc = acc >> spare;
+        for i in (0..n * k / 8).rev() {
+            out.push((acc >> (8 * i)) as u8);
+        }
+        Ok(())
+    }
+
+}
+
+// Writes the encoding of whatever is written to it to `inner`, a group at
+// a time
+pub struct EncodeWriter<W: Write> {
+    // Only None after finish() has handed the writer back
+    inner: Option<W>,
+    encoding: Encoding,
+    // bytes short of a whole group
+    pending: Vec<u8>,
+    finished: bool,
+}
+
+impl<W: Write> EncodeWriter<W> {
+
+    pub fn new(inner: W, encoding: Encoding) -> Self {
+        EncodeWriter { inner: Some(inner), encoding, pending: Vec::new(), finished: false }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    // Write out the last, partial group with its padding; later writes are
+    // an error. Dropping the writer does this too, ignoring errors.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        let mut out = Vec::new();
+        if !self.pending.is_empty() {
+            self.encoding.encode_group(&self.pending, &mut out);
+        }
+        let inner = self.get_mut();
+        inner.write_all(&out)?;
+        inner.flush()
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+}
+
+impl<W: Write> Write for EncodeWriter<W> {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other("write after finish"));
+        }
+        let bytes = self.encoding.group().0;
+        let mut out = Vec::with_capacity(self.encoding.encoded_len(buf.len() + bytes));
+        let mut rest = buf;
+        if !self.pending.is_empty() {
+            let take = (bytes - self.pending.len()).min(rest.len());
+            self.pending.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+            if self.pending.len() < bytes {
+                return Ok(buf.len());
+            }
+            self.encoding.encode_group(&self.pending, &mut out);
+            self.pending.clear();
+        }
+        let whole = rest.len() / bytes * bytes;
+        for chunk in rest[..whole].chunks(bytes) {
+            self.encoding.encode_group(chunk, &mut out);
+        }
+        self.pending.extend_from_slice(&rest[whole..]);
+        self.get_mut().write_all(&out)?;
+        Ok(buf.len())
+    }
+
+    // A partial group stays buffered until it fills or the writer finishes
+    fn flush(&mut self) -> io::Result<()> {
+        self.get_mut().flush()
+    }
+
+}
+
+impl<W: Write> Drop for EncodeWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+// Reads the bytes that the text from `inner` encodes
+pub struct DecodeReader<R: Read> {
+    inner: R,
+    decoder: Decoder,
+    // decoded but not yet read, from `start` on
+    out: Vec<u8>,
+    start: usize,
+    eof: bool,
+}
+
+impl<R: Read> DecodeReader<R> {
+
+    pub fn new(inner: R, encoding: Encoding) -> Self {
+        DecodeReader { inner, decoder: Decoder::new(encoding), out: Vec::new(), start: 0, eof: false }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.decoder.encoding
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    // Text already pulled from the reader is lost
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+}
+
+impl<R: Read> Read for DecodeReader<R> {
+
+    // Text that isn't a valid encoding fails with InvalidData, or
+    // UnexpectedEof if it stops partway through a group
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut chunk = [0u8; CHUNK];
+        loop {
+            if self.start < self.out.len() || buf.is_empty() {
+                let n = buf.len().min(self.out.len() - self.start);
+                buf[..n].copy_from_slice(&self.out[self.start..self.start + n]);
+                self.start += n;
+                return Ok(n);
+            }
+            if self.eof {
+                return Ok(0);
+            }
+            self.out.clear();
+            self.start = 0;
+            match self.inner.read(&mut chunk) {
+                Ok(0) => {
+                    self.eof = true;
+                    self.decoder.finish(&mut self.out)?;
+                }
+                Ok(n) => {
+                    for &c in &chunk[..n] {
+                        self.decoder.push(c, &mut self.out)?;
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{DecodeReader, EncodeWriter, Encoding, EncodingError};
+    use std::io::{self, Read, Write};
+
+    #[test]
+    fn test_vectors() {
+        // RFC 4648, section 10
+        let inputs = ["", "f", "fo", "foo", "foob", "fooba", "foobar"];
+        let expected = [
+            (Encoding::Base64, ["", "Zg==", "Zm8=", "Zm9v", "Zm9vYg==", "Zm9vYmE=", "Zm9vYmFy"]),
+            (Encoding::Base32, ["", "MY======", "MZXQ====", "MZXW6===", "MZXW6YQ=", "MZXW6YTB", "MZXW6YTBOI======"]),
+            (Encoding::Base32Hex, ["", "CO======", "CPNG====", "CPNMU===", "CPNMUOG=", "CPNMUOJ1", "CPNMUOJ1E8======"]),
+            (Encoding::Base16, ["", "66", "666F", "666F6F", "666F6F62", "666F6F6261", "666F6F626172"]),
+        ];
+        for (encoding, texts) in expected.iter() {
+            for (input, text) in inputs.iter().zip(texts.iter()) {
+                assert_eq!(encoding.encode(input.as_bytes()), *text);
+                assert_eq!(encoding.decode(text).unwrap(), input.as_bytes());
+                assert_eq!(encoding.encoded_len(input.len()), text.len());
+            }
+        }
+        assert_eq!(Encoding::Ascii85.encode(b"Man sure."), "9jqo^F*2M7/c");
+        assert_eq!(Encoding::Ascii85.encode(&[0, 0, 0, 0, 0]), "z!!");
+        assert_eq!(Encoding::Base64Url.encode(&[0xfb, 0xff]), "-_8");
+
+        // lenient where it costs nothing
+        assert_eq!(Encoding::Base64.decode("Zm9v\r\nYmE").unwrap(), b"fooba");
+        assert_eq!(Encoding::Base32.decode("mzxw6ytb").unwrap(), b"fooba");
+        assert_eq!(Encoding::Base64Url.decode("-_8=").unwrap(), [0xfb, 0xff]);
+
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * i % 251) as u8).chain(vec![0; 9]).collect();
+        for &encoding in Encoding::ALL.iter() {
+            for len in [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 100, data.len()] {
+                let text = encoding.encode(&data[..len]);
+                assert!(text.len() <= encoding.encoded_len(len));
+                assert_eq!(encoding.decode(&text).unwrap(), &data[..len], "{} of {} bytes", encoding, len);
+            }
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let base64 = Encoding::Base64;
+        assert_eq!(base64.decode("Zm9v!"), Err(EncodingError::InvalidChar { byte: b'!', pos: 4 }));
+        assert_eq!(base64.decode("Zg="), Err(EncodingError::BadPadding { pos: 3 }));
+        assert_eq!(base64.decode("=Zg"), Err(EncodingError::BadPadding { pos: 0 }));
+        assert_eq!(base64.decode("Z"), Err(EncodingError::Truncated));
+        // bits set past the last byte
+        assert_eq!(base64.decode("Zh=="), Err(EncodingError::InvalidChar { byte: b'h', pos: 1 }));
+        assert_eq!(base64.decode("Zg==Zg=="), Err(EncodingError::InvalidChar { byte: b'Z', pos: 4 }));
+        assert_eq!(Encoding::Base16.decode("6"), Err(EncodingError::Truncated));
+        assert_eq!(Encoding::Base16.decode("66="), Err(EncodingError::InvalidChar { byte: b'=', pos: 2 }));
+        assert_eq!(Encoding::Ascii85.decode("s8W-\""), Err(EncodingError::Overflow { pos: 4 }));
+        assert_eq!(Encoding::Ascii85.decode("9jzqo^"), Err(EncodingError::InvalidChar { byte: b'z', pos: 2 }));
+        let err: io::Error = EncodingError::Truncated.into();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_streaming() {
+        let data: Vec<u8> = (0..5000u32).map(|i| (i * 31 % 256) as u8).collect();
+        for &encoding in Encoding::ALL.iter() {
+            let mut writer = EncodeWriter::new(Vec::new(), encoding);
+            for piece in data.chunks(7) {
+                writer.write_all(piece).unwrap();
+            }
+            let text = writer.finish().unwrap();
+            assert_eq!(text, encoding.encode(&data).into_bytes());
+
+            let mut reader = DecodeReader::new(&text[..], encoding);
+            let mut back = Vec::new();
+            let mut buf = [0u8; 13];
+            loop {
+                match reader.read(&mut buf).unwrap() {
+                    0 => break,
+                    n => back.extend_from_slice(&buf[..n]),
+                }
+            }
+            assert_eq!(back, data);
+        }
+        let mut reader = DecodeReader::new(&b"Zm9vY"[..], Encoding::Base64);
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+}
@@ -0,0 +1,514 @@
+// The crate-wide error type. Each module keeps its own error enum with the
+// exact reason; all of them convert into Error, so code that strings
+// several modules together can return entrust::Result and use `?`. The
+// module error, where there is one, stays reachable through source().
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+use crate::archive::ArchiveError;
+use crate::arithmetic::ArithError;
+use crate::bwt::BwtError;
+use crate::codes::varint::VarintError;
+use crate::coder::CoderError;
+use crate::container::ContainerError;
+use crate::deflate::DeflateError;
+use crate::ecc::convolutional::ConvError;
+use crate::encoding::EncodingError;
+use crate::eval::EvalError;
+use crate::ecc::hamming::HammingError;
+use crate::ecc::reed_solomon::RsError;
+use crate::frequency::FrequencyError;
+use crate::fse::FseError;
+use crate::gzip::GzError;
+use crate::huffman::{BuildError, DecodeError, EncodeError, TableError};
+use crate::interop::InteropError;
+use crate::limits::LimitExceeded;
+use crate::lz77::Lz77Error;
+use crate::lzss::LzssError;
+use crate::numeric::NumericError;
+use crate::pipeline::PipelineError;
+use crate::ppm::PpmError;
+use crate::probability::ProbError;
+use crate::progress::Cancelled;
+use crate::rans::RansError;
+use crate::rle::RleError;
+use crate::stream::StreamError;
+use crate::tokenize::TokenError;
+use crate::zlib::ZlibError;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+    // The input symbol at `pos` has no codeword
+    UnknownSymbol { pos: usize },
+    // Nothing to build a code from
+    EmptyAlphabet,
+    // An argument out of range, e.g. a codeword length limit too small
+    // for the alphabet
+    InvalidParameter(&'static str),
+    // Compressed data ends early
+    TruncatedStream,
+    // A code or frequency table that can't be used
+    InvalidTable(Box<dyn StdError + Send + Sync>),
+    // Compressed data that doesn't decode
+    InvalidData(Box<dyn StdError + Send + Sync>),
+    ChecksumMismatch,
+    // A container or table format version this build doesn't know
+    UnsupportedVersion(u8),
+    // A container codec id this build doesn't know
+    UnsupportedCodec(u8),
+    // A checksum id this build doesn't know
+    UnsupportedChecksum(u8),
+    // Stopped through a cancellation flag (progress.rs)
+    Cancelled,
+    // Decoding would go past a DecodeOptions limit (limits.rs)
+    LimitExceeded(LimitExceeded),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownSymbol { pos } => write!(f, "no codeword for the symbol at position {}", pos),
+            Error::EmptyAlphabet => write!(f, "can't build a code over an empty alphabet"),
+            Error::InvalidParameter(what) => write!(f, "invalid parameter: {}", what),
+            Error::TruncatedStream => write!(f, "compressed data is truncated"),
+            Error::InvalidTable(e) => write!(f, "invalid table: {}", e),
+            Error::InvalidData(e) => write!(f, "invalid compressed data: {}", e),
+            Error::ChecksumMismatch => write!(f, "checksum mismatch"),
+            Error::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+            Error::UnsupportedCodec(id) => write!(f, "unsupported codec id {}", id),
+            Error::UnsupportedChecksum(id) => write!(f, "unsupported checksum id {}", id),
+            Error::Cancelled => write!(f, "operation cancelled"),
+            Error::LimitExceeded(e) => write!(f, "{}", e),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::InvalidTable(e) | Error::InvalidData(e) => Some(e.as_ref()),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+
+    fn data<E: StdError + Send + Sync + 'static>(e: E) -> Self {
+        Error::InvalidData(Box::new(e))
+    }
+
+}
+
+// Errors from the file functions carry a ContainerError inside; that comes
+// back out as its own variant. A bare UnexpectedEof is a truncated stream.
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        if e.get_ref().is_some_and(|inner| inner.is::<ContainerError>()) {
+            let inner = e.into_inner().unwrap().downcast::<ContainerError>().unwrap();
+            return (*inner).into();
+        }
+        if e.get_ref().is_some_and(|inner| inner.is::<ArchiveError>()) {
+            let inner = e.into_inner().unwrap().downcast::<ArchiveError>().unwrap();
+            return (*inner).into();
+        }
+        if e.get_ref().is_some_and(|inner| inner.is::<GzError>()) {
+            let inner = e.into_inner().unwrap().downcast::<GzError>().unwrap();
+            return (*inner).into();
+        }
+        if e.get_ref().is_some_and(|inner| inner.is::<StreamError>()) {
+            let inner = e.into_inner().unwrap().downcast::<StreamError>().unwrap();
+            return (*inner).into();
+        }
+        if e.get_ref().is_some_and(|inner| inner.is::<FrequencyError>()) {
+            let inner = e.into_inner().unwrap().downcast::<FrequencyError>().unwrap();
+            return (*inner).into();
+        }
+        if e.get_ref().is_some_and(|inner| inner.is::<Cancelled>()) {
+            return Error::Cancelled;
+        }
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => Error::TruncatedStream,
+            _ => Error::Io(e),
+        }
+    }
+}
+
+impl<S> From<EncodeError<S>> for Error {
+    fn from(e: EncodeError<S>) -> Self {
+        Error::UnknownSymbol { pos: e.pos }
+    }
+}
+
+impl From<BuildError> for Error {
+    fn from(e: BuildError) -> Self {
+        match e {
+            BuildError::EmptyAlphabet => Error::EmptyAlphabet,
+            BuildError::MaxLengthTooSmall => {
+                Error::InvalidParameter("maximum codeword length too small for the alphabet")
+            }
+        }
+    }
+}
+
+impl From<TableError> for Error {
+    fn from(e: TableError) -> Self {
+        match e {
+            TableError::Truncated => Error::TruncatedStream,
+            TableError::UnsupportedVersion(v) => Error::UnsupportedVersion(v),
+            TableError::ChecksumMismatch => Error::ChecksumMismatch,
+            TableError::LimitExceeded(e) => Error::LimitExceeded(e),
+            e => Error::InvalidTable(Box::new(e)),
+        }
+    }
+}
+
+impl From<FrequencyError> for Error {
+    fn from(e: FrequencyError) -> Self {
+        match e {
+            FrequencyError::Truncated => Error::TruncatedStream,
+            FrequencyError::UnsupportedVersion(v) => Error::UnsupportedVersion(v),
+            FrequencyError::ChecksumMismatch => Error::ChecksumMismatch,
+            e => Error::InvalidTable(Box::new(e)),
+        }
+    }
+}
+
+impl From<ProbError> for Error {
+    fn from(e: ProbError) -> Self {
+        match e {
+            ProbError::Empty => Error::EmptyAlphabet,
+            ProbError::TooManySymbols { .. } => Error::InvalidParameter("precision too low for the alphabet"),
+            ProbError::BadPrecision(_) => Error::InvalidParameter("precision over the maximum"),
+        }
+    }
+}
+
+impl From<InteropError> for Error {
+    fn from(e: InteropError) -> Self {
+        match e {
+            InteropError::Truncated => Error::TruncatedStream,
+            InteropError::Table(e) => e.into(),
+            e => Error::InvalidTable(Box::new(e)),
+        }
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        match e {
+            DecodeError::TruncatedCodeword { .. } => Error::TruncatedStream,
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<ContainerError> for Error {
+    fn from(e: ContainerError) -> Self {
+        match e {
+            ContainerError::UnsupportedVersion(v) => Error::UnsupportedVersion(v),
+            ContainerError::UnknownCodec(id) => Error::UnsupportedCodec(id),
+            ContainerError::UnknownChecksum(id) => Error::UnsupportedChecksum(id),
+            ContainerError::Truncated => Error::TruncatedStream,
+            ContainerError::Table(e) => e.into(),
+            ContainerError::ChecksumMismatch => Error::ChecksumMismatch,
+            ContainerError::Ecc(e) => e.into(),
+            ContainerError::LimitExceeded(e) => Error::LimitExceeded(e),
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<CoderError> for Error {
+    fn from(e: CoderError) -> Self {
+        match e {
+            CoderError::Encode(e) => e.into(),
+            CoderError::Build(e) => e.into(),
+            CoderError::BufferTooSmall => Error::InvalidParameter("output buffer too small"),
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<DeflateError> for Error {
+    fn from(e: DeflateError) -> Self {
+        match e {
+            DeflateError::Truncated => Error::TruncatedStream,
+            DeflateError::LimitExceeded(e) => Error::LimitExceeded(e),
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<GzError> for Error {
+    fn from(e: GzError) -> Self {
+        match e {
+            GzError::Truncated => Error::TruncatedStream,
+            GzError::Deflate(DeflateError::LimitExceeded(e)) => Error::LimitExceeded(e),
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<RsError> for Error {
+    fn from(e: RsError) -> Self {
+        match e {
+            RsError::Truncated => Error::TruncatedStream,
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<ConvError> for Error {
+    fn from(e: ConvError) -> Self {
+        match e {
+            ConvError::Truncated => Error::TruncatedStream,
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<HammingError> for Error {
+    fn from(e: HammingError) -> Self {
+        match e {
+            HammingError::Truncated => Error::TruncatedStream,
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<StreamError> for Error {
+    fn from(e: StreamError) -> Self {
+        match e {
+            StreamError::UnknownSymbol { pos } => Error::UnknownSymbol { pos },
+            StreamError::Finished => Error::InvalidParameter("input after the stream was finished"),
+            StreamError::Truncated => Error::TruncatedStream,
+            StreamError::LimitExceeded(e) => Error::LimitExceeded(e),
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<LimitExceeded> for Error {
+    fn from(e: LimitExceeded) -> Self {
+        Error::LimitExceeded(e)
+    }
+}
+
+impl From<Cancelled> for Error {
+    fn from(_: Cancelled) -> Self {
+        Error::Cancelled
+    }
+}
+
+impl From<ArchiveError> for Error {
+    fn from(e: ArchiveError) -> Self {
+        match e {
+            ArchiveError::Container(e) => e.into(),
+            ArchiveError::Truncated => Error::TruncatedStream,
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<EvalError> for Error {
+    fn from(e: EvalError) -> Self {
+        match e {
+            EvalError::Io(e) => e.into(),
+        }
+    }
+}
+
+impl From<ZlibError> for Error {
+    fn from(e: ZlibError) -> Self {
+        match e {
+            ZlibError::Truncated => Error::TruncatedStream,
+            ZlibError::Deflate(DeflateError::LimitExceeded(e)) => Error::LimitExceeded(e),
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<RansError> for Error {
+    fn from(e: RansError) -> Self {
+        match e {
+            RansError::Truncated => Error::TruncatedStream,
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<ArithError> for Error {
+    fn from(e: ArithError) -> Self {
+        match e {
+            ArithError::Truncated => Error::TruncatedStream,
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<PpmError> for Error {
+    fn from(e: PpmError) -> Self {
+        match e {
+            PpmError::Truncated => Error::TruncatedStream,
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<NumericError> for Error {
+    fn from(e: NumericError) -> Self {
+        match e {
+            NumericError::Truncated => Error::TruncatedStream,
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<VarintError> for Error {
+    fn from(e: VarintError) -> Self {
+        match e {
+            VarintError::Truncated => Error::TruncatedStream,
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<EncodingError> for Error {
+    fn from(e: EncodingError) -> Self {
+        match e {
+            EncodingError::Truncated => Error::TruncatedStream,
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<FseError> for Error {
+    fn from(e: FseError) -> Self {
+        match e {
+            FseError::Truncated => Error::TruncatedStream,
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<Lz77Error> for Error {
+    fn from(e: Lz77Error) -> Self {
+        match e {
+            Lz77Error::Truncated => Error::TruncatedStream,
+            Lz77Error::LimitExceeded(e) => Error::LimitExceeded(e),
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<LzssError> for Error {
+    fn from(e: LzssError) -> Self {
+        match e {
+            LzssError::Truncated => Error::TruncatedStream,
+            LzssError::LimitExceeded(e) => Error::LimitExceeded(e),
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<RleError> for Error {
+    fn from(e: RleError) -> Self {
+        match e {
+            RleError::Truncated => Error::TruncatedStream,
+            RleError::LimitExceeded(e) => Error::LimitExceeded(e),
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<BwtError> for Error {
+    fn from(e: BwtError) -> Self {
+        Error::data(e)
+    }
+}
+
+impl From<PipelineError> for Error {
+    fn from(e: PipelineError) -> Self {
+        match e {
+            PipelineError::Truncated => Error::TruncatedStream,
+            PipelineError::LimitExceeded(e) => Error::LimitExceeded(e),
+            e => Error::data(e),
+        }
+    }
+}
+
+impl From<TokenError> for Error {
+    fn from(e: TokenError) -> Self {
+        match e {
+            TokenError::Truncated => Error::TruncatedStream,
+            TokenError::Table(e) => e.into(),
+            e => Error::data(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Error, Result};
+    use crate::container::{self, Codec, ContainerError};
+    use crate::huffman::{ByteHuffman, CharHuffman, TableError};
+    use crate::rans::Rans;
+    use std::error::Error as _;
+    use std::io;
+
+    fn round_trip(data: &[u8]) -> Result<Vec<u8>> {
+        let code = ByteHuffman::try_new_bytes(data)?;
+        let (packed, nbits) = code.try_encode_bytes_packed(data)?;
+        let rans = Rans::try_new(data)?;
+        let decoded = rans.decode(&rans.encode(&code.decode_bytes_packed(&packed, nbits))?, data.len())?;
+        Ok(container::decompress_from_slice(&container::compress_to_vec(&decoded, Codec::Huffman))?)
+    }
+
+    #[test]
+    fn test_question_mark() {
+        assert_eq!(round_trip(b"abracadabra").unwrap(), b"abracadabra");
+        assert!(matches!(round_trip(b""), Err(Error::EmptyAlphabet)));
+
+        let code = CharHuffman::new("abc");
+        let err: Error = code.try_encode("abxc").unwrap_err().into();
+        assert!(matches!(err, Error::UnknownSymbol { pos: 2 }));
+        assert_eq!(err.to_string(), "no codeword for the symbol at position 2");
+    }
+
+    #[test]
+    fn test_conversions() {
+        let err: Error = ContainerError::ChecksumMismatch.into();
+        assert!(matches!(err, Error::ChecksumMismatch));
+        let err: Error = ContainerError::Truncated.into();
+        assert!(matches!(err, Error::TruncatedStream));
+        let err: Error = ContainerError::Table(TableError::Oversubscribed).into();
+        assert!(matches!(err, Error::InvalidTable(_)));
+        assert_eq!(err.source().unwrap().to_string(), TableError::Oversubscribed.to_string());
+        let err: Error = ContainerError::CorruptPayload.into();
+        assert!(matches!(err, Error::InvalidData(_)));
+        let err: Error = CharHuffman::new("ab").try_decode_string("0x").unwrap_err().into();
+        assert!(matches!(err, Error::InvalidData(_)));
+
+        // io errors from the file functions give up the ContainerError inside
+        let err: Error = io::Error::from(ContainerError::UnsupportedVersion(9)).into();
+        assert!(matches!(err, Error::UnsupportedVersion(9)));
+        let err: Error = ContainerError::UnknownCodec(0xee).into();
+        assert_eq!(err.to_string(), "unsupported codec id 238");
+        let err: Error = ContainerError::UnknownChecksum(0xee).into();
+        assert!(matches!(err, Error::UnsupportedChecksum(0xee)));
+        let err: Error = io::Error::new(io::ErrorKind::UnexpectedEof, "short").into();
+        assert!(matches!(err, Error::TruncatedStream));
+        let err: Error = io::Error::new(io::ErrorKind::PermissionDenied, "no").into();
+        assert!(matches!(err, Error::Io(_)));
+    }
+
+}
@@ -0,0 +1,186 @@
+// Huffman codes over an open alphabet. Alongside the trained symbols the
+// code has one reserved ESCAPE symbol; a symbol without a codeword of its
+// own goes out as ESCAPE followed by its raw value in S::BITS bits (21 for
+// chars, 8 for bytes). A code trained on one corpus can then encode any
+// later input, paying extra only for what it hasn't seen.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::bitio::{self, BitReader, BitWriter};
+use crate::huffman::{HuffmanCode, ScalarSymbol};
+
+// Escape sorts first, which only matters for tie-breaking
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Escaped<S> {
+    Escape,
+    Symbol(S),
+}
+
+#[derive(Clone)]
+pub struct EscapedHuffman<S: ScalarSymbol = char> {
+    code: HuffmanCode<Escaped<S>>,
+}
+
+impl<S: ScalarSymbol> EscapedHuffman<S> {
+
+    // ESCAPE gets the weight of a symbol seen once
+    pub fn from_frequencies(freq: &HashMap<S, u64>) -> Self {
+        EscapedHuffman::with_escape_frequency(freq, 1)
+    }
+
+    // A higher escape frequency shortens the escape codeword, for input
+    // expected to stray further from the training data. Never fails: an
+    // empty map gives a code where everything is escaped.
+    pub fn with_escape_frequency(freq: &HashMap<S, u64>, escape_freq: u64) -> Self {
+        let mut escaped: HashMap<Escaped<S>, u64> = freq.iter()
+            .map(|(sym, &f)| (Escaped::Symbol(sym.clone()), f))
+            .collect();
+        escaped.insert(Escaped::Escape, escape_freq);
+        EscapedHuffman { code: HuffmanCode::from_frequencies(&escaped) }
+    }
+
+    pub fn from_symbols<I: IntoIterator<Item=S>>(symbols: I) -> Self {
+        let mut freqs = HashMap::new();
+        for sym in symbols {
+            *freqs.entry(sym).or_insert(0) += 1;
+        }
+        EscapedHuffman::from_frequencies(&freqs)
+    }
+
+    // The underlying code, ESCAPE included
+    pub fn code(&self) -> &HuffmanCode<Escaped<S>> {
+        &self.code
+    }
+
+    // Whether `sym` has a codeword of its own
+    pub fn contains(&self, sym: &S) -> bool {
+        self.code.codewords().contains_key(&Escaped::Symbol(sym.clone()))
+    }
+
+    pub fn write_symbol<W: Write>(&self, sym: &S, out: &mut BitWriter<W>) -> io::Result<()> {
+        let wrapped = Escaped::Symbol(sym.clone());
+        if self.code.codewords().contains_key(&wrapped) {
+            return self.code.write_symbol(&wrapped, out);
+        }
+        self.code.write_symbol(&Escaped::Escape, out)?;
+        out.write_bits(sym.to_u32() as u64, S::BITS)
+    }
+
+    // An escaped value that isn't a valid S is an InvalidData error
+    pub fn read_symbol<R: Read>(&self, input: &mut BitReader<R>) -> io::Result<S> {
+        match self.code.read_symbol(input)? {
+            Escaped::Symbol(sym) => Ok(sym),
+            Escaped::Escape => {
+                let v = input.read_bits(S::BITS)? as u32;
+                S::from_u32(v).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid escaped symbol"))
+            }
+        }
+    }
+
+    pub fn encode_symbols_packed(&self, symbols: &[S]) -> (Vec<u8>, usize) {
+        bitio::pack(|w| {
+            for sym in symbols {
+                self.write_symbol(sym, w)?;
+            }
+            Ok(())
+        })
+    }
+
+    // Stops at the first symbol that doesn't fit in `nbits`
+    pub fn decode_symbols_packed(&self, bytes: &[u8], nbits: usize) -> Vec<S> {
+        let mut input = BitReader::new(bytes);
+        let mut ret = Vec::new();
+        while let Ok(sym) = self.read_symbol(&mut input) {
+            if input.bits_read() as usize > nbits {
+                break;
+            }
+            ret.push(sym);
+        }
+        ret
+    }
+
+    pub fn encode_symbols(&self, symbols: &[S]) -> String {
+        let (bytes, nbits) = self.encode_symbols_packed(symbols);
+        bitio::unpack(&bytes, nbits).map(|x| if x { '1' } else { '0' }).collect()
+    }
+
+    pub fn decode_symbols(&self, s: &str) -> Vec<S> {
+        let (bytes, nbits) = bitio::pack(|w| {
+            for x in s.chars() {
+                w.write_bit(x != '0')?;
+            }
+            Ok(())
+        });
+        self.decode_symbols_packed(&bytes, nbits)
+    }
+
+}
+
+impl EscapedHuffman<char> {
+
+    pub fn new(s: &str) -> Self {
+        EscapedHuffman::from_symbols(s.chars())
+    }
+
+    pub fn encode_string(&self, s: &str) -> String {
+        self.encode_symbols(&s.chars().collect::<Vec<char>>())
+    }
+
+    pub fn decode_string(&self, s: &str) -> String {
+        self.decode_symbols(s).into_iter().collect()
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Escaped, EscapedHuffman};
+    use crate::bitio::BitReader;
+    use std::collections::HashMap;
+    use std::io;
+
+    #[test]
+    fn test_unseen_symbols() {
+        let code = EscapedHuffman::new("the quick brown fox jumped over the lazy dog");
+        for s in &["the lazy dog", "THE LAZY DOG", "ünïcödé ☃ and emoji 🦀", ""] {
+            assert_eq!(code.decode_string(&code.encode_string(s)), *s);
+        }
+        assert!(code.contains(&'q'));
+        assert!(!code.contains(&'Q'));
+
+        // an escaped symbol costs the escape codeword plus 21 bits
+        let escape = code.code().codewords()[&Escaped::Escape].len();
+        assert_eq!(code.encode_string("Q").len(), escape + 21);
+    }
+
+    #[test]
+    fn test_bytes() {
+        let code = EscapedHuffman::from_symbols(b"aaaabbc".iter().cloned());
+        let data: Vec<u8> = (0..=255u8).collect();
+        let (packed, nbits) = code.encode_symbols_packed(&data);
+        assert_eq!(code.decode_symbols_packed(&packed, nbits), data);
+
+        // an untrained code escapes everything
+        let empty = EscapedHuffman::<u8>::from_frequencies(&HashMap::new());
+        let (packed, nbits) = empty.encode_symbols_packed(b"xyz");
+        assert_eq!(nbits, 3 * 9);
+        assert_eq!(empty.decode_symbols_packed(&packed, nbits), b"xyz");
+    }
+
+    #[test]
+    fn test_escape_frequency() {
+        let freq: HashMap<char, u64> = vec![('a', 50), ('b', 30), ('c', 20)].into_iter().collect();
+        let rare = EscapedHuffman::from_frequencies(&freq);
+        let common = EscapedHuffman::with_escape_frequency(&freq, 60);
+        assert!(common.encode_string("z").len() < rare.encode_string("z").len());
+
+        // 21 bits can hold values beyond the last char
+        let empty = EscapedHuffman::<char>::from_frequencies(&HashMap::new());
+        let bytes = [0b0110_1100, 0b0000_0000, 0b0000_0000];
+        let err = empty.read_symbol(&mut BitReader::new(&bytes[..])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+}
@@ -0,0 +1,429 @@
+// Every codec in the crate run over a corpus (Calgary, Canterbury, or a
+// directory of your own data), for picking one empirically: how small each
+// makes each file and how fast it gets there and back.
+//
+// The bare entropy coders (coder.rs) are trained on each file and timed
+// with the training; what they'd need to send their model isn't counted, so
+// they show the best a static code can do. The container codecs, DEFLATE,
+// zlib and gzip count everything they write. Every result is decoded and
+// checked against the input; one that doesn't come back is reported as
+// failed, without sizes or times, and the run goes on.
+//
+// Times are the best of `runs` tries, so raise it for small files. Empty
+// files have nothing to measure and are left out.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::coder::CodecId;
+use crate::container::{self, Codec};
+use crate::deflate;
+use crate::gzip::{self, GzHeader};
+use crate::zlib;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Coder(CodecId),
+    // The container format at the codec's default level
+    Container(Codec),
+    // A raw DEFLATE stream (deflate.rs)
+    Deflate,
+    // DEFLATE in a zlib or gzip wrapper (zlib.rs, gzip.rs)
+    Zlib,
+    Gzip,
+}
+
+impl Method {
+
+    pub fn all() -> Vec<Method> {
+        let mut all: Vec<Method> = CodecId::ALL.iter().map(|&id| Method::Coder(id)).collect();
+        all.extend(Codec::ALL.iter().map(|&c| Method::Container(c)));
+        all.extend([Method::Deflate, Method::Zlib, Method::Gzip]);
+        all
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Method::Coder(id) => id.name(),
+            Method::Container(Codec::Huffman) => "container-huffman",
+            Method::Container(Codec::BwtPipeline) => "container-bwt",
+            Method::Container(Codec::Lzss) => "container-lzss",
+            Method::Container(Codec::Ppm) => "container-ppm",
+            Method::Deflate => "deflate",
+            Method::Zlib => "zlib",
+            Method::Gzip => "gzip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Method> {
+        Method::all().into_iter().find(|m| m.name() == name)
+    }
+
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug)]
+pub enum EvalError {
+    Io(io::Error),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for EvalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EvalError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for EvalError {
+    fn from(e: io::Error) -> Self {
+        EvalError::Io(e)
+    }
+}
+
+impl From<EvalError> for io::Error {
+    fn from(e: EvalError) -> Self {
+        match e {
+            EvalError::Io(e) => e,
+        }
+    }
+}
+
+// One method on one file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    pub file: String,
+    pub method: Method,
+    pub original: u64,
+    pub compressed: u64,
+    pub encode_secs: f64,
+    pub decode_secs: f64,
+    // The method failed on the file or decoded it to something else;
+    // compressed and the times are then 0
+    pub failed: bool,
+}
+
+impl Measurement {
+
+    // compressed / original, as in stats::CompressionReport; lower is
+    // better. This and the speeds are 0 for a failed measurement.
+    pub fn ratio(&self) -> f64 {
+        if self.failed {
+            return 0.0;
+        }
+        self.compressed as f64 / self.original as f64
+    }
+
+    // Original megabytes (10^6 bytes) a second
+    pub fn encode_speed(&self) -> f64 {
+        if self.failed {
+            return 0.0;
+        }
+        speed(self.original, self.encode_secs)
+    }
+
+    pub fn decode_speed(&self) -> f64 {
+        if self.failed {
+            return 0.0;
+        }
+        speed(self.original, self.decode_secs)
+    }
+
+}
+
+fn speed(bytes: u64, secs: f64) -> f64 {
+    bytes as f64 / 1e6 / secs.max(1e-9)
+}
+
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+    methods: Vec<Method>,
+    runs: u32,
+}
+
+impl Evaluation {
+
+    // Every method, timed once
+    pub fn new() -> Self {
+        Evaluation { methods: Method::all(), runs: 1 }
+    }
+
+    pub fn methods(mut self, methods: &[Method]) -> Self {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    // Tries per timing, at least 1
+    pub fn runs(mut self, runs: u32) -> Self {
+        self.runs = runs.max(1);
+        self
+    }
+
+    // The files under `dir`, recursively, named by their path from it
+    pub fn run_dir<P: AsRef<Path>>(&self, dir: P) -> Result<Report, EvalError> {
+        let mut paths = Vec::new();
+        collect_files(dir.as_ref(), &mut paths)?;
+        paths.sort();
+        let mut measurements = Vec::new();
+        for path in paths {
+            let name = path.strip_prefix(dir.as_ref()).unwrap_or(&path).display().to_string();
+            measurements.extend(self.measure(&name, &fs::read(&path)?));
+        }
+        Ok(Report { measurements })
+    }
+
+    // Each method on `data`, in order; none for empty data
+    pub fn measure(&self, file: &str, data: &[u8]) -> Vec<Measurement> {
+        if data.is_empty() {
+            return Vec::new();
+        }
+        self.methods.iter().map(|&method| {
+            let ((compressed, encode_secs, decode_secs), failed) = match self.time(method, data) {
+                Some(measured) => (measured, false),
+                None => ((0, 0.0, 0.0), true),
+            };
+            Measurement { file: file.to_string(), method, original: data.len() as u64, compressed, encode_secs,
+                          decode_secs, failed }
+        }).collect()
+    }
+
+    // (compressed size, encode time, decode time), or None if `data`
+    // doesn't come back
+    fn time(&self, method: Method, data: &[u8]) -> Option<(u64, f64, f64)> {
+        match method {
+            Method::Coder(id) => {
+                let (encoded, encode_secs) = best_of(self.runs, || {
+                    let coder = id.train(data).ok()?;
+                    let bits = coder.encode(data).ok()?;
+                    Some((coder, bits))
+                });
+                let (coder, bits) = encoded?;
+                let (decoded, decode_secs) = best_of(self.runs, || coder.decode(&bits));
+                let size = bits.len().div_ceil(8) as u64;
+                (decoded.ok()? == data).then_some((size, encode_secs, decode_secs))
+            }
+            Method::Container(codec) => {
+                let (packed, encode_secs) = best_of(self.runs, || container::compress_to_vec(data, codec));
+                let (decoded, decode_secs) = best_of(self.runs, || container::decompress_from_slice(&packed));
+                (decoded.ok()? == data).then_some((packed.len() as u64, encode_secs, decode_secs))
+            }
+            Method::Deflate => {
+                let (packed, encode_secs) = best_of(self.runs, || deflate::deflate(data));
+                let (decoded, decode_secs) = best_of(self.runs, || deflate::inflate(&packed));
+                (decoded.ok()? == data).then_some((packed.len() as u64, encode_secs, decode_secs))
+            }
+            Method::Zlib => {
+                let (packed, encode_secs) = best_of(self.runs, || zlib::compress(data));
+                let (decoded, decode_secs) = best_of(self.runs, || zlib::decompress(&packed));
+                (decoded.ok()? == data).then_some((packed.len() as u64, encode_secs, decode_secs))
+            }
+            Method::Gzip => {
+                let (packed, encode_secs) = best_of(self.runs, || gzip::compress(data, &GzHeader::default()));
+                let (decoded, decode_secs) = best_of(self.runs, || gzip::decompress(&packed));
+                (decoded.ok()?.1 == data).then_some((packed.len() as u64, encode_secs, decode_secs))
+            }
+        }
+    }
+
+}
+
+impl Default for Evaluation {
+    fn default() -> Self {
+        Evaluation::new()
+    }
+}
+
+// The last result and the shortest time
+fn best_of<T, F: FnMut() -> T>(runs: u32, mut f: F) -> (T, f64) {
+    let mut best = f64::INFINITY;
+    let mut result = None;
+    for _ in 0..runs {
+        let start = Instant::now();
+        result = Some(f());
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+    (result.expect("at least one run"), best)
+}
+
+fn collect_files(dir: &Path, out: &mut Vec<std::path::PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            collect_files(&entry.path(), out)?;
+        } else if kind.is_file() {
+            out.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub measurements: Vec<Measurement>,
+}
+
+impl Report {
+
+    // Each method over the whole corpus, sizes and times summed, with
+    // "(total)" for a file name. Only the files a method got back count; if
+    // there were others its total is failed too.
+    pub fn totals(&self) -> Vec<Measurement> {
+        let mut totals: Vec<Measurement> = Vec::new();
+        for m in &self.measurements {
+            let i = match totals.iter().position(|t| t.method == m.method) {
+                Some(i) => i,
+                None => {
+                    totals.push(Measurement { file: "(total)".to_string(), method: m.method, original: 0, compressed: 0,
+                                              encode_secs: 0.0, decode_secs: 0.0, failed: false });
+                    totals.len() - 1
+                }
+            };
+            let t = &mut totals[i];
+            if m.failed {
+                t.failed = true;
+                continue;
+            }
+            t.original += m.original;
+            t.compressed += m.compressed;
+            t.encode_secs += m.encode_secs;
+            t.decode_secs += m.decode_secs;
+        }
+        totals
+    }
+
+    // A header line, then a row per measurement and one per total; ok is
+    // false for a failed one
+    pub fn to_csv(&self) -> String {
+        let mut out = "file,method,original,compressed,ratio,encode_mb_s,decode_mb_s,ok\n".to_string();
+        for m in self.measurements.iter().chain(&self.totals()) {
+            out.push_str(&format!("{},{},{},{},{:.4},{:.2},{:.2},{}\n", csv_field(&m.file), m.method, m.original,
+                                  m.compressed, m.ratio(), m.encode_speed(), m.decode_speed(), !m.failed));
+        }
+        out
+    }
+
+    // {"files": [...], "totals": [...]}, objects with the CSV's columns
+    pub fn to_json(&self) -> String {
+        let rows = |ms: &[Measurement]| -> String {
+            let objects: Vec<String> = ms.iter().map(|m| format!(
+                "    {{\"file\": {}, \"method\": \"{}\", \"original\": {}, \"compressed\": {}, \"ratio\": {:.4}, \
+                 \"encode_mb_s\": {:.2}, \"decode_mb_s\": {:.2}, \"ok\": {}}}",
+                json_string(&m.file), m.method, m.original, m.compressed, m.ratio(), m.encode_speed(),
+                m.decode_speed(), !m.failed)).collect();
+            if objects.is_empty() {
+                return "[]".to_string();
+            }
+            format!("[\n{}\n  ]", objects.join(",\n"))
+        };
+        format!("{{\n  \"files\": {},\n  \"totals\": {}\n}}\n", rows(&self.measurements), rows(&self.totals()))
+    }
+
+}
+
+// Quoted if it has to be (RFC 4180)
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Evaluation, Measurement, Method, Report};
+    use crate::coder::CodecId;
+    use crate::container::Codec;
+
+    #[test]
+    fn test_measure() {
+        let data = b"it was the best of times, it was the worst of times".repeat(20);
+        let measured = Evaluation::new().runs(2).measure("dickens", &data);
+        assert_eq!(measured.iter().map(|m| m.method).collect::<Vec<_>>(), Method::all());
+        for m in &measured {
+            assert_eq!((m.file.as_str(), m.original), ("dickens", data.len() as u64));
+            assert!(!m.failed && m.ratio() < 1.0 && m.encode_speed() > 0.0 && m.decode_speed() > 0.0, "{:?}", m);
+        }
+        // LZ finds the repeats a bare order-0 code can't
+        let ratio = |method| measured.iter().find(|m| m.method == method).unwrap().ratio();
+        assert!(ratio(Method::Deflate) < ratio(Method::Coder(CodecId::Huffman)) / 4.0);
+
+        assert!(Evaluation::new().measure("empty", b"").is_empty());
+        assert_eq!(Method::from_name("container-bwt"), Some(Method::Container(Codec::BwtPipeline)));
+        assert_eq!(Method::from_name("arithmetic"), Some(Method::Coder(CodecId::Arithmetic)));
+        assert_eq!(Method::from_name("gzip"), Some(Method::Gzip));
+        assert!(Method::all().iter().all(|&m| Method::from_name(m.name()) == Some(m)));
+    }
+
+    #[test]
+    fn test_report() {
+        let methods = [Method::Coder(CodecId::Rans), Method::Deflate];
+        let eval = Evaluation::new().methods(&methods);
+        let mut measurements = eval.measure("a, \"quoted\"", &b"abracadabra".repeat(10));
+        measurements.extend(eval.measure("b", b"zzzzzzzzzz"));
+        let report = Report { measurements };
+
+        let totals = report.totals();
+        assert_eq!(totals.len(), 2);
+        assert_eq!((totals[1].file.as_str(), totals[1].method, totals[1].original), ("(total)", Method::Deflate, 120));
+
+        let csv = report.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 1 + 4 + 2);
+        assert!(lines[1].starts_with("\"a, \"\"quoted\"\"\",rans,110,"));
+        assert!(lines[6].starts_with("(total),deflate,120,"));
+
+        let json = report.to_json();
+        assert!(json.contains("\"file\": \"a, \\\"quoted\\\"\", \"method\": \"rans\", \"original\": 110"));
+        assert!(json.contains("\"totals\": [\n    {\"file\": \"(total)\""));
+        assert_eq!(Report { measurements: Vec::new() }.to_json(), "{\n  \"files\": [],\n  \"totals\": []\n}\n");
+
+        // a method that fails on one file keeps its row, and the rest go on
+        let mut measurements = report.measurements.clone();
+        measurements[1] = Measurement { compressed: 0, encode_secs: 0.0, decode_secs: 0.0, failed: true,
+                                        ..measurements[1].clone() };
+        let report = Report { measurements };
+        let totals = report.totals();
+        assert!(!totals[0].failed && totals[1].failed);
+        assert_eq!((totals[1].original, totals[1].ratio()), (10, 0.0));
+        let csv = report.to_csv();
+        assert!(csv.lines().nth(2).unwrap().starts_with("\"a, \"\"quoted\"\"\",deflate,110,0,0.0000,0.00,0.00,false"));
+        assert!(csv.lines().nth(3).unwrap().ends_with(",true"));
+        assert!(report.to_json().contains("\"decode_mb_s\": 0.00, \"ok\": false}"));
+    }
+
+}
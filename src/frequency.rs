@@ -0,0 +1,360 @@
+// Symbol counts kept up to date as data goes by, so a code can be rebuilt
+// from recent traffic (HuffmanCode::rebuild) without rescanning it. Tables
+// from several workers or time windows merge by adding counts, and
+// halve() ages old counts so newer traffic weighs more.
+//
+// A table trained once on a large corpus can be saved and shipped with an
+// application; every node that loads it builds the same code from it, as
+// building sorts the symbols first. The format (to_bytes, save), integers
+// big-endian or LEB128:
+//   magic    2 bytes "FT"
+//   version  u8 (FREQ_VERSION)
+//   alphabet u8 (Alphabet::id of S)
+//   crc      u32 CRC-32 of everything after it
+//   LEB128 number of symbols
+//   per symbol, in increasing order of value: LEB128 value, as the gap from
+//   the one before after the first, then LEB128 count
+// With the `serde` feature a table is also a map from symbol to count, for
+// JSON and the like.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::iter::FromIterator;
+use std::path::Path;
+
+use crate::checksum;
+use crate::codes::varint::{self, VarintError};
+use crate::huffman::{Alphabet, ScalarSymbol, Symbol};
+
+pub const FREQ_MAGIC: [u8; 2] = *b"FT";
+pub const FREQ_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrequencyError {
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u8),
+    // The table was saved for a different symbol type
+    WrongAlphabet { expected: Alphabet, found: u8 },
+    ChecksumMismatch,
+    // A value that isn't one of the symbol type's
+    InvalidSymbol(u64),
+    // A LEB128 field wider than 64 bits
+    Overflow,
+    // Counts that add up to more than a u64 holds, which no code could be
+    // built from
+    TotalOverflow,
+}
+
+impl fmt::Display for FrequencyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrequencyError::Truncated => write!(f, "frequency table is truncated"),
+            FrequencyError::BadMagic => write!(f, "not a frequency table"),
+            FrequencyError::UnsupportedVersion(v) => write!(f, "unsupported frequency table version {}", v),
+            FrequencyError::WrongAlphabet { expected, found } => match Alphabet::from_id(*found) {
+                Some(found) => write!(f, "frequency table is over {} symbols, not {}", found, expected),
+                None => write!(f, "frequency table has unknown alphabet type {}", found),
+            },
+            FrequencyError::ChecksumMismatch => write!(f, "frequency table checksum mismatch"),
+            FrequencyError::InvalidSymbol(v) => write!(f, "frequency table has invalid symbol value {}", v),
+            FrequencyError::Overflow => write!(f, "frequency table field overflows 64 bits"),
+            FrequencyError::TotalOverflow => write!(f, "frequency table counts add up to over 64 bits"),
+        }
+    }
+}
+
+impl Error for FrequencyError {}
+
+impl From<VarintError> for FrequencyError {
+    fn from(e: VarintError) -> Self {
+        match e {
+            VarintError::Truncated => FrequencyError::Truncated,
+            VarintError::Overflow => FrequencyError::Overflow,
+        }
+    }
+}
+
+impl From<FrequencyError> for io::Error {
+    fn from(e: FrequencyError) -> Self {
+        let kind = match e {
+            FrequencyError::Truncated => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrequencyTable<S: Symbol = char> {
+    counts: HashMap<S, u64>,
+    total: u64,
+}
+
+impl<S: Symbol> FrequencyTable<S> {
+
+    pub fn new() -> Self {
+        FrequencyTable { counts: HashMap::new(), total: 0 }
+    }
+
+    pub fn observe(&mut self, sym: S) {
+        self.observe_n(sym, 1);
+    }
+
+    // Counts saturate rather than overflow
+    pub fn observe_n(&mut self, sym: S, n: u64) {
+        if n == 0 {
+            return;
+        }
+        let count = self.counts.entry(sym).or_insert(0);
+        *count = count.saturating_add(n);
+        self.total = self.total.saturating_add(n);
+    }
+
+    pub fn observe_all<I: IntoIterator<Item=S>>(&mut self, symbols: I) {
+        for sym in symbols {
+            self.observe(sym);
+        }
+    }
+
+    // Add `other`'s counts to this table
+    pub fn merge(&mut self, other: &FrequencyTable<S>) {
+        for (sym, &n) in &other.counts {
+            self.observe_n(sym.clone(), n);
+        }
+    }
+
+    // Halve every count, dropping symbols that reach zero
+    pub fn halve(&mut self) {
+        self.counts.retain(|_, n| {
+            *n /= 2;
+            *n > 0
+        });
+        self.total = self.counts.values().fold(0u64, |acc, &n| acc.saturating_add(n));
+    }
+
+    pub fn clear(&mut self) {
+        self.counts.clear();
+        self.total = 0;
+    }
+
+    pub fn count(&self, sym: &S) -> u64 {
+        self.counts.get(sym).cloned().unwrap_or(0)
+    }
+
+    // Sum of all counts
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    // Number of distinct symbols seen
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(&S, u64)> + '_ {
+        self.counts.iter().map(|(sym, &n)| (sym, n))
+    }
+
+    // In the form the HuffmanCode constructors take
+    pub fn as_map(&self) -> &HashMap<S, u64> {
+        &self.counts
+    }
+
+    pub fn into_map(self) -> HashMap<S, u64> {
+        self.counts
+    }
+
+}
+
+impl<S: ScalarSymbol> FrequencyTable<S> {
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut entries: Vec<(u32, u64)> = self.counts.iter().map(|(s, &n)| (s.to_u32(), n)).collect();
+        entries.sort();
+
+        let mut body = Vec::new();
+        varint::write_u64(&mut body, entries.len() as u64);
+        let mut prev = 0;
+        for (v, n) in entries {
+            varint::write_u64(&mut body, (v - prev) as u64);
+            varint::write_u64(&mut body, n);
+            prev = v;
+        }
+
+        let mut out = FREQ_MAGIC.to_vec();
+        out.extend_from_slice(&[FREQ_VERSION, S::ALPHABET.id()]);
+        out.extend_from_slice(&checksum::crc32(&body).to_be_bytes());
+        out.extend_from_slice(&body);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FrequencyError> {
+        if bytes.len() < 8 {
+            return Err(FrequencyError::Truncated);
+        }
+        let (header, mut rest) = bytes.split_at(8);
+        if header[..2] != FREQ_MAGIC {
+            return Err(FrequencyError::BadMagic);
+        }
+        if header[2] != FREQ_VERSION {
+            return Err(FrequencyError::UnsupportedVersion(header[2]));
+        }
+        if header[3] != S::ALPHABET.id() {
+            return Err(FrequencyError::WrongAlphabet { expected: S::ALPHABET, found: header[3] });
+        }
+        if checksum::crc32(rest) != u32::from_be_bytes([header[4], header[5], header[6], header[7]]) {
+            return Err(FrequencyError::ChecksumMismatch);
+        }
+
+        let n = varint::read_u64(&mut rest)?;
+        let mut table = FrequencyTable::new();
+        let mut value = 0u64;
+        for i in 0..n {
+            let gap = varint::read_u64(&mut rest)?;
+            // a zero gap after the first would repeat a symbol
+            if i > 0 && gap == 0 {
+                return Err(FrequencyError::InvalidSymbol(value));
+            }
+            value = value.saturating_add(gap);
+            let sym = u32::try_from(value).ok().and_then(S::from_u32).ok_or(FrequencyError::InvalidSymbol(value))?;
+            let count = varint::read_u64(&mut rest)?;
+            table.total().checked_add(count).ok_or(FrequencyError::TotalOverflow)?;
+            table.observe_n(sym, count);
+        }
+        Ok(table)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(FrequencyTable::from_bytes(&fs::read(path)?)?)
+    }
+
+}
+
+impl<S: Symbol> Default for FrequencyTable<S> {
+    fn default() -> Self {
+        FrequencyTable::new()
+    }
+}
+
+// Zero counts are dropped
+impl<S: Symbol> From<HashMap<S, u64>> for FrequencyTable<S> {
+    fn from(mut counts: HashMap<S, u64>) -> Self {
+        counts.retain(|_, n| *n > 0);
+        let total = counts.values().fold(0u64, |acc, &n| acc.saturating_add(n));
+        FrequencyTable { counts, total }
+    }
+}
+
+impl<S: Symbol> FromIterator<S> for FrequencyTable<S> {
+    fn from_iter<I: IntoIterator<Item=S>>(symbols: I) -> Self {
+        let mut table = FrequencyTable::new();
+        table.observe_all(symbols);
+        table
+    }
+}
+
+impl<S: Symbol> Extend<S> for FrequencyTable<S> {
+    fn extend<I: IntoIterator<Item=S>>(&mut self, symbols: I) {
+        self.observe_all(symbols);
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{FrequencyError, FrequencyTable};
+    use crate::huffman::{Alphabet, BuildError, ByteHuffman, CharHuffman};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_counts() {
+        let mut table: FrequencyTable<char> = "hello".chars().collect();
+        assert_eq!((table.count(&'l'), table.count(&'z'), table.total(), table.len()), (2, 0, 5, 4));
+        table.observe('z');
+        table.observe_n('h', 0);
+        let other: FrequencyTable<char> = "world".chars().collect();
+        table.merge(&other);
+        assert_eq!((table.count(&'l'), table.count(&'o'), table.total()), (3, 2, 11));
+
+        table.halve();
+        assert_eq!((table.count(&'l'), table.count(&'z'), table.total(), table.len()), (1, 0, 2, 2));
+        table.observe_n('x', u64::MAX);
+        assert_eq!(table.total(), u64::MAX);
+
+        let map: HashMap<char, u64> = [('a', 3), ('b', 0)].iter().cloned().collect();
+        let table = FrequencyTable::from(map);
+        assert_eq!((table.len(), table.total()), (1, 3));
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let mut table = FrequencyTable::new();
+        table.extend(b"aaaaaaab".iter().cloned());
+        let mut code = ByteHuffman::try_from_frequencies(table.as_map()).unwrap();
+        assert_eq!(code.code_for(&b'c'), None);
+
+        // traffic shifts towards 'c'
+        table.halve();
+        table.extend(b"cccccccccccccccccd".iter().cloned());
+        code.rebuild(&table).unwrap();
+        assert_eq!(code.code_for(&b'c').unwrap().len(), 1);
+        assert_eq!(code.frequencies(), table.as_map());
+        let (packed, nbits) = code.encode_bytes_packed(b"cad");
+        assert_eq!(code.decode_bytes_packed(&packed, nbits), b"cad");
+
+        // an empty table leaves the code alone
+        assert_eq!(code.rebuild(&FrequencyTable::new()), Err(BuildError::EmptyAlphabet));
+        assert_eq!(code.code_for(&b'c').unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let text = "it is a truth universally acknowledged, that a single man in possession of a good fortune";
+        let table: FrequencyTable<char> = text.chars().chain("\u{1F600}".chars()).collect();
+        let bytes = table.to_bytes();
+        assert_eq!(bytes[..4], [b'F', b'T', 1, Alphabet::Char.id()]);
+        assert_eq!(FrequencyTable::from_bytes(&bytes), Ok(table.clone()));
+        // the same code wherever it's loaded
+        let loaded = FrequencyTable::<char>::from_bytes(&bytes).unwrap();
+        let (a, b) = (CharHuffman::from_frequencies(table.as_map()), CharHuffman::from_frequencies(loaded.as_map()));
+        assert_eq!(a.serialize_table(), b.serialize_table());
+
+        // every byte seen, counts to 2^16: about three bytes a symbol
+        let bytes_table: FrequencyTable<u8> = (0..1 << 16).map(|i: u32| (i * i % 251) as u8).collect();
+        assert!(bytes_table.to_bytes().len() < 8 + 3 * 256);
+        let path = std::env::temp_dir().join(format!("entrust-{}-frequencies", std::process::id()));
+        bytes_table.save(&path).unwrap();
+        let loaded = FrequencyTable::<u8>::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), bytes_table);
+        assert_eq!(FrequencyTable::<u8>::from_bytes(&FrequencyTable::<u8>::new().to_bytes()), Ok(FrequencyTable::new()));
+
+        assert_eq!(FrequencyTable::<u8>::from_bytes(&bytes),
+                   Err(FrequencyError::WrongAlphabet { expected: Alphabet::Byte, found: Alphabet::Char.id() }));
+        let mut flipped = bytes.clone();
+        flipped[10] ^= 1;
+        assert_eq!(FrequencyTable::<char>::from_bytes(&flipped), Err(FrequencyError::ChecksumMismatch));
+        assert_eq!(FrequencyTable::<char>::from_bytes(&bytes[..6]), Err(FrequencyError::Truncated));
+        assert_eq!(FrequencyTable::<char>::from_bytes(b"HT\x01\x01\0\0\0\0"), Err(FrequencyError::BadMagic));
+
+        // counts past u64::MAX between them
+        let mut huge = FrequencyTable::<u8>::new();
+        huge.observe_n(b'a', u64::MAX);
+        huge.observe_n(b'b', 1);
+        assert_eq!(FrequencyTable::<u8>::from_bytes(&huge.to_bytes()), Err(FrequencyError::TotalOverflow));
+    }
+
+}
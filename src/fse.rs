@@ -0,0 +1,281 @@
+// Finite state entropy: tANS (Duda's tabled asymmetric numeral systems) as
+// used by zstd, after Yann Collet's FSE. Frequencies are normalised to sum
+// to the table size 2^table_log and spread over the table; encoding and
+// decoding are then a table lookup plus a few bits of I/O per symbol, with
+// compression close to arithmetic coding.
+//
+// The encoder walks the input backwards, so its bit fields are written out
+// in reverse: the stream (MSB-first, zero-padded) opens with the final
+// encoder state, then the fields in the order the decoder reads them.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::bitio::{self, BitReader};
+use crate::histogram;
+use crate::huffman::{BuildError, EncodeError};
+use crate::rans::normalize;
+use crate::scratch::{self, Scratch};
+
+pub const DEFAULT_TABLE_LOG: u32 = 11;
+pub const MIN_TABLE_LOG: u32 = 5;
+pub const MAX_TABLE_LOG: u32 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FseError {
+    // The stream ran out before every symbol was decoded
+    Truncated,
+    // The stream doesn't end in the encoder's initial state, so it was
+    // damaged or encoded with a different table
+    Corrupt,
+}
+
+impl fmt::Display for FseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FseError::Truncated => write!(f, "FSE stream is truncated"),
+            FseError::Corrupt => write!(f, "FSE stream is corrupt"),
+        }
+    }
+}
+
+impl Error for FseError {}
+
+#[derive(Debug, Clone, Copy)]
+struct DecodeEntry {
+    symbol: u8,
+    nbits: u8,
+    // Next state, before adding the `nbits` bits read
+    base: u16,
+}
+
+#[derive(Clone)]
+pub struct Fse {
+    table_log: u32,
+    // Normalised frequency of each byte (summing to 2^table_log) and the
+    // running total before it
+    freqs: [u32; 256],
+    cum: [u32; 257],
+    // Encoder states are in [2^table_log, 2^(table_log + 1)). Having shifted
+    // the state down into [freq, 2 * freq) for symbol s, the next state is
+    // encode_states[cum[s] + state - freq].
+    encode_states: Arc<[u16]>,
+    // Indexed by decoder state (encoder state minus 2^table_log)
+    decode_table: Arc<[DecodeEntry]>,
+}
+
+impl Fse {
+
+    pub fn new(data: &[u8]) -> Self {
+        Fse::try_new(data).expect("can't build an FSE table for empty input")
+    }
+
+    pub fn try_new(data: &[u8]) -> Result<Self, BuildError> {
+        Fse::from_frequencies(&histogram::byte_frequencies(data), DEFAULT_TABLE_LOG)
+    }
+
+    // Larger tables track the frequencies more closely but take longer to
+    // build and fall out of cache. MaxLengthTooSmall if 2^table_log slots
+    // can't hold the alphabet; panics unless table_log is within
+    // MIN_TABLE_LOG..=MAX_TABLE_LOG.
+    pub fn from_frequencies(freq: &HashMap<u8, u64>, table_log: u32) -> Result<Self, BuildError> {
+        assert!((MIN_TABLE_LOG..=MAX_TABLE_LOG).contains(&table_log),
+                "table_log must be in {}..={}", MIN_TABLE_LOG, MAX_TABLE_LOG);
+        let mut counts = [0u64; 256];
+        for (&b, &f) in freq {
+            counts[b as usize] = f;
+        }
+        if counts.iter().all(|&c| c == 0) {
+            return Err(BuildError::EmptyAlphabet);
+        }
+        let freqs = normalize(&counts, table_log).ok_or(BuildError::MaxLengthTooSmall)?;
+        let mut cum = [0u32; 257];
+        for i in 0..256 {
+            cum[i + 1] = cum[i] + freqs[i];
+        }
+
+        // Spread each symbol's slots across the table, so that every part
+        // of the state range sees a mix of symbols. The step is odd, hence
+        // coprime with the table size, and visits every slot once.
+        let size = 1usize << table_log;
+        let mask = size - 1;
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut spread = vec![0u8; size];
+        let mut pos = 0;
+        for (b, &f) in freqs.iter().enumerate() {
+            for _ in 0..f {
+                spread[pos] = b as u8;
+                pos = (pos + step) & mask;
+            }
+        }
+
+        let mut next = freqs;
+        let mut encode_states = vec![0u16; size];
+        let mut decode_table = Vec::with_capacity(size);
+        for (u, &b) in spread.iter().enumerate() {
+            let s = b as usize;
+            let x = next[s];
+            next[s] += 1;
+            encode_states[(cum[s] + x - freqs[s]) as usize] = (size + u) as u16;
+            let nbits = table_log - floor_log2(x);
+            decode_table.push(DecodeEntry {
+                symbol: b,
+                nbits: nbits as u8,
+                base: ((x << nbits) as usize - size) as u16,
+            });
+        }
+        Ok(Fse { table_log, freqs, cum, encode_states: encode_states.into(), decode_table: decode_table.into() })
+    }
+
+    pub fn table_log(&self) -> u32 {
+        self.table_log
+    }
+
+    // Normalised frequency of `b` (out of 2^table_log)
+    pub fn frequency(&self, b: u8) -> u32 {
+        self.freqs[b as usize]
+    }
+
+    pub fn encode(&self, data: &[u8]) -> Result<Vec<u8>, EncodeError<u8>> {
+        scratch::with_scratch(|s| self.encode_with_scratch(data, s))
+    }
+
+    // encode(), keeping the fields to be written in reverse in `scratch`
+    pub fn encode_with_scratch(&self, data: &[u8], scratch: &mut Scratch) -> Result<Vec<u8>, EncodeError<u8>> {
+        if let Some(pos) = data.iter().position(|&b| self.freqs[b as usize] == 0) {
+            return Err(EncodeError { symbol: data[pos], pos });
+        }
+
+        let size = 1u32 << self.table_log;
+        let mut state = size;
+        let fields = &mut scratch.fields;
+        fields.clear();
+        for &b in data.iter().rev() {
+            let s = b as usize;
+            let f = self.freqs[s];
+            // shift the state down into [f, 2f)
+            let mut n = floor_log2(state) - floor_log2(f);
+            if state >> n < f {
+                n -= 1;
+            }
+            fields.push((state & ((1 << n) - 1), n));
+            state = self.encode_states[(self.cum[s] + (state >> n) - f) as usize] as u32;
+        }
+
+        let (bytes, _) = bitio::pack(|w| {
+            w.write_bits((state - size) as u64, self.table_log)?;
+            for &(bits, n) in fields.iter().rev() {
+                w.write_bits(bits as u64, n)?;
+            }
+            Ok(())
+        });
+        Ok(bytes)
+    }
+
+    // `len` is the number of symbols encoded, which the stream doesn't record
+    pub fn decode(&self, bytes: &[u8], len: usize) -> Result<Vec<u8>, FseError> {
+        // don't trust `len` with a huge up-front allocation
+        let mut out = Vec::with_capacity(len.min(1 << 20));
+        self.decode_each(bytes, len, |b| out.push(b))?;
+        Ok(out)
+    }
+
+    // decode() of out.len() symbols, into `out`
+    pub fn decode_into(&self, bytes: &[u8], out: &mut [u8]) -> Result<(), FseError> {
+        let len = out.len();
+        let mut slots = out.iter_mut();
+        self.decode_each(bytes, len, |b| *slots.next().unwrap() = b)
+    }
+
+    fn decode_each<F: FnMut(u8)>(&self, bytes: &[u8], len: usize, mut emit: F) -> Result<(), FseError> {
+        let mut r = BitReader::new(bytes);
+        let truncated = |_| FseError::Truncated;
+        let mut state = r.read_bits(self.table_log).map_err(truncated)? as usize;
+        for _ in 0..len {
+            let entry = self.decode_table[state];
+            emit(entry.symbol);
+            state = entry.base as usize + r.read_bits(entry.nbits as u32).map_err(truncated)? as usize;
+        }
+        // Back at the encoder's initial state, with only padding left
+        if state != 0 || r.bits_read().div_ceil(8) != bytes.len() as u64 {
+            return Err(FseError::Corrupt);
+        }
+        Ok(())
+    }
+
+}
+
+fn floor_log2(x: u32) -> u32 {
+    31 - x.leading_zeros()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Fse, FseError, MIN_TABLE_LOG};
+    use crate::huffman::{BuildError, ByteHuffman, EncodeError};
+    use crate::rans::Rans;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_round_trip() {
+        let samples: Vec<Vec<u8>> = vec![b"a".to_vec(), b"ab".to_vec(), b"abracadabra".to_vec(),
+                                         (0..=255u8).collect(),
+                                         b"the quick brown fox jumped over the lazy dog".repeat(300)];
+        for data in samples {
+            let fse = Fse::new(&data);
+            let encoded = fse.encode(&data).unwrap();
+            assert_eq!(fse.decode(&encoded, data.len()).unwrap(), data);
+        }
+        let fse = Fse::new(b"xyz");
+        assert_eq!(fse.decode(&fse.encode(b"").unwrap(), 0).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_table_log() {
+        let data = b"she sells sea shells on the sea shore".repeat(20);
+        let freq: HashMap<u8, u64> = data.iter().fold(HashMap::new(), |mut m, &b| {
+            *m.entry(b).or_insert(0) += 1;
+            m
+        });
+        for log in MIN_TABLE_LOG..=12 {
+            let fse = Fse::from_frequencies(&freq, log).unwrap();
+            let total: u32 = (0..=255u8).map(|b| fse.frequency(b)).sum();
+            assert_eq!(total, 1 << log);
+            assert_eq!(fse.decode(&fse.encode(&data).unwrap(), data.len()).unwrap(), data);
+        }
+
+        let all: HashMap<u8, u64> = (0..=255u8).map(|b| (b, 1)).collect();
+        assert_eq!(Fse::from_frequencies(&all, 7).err(), Some(BuildError::MaxLengthTooSmall));
+        assert_eq!(Fse::from_frequencies(&HashMap::new(), 8).err(), Some(BuildError::EmptyAlphabet));
+        assert_eq!(Fse::new(b"ab").encode(b"abc").err(), Some(EncodeError { symbol: b'c', pos: 2 }));
+    }
+
+    #[test]
+    fn test_compression() {
+        // p(a) = 0.95: Huffman can't go below a bit per symbol
+        let data: Vec<u8> = (0..20_000).map(|i| if i % 20 == 0 { b'b' } else { b'a' }).collect();
+        let encoded = Fse::new(&data).encode(&data).unwrap();
+        let (_, huffman_bits) = ByteHuffman::new_bytes(&data).encode_bytes_packed(&data);
+        assert!(encoded.len() * 8 < huffman_bits / 3);
+
+        // and stays close to rANS on ordinary text
+        let text = b"It is a truth universally acknowledged, that a single man in possession \
+                     of a good fortune, must be in want of a wife.".repeat(50);
+        let fse = Fse::new(&text).encode(&text).unwrap().len();
+        let rans = Rans::new(&text).encode(&text).unwrap().len();
+        assert!(fse < rans + rans / 50);
+    }
+
+    #[test]
+    fn test_corrupt_streams() {
+        let data = b"fifty liquors yeah good".repeat(10);
+        let fse = Fse::new(&data);
+        let encoded = fse.encode(&data).unwrap();
+        assert_eq!(fse.decode(&encoded[..encoded.len() / 2], data.len()), Err(FseError::Truncated));
+        assert_eq!(fse.decode(&encoded, data.len() - 1), Err(FseError::Corrupt));
+    }
+
+}
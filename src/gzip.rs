@@ -0,0 +1,402 @@
+// gzip files (RFC 1952): one or more members, each a header, a raw DEFLATE
+// stream from the deflate module and a trailer of the data's CRC-32 and its
+// length mod 2^32, both little-endian. The header written is
+//   1f 8b, CM = 8 (deflate), FLG, MTIME u32, XFL = 0, OS = 255 (unknown)
+// followed by the zero-terminated file name and comment when set. Reading
+// also accepts (and skips) FEXTRA and checks FHCRC.
+//
+// Members decompress to the concatenation of their data; the header that's
+// returned is the first member's.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::checksum::crc32;
+use crate::deflate::{deflate, inflate_prefix_with, DeflateError};
+use crate::limits::{DecodeOptions, Limit, LimitExceeded};
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+const CM_DEFLATE: u8 = 8;
+const OS_UNKNOWN: u8 = 255;
+
+const FTEXT: u8 = 1;
+const FHCRC: u8 = 2;
+const FEXTRA: u8 = 4;
+const FNAME: u8 = 8;
+const FCOMMENT: u8 = 16;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GzHeader {
+    // Modification time of the original file in Unix seconds, 0 for none
+    pub mtime: u32,
+    // Original file name, ISO 8859-1 by the RFC, without a NUL
+    pub filename: Option<Vec<u8>>,
+    pub comment: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GzError {
+    Truncated,
+    NotGzip,                 // the magic bytes are missing
+    UnsupportedMethod(u8),   // CM other than deflate
+    ReservedFlags,           // FLG bits 5-7 set
+    HeaderCrcMismatch,
+    Deflate(DeflateError),
+    CrcMismatch,
+    LengthMismatch,          // ISIZE disagrees with the data
+}
+
+impl fmt::Display for GzError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GzError::Truncated => write!(f, "gzip stream is truncated"),
+            GzError::NotGzip => write!(f, "not a gzip stream"),
+            GzError::UnsupportedMethod(cm) => write!(f, "unsupported gzip compression method {}", cm),
+            GzError::ReservedFlags => write!(f, "reserved gzip header flags are set"),
+            GzError::HeaderCrcMismatch => write!(f, "gzip header checksum mismatch"),
+            GzError::Deflate(e) => write!(f, "{}", e),
+            GzError::CrcMismatch => write!(f, "gzip data checksum mismatch"),
+            GzError::LengthMismatch => write!(f, "gzip data length mismatch"),
+        }
+    }
+}
+
+impl Error for GzError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GzError::Deflate(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DeflateError> for GzError {
+    fn from(e: DeflateError) -> Self {
+        match e {
+            DeflateError::Truncated => GzError::Truncated,
+            e => GzError::Deflate(e),
+        }
+    }
+}
+
+impl From<GzError> for io::Error {
+    fn from(e: GzError) -> Self {
+        let kind = match e {
+            GzError::Truncated => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+// A single-member gzip file holding `data`
+pub fn compress(data: &[u8], header: &GzHeader) -> Vec<u8> {
+    let mut flags = 0;
+    if header.filename.is_some() {
+        flags |= FNAME;
+    }
+    if header.comment.is_some() {
+        flags |= FCOMMENT;
+    }
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&[CM_DEFLATE, flags]);
+    out.extend_from_slice(&header.mtime.to_le_bytes());
+    out.extend_from_slice(&[0, OS_UNKNOWN]);
+    for field in [&header.filename, &header.comment].iter().filter_map(|f| f.as_ref()) {
+        // a NUL would end the field early
+        out.extend(field.iter().filter(|&&b| b != 0));
+        out.push(0);
+    }
+    out.extend(deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+pub fn decompress(bytes: &[u8]) -> Result<(GzHeader, Vec<u8>), GzError> {
+    decompress_with(bytes, &DecodeOptions::new())
+}
+
+// decompress, with the output of all the members together held to
+// `limits` (deflate::inflate_with)
+pub fn decompress_with(bytes: &[u8], limits: &DecodeOptions) -> Result<(GzHeader, Vec<u8>), GzError> {
+    let mut rest = bytes;
+    let mut first = None;
+    let mut out = Vec::new();
+    loop {
+        let header = read_member(&mut rest, &mut out, limits)?;
+        first.get_or_insert(header);
+        if rest.is_empty() {
+            return Ok((first.unwrap(), out));
+        }
+    }
+}
+
+// Decompress one member onto `out`
+fn read_member(rest: &mut &[u8], out: &mut Vec<u8>, limits: &DecodeOptions) -> Result<GzHeader, GzError> {
+    let start = *rest;
+    let fixed = take(rest, 10)?;
+    if fixed[..2] != MAGIC {
+        return Err(GzError::NotGzip);
+    }
+    if fixed[2] != CM_DEFLATE {
+        return Err(GzError::UnsupportedMethod(fixed[2]));
+    }
+    let flags = fixed[3];
+    if flags & !(FTEXT | FHCRC | FEXTRA | FNAME | FCOMMENT) != 0 {
+        return Err(GzError::ReservedFlags);
+    }
+    let mut header = GzHeader { mtime: u32::from_le_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]), ..GzHeader::default() };
+    if flags & FEXTRA != 0 {
+        let xlen = take(rest, 2)?;
+        take(rest, u16::from_le_bytes([xlen[0], xlen[1]]) as usize)?;
+    }
+    if flags & FNAME != 0 {
+        header.filename = Some(take_cstr(rest)?.to_vec());
+    }
+    if flags & FCOMMENT != 0 {
+        header.comment = Some(take_cstr(rest)?.to_vec());
+    }
+    if flags & FHCRC != 0 {
+        let len = start.len() - rest.len();
+        let crc = take(rest, 2)?;
+        if u16::from_le_bytes([crc[0], crc[1]]) != crc32(&start[..len]) as u16 {
+            return Err(GzError::HeaderCrcMismatch);
+        }
+    }
+
+    // what's left of the limit after the members before this one, though
+    // the error gives the limit as set
+    let max = limits.get_max_output_bytes();
+    let left = limits.max_output_bytes(max - out.len() as u64);
+    let (data, used) = inflate_prefix_with(rest, &left).map_err(|e| match e {
+        DeflateError::LimitExceeded(_) => DeflateError::LimitExceeded(LimitExceeded { limit: Limit::OutputBytes, max }),
+        e => e,
+    })?;
+    *rest = &rest[used..];
+    let trailer = take(rest, 8)?;
+    if u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) != crc32(&data) {
+        return Err(GzError::CrcMismatch);
+    }
+    if u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]) != data.len() as u32 {
+        return Err(GzError::LengthMismatch);
+    }
+    out.extend(data);
+    Ok(header)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], GzError> {
+    if bytes.len() < n {
+        return Err(GzError::Truncated);
+    }
+    let (head, tail) = bytes.split_at(n);
+    *bytes = tail;
+    Ok(head)
+}
+
+// A zero-terminated field, without the NUL
+fn take_cstr<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8], GzError> {
+    let len = bytes.iter().position(|&b| b == 0).ok_or(GzError::Truncated)?;
+    let field = take(bytes, len + 1)?;
+    Ok(&field[..len])
+}
+
+// Writes a gzip file of everything written to it. DEFLATE here is one-shot,
+// so the data is held until finish() and compressed then.
+pub struct GzEncoder<W: Write> {
+    // Only None after finish() has handed the writer back
+    inner: Option<W>,
+    header: GzHeader,
+    buf: Vec<u8>,
+    finished: bool,
+}
+
+impl<W: Write> GzEncoder<W> {
+
+    pub fn new(inner: W, header: GzHeader) -> Self {
+        GzEncoder { inner: Some(inner), header, buf: Vec::new(), finished: false }
+    }
+
+    pub fn header(&self) -> &GzHeader {
+        &self.header
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    // Compress and write out everything; later writes are an error.
+    // Dropping the encoder does this too, ignoring errors.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        let out = compress(&self.buf, &self.header);
+        self.get_mut().write_all(&out)?;
+        self.buf = Vec::new();
+        self.finished = true;
+        self.get_mut().flush()
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+}
+
+impl<W: Write> Write for GzEncoder<W> {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other("write after finish"));
+        }
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    // Nothing can go out before finish()
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+}
+
+impl<W: Write> Drop for GzEncoder<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+// Reads the decompressed data of a gzip file. The whole file is read and
+// checked on the first read() call; errors are io::Errors carrying a
+// GzError, UnexpectedEof for a truncated file.
+pub struct GzDecoder<R: Read> {
+    inner: R,
+    header: Option<GzHeader>,
+    out: Vec<u8>,
+    pos: usize,
+    limits: DecodeOptions,
+}
+
+impl<R: Read> GzDecoder<R> {
+
+    pub fn new(inner: R) -> Self {
+        GzDecoder { inner, header: None, out: Vec::new(), pos: 0, limits: DecodeOptions::new() }
+    }
+
+    // Decompress with decompress_with and these limits
+    pub fn limits(mut self, limits: DecodeOptions) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // The first member's header, once something has been read
+    pub fn header(&self) -> Option<&GzHeader> {
+        self.header.as_ref()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+}
+
+impl<R: Read> Read for GzDecoder<R> {
+
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.header.is_none() {
+            let mut bytes = Vec::new();
+            self.inner.read_to_end(&mut bytes)?;
+            let (header, data) = decompress_with(&bytes, &self.limits)?;
+            self.header = Some(header);
+            self.out = data;
+        }
+        let n = out.len().min(self.out.len() - self.pos);
+        out[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{compress, decompress, decompress_with, GzDecoder, GzEncoder, GzError, GzHeader};
+    use crate::deflate::DeflateError;
+    use crate::limits::{DecodeOptions, Limit, LimitExceeded};
+    use std::io::{self, Read, Write};
+
+    #[test]
+    fn test_gzip_file() {
+        // `printf 'hello, gzip\n' > hello.txt; gzip -9 hello.txt`
+        let file = [0x1f, 0x8b, 0x08, 0x08, 0x40, 0x2e, 0x5f, 0x65, 0x02, 0x03, 0x68, 0x65, 0x6c, 0x6c,
+                    0x6f, 0x2e, 0x74, 0x78, 0x74, 0x00, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0x48,
+                    0xaf, 0xca, 0x2c, 0xe0, 0x02, 0x00, 0x86, 0x1f, 0x82, 0xa4, 0x0c, 0x00, 0x00, 0x00];
+        let (header, data) = decompress(&file).unwrap();
+        assert_eq!(data, b"hello, gzip\n");
+        assert_eq!(header.filename.as_deref(), Some(&b"hello.txt"[..]));
+        assert_eq!(header.mtime, 0x655f2e40);
+
+        let mut bad = file;
+        bad[34] ^= 1;
+        assert_eq!(decompress(&bad), Err(GzError::CrcMismatch));
+        assert_eq!(decompress(&file[..file.len() - 1]), Err(GzError::Truncated));
+        assert_eq!(decompress(&file[1..]), Err(GzError::NotGzip));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let header = GzHeader { mtime: 1_700_000_000, filename: Some(b"notes.txt".to_vec()), comment: Some(b"hi".to_vec()) };
+        let data = b"gzip wraps deflate, gzip wraps deflate, gzip wraps deflate".repeat(50);
+        let file = compress(&data, &header);
+        assert_eq!(decompress(&file).unwrap(), (header.clone(), data.clone()));
+
+        // two members make one stream
+        let both = [file.clone(), compress(b"!", &GzHeader::default())].concat();
+        assert_eq!(decompress(&both).unwrap().1, [&data[..], b"!"].concat());
+
+        let mut encoder = GzEncoder::new(Vec::new(), header.clone());
+        for chunk in data.chunks(100) {
+            encoder.write_all(chunk).unwrap();
+        }
+        let written = encoder.finish().unwrap();
+        assert_eq!(written, file);
+        let mut decoder = GzDecoder::new(&written[..]);
+        let mut out = Vec::new();
+        decoder.read_to_end(&mut out).unwrap();
+        assert_eq!((decoder.header(), out), (Some(&header), data));
+
+        let err = GzDecoder::new(&written[..20]).read(&mut [0; 8]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_limits() {
+        let data = b"abc".repeat(1000);
+        let file = compress(&data, &GzHeader::default());
+        // the limit covers every member together
+        let both = [file.clone(), file].concat();
+        let over = GzError::Deflate(DeflateError::LimitExceeded(LimitExceeded { limit: Limit::OutputBytes, max: 5000 }));
+        assert_eq!(decompress_with(&both, &DecodeOptions::new().max_output_bytes(5000)), Err(over));
+        assert_eq!(decompress_with(&both, &DecodeOptions::new().max_output_bytes(6000)).unwrap().1.len(), 6000);
+
+        let mut decoder = GzDecoder::new(&both[..]).limits(DecodeOptions::new().max_output_bytes(5999));
+        let err = decoder.read(&mut [0; 8]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(crate::Error::from(err), crate::Error::LimitExceeded(_)));
+    }
+
+}
@@ -0,0 +1,100 @@
+// A fast non-cryptographic hasher for the crate's symbol maps. std's
+// SipHash guards against inputs crafted to collide, at a cost that
+// dominates an encoder looking up a codeword per symbol; FxHash (rustc's,
+// from Firefox) is a rotate, an xor and a multiply per word.
+//
+// The codeword maps of HuffmanCode and CanonicalHuffman use it. Their keys
+// are the alphabet a code was built for, so collisions can only be forced
+// by someone choosing the training data; frequency maps coming in from
+// callers are std HashMaps, hashed however the caller likes.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+
+pub type FxBuildHasher = BuildHasherDefault<FxHasher>;
+pub type FxHashMap<K, V> = HashMap<K, V, FxBuildHasher>;
+
+const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FxHasher {
+    hash: u64,
+}
+
+impl FxHasher {
+
+    #[inline]
+    fn add(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(SEED);
+    }
+
+}
+
+impl Hasher for FxHasher {
+
+    fn write(&mut self, bytes: &[u8]) {
+        let mut words = bytes.chunks_exact(8);
+        for word in &mut words {
+            let mut arr = [0u8; 8];
+            arr.copy_from_slice(word);
+            self.add(u64::from_le_bytes(arr));
+        }
+        let rest = words.remainder();
+        if !rest.is_empty() {
+            let mut arr = [0u8; 8];
+            arr[..rest.len()].copy_from_slice(rest);
+            // the length keeps "a" and "a\0" apart
+            self.add(u64::from_le_bytes(arr) ^ ((rest.len() as u64) << 56));
+        }
+    }
+
+    #[inline]
+    fn write_u8(&mut self, i: u8) {
+        self.add(i as u64);
+    }
+
+    #[inline]
+    fn write_u16(&mut self, i: u16) {
+        self.add(i as u64);
+    }
+
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.add(i as u64);
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.add(i);
+    }
+
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.add(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{FxBuildHasher, FxHashMap};
+    use std::hash::BuildHasher;
+
+    #[test]
+    fn test_hasher() {
+        let build = FxBuildHasher::default();
+        assert_eq!(build.hash_one(42u32), build.hash_one(42u32));
+        assert_ne!(build.hash_one(1u8), build.hash_one(2u8));
+        assert_ne!(build.hash_one("a"), build.hash_one("a\0"));
+        assert_ne!(build.hash_one(&b"abcdefgh"[..]), build.hash_one(&b"abcdefgi"[..]));
+
+        let map: FxHashMap<String, usize> = (0..1000).map(|i| (i.to_string(), i)).collect();
+        assert!((0..1000).all(|i| map[&i.to_string()] == i));
+    }
+
+}
@@ -0,0 +1,339 @@
+// Symbol counts over a whole input, the starting point for building any of
+// the crate's codes. Histograms of separate chunks or files merge by adding
+// counts (or with sum()), so a corpus can be scanned in pieces and reduced
+// afterwards. FrequencyTable is the running counterpart for adaptive use.
+//
+// Byte counting, which dominates the first pass of two-pass compression,
+// goes through count_bytes: four partial tables take a byte each in turn,
+// so runs of one byte don't queue up on a single counter, and are merged at
+// the end. With the `simd` feature on x86_64, blocks of 16 equal bytes are
+// also spotted with SSE2 and counted at once.
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::iter::{FromIterator, Sum};
+
+use crate::huffman::Symbol;
+use crate::probability::{ProbError, ProbTable};
+
+// Bytes counted into the u32 partial tables before they're added to the
+// totals, well inside their range
+const CHUNK: usize = 1 << 24;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram<S: Symbol = u8> {
+    counts: HashMap<S, u64>,
+    total: u64,
+}
+
+impl<S: Symbol> Histogram<S> {
+
+    pub fn new() -> Self {
+        Histogram { counts: HashMap::new(), total: 0 }
+    }
+
+    pub fn add(&mut self, sym: S) {
+        self.add_n(sym, 1);
+    }
+
+    // Counts saturate rather than overflow
+    pub fn add_n(&mut self, sym: S, n: u64) {
+        if n == 0 {
+            return;
+        }
+        let count = self.counts.entry(sym).or_insert(0);
+        *count = count.saturating_add(n);
+        self.total = self.total.saturating_add(n);
+    }
+
+    pub fn merge(&mut self, other: &Histogram<S>) {
+        for (sym, &n) in &other.counts {
+            self.add_n(sym.clone(), n);
+        }
+    }
+
+    pub fn count(&self, sym: &S) -> u64 {
+        self.counts.get(sym).cloned().unwrap_or(0)
+    }
+
+    // Sum of all counts
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    // Number of distinct symbols seen
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    // 0.0 for an empty histogram
+    pub fn probability(&self, sym: &S) -> f64 {
+        if self.total == 0 { 0.0 } else { self.count(sym) as f64 / self.total as f64 }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(&S, u64)> + '_ {
+        self.counts.iter().map(|(sym, &n)| (sym, n))
+    }
+
+    // The k most frequent symbols, most frequent first; ties go to the
+    // smaller symbol so the answer doesn't depend on hashing
+    pub fn top_k(&self, k: usize) -> Vec<(S, u64)> {
+        let mut entries: Vec<(&S, u64)> = self.iter().collect();
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        entries.into_iter().take(k).map(|(sym, n)| (sym.clone(), n)).collect()
+    }
+
+    // The symbols in ascending order, and a ProbTable over them (symbol i
+    // of the table is the i-th of them)
+    pub fn prob_table(&self, precision: u32) -> Result<(Vec<S>, ProbTable), ProbError> {
+        let mut entries: Vec<(&S, u64)> = self.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        let counts: Vec<u64> = entries.iter().map(|&(_, n)| n).collect();
+        let table = ProbTable::from_counts(&counts, precision)?;
+        Ok((entries.into_iter().map(|(sym, _)| sym.clone()).collect(), table))
+    }
+
+    // Counts scaled to sum to exactly 2^scale_bits with every seen symbol
+    // kept at one or more, in ascending symbol order: the form the rANS and
+    // FSE coders work from. None if the histogram is empty or has more
+    // symbols than 2^scale_bits.
+    pub fn normalize(&self, scale_bits: u32) -> Option<Vec<(S, u32)>> {
+        let (symbols, table) = self.prob_table(scale_bits).ok()?;
+        Some(symbols.into_iter().zip(table.freqs().iter().cloned()).collect())
+    }
+
+    // In the form the HuffmanCode constructors take
+    pub fn as_map(&self) -> &HashMap<S, u64> {
+        &self.counts
+    }
+
+    pub fn into_map(self) -> HashMap<S, u64> {
+        self.counts
+    }
+
+}
+
+impl Histogram<u8> {
+
+    pub fn from_bytes(data: &[u8]) -> Self {
+        Histogram::from(count_bytes(data))
+    }
+
+    // Count every byte `input` yields
+    pub fn from_reader<R: Read>(mut input: R) -> io::Result<Self> {
+        let mut counts = [0u64; 256];
+        let mut buf = [0u8; 1 << 16];
+        loop {
+            let n = match input.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            add_bytes(&mut counts, &buf[..n]);
+        }
+        Ok(Histogram::from(counts))
+    }
+
+    // Dense form, indexed by byte
+    pub fn to_array(&self) -> [u64; 256] {
+        let mut counts = [0u64; 256];
+        for (&b, n) in self.iter() {
+            counts[b as usize] = n;
+        }
+        counts
+    }
+
+}
+
+// Occurrences of each byte, indexed by byte
+pub fn count_bytes(data: &[u8]) -> [u64; 256] {
+    let mut counts = [0u64; 256];
+    add_bytes(&mut counts, data);
+    counts
+}
+
+// count_bytes, adding to counts already taken
+pub fn add_bytes(counts: &mut [u64; 256], data: &[u8]) {
+    let mut tables = [[0u32; 256]; 4];
+    for chunk in data.chunks(CHUNK) {
+        count_chunk(&mut tables, chunk);
+        for table in tables.iter_mut() {
+            for (total, n) in counts.iter_mut().zip(table.iter_mut()) {
+                *total += *n as u64;
+                *n = 0;
+            }
+        }
+    }
+}
+
+// The bytes that occur, with their counts: what the byte coders train on
+pub(crate) fn byte_frequencies(data: &[u8]) -> HashMap<u8, u64> {
+    count_bytes(data).iter().enumerate().filter(|&(_, &n)| n > 0).map(|(b, &n)| (b as u8, n)).collect()
+}
+
+fn count_chunk(tables: &mut [[u32; 256]; 4], data: &[u8]) {
+    let mut blocks = data.chunks_exact(16);
+    for block in &mut blocks {
+        #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+        {
+            if let Some(b) = simd::uniform(block) {
+                tables[0][b as usize] += 16;
+                continue;
+            }
+        }
+        for quad in block.chunks_exact(4) {
+            tables[0][quad[0] as usize] += 1;
+            tables[1][quad[1] as usize] += 1;
+            tables[2][quad[2] as usize] += 1;
+            tables[3][quad[3] as usize] += 1;
+        }
+    }
+    for &b in blocks.remainder() {
+        tables[0][b as usize] += 1;
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod simd {
+
+    use std::arch::x86_64::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8};
+
+    // The byte a 16-byte block is made of, if it's all one
+    pub(super) fn uniform(block: &[u8]) -> Option<u8> {
+        assert_eq!(block.len(), 16);
+        // SAFETY: SSE2 is always there on x86_64, the block holds the 16
+        // bytes read, and loadu doesn't need them aligned
+        let mask = unsafe {
+            let v = _mm_loadu_si128(block.as_ptr() as *const __m128i);
+            _mm_movemask_epi8(_mm_cmpeq_epi8(v, _mm_set1_epi8(block[0] as i8)))
+        };
+        if mask == 0xffff {
+            Some(block[0])
+        } else {
+            None
+        }
+    }
+
+}
+
+impl<S: Symbol> Default for Histogram<S> {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
+
+impl From<[u64; 256]> for Histogram<u8> {
+    fn from(counts: [u64; 256]) -> Self {
+        let mut hist = Histogram::new();
+        for (b, &n) in counts.iter().enumerate() {
+            hist.add_n(b as u8, n);
+        }
+        hist
+    }
+}
+
+// Zero counts are dropped
+impl<S: Symbol> From<HashMap<S, u64>> for Histogram<S> {
+    fn from(mut counts: HashMap<S, u64>) -> Self {
+        counts.retain(|_, n| *n > 0);
+        let total = counts.values().fold(0u64, |acc, &n| acc.saturating_add(n));
+        Histogram { counts, total }
+    }
+}
+
+impl<S: Symbol> FromIterator<S> for Histogram<S> {
+    fn from_iter<I: IntoIterator<Item=S>>(symbols: I) -> Self {
+        let mut hist = Histogram::new();
+        hist.extend(symbols);
+        hist
+    }
+}
+
+impl<S: Symbol> Extend<S> for Histogram<S> {
+    fn extend<I: IntoIterator<Item=S>>(&mut self, symbols: I) {
+        for sym in symbols {
+            self.add(sym);
+        }
+    }
+}
+
+// The reduce step of a map-reduce scan
+impl<S: Symbol> Sum for Histogram<S> {
+    fn sum<I: Iterator<Item=Histogram<S>>>(parts: I) -> Self {
+        parts.fold(Histogram::new(), |mut acc, part| {
+            acc.merge(&part);
+            acc
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{add_bytes, count_bytes, Histogram};
+    use crate::huffman::ByteHuffman;
+
+    #[test]
+    fn test_counts() {
+        let text = b"abracadabra";
+        let hist: Histogram = text.iter().cloned().collect();
+        assert_eq!((hist.count(&b'a'), hist.count(&b'z'), hist.total(), hist.len()), (5, 0, 11, 5));
+        assert_eq!(hist, Histogram::from_reader(&text[..]).unwrap());
+        assert_eq!(hist.to_array()[b'r' as usize], 2);
+        assert_eq!(hist.top_k(3), vec![(b'a', 5), (b'b', 2), (b'r', 2)]);
+        assert!((hist.probability(&b'a') - 5.0 / 11.0).abs() < 1e-12);
+
+        // chunks counted separately add up to the whole
+        let parts: Histogram = text.chunks(3).map(|c| c.iter().cloned().collect::<Histogram>()).sum();
+        assert_eq!(parts, hist);
+        let code = ByteHuffman::from_frequencies(hist.as_map());
+        assert_eq!(code.code_for(&b'a').unwrap().len(), 1);
+
+        let words: Histogram<&str> = "to be or not to be".split(' ').collect();
+        assert_eq!(words.top_k(1), vec![("be", 2)]);
+    }
+
+    #[test]
+    fn test_count_bytes() {
+        // runs long and short, across block edges, and a ragged tail
+        let mut data: Vec<u8> = (0..10_000u32).map(|i| (i * 31 % 257) as u8).collect();
+        data.extend(vec![7; 100]);
+        data.extend(b"abc".repeat(33));
+        data.extend(vec![0xff; 17]);
+        for len in [0, 1, 15, 16, 17, 100, data.len()] {
+            let mut expected = [0u64; 256];
+            for &b in &data[..len] {
+                expected[b as usize] += 1;
+            }
+            assert_eq!(count_bytes(&data[..len]), expected);
+        }
+        let once = count_bytes(&data);
+        let mut twice = once;
+        add_bytes(&mut twice, &data);
+        assert!(once.iter().zip(twice.iter()).all(|(&a, &b)| b == 2 * a));
+        assert_eq!(Histogram::from_bytes(&data), data.iter().cloned().collect());
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut hist: Histogram = b"aaaaaaaaab".iter().cloned().collect();
+        hist.add_n(b'c', 1_000_000);
+        let freqs = hist.normalize(12).unwrap();
+        assert_eq!(freqs.iter().map(|&(_, q)| q).sum::<u32>(), 1 << 12);
+        assert!(freqs.iter().all(|&(_, q)| q >= 1));
+        assert_eq!(freqs.iter().map(|&(b, _)| b).collect::<Vec<_>>(), b"abc");
+
+        assert_eq!(Histogram::<u8>::new().normalize(12), None);
+        let all: Histogram = (0..=255u8).collect();
+        assert_eq!(all.normalize(7), None);
+        assert!(all.normalize(8).unwrap().iter().all(|&(_, q)| q == 1));
+        let (symbols, table) = hist.prob_table(12).unwrap();
+        assert_eq!((symbols, table.freq(2)), (b"abc".to_vec(), freqs[2].1));
+    }
+
+}
@@ -1,140 +1,1329 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, OnceLock};
+
+use crate::bitio::{self, BitReader, BitWriter, Bits};
+use crate::canonical::{self, CanonicalHuffman};
+use crate::checksum;
+use crate::codes::varint;
+use crate::frequency::FrequencyTable;
+use crate::hash::FxHashMap;
+use crate::histogram::{self, Histogram};
+use crate::limits::{DecodeOptions, LimitExceeded};
+use crate::profiles::Profile;
+use crate::stats::CodeAudit;
+
+// Anything that can be Huffman coded: chars, bytes, u16 tokens, words,
+// k-mers, enum variants... Ord is needed so ties between equally frequent
+// symbols are broken the same way every time (see Queued).
+pub trait Symbol: Eq + Hash + Ord + Clone {}
+
+impl<T: Eq + Hash + Ord + Clone> Symbol for T {}
+
+// Symbols with a numeric value of at most 32 bits, which is how they're
+// written by serialize_table().
+pub trait ScalarSymbol: Symbol {
+    // Bits needed for any value, e.g. for writing one raw
+    const BITS: u32 = 32;
+    // Recorded in the table header, so a table isn't read back as codes
+    // over a different alphabet
+    const ALPHABET: Alphabet = Alphabet::U32;
+    fn to_u32(&self) -> u32;
+    fn from_u32(v: u32) -> Option<Self>;
+}
+
+impl ScalarSymbol for char {
+    const BITS: u32 = 21;
+    const ALPHABET: Alphabet = Alphabet::Char;
+    fn to_u32(&self) -> u32 { *self as u32 }
+    fn from_u32(v: u32) -> Option<Self> { std::char::from_u32(v) }
+}
+
+impl ScalarSymbol for u8 {
+    const BITS: u32 = 8;
+    const ALPHABET: Alphabet = Alphabet::Byte;
+    fn to_u32(&self) -> u32 { *self as u32 }
+    fn from_u32(v: u32) -> Option<Self> { if v <= 0xff { Some(v as u8) } else { None } }
+}
+
+impl ScalarSymbol for u16 {
+    const BITS: u32 = 16;
+    const ALPHABET: Alphabet = Alphabet::U16;
+    fn to_u32(&self) -> u32 { *self as u32 }
+    fn from_u32(v: u32) -> Option<Self> { if v <= 0xffff { Some(v as u16) } else { None } }
+}
+
+impl ScalarSymbol for u32 {
+    fn to_u32(&self) -> u32 { *self }
+    fn from_u32(v: u32) -> Option<Self> { Some(v) }
+}
+
+// The symbol type a serialized table was written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alphabet {
+    Char = 0,
+    Byte = 1,
+    U16 = 2,
+    U32 = 3,
+}
+
+impl Alphabet {
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Alphabet::Char),
+            1 => Some(Alphabet::Byte),
+            2 => Some(Alphabet::U16),
+            3 => Some(Alphabet::U32),
+            _ => None,
+        }
+    }
 
-struct HNode {
-    freq: i32,
-    ch: Option<char>, 
-    left: Option<Box<HNode>>,
-    right: Option<Box<HNode>>,
 }
 
-impl HNode {
+impl fmt::Display for Alphabet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Alphabet::Char => "char",
+            Alphabet::Byte => "byte",
+            Alphabet::U16 => "u16",
+            Alphabet::U32 => "u32",
+        })
+    }
+}
+
+// The interface shared by the static prefix codes in this crate (Huffman,
+// Shannon-Fano), so one can stand in for the other and both can be
+// measured on the same input.
+pub trait PrefixCode<S: Symbol> {
+
+    fn codeword(&self, sym: &S) -> Option<&Bits>;
+
+    // Decoding stops quietly at a trailing partial codeword
+    fn decode_bits(&self, bits: &Bits) -> Vec<S>;
+
+    // Panics on a symbol without a codeword
+    fn encode_bits(&self, symbols: &[S]) -> Bits {
+        let mut ret = Bits::default();
+        for sym in symbols {
+            ret.append(self.codeword(sym).expect("symbol has no codeword"));
+        }
+        ret
+    }
 
-    pub fn new(freq: i32, ch: Option<char>) -> Self {
+    // The same as '0'/'1' strings
+    fn encode_symbols(&self, symbols: &[S]) -> String {
+        self.encode_bits(symbols).to_bit_string()
+    }
+
+    fn decode_symbols(&self, s: &str) -> Vec<S> {
+        self.decode_bits(&s.chars().map(|x| x != '0').collect())
+    }
+
+    // Expected bits per symbol for a source with these frequencies;
+    // symbols without a codeword are left out
+    fn average_length(&self, freq: &HashMap<S, u64>) -> f64 {
+        let (bits, total) = freq.iter()
+            .filter_map(|(sym, &f)| self.codeword(sym).map(|c| (c.len() as u64 * f, f)))
+            .fold((0, 0), |(bits, total), (b, f)| (bits + b, total + f));
+        if total == 0 { 0.0 } else { bits as f64 / total as f64 }
+    }
+
+}
+
+const NONE: usize = usize::MAX;
+
+// A headerless table starts with its u32 symbol count, which would have to
+// be over a billion for its first bytes to read "HT".
+const TABLE_MAGIC: [u8; 2] = *b"HT";
+const TABLE_VERSION: u8 = 1;
+
+// Tree nodes live in one Vec and refer to each other by index, so a deep
+// tree is neither a chain of allocations nor a chain of recursive drops.
+#[derive(Clone)]
+struct HNode<S> {
+    freq: u64,
+    sym: Option<S>,
+    children: [usize; 2], // by bit; NONE where there's no child
+}
+
+impl<S> HNode<S> {
+
+    pub fn new(freq: u64, sym: Option<S>) -> Self {
         HNode {
-            freq: freq, ch: ch,
-            left: None, right: None,
+            freq, sym,
+            children: [NONE; 2],
+        }
+    }
+
+}
+
+// What to do with a symbol that has no codeword in the code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownSymbolPolicy<S = char> {
+    Error,     // fail with an EncodeError
+    Skip,      // drop the symbol from the output
+    Escape(S), // emit the codeword of the given (known) escape symbol
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodeError<S = char> {
+    pub symbol: S,
+    pub pos: usize, // index of `symbol` in the input, counted in symbols
+}
+
+impl<S: fmt::Debug> fmt::Display for EncodeError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no codeword for {:?} at position {}", self.symbol, self.pos)
+    }
+}
+
+impl<S: fmt::Debug> Error for EncodeError<S> {}
+
+// Reasons a serialized code table can't be turned back into a code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableError {
+    Truncated,             // ran out of bytes mid-table
+    InvalidSymbol,         // symbol bytes aren't valid UTF-8 / scalar values
+    DuplicateSymbol(char), // the same symbol was given two codewords
+    Oversubscribed,        // codeword lengths violate the Kraft inequality
+    NotPrefixFree,         // one codeword is a prefix of another
+    EmptyCodeword,         // a codeword of no bits, which decodes without reading any
+    UnsupportedVersion(u8),   // table header from a newer format
+    // the table was written for a different symbol type
+    WrongAlphabet { expected: Alphabet, found: u8 },
+    ChecksumMismatch,         // table bytes don't match the CRC in the header
+    LimitExceeded(LimitExceeded), // more symbols or deeper codewords than allowed
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TableError::Truncated => write!(f, "code table is truncated"),
+            TableError::InvalidSymbol => write!(f, "code table contains an invalid symbol"),
+            TableError::DuplicateSymbol(ch) => write!(f, "symbol {:?} appears twice in code table", ch),
+            TableError::Oversubscribed => write!(f, "code table lengths are oversubscribed"),
+            TableError::NotPrefixFree => write!(f, "code table is not a prefix code"),
+            TableError::EmptyCodeword => write!(f, "code table has an empty codeword"),
+            TableError::UnsupportedVersion(v) => write!(f, "unsupported code table version {}", v),
+            TableError::WrongAlphabet { expected, found } => match Alphabet::from_id(*found) {
+                Some(found) => write!(f, "code table is over {} symbols, not {}", found, expected),
+                None => write!(f, "code table has unknown alphabet type {}", found),
+            },
+            TableError::ChecksumMismatch => write!(f, "code table checksum mismatch"),
+            TableError::LimitExceeded(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for TableError {}
+
+impl From<LimitExceeded> for TableError {
+    fn from(e: LimitExceeded) -> Self {
+        TableError::LimitExceeded(e)
+    }
+}
+
+// Why a bit string doesn't decode. Positions count bits from the start of
+// the input and point at the first bit of the offending codeword.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    TruncatedCodeword { pos: usize }, // the input ends partway through a codeword
+    InvalidBitstream { pos: usize },  // no codeword starts with these bits
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::TruncatedCodeword { pos } => write!(f, "input ends inside the codeword at bit {}", pos),
+            DecodeError::InvalidBitstream { pos } => write!(f, "no codeword matches the bits at {}", pos),
         }
     }
+}
 
+impl Error for DecodeError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    EmptyAlphabet,      // nothing to build a code for
+    MaxLengthTooSmall,  // 2^max_len codewords can't cover the alphabet
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::EmptyAlphabet => write!(f, "can't build a code over an empty alphabet"),
+            BuildError::MaxLengthTooSmall => write!(f, "maximum codeword length too small for the alphabet"),
+        }
+    }
 }
 
-pub struct HuffmanCode {
+impl Error for BuildError {}
+
+// A code never changes once built, so its tables are shared: cloning one
+// is a few reference counts, and a code trained once can be handed to any
+// number of threads (it's Send + Sync whenever the symbols are).
+#[derive(Clone)]
+pub struct HuffmanCode<S: Symbol = char> {
     // The input distribution underlying a particular Huffman code
-    // is provided via the `data` field, currently just the basis string.
-    // TODO: provide a representation of the underlying _frequency map_
-    // and change HuffmanCode::new() to take a freq_map rather than &str.
-    data: String,  
-    root: Box<HNode>,
-    code: HashMap<char, String>
+    // is kept as the frequency map the tree was built from.
+    freqs: Arc<HashMap<S, u64>>,
+    // Decoding tree; `root` indexes into `nodes`
+    nodes: Arc<[HNode<S>]>,
+    root: usize,
+    code: Arc<FxHashMap<S, Bits>>,
+    // Codewords indexed by symbol value, for scalar symbols below 256;
+    // built on first use (see DenseCode)
+    dense: Arc<OnceLock<Option<DenseCode>>>,
 }
 
-impl HuffmanCode {
-    
-    pub fn new(s: &str) -> Self {
-        let root = generate_tree(s);
-        let mut code: HashMap<char, String> = HashMap::new();
-        assign_codes(&root, &mut code, "".to_string());
-        
-        HuffmanCode { data: s.to_string(), 
-                      root: root, 
-                      code: code 
+pub type CharHuffman = HuffmanCode<char>;
+pub type ByteHuffman = HuffmanCode<u8>;
+
+impl<S: Symbol> HuffmanCode<S> {
+
+    // Panics on an empty sequence; see try_from_symbols
+    pub fn from_symbols<I: IntoIterator<Item=S>>(symbols: I) -> Self {
+        HuffmanCode::try_from_symbols(symbols).expect("can't build a Huffman code for empty input")
+    }
+
+    pub fn try_from_symbols<I: IntoIterator<Item=S>>(symbols: I) -> Result<Self, BuildError> {
+        let mut freqs = HashMap::new();
+        for sym in symbols {
+            *freqs.entry(sym).or_insert(0) += 1;
         }
+        HuffmanCode::try_from_frequencies(&freqs)
     }
 
-    pub fn encode_string(&self, s: &str) -> String {
-        
-        let mut ret = "".to_string();
-        let mut token: Option<&String>;
-        
-        for ch in s.chars() {
-            token = self.code.get(&ch);
-            ret.push_str(token.unwrap());
+    // Build a code from an externally computed distribution, e.g. counts
+    // gathered over a larger corpus than the strings being encoded.
+    // Panics on an empty map; see try_from_frequencies
+    pub fn from_frequencies(freq: &HashMap<S, u64>) -> Self {
+        HuffmanCode::try_from_frequencies(freq).expect("can't build a Huffman code without symbols")
+    }
+
+    pub fn try_from_frequencies(freq: &HashMap<S, u64>) -> Result<Self, BuildError> {
+        if freq.is_empty() {
+            return Err(BuildError::EmptyAlphabet);
+        }
+        let (nodes, root) = generate_tree(freq);
+        let code = assign_codes(&nodes, root);
+
+        Ok(HuffmanCode::assemble(freq.clone(), nodes, root, code))
+    }
+
+    // Optimal prefix code among those whose codewords are at most `max_len`
+    // bits long (15 for DEFLATE, 16 for JPEG), found with the package-merge
+    // algorithm of Larmore and Hirschberg. Codewords are assigned
+    // canonically from the resulting lengths.
+    pub fn with_max_length(freq: &HashMap<S, u64>, max_len: u8) -> Result<Self, BuildError> {
+        if freq.is_empty() {
+            return Err(BuildError::EmptyAlphabet);
+        }
+        let mut leaves: Vec<(u64, &S)> = freq.iter().map(|(sym, f)| (*f, sym)).collect();
+        leaves.sort();
+        let n = leaves.len();
+        if max_len == 0 || (max_len < 64 && (1u64 << max_len) < n as u64) {
+            return Err(BuildError::MaxLengthTooSmall);
+        }
+
+        let lengths: Vec<(S, u8)> = if n == 1 {
+            vec![(leaves[0].1.clone(), 1)]
+        } else {
+            // no Huffman code is deeper than n - 1, so a larger limit changes nothing
+            let max_len = (max_len as usize).min(n - 1);
+            let weights: Vec<u64> = leaves.iter().map(|&(f, _)| f).collect();
+            package_merge(&weights, max_len).into_iter()
+                .zip(leaves.iter())
+                .map(|(len, &(_, sym))| (sym.clone(), len))
+                .collect()
+        };
+
+        let code: FxHashMap<S, Bits> = canonical::canonical_codewords(&lengths)
+            .expect("package-merge lengths satisfy the Kraft inequality")
+            .into_iter()
+            .collect();
+        let (nodes, root) = tree_from_codes(&code).expect("canonical codewords form a prefix code");
+        Ok(HuffmanCode::assemble(freq.clone(), nodes, root, code))
+    }
+
+    // Replace this code with one built from `table`, e.g. counts gathered
+    // from recent traffic. The code is left as it was on error.
+    pub fn rebuild(&mut self, table: &FrequencyTable<S>) -> Result<(), BuildError> {
+        *self = HuffmanCode::try_from_frequencies(table.as_map())?;
+        Ok(())
+    }
+
+    // Check this code against `table`: whether it's a prefix code, how much
+    // of the code space it uses, and whether it's as short on average as a
+    // Huffman code built from the table. For tables from outside (from_table,
+    // serde) before trusting them with data distributed like `table`.
+    pub fn verify(&self, table: &FrequencyTable<S>) -> CodeAudit<S> {
+        let mut codewords: Vec<String> = self.code.values().map(|c| c.to_bit_string()).collect();
+        codewords.sort();
+        // sorted, a codeword is directly followed by any it's a prefix of
+        let prefix_free = codewords.windows(2).all(|pair| !pair[1].starts_with(&pair[0]));
+
+        let mut counts = vec![0u64; codewords.last().map_or(0, |c| c.len()) + 1];
+        for c in &codewords {
+            counts[c.len()] += 1;
+        }
+        let kraft_sum = counts.iter().enumerate().map(|(len, &n)| n as f64 * 0.5f64.powi(len as i32)).sum();
+        // codewords still free at each length, as in canonical::satisfies_kraft
+        let mut left: u64 = 1;
+        let mut over = false;
+        for (len, &n) in counts.iter().enumerate() {
+            if len > 0 {
+                match left.checked_mul(2) {
+                    Some(doubled) => left = doubled,
+                    // 2^64 free codewords are more than the code has left to use
+                    None => break,
+                }
+            }
+            if left < n {
+                over = true;
+                break;
+            }
+            left -= n;
+        }
+
+        let mut missing = Vec::new();
+        let (mut bits, mut covered) = (0u128, 0u64);
+        for (sym, f) in table.iter() {
+            match self.code.get(sym) {
+                Some(c) => {
+                    bits += f as u128 * c.len() as u128;
+                    covered += f;
+                }
+                None => missing.push(sym.clone()),
+            }
+        }
+        missing.sort();
+        let optimal_bits: u128 = HuffmanCode::try_from_frequencies(table.as_map()).map_or(0, |h| {
+            table.iter().map(|(sym, f)| f as u128 * h.code[sym].len() as u128).sum()
+        });
+        let per_symbol = |bits: u128, n: u64| if n == 0 { 0.0 } else { bits as f64 / n as f64 };
+        CodeAudit {
+            prefix_free,
+            kraft_sum,
+            complete: !over && left == 0,
+            optimal: missing.is_empty() && bits == optimal_bits,
+            missing,
+            expected_length: per_symbol(bits, covered),
+            optimal_length: per_symbol(optimal_bits, table.total()),
+        }
+    }
+
+    // Note that a code rebuilt by from_table() only knows its codewords,
+    // so its frequency map is empty.
+    pub fn frequencies(&self) -> &HashMap<S, u64> {
+        &self.freqs
+    }
+
+    pub(crate) fn codewords(&self) -> &FxHashMap<S, Bits> {
+        &self.code
+    }
+
+    fn assemble(freqs: HashMap<S, u64>, nodes: Vec<HNode<S>>, root: usize, code: FxHashMap<S, Bits>) -> Self {
+        HuffmanCode { freqs: Arc::new(freqs), nodes: nodes.into(), root, code: Arc::new(code), dense: Arc::default() }
+    }
+
+    // For codes built some other way (Shannon-Fano), to get the decoding
+    // tree. The codewords must be prefix-free.
+    pub(crate) fn from_codewords(freqs: HashMap<S, u64>, code: HashMap<S, Bits>) -> Self {
+        HuffmanCode::try_from_codewords(freqs, code).expect("codewords form a prefix code")
+    }
+
+    // For codewords from outside (serde), which may not be a prefix code
+    pub(crate) fn try_from_codewords(freqs: HashMap<S, u64>, code: HashMap<S, Bits>)
+        -> Result<Self, TableError> {
+        let (nodes, root) = tree_from_codes(&code)?;
+        Ok(HuffmanCode::assemble(freqs, nodes, root, code.into_iter().collect()))
+    }
+
+    // The codeword for `sym`, if it has one
+    pub fn code_for(&self, sym: &S) -> Option<&Bits> {
+        self.code.get(sym)
+    }
+
+    // (symbol, codeword length) for every symbol, shortest codewords first
+    // and ties in symbol order; enough to rebuild the code canonically
+    pub fn code_lengths(&self) -> impl Iterator<Item=(S, usize)> + '_ {
+        let mut lengths: Vec<(usize, &S)> = self.code.iter().map(|(sym, c)| (c.len(), sym)).collect();
+        lengths.sort();
+        lengths.into_iter().map(|(len, sym)| (sym.clone(), len))
+    }
+
+    // The symbols that have codewords, in order
+    pub fn alphabet(&self) -> Vec<&S> {
+        let mut symbols: Vec<&S> = self.code.keys().collect();
+        symbols.sort();
+        symbols
+    }
+
+    // Panics on a symbol without a codeword; see try_encode_bits
+    pub fn encode_bits(&self, symbols: &[S]) -> Bits {
+        self.try_encode_bits(symbols, UnknownSymbolPolicy::Error)
+            .unwrap_or_else(|e| panic!("no codeword for the symbol at position {}", e.pos))
+    }
+
+    pub fn try_encode_bits(&self, symbols: &[S], policy: UnknownSymbolPolicy<S>) -> Result<Bits, EncodeError<S>> {
+
+        let mut ret = Bits::default();
+
+        for (pos, sym) in symbols.iter().enumerate() {
+            let token = match (self.code.get(sym), &policy) {
+                (Some(token), _) => token,
+                (None, UnknownSymbolPolicy::Skip) => continue,
+                (None, UnknownSymbolPolicy::Escape(esc)) => {
+                    // an escape symbol the code can't express is no better than none
+                    self.code.get(esc).ok_or_else(|| EncodeError { symbol: sym.clone(), pos })?
+                }
+                (None, UnknownSymbolPolicy::Error) => return Err(EncodeError { symbol: sym.clone(), pos }),
+            };
+            ret.append(token);
+        }
+        Ok(ret)
+    }
+
+    // Decoding stops quietly at anything that isn't a codeword; see
+    // try_decode_bits
+    pub fn decode_bits(&self, bits: &Bits) -> Vec<S> {
+        self.decode_stream(bits.iter())
+    }
+
+    // The bits must split into whole codewords
+    pub fn try_decode_bits(&self, bits: &Bits) -> Result<Vec<S>, DecodeError> {
+        self.try_decode_stream(bits.iter())
+    }
+
+    // The same as '0'/'1' strings, for display and tests.
+    // Panics on a symbol without a codeword; see try_encode_symbols
+    pub fn encode_symbols(&self, symbols: &[S]) -> String {
+        self.encode_bits(symbols).to_bit_string()
+    }
+
+    pub fn try_encode_symbols(&self, symbols: &[S], policy: UnknownSymbolPolicy<S>)
+        -> Result<String, EncodeError<S>> {
+        Ok(self.try_encode_bits(symbols, policy)?.to_bit_string())
+    }
+
+    pub fn decode_symbols(&self, s: &str) -> Vec<S> {
+        self.decode_stream(s.chars().map(|x| x != '0'))
+    }
+
+    // Every character must be '0' or '1'
+    pub fn try_decode_symbols(&self, s: &str) -> Result<Vec<S>, DecodeError> {
+        if let Some(pos) = s.chars().position(|x| x != '0' && x != '1') {
+            return Err(DecodeError::InvalidBitstream { pos });
+        }
+        self.try_decode_stream(s.chars().map(|x| x == '1'))
+    }
+
+    // Packed counterpart of encode_symbols: codewords are written MSB-first
+    // into bytes, and the number of meaningful bits is returned alongside
+    // since the final byte is zero-padded.
+    // Panics on a symbol without a codeword; see try_encode_symbols_packed
+    pub fn encode_symbols_packed(&self, symbols: &[S]) -> (Vec<u8>, usize) {
+        self.try_encode_symbols_packed(symbols)
+            .unwrap_or_else(|e| panic!("no codeword for the symbol at position {}", e.pos))
+    }
+
+    pub fn try_encode_symbols_packed(&self, symbols: &[S]) -> Result<(Vec<u8>, usize), EncodeError<S>> {
+        if let Some(pos) = symbols.iter().position(|sym| !self.code.contains_key(sym)) {
+            return Err(EncodeError { symbol: symbols[pos].clone(), pos });
+        }
+        Ok(bitio::concat(symbols.iter().map(|sym| &self.code[sym])))
+    }
+
+    pub fn decode_symbols_packed(&self, bytes: &[u8], nbits: usize) -> Vec<S> {
+        self.decode_stream(bitio::unpack(bytes, nbits))
+    }
+
+    // An `nbits` beyond the end of `bytes` counts as truncation
+    pub fn try_decode_symbols_packed(&self, bytes: &[u8], nbits: usize) -> Result<Vec<S>, DecodeError> {
+        let ret = self.try_decode_stream(bitio::unpack(bytes, nbits))?;
+        if nbits > bytes.len() * 8 {
+            return Err(DecodeError::TruncatedCodeword { pos: bytes.len() * 8 });
+        }
+        Ok(ret)
+    }
+
+    // Lazy counterpart of encode_symbols_packed: bytes come out as soon as
+    // the symbols pulled so far fill them, and the last one is zero-padded.
+    // The iterator panics on a symbol without a codeword.
+    pub fn encode_iter<I: IntoIterator<Item=S>>(&self, symbols: I) -> EncodeIter<'_, S, I::IntoIter> {
+        EncodeIter { code: self, symbols: symbols.into_iter(), codeword: None, bit: 0, nbits: 0 }
+    }
+
+    // Lazy counterpart of decode_symbols_packed. Without a bit count the
+    // padding of the final byte may decode as extra symbols, so take() as
+    // many as were encoded. Like decode_symbols it stops quietly at bits
+    // that don't complete a codeword.
+    pub fn decode_iter<I: IntoIterator<Item=u8>>(&self, bytes: I) -> DecodeIter<'_, S, I::IntoIter> {
+        DecodeIter { code: self, bytes: bytes.into_iter(), byte: 0, left: 0 }
+    }
+
+    // Emit the codeword for one symbol; a symbol outside the code is an
+    // InvalidInput error.
+    pub fn write_symbol<W: Write>(&self, sym: &S, out: &mut BitWriter<W>) -> io::Result<()> {
+        let codeword = self.code.get(sym).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "symbol has no codeword")
+        })?;
+        write_codeword(out, codeword)
+    }
+
+    // Read bits until they spell out a whole codeword
+    pub fn read_symbol<R: Read>(&self, input: &mut BitReader<R>) -> io::Result<S> {
+        let mut node = self.root;
+        loop {
+            if let Some(ref sym) = self.nodes[node].sym {
+                return Ok(sym.clone());
+            }
+            node = self.nodes[node].children[input.read_bit()? as usize];
+            if node == NONE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "bits don't match any codeword"));
+            }
+        }
+    }
+
+    fn decode_stream<I: Iterator<Item=bool>>(&self, bits: I) -> Vec<S> {
+
+        let mut ret = Vec::new();
+        let mut node = self.root;
+
+        for x in bits {
+            // walk left for 0, right for 1; a missing child leaves us in place
+            let next = self.nodes[node].children[x as usize];
+            if next != NONE {
+                node = next;
+            }
+            if let Some(ref sym) = self.nodes[node].sym {
+                ret.push(sym.clone());
+                node = self.root;
+            }
         }
         ret
     }
 
-    pub fn decode_string(&self, s: &str) -> String {
+    fn try_decode_stream<I: Iterator<Item=bool>>(&self, bits: I) -> Result<Vec<S>, DecodeError> {
+        let mut ret = Vec::new();
+        let mut node = self.root;
+        let mut start = 0; // where the current codeword began
+        let mut partial = false;
+
+        for (pos, x) in bits.enumerate() {
+            node = self.nodes[node].children[x as usize];
+            if node == NONE {
+                return Err(DecodeError::InvalidBitstream { pos: start });
+            }
+            partial = true;
+            if let Some(ref sym) = self.nodes[node].sym {
+                ret.push(sym.clone());
+                node = self.root;
+                start = pos + 1;
+                partial = false;
+            }
+        }
+        if partial {
+            return Err(DecodeError::TruncatedCodeword { pos: start });
+        }
+        Ok(ret)
+    }
+
+}
 
-        let mut ret = "".to_string();
-        let mut node = &self.root;
+// Pictures of the tree, for teaching and debugging. Symbols are shown with
+// their Debug form; frequencies only when the code has them (a code read
+// from a table doesn't).
+impl<S: Symbol + fmt::Debug> HuffmanCode<S> {
 
-        for x in s.chars() {
-            if x == '0' { // walk left for 0
-                if let Some(ref l) = node.left {
-                    node = l;
+    // Graphviz source; render with e.g. `dot -Tsvg`. Edges are labelled
+    // with their bit, leaves with symbol, frequency and codeword.
+    pub fn to_dot(&self) -> String {
+        let mut out = "digraph huffman {\n    node [fontname=\"monospace\"];\n".to_string();
+        let mut next_id = 1;
+        let mut stack = vec![(self.root, 0, String::new())];
+        while let Some((i, id, codeword)) = stack.pop() {
+            let node = &self.nodes[i];
+            if let Some(ref sym) = node.sym {
+                let mut label = dot_escape(&format!("{:?}", sym));
+                if let Some(f) = self.freqs.get(sym) {
+                    label += &format!("\\n{}", f);
                 }
+                label += &format!("\\n{}", codeword);
+                out += &format!("    n{} [shape=box, label=\"{}\"];\n", id, label);
+                continue;
+            }
+            let label = if self.freqs.is_empty() { String::new() } else { node.freq.to_string() };
+            out += &format!("    n{} [shape=circle, label=\"{}\"];\n", id, label);
+            // edges in bit order, so dot draws 0 on the left; the stack then
+            // takes the right child first to visit the left subtree first
+            let mut children = Vec::new();
+            for (bit, &child) in ['0', '1'].iter().zip(node.children.iter()) {
+                if child != NONE {
+                    out += &format!("    n{} -> n{} [label=\"{}\"];\n", id, next_id, bit);
+                    children.push((child, next_id, format!("{}{}", codeword, bit)));
+                    next_id += 1;
+                }
+            }
+            stack.extend(children.into_iter().rev());
+        }
+        out += "}\n";
+        out
+    }
+
+    // The tree sideways in plain ASCII, 0 branches above 1 branches:
+    //
+    //   (6)
+    //   +-0- 'c' (3) = 0
+    //   `-1- (3)
+    //        +-0- 'a' (1) = 10
+    //        `-1- 'b' (2) = 11
+    pub fn to_ascii_tree(&self) -> String {
+        let mut out = String::new();
+        // (node, indent for its children, line prefix, codeword)
+        let mut stack = vec![(self.root, String::new(), String::new(), String::new())];
+        while let Some((i, indent, prefix, codeword)) = stack.pop() {
+            let node = &self.nodes[i];
+            out += &prefix;
+            if let Some(ref sym) = node.sym {
+                out += &format!("{:?}", sym);
+                if let Some(f) = self.freqs.get(sym) {
+                    out += &format!(" ({})", f);
+                }
+                out += &format!(" = {}\n", codeword);
+                continue;
+            }
+            if self.freqs.is_empty() {
+                out += "*\n";
             } else {
-                if let Some(ref r) = node.right {
-                    node = r; // else (1), walk right
+                out += &format!("({})\n", node.freq);
+            }
+            let children: Vec<(char, usize)> = ['0', '1'].iter().cloned()
+                .zip(node.children.iter().cloned())
+                .filter(|&(_, child)| child != NONE)
+                .collect();
+            for (i, &(bit, child)) in children.iter().enumerate().rev() {
+                let last = i + 1 == children.len();
+                let branch = if last { '`' } else { '+' };
+                let rail = if last { ' ' } else { '|' };
+                stack.push((child,
+                            format!("{}{}    ", indent, rail),
+                            format!("{}{}-{}- ", indent, branch, bit),
+                            format!("{}{}", codeword, bit)));
+            }
+        }
+        out
+    }
+
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl<S: Symbol> PrefixCode<S> for HuffmanCode<S> {
+
+    fn codeword(&self, sym: &S) -> Option<&Bits> {
+        self.code.get(sym)
+    }
+
+    fn decode_bits(&self, bits: &Bits) -> Vec<S> {
+        HuffmanCode::decode_bits(self, bits)
+    }
+
+    fn encode_bits(&self, symbols: &[S]) -> Bits {
+        HuffmanCode::encode_bits(self, symbols)
+    }
+
+}
+
+impl<S: ScalarSymbol> HuffmanCode<S> {
+
+    // The byte-indexed codewords, if every symbol's value is below 256
+    fn dense(&self) -> Option<&DenseCode> {
+        self.dense.get_or_init(|| DenseCode::new(&self.code)).as_ref()
+    }
+
+    // encode_symbols_packed for symbols straight from an iterator, through
+    // the byte-indexed codewords where there are any
+    fn try_encode_scalars_packed<I>(&self, symbols: I) -> Result<(Vec<u8>, usize), EncodeError<S>>
+        where I: Iterator<Item=S> + Clone {
+        match self.dense() {
+            Some(dense) => dense.pack(symbols),
+            None => self.try_encode_symbols_packed(&symbols.collect::<Vec<S>>()),
+        }
+    }
+
+    // Serialize the code itself, so data can be decoded somewhere the basis
+    // string isn't available. Layout (all integers big-endian):
+    //   magic    2 bytes "HT"
+    //   version  u8 (TABLE_VERSION)
+    //   alphabet u8 (Alphabet::id of S)
+    //   crc      u32 CRC-32 of everything after it
+    //   u32 number of symbols
+    //   per symbol: u32 symbol value, codeword length as a LEB128 varint,
+    //               then the codeword packed MSB-first into ceil(len/8) bytes
+    // Codewords are stored verbatim rather than as canonical lengths, so the
+    // result decodes exactly what this code encoded.
+    pub fn serialize_table(&self) -> Vec<u8> {
+        let mut entries: Vec<(u32, &Bits)> = self.code.iter().map(|(s, c)| (s.to_u32(), c)).collect();
+        entries.sort();
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for (v, codeword) in entries {
+            body.extend_from_slice(&v.to_be_bytes());
+            varint::write_u64(&mut body, codeword.len() as u64);
+            body.extend_from_slice(codeword.as_bytes());
+        }
+
+        let mut out = TABLE_MAGIC.to_vec();
+        out.push(TABLE_VERSION);
+        out.push(S::ALPHABET.id());
+        out.extend_from_slice(&checksum::crc32(&body).to_be_bytes());
+        out.extend_from_slice(&body);
+        out
+    }
+
+    // Also reads tables written before the header existed, which start
+    // straight with the symbol count.
+    pub fn from_table(bytes: &[u8]) -> Result<Self, TableError> {
+        HuffmanCode::from_table_with(bytes, &DecodeOptions::new())
+    }
+
+    // from_table, refusing tables with more symbols or longer codewords
+    // than `limits` allows
+    pub fn from_table_with(bytes: &[u8], limits: &DecodeOptions) -> Result<Self, TableError> {
+        let mut rest = bytes;
+        if rest.starts_with(&TABLE_MAGIC) {
+            rest = &rest[TABLE_MAGIC.len()..];
+            let [version, alphabet] = take_array(&mut rest)?;
+            if version != TABLE_VERSION {
+                return Err(TableError::UnsupportedVersion(version));
+            }
+            if alphabet != S::ALPHABET.id() {
+                return Err(TableError::WrongAlphabet { expected: S::ALPHABET, found: alphabet });
+            }
+            let crc = u32::from_be_bytes(take_array(&mut rest)?);
+            if checksum::crc32(rest) != crc {
+                return Err(TableError::ChecksumMismatch);
+            }
+        }
+        let n = u32::from_be_bytes(take_array(&mut rest)?);
+        limits.check_table_symbols(n)?;
+
+        let mut code = FxHashMap::default();
+        for _ in 0..n {
+            let v = u32::from_be_bytes(take_array(&mut rest)?);
+            let sym = S::from_u32(v).ok_or(TableError::InvalidSymbol)?;
+            // a length past 64 bits can't fit in what's left either
+            let len = varint::read_u64(&mut rest).map_err(|_| TableError::Truncated)?;
+            limits.check_depth(len)?;
+            if len.div_ceil(8) > rest.len() as u64 {
+                return Err(TableError::Truncated);
+            }
+            let nbytes = len.div_ceil(8) as usize;
+            let codeword = Bits::new(rest[..nbytes].to_vec(), len as usize);
+            rest = &rest[nbytes..];
+            if code.insert(sym, codeword).is_some() {
+                // report the value as a char where it is one
+                let ch = std::char::from_u32(v).unwrap_or(std::char::REPLACEMENT_CHARACTER);
+                return Err(TableError::DuplicateSymbol(ch));
+            }
+        }
+
+        let (nodes, root) = tree_from_codes(&code)?;
+        Ok(HuffmanCode::assemble(HashMap::new(), nodes, root, code))
+    }
+
+}
+
+impl HuffmanCode<char> {
+
+    // Panics on an empty string; see try_new
+    pub fn new(s: &str) -> Self {
+        HuffmanCode::try_new(s).expect("can't build a Huffman code for an empty string")
+    }
+
+    pub fn try_new(s: &str) -> Result<Self, BuildError> {
+        HuffmanCode::try_from_frequencies(s.chars().collect::<Histogram<char>>().as_map())
+    }
+
+    // Panics on a character without a codeword; see try_encode
+    pub fn encode_string(&self, s: &str) -> String {
+        self.try_encode(s).expect("character has no codeword")
+    }
+
+    pub fn decode_string(&self, s: &str) -> String {
+        self.decode_symbols(s).into_iter().collect()
+    }
+
+    pub fn try_decode_string(&self, s: &str) -> Result<String, DecodeError> {
+        Ok(self.try_decode_symbols(s)?.into_iter().collect())
+    }
+
+    // Keep only the codeword lengths of this code and reassign codewords
+    // canonically (see the canonical module).
+    pub fn to_canonical(&self) -> CanonicalHuffman {
+        // Canonical lengths are single bytes, which only a hand-made table
+        // can outgrow; that panics.
+        use std::convert::TryFrom;
+        let lengths: Vec<(char, u8)> = self.code.iter()
+            .map(|(&ch, c)| (ch, u8::try_from(c.len()).expect("canonical codewords are at most 255 bits")))
+            .collect();
+        CanonicalHuffman::from_lengths(&lengths)
+            .expect("a Huffman tree always satisfies the Kraft inequality")
+    }
+
+    pub fn try_encode(&self, s: &str) -> Result<String, EncodeError> {
+        self.try_encode_with(s, UnknownSymbolPolicy::Error)
+    }
+
+    pub fn try_encode_with(&self, s: &str, policy: UnknownSymbolPolicy)
+        -> Result<String, EncodeError> {
+        let chars: Vec<char> = s.chars().collect();
+        self.try_encode_symbols(&chars, policy)
+    }
+
+    // Panics on a character without a codeword; see try_encode_packed
+    pub fn encode_packed(&self, s: &str) -> (Vec<u8>, usize) {
+        self.try_encode_packed(s).expect("character has no codeword")
+    }
+
+    pub fn try_encode_packed(&self, s: &str) -> Result<(Vec<u8>, usize), EncodeError> {
+        self.try_encode_scalars_packed(s.chars())
+    }
+
+    pub fn decode_packed(&self, bytes: &[u8], nbits: usize) -> String {
+        self.decode_symbols_packed(bytes, nbits).into_iter().collect()
+    }
+
+    pub fn try_decode_packed(&self, bytes: &[u8], nbits: usize) -> Result<String, DecodeError> {
+        Ok(self.try_decode_symbols_packed(bytes, nbits)?.into_iter().collect())
+    }
+
+}
+
+impl HuffmanCode<u8> {
+
+    // Byte-oriented construction for binary payloads, which never go
+    // through a lossy UTF-8 conversion.
+    // Panics on empty input; see try_new_bytes
+    pub fn new_bytes(data: &[u8]) -> Self {
+        HuffmanCode::try_new_bytes(data).expect("can't build a Huffman code for empty input")
+    }
+
+    pub fn try_new_bytes(data: &[u8]) -> Result<Self, BuildError> {
+        HuffmanCode::try_from_frequencies(&histogram::byte_frequencies(data))
+    }
+
+    // A fixed code from built-in frequencies, for messages too short to be
+    // worth sending a table with. Covers every byte.
+    pub fn for_profile(profile: Profile) -> Self {
+        HuffmanCode::from_frequencies(&profile.frequencies())
+    }
+
+    pub fn encode_bytes(&self, data: &[u8]) -> String {
+        self.encode_symbols(data)
+    }
+
+    pub fn decode_bytes(&self, s: &str) -> Vec<u8> {
+        self.decode_symbols(s)
+    }
+
+    // Panics on a byte without a codeword; see try_encode_bytes_packed
+    pub fn encode_bytes_packed(&self, data: &[u8]) -> (Vec<u8>, usize) {
+        self.try_encode_bytes_packed(data)
+            .unwrap_or_else(|e| panic!("no codeword for the symbol at position {}", e.pos))
+    }
+
+    pub fn try_encode_bytes_packed(&self, data: &[u8]) -> Result<(Vec<u8>, usize), EncodeError<u8>> {
+        self.try_encode_scalars_packed(data.iter().cloned())
+    }
+
+    // write_symbol without a hash lookup or a bit at a time
+    pub fn write_byte<W: Write>(&self, b: u8, out: &mut BitWriter<W>) -> io::Result<()> {
+        match self.dense() {
+            Some(dense) => match dense.codes[b as usize] {
+                (_, 0) => Err(io::Error::new(io::ErrorKind::InvalidInput, "symbol has no codeword")),
+                (bits, len) => out.write_bits(bits, len as u32),
+            },
+            None => self.write_symbol(&b, out),
+        }
+    }
+
+    pub fn decode_bytes_packed(&self, bytes: &[u8], nbits: usize) -> Vec<u8> {
+        self.decode_symbols_packed(bytes, nbits)
+    }
+
+    pub fn try_decode_bytes(&self, s: &str) -> Result<Vec<u8>, DecodeError> {
+        self.try_decode_symbols(s)
+    }
+
+    pub fn try_decode_bytes_packed(&self, bytes: &[u8], nbits: usize) -> Result<Vec<u8>, DecodeError> {
+        self.try_decode_symbols_packed(bytes, nbits)
+    }
+
+}
+
+pub struct EncodeIter<'a, S: Symbol, I> {
+    code: &'a HuffmanCode<S>,
+    symbols: I,
+    codeword: Option<&'a Bits>, // the codeword being written out
+    bit: usize,                 // next bit of it
+    nbits: usize,
+}
+
+impl<S: Symbol, I> EncodeIter<'_, S, I> {
+
+    // Meaningful bits in the bytes produced so far; once the iterator is
+    // exhausted, the `nbits` to decode them with
+    pub fn bit_len(&self) -> usize {
+        self.nbits
+    }
+
+}
+
+impl<S: Symbol, I: Iterator<Item=S>> Iterator for EncodeIter<'_, S, I> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let mut byte = 0u8;
+        let mut filled = 0;
+        while filled < 8 {
+            match self.codeword {
+                Some(c) if self.bit < c.len() => {
+                    byte |= (c.get(self.bit) as u8) << (7 - filled);
+                    self.bit += 1;
+                    filled += 1;
                 }
+                _ => match self.symbols.next() {
+                    Some(sym) => {
+                        self.codeword = Some(self.code.code.get(&sym).expect("symbol has no codeword"));
+                        self.bit = 0;
+                    }
+                    None => {
+                        self.codeword = None;
+                        break;
+                    }
+                },
             }
-            if let Some(ch) = node.ch {
-                ret.push(ch);
-                node = &self.root;
+        }
+        self.nbits += filled;
+        if filled == 0 { None } else { Some(byte) }
+    }
+}
+
+pub struct DecodeIter<'a, S: Symbol, I> {
+    code: &'a HuffmanCode<S>,
+    bytes: I,
+    byte: u8,
+    left: u32, // unread bits of `byte`, from the top
+}
+
+impl<S: Symbol, I: Iterator<Item=u8>> Iterator for DecodeIter<'_, S, I> {
+    type Item = S;
+
+    fn next(&mut self) -> Option<S> {
+        let nodes = &self.code.nodes;
+        let mut node = self.code.root;
+        loop {
+            if let Some(ref sym) = nodes[node].sym {
+                return Some(sym.clone());
+            }
+            let x = self.take_bit()?;
+            node = nodes[node].children[x as usize];
+            if node == NONE {
+                return None;
             }
         }
-        ret
     }
+}
+
+impl<S: Symbol, I: Iterator<Item=u8>> DecodeIter<'_, S, I> {
+
+    fn take_bit(&mut self) -> Option<bool> {
+        if self.left == 0 {
+            self.byte = self.bytes.next()?;
+            self.left = 8;
+        }
+        self.left -= 1;
+        Some(self.byte >> self.left & 1 != 0)
+    }
+
+}
 
+// Every codeword of a scalar code over values below 256, right-aligned in
+// a u64 next to its length (0 for none), so a hot encoding loop indexes an
+// array instead of hashing. Codes with a codeword over MAX_DENSE_LEN bits
+// don't get one; they'd overflow pack()'s accumulator.
+struct DenseCode {
+    codes: [(u64, u8); 256],
 }
 
-fn freq_map(s: &str) -> HashMap<char, i32> {
-    let mut freq_map = HashMap::new();
-    for ch in s.chars() {
-        let count = freq_map.entry(ch).or_insert(0); // Get prior occurrences of character (initialize to 0 if none)
-        *count += 1;
+const MAX_DENSE_LEN: usize = 56;
+
+impl DenseCode {
+
+    fn new<S: ScalarSymbol>(code: &FxHashMap<S, Bits>) -> Option<Self> {
+        let mut codes = [(0u64, 0u8); 256];
+        for (sym, codeword) in code {
+            let slot = codes.get_mut(sym.to_u32() as usize)?;
+            if codeword.is_empty() || codeword.len() > MAX_DENSE_LEN {
+                return None;
+            }
+            *slot = (codeword.iter().fold(0, |v, bit| v << 1 | bit as u64), codeword.len() as u8);
+        }
+        Some(DenseCode { codes })
     }
-    freq_map
+
+    // Symbols to MSB-first packed bytes and their bit count, as
+    // bitio::concat does for codewords
+    fn pack<S: ScalarSymbol, I: Iterator<Item=S> + Clone>(&self, symbols: I)
+        -> Result<(Vec<u8>, usize), EncodeError<S>> {
+        let lookup = |sym: &S| self.codes.get(sym.to_u32() as usize).filter(|&&(_, len)| len > 0);
+        // sizing the output is a pass over the symbols anyway, so check them on it
+        let mut nbits = 0;
+        for (pos, sym) in symbols.clone().enumerate() {
+            match lookup(&sym) {
+                Some(&(_, len)) => nbits += len as usize,
+                None => return Err(EncodeError { symbol: sym, pos }),
+            }
+        }
+        let mut out = Vec::with_capacity(nbits.div_ceil(8));
+        let mut acc = 0u64; // pending bits in the low `pending`, junk above
+        let mut pending = 0u32;
+        for sym in symbols {
+            let (bits, len) = *lookup(&sym).expect("checked above");
+            acc = acc << len | bits;
+            pending += len as u32;
+            while pending >= 8 {
+                pending -= 8;
+                out.push((acc >> pending) as u8);
+            }
+        }
+        if pending > 0 {
+            out.push((acc << (8 - pending)) as u8);
+        }
+        Ok((out, nbits))
+    }
+
 }
 
-fn generate_tree(s: &str) -> Box<HNode> {
-    // Build frequency table
-    let freq_map = freq_map(s);
+// Write a codeword bit by bit
+pub(crate) fn write_codeword<W: Write>(out: &mut BitWriter<W>, codeword: &Bits) -> io::Result<()> {
+    for x in codeword.iter() {
+        out.write_bit(x)?;
+    }
+    Ok(())
+}
+
+fn take_array<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], TableError> {
+    if bytes.len() < N {
+        return Err(TableError::Truncated);
+    }
+    let (head, tail) = bytes.split_at(N);
+    *bytes = tail;
+    let mut arr = [0; N];
+    arr.copy_from_slice(head);
+    Ok(arr)
+}
+
+// Priority queue entry for tree construction. BinaryHeap is a max-heap, so
+// the ordering is reversed to pop the least frequent node first.
+//
+// Ties are broken by the node's index in the arena, which makes the code a
+// function of the frequencies alone (not of HashMap iteration order):
+// leaves are stored in ascending symbol order, and merged nodes after them
+// in the order they're created. Of two nodes with equal frequency the one
+// with the lower index is popped first, and the first node popped for a
+// merge becomes the left (0) child.
+#[derive(PartialEq, Eq)]
+struct Queued {
+    freq: u64,
+    node: usize,
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.freq, other.node).cmp(&(self.freq, self.node))
+    }
+}
+
+// The arena and the index of the root
+fn generate_tree<S: Symbol>(freq_map: &HashMap<S, u64>) -> (Vec<HNode<S>>, usize) {
+    let mut leaves: Vec<(&S, &u64)> = freq_map.iter().collect();
+    leaves.sort();
+
+    // a full binary tree over n leaves has 2n - 1 nodes, plus one for a lone leaf's parent
+    let mut nodes: Vec<HNode<S>> = Vec::with_capacity(2 * leaves.len());
+    nodes.extend(leaves.into_iter().map(|(k, v)| HNode::new(*v, Some(k.clone()))));
 
-    // Build nodelist
-    let mut nodes: Vec<Box<HNode>> = 
-            freq_map.iter()
-              .map(|(k,v)| Box::new(HNode::new(*v, Some(*k))))
-              .collect();
+    // Build the queue of leaves: O(n log n) overall rather than re-sorting
+    // the whole node list on every merge.
+    let mut queue: BinaryHeap<Queued> = nodes.iter().enumerate()
+        .map(|(node, n)| Queued { freq: n.freq, node })
+        .collect();
 
     // While there are nodes to merge...
-    while nodes.len() > 1 {
-        nodes.sort_by(|a, b| (&(b.freq)).cmp(&(a.freq)));
+    while queue.len() > 1 {
         // pop off the smallest two nodes...
-        let a = nodes.pop().unwrap();
-        let b = nodes.pop().unwrap();
+        let a = queue.pop().unwrap().node;
+        let b = queue.pop().unwrap().node;
         // ...create a new node with those two as its children...
-        let mut c = Box::new(HNode::new(a.freq + b.freq, None));
-        c.left = Some(a);
-        c.right = Some(b);
-        // ...and reinsert the merged node at the other end of the queue.
+        let mut c = HNode::new(nodes[a].freq + nodes[b].freq, None);
+        c.children = [a, b];
+        // ...and put the merged node back in the queue.
+        queue.push(Queued { freq: c.freq, node: nodes.len() });
         nodes.push(c);
     }
-    nodes.pop().unwrap()
+    let root = queue.pop().unwrap().node;
+
+    // A lone symbol would sit at the root with an empty codeword, which
+    // can't be told apart in a bitstream; hang it off a parent so it gets
+    // the 1-bit codeword "0" instead.
+    if nodes[root].sym.is_some() {
+        let mut parent = HNode::new(nodes[root].freq, None);
+        parent.children[0] = root;
+        nodes.push(parent);
+        return (nodes, root + 1);
+    }
+    (nodes, root)
 }
 
-fn assign_codes(node: &Box<HNode>, // call this function with node == your root node
-                codes: &mut HashMap<char, String>,
-                code: String ){
-    
-    // If HNode has a valid 'ch' field, it's a leaf (base case)
-    if let Some(ch) = node.ch {
-        codes.insert(ch, code);
-    } else { // walk the tree, appending l->0, r->1, until a leaf is reached
-        if let Some(ref l) = node.left {
-            assign_codes(l, codes, code.clone() + "0");
+// Package-merge: codeword lengths (at most `max_len`) minimizing the total
+// weighted length for `weights`, which must be sorted ascending and number
+// at least two. Think of every symbol as max_len coins, one per level; at
+// each level from the deepest up, adjacent pairs of the previous level's
+// list are packaged and merged with the symbols' own coins. The cheapest
+// 2n - 2 items of the final list are chosen, and a symbol's length is the
+// number of its coins among them.
+fn package_merge(weights: &[u64], max_len: usize) -> Vec<u8> {
+    let n = weights.len();
+    let leaves: Vec<(u128, Option<usize>)> =
+        weights.iter().enumerate().map(|(i, &w)| (w as u128, Some(i))).collect();
+
+    // levels[0] is the deepest level's list; None marks a package
+    let mut levels = vec![leaves.clone()];
+    for _ in 1..max_len {
+        let prev = levels.last().unwrap();
+        let packages: Vec<(u128, Option<usize>)> = prev.chunks_exact(2)
+            .map(|pair| (pair[0].0 + pair[1].0, None))
+            .collect();
+        let mut merged = Vec::with_capacity(n + packages.len());
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < packages.len() {
+            // on equal weight the symbol's own coin goes first
+            if j == packages.len() || (i < n && leaves[i].0 <= packages[j].0) {
+                merged.push(leaves[i]);
+                i += 1;
+            } else {
+                merged.push(packages[j]);
+                j += 1;
+            }
+        }
+        levels.push(merged);
+    }
+
+    // The packages chosen at one level always consist of a prefix of the
+    // level below it, so walking down only needs to track prefix lengths.
+    let mut lengths = vec![0u8; n];
+    let mut take = 2 * n - 2;
+    for level in levels.iter().rev() {
+        let mut packages = 0;
+        for item in &level[..take] {
+            match item.1 {
+                Some(i) => lengths[i] += 1,
+                None => packages += 1,
+            }
+        }
+        take = 2 * packages;
+    }
+    lengths
+}
+
+// Rebuild the tree a set of codewords describes, checking that they really
+// form a prefix code (no codeword passes through or ends on another's leaf).
+// The root is node 0.
+fn tree_from_codes<S: Symbol, H>(code: &HashMap<S, Bits, H>) -> Result<(Vec<HNode<S>>, usize), TableError> {
+    let mut nodes = vec![HNode::new(0, None)];
+    for (sym, codeword) in code {
+        // it would make the root a leaf, and reading a symbol take no bits:
+        // a decoder looping until its input runs out would never stop
+        if codeword.is_empty() {
+            return Err(TableError::EmptyCodeword);
         }
-        if let Some(ref r) = node.right {
-            assign_codes(r, codes, code.clone() + "1");
+        let mut node = 0;
+        for x in codeword.iter() {
+            if nodes[node].sym.is_some() {
+                return Err(TableError::NotPrefixFree);
+            }
+            if nodes[node].children[x as usize] == NONE {
+                nodes[node].children[x as usize] = nodes.len();
+                nodes.push(HNode::new(0, None));
+            }
+            node = nodes[node].children[x as usize];
         }
+        if nodes[node].sym.is_some() || nodes[node].children != [NONE; 2] {
+            return Err(TableError::NotPrefixFree);
+        }
+        nodes[node].sym = Some(sym.clone());
     }
+    Ok((nodes, 0))
+}
+
+// The codeword of every leaf below `root`, appending 0 for a left and 1 for
+// a right branch. Walks with an explicit stack: a skewed tree is about as
+// deep as the alphabet is big, which recursion can't be trusted with.
+fn assign_codes<S: Symbol>(nodes: &[HNode<S>], root: usize) -> FxHashMap<S, Bits> {
+    let mut codes = FxHashMap::default();
+    let mut stack = vec![(root, Bits::default())];
+    while let Some((node, code)) = stack.pop() {
+        // If HNode has a valid 'sym' field, it's a leaf
+        if let Some(ref sym) = nodes[node].sym {
+            codes.insert(sym.clone(), code);
+            continue;
+        }
+        for (bit, &child) in nodes[node].children.iter().enumerate() {
+            if child != NONE {
+                let mut next = code.clone();
+                next.push(bit == 1);
+                stack.push((child, next));
+            }
+        }
+    }
+    codes
 }
 
 #[cfg(test)]
 mod test {
     
-    use super::{HuffmanCode};
+    use super::{HuffmanCode, CharHuffman, ByteHuffman, UnknownSymbolPolicy, BuildError, DecodeError, EncodeError,
+                TableError, Alphabet, PrefixCode, assign_codes};
+    use crate::histogram::Histogram;
+    use crate::shannon_fano::ShannonFano;
+    use crate::bitio::{BitOrder, BitReader, BitWriter, Bits};
+    use crate::limits::DecodeOptions;
+    use crate::stats::CompressionReport;
     use itertools::Itertools;
+    use std::collections::HashMap;
+    use std::sync::Arc;
 
     #[test]
     fn test_compressor() {
@@ -147,41 +1336,541 @@ mod test {
     }
 
     fn attempt_compress(s: &str) {
-        let _s = s.clone();
+        let _s = s;
         let encoder = HuffmanCode::new(_s);
         let bin_seq = encoder.encode_string(_s);
         let decoded_str = encoder.decode_string(&bin_seq.clone());
-        // let nbits_in = 8*(_s.chars().count());
-        // let nbits_out = bin_seq.chars().count();
-        // let compression_ratio: f32 = nbits_out as f32 / nbits_in as f32;
-        // println!("Compression ratio: {}", compression_ratio);
+        let report = CompressionReport::for_str(&encoder, _s).unwrap();
+        assert_eq!(report.output_bits as usize, bin_seq.len());
+        assert!(report.ratio < 1.0);
         assert_eq!(_s, decoded_str);
     }
 
+    #[test]
+    fn test_from_frequencies() {
+        let s = "dagoth ur was a hotep";
+        let mut freqs: HashMap<char, u64> = HashMap::new();
+        for ch in "abcdefghijklmnopqrstuvwxyz ".chars() {
+            freqs.insert(ch, 1);
+        }
+        freqs.insert('a', 40);
+        freqs.insert(' ', 25);
+        let encoder = HuffmanCode::from_frequencies(&freqs);
+        assert_eq!(encoder.frequencies(), &freqs);
+        assert_eq!(s, encoder.decode_string(&encoder.encode_string(s)));
+        // the most frequent symbol gets the shortest codeword
+        let a = encoder.code_for(&'a').unwrap().len();
+        assert!(encoder.code_lengths().all(|(_, len)| a <= len));
+
+        let from_str = HuffmanCode::new(s);
+        assert_eq!(from_str.frequencies(), s.chars().collect::<Histogram<char>>().as_map());
+    }
+
+    #[test]
+    fn test_bytes() {
+        let data: Vec<u8> = (0..=255u8).chain(vec![0, 0, 0, 0xff, 0x80, 0x80]).collect();
+        let encoder = ByteHuffman::new_bytes(&data);
+        let bin_seq = encoder.encode_bytes(&data);
+        assert_eq!(data, encoder.decode_bytes(&bin_seq));
+        assert_eq!(encoder.frequencies()[&0], 4);
+
+        let binary = [0xde, 0xad, 0xbe, 0xef, 0xde, 0xad];
+        let encoder = ByteHuffman::new_bytes(&binary);
+        assert_eq!(binary.to_vec(), encoder.decode_bytes(&encoder.encode_bytes(&binary)));
+    }
+
+    #[test]
+    fn test_packed() {
+        let s = "the quick brown fox jumped over the lazy dog";
+        let encoder = HuffmanCode::new(s);
+        let (packed, nbits) = encoder.encode_packed(s);
+        let bin_seq = encoder.encode_string(s);
+        assert_eq!(nbits, bin_seq.len());
+        assert_eq!(packed.len(), nbits.div_ceil(8));
+        assert!(packed.len() < s.len());
+        assert_eq!(s, encoder.decode_packed(&packed, nbits));
+
+        // the packed bits agree with the '0'/'1' representation
+        let unpacked: String = (0..nbits)
+            .map(|i| if packed[i / 8] & (0x80 >> (i % 8)) != 0 { '1' } else { '0' })
+            .collect();
+        assert_eq!(bin_seq, unpacked);
+
+        let data = [7u8, 7, 7, 0, 255, 7, 1];
+        let encoder = ByteHuffman::new_bytes(&data);
+        let (packed, nbits) = encoder.encode_bytes_packed(&data);
+        assert_eq!(data.to_vec(), encoder.decode_bytes_packed(&packed, nbits));
+    }
+
+    #[test]
+    fn test_bit_streams() {
+        let s = "fifty liquors yeah good";
+        let encoder = HuffmanCode::new(s);
+        let mut writer = BitWriter::with_order(Vec::new(), BitOrder::LsbFirst);
+        for ch in s.chars() {
+            encoder.write_symbol(&ch, &mut writer).unwrap();
+        }
+        assert!(encoder.write_symbol(&'z', &mut writer).is_err());
+        let bytes = writer.into_inner().unwrap();
+
+        let mut reader = BitReader::with_order(&bytes[..], BitOrder::LsbFirst);
+        let decoded: String = s.chars().map(|_| encoder.read_symbol(&mut reader).unwrap()).collect();
+        assert_eq!(s, decoded);
+    }
+
+    #[test]
+    fn test_try_encode() {
+        let encoder = HuffmanCode::new("hello world");
+        assert_eq!(encoder.try_encode("hello").unwrap(), encoder.encode_string("hello"));
+        assert_eq!(encoder.try_encode("held up"),
+                   Err(EncodeError { symbol: 'u', pos: 5 }));
+
+        let skipped = encoder.try_encode_with("held up", UnknownSymbolPolicy::Skip).unwrap();
+        assert_eq!(encoder.decode_string(&skipped), "held ");
+
+        let escaped = encoder.try_encode_with("held up", UnknownSymbolPolicy::Escape('?'));
+        assert_eq!(escaped, Err(EncodeError { symbol: 'u', pos: 5 }));
+        let escaped = encoder.try_encode_with("held up", UnknownSymbolPolicy::Escape('o')).unwrap();
+        assert_eq!(encoder.decode_string(&escaped), "held oo");
+    }
+
+    #[test]
+    fn test_to_canonical() {
+        let s = "the quick brown fox jumped over the lazy dog";
+        let encoder = HuffmanCode::new(s);
+        let canonical = encoder.to_canonical();
+        for (ch, len) in canonical.lengths() {
+            assert_eq!(encoder.code_for(&ch).unwrap().len(), len as usize);
+        }
+        assert_eq!(s, canonical.decode_string(&canonical.encode_string(s)));
+    }
+
+    #[test]
+    fn test_table_round_trip() {
+        let s = "dagoth ur was a hotep, ünïcödé too";
+        let encoder = HuffmanCode::new(s);
+        let table = encoder.serialize_table();
+        let decoder = CharHuffman::from_table(&table).unwrap();
+        assert!(decoder.code_lengths().eq(encoder.code_lengths()));
+        for ch in encoder.alphabet() {
+            assert_eq!(decoder.code_for(ch), encoder.code_for(ch));
+        }
+        assert_eq!(s, decoder.decode_string(&encoder.encode_string(s)));
+        let (packed, nbits) = encoder.encode_packed(s);
+        assert_eq!(s, decoder.decode_packed(&packed, nbits));
+
+        assert_eq!(CharHuffman::from_table(&table[..6]).err(), Some(TableError::Truncated));
+        // 'a' -> 0, 'b' -> 01: 'a' is a prefix of 'b'
+        let bad = [0, 0, 0, 2, 0, 0, 0, 0x61, 1, 0x00, 0, 0, 0, 0x62, 2, 0x40];
+        assert_eq!(CharHuffman::from_table(&bad).err(), Some(TableError::NotPrefixFree));
+        // 'a' -> the empty codeword, which would decode forever
+        let empty = [0, 0, 0, 1, 0, 0, 0, 0x61, 0];
+        assert_eq!(CharHuffman::from_table(&empty).err(), Some(TableError::EmptyCodeword));
+
+        // lengths past 127 bits take a second byte, and past 255 still fit
+        let long: HashMap<char, Bits> = [('a', "0".to_string()), ('b', "1".repeat(300)), ('c', "1".repeat(299) + "0")]
+            .iter().map(|(ch, s)| (*ch, Bits::from_bit_string(s).unwrap())).collect();
+        let long = HuffmanCode::from_codewords(HashMap::new(), long);
+        let table = long.serialize_table();
+        assert_eq!(table[16..18], [1, 0]);
+        assert_eq!(table[22..24], [0xac, 0x02]);
+        let restored = CharHuffman::from_table(&table).unwrap();
+        assert!(restored.code_lengths().eq(long.code_lengths()));
+        assert_eq!(CharHuffman::from_table(&table[8..23]).err(), Some(TableError::Truncated));
+    }
+
+    #[test]
+    fn test_large_alphabet() {
+        // ~60k distinct symbols with varied weights; quadratic construction
+        // would take far too long here
+        let freqs: HashMap<char, u64> = (0..60_000u32)
+            .filter_map(std::char::from_u32)
+            .enumerate()
+            .map(|(i, ch)| (ch, 1 + (i as u64 * 7919) % 1000))
+            .collect();
+        let encoder = HuffmanCode::from_frequencies(&freqs);
+        assert_eq!(encoder.alphabet().len(), freqs.len());
+        let s: String = freqs.keys().take(500).collect();
+        assert_eq!(s, encoder.decode_string(&encoder.encode_string(&s)));
+    }
+
+    #[test]
+    fn test_deterministic_ties() {
+        // a: 2, b: 2, c: 1 -- pop c, then a (ties go to the lower symbol),
+        // then b and the merged node
+        let encoder = HuffmanCode::new("aabbc");
+        assert_eq!(encoder.code_for(&'b').unwrap().to_bit_string(), "0");
+        assert_eq!(encoder.code_for(&'c').unwrap().to_bit_string(), "10");
+        assert_eq!(encoder.code_for(&'a').unwrap().to_bit_string(), "11");
+
+        // every map over the same counts yields the same code, whatever its
+        // insertion order or hasher seed
+        let s = "abcdefghijklmnopqrstuvwxyz";
+        let reference = HuffmanCode::new(s);
+        for _ in 0..10 {
+            let mut freqs = HashMap::new();
+            for ch in s.chars().rev() {
+                freqs.insert(ch, 1);
+            }
+            let code = HuffmanCode::from_frequencies(&freqs);
+            assert_eq!(code.alphabet(), reference.alphabet());
+            assert!(reference.alphabet().into_iter().all(|ch| code.code_for(ch) == reference.code_for(ch)));
+        }
+    }
+
+    #[test]
+    fn test_malformed_bitstreams() {
+        let s = "dagoth ur was a hotep";
+        let encoder = HuffmanCode::new(s);
+        let bits = encoder.encode_string(s);
+        assert_eq!(encoder.try_decode_string(&bits).unwrap(), s);
+
+        // cut inside the last codeword
+        let last = encoder.code_for(&'p').unwrap().len();
+        let cut = &bits[..bits.len() - 1];
+        assert_eq!(encoder.try_decode_string(cut), Err(DecodeError::TruncatedCodeword { pos: bits.len() - last }));
+        assert_eq!(encoder.try_decode_string("01x"), Err(DecodeError::InvalidBitstream { pos: 2 }));
+
+        // a single-symbol code has no codeword starting with 1
+        let single = HuffmanCode::new("aaaa");
+        assert_eq!(single.try_decode_string("001"), Err(DecodeError::InvalidBitstream { pos: 2 }));
+        assert_eq!(single.try_decode_packed(&[0], 3).unwrap(), "aaa");
+        assert_eq!(single.try_decode_packed(&[0], 9), Err(DecodeError::TruncatedCodeword { pos: 8 }));
+
+        // flip random bits: decoding must fail cleanly or produce something,
+        // never panic, and a complete code never sees an invalid prefix
+        let data = b"the quick brown fox jumped over the lazy dog".repeat(4);
+        let code = ByteHuffman::new_bytes(&data);
+        let (packed, nbits) = code.encode_bytes_packed(&data);
+        let mut x = 0x9E37_79B9_7F4A_7C15u64;
+        for _ in 0..500 {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            let mut flipped = packed.clone();
+            let bit = (x % nbits as u64) as usize;
+            flipped[bit / 8] ^= 0x80 >> (bit % 8);
+            let cut = nbits - (x >> 32) as usize % 4;
+            match code.try_decode_bytes_packed(&flipped, cut) {
+                Ok(decoded) => assert_eq!(code.encode_bytes_packed(&decoded).1, cut),
+                Err(DecodeError::TruncatedCodeword { pos }) => assert!(pos < cut),
+                Err(e) => panic!("unexpected {:?}", e),
+            }
+        }
+
+        // codes from a table can be incomplete
+        let mut partial = HashMap::new();
+        partial.insert(b'a', Bits::from_bit_string("0").unwrap());
+        partial.insert(b'b', Bits::from_bit_string("10").unwrap());
+        let partial = ByteHuffman::from_codewords(HashMap::new(), partial);
+        assert_eq!(partial.try_decode_bytes("01011"), Err(DecodeError::InvalidBitstream { pos: 3 }));
+        assert_eq!(partial.decode_bytes("01011"), b"ab");
+    }
+
+    #[test]
+    fn test_degenerate_inputs() {
+        assert_eq!(HuffmanCode::try_new("").err(), Some(BuildError::EmptyAlphabet));
+        assert_eq!(ByteHuffman::try_new_bytes(&[]).err(), Some(BuildError::EmptyAlphabet));
+        assert_eq!(HuffmanCode::try_from_frequencies(&HashMap::<char, u64>::new()).err(), Some(BuildError::EmptyAlphabet));
+
+        // a single distinct symbol gets a one-bit codeword
+        let encoder = HuffmanCode::try_new("aaaa").unwrap();
+        assert_eq!(encoder.code_for(&'a').unwrap().to_bit_string(), "0");
+        assert_eq!(encoder.encode_string("aaaa"), "0000");
+        assert_eq!(encoder.decode_string("0000"), "aaaa");
+        let (packed, nbits) = encoder.encode_packed("aaa");
+        assert_eq!((packed, nbits), (vec![0], 3));
+        assert_eq!(encoder.decode_packed(&[0], 3), "aaa");
+
+        let table = encoder.serialize_table();
+        assert_eq!(CharHuffman::from_table(&table).unwrap().decode_string("00"), "aa");
+        assert_eq!(encoder.to_canonical().codeword('a').map(|c| c.to_bit_string()).as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn test_generic_symbols() {
+        // word tokens
+        let words: Vec<String> = "the cat and the dog and the bird".split(' ').map(String::from).collect();
+        let encoder = HuffmanCode::from_symbols(words.clone());
+        let the = encoder.code_for(&"the".to_string()).unwrap().len();
+        assert!(encoder.code_lengths().all(|(_, len)| len >= the));
+        assert_eq!(words, encoder.decode_symbols(&encoder.encode_symbols(&words)));
+        let (packed, nbits) = encoder.encode_symbols_packed(&words);
+        assert_eq!(words, encoder.decode_symbols_packed(&packed, nbits));
+
+        // DNA bases as an enum
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        enum Base { A, C, G, T }
+        let dna = vec![Base::A, Base::A, Base::C, Base::G, Base::A, Base::T, Base::A];
+        let encoder = HuffmanCode::from_symbols(dna.clone());
+        assert_eq!(dna, encoder.decode_symbols(&encoder.encode_symbols(&dna)));
+        assert_eq!(encoder.try_encode_symbols(&[Base::A, Base::T], UnknownSymbolPolicy::Error).unwrap(),
+                   encoder.encode_symbols(&[Base::A, Base::T]));
+        let only_a = HuffmanCode::from_symbols(vec![Base::A, Base::C]);
+        assert_eq!(only_a.try_encode_symbols(&dna, UnknownSymbolPolicy::Error),
+                   Err(EncodeError { symbol: Base::G, pos: 3 }));
+
+        // 16-bit tokens survive a table round trip
+        let tokens: Vec<u16> = vec![1000, 1000, 65535, 7, 1000, 7];
+        let encoder = HuffmanCode::from_symbols(tokens.clone());
+        let decoder = HuffmanCode::<u16>::from_table(&encoder.serialize_table()).unwrap();
+        assert_eq!(tokens, decoder.decode_symbols(&encoder.encode_symbols(&tokens)));
+        assert_eq!(ByteHuffman::from_table(&encoder.serialize_table()).err(),
+                   Some(TableError::WrongAlphabet { expected: Alphabet::Byte, found: Alphabet::U16.id() }));
+    }
+
+    #[test]
+    fn test_iterators() {
+        let s = "the quick brown fox jumped over the lazy dog";
+        let encoder = HuffmanCode::new(s);
+        let mut bytes = encoder.encode_iter(s.chars());
+        let packed: Vec<u8> = bytes.by_ref().collect();
+        assert_eq!((packed.clone(), bytes.bit_len()), encoder.encode_packed(s));
+        let decoded: String = encoder.decode_iter(packed).take(s.chars().count()).collect();
+        assert_eq!(decoded, s);
+
+        // chained lazily, without collecting in between
+        let data = b"abracadabra, abracadabra";
+        let code = ByteHuffman::new_bytes(data);
+        assert!(code.decode_iter(code.encode_iter(data.iter().cloned())).take(data.len()).eq(data.iter().cloned()));
+        assert_eq!(code.encode_iter(std::iter::empty()).next(), None);
+        // a cut-off stream decodes to a prefix of the input
+        let (packed, _) = code.encode_bytes_packed(data);
+        let prefix: Vec<u8> = code.decode_iter(packed[..3].to_vec()).collect();
+        assert!(!prefix.is_empty() && data.starts_with(&prefix[..prefix.len() - 1]));
+    }
+
+    #[test]
+    fn test_bits() {
+        let s = "the quick brown fox jumped over the lazy dog";
+        let chars: Vec<char> = s.chars().collect();
+        let encoder = HuffmanCode::new(s);
+        let bits = encoder.encode_bits(&chars);
+        assert_eq!(bits.to_string(), encoder.encode_string(s));
+        assert_eq!(bits.clone().into_parts(), encoder.encode_packed(s));
+        assert_eq!(encoder.decode_bits(&bits), chars);
+        let sf = ShannonFano::from_symbols(s.chars());
+        assert_eq!(PrefixCode::decode_bits(&sf, &PrefixCode::encode_bits(&sf, &chars)), chars);
+
+        // a codeword cut short is reported where it starts
+        let first = encoder.code_for(&'t').unwrap().len();
+        let cut = bits.slice(0..first + 1);
+        assert_eq!(encoder.try_decode_bits(&cut), Err(DecodeError::TruncatedCodeword { pos: first }));
+        assert_eq!(encoder.try_encode_bits(&['t', '!'], UnknownSymbolPolicy::Skip).unwrap(), bits.slice(0..first));
+    }
+
+    #[test]
+    fn test_dense() {
+        let data = b"the quick brown fox jumped over the lazy dog, again and again".repeat(5);
+        let code = ByteHuffman::new_bytes(&data);
+        assert!(code.dense().is_some());
+        // the array agrees with the map, packed and a symbol at a time
+        let packed = code.encode_bytes_packed(&data);
+        assert_eq!(packed, code.encode_symbols_packed(&data));
+        let mut writer = BitWriter::new(Vec::new());
+        for &b in &data {
+            code.write_byte(b, &mut writer).unwrap();
+        }
+        writer.align().unwrap();
+        assert_eq!(writer.into_inner().unwrap(), packed.0);
+        assert_eq!(code.try_encode_bytes_packed(b"dogs"), Err(EncodeError { symbol: b's', pos: 3 }));
+        assert!(code.write_byte(b'#', &mut BitWriter::new(Vec::new())).is_err());
+
+        // chars past 255 and codewords too long for the array use the map
+        let text = "na\u{ef}ve caf\u{e9} \u{2603}";
+        let chars = CharHuffman::new(text);
+        assert!(chars.dense().is_none());
+        assert_eq!(chars.encode_packed(text), chars.encode_symbols_packed(&text.chars().collect::<Vec<_>>()));
+        let mut fib = HashMap::new();
+        let (mut a, mut b) = (1u64, 1u64);
+        for sym in 0..60u8 {
+            fib.insert(sym, a);
+            let next = a + b;
+            a = b;
+            b = next;
+        }
+        let deep = ByteHuffman::from_frequencies(&fib);
+        assert!(deep.dense().is_none());
+        let all: Vec<u8> = (0..60).collect();
+        let (packed, nbits) = deep.encode_bytes_packed(&all);
+        assert_eq!(deep.decode_bytes_packed(&packed, nbits), all);
+    }
+
+    #[test]
+    fn test_shared() {
+        let code = ByteHuffman::new_bytes(b"the quick brown fox jumped over the lazy dog");
+        let clone = code.clone();
+        assert!(Arc::ptr_eq(&code.code, &clone.code) && Arc::ptr_eq(&code.nodes, &clone.nodes));
+
+        // one code serving several threads at once
+        let shared = Arc::new(code);
+        let messages: Vec<Vec<u8>> = (0..8).map(|i| b"the lazy dog".repeat(i + 1)).collect();
+        std::thread::scope(|scope| {
+            for message in &messages {
+                let code = Arc::clone(&shared);
+                scope.spawn(move || {
+                    let (packed, nbits) = code.encode_bytes_packed(message);
+                    assert_eq!(code.decode_bytes_packed(&packed, nbits), *message);
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_table_header() {
+        let encoder = ByteHuffman::new_bytes(b"abracadabra");
+        let table = encoder.serialize_table();
+        assert_eq!(&table[..4], [b'H', b'T', 1, Alphabet::Byte.id()]);
+
+        // any flipped bit in the body is caught before decoding starts
+        let mut bad = table.clone();
+        *bad.last_mut().unwrap() ^= 1;
+        assert_eq!(ByteHuffman::from_table(&bad).err(), Some(TableError::ChecksumMismatch));
+        assert_eq!(ByteHuffman::from_table(&table[..table.len() - 1]).err(), Some(TableError::ChecksumMismatch));
+        let mut newer = table.clone();
+        newer[2] = 2;
+        assert_eq!(ByteHuffman::from_table(&newer).err(), Some(TableError::UnsupportedVersion(2)));
+        assert_eq!(CharHuffman::from_table(&table).err(),
+                   Some(TableError::WrongAlphabet { expected: Alphabet::Char, found: Alphabet::Byte.id() }));
+
+        // headerless tables from before still load
+        let legacy = &table[8..];
+        let decoder = ByteHuffman::from_table(legacy).unwrap();
+        for b in encoder.alphabet() {
+            assert_eq!(decoder.code_for(b), encoder.code_for(b));
+        }
+    }
+
+    #[test]
+    fn test_max_length() {
+        // Fibonacci weights give a maximally deep Huffman tree
+        let fib = [1u64, 1, 2, 3, 5, 8, 13, 21, 34, 55];
+        let freqs: HashMap<char, u64> = "abcdefghij".chars().zip(fib.iter().cloned()).collect();
+        let unlimited = HuffmanCode::from_frequencies(&freqs);
+        assert_eq!(unlimited.code_lengths().map(|(_, len)| len).max(), Some(9));
+        let cost = |code: &HuffmanCode| -> u64 {
+            freqs.iter().map(|(ch, f)| f * code.code_for(ch).unwrap().len() as u64).sum()
+        };
+
+        // without a binding limit package-merge is as good as Huffman
+        let loose = HuffmanCode::with_max_length(&freqs, 15).unwrap();
+        assert_eq!(cost(&loose), cost(&unlimited));
+
+        for max_len in 4..9 {
+            let limited = HuffmanCode::with_max_length(&freqs, max_len).unwrap();
+            assert!(limited.code_lengths().all(|(_, len)| len <= max_len as usize));
+            // a complete code: Kraft sum is exactly one
+            let kraft: f64 = limited.code_lengths().map(|(_, len)| 0.5f64.powi(len as i32)).sum();
+            assert!((kraft - 1.0).abs() < 1e-12);
+            assert!(cost(&limited) >= cost(&unlimited));
+            let s = "jihgfedcbaabcdefghij";
+            assert_eq!(s, limited.decode_string(&limited.encode_string(s)));
+        }
+        // 10 symbols don't fit in 3-bit codewords
+        assert_eq!(HuffmanCode::with_max_length(&freqs, 3).err(), Some(BuildError::MaxLengthTooSmall));
+
+        let one: HashMap<char, u64> = vec![('x', 3)].into_iter().collect();
+        assert_eq!(HuffmanCode::with_max_length(&one, 1).unwrap().code_for(&'x').unwrap().to_bit_string(), "0");
+    }
+
+    #[test]
+    fn test_inspection() {
+        let encoder = HuffmanCode::new("abbccc");
+        assert_eq!(encoder.alphabet(), vec![&'a', &'b', &'c']);
+        let lengths: Vec<(char, usize)> = encoder.code_lengths().collect();
+        assert_eq!(lengths, vec![('c', 1), ('a', 2), ('b', 2)]);
+        let c = encoder.code_for(&'c').unwrap();
+        assert_eq!((c.len(), c.as_bytes()), (1, &[0u8][..]));
+        assert_eq!(encoder.code_for(&'b').unwrap().to_bit_string(), "11");
+        assert_eq!(encoder.code_for(&'z'), None);
+        // a table carries the same code
+        let restored = CharHuffman::from_table(&encoder.serialize_table()).unwrap();
+        assert!(restored.code_lengths().eq(encoder.code_lengths()));
+    }
+
+    #[test]
+    fn test_pictures() {
+        let encoder = HuffmanCode::new("abbccc");
+        assert_eq!(encoder.to_ascii_tree(), "(6)\n\
+                                             +-0- 'c' (3) = 0\n\
+                                             `-1- (3)\n\
+                                             \x20    +-0- 'a' (1) = 10\n\
+                                             \x20    `-1- 'b' (2) = 11\n");
+
+        let dot = encoder.to_dot();
+        assert!(dot.starts_with("digraph huffman {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("n0 [shape=circle, label=\"6\"];"));
+        assert!(dot.contains("[shape=box, label=\"'b'\\n2\\n11\"];"));
+        assert_eq!(dot.matches(" -> ").count(), 4);
+
+        // quotes in symbols are escaped; table codes have no frequencies
+        let quoted = CharHuffman::from_table(&HuffmanCode::new("\"\"x").serialize_table()).unwrap();
+        let dot = quoted.to_dot();
+        assert!(dot.contains("label=\"'\\\"'\\n"));
+        assert!(dot.contains("n0 [shape=circle, label=\"\"];"));
+        assert!(quoted.to_ascii_tree().starts_with("*\n"));
+    }
+
     #[test]
     fn test_internals() {
         let encoder = HuffmanCode::new("dagoth ur was a hotep");
-        let code1: Vec<&String> = encoder.code.values().collect();
-        let code2: Vec<&String> = encoder.code.values().collect();
+        let code1: Vec<String> = encoder.code.values().map(|c| c.to_bit_string()).collect();
+        let code2: Vec<String> = encoder.code.values().map(|c| c.to_bit_string()).collect();
         assert_eq!(code1, code2);
+        // one allocation for the whole tree, 2n - 1 nodes for n leaves
+        assert_eq!(encoder.nodes.len(), 2 * code1.len() - 1);
+        assert_eq!(HuffmanCode::new("aaa").nodes.len(), 2);
         assert!(codewords_are_unique(code1));
         assert!(is_valid_prefix_code(code2));
     }
     
-    fn codewords_are_unique(symbols: Vec<&String>) -> bool {
+    fn codewords_are_unique(symbols: Vec<String>) -> bool {
         // Ensure codewords are unique (no duplicates)
         for c in symbols.iter().combinations(2) {
-            assert!(!((*c[0]) == (*c[1])));
+            assert!((*c[0]) != (*c[1]));
         }
         true
     }
 
-    fn is_valid_prefix_code(symbols: Vec<&String>) -> bool {
+    fn is_valid_prefix_code(symbols: Vec<String>) -> bool {
         // Ensure no codewords are prefixes of any other codewords (i.e., 'symbols' is a prefix code)
         for p in symbols.iter().permutations(2) {
-            assert!(!((*p[0]).starts_with(*p[1])));
+            assert!(!(p[0].starts_with(p[1].as_str())));
         }
         true
     }
 
+    #[test]
+    fn test_deep_tree() {
+        // Fibonacci frequencies make a chain: 90 symbols, codewords up to 89 bits
+        let mut fib = (1u64, 1u64);
+        let mut freqs = HashMap::new();
+        for sym in 0..90u32 {
+            freqs.insert(sym, fib.0);
+            fib = (fib.1, fib.0 + fib.1);
+        }
+        let chain = HuffmanCode::from_frequencies(&freqs);
+        assert_eq!(chain.code_lengths().map(|(_, len)| len).max(), Some(89));
+
+        // u64 frequencies can't go much deeper, but a table can: a unary
+        // code over 10k symbols is a tree 10k levels deep
+        let n = 10_000u32;
+        let unary: HashMap<u32, Bits> = (0..n)
+            .map(|i| (i, (0..(i + 1).min(n - 1)).map(|j| j < i).collect()))
+            .collect();
+        let deep = HuffmanCode::from_codewords(HashMap::new(), unary.clone());
+        assert_eq!(assign_codes(&deep.nodes, deep.root).into_iter().collect::<HashMap<_, _>>(), unary);
+        let symbols = [n - 1, 0, n / 2, n - 2];
+        let (packed, nbits) = deep.encode_symbols_packed(&symbols);
+        assert_eq!(deep.try_decode_symbols_packed(&packed, nbits).unwrap(), symbols);
+
+        // lengths past 255 bits survive a table round trip
+        let table = deep.serialize_table();
+        let restored = HuffmanCode::<u32>::from_table(&table).unwrap();
+        assert!(restored.code_lengths().eq(deep.code_lengths()));
+        assert!(matches!(HuffmanCode::<u32>::from_table_with(&table, &DecodeOptions::new().max_depth(255)),
+                         Err(TableError::LimitExceeded(_))));
+    }
+
 }
\ No newline at end of file
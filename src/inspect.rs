@@ -0,0 +1,147 @@
+// Annotated dumps of bit streams, for working out where an encoder and a
+// decoder from another implementation part ways: dump() lays the bits out
+// by offset next to their bytes in hex, and dump_with() splits them into
+// the codewords of a Huffman code, each with the symbol it decodes to.
+//
+// Bits are shown in the order they're read, which for this crate's codes
+// is MSB-first; a DEFLATE stream (LSB-first) can be turned around a byte at
+// a time with u8::reverse_bits before comparing.
+
+use std::fmt::{self, Write};
+
+use crate::bitio::{BitReader, Bits};
+use crate::huffman::{HuffmanCode, Symbol};
+
+// Bytes per row of dump()
+const ROW_BYTES: usize = 4;
+
+// What a run of bits decodes to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decoded<S> {
+    Symbol(S),
+    // The start of a codeword that the bits end in the middle of
+    Partial,
+    // Bits that lead off the code tree; nothing after them is decoded
+    Invalid,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span<S> {
+    // Offset of the first bit and the number of bits
+    pub start: usize,
+    pub len: usize,
+    pub decoded: Decoded<S>,
+}
+
+// The bits as `code` splits them up: a span per codeword, and a last one
+// for whatever doesn't decode
+pub fn spans<S: Symbol>(bits: &Bits, code: &HuffmanCode<S>) -> Vec<Span<S>> {
+    let mut reader = BitReader::new(bits.as_bytes());
+    let mut ret = Vec::new();
+    let mut start = 0;
+    while start < bits.len() {
+        let result = code.read_symbol(&mut reader);
+        let end = reader.bits_read() as usize;
+        // reading into the padding of the last byte is running out of bits
+        let decoded = match result {
+            Ok(_) if end > bits.len() => Decoded::Partial,
+            // a step that reads no bits would be repeated forever
+            Ok(_) if end == start => Decoded::Invalid,
+            Ok(sym) => Decoded::Symbol(sym),
+            Err(_) if end >= bits.len() => Decoded::Partial,
+            Err(_) => Decoded::Invalid,
+        };
+        let len = match decoded {
+            Decoded::Symbol(_) => end - start,
+            _ => bits.len() - start,
+        };
+        ret.push(Span { start, len, decoded });
+        start += len;
+    }
+    ret
+}
+
+// Offsets, bits and hex, four bytes to a row. The last byte shows only the
+// bits that count; its hex includes the padding.
+pub fn dump(bits: &Bits) -> String {
+    let mut out = String::new();
+    writeln!(out, "{:>8} {:>7}  {:<35}  hex", "bit", "byte", "bits").unwrap();
+    for (row, chunk) in bits.as_bytes().chunks(ROW_BYTES).enumerate() {
+        let first = row * ROW_BYTES;
+        let groups: Vec<String> = chunk.iter().enumerate().map(|(i, b)| {
+            let n = (bits.len() - 8 * (first + i)).min(8);
+            format!("{:08b}", b)[..n].to_string()
+        }).collect();
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        writeln!(out, "{:>8} {:>7}  {:<35}  {}", 8 * first, first, groups.join(" "), hex.join(" ")).unwrap();
+    }
+    writeln!(out, "{} bits", bits.len()).unwrap();
+    out
+}
+
+// A row per codeword of `code`: where it starts, as a bit offset and as
+// byte.bit, its bits and the symbol they decode to
+pub fn dump_with<S: Symbol + fmt::Debug>(bits: &Bits, code: &HuffmanCode<S>) -> String {
+    let spans = spans(bits, code);
+    let width = spans.iter().map(|span| span.len).max().unwrap_or(0).clamp(8, 32);
+    let mut out = String::new();
+    writeln!(out, "{:>8} {:>9}  {:>3}  {:<width$}  symbol", "bit", "byte.bit", "len", "codeword", width = width)
+        .unwrap();
+    for span in &spans {
+        let shown = match &span.decoded {
+            Decoded::Symbol(sym) => format!("{:?}", sym),
+            Decoded::Partial => "(partial codeword)".to_string(),
+            Decoded::Invalid => "(no such codeword)".to_string(),
+        };
+        let at = format!("{}.{}", span.start / 8, span.start % 8);
+        let codeword = bits.slice(span.start..span.start + span.len).to_bit_string();
+        writeln!(out, "{:>8} {:>9}  {:>3}  {:<width$}  {}", span.start, at, span.len, codeword, shown, width = width)
+            .unwrap();
+    }
+    let symbols = spans.iter().filter(|span| matches!(span.decoded, Decoded::Symbol(_))).count();
+    writeln!(out, "{} bits, {} symbols", bits.len(), symbols).unwrap();
+    out
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{dump, dump_with, spans, Decoded, Span};
+    use crate::bitio::Bits;
+    use crate::huffman::HuffmanCode;
+    use std::collections::HashMap;
+
+    fn bits(s: &str) -> Bits {
+        Bits::from_bit_string(s).unwrap()
+    }
+
+    #[test]
+    fn test_dump() {
+        let text = dump(&bits("101100100111000011111111000000001"));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[1].trim_end(), "       0       0  10110010 01110000 11111111 00000000  b2 70 ff 00");
+        assert_eq!(lines[2].split_whitespace().collect::<Vec<_>>(), ["32", "4", "1", "80"]);
+        assert_eq!(lines[3], "33 bits");
+        assert_eq!(dump(&Bits::default()).lines().nth(1), Some("0 bits"));
+    }
+
+    #[test]
+    fn test_spans() {
+        let codewords: HashMap<char, Bits> = vec![('a', bits("0")), ('b', bits("10")), ('c', bits("110"))]
+            .into_iter().collect();
+        let code = HuffmanCode::from_codewords(HashMap::new(), codewords);
+        let span = |start, len, decoded| Span { start, len, decoded };
+        assert_eq!(spans(&bits("0101101"), &code),
+                   vec![span(0, 1, Decoded::Symbol('a')), span(1, 2, Decoded::Symbol('b')),
+                        span(3, 3, Decoded::Symbol('c')), span(6, 1, Decoded::Partial)]);
+        // 111 leads nowhere
+        assert_eq!(spans(&bits("10111000"), &code),
+                   vec![span(0, 2, Decoded::Symbol('b')), span(2, 6, Decoded::Invalid)]);
+
+        let text = dump_with(&bits("0101101"), &code);
+        assert!(text.contains("\n       3       0.3    3  110       'c'\n"));
+        assert!(text.contains("(partial codeword)"));
+        assert!(text.ends_with("7 bits, 3 symbols\n"));
+    }
+
+}
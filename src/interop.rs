@@ -0,0 +1,243 @@
+// Code tables in other formats' layouts, to read the Huffman tables out of
+// real files or hand this crate's codes to them. Both map onto
+// CanonicalHuffman, each byte or symbol index standing for the char of the
+// same value.
+//
+// JPEG (ITU T.81, B.2.4.2): a DHT segment is the marker FF C4 and a 16-bit
+// length counting itself, then one or more tables of
+//   Tc/Th: class in the high nibble (0 DC, 1 AC), destination 0-3 in the low
+//   BITS: 16 counts, of codewords of lengths 1 to 16
+//   HUFFVAL: the symbol bytes in codeword order
+// DEFLATE (RFC 1951, 3.2.2): one code length per symbol index, zero for
+// symbols that don't occur, at most 15.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+use crate::canonical::CanonicalHuffman;
+use crate::huffman::TableError;
+
+const DHT_MARKER: [u8; 2] = [0xff, 0xc4];
+const JPEG_MAX_LEN: u8 = 16;
+const DEFLATE_MAX_LEN: u8 = 15;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InteropError {
+    Truncated,
+    NotDht,                // the segment doesn't start with FF C4
+    InvalidTableClass(u8), // a Tc/Th byte with class above 1 or destination above 3
+    TooLong { max: u8 },   // a codeword longer than the format allows
+    SymbolOutOfRange(char), // a symbol the format has no value for
+    // JPEG reserves the all-ones codeword, so a table may not use up the whole code space
+    CompleteCode,
+    SegmentTooLong, // more tables than a 16-bit segment length can cover
+    Table(TableError),
+}
+
+impl fmt::Display for InteropError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InteropError::Truncated => write!(f, "table is truncated"),
+            InteropError::NotDht => write!(f, "not a DHT segment"),
+            InteropError::InvalidTableClass(b) => write!(f, "invalid Huffman table class/destination {:#04x}", b),
+            InteropError::TooLong { max } => write!(f, "codeword longer than the format's {} bits", max),
+            InteropError::SymbolOutOfRange(ch) => write!(f, "symbol {:?} out of range for the format", ch),
+            InteropError::CompleteCode => write!(f, "JPEG tables can't use the all-ones codeword"),
+            InteropError::SegmentTooLong => write!(f, "tables don't fit in one DHT segment"),
+            InteropError::Table(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for InteropError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InteropError::Table(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<TableError> for InteropError {
+    fn from(e: TableError) -> Self {
+        InteropError::Table(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct DhtTable {
+    pub class: u8,       // 0 for DC, 1 for AC
+    pub destination: u8, // table slot, 0-3
+    pub code: CanonicalHuffman,
+}
+
+// Every table in a DHT segment. `segment` starts at the marker; anything
+// after the length it declares is ignored, so a slice running on to the end
+// of the file will do.
+pub fn parse_dht(segment: &[u8]) -> Result<Vec<DhtTable>, InteropError> {
+    if segment.len() < 4 {
+        return Err(InteropError::Truncated);
+    }
+    if segment[..2] != DHT_MARKER {
+        return Err(InteropError::NotDht);
+    }
+    let len = u16::from_be_bytes([segment[2], segment[3]]) as usize;
+    if len < 2 || segment.len() < 2 + len {
+        return Err(InteropError::Truncated);
+    }
+    let mut rest = &segment[4..2 + len];
+    let mut tables = Vec::new();
+    while !rest.is_empty() {
+        let tc_th = take(&mut rest, 1)?[0];
+        let (class, destination) = (tc_th >> 4, tc_th & 0x0f);
+        if class > 1 || destination > 3 {
+            return Err(InteropError::InvalidTableClass(tc_th));
+        }
+        let counts: Vec<u32> = take(&mut rest, JPEG_MAX_LEN as usize)?.iter().map(|&n| n as u32).collect();
+        let total = counts.iter().sum::<u32>() as usize;
+        let symbols: Vec<char> = take(&mut rest, total)?.iter().map(|&b| b as char).collect();
+        let code = CanonicalHuffman::from_counts(&counts, &symbols)?;
+        tables.push(DhtTable { class, destination, code });
+    }
+    Ok(tables)
+}
+
+// One DHT segment holding all of `tables`
+pub fn write_dht(tables: &[DhtTable]) -> Result<Vec<u8>, InteropError> {
+    let mut out = DHT_MARKER.to_vec();
+    out.extend_from_slice(&[0, 0]); // length, filled in below
+    for table in tables {
+        if table.class > 1 || table.destination > 3 {
+            return Err(InteropError::InvalidTableClass(table.class << 4 | table.destination));
+        }
+        let counts = table.code.counts();
+        if counts.len() > JPEG_MAX_LEN as usize {
+            return Err(InteropError::TooLong { max: JPEG_MAX_LEN });
+        }
+        if is_complete(counts) {
+            return Err(InteropError::CompleteCode);
+        }
+        out.push(table.class << 4 | table.destination);
+        out.extend((0..JPEG_MAX_LEN as usize).map(|i| counts.get(i).cloned().unwrap_or(0) as u8));
+        for &ch in table.code.symbols() {
+            out.push(u8::try_from(ch as u32).map_err(|_| InteropError::SymbolOutOfRange(ch))?);
+        }
+    }
+    let len = u16::try_from(out.len() - 2).map_err(|_| InteropError::SegmentTooLong)?;
+    out[2..4].copy_from_slice(&len.to_be_bytes());
+    Ok(out)
+}
+
+// A DEFLATE code length sequence as a code over the chars U+0000 up to
+// U+0000 + lengths.len()
+pub fn from_deflate_lengths(lengths: &[u8]) -> Result<CanonicalHuffman, InteropError> {
+    if lengths.iter().any(|&len| len > DEFLATE_MAX_LEN) {
+        return Err(InteropError::TooLong { max: DEFLATE_MAX_LEN });
+    }
+    let pairs = lengths.iter().enumerate()
+        .map(|(i, &len)| std::char::from_u32(i as u32).map(|ch| (ch, len)).ok_or(TableError::InvalidSymbol))
+        .collect::<Result<Vec<(char, u8)>, _>>()?;
+    Ok(CanonicalHuffman::from_lengths(&pairs)?)
+}
+
+// The code length of every symbol index below `alphabet_size` (286 for
+// literal/lengths, 30 for distances); zero where there's no codeword
+pub fn to_deflate_lengths(code: &CanonicalHuffman, alphabet_size: usize) -> Result<Vec<u8>, InteropError> {
+    let mut lengths = vec![0u8; alphabet_size];
+    for (ch, len) in code.lengths() {
+        if len > DEFLATE_MAX_LEN {
+            return Err(InteropError::TooLong { max: DEFLATE_MAX_LEN });
+        }
+        *lengths.get_mut(ch as usize).ok_or(InteropError::SymbolOutOfRange(ch))? = len;
+    }
+    Ok(lengths)
+}
+
+// Whether codewords with these counts per length 1, 2, ... fill the code space
+fn is_complete(counts: &[u32]) -> bool {
+    let mut left: u64 = 1;
+    for &n in counts {
+        left = 2 * left - (n as u64).min(2 * left);
+    }
+    left == 0
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], InteropError> {
+    if bytes.len() < n {
+        return Err(InteropError::Truncated);
+    }
+    let (head, tail) = bytes.split_at(n);
+    *bytes = tail;
+    Ok(head)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{from_deflate_lengths, parse_dht, to_deflate_lengths, write_dht, DhtTable, InteropError};
+    use crate::canonical::CanonicalHuffman;
+    use crate::huffman::TableError;
+
+    // T.81 Table K.3, luminance DC, then an AC table whose symbols aren't
+    // in value order (the start of Table K.5)
+    fn dht() -> Vec<u8> {
+        let mut seg = vec![0xff, 0xc4, 0, 0, 0x00];
+        seg.extend_from_slice(&[0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0]);
+        seg.extend(0..=11);
+        seg.push(0x10);
+        seg.extend_from_slice(&[0, 2, 1, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        seg.extend_from_slice(&[0x01, 0x02, 0x03, 0x00, 0x04, 0x11]);
+        let len = (seg.len() - 2) as u16;
+        seg[2..4].copy_from_slice(&len.to_be_bytes());
+        seg
+    }
+
+    #[test]
+    fn test_jpeg() {
+        let seg = dht();
+        let tables = parse_dht(&seg).unwrap();
+        assert_eq!(tables.iter().map(|t| (t.class, t.destination)).collect::<Vec<_>>(), vec![(0, 0), (1, 0)]);
+        let bits = |code: &CanonicalHuffman, b: u8| code.codeword(b as char).unwrap().to_bit_string();
+        assert_eq!((bits(&tables[0].code, 0), bits(&tables[0].code, 11)), ("00".to_string(), "111111110".to_string()));
+        // EOB keeps its place after 0x03 rather than sorting first
+        assert_eq!(bits(&tables[1].code, 0x00), "1010");
+        assert_eq!(write_dht(&tables).unwrap(), seg);
+
+        // trailing bytes past the segment are fine; a short one isn't
+        let mut longer = seg.clone();
+        longer.extend_from_slice(&[0xff, 0xda]);
+        assert_eq!(parse_dht(&longer).unwrap().len(), 2);
+        assert_eq!(parse_dht(&seg[..seg.len() - 1]).err(), Some(InteropError::Truncated));
+        assert_eq!(parse_dht(&[0xff, 0xd8, 0, 2]).err(), Some(InteropError::NotDht));
+        let mut bad = seg;
+        bad[4] = 0x24;
+        assert_eq!(parse_dht(&bad).err(), Some(InteropError::InvalidTableClass(0x24)));
+
+        let complete = CanonicalHuffman::from_lengths(&[('a', 1), ('b', 1)]).unwrap();
+        let table = DhtTable { class: 0, destination: 1, code: complete };
+        assert_eq!(write_dht(&[table]).err(), Some(InteropError::CompleteCode));
+        let wide = CanonicalHuffman::from_lengths(&[('Ā', 1)]).unwrap();
+        let table = DhtTable { class: 0, destination: 1, code: wide };
+        assert_eq!(write_dht(&[table]).err(), Some(InteropError::SymbolOutOfRange('Ā')));
+    }
+
+    #[test]
+    fn test_deflate() {
+        // the fixed literal/length code of RFC 1951, 3.2.6
+        let mut lengths = vec![8u8; 144];
+        lengths.extend(vec![9; 112]);
+        lengths.extend(vec![7; 24]);
+        lengths.extend(vec![8; 8]);
+        let code = from_deflate_lengths(&lengths).unwrap();
+        let bits = |i: u32| code.codeword(std::char::from_u32(i).unwrap()).unwrap().to_bit_string();
+        assert_eq!((bits(0), bits(144), bits(256), bits(280)),
+                   ("00110000".to_string(), "110010000".to_string(), "0000000".to_string(), "11000000".to_string()));
+        assert_eq!(to_deflate_lengths(&code, 288).unwrap(), lengths);
+        assert_eq!(to_deflate_lengths(&code, 286).err(), Some(InteropError::SymbolOutOfRange('\u{11e}')));
+
+        assert_eq!(from_deflate_lengths(&[1, 1, 1]).err(), Some(InteropError::Table(TableError::Oversubscribed)));
+        assert_eq!(from_deflate_lengths(&[16, 1]).err(), Some(InteropError::TooLong { max: 15 }));
+    }
+
+}
@@ -1 +1,97 @@
-pub mod huffman;
\ No newline at end of file
+pub mod huffman;
+pub mod canonical;
+pub mod interop;
+pub mod adaptive;
+pub mod bitio;
+pub mod hash;
+pub mod stream;
+pub mod container;
+pub mod seekable;
+pub mod archive;
+pub mod table;
+pub mod stats;
+pub mod analysis;
+pub mod frequency;
+pub mod histogram;
+pub mod probability;
+pub mod lz77;
+pub mod lzss;
+pub mod matchfind;
+pub mod dictionary;
+pub mod deflate;
+pub mod gzip;
+pub mod zlib;
+pub mod rans;
+pub mod arithmetic;
+pub mod models;
+pub mod ppm;
+pub mod fse;
+pub mod rle;
+pub mod bwt;
+pub mod mtf;
+pub mod pipeline;
+pub mod elias;
+pub mod codes;
+pub mod numeric;
+pub mod shannon_fano;
+pub mod nary;
+pub mod unequal;
+pub mod coder;
+pub mod scratch;
+pub mod progress;
+pub mod limits;
+pub mod eval;
+pub mod escape;
+pub mod encoding;
+pub mod inspect;
+pub mod text;
+pub mod tokenize;
+pub mod profiles;
+pub mod checksum;
+pub mod ecc;
+pub mod error;
+#[cfg(feature = "serde")]
+mod serde_support;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "async")]
+pub mod async_stream;
+#[cfg(any(test, feature = "proptest-support"))]
+pub mod testing;
+
+pub use error::{Error, Result};
+pub use bitio::Bits;
+pub use container::{compress_to_vec, compress_with, decompress_from_slice, decompress_from_slice_with, compress_file,
+                    compress_file_with, compress_file_with_progress, compress_file_cancellable, decompress_file,
+                    decompress_file_with, decompress_file_with_progress, decompress_file_cancellable, read_header,
+                    Codec, CompressOptions, Header};
+pub use limits::DecodeOptions;
+
+// Codes, coders and other trained or configured state never change once
+// built, so they can be shared between threads (an Arc, or a clone, which
+// for the code tables only bumps reference counts). This stops compiling if
+// one of them stops being Send + Sync.
+const _: fn() = || {
+    fn shared<T: Send + Sync>() {}
+    shared::<huffman::HuffmanCode<char>>();
+    shared::<huffman::ByteHuffman>();
+    shared::<canonical::CanonicalHuffman>();
+    shared::<shannon_fano::ShannonFano<u8>>();
+    shared::<nary::NaryHuffman<char>>();
+    shared::<unequal::UnequalCostCode<char>>();
+    shared::<text::TextHuffman>();
+    shared::<frequency::FrequencyTable<u8>>();
+    shared::<probability::ProbTable>();
+    shared::<rans::Rans>();
+    shared::<fse::Fse>();
+    shared::<dictionary::Dictionary>();
+    shared::<pipeline::Pipeline>();
+    shared::<Box<dyn coder::Coder>>();
+    shared::<CompressOptions>();
+    shared::<Bits>();
+    shared::<scratch::ScratchPool>();
+};
@@ -0,0 +1,138 @@
+// Limits on what decoding untrusted input may cost. A few hundred bytes of
+// DEFLATE can claim gigabytes of output, a container header can declare
+// any length it likes, and a code table can list millions of symbols or
+// codewords hundreds of bits deep. DecodeOptions caps each of those; a
+// decoder that would go past one stops with LimitExceeded before it has
+// allocated or done the work.
+//
+// The defaults are no limits at all, which is what the plain decoding
+// functions use. The *_with versions take a DecodeOptions:
+// HuffmanCode::from_table_with, container::decompress_from_slice_with and
+// decompress_file_with, Archive::decompress_with and open_with,
+// deflate::inflate_with, zlib::decompress_with, gzip::decompress_with,
+// lzss::decode_with, lz77::decode_with and decompress_with, and
+// rle::decode_counted_with and decode_escaped_with; likewise
+// Pipeline::decompress_limited. The stream decoders, SeekableDecoder and
+// Pipeline take one through limits().
+// Decoders told the output length by their caller (rANS, FSE, the bare
+// Huffman codes) are bounded by it already.
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeOptions {
+    max_output_bytes: u64,
+    max_table_symbols: u32,
+    max_depth: u32,
+}
+
+impl DecodeOptions {
+
+    // No limits
+    pub fn new() -> Self {
+        DecodeOptions { max_output_bytes: u64::MAX, max_table_symbols: u32::MAX, max_depth: u32::MAX }
+    }
+
+    // Most bytes of decoded output, across the whole input
+    pub fn max_output_bytes(mut self, max: u64) -> Self {
+        self.max_output_bytes = max;
+        self
+    }
+
+    // Most symbols a code table may list
+    pub fn max_table_symbols(mut self, max: u32) -> Self {
+        self.max_table_symbols = max;
+        self
+    }
+
+    // Longest codeword a code table may hold, in bits: the depth of its
+    // tree, and so the work decoding one symbol can take
+    pub fn max_depth(mut self, max: u32) -> Self {
+        self.max_depth = max;
+        self
+    }
+
+    pub fn get_max_output_bytes(&self) -> u64 {
+        self.max_output_bytes
+    }
+
+    pub fn get_max_table_symbols(&self) -> u32 {
+        self.max_table_symbols
+    }
+
+    pub fn get_max_depth(&self) -> u32 {
+        self.max_depth
+    }
+
+    pub(crate) fn check_output(&self, len: u64) -> Result<(), LimitExceeded> {
+        check(Limit::OutputBytes, len, self.max_output_bytes)
+    }
+
+    pub(crate) fn check_table_symbols(&self, n: u32) -> Result<(), LimitExceeded> {
+        check(Limit::TableSymbols, n as u64, self.max_table_symbols as u64)
+    }
+
+    pub(crate) fn check_depth(&self, len: u64) -> Result<(), LimitExceeded> {
+        check(Limit::Depth, len, self.max_depth as u64)
+    }
+
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions::new()
+    }
+}
+
+fn check(limit: Limit, value: u64, max: u64) -> Result<(), LimitExceeded> {
+    if value > max {
+        return Err(LimitExceeded { limit, max });
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    OutputBytes,
+    TableSymbols,
+    Depth,
+}
+
+// Which limit the input would have gone past, and what it was set to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub limit: Limit,
+    pub max: u64,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.limit {
+            Limit::OutputBytes => write!(f, "output would exceed the limit of {} bytes", self.max),
+            Limit::TableSymbols => write!(f, "code table has more than the limit of {} symbols", self.max),
+            Limit::Depth => write!(f, "codeword longer than the limit of {} bits", self.max),
+        }
+    }
+}
+
+impl Error for LimitExceeded {}
+
+#[cfg(test)]
+mod test {
+
+    use super::{DecodeOptions, Limit, LimitExceeded};
+
+    #[test]
+    fn test_checks() {
+        let options = DecodeOptions::new().max_output_bytes(100).max_table_symbols(3).max_depth(12);
+        assert_eq!(options.check_output(100), Ok(()));
+        assert_eq!(options.check_output(101), Err(LimitExceeded { limit: Limit::OutputBytes, max: 100 }));
+        assert!(options.check_table_symbols(4).is_err() && options.check_depth(13).is_err());
+        // nothing is over the defaults
+        let none = DecodeOptions::default();
+        assert!(none.check_output(u64::MAX).is_ok() && none.check_table_symbols(u32::MAX).is_ok());
+        assert!(none.check_depth(u32::MAX as u64).is_ok());
+    }
+
+}
@@ -0,0 +1,554 @@
+// LZ77 (Ziv & Lempel, 1977): replace each repeated stretch of input with a
+// back-reference into a sliding window over what came before. The output is
+// a sequence of (offset, length, literal) triples: copy `length` bytes
+// starting `offset` bytes back, then emit `literal`. A triple with length 0
+// is a bare literal. Matches stop one byte short of the end of the input so
+// that every triple has a literal.
+//
+// Serialized, a triple is five bytes: offset and length as u16 big-endian,
+// then the literal.
+
+use std::cmp;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::limits::{DecodeOptions, LimitExceeded};
+
+// Shorter matches cost more than the literals they replace
+pub const MIN_MATCH: usize = 3;
+pub const MAX_WINDOW: usize = u16::MAX as usize;
+
+const HASH_BITS: u32 = 15;
+// How many earlier positions with the same hash to try before settling
+const MAX_CHAIN: usize = 128;
+const TRIPLE_LEN: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Triple {
+    pub offset: u16,
+    pub length: u16,
+    pub literal: u8,
+}
+
+impl Triple {
+
+    fn to_bytes(self) -> [u8; TRIPLE_LEN] {
+        let [o0, o1] = self.offset.to_be_bytes();
+        let [l0, l1] = self.length.to_be_bytes();
+        [o0, o1, l0, l1, self.literal]
+    }
+
+    fn from_bytes(b: [u8; TRIPLE_LEN]) -> Self {
+        Triple {
+            offset: u16::from_be_bytes([b[0], b[1]]),
+            length: u16::from_be_bytes([b[2], b[3]]),
+            literal: b[4],
+        }
+    }
+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lz77 {
+    window: usize,
+    max_match: usize,
+}
+
+impl Lz77 {
+
+    // Panics unless 1 <= window <= MAX_WINDOW and
+    // MIN_MATCH <= max_match <= u16::MAX
+    pub fn new(window: usize, max_match: usize) -> Self {
+        assert!((1..=MAX_WINDOW).contains(&window), "window must be in 1..={}", MAX_WINDOW);
+        assert!((MIN_MATCH..=u16::MAX as usize).contains(&max_match),
+                "max_match must be in {}..={}", MIN_MATCH, u16::MAX);
+        Lz77 { window, max_match }
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    pub fn max_match(&self) -> usize {
+        self.max_match
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Vec<Triple> {
+        let mut finder = MatchFinder::new(self);
+        let mut triples = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let triple = finder.next_triple(data, 0, pos);
+            pos += triple.length as usize + 1;
+            triples.push(triple);
+        }
+        triples
+    }
+
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        self.compress(data).into_iter().flat_map(Triple::to_bytes).collect()
+    }
+
+}
+
+// DEFLATE's window and maximum match length
+impl Default for Lz77 {
+    fn default() -> Self {
+        Lz77::new(32 * 1024, 258)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lz77Error {
+    // The serialized triples end partway through one
+    Truncated,
+    // The triple at this index reaches back before the start of the output
+    BadOffset(usize),
+    // More output than DecodeOptions::max_output_bytes
+    LimitExceeded(LimitExceeded),
+}
+
+impl fmt::Display for Lz77Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Lz77Error::Truncated => write!(f, "LZ77 data is truncated"),
+            Lz77Error::BadOffset(i) => write!(f, "triple {} refers back past the start of the data", i),
+            Lz77Error::LimitExceeded(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for Lz77Error {}
+
+impl From<LimitExceeded> for Lz77Error {
+    fn from(e: LimitExceeded) -> Self {
+        Lz77Error::LimitExceeded(e)
+    }
+}
+
+pub fn decompress(triples: &[Triple]) -> Result<Vec<u8>, Lz77Error> {
+    decompress_with(triples, &DecodeOptions::new())
+}
+
+// decompress, stopping with LimitExceeded before the output passes
+// max_output_bytes; five bytes of triple can stand for 65536
+pub fn decompress_with(triples: &[Triple], limits: &DecodeOptions) -> Result<Vec<u8>, Lz77Error> {
+    let mut out = Vec::new();
+    for (i, triple) in triples.iter().enumerate() {
+        limits.check_output(out.len() as u64 + triple.length as u64 + 1)?;
+        if !apply(&mut out, triple) {
+            return Err(Lz77Error::BadOffset(i));
+        }
+    }
+    Ok(out)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Vec<u8>, Lz77Error> {
+    decode_with(bytes, &DecodeOptions::new())
+}
+
+pub fn decode_with(bytes: &[u8], limits: &DecodeOptions) -> Result<Vec<u8>, Lz77Error> {
+    if !bytes.len().is_multiple_of(TRIPLE_LEN) {
+        return Err(Lz77Error::Truncated);
+    }
+    let triples: Vec<Triple> = bytes.chunks(TRIPLE_LEN)
+        .map(|b| Triple::from_bytes([b[0], b[1], b[2], b[3], b[4]]))
+        .collect();
+    decompress_with(&triples, limits)
+}
+
+// Append the bytes `triple` stands for; false if its offset is invalid
+fn apply(out: &mut Vec<u8>, triple: &Triple) -> bool {
+    if triple.length > 0 {
+        let offset = triple.offset as usize;
+        if offset == 0 || offset > out.len() {
+            return false;
+        }
+        // The match may overlap the bytes it produces, so copy one at a time
+        let start = out.len() - offset;
+        for i in 0..triple.length as usize {
+            let b = out[start + i];
+            out.push(b);
+        }
+    }
+    out.push(triple.literal);
+    true
+}
+
+// Hash chains over MIN_MATCH-byte prefixes. Positions are absolute stream
+// offsets, so the same finder serves a whole slice or a stream fed through
+// a buffer holding the bytes from position `base` on.
+struct MatchFinder {
+    window: usize,
+    max_match: usize,
+    // Most recent position (plus one; zero for none) with each hash
+    head: Vec<usize>,
+    // prev[pos % window] is the previous position with the same hash as pos
+    prev: Vec<usize>,
+}
+
+impl MatchFinder {
+
+    fn new(lz: &Lz77) -> Self {
+        MatchFinder {
+            window: lz.window,
+            max_match: lz.max_match,
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; lz.window],
+        }
+    }
+
+    // Find the triple starting at `pos`, and index every position it covers
+    fn next_triple(&mut self, buf: &[u8], base: usize, pos: usize) -> Triple {
+        let end = base + buf.len();
+        let limit = cmp::min(self.max_match, end - pos - 1);
+        let (offset, length) = if limit >= MIN_MATCH { self.longest(buf, base, pos, limit) } else { (0, 0) };
+        for p in pos..=pos + length {
+            self.insert(buf, base, p);
+        }
+        Triple { offset: offset as u16, length: length as u16, literal: buf[pos + length - base] }
+    }
+
+    fn longest(&self, buf: &[u8], base: usize, pos: usize, limit: usize) -> (usize, usize) {
+        let i = pos - base;
+        let target = &buf[i..i + limit];
+        let mut best = (0, 0);
+        let mut candidate = self.head[hash(&buf[i..])];
+        let mut chain = MAX_CHAIN;
+        while candidate != 0 && chain > 0 {
+            let c = candidate - 1;
+            if pos - c > self.window {
+                break;
+            }
+            let len = buf[c - base..].iter().zip(target).take_while(|(a, b)| a == b).count();
+            if len > best.1 {
+                best = (pos - c, len);
+                if len == limit {
+                    break;
+                }
+            }
+            candidate = self.prev[c % self.window];
+            chain -= 1;
+        }
+        if best.1 < MIN_MATCH { (0, 0) } else { best }
+    }
+
+    fn insert(&mut self, buf: &[u8], base: usize, pos: usize) {
+        let i = pos - base;
+        if i + MIN_MATCH > buf.len() {
+            return;
+        }
+        let h = hash(&buf[i..]);
+        self.prev[pos % self.window] = self.head[h];
+        self.head[h] = pos + 1;
+    }
+
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let key = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+    (key.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+// Writes the serialized triples for everything written to it. Output is
+// identical to Lz77::encode on the concatenated input.
+pub struct Lz77Encoder<W: Write> {
+    // Only None after finish() has handed the writer back
+    inner: Option<W>,
+    finder: MatchFinder,
+    // Stream bytes from position `base` on: at least a window of history
+    // (where there is one) before `pos`, then input not yet compressed
+    buf: Vec<u8>,
+    base: usize,
+    pos: usize,
+    out: Vec<u8>,
+    finished: bool,
+}
+
+impl<W: Write> Lz77Encoder<W> {
+
+    pub fn new(inner: W, lz: Lz77) -> Self {
+        Lz77Encoder {
+            inner: Some(inner),
+            finder: MatchFinder::new(&lz),
+            buf: Vec::new(),
+            base: 0,
+            pos: 0,
+            out: Vec::new(),
+            finished: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    // Compress the input still held back for lookahead; later writes are
+    // an error. Dropping the encoder does this too, ignoring errors.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.compress_pending(true);
+        self.dump()?;
+        self.finished = true;
+        self.get_mut().flush()
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    // Until the end of the input, only compress where a full-length match
+    // would fit, so matches come out the same as in a one-shot compress
+    fn compress_pending(&mut self, all: bool) {
+        let end = self.base + self.buf.len();
+        let lookahead = if all { 1 } else { self.finder.max_match + MIN_MATCH };
+        while end - self.pos >= lookahead {
+            let triple = self.finder.next_triple(&self.buf, self.base, self.pos);
+            self.pos += triple.length as usize + 1;
+            self.out.extend_from_slice(&triple.to_bytes());
+        }
+        let window = self.finder.window;
+        if self.pos - self.base > 2 * window {
+            let cut = self.pos - window - self.base;
+            self.buf.drain(..cut);
+            self.base += cut;
+        }
+    }
+
+    fn dump(&mut self) -> io::Result<()> {
+        if !self.out.is_empty() {
+            self.inner.as_mut().unwrap().write_all(&self.out)?;
+            self.out.clear();
+        }
+        Ok(())
+    }
+
+}
+
+impl<W: Write> Write for Lz77Encoder<W> {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other("write after finish"));
+        }
+        self.buf.extend_from_slice(buf);
+        self.compress_pending(false);
+        self.dump()?;
+        Ok(buf.len())
+    }
+
+    // Pushes out every finished triple; the last few input bytes stay
+    // buffered until more data arrives or the stream is finished.
+    fn flush(&mut self) -> io::Result<()> {
+        self.dump()?;
+        self.get_mut().flush()
+    }
+
+}
+
+impl<W: Write> Drop for Lz77Encoder<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+pub struct Lz77Decoder<R: Read> {
+    inner: R,
+    // Decoded bytes, trimmed to the last MAX_WINDOW as history; those from
+    // `read_pos` on haven't been returned yet
+    history: Vec<u8>,
+    read_pos: usize,
+    // Bytes decoded so far, for the limits
+    produced: u64,
+    limits: DecodeOptions,
+}
+
+impl<R: Read> Lz77Decoder<R> {
+
+    pub fn new(inner: R) -> Self {
+        Lz77Decoder { inner, history: Vec::new(), read_pos: 0, produced: 0, limits: DecodeOptions::new() }
+    }
+
+    // Fail with InvalidData rather than decode more than max_output_bytes
+    pub fn limits(mut self, limits: DecodeOptions) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    // Decoded bytes not yet read are lost
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // None at a clean end of stream
+    fn read_triple(&mut self) -> io::Result<Option<Triple>> {
+        let mut bytes = [0u8; TRIPLE_LEN];
+        let mut filled = 0;
+        while filled < TRIPLE_LEN {
+            match self.inner.read(&mut bytes[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ends mid-triple")),
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Some(Triple::from_bytes(bytes)))
+    }
+
+}
+
+impl<R: Read> Read for Lz77Decoder<R> {
+
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        while self.read_pos == self.history.len() {
+            if self.history.len() > 2 * MAX_WINDOW {
+                let cut = self.history.len() - MAX_WINDOW;
+                self.history.drain(..cut);
+                self.read_pos -= cut;
+            }
+            match self.read_triple()? {
+                None => return Ok(0),
+                Some(triple) => {
+                    self.produced += triple.length as u64 + 1;
+                    self.limits.check_output(self.produced)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    if !apply(&mut self.history, &triple) {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "offset before start of stream"));
+                    }
+                }
+            }
+        }
+        let pending = &self.history[self.read_pos..];
+        let n = cmp::min(pending.len(), out.len());
+        out[..n].copy_from_slice(&pending[..n]);
+        self.read_pos += n;
+        Ok(n)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{decode, decode_with, decompress, Lz77, Lz77Decoder, Lz77Encoder, Lz77Error, Triple};
+    use crate::limits::{DecodeOptions, Limit, LimitExceeded};
+    use std::io::{self, Read, Write};
+
+    // Hands out its data one byte per read() call
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || out.is_empty() {
+                return Ok(0);
+            }
+            out[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    fn samples() -> Vec<Vec<u8>> {
+        // xorshift noise doesn't compress; the text does
+        let mut x = 0x2545f491u32;
+        let noise: Vec<u8> = (0..100_000).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        }).collect();
+        vec![b"".to_vec(), b"a".to_vec(), b"aaaaaaaaaaaaaaaaaaaaaa".to_vec(), (0..=255u8).collect(),
+             b"the quick brown fox jumped over the lazy dog. ".repeat(3000), noise]
+    }
+
+    #[test]
+    fn test_triples() {
+        let triples = Lz77::default().compress(b"abababab");
+        let t = |offset, length, literal| Triple { offset, length, literal };
+        assert_eq!(triples, vec![t(0, 0, b'a'), t(0, 0, b'b'), t(2, 5, b'b')]);
+        assert_eq!(decompress(&triples).unwrap(), b"abababab");
+
+        assert_eq!(decompress(&[t(1, 1, b'a')]), Err(Lz77Error::BadOffset(0)));
+        assert_eq!(decode(&[0, 0, 0]), Err(Lz77Error::Truncated));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for lz in &[Lz77::default(), Lz77::new(16, 3), Lz77::new(65535, 65535)] {
+            for data in samples() {
+                let triples = lz.compress(&data);
+                assert!(triples.iter().all(|t| t.offset as usize <= lz.window()
+                                           && t.length as usize <= lz.max_match()));
+                assert_eq!(decompress(&triples).unwrap(), data);
+                assert_eq!(decode(&lz.encode(&data)).unwrap(), data);
+            }
+        }
+        let text = b"the quick brown fox jumped over the lazy dog. ".repeat(3000);
+        assert!(Lz77::default().encode(&text).len() < text.len() / 20);
+    }
+
+    #[test]
+    fn test_streams() {
+        for lz in &[Lz77::default(), Lz77::new(100, 10)] {
+            for data in samples() {
+                let mut encoder = Lz77Encoder::new(Vec::new(), *lz);
+                for chunk in data.chunks(1000) {
+                    encoder.write_all(chunk).unwrap();
+                }
+                let encoded = encoder.finish().unwrap();
+                assert_eq!(encoded, lz.encode(&data));
+
+                let mut decoded = Vec::new();
+                Lz77Decoder::new(Trickle(&encoded)).read_to_end(&mut decoded).unwrap();
+                assert_eq!(decoded, data);
+            }
+        }
+
+        let mut sink = Vec::new();
+        let err = Lz77Decoder::new(&[0u8, 0, 0][..]).read_to_end(&mut sink).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = Lz77Decoder::new(&[0u8, 1, 0, 1, 0][..]).read_to_end(&mut sink).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_limits() {
+        let data = vec![7u8; 10_000];
+        let encoded = Lz77::new(16, 300).encode(&data);
+        let limits = DecodeOptions::new().max_output_bytes(9999);
+        assert_eq!(decode_with(&encoded, &limits),
+                   Err(Lz77Error::LimitExceeded(LimitExceeded { limit: Limit::OutputBytes, max: 9999 })));
+        assert_eq!(decode_with(&encoded, &limits.max_output_bytes(10_000)).unwrap(), data);
+
+        let mut sink = Vec::new();
+        let err = Lz77Decoder::new(&encoded[..]).limits(limits).read_to_end(&mut sink).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(sink.len() < 10_000);
+        sink.clear();
+        Lz77Decoder::new(&encoded[..]).limits(limits.max_output_bytes(10_000)).read_to_end(&mut sink).unwrap();
+        assert_eq!(sink, data);
+    }
+
+}
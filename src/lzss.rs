@@ -0,0 +1,400 @@
+// LZSS (Storer & Szymanski, 1982): like LZ77, but each token is either a
+// literal or a match, told apart by a flag bit, so a match doesn't have to
+// drag a literal along and short matches that wouldn't pay for themselves
+// aren't used at all. Window size, shortest and longest match, and how
+// matches are found and chosen (see matchfind) are set on the builder;
+// Lzss::level picks them all for a compression level from 1 to 9.
+//
+// Serialized, the tokens make a byte stream meant to go through one of the
+// entropy coders (any Coder, trained on it):
+//
+//   min_match  u8
+//   groups of up to 8 tokens: a flag byte, bit i (LSB first) set if token
+//   i is a match, then the tokens: a literal byte, or a match as
+//   offset - 1 and length - min_match, both u16 big-endian
+//
+// Everything is byte-aligned and matches cost the same five bytes whatever
+// their size; the entropy coder takes care of the skew.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+use crate::dictionary::Dictionary;
+use crate::limits::{DecodeOptions, LimitExceeded};
+use crate::matchfind::{self, BinaryTree, CostModel, FinderKind, HashChain, Match, Parse, Step, Strategy};
+
+pub const MAX_WINDOW: usize = 1 << 16;
+pub const MAX_MATCH: usize = u16::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    // Copy `length` bytes starting `offset` bytes back
+    Match { offset: u32, length: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lzss {
+    window: usize,
+    min_match: usize,
+    max_match: usize,
+    strategy: Strategy,
+}
+
+impl Lzss {
+
+    // The classic parameters: a 4K window, matches of 3 to 18 bytes, greedy,
+    // from a hash chain
+    pub fn new() -> Self {
+        let strategy = Strategy { finder: FinderKind::HashChain, parse: Parse::Greedy, depth: 128 };
+        Lzss { window: 4096, min_match: 3, max_match: 18, strategy }
+    }
+
+    // A 32K window and matches of up to 258 bytes (64K and 1024 at level 9),
+    // with Strategy::level's finder and parse. Panics unless 1 <= level <= 9.
+    pub fn level(level: u8) -> Self {
+        let lz = Lzss::new().strategy(Strategy::level(level));
+        match level {
+            9 => lz.window(MAX_WINDOW).max_match(1024),
+            _ => lz.window(32 * 1024).max_match(258),
+        }
+    }
+
+    // Panics unless 1 <= window <= MAX_WINDOW
+    pub fn window(mut self, window: usize) -> Self {
+        assert!((1..=MAX_WINDOW).contains(&window), "window must be in 1..={}", MAX_WINDOW);
+        self.window = window;
+        self
+    }
+
+    // Panics unless 2 <= min_match <= 255. A max_match below it is raised
+    // to match.
+    pub fn min_match(mut self, min_match: usize) -> Self {
+        assert!((2..=u8::MAX as usize).contains(&min_match), "min_match must be in 2..=255");
+        self.min_match = min_match;
+        self.max_match = self.max_match.max(min_match);
+        self
+    }
+
+    // Panics unless min_match <= max_match <= MAX_MATCH
+    pub fn max_match(mut self, max_match: usize) -> Self {
+        assert!((self.min_match..=MAX_MATCH).contains(&max_match),
+                "max_match must be in {}..={}", self.min_match, MAX_MATCH);
+        self.max_match = max_match;
+        self
+    }
+
+    // Greedy or lazy matching, in place of the current parse
+    pub fn lazy(mut self, lazy: bool) -> Self {
+        self.strategy.parse = if lazy { Parse::Lazy } else { Parse::Greedy };
+        self
+    }
+
+    // Panics on a depth of zero
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        assert!(strategy.depth > 0, "match finder depth must be positive");
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn get_window(&self) -> usize {
+        self.window
+    }
+
+    pub fn get_min_match(&self) -> usize {
+        self.min_match
+    }
+
+    pub fn get_max_match(&self) -> usize {
+        self.max_match
+    }
+
+    pub fn is_lazy(&self) -> bool {
+        self.strategy.parse == Parse::Lazy
+    }
+
+    pub fn get_strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Vec<Token> {
+        self.compress_after(data, 0)
+    }
+
+    // Matches can reach back into the dictionary as if it came just before
+    // `data`; only the last `window` bytes of it are in reach
+    pub fn compress_with_dict(&self, data: &[u8], dict: &Dictionary) -> Vec<Token> {
+        let history = &dict.as_bytes()[dict.len().saturating_sub(self.window)..];
+        self.compress_after(&[history, data].concat(), history.len())
+    }
+
+    // Tokens for data[start..], with what's before it as history
+    fn compress_after(&self, data: &[u8], start: usize) -> Vec<Token> {
+        let Strategy { finder, parse, depth } = self.strategy;
+        let steps = match finder {
+            FinderKind::HashChain => {
+                let finder = HashChain::new(self.window, self.min_match, self.max_match, depth);
+                matchfind::parse(data, start, parse, finder, &SerializedSize)
+            }
+            FinderKind::BinaryTree => {
+                let finder = BinaryTree::new(self.window, self.min_match, self.max_match, depth);
+                matchfind::parse(data, start, parse, finder, &SerializedSize)
+            }
+        };
+        let mut pos = start;
+        steps.into_iter().map(|step| match step {
+            Step::Literal => {
+                pos += 1;
+                Token::Literal(data[pos - 1])
+            }
+            Step::Match(Match { offset, length }) => {
+                pos += length;
+                Token::Match { offset: offset as u32, length: length as u32 }
+            }
+        }).collect()
+    }
+
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        serialize(&self.compress(data), self.min_match)
+    }
+
+    pub fn encode_with_dict(&self, data: &[u8], dict: &Dictionary) -> Vec<u8> {
+        serialize(&self.compress_with_dict(data, dict), self.min_match)
+    }
+
+}
+
+impl Default for Lzss {
+    fn default() -> Self {
+        Lzss::new()
+    }
+}
+
+// Bits a token takes serialized: its flag, and one byte or four
+struct SerializedSize;
+
+impl CostModel for SerializedSize {
+
+    fn literal(&self, _: u8) -> u32 {
+        9
+    }
+
+    fn matched(&self, _: Match) -> u32 {
+        33
+    }
+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LzssError {
+    // The stream ends partway through a token, or before the header
+    Truncated,
+    // The token at this index reaches back before the start of the output
+    BadOffset(usize),
+    LimitExceeded(LimitExceeded),
+}
+
+impl fmt::Display for LzssError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LzssError::Truncated => write!(f, "LZSS data is truncated"),
+            LzssError::BadOffset(i) => write!(f, "token {} refers back past the start of the data", i),
+            LzssError::LimitExceeded(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for LzssError {}
+
+impl From<LimitExceeded> for LzssError {
+    fn from(e: LimitExceeded) -> Self {
+        LzssError::LimitExceeded(e)
+    }
+}
+
+// Panics on a match shorter than min_match or too long or far for the format
+pub fn serialize(tokens: &[Token], min_match: usize) -> Vec<u8> {
+    let mut out = vec![min_match as u8];
+    for group in tokens.chunks(8) {
+        let flags_at = out.len();
+        out.push(0);
+        for (i, token) in group.iter().enumerate() {
+            match *token {
+                Token::Literal(b) => out.push(b),
+                Token::Match { offset, length } => {
+                    let offset = u16::try_from(offset - 1).expect("offset within MAX_WINDOW");
+                    let length = u16::try_from(length as usize - min_match).expect("length within the format");
+                    out[flags_at] |= 1 << i;
+                    out.extend_from_slice(&offset.to_be_bytes());
+                    out.extend_from_slice(&length.to_be_bytes());
+                }
+            }
+        }
+    }
+    out
+}
+
+pub fn deserialize(bytes: &[u8]) -> Result<Vec<Token>, LzssError> {
+    let (&min_match, mut rest) = bytes.split_first().ok_or(LzssError::Truncated)?;
+    let mut tokens = Vec::new();
+    while let Some((&flags, tail)) = rest.split_first() {
+        rest = tail;
+        for i in 0..8 {
+            if flags & 1 << i == 0 {
+                match rest.split_first() {
+                    Some((&b, tail)) => {
+                        tokens.push(Token::Literal(b));
+                        rest = tail;
+                    }
+                    // a short final group; only literal flags can go unused
+                    None if flags >> i == 0 => break,
+                    None => return Err(LzssError::Truncated),
+                }
+            } else {
+                if rest.len() < 4 {
+                    return Err(LzssError::Truncated);
+                }
+                let offset = u16::from_be_bytes([rest[0], rest[1]]) as u32 + 1;
+                let length = u16::from_be_bytes([rest[2], rest[3]]) as u32 + min_match as u32;
+                tokens.push(Token::Match { offset, length });
+                rest = &rest[4..];
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+pub fn decompress(tokens: &[Token]) -> Result<Vec<u8>, LzssError> {
+    decompress_after(Vec::new(), tokens, &DecodeOptions::new())
+}
+
+// Needs the dictionary the tokens were made with; a different one decodes
+// to the wrong bytes, or fails if the offsets don't fit it
+pub fn decompress_with_dict(tokens: &[Token], dict: &Dictionary) -> Result<Vec<u8>, LzssError> {
+    let out = decompress_after(dict.as_bytes().to_vec(), tokens, &DecodeOptions::new())?;
+    Ok(out[dict.len()..].to_vec())
+}
+
+// Append what the tokens stand for to `out`, which they can refer back into.
+// What's already in `out` doesn't count against the limit.
+fn decompress_after(mut out: Vec<u8>, tokens: &[Token], limits: &DecodeOptions) -> Result<Vec<u8>, LzssError> {
+    let base = out.len() as u64;
+    for (i, token) in tokens.iter().enumerate() {
+        match *token {
+            Token::Literal(b) => {
+                limits.check_output(out.len() as u64 - base + 1)?;
+                out.push(b);
+            }
+            Token::Match { offset, length } => {
+                let offset = offset as usize;
+                if offset == 0 || offset > out.len() {
+                    return Err(LzssError::BadOffset(i));
+                }
+                limits.check_output(out.len() as u64 - base + length as u64)?;
+                // The match may overlap the bytes it produces, so copy one at a time
+                let start = out.len() - offset;
+                for j in 0..length as usize {
+                    let b = out[start + j];
+                    out.push(b);
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Vec<u8>, LzssError> {
+    decompress(&deserialize(bytes)?)
+}
+
+// decode, stopping with LimitExceeded before the output passes
+// max_output_bytes. Every match costs five bytes however long it is, so
+// without a limit a few kilobytes can stand for gigabytes.
+pub fn decode_with(bytes: &[u8], limits: &DecodeOptions) -> Result<Vec<u8>, LzssError> {
+    decompress_after(Vec::new(), &deserialize(bytes)?, limits)
+}
+
+pub fn decode_with_dict(bytes: &[u8], dict: &Dictionary) -> Result<Vec<u8>, LzssError> {
+    decompress_with_dict(&deserialize(bytes)?, dict)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{decode, decode_with, decompress, decompress_with_dict, deserialize, serialize, Lzss, LzssError, Token};
+    use crate::coder::CodecId;
+    use crate::dictionary::Dictionary;
+    use crate::limits::{DecodeOptions, Limit, LimitExceeded};
+
+    fn samples() -> Vec<Vec<u8>> {
+        vec![b"".to_vec(), b"a".to_vec(), b"aaaaaaaaaaaaaaaaaaaaaa".to_vec(), (0..=255u8).collect(),
+             b"the quick brown fox jumped over the lazy dog. ".repeat(500)]
+    }
+
+    #[test]
+    fn test_tokens() {
+        let tokens = Lzss::new().compress(b"abababab");
+        let m = |offset, length| Token::Match { offset, length };
+        assert_eq!(tokens, vec![Token::Literal(b'a'), Token::Literal(b'b'), m(2, 6)]);
+        assert_eq!(decompress(&tokens).unwrap(), b"abababab");
+
+        // greedy takes "abc" at once; lazy sees "bcdef" starting one later
+        let data = b"abc.bcdef.abcdef";
+        let greedy = Lzss::new().compress(data);
+        let lazy = Lzss::new().lazy(true).compress(data);
+        assert_eq!(&greedy[10..], &[m(10, 3), m(7, 3)]);
+        assert_eq!(&lazy[10..], &[Token::Literal(b'a'), m(7, 5)]);
+
+        assert_eq!(decompress(&[m(1, 3)]), Err(LzssError::BadOffset(0)));
+        assert_eq!(decode(&[3, 1, 0, 0]), Err(LzssError::Truncated));
+        assert_eq!(deserialize(&[]), Err(LzssError::Truncated));
+
+        // only the last `window` bytes of the dictionary are in reach
+        let dict = Dictionary::new(b"abcdefgh..........".to_vec());
+        assert_eq!(Lzss::new().compress_with_dict(b"abcdefgh", &dict), vec![m(18, 8)]);
+        let near = Lzss::new().window(10).compress_with_dict(b"abcdefgh", &dict);
+        assert!(near.iter().all(|t| matches!(t, Token::Literal(_))));
+        assert_eq!(decompress_with_dict(&[m(18, 8)], &dict).unwrap(), b"abcdefgh");
+        assert_eq!(decompress_with_dict(&[m(19, 8)], &dict), Err(LzssError::BadOffset(0)));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut configs = vec![Lzss::new(), Lzss::new().lazy(true), Lzss::new().window(1 << 16).max_match(258),
+                               Lzss::new().min_match(2).max_match(2), Lzss::new().window(1).min_match(5)];
+        configs.extend((1..=9).map(Lzss::level));
+        for lz in configs.iter() {
+            for data in samples() {
+                assert_eq!(decode(&lz.encode(&data)).unwrap(), data);
+            }
+        }
+        let text = &samples()[4];
+        let big = Lzss::new().window(1 << 16).max_match(1000);
+        assert!(big.encode(text).len() < text.len() / 20);
+        assert!(Lzss::level(9).encode(text).len() <= Lzss::level(1).encode(text).len());
+
+        // the token bytes go through any entropy coder
+        let tokens = Lzss::new().lazy(true).encode(text);
+        for codec in CodecId::ALL.iter() {
+            let coder = codec.train(&tokens).unwrap();
+            let bits = coder.encode(&tokens).unwrap();
+            assert_eq!(decode(&coder.decode(&bits).unwrap()).unwrap(), *text);
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        // 300 bytes of tokens for 4MB of zeros
+        let mut tokens = vec![Token::Literal(0)];
+        tokens.extend(std::iter::repeat_n(Token::Match { offset: 1, length: 1 << 16 }, 64));
+        let bomb = serialize(&tokens, 3);
+        let limits = DecodeOptions::new().max_output_bytes(1 << 20);
+        let over = LimitExceeded { limit: Limit::OutputBytes, max: 1 << 20 };
+        assert_eq!(decode_with(&bomb, &limits), Err(LzssError::LimitExceeded(over)));
+        assert_eq!(decode_with(&bomb, &limits.max_output_bytes((1 << 22) + 1)).unwrap().len(), (1 << 22) + 1);
+        assert_eq!(decode_with(&[3, 0, 1], &limits.max_output_bytes(0)).unwrap_err(),
+                   LzssError::LimitExceeded(LimitExceeded { limit: Limit::OutputBytes, max: 0 }));
+    }
+
+}
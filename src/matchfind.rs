@@ -0,0 +1,511 @@
+// Match finders for the LZ stages. Finding matches is where an LZ coder
+// spends its time, and how hard it looks decides how good the matches are,
+// so the finder and the way its matches are turned into tokens (the parse)
+// are chosen separately:
+//
+//   HashChain   lists the earlier positions sharing a hash of the next few
+//               bytes, newest first, and tries up to `depth` of them
+//   BinaryTree  keeps the window's positions in a binary search tree
+//               ordered by the bytes that follow them (LZMA's bt), so a
+//               search visits matches in order of length, finding the
+//               longest with fewer comparisons on repetitive data
+//
+//   Greedy      takes the longest match at each position
+//   Lazy        first checks whether the next position starts a longer
+//               one, and if so emits a literal instead
+//   Optimal     finds the cheapest token sequence for the whole input as a
+//               shortest path, positions as nodes and tokens as edges
+//               weighted by their cost in bits
+//
+// Strategy::level picks a combination for levels 1 (fast) to 9 (small).
+
+use std::cmp;
+
+const HASH_BITS: u32 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub offset: usize,
+    pub length: usize,
+}
+
+// A match finder over one input slice. Positions are fed in order, each
+// exactly once, through either find() or skip().
+pub trait MatchFinder {
+    // Matches at `pos` of at least min_match bytes into `out`, lengths
+    // strictly increasing (so the last is the longest found); then index
+    // `pos` for later searches
+    fn find(&mut self, data: &[u8], pos: usize, out: &mut Vec<Match>);
+    // Index `pos` without searching
+    fn skip(&mut self, data: &[u8], pos: usize);
+    fn min_match(&self) -> usize;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FinderKind {
+    HashChain,
+    BinaryTree,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Parse {
+    Greedy,
+    Lazy,
+    Optimal,
+}
+
+// What an LZ stage should use, with `depth` the most candidates a finder
+// looks at per position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Strategy {
+    pub finder: FinderKind,
+    pub parse: Parse,
+    pub depth: usize,
+}
+
+impl Strategy {
+
+    // Panics unless 1 <= level <= 9
+    pub fn level(level: u8) -> Self {
+        let (finder, parse, depth) = match level {
+            1 => (FinderKind::HashChain, Parse::Greedy, 4),
+            2 => (FinderKind::HashChain, Parse::Greedy, 8),
+            3 => (FinderKind::HashChain, Parse::Greedy, 16),
+            4 => (FinderKind::HashChain, Parse::Lazy, 16),
+            5 => (FinderKind::HashChain, Parse::Lazy, 32),
+            6 => (FinderKind::HashChain, Parse::Lazy, 128),
+            7 => (FinderKind::BinaryTree, Parse::Lazy, 32),
+            8 => (FinderKind::BinaryTree, Parse::Optimal, 32),
+            9 => (FinderKind::BinaryTree, Parse::Optimal, 128),
+            _ => panic!("level must be in 1..=9"),
+        };
+        Strategy { finder, parse, depth }
+    }
+
+}
+
+// Level 6, the usual default
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::level(6)
+    }
+}
+
+pub struct HashChain {
+    window: usize,
+    min_match: usize,
+    max_match: usize,
+    depth: usize,
+    // Most recent position (plus one; zero for none) with each hash
+    head: Vec<usize>,
+    // prev[pos % window] is the previous position with the same hash as pos
+    prev: Vec<usize>,
+}
+
+impl HashChain {
+
+    // Panics unless window, min_match and depth are positive and
+    // min_match <= max_match
+    pub fn new(window: usize, min_match: usize, max_match: usize, depth: usize) -> Self {
+        assert!(window > 0 && min_match > 0 && depth > 0 && min_match <= max_match, "invalid match finder parameters");
+        HashChain { window, min_match, max_match, depth, head: vec![0; 1 << HASH_BITS], prev: vec![0; window] }
+    }
+
+}
+
+impl MatchFinder for HashChain {
+
+    fn find(&mut self, data: &[u8], pos: usize, out: &mut Vec<Match>) {
+        let limit = cmp::min(self.max_match, data.len() - pos);
+        if limit >= self.min_match {
+            let target = &data[pos..pos + limit];
+            let mut best = self.min_match - 1;
+            let mut candidate = self.head[hash(data, pos, self.min_match)];
+            let mut chain = self.depth;
+            while candidate != 0 && chain > 0 {
+                let c = candidate - 1;
+                if pos - c > self.window {
+                    break;
+                }
+                let len = data[c..].iter().zip(target).take_while(|(a, b)| a == b).count();
+                if len > best {
+                    best = len;
+                    out.push(Match { offset: pos - c, length: len });
+                    if len == limit {
+                        break;
+                    }
+                }
+                candidate = self.prev[c % self.window];
+                chain -= 1;
+            }
+        }
+        self.skip(data, pos);
+    }
+
+    fn skip(&mut self, data: &[u8], pos: usize) {
+        if pos + key_len(self.min_match) > data.len() {
+            return;
+        }
+        let h = hash(data, pos, self.min_match);
+        self.prev[pos % self.window] = self.head[h];
+        self.head[h] = pos + 1;
+    }
+
+    fn min_match(&self) -> usize {
+        self.min_match
+    }
+
+}
+
+// Each position in the window is a node with two children, stored at
+// tree[2 * (pos % (window + 1))] (smaller suffixes) and the slot after it
+// (greater); positions are stored plus one, zero for none. The extra slot
+// keeps a position from sharing one with the oldest it can match. Every
+// hash bucket roots a tree, and a new position becomes the root of its
+// bucket's tree, the old tree split around it on the way down. Nodes that
+// have left the window are cut off when reached.
+//
+// Positions closer than max_match to the end are searched but not added:
+// a shortened key would break the ordering the search relies on.
+pub struct BinaryTree {
+    window: usize,
+    min_match: usize,
+    max_match: usize,
+    depth: usize,
+    head: Vec<usize>,
+    tree: Vec<usize>,
+}
+
+impl BinaryTree {
+
+    // Panics unless window, min_match and depth are positive and
+    // min_match <= max_match
+    pub fn new(window: usize, min_match: usize, max_match: usize, depth: usize) -> Self {
+        assert!(window > 0 && min_match > 0 && depth > 0 && min_match <= max_match, "invalid match finder parameters");
+        BinaryTree { window, min_match, max_match, depth, head: vec![0; 1 << HASH_BITS], tree: vec![0; 2 * (window + 1)] }
+    }
+
+    // Walk the tree for `pos`, collecting matches; with `insert`, also
+    // make `pos` the new root, splitting the tree into its two subtrees
+    fn walk(&mut self, data: &[u8], pos: usize, insert: bool, mut out: Option<&mut Vec<Match>>) {
+        let limit = cmp::min(self.max_match, data.len() - pos);
+        let h = hash(data, pos, self.min_match);
+        let mut candidate = self.head[h];
+        let slot = 2 * (pos % (self.window + 1));
+        // where the next node smaller (left) or greater (right) than pos goes
+        let (mut left, mut right) = (slot, slot + 1);
+        // bytes every node still to come shares with pos, from each side
+        let (mut left_len, mut right_len) = (0, 0);
+        let mut best = self.min_match - 1;
+        if insert {
+            self.head[h] = pos + 1;
+        }
+        let mut depth = self.depth;
+        loop {
+            if candidate == 0 || pos - (candidate - 1) > self.window || depth == 0 {
+                if insert {
+                    self.tree[left] = 0;
+                    self.tree[right] = 0;
+                }
+                return;
+            }
+            depth -= 1;
+            let c = candidate - 1;
+            let node = 2 * (c % (self.window + 1));
+            let mut len = cmp::min(left_len, right_len);
+            while len < limit && data[c + len] == data[pos + len] {
+                len += 1;
+            }
+            if len > best {
+                best = len;
+                if let Some(out) = out.as_mut() {
+                    out.push(Match { offset: pos - c, length: len });
+                }
+            }
+            if len == limit {
+                // as long as pos could see: pos takes over c's children
+                if insert {
+                    self.tree[left] = self.tree[node];
+                    self.tree[right] = self.tree[node + 1];
+                }
+                return;
+            }
+            if data[c + len] < data[pos + len] {
+                // c and its smaller subtree sort before pos
+                if insert {
+                    self.tree[left] = candidate;
+                }
+                left = node + 1;
+                candidate = self.tree[left];
+                left_len = len;
+            } else {
+                if insert {
+                    self.tree[right] = candidate;
+                }
+                right = node;
+                candidate = self.tree[right];
+                right_len = len;
+            }
+        }
+    }
+
+    fn can_insert(&self, data: &[u8], pos: usize) -> bool {
+        pos + self.max_match <= data.len()
+    }
+
+}
+
+impl MatchFinder for BinaryTree {
+
+    fn find(&mut self, data: &[u8], pos: usize, out: &mut Vec<Match>) {
+        if data.len() - pos >= self.min_match {
+            let insert = self.can_insert(data, pos);
+            self.walk(data, pos, insert, Some(out));
+        }
+    }
+
+    fn skip(&mut self, data: &[u8], pos: usize) {
+        if self.can_insert(data, pos) {
+            self.walk(data, pos, true, None);
+        }
+    }
+
+    fn min_match(&self) -> usize {
+        self.min_match
+    }
+
+}
+
+// Bytes hashed: up to three of the min_match every match shares
+fn key_len(min_match: usize) -> usize {
+    cmp::min(min_match, 3)
+}
+
+fn hash(data: &[u8], pos: usize, min_match: usize) -> usize {
+    let key = data[pos..pos + key_len(min_match)].iter().fold(0u32, |acc, &b| acc << 8 | b as u32);
+    (key.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+// What a token costs, for the optimal parse
+pub trait CostModel {
+    fn literal(&self, byte: u8) -> u32;
+    fn matched(&self, m: Match) -> u32;
+}
+
+// A parsed input: a literal, or a match covering the next `length` bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Literal,
+    Match(Match),
+}
+
+// Steps covering data[start..]. The bytes before `start` are history, such
+// as a preset dictionary: matches can reach into them, but they aren't
+// parsed themselves.
+pub fn parse<F, C>(data: &[u8], start: usize, parse: Parse, mut finder: F, costs: &C) -> Vec<Step>
+    where F: MatchFinder, C: CostModel {
+    for pos in 0..start {
+        finder.skip(data, pos);
+    }
+    match parse {
+        Parse::Greedy => parse_greedy(data, start, &mut finder, false),
+        Parse::Lazy => parse_greedy(data, start, &mut finder, true),
+        Parse::Optimal => parse_optimal(data, start, &mut finder, costs),
+    }
+}
+
+fn parse_greedy<F: MatchFinder>(data: &[u8], start: usize, finder: &mut F, lazy: bool) -> Vec<Step> {
+    let mut steps = Vec::new();
+    let mut found = Vec::new();
+    let mut longest = |finder: &mut F, pos: usize| {
+        found.clear();
+        finder.find(data, pos, &mut found);
+        found.last().cloned()
+    };
+    // positions before `indexed` have been fed to the finder
+    let mut indexed = start;
+    // the search at `pos` when a lazy check already did it
+    let mut pending = None;
+    let mut pos = start;
+    while pos < data.len() {
+        let current = match pending.take() {
+            Some(found) => found,
+            None => {
+                indexed = pos + 1;
+                longest(finder, pos)
+            }
+        };
+        let m = match current {
+            Some(m) => m,
+            None => {
+                steps.push(Step::Literal);
+                pos += 1;
+                continue;
+            }
+        };
+        if lazy && pos + 1 < data.len() {
+            let next = longest(finder, pos + 1);
+            indexed = pos + 2;
+            if next.is_some_and(|n| n.length > m.length) {
+                steps.push(Step::Literal);
+                pos += 1;
+                pending = Some(next);
+                continue;
+            }
+        }
+        for p in indexed..pos + m.length {
+            finder.skip(data, p);
+        }
+        indexed = cmp::max(indexed, pos + m.length);
+        steps.push(Step::Match(m));
+        pos += m.length;
+    }
+    steps
+}
+
+fn parse_optimal<F, C>(data: &[u8], start: usize, finder: &mut F, costs: &C) -> Vec<Step>
+    where F: MatchFinder, C: CostModel {
+    let n = data.len() - start;
+    // cost[i]: cheapest way to code data[start..start + i], and the step
+    // ending there
+    let mut cost = vec![u64::MAX; n + 1];
+    let mut via = vec![Step::Literal; n + 1];
+    cost[0] = 0;
+    let mut found = Vec::new();
+    for pos in 0..n {
+        found.clear();
+        finder.find(data, start + pos, &mut found);
+        let here = cost[pos];
+        let lit = here + costs.literal(data[start + pos]) as u64;
+        if lit < cost[pos + 1] {
+            cost[pos + 1] = lit;
+            via[pos + 1] = Step::Literal;
+        }
+        // every length up to each match's, at that match's offset
+        let mut shorter = finder.min_match() - 1;
+        for m in &found {
+            for length in shorter + 1..=m.length {
+                let step = Match { offset: m.offset, length };
+                let c = here + costs.matched(step) as u64;
+                if c < cost[pos + length] {
+                    cost[pos + length] = c;
+                    via[pos + length] = Step::Match(step);
+                }
+            }
+            shorter = m.length;
+        }
+    }
+
+    let mut steps = Vec::new();
+    let mut end = n;
+    while end > 0 {
+        let step = via[end];
+        end -= match step {
+            Step::Literal => 1,
+            Step::Match(m) => m.length,
+        };
+        steps.push(step);
+    }
+    steps.reverse();
+    steps
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{parse, BinaryTree, CostModel, FinderKind, HashChain, Match, MatchFinder, Parse, Step, Strategy};
+
+    // A literal is a flag bit and a byte; a match a flag bit and 4 bytes
+    struct Flat;
+
+    impl CostModel for Flat {
+        fn literal(&self, _: u8) -> u32 {
+            9
+        }
+        fn matched(&self, _: Match) -> u32 {
+            33
+        }
+    }
+
+    fn expand(data: &[u8], steps: &[Step]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for step in steps {
+            match *step {
+                Step::Literal => out.push(data[out.len()]),
+                Step::Match(m) => {
+                    assert!(m.offset > 0 && m.offset <= out.len());
+                    for _ in 0..m.length {
+                        out.push(out[out.len() - m.offset]);
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_finders() {
+        let data = b"abcde_abcdx_abcdef_abcdefg";
+        let finders: Vec<Box<dyn MatchFinder>> = vec![Box::new(HashChain::new(64, 3, 8, 16)),
+                                                      Box::new(BinaryTree::new(64, 3, 8, 16))];
+        for mut finder in finders {
+            let mut found = Vec::new();
+            for pos in 0..12 {
+                finder.skip(data, pos);
+            }
+            finder.find(data, 12, &mut found);
+            let m = |offset, length| Match { offset, length };
+            assert_eq!(found, vec![m(6, 4), m(12, 5)]);
+            for pos in 13..19 {
+                finder.skip(data, pos);
+            }
+            found.clear();
+            finder.find(data, 19, &mut found);
+            assert_eq!(found.last(), Some(&m(7, 6)));
+        }
+        // with a 7-byte window only the nearest copy is in reach
+        let mut short = BinaryTree::new(7, 3, 8, 16);
+        let mut found = Vec::new();
+        for pos in 0..19 {
+            short.skip(data, pos);
+        }
+        short.find(data, 19, &mut found);
+        assert_eq!(found, vec![Match { offset: 7, length: 6 }]);
+    }
+
+    #[test]
+    fn test_parses() {
+        let mut x = 0x2545f491u32;
+        let mut data = b"it was the best of times, it was the worst of times, ".repeat(40);
+        data.extend((0..2000).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            b"abcd"[(x % 4) as usize]
+        }));
+        let total = |steps: &[Step]| -> u64 {
+            steps.iter().map(|s| match *s {
+                Step::Literal => 9,
+                Step::Match(m) => Flat.matched(m) as u64,
+            }).sum()
+        };
+        let mut costs = Vec::new();
+        for level in 1..=9 {
+            let s = Strategy::level(level);
+            let steps = match s.finder {
+                FinderKind::HashChain => parse(&data, 0, s.parse, HashChain::new(4096, 3, 258, s.depth), &Flat),
+                FinderKind::BinaryTree => parse(&data, 0, s.parse, BinaryTree::new(4096, 3, 258, s.depth), &Flat),
+            };
+            assert_eq!(expand(&data, &steps), data);
+            costs.push(total(&steps));
+        }
+        // the optimal parse is never beaten
+        let best = *costs.iter().min().unwrap();
+        assert_eq!(costs[8], best);
+        assert!(costs[0] > best);
+        for p in [Parse::Greedy, Parse::Lazy, Parse::Optimal].iter() {
+            assert!(parse(b"", 0, *p, HashChain::new(16, 3, 8, 4), &Flat).is_empty());
+        }
+    }
+
+}
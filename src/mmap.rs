@@ -0,0 +1,83 @@
+// Read-only maps of whole files, for container::compress_mmap. On unix the
+// file is mapped with mmap(2), so both passes over it read the page cache
+// directly instead of copying through a buffer; elsewhere it's read into
+// memory, which gives up that saving but keeps the API the same.
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+pub(crate) struct Mmap {
+    #[cfg(unix)]
+    ptr: *mut libc::c_void,
+    #[cfg(unix)]
+    len: usize,
+    #[cfg(not(unix))]
+    data: Vec<u8>,
+}
+
+impl Mmap {
+
+    // Safety: the file must not be truncated or written to while the map
+    // is alive; a read past the new end of the file kills the process
+    // with SIGBUS, and a write changes bytes behind the slice's back.
+    #[cfg(unix)]
+    pub(crate) unsafe fn open(path: &Path) -> io::Result<Self> {
+        use std::convert::TryFrom;
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::other("file is too big to map"))?;
+        if len == 0 {
+            // mmap refuses zero-length maps
+            return Ok(Mmap { ptr: std::ptr::null_mut(), len });
+        }
+        let ptr = libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0);
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // both passes read front to back; this is only a hint, so a failure
+        // changes nothing
+        libc::madvise(ptr, len, libc::MADV_SEQUENTIAL);
+        Ok(Mmap { ptr, len })
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) unsafe fn open(path: &Path) -> io::Result<Self> {
+        Ok(Mmap { data: std::fs::read(path)? })
+    }
+
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    #[cfg(unix)]
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // SAFETY: the map covers `len` readable bytes until drop, and open's
+        // caller promised nothing changes them
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+
+    #[cfg(not(unix))]
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // SAFETY: ptr and len are the map open() made, unmapped only here
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
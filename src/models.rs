@@ -0,0 +1,227 @@
+// Adaptive probability models over bytes for the arithmetic coder. A model
+// starts with every byte equally likely and shifts weight to what it has
+// seen; encoder and decoder update theirs in step, so nothing about the
+// model is transmitted.
+//
+// Order0 predicts from overall counts. ContextModel keeps one Order0 per
+// context, where a Context decides which one applies to the next byte from
+// the ones before it: the previous byte gives the classic order-1 model,
+// which on text captures digraphs that a static Huffman code can't.
+
+use crate::arithmetic::MAX_TOTAL;
+
+// Probabilities for the next byte, as counts; see arithmetic
+pub trait Model {
+
+    // Cumulative count of the bytes below `sym`, and the count of `sym`
+    fn range(&self, sym: u8) -> (u32, u32);
+
+    // Sum of all counts; at most arithmetic::MAX_TOTAL
+    fn total(&self) -> u32;
+
+    // The byte whose range holds `target` (below total()), with its range
+    fn find(&self, target: u32) -> (u8, u32, u32);
+
+    // Account for `sym` having been coded
+    fn update(&mut self, sym: u8);
+
+}
+
+// Each sighting adds this much, so recent bytes outweigh the initial 1s
+const INCREMENT: u32 = 24;
+
+#[derive(Debug, Clone)]
+pub struct Order0 {
+    counts: [u32; 256],
+    total: u32,
+}
+
+impl Order0 {
+
+    pub fn new() -> Self {
+        Order0 { counts: [1; 256], total: 256 }
+    }
+
+}
+
+impl Default for Order0 {
+    fn default() -> Self {
+        Order0::new()
+    }
+}
+
+impl Model for Order0 {
+
+    fn range(&self, sym: u8) -> (u32, u32) {
+        (self.counts[..sym as usize].iter().sum(), self.counts[sym as usize])
+    }
+
+    fn total(&self) -> u32 {
+        self.total
+    }
+
+    fn find(&self, target: u32) -> (u8, u32, u32) {
+        let mut low = 0;
+        for (sym, &count) in self.counts.iter().enumerate() {
+            if target < low + count {
+                return (sym as u8, low, count);
+            }
+            low += count;
+        }
+        panic!("target beyond the model's total");
+    }
+
+    fn update(&mut self, sym: u8) {
+        self.counts[sym as usize] += INCREMENT;
+        self.total += INCREMENT;
+        if self.total > MAX_TOTAL {
+            // halve, keeping every byte possible
+            self.total = 0;
+            for c in self.counts.iter_mut() {
+                *c = c.div_ceil(2);
+                self.total += *c;
+            }
+        }
+    }
+
+}
+
+// Which of a fixed number of contexts the next byte is coded in
+pub trait Context {
+
+    // How many there are; current() is always below this
+    fn contexts(&self) -> usize;
+
+    fn current(&self) -> usize;
+
+    // Move on past `sym`
+    fn push(&mut self, sym: u8);
+
+}
+
+// The previous byte (0 at the start)
+#[derive(Debug, Clone, Default)]
+pub struct PreviousByte(u8);
+
+impl Context for PreviousByte {
+
+    fn contexts(&self) -> usize {
+        256
+    }
+
+    fn current(&self) -> usize {
+        self.0 as usize
+    }
+
+    fn push(&mut self, sym: u8) {
+        self.0 = sym;
+    }
+
+}
+
+// An Order0 model for each context, created the first time it's used
+#[derive(Debug, Clone)]
+pub struct ContextModel<C: Context> {
+    context: C,
+    models: Vec<Option<Order0>>,
+    fallback: Order0, // what an unused context looks like
+}
+
+impl<C: Context> ContextModel<C> {
+
+    pub fn new(context: C) -> Self {
+        let n = context.contexts();
+        ContextModel { context, models: vec![None; n], fallback: Order0::new() }
+    }
+
+    pub fn context(&self) -> &C {
+        &self.context
+    }
+
+    fn model(&self) -> &Order0 {
+        self.models[self.context.current()].as_ref().unwrap_or(&self.fallback)
+    }
+
+}
+
+impl<C: Context> Model for ContextModel<C> {
+
+    fn range(&self, sym: u8) -> (u32, u32) {
+        self.model().range(sym)
+    }
+
+    fn total(&self) -> u32 {
+        self.model().total()
+    }
+
+    fn find(&self, target: u32) -> (u8, u32, u32) {
+        self.model().find(target)
+    }
+
+    fn update(&mut self, sym: u8) {
+        let current = self.context.current();
+        self.models[current].get_or_insert_with(Order0::new).update(sym);
+        self.context.push(sym);
+    }
+
+}
+
+pub type Order1 = ContextModel<PreviousByte>;
+
+impl Default for Order1 {
+    fn default() -> Self {
+        ContextModel::new(PreviousByte::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Context, ContextModel, Model, Order0, Order1};
+    use crate::arithmetic::{decode, encode};
+    use crate::huffman::ByteHuffman;
+
+    const TEXT: &str = "It was the best of times, it was the worst of times, it was the age of wisdom, \
+                        it was the age of foolishness, it was the epoch of belief, it was the epoch of \
+                        incredulity, it was the season of Light, it was the season of Darkness, it was \
+                        the spring of hope, it was the winter of despair.";
+
+    #[test]
+    fn test_models() {
+        let mut model = Order0::new();
+        assert_eq!((model.range(b'a'), model.total()), ((97, 1), 256));
+        for _ in 0..10_000 {
+            model.update(b'a');
+        }
+        assert!(model.total() <= super::MAX_TOTAL);
+        assert_eq!(model.range(b'b').1, 1);
+        let (low, freq) = model.range(b'a');
+        assert_eq!(model.find(low + freq - 1), (b'a', low, freq));
+
+        // order-0 does about as well as a static Huffman code (even with
+        // its table left out); order-1 does far better
+        let text = TEXT.repeat(16);
+        let huffman = ByteHuffman::new_bytes(text.as_bytes()).encode_bytes_packed(text.as_bytes()).0.len();
+        let order0 = encode(text.as_bytes(), Order0::new());
+        let order1 = encode(text.as_bytes(), Order1::default());
+        assert!(order0.len() < huffman * 21 / 20);
+        assert!(order1.len() * 3 < huffman * 2);
+        assert_eq!(decode(&order1, text.len(), Order1::default()).unwrap(), text.as_bytes());
+    }
+
+    #[test]
+    fn test_custom_context() {
+        // columns of a fixed-width record each get their own statistics
+        struct Column { pos: usize }
+        impl Context for Column {
+            fn contexts(&self) -> usize { 4 }
+            fn current(&self) -> usize { self.pos % 4 }
+            fn push(&mut self, _: u8) { self.pos += 1; }
+        }
+        let data: Vec<u8> = (0..2000u32).flat_map(|i| vec![b'#', (i % 7) as u8, b'0' + (i % 3) as u8, b'\n']).collect();
+        let packed = encode(&data, ContextModel::new(Column { pos: 0 }));
+        assert!(packed.len() < encode(&data, Order0::new()).len());
+        assert_eq!(decode(&packed, data.len(), ContextModel::new(Column { pos: 0 })).unwrap(), data);
+    }
+
+}
@@ -0,0 +1,119 @@
+// Move-to-front coding: each byte is replaced by its position in a list of
+// all 256 byte values, and then moved towards the front of the list.
+// Recently seen bytes get small ranks, so the clustered output of the BWT
+// turns into mostly zeros and ones for run-length and entropy coding.
+//
+// The MTF-1 and MTF-2 variants of Balkenhol, Kurtz and Shtarkov ("Modifications
+// of the Burrows and Wheeler Data Compression Algorithm") promote more
+// cautiously, so that one stray byte doesn't displace the current front.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MtfVariant {
+    // Every byte moves to the front
+    #[default]
+    Standard,
+    // A byte at rank 1 moves to the front, one further back to rank 1
+    Mtf1,
+    // As MTF-1, except a byte at rank 1 only moves to the front if the
+    // previous rank wasn't 0
+    Mtf2,
+}
+
+struct List {
+    order: [u8; 256],
+    variant: MtfVariant,
+    last_rank: usize,
+}
+
+impl List {
+
+    fn new(variant: MtfVariant) -> Self {
+        let mut order = [0u8; 256];
+        for (i, b) in order.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        // pretend the previous rank wasn't 0, so MTF-2 starts like MTF-1
+        List { order, variant, last_rank: 1 }
+    }
+
+    // Move whatever is at `rank` to where the variant says
+    fn promote(&mut self, rank: usize) {
+        let target = match (self.variant, rank) {
+            (_, 0) => 0,
+            (MtfVariant::Standard, _) => 0,
+            (MtfVariant::Mtf1, 1) => 0,
+            (MtfVariant::Mtf2, 1) if self.last_rank != 0 => 0,
+            _ => 1,
+        };
+        let b = self.order[rank];
+        self.order.copy_within(target..rank, target + 1);
+        self.order[target] = b;
+        self.last_rank = rank;
+    }
+
+}
+
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    encode_with(data, MtfVariant::Standard)
+}
+
+pub fn decode(ranks: &[u8]) -> Vec<u8> {
+    decode_with(ranks, MtfVariant::Standard)
+}
+
+pub fn encode_with(data: &[u8], variant: MtfVariant) -> Vec<u8> {
+    let mut list = List::new(variant);
+    data.iter()
+        .map(|&b| {
+            let rank = list.order.iter().position(|&x| x == b).unwrap();
+            list.promote(rank);
+            rank as u8
+        })
+        .collect()
+}
+
+pub fn decode_with(ranks: &[u8], variant: MtfVariant) -> Vec<u8> {
+    let mut list = List::new(variant);
+    ranks.iter()
+        .map(|&rank| {
+            let b = list.order[rank as usize];
+            list.promote(rank as usize);
+            b
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{decode, decode_with, encode, encode_with, MtfVariant};
+    use crate::bwt;
+
+    #[test]
+    fn test_mtf() {
+        assert_eq!(encode(b"aaabbbaaa"), vec![97, 0, 0, 98, 0, 0, 1, 0, 0]);
+        assert_eq!(encode(&[2, 1, 2, 0]), vec![2, 2, 1, 2]);
+        assert_eq!(decode(&[2, 2, 1, 2]), vec![2, 1, 2, 0]);
+    }
+
+    #[test]
+    fn test_variants() {
+        // bytes from further back only reach rank 1, so the lone b doesn't
+        // cost the a-run its rank 0
+        assert_eq!(encode_with(b"aaabaa", MtfVariant::Standard), vec![97, 0, 0, 98, 1, 0]);
+        assert_eq!(encode_with(b"aaabaa", MtfVariant::Mtf1), vec![97, 1, 0, 98, 0, 0]);
+        // after a rank 0, MTF-2 leaves a rank 1 byte where it is
+        assert_eq!(encode_with(b"aababa", MtfVariant::Mtf1), vec![97, 1, 98, 0, 1, 1]);
+        assert_eq!(encode_with(b"aababa", MtfVariant::Mtf2), vec![97, 1, 98, 0, 1, 0]);
+
+        let text = b"the quick brown fox jumped over the lazy dog".repeat(30);
+        let (transformed, _) = bwt::forward(&text);
+        for &variant in &[MtfVariant::Standard, MtfVariant::Mtf1, MtfVariant::Mtf2] {
+            let ranks = encode_with(&transformed, variant);
+            assert_eq!(decode_with(&ranks, variant), transformed);
+            // BWT output codes mostly to zeros
+            assert!(ranks.iter().filter(|&&r| r == 0).count() > ranks.len() * 3 / 4);
+        }
+    }
+
+}
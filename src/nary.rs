@@ -0,0 +1,276 @@
+// Huffman codes over a k-symbol output alphabet (2 <= k <= 256): each
+// codeword is a string of base-k digits, for channels that carry more than
+// bits (four-letter DNA storage, byte-aligned output with k = 256, ...).
+//
+// Huffman's algorithm carries over by merging the k least frequent nodes
+// at a time. That only ends in a full tree when (n - 1) is a multiple of
+// (k - 1), so the alphabet is first padded with zero-frequency dummy
+// symbols; they end up deepest in the tree, and the codewords that would
+// lead to them are never produced. Without the padding the top of the tree
+// would be the one left short, wasting short codewords.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::bitio::{self, BitReader};
+use crate::huffman::{BuildError, DecodeError, EncodeError, Symbol};
+
+enum NNode<S> {
+    Leaf(S),
+    Dummy,
+    Inner(Vec<usize>), // children, in digit order
+}
+
+pub struct NaryHuffman<S: Symbol = char> {
+    radix: usize,
+    freqs: HashMap<S, u64>,
+    // Arena of tree nodes; the root is the last one
+    nodes: Vec<NNode<S>>,
+    code: HashMap<S, Vec<u8>>,
+}
+
+impl<S: Symbol> NaryHuffman<S> {
+
+    // Panics on an empty map; see try_from_frequencies
+    pub fn from_frequencies(freq: &HashMap<S, u64>, radix: usize) -> Self {
+        NaryHuffman::try_from_frequencies(freq, radix).expect("can't build a Huffman code without symbols")
+    }
+
+    // Panics unless 2 <= radix <= 256. Ties are broken as in HuffmanCode:
+    // dummies, then leaves in symbol order, then merged nodes as created.
+    pub fn try_from_frequencies(freq: &HashMap<S, u64>, radix: usize) -> Result<Self, BuildError> {
+        assert!((2..=256).contains(&radix), "radix must be in 2..=256");
+        if freq.is_empty() {
+            return Err(BuildError::EmptyAlphabet);
+        }
+        let mut leaves: Vec<(&S, &u64)> = freq.iter().collect();
+        leaves.sort();
+
+        // a lone symbol still needs a one-digit codeword, so at least two leaves
+        let mut dummies = 0;
+        while leaves.len() + dummies < 2 || !(leaves.len() + dummies - 1).is_multiple_of(radix - 1) {
+            dummies += 1;
+        }
+        let mut nodes: Vec<NNode<S>> = Vec::with_capacity(2 * (leaves.len() + dummies));
+        let mut queue: BinaryHeap<Reverse<(u64, usize)>> = BinaryHeap::new();
+        for _ in 0..dummies {
+            queue.push(Reverse((0, nodes.len())));
+            nodes.push(NNode::Dummy);
+        }
+        for (sym, &f) in leaves {
+            queue.push(Reverse((f, nodes.len())));
+            nodes.push(NNode::Leaf(sym.clone()));
+        }
+        while queue.len() > 1 {
+            let mut total = 0u64;
+            let mut children = Vec::with_capacity(radix);
+            for _ in 0..radix {
+                let Reverse((f, i)) = queue.pop().expect("padding keeps merges full");
+                total += f;
+                children.push(i);
+            }
+            queue.push(Reverse((total, nodes.len())));
+            nodes.push(NNode::Inner(children));
+        }
+
+        let mut code = HashMap::new();
+        let mut stack = vec![(nodes.len() - 1, Vec::new())];
+        while let Some((i, prefix)) = stack.pop() {
+            match &nodes[i] {
+                NNode::Leaf(sym) => {
+                    code.insert(sym.clone(), prefix);
+                }
+                NNode::Dummy => {}
+                NNode::Inner(children) => {
+                    for (digit, &child) in children.iter().enumerate() {
+                        let mut next = prefix.clone();
+                        next.push(digit as u8);
+                        stack.push((child, next));
+                    }
+                }
+            }
+        }
+        Ok(NaryHuffman { radix, freqs: freq.clone(), nodes, code })
+    }
+
+    pub fn from_symbols<I: IntoIterator<Item=S>>(symbols: I, radix: usize) -> Self {
+        NaryHuffman::try_from_symbols(symbols, radix).expect("can't build a Huffman code for empty input")
+    }
+
+    pub fn try_from_symbols<I: IntoIterator<Item=S>>(symbols: I, radix: usize) -> Result<Self, BuildError> {
+        let mut freqs = HashMap::new();
+        for sym in symbols {
+            *freqs.entry(sym).or_insert(0) += 1;
+        }
+        NaryHuffman::try_from_frequencies(&freqs, radix)
+    }
+
+    pub fn radix(&self) -> usize {
+        self.radix
+    }
+
+    pub fn frequencies(&self) -> &HashMap<S, u64> {
+        &self.freqs
+    }
+
+    pub fn codeword(&self, sym: &S) -> Option<&[u8]> {
+        self.code.get(sym).map(|c| c.as_slice())
+    }
+
+    // Expected digits per symbol under the frequencies the code was built from
+    pub fn average_length(&self) -> f64 {
+        let total: u64 = self.freqs.values().sum();
+        if total == 0 {
+            return 0.0;
+        }
+        let digits: u64 = self.freqs.iter().map(|(sym, f)| f * self.code[sym].len() as u64).sum();
+        digits as f64 / total as f64
+    }
+
+    // Bits each digit takes when packed: ceil(log2(radix))
+    pub fn bits_per_digit(&self) -> u32 {
+        usize::BITS - (self.radix - 1).leading_zeros()
+    }
+
+    // Panics on a symbol without a codeword; see try_encode_digits
+    pub fn encode_digits(&self, symbols: &[S]) -> Vec<u8> {
+        self.try_encode_digits(symbols)
+            .unwrap_or_else(|e| panic!("no codeword for the symbol at position {}", e.pos))
+    }
+
+    pub fn try_encode_digits(&self, symbols: &[S]) -> Result<Vec<u8>, EncodeError<S>> {
+        let mut out = Vec::new();
+        for (pos, sym) in symbols.iter().enumerate() {
+            let codeword = self.code.get(sym).ok_or_else(|| EncodeError { symbol: sym.clone(), pos })?;
+            out.extend_from_slice(codeword);
+        }
+        Ok(out)
+    }
+
+    // Positions in errors count digits
+    pub fn decode_digits(&self, digits: &[u8]) -> Result<Vec<S>, DecodeError> {
+        self.decode_iter(digits.iter().cloned())
+    }
+
+    // Digits packed at bits_per_digit() bits each, MSB-first; returns the
+    // bytes and the number of digits. With radix 256 that's one byte per
+    // digit; radixes that aren't powers of two leave some values unused.
+    pub fn encode_packed(&self, symbols: &[S]) -> (Vec<u8>, usize) {
+        self.try_encode_packed(symbols)
+            .unwrap_or_else(|e| panic!("no codeword for the symbol at position {}", e.pos))
+    }
+
+    pub fn try_encode_packed(&self, symbols: &[S]) -> Result<(Vec<u8>, usize), EncodeError<S>> {
+        let digits = self.try_encode_digits(symbols)?;
+        let width = self.bits_per_digit();
+        let (packed, _) = bitio::pack(|w| {
+            for &d in &digits {
+                w.write_bits(d as u64, width)?;
+            }
+            Ok(())
+        });
+        Ok((packed, digits.len()))
+    }
+
+    // A digit value at or above the radix is an invalid bitstream
+    pub fn decode_packed(&self, bytes: &[u8], ndigits: usize) -> Result<Vec<S>, DecodeError> {
+        let width = self.bits_per_digit();
+        if (ndigits as u64) * (width as u64) > bytes.len() as u64 * 8 {
+            return Err(DecodeError::TruncatedCodeword { pos: bytes.len() * 8 / width as usize });
+        }
+        let mut reader = BitReader::new(bytes);
+        // width is at most 8, so every digit fits a u8
+        let digits = (0..ndigits).map(move |_| reader.read_bits(width).expect("length checked above") as u8);
+        self.decode_iter(digits)
+    }
+
+    fn decode_iter<I: Iterator<Item=u8>>(&self, digits: I) -> Result<Vec<S>, DecodeError> {
+        let root = self.nodes.len() - 1;
+        let mut ret = Vec::new();
+        let mut node = root;
+        let mut start = 0;
+        for (pos, d) in digits.enumerate() {
+            let children = match &self.nodes[node] {
+                NNode::Inner(children) => children,
+                _ => unreachable!("walk restarts at the root after every leaf"),
+            };
+            node = *children.get(d as usize).ok_or(DecodeError::InvalidBitstream { pos: start })?;
+            match &self.nodes[node] {
+                NNode::Leaf(sym) => {
+                    ret.push(sym.clone());
+                    node = root;
+                    start = pos + 1;
+                }
+                NNode::Dummy => return Err(DecodeError::InvalidBitstream { pos: start }),
+                NNode::Inner(_) => {}
+            }
+        }
+        if node != root {
+            return Err(DecodeError::TruncatedCodeword { pos: start });
+        }
+        Ok(ret)
+    }
+
+}
+
+// Digits as 0-9a-z, e.g. for printing a ternary or DNA code; None if any
+// digit is 36 or more
+pub fn digit_string(digits: &[u8]) -> Option<String> {
+    digits.iter().map(|&d| std::char::from_digit(d as u32, 36)).collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{digit_string, NaryHuffman};
+    use crate::huffman::{DecodeError, HuffmanCode};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_ternary() {
+        // 4 symbols, radix 3: one dummy makes (n - 1) even
+        let freqs: HashMap<char, u64> = [('a', 40), ('b', 30), ('c', 20), ('d', 10)].iter().cloned().collect();
+        let code = NaryHuffman::from_frequencies(&freqs, 3);
+        let lengths: Vec<usize> = "abcd".chars().map(|ch| code.codeword(&ch).unwrap().len()).collect();
+        assert_eq!(lengths, vec![1, 1, 2, 2]);
+        // without the dummy it's b, c, d under one node and 1.6 digits a symbol
+        assert_eq!(code.average_length(), 1.3);
+        assert_eq!(code.decode_digits(&[1, 0]), Err(DecodeError::InvalidBitstream { pos: 0 }));
+
+        let text: Vec<char> = "abcabcdd".chars().collect();
+        let digits = code.encode_digits(&text);
+        assert_eq!(code.decode_digits(&digits).unwrap(), text);
+        assert!(digit_string(&digits).unwrap().chars().all(|ch| "012".contains(ch)));
+        assert_eq!(code.decode_digits(&digits[..digits.len() - 1]),
+                   Err(DecodeError::TruncatedCodeword { pos: digits.len() - 2 }));
+        assert_eq!(code.decode_digits(&[3]), Err(DecodeError::InvalidBitstream { pos: 0 }));
+    }
+
+    #[test]
+    fn test_radixes() {
+        let data = b"ACGTTGCAACGGGTTTAAACCCGTGTGTACGATCGATCGTAGCTAGCTAGCTGATCGA".repeat(7);
+        let binary = HuffmanCode::from_symbols(data.iter().cloned());
+        for &radix in [2, 3, 4, 5, 16, 256].iter() {
+            let code = NaryHuffman::from_symbols(data.iter().cloned(), radix);
+            let (packed, ndigits) = code.encode_packed(&data);
+            assert_eq!(code.decode_packed(&packed, ndigits).unwrap(), data);
+            if radix == 2 {
+                // same lengths as the binary code
+                assert!(binary.code_lengths().all(|(b, len)| code.codeword(&b).unwrap().len() == len));
+            }
+        }
+        // four letters, four digits: one digit each
+        let dna = NaryHuffman::from_symbols(b"ACGT".iter().cloned(), 4);
+        assert_eq!(dna.bits_per_digit(), 2);
+        assert!(b"ACGT".iter().all(|b| dna.codeword(b).unwrap().len() == 1));
+        let bytes = NaryHuffman::from_symbols(0..=255u16, 256);
+        assert_eq!(bytes.encode_packed(&[7, 255]).0.len(), 2);
+
+        let lone = NaryHuffman::from_symbols("aaa".chars(), 7);
+        assert_eq!(lone.codeword(&'a').unwrap().len(), 1);
+        let (packed, ndigits) = lone.encode_packed(&['a', 'a']);
+        assert_eq!(lone.decode_packed(&packed, ndigits + 2), Err(DecodeError::TruncatedCodeword { pos: 2 }));
+        assert!(NaryHuffman::<char>::try_from_frequencies(&HashMap::new(), 3).is_err());
+    }
+
+}
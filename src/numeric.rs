@@ -0,0 +1,191 @@
+// Transforms that turn numeric sequences (timestamps, sensor readings,
+// counters) into small residuals for the integer codes and entropy coders:
+//
+// - delta: each value minus the one before it; a slowly changing series
+//   becomes small numbers
+// - delta of delta: the delta applied twice; a regular series (a clock
+//   ticking at a fixed rate) becomes mostly zeros
+// - zigzag: signed to unsigned as 0, -1, 1, -2, 2 ..., so residuals of
+//   small magnitude stay small whatever their sign
+//
+// The first value is kept as it is (its delta from 0). Arithmetic wraps,
+// so every i64 and u64 sequence round trips, however wide its jumps.
+//
+// Residuals can go on to codes::integers (Rice, Exp-Golomb) or a
+// HuffmanCode<u64>, or out as LEB128 varints with to_varints.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::codes::varint::{self, VarintError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumericError {
+    // The bytes end partway through a varint
+    Truncated,
+    // A varint longer than a u64 holds
+    Overflow,
+}
+
+impl fmt::Display for NumericError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NumericError::Truncated => write!(f, "varint data is truncated"),
+            NumericError::Overflow => write!(f, "varint overflows u64"),
+        }
+    }
+}
+
+impl Error for NumericError {}
+
+impl From<VarintError> for NumericError {
+    fn from(e: VarintError) -> Self {
+        match e {
+            VarintError::Truncated => NumericError::Truncated,
+            VarintError::Overflow => NumericError::Overflow,
+        }
+    }
+}
+
+// How many times to difference a sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delta {
+    None,
+    First,
+    Second, // delta of delta
+}
+
+pub fn zigzag(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+pub fn unzigzag(u: u64) -> i64 {
+    (u >> 1) as i64 ^ -((u & 1) as i64)
+}
+
+pub fn delta(values: &[i64]) -> Vec<i64> {
+    let mut prev = 0i64;
+    values.iter().map(|&v| {
+        let d = v.wrapping_sub(prev);
+        prev = v;
+        d
+    }).collect()
+}
+
+pub fn undelta(deltas: &[i64]) -> Vec<i64> {
+    let mut acc = 0i64;
+    deltas.iter().map(|&d| {
+        acc = acc.wrapping_add(d);
+        acc
+    }).collect()
+}
+
+pub fn delta_of_delta(values: &[i64]) -> Vec<i64> {
+    delta(&delta(values))
+}
+
+pub fn undelta_of_delta(deltas: &[i64]) -> Vec<i64> {
+    undelta(&undelta(deltas))
+}
+
+// Unsigned values, whose differences can be negative
+pub fn delta_u64(values: &[u64]) -> Vec<i64> {
+    let mut prev = 0u64;
+    values.iter().map(|&v| {
+        let d = v.wrapping_sub(prev) as i64;
+        prev = v;
+        d
+    }).collect()
+}
+
+pub fn undelta_u64(deltas: &[i64]) -> Vec<u64> {
+    let mut acc = 0u64;
+    deltas.iter().map(|&d| {
+        acc = acc.wrapping_add(d as u64);
+        acc
+    }).collect()
+}
+
+// Differenced `order` times and zigzagged: ready for an unsigned code
+pub fn residuals(values: &[i64], order: Delta) -> Vec<u64> {
+    let diffs = match order {
+        Delta::None => values.to_vec(),
+        Delta::First => delta(values),
+        Delta::Second => delta_of_delta(values),
+    };
+    diffs.into_iter().map(zigzag).collect()
+}
+
+pub fn from_residuals(residuals: &[u64], order: Delta) -> Vec<i64> {
+    let diffs: Vec<i64> = residuals.iter().map(|&u| unzigzag(u)).collect();
+    match order {
+        Delta::None => diffs,
+        Delta::First => undelta(&diffs),
+        Delta::Second => undelta_of_delta(&diffs),
+    }
+}
+
+// Unsigned LEB128, one after another; see codes::varint
+pub fn to_varints(values: &[u64]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(values.len());
+    for &v in values {
+        varint::write_u64(&mut out, v);
+    }
+    out
+}
+
+pub fn from_varints(bytes: &[u8]) -> Result<Vec<u64>, NumericError> {
+    let mut rest = bytes;
+    let mut out = Vec::new();
+    while !rest.is_empty() {
+        out.push(varint::read_u64(&mut rest)?);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{delta_u64, from_residuals, from_varints, residuals, to_varints, undelta_u64, unzigzag, zigzag,
+                Delta, NumericError};
+    use crate::bitio::{self, BitReader};
+    use crate::codes::integers::{read_rice, write_rice};
+
+    #[test]
+    fn test_transforms() {
+        assert_eq!([0, -1, 1, -2, 2, i64::MAX, i64::MIN].iter().map(|&v| zigzag(v)).collect::<Vec<_>>(),
+                   vec![0, 1, 2, 3, 4, u64::MAX - 1, u64::MAX]);
+        for &v in [0, 1, -1, 12345, -98765, i64::MAX, i64::MIN].iter() {
+            assert_eq!(unzigzag(zigzag(v)), v);
+        }
+
+        let wild = vec![i64::MAX, i64::MIN, 0, -1, i64::MAX, 3];
+        for &order in [Delta::None, Delta::First, Delta::Second].iter() {
+            assert_eq!(from_residuals(&residuals(&wild, order), order), wild);
+        }
+        let unsigned = vec![u64::MAX, 0, 5, 3, u64::MAX / 2];
+        assert_eq!(undelta_u64(&delta_u64(&unsigned)), unsigned);
+
+        // a clock with a little jitter: delta of delta leaves next to nothing
+        let ticks: Vec<i64> = (0..1000).map(|i| 1_700_000_000_000 + i * 1000 + [0, 1, -1, 0][i as usize % 4]).collect();
+        let res = residuals(&ticks, Delta::Second);
+        assert!(res[2..].iter().all(|&r| r <= 6));
+        let (packed, nbits) = bitio::pack(|w| res[2..].iter().try_for_each(|&r| write_rice(w, r, 1)));
+        assert!(packed.len() < 500); // of 8000 raw
+        let mut reader = BitReader::new(&packed[..]);
+        let back: Vec<u64> = (2..res.len()).map(|_| read_rice(&mut reader, 1).unwrap()).collect();
+        assert_eq!((back.as_slice(), reader.bits_read() as usize), (&res[2..], nbits));
+    }
+
+    #[test]
+    fn test_varints() {
+        let values = vec![0, 1, 127, 128, 300, 16383, 16384, u64::MAX];
+        let bytes = to_varints(&values);
+        assert_eq!(&bytes[..6], [0, 1, 0x7f, 0x80, 0x01, 0xac]);
+        assert_eq!(from_varints(&bytes).unwrap(), values);
+        assert_eq!(from_varints(&[0x80]), Err(NumericError::Truncated));
+        assert_eq!(from_varints(&[0xff; 10]), Err(NumericError::Overflow));
+        assert_eq!(from_varints(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]), Err(NumericError::Overflow));
+    }
+
+}
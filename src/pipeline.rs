@@ -0,0 +1,646 @@
+// Chains of byte-to-byte transforms, e.g. a bzip2-style
+//
+//   Pipeline::new().then(Bwt).then(Mtf).then(Rle).then(Huffman)
+//
+// Each stage's output carries whatever side information its inverse needs
+// (BWT primary index, code tables, lengths), and the pipeline output opens
+// with the list of stage ids, so decompress() needs nothing but the bytes:
+//
+//   count   u8       number of stages
+//   ids     count bytes, in the order the stages were applied
+//   data    output of the last stage
+//
+// Integers inside stage output are big-endian.
+//
+// A DecodeOptions (Pipeline::limits, decompress_limited) caps every stage's
+// output, each being held in memory in turn, and the code tables read by
+// Huffman stages. The stages undone before the last can give more than the
+// pipeline does, since their output carries the side information of the
+// stages before them (the BWT primary index) and escaped run-length codes
+// run up to three times as long as their input; Pipeline::stage_limits
+// gives them limits of their own.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::ecc::interleave::{self, BlockInterleaver};
+use crate::ecc::reed_solomon;
+use crate::huffman::{ByteHuffman, TableError};
+use crate::limits::{DecodeOptions, LimitExceeded};
+use crate::lz77;
+use crate::mtf;
+use crate::rans;
+use crate::rle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineError {
+    Truncated,
+    UnknownStage(u8),
+    // The header lists other stages than the pipeline decompressing it
+    StageMismatch,
+    // The input to the inverse of this stage is corrupt
+    CorruptStage(u8),
+    // A stage's output or code table would go past the DecodeOptions
+    LimitExceeded(LimitExceeded),
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PipelineError::Truncated => write!(f, "pipeline data is truncated"),
+            PipelineError::UnknownStage(id) => write!(f, "unknown pipeline stage id {}", id),
+            PipelineError::StageMismatch => write!(f, "data was compressed by a different pipeline"),
+            PipelineError::CorruptStage(id) => write!(f, "corrupt input to pipeline stage {}", id),
+            PipelineError::LimitExceeded(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for PipelineError {}
+
+impl From<LimitExceeded> for PipelineError {
+    fn from(e: LimitExceeded) -> Self {
+        PipelineError::LimitExceeded(e)
+    }
+}
+
+// A reversible stage. `id` goes in the pipeline header; ids 0-127 are
+// reserved for the stages in this module. Stages are Send + Sync so that a
+// Pipeline is too.
+pub trait Transform: Send + Sync {
+    fn id(&self) -> u8;
+    fn forward(&self, data: &[u8]) -> Vec<u8>;
+    fn inverse(&self, data: &[u8]) -> Result<Vec<u8>, PipelineError>;
+
+    // inverse, failing with LimitExceeded rather than produce more than
+    // `limits` allows. This checks once the stage has run, which is
+    // enough for stages whose output is about the size of their input;
+    // those that can expand it override this to check as they go.
+    fn inverse_with(&self, data: &[u8], limits: &DecodeOptions) -> Result<Vec<u8>, PipelineError> {
+        let out = self.inverse(data)?;
+        limits.check_output(out.len() as u64)?;
+        Ok(out)
+    }
+}
+
+pub struct Bwt;
+pub struct Mtf;
+pub struct Rle;
+pub struct Lz77;
+pub struct Huffman;
+pub struct Rans;
+// Forward error correction with this many parity bytes per 255-byte
+// codeword, correcting half as many bad bytes in each. Goes last.
+pub struct ReedSolomon(pub u8);
+// Interleavers (crate::ecc::interleave) for after ReedSolomon, so a burst
+// on the channel is spread over many codewords. Their parameters lead
+// their output in the clear.
+pub struct BlockInterleave { pub rows: u32, pub cols: u32 }
+pub struct ConvInterleave { pub branches: u32, pub delay: u32 }
+
+const BWT: u8 = 1;
+const MTF: u8 = 2;
+const RLE: u8 = 3;
+const LZ77: u8 = 4;
+const HUFFMAN: u8 = 5;
+const RANS: u8 = 6;
+const REED_SOLOMON: u8 = 7;
+const BLOCK_INTERLEAVE: u8 = 8;
+const CONV_INTERLEAVE: u8 = 9;
+
+// Rare in move-to-front output, where the runs are
+const RLE_ESCAPE: u8 = 0xff;
+
+fn stage_from_id(id: u8) -> Option<Box<dyn Transform>> {
+    Some(match id {
+        BWT => Box::new(Bwt),
+        MTF => Box::new(Mtf),
+        RLE => Box::new(Rle),
+        LZ77 => Box::new(Lz77),
+        HUFFMAN => Box::new(Huffman),
+        RANS => Box::new(Rans),
+        // the parity count is read back from the stage's output
+        REED_SOLOMON => Box::new(ReedSolomon(0)),
+        // as are the interleavers' parameters
+        BLOCK_INTERLEAVE => Box::new(BlockInterleave { rows: 1, cols: 1 }),
+        CONV_INTERLEAVE => Box::new(ConvInterleave { branches: 1, delay: 0 }),
+        _ => return None,
+    })
+}
+
+// primary index u64, then the transformed bytes
+impl Transform for Bwt {
+
+    fn id(&self) -> u8 {
+        BWT
+    }
+
+    fn forward(&self, data: &[u8]) -> Vec<u8> {
+        let (transformed, primary) = crate::bwt::forward(data);
+        let mut out = (primary as u64).to_be_bytes().to_vec();
+        out.extend_from_slice(&transformed);
+        out
+    }
+
+    fn inverse(&self, data: &[u8]) -> Result<Vec<u8>, PipelineError> {
+        let mut rest = data;
+        let primary = take_u64(&mut rest)?;
+        crate::bwt::inverse(rest, primary as usize).map_err(|_| PipelineError::CorruptStage(BWT))
+    }
+
+}
+
+impl Transform for Mtf {
+
+    fn id(&self) -> u8 {
+        MTF
+    }
+
+    fn forward(&self, data: &[u8]) -> Vec<u8> {
+        mtf::encode(data)
+    }
+
+    fn inverse(&self, data: &[u8]) -> Result<Vec<u8>, PipelineError> {
+        Ok(mtf::decode(data))
+    }
+
+}
+
+impl Transform for Rle {
+
+    fn id(&self) -> u8 {
+        RLE
+    }
+
+    fn forward(&self, data: &[u8]) -> Vec<u8> {
+        rle::encode_escaped(data, RLE_ESCAPE)
+    }
+
+    fn inverse(&self, data: &[u8]) -> Result<Vec<u8>, PipelineError> {
+        self.inverse_with(data, &DecodeOptions::new())
+    }
+
+    fn inverse_with(&self, data: &[u8], limits: &DecodeOptions) -> Result<Vec<u8>, PipelineError> {
+        rle::decode_escaped_with(data, RLE_ESCAPE, limits).map_err(|e| match e {
+            rle::RleError::LimitExceeded(e) => PipelineError::LimitExceeded(e),
+            _ => PipelineError::CorruptStage(RLE),
+        })
+    }
+
+}
+
+impl Transform for Lz77 {
+
+    fn id(&self) -> u8 {
+        LZ77
+    }
+
+    fn forward(&self, data: &[u8]) -> Vec<u8> {
+        lz77::Lz77::default().encode(data)
+    }
+
+    fn inverse(&self, data: &[u8]) -> Result<Vec<u8>, PipelineError> {
+        self.inverse_with(data, &DecodeOptions::new())
+    }
+
+    fn inverse_with(&self, data: &[u8], limits: &DecodeOptions) -> Result<Vec<u8>, PipelineError> {
+        lz77::decode_with(data, limits).map_err(|e| match e {
+            lz77::Lz77Error::LimitExceeded(e) => PipelineError::LimitExceeded(e),
+            _ => PipelineError::CorruptStage(LZ77),
+        })
+    }
+
+}
+
+// table_len u32, the code table, bit count u64, then the packed codewords.
+// Empty input stays empty.
+impl Transform for Huffman {
+
+    fn id(&self) -> u8 {
+        HUFFMAN
+    }
+
+    fn forward(&self, data: &[u8]) -> Vec<u8> {
+        if data.is_empty() {
+            return Vec::new();
+        }
+        let code = ByteHuffman::new_bytes(data);
+        let table = code.serialize_table();
+        let (packed, nbits) = code.encode_bytes_packed(data);
+        let mut out = (table.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(&table);
+        out.extend_from_slice(&(nbits as u64).to_be_bytes());
+        out.extend_from_slice(&packed);
+        out
+    }
+
+    fn inverse(&self, data: &[u8]) -> Result<Vec<u8>, PipelineError> {
+        self.inverse_with(data, &DecodeOptions::new())
+    }
+
+    // Every codeword has at least one bit, so the output is no more than
+    // eight times the input, and can be checked once decoded
+    fn inverse_with(&self, data: &[u8], limits: &DecodeOptions) -> Result<Vec<u8>, PipelineError> {
+        if data.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut rest = data;
+        let table_len = take_u32(&mut rest)? as usize;
+        let table = take(&mut rest, table_len)?;
+        let code = ByteHuffman::from_table_with(table, limits).map_err(|e| match e {
+            TableError::LimitExceeded(e) => PipelineError::LimitExceeded(e),
+            _ => PipelineError::CorruptStage(HUFFMAN),
+        })?;
+        let nbits = take_u64(&mut rest)?;
+        if nbits.div_ceil(8) != rest.len() as u64 {
+            return Err(PipelineError::CorruptStage(HUFFMAN));
+        }
+        let out = code.decode_bytes_packed(rest, nbits as usize);
+        limits.check_output(out.len() as u64)?;
+        Ok(out)
+    }
+
+}
+
+// symbol count u64, used-byte count u16, then (byte, scaled frequency u16)
+// for each, then the rANS stream. Empty input stays empty.
+impl Transform for Rans {
+
+    fn id(&self) -> u8 {
+        RANS
+    }
+
+    fn forward(&self, data: &[u8]) -> Vec<u8> {
+        if data.is_empty() {
+            return Vec::new();
+        }
+        let coder = rans::Rans::new(data);
+        let used: Vec<u8> = (0..=255u8).filter(|&b| coder.frequency(b) > 0).collect();
+        let mut out = (data.len() as u64).to_be_bytes().to_vec();
+        out.extend_from_slice(&(used.len() as u16).to_be_bytes());
+        for b in used {
+            out.push(b);
+            out.extend_from_slice(&(coder.frequency(b) as u16).to_be_bytes());
+        }
+        out.extend(coder.encode(data).expect("table built from the data covers it"));
+        out
+    }
+
+    fn inverse(&self, data: &[u8]) -> Result<Vec<u8>, PipelineError> {
+        self.inverse_with(data, &DecodeOptions::new())
+    }
+
+    fn inverse_with(&self, data: &[u8], limits: &DecodeOptions) -> Result<Vec<u8>, PipelineError> {
+        if data.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut rest = data;
+        let len = take_u64(&mut rest)?;
+        limits.check_output(len)?;
+        let used = take(&mut rest, 2)?;
+        let mut freq = HashMap::new();
+        for _ in 0..u16::from_be_bytes([used[0], used[1]]) {
+            let entry = take(&mut rest, 3)?;
+            freq.insert(entry[0], u16::from_be_bytes([entry[1], entry[2]]) as u64);
+        }
+        // Scaled frequencies already sum to 2^SCALE_BITS, so rebuilding
+        // from them reproduces the encoder's table
+        if freq.values().sum::<u64>() != 1 << rans::SCALE_BITS {
+            return Err(PipelineError::CorruptStage(RANS));
+        }
+        rans::Rans::from_frequencies(&freq).ok()
+            .and_then(|coder| coder.decode(rest, len as usize).ok())
+            .ok_or(PipelineError::CorruptStage(RANS))
+    }
+
+}
+
+// reed_solomon::ReedSolomon::protect; a corrupt stage is one past repair
+impl Transform for ReedSolomon {
+
+    fn id(&self) -> u8 {
+        REED_SOLOMON
+    }
+
+    // Panics unless 0 < parity < 255
+    fn forward(&self, data: &[u8]) -> Vec<u8> {
+        reed_solomon::ReedSolomon::new(self.0 as usize).protect(data)
+    }
+
+    fn inverse(&self, data: &[u8]) -> Result<Vec<u8>, PipelineError> {
+        reed_solomon::recover(data).map_err(|_| PipelineError::CorruptStage(REED_SOLOMON))
+    }
+
+}
+
+// rows u32, cols u32, then the interleaved bytes
+impl Transform for BlockInterleave {
+
+    fn id(&self) -> u8 {
+        BLOCK_INTERLEAVE
+    }
+
+    // Panics unless rows and cols are both nonzero
+    fn forward(&self, data: &[u8]) -> Vec<u8> {
+        let block = BlockInterleaver::new(self.rows as usize, self.cols as usize);
+        let mut out = self.rows.to_be_bytes().to_vec();
+        out.extend_from_slice(&self.cols.to_be_bytes());
+        out.extend(block.interleave(data));
+        out
+    }
+
+    fn inverse(&self, data: &[u8]) -> Result<Vec<u8>, PipelineError> {
+        let mut rest = data;
+        let (rows, cols) = (take_u32(&mut rest)?, take_u32(&mut rest)?);
+        if rows == 0 || cols == 0 {
+            return Err(PipelineError::CorruptStage(BLOCK_INTERLEAVE));
+        }
+        Ok(BlockInterleaver::new(rows as usize, cols as usize).deinterleave(rest))
+    }
+
+}
+
+// branches u32, delay u32, then the interleaved bytes, which run
+// branches * (branches - 1) * delay bytes longer than the input
+impl Transform for ConvInterleave {
+
+    fn id(&self) -> u8 {
+        CONV_INTERLEAVE
+    }
+
+    // Panics if branches is 0
+    fn forward(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = self.branches.to_be_bytes().to_vec();
+        out.extend_from_slice(&self.delay.to_be_bytes());
+        out.extend(interleave::interleave_convolutional(data, self.branches as usize, self.delay as usize));
+        out
+    }
+
+    fn inverse(&self, data: &[u8]) -> Result<Vec<u8>, PipelineError> {
+        let mut rest = data;
+        let (branches, delay) = (take_u32(&mut rest)? as u64, take_u32(&mut rest)? as u64);
+        // checked before the delay lines are allocated
+        let latency = (branches * branches.saturating_sub(1)).checked_mul(delay);
+        if branches == 0 || latency.is_none_or(|l| l > rest.len() as u64) {
+            return Err(PipelineError::CorruptStage(CONV_INTERLEAVE));
+        }
+        interleave::deinterleave_convolutional(rest, branches as usize, delay as usize)
+            .ok_or(PipelineError::CorruptStage(CONV_INTERLEAVE))
+    }
+
+}
+
+pub struct Pipeline {
+    stages: Vec<Box<dyn Transform>>,
+    limits: DecodeOptions,
+    // For every stage but the first; None for `limits`
+    stage_limits: Option<DecodeOptions>,
+}
+
+impl Pipeline {
+
+    pub fn new() -> Self {
+        Pipeline { stages: Vec::new(), limits: DecodeOptions::new(), stage_limits: None }
+    }
+
+    // The bzip2-style chain: BWT, move-to-front, run-length, Huffman
+    pub fn bwt() -> Self {
+        Pipeline::new().then(Bwt).then(Mtf).then(Rle).then(Huffman)
+    }
+
+    // Panics past 255 stages, the most the header can list
+    pub fn then<T: Transform + 'static>(mut self, stage: T) -> Self {
+        assert!(self.stages.len() < u8::MAX as usize, "too many pipeline stages");
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    // Limits for decompress_with
+    pub fn limits(mut self, limits: DecodeOptions) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // Limits for decompress_with to hold the stages other than the first
+    // to, whose inverses run before it; by default those of limits()
+    pub fn stage_limits(mut self, limits: DecodeOptions) -> Self {
+        self.stage_limits = Some(limits);
+        self
+    }
+
+    // Stage ids in the order they are applied
+    pub fn ids(&self) -> Vec<u8> {
+        self.stages.iter().map(|s| s.id()).collect()
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![self.stages.len() as u8];
+        out.extend(self.ids());
+        let mut current = data.to_vec();
+        for stage in &self.stages {
+            current = stage.forward(&current);
+        }
+        out.extend(current);
+        out
+    }
+
+    // Undo whatever pipeline produced `bytes`, as listed in its header.
+    // Custom stages (ids 128 and up) can't be looked up; use
+    // decompress_with for those.
+    pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>, PipelineError> {
+        Pipeline::decompress_limited(bytes, &DecodeOptions::new())
+    }
+
+    // decompress, stopping with LimitExceeded before any stage's output
+    // passes max_output_bytes
+    pub fn decompress_limited(bytes: &[u8], limits: &DecodeOptions) -> Result<Vec<u8>, PipelineError> {
+        let (ids, mut current) = split_header(bytes)?;
+        for &id in ids.iter().rev() {
+            let stage = stage_from_id(id).ok_or(PipelineError::UnknownStage(id))?;
+            current = stage.inverse_with(&current, limits)?;
+        }
+        Ok(current)
+    }
+
+    // Undo this pipeline, checking the header lists its stages
+    pub fn decompress_with(&self, bytes: &[u8]) -> Result<Vec<u8>, PipelineError> {
+        let (ids, mut current) = split_header(bytes)?;
+        if ids != self.ids() {
+            return Err(PipelineError::StageMismatch);
+        }
+        let stage_limits = self.stage_limits.as_ref().unwrap_or(&self.limits);
+        for (i, stage) in self.stages.iter().enumerate().rev() {
+            current = stage.inverse_with(&current, if i == 0 { &self.limits } else { stage_limits })?;
+        }
+        Ok(current)
+    }
+
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline::new()
+    }
+}
+
+fn split_header(bytes: &[u8]) -> Result<(&[u8], Vec<u8>), PipelineError> {
+    let mut rest = bytes;
+    let count = take(&mut rest, 1)?[0] as usize;
+    let ids = take(&mut rest, count)?;
+    Ok((ids, rest.to_vec()))
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], PipelineError> {
+    if bytes.len() < n {
+        return Err(PipelineError::Truncated);
+    }
+    let (head, tail) = bytes.split_at(n);
+    *bytes = tail;
+    Ok(head)
+}
+
+fn take_u32(bytes: &mut &[u8]) -> Result<u32, PipelineError> {
+    let b = take(bytes, 4)?;
+    Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn take_u64(bytes: &mut &[u8]) -> Result<u64, PipelineError> {
+    let b = take(bytes, 8)?;
+    let mut arr = [0u8; 8];
+    arr.copy_from_slice(b);
+    Ok(u64::from_be_bytes(arr))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{BlockInterleave, Bwt, ConvInterleave, Huffman, Lz77, Mtf, Pipeline, PipelineError, Rans, ReedSolomon,
+                Rle, Transform};
+    use crate::limits::{DecodeOptions, Limit, LimitExceeded};
+
+    fn samples() -> Vec<Vec<u8>> {
+        vec![b"".to_vec(), b"a".to_vec(), b"banana".to_vec(), (0..=255u8).collect(),
+             b"It is a truth universally acknowledged, that a single man in possession \
+               of a good fortune, must be in want of a wife.".repeat(100)]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let pipelines = vec![
+            Pipeline::new(),
+            Pipeline::bwt(),
+            Pipeline::new().then(Bwt).then(Mtf).then(Rans),
+            Pipeline::new().then(Lz77).then(Huffman),
+            Pipeline::bwt().then(ReedSolomon(32)),
+            Pipeline::new().then(Huffman).then(ReedSolomon(8)).then(BlockInterleave { rows: 10, cols: 255 }),
+            Pipeline::new().then(Huffman).then(ConvInterleave { branches: 5, delay: 3 }),
+        ];
+        for pipeline in &pipelines {
+            for data in samples() {
+                let compressed = pipeline.compress(&data);
+                assert_eq!(Pipeline::decompress(&compressed).unwrap(), data);
+                assert_eq!(pipeline.decompress_with(&compressed).unwrap(), data);
+            }
+        }
+
+        let text = &samples()[4];
+        let bzip = pipelines[1].compress(text).len();
+        let huffman = Pipeline::new().then(Huffman).compress(text).len();
+        assert!(bzip < huffman / 5);
+    }
+
+    // Custom stages only work through decompress_with
+    struct Xor(u8);
+
+    impl Transform for Xor {
+        fn id(&self) -> u8 {
+            200
+        }
+        fn forward(&self, data: &[u8]) -> Vec<u8> {
+            data.iter().map(|b| b ^ self.0).collect()
+        }
+        fn inverse(&self, data: &[u8]) -> Result<Vec<u8>, PipelineError> {
+            Ok(self.forward(data))
+        }
+    }
+
+    #[test]
+    fn test_custom_stage() {
+        let pipeline = Pipeline::new().then(Xor(0x5a)).then(Huffman);
+        assert_eq!(pipeline.ids(), vec![200, 5]);
+        let compressed = pipeline.compress(b"custom");
+        assert_eq!(pipeline.decompress_with(&compressed).unwrap(), b"custom");
+        assert_eq!(Pipeline::decompress(&compressed), Err(PipelineError::UnknownStage(200)));
+    }
+
+    #[test]
+    fn test_corrupt_input() {
+        let pipeline = Pipeline::new().then(Bwt).then(Huffman);
+        let compressed = pipeline.compress(b"banana bandana");
+        assert_eq!(Pipeline::decompress(&[]), Err(PipelineError::Truncated));
+        assert_eq!(Pipeline::decompress(&compressed[..2]), Err(PipelineError::Truncated));
+        assert_eq!(Pipeline::decompress(&compressed[..compressed.len() - 1]),
+                   Err(PipelineError::CorruptStage(5)));
+        assert_eq!(Pipeline::new().then(Rle).decompress_with(&compressed),
+                   Err(PipelineError::StageMismatch));
+    }
+
+    #[test]
+    fn test_limits() {
+        let text = &samples()[4];
+        let over = |max| Err(PipelineError::LimitExceeded(LimitExceeded { limit: Limit::OutputBytes, max }));
+        for pipeline in &[Pipeline::new().then(Lz77).then(Huffman), Pipeline::new().then(Rle),
+                          Pipeline::new().then(Mtf).then(Rans)] {
+            let compressed = pipeline.compress(text);
+            let limits = DecodeOptions::new().max_output_bytes(text.len() as u64);
+            assert_eq!(Pipeline::decompress_limited(&compressed, &limits).unwrap(), *text);
+            let limits = limits.max_output_bytes(text.len() as u64 - 1);
+            assert_eq!(Pipeline::decompress_limited(&compressed, &limits), over(text.len() as u64 - 1));
+        }
+
+        // the bzip2 chain's intermediate output carries the BWT primary
+        // index, and run-length codes can be longer than what they stand for
+        let compressed = Pipeline::bwt().compress(text);
+        let len = text.len() as u64;
+        let limits = DecodeOptions::new().max_output_bytes(len);
+        assert_eq!(Pipeline::bwt().limits(limits).decompress_with(&compressed), over(len));
+        let stages = DecodeOptions::new().max_output_bytes(3 * (len + 8));
+        let bwt = Pipeline::bwt().limits(limits).stage_limits(stages);
+        assert_eq!(bwt.decompress_with(&compressed).unwrap(), *text);
+        let bwt = Pipeline::bwt().limits(limits.max_output_bytes(len - 1)).stage_limits(stages);
+        assert_eq!(bwt.decompress_with(&compressed), over(len - 1));
+        let limits = DecodeOptions::new().max_table_symbols(2);
+        assert_eq!(Pipeline::bwt().limits(limits).decompress_with(&compressed),
+                   Err(PipelineError::LimitExceeded(LimitExceeded { limit: Limit::TableSymbols, max: 2 })));
+    }
+
+    #[test]
+    fn test_error_correction() {
+        let pipeline = Pipeline::bwt().then(ReedSolomon(16));
+        let text = &samples()[4];
+        let mut compressed = pipeline.compress(text);
+        // ids, header codeword, then a few bytes of damage per codeword
+        for at in (30..compressed.len()).step_by(97) {
+            compressed[at] ^= 0x33;
+        }
+        assert_eq!(Pipeline::decompress(&compressed).unwrap(), *text);
+        for at in (31..61).step_by(3) {
+            compressed[at] ^= 0xff;
+        }
+        assert_eq!(Pipeline::decompress(&compressed), Err(PipelineError::CorruptStage(7)));
+
+        // a 40-byte burst is too much for one codeword, but spread over
+        // eight by an interleaver it's five bad bytes in each
+        // (with enough data to fill the interleaver's eight rows)
+        let text = b"ought to be enough for anybody".repeat(70);
+        let plain = Pipeline::new().then(ReedSolomon(10));
+        let interleaved = Pipeline::new().then(ReedSolomon(10)).then(BlockInterleave { rows: 8, cols: 255 });
+        for (pipeline, survives) in [(plain, false), (interleaved, true)].iter() {
+            let mut compressed = pipeline.compress(&text);
+            compressed[200..240].iter_mut().for_each(|b| *b = !*b);
+            assert_eq!(Pipeline::decompress(&compressed).is_ok(), *survives);
+        }
+    }
+
+}
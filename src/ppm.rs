@@ -0,0 +1,319 @@
+// PPM (prediction by partial matching) over bytes, arithmetic coded. Each
+// byte is predicted from the longest context (the previous `order` bytes)
+// that has been seen before; if it hasn't followed that context yet, an
+// escape is coded and the next shorter context tried, down to order 0 and
+// finally a uniform order -1 over all bytes.
+//
+// Escapes follow PPMD: a symbol seen c times weighs 2c - 1 and the escape
+// weighs as many as there are distinct symbols, so a context with a few
+// well-established followers rarely escapes. Symbols already ruled out by a
+// longer context are excluded from the shorter ones, and after coding only
+// the contexts from the one that predicted the byte up are updated.
+//
+// Output: the order as one byte, then the arithmetic coded stream. The
+// number of bytes isn't recorded; the caller keeps it (the container does).
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::arithmetic::{self, ArithError, ArithmeticDecoder, ArithmeticEncoder};
+use crate::bitio::{self, BitReader, BitWriter};
+
+pub const MAX_ORDER: u8 = 8;
+pub const DEFAULT_ORDER: u8 = 4;
+// Counts in a context are halved past this, which keeps the coder's totals
+// in range and lets old statistics fade
+const MAX_COUNT: u32 = 1 << 13;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpmError {
+    Truncated,
+    // The stream claims an order over MAX_ORDER
+    BadOrder(u8),
+    // The stream escapes past every byte, or otherwise decodes to
+    // something compress() couldn't have written
+    Corrupt,
+}
+
+impl fmt::Display for PpmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PpmError::Truncated => write!(f, "PPM stream is truncated"),
+            PpmError::BadOrder(order) => write!(f, "PPM order {} is over the maximum of {}", order, MAX_ORDER),
+            PpmError::Corrupt => write!(f, "PPM stream is corrupt"),
+        }
+    }
+}
+
+impl Error for PpmError {}
+
+impl From<ArithError> for PpmError {
+    fn from(e: ArithError) -> Self {
+        match e {
+            ArithError::Truncated => PpmError::Truncated,
+            ArithError::Corrupt => PpmError::Corrupt,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ppm {
+    order: u8,
+}
+
+impl Ppm {
+
+    // Longer contexts predict better once there's enough data to fill
+    // them, and take more memory. Panics unless order <= MAX_ORDER
+    pub fn new(order: u8) -> Self {
+        assert!(order <= MAX_ORDER, "PPM order must be at most {}", MAX_ORDER);
+        Ppm { order }
+    }
+
+    // The order for a compression level (1..=9); the default level gets
+    // DEFAULT_ORDER
+    pub fn level(level: u8) -> Self {
+        Ppm::new(1 + level.clamp(1, 9) / 2)
+    }
+
+    pub fn order(&self) -> u8 {
+        self.order
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![self.order];
+        out.extend(bitio::pack(|w| {
+            let mut model = State::new(self.order);
+            let mut enc = ArithmeticEncoder::new();
+            for &b in data {
+                model.encode(b, &mut enc, w)?;
+            }
+            enc.finish(w)
+        }).0);
+        out
+    }
+
+    // Decode `len` bytes of compress()'s output
+    pub fn decompress(bytes: &[u8], len: usize) -> Result<Vec<u8>, PpmError> {
+        let (&order, rest) = bytes.split_first().ok_or(PpmError::Truncated)?;
+        if order > MAX_ORDER {
+            return Err(PpmError::BadOrder(order));
+        }
+        let fail = |e| PpmError::from(arithmetic::from_io(e));
+        let mut input = BitReader::new(rest);
+        let mut dec = ArithmeticDecoder::new(&mut input).map_err(fail)?;
+        let mut model = State::new(order);
+        let mut out = Vec::with_capacity(len.min(1 << 20));
+        for _ in 0..len {
+            out.push(model.decode(&mut dec, &mut input).map_err(fail)?);
+        }
+        Ok(out)
+    }
+
+}
+
+impl Default for Ppm {
+    fn default() -> Self {
+        Ppm::new(DEFAULT_ORDER)
+    }
+}
+
+// The bytes that have followed one context, with their counts
+#[derive(Default)]
+struct Stats {
+    symbols: Vec<(u8, u32)>,
+    total: u32,
+}
+
+impl Stats {
+
+    fn add(&mut self, sym: u8) {
+        match self.symbols.iter_mut().find(|(s, _)| *s == sym) {
+            Some((_, c)) => *c += 1,
+            None => self.symbols.push((sym, 1)),
+        }
+        self.total += 1;
+        if self.total > MAX_COUNT {
+            self.total = 0;
+            for (_, c) in self.symbols.iter_mut() {
+                *c = c.div_ceil(2);
+                self.total += *c;
+            }
+        }
+    }
+
+}
+
+struct State {
+    order: u8,
+    // keyed by the context's length and its bytes, most recent lowest
+    contexts: HashMap<(u8, u64), Stats>,
+    history: u64,
+    seen: u64,
+}
+
+// The candidates a context offers once exclusions are taken out: each
+// symbol's weight, then the escape's
+struct Candidates {
+    symbols: Vec<(u8, u32)>,
+    escape: u32,
+    total: u32,
+}
+
+impl State {
+
+    fn new(order: u8) -> Self {
+        State { order, contexts: HashMap::new(), history: 0, seen: 0 }
+    }
+
+    // Context lengths to try, longest first
+    fn orders(&self) -> impl Iterator<Item=u8> {
+        (0..=(self.order as u64).min(self.seen) as u8).rev()
+    }
+
+    fn key(&self, k: u8) -> (u8, u64) {
+        let mask = if k == 8 { u64::MAX } else { (1u64 << (8 * k)) - 1 };
+        (k, self.history & mask)
+    }
+
+    fn candidates(&self, k: u8, excluded: &[bool; 256]) -> Option<Candidates> {
+        let stats = self.contexts.get(&self.key(k))?;
+        let symbols: Vec<(u8, u32)> = stats.symbols.iter()
+            .filter(|(s, _)| !excluded[*s as usize])
+            .map(|&(s, c)| (s, 2 * c - 1))
+            .collect();
+        if symbols.is_empty() {
+            return None;
+        }
+        let escape = symbols.len() as u32;
+        let total = symbols.iter().map(|&(_, w)| w).sum::<u32>() + escape;
+        Some(Candidates { symbols, escape, total })
+    }
+
+    fn encode<W: Write>(&mut self, sym: u8, enc: &mut ArithmeticEncoder, out: &mut BitWriter<W>) -> io::Result<()> {
+        let mut excluded = [false; 256];
+        let mut found = None;
+        for k in self.orders() {
+            let cands = match self.candidates(k, &excluded) {
+                Some(cands) => cands,
+                None => continue,
+            };
+            let mut low = 0;
+            for &(s, w) in &cands.symbols {
+                if s == sym {
+                    enc.encode(low, w, cands.total, out)?;
+                    found = Some(k);
+                    break;
+                }
+                low += w;
+            }
+            if found.is_some() {
+                break;
+            }
+            enc.encode(low, cands.escape, cands.total, out)?;
+            for &(s, _) in &cands.symbols {
+                excluded[s as usize] = true;
+            }
+        }
+        if found.is_none() {
+            let index = excluded[..sym as usize].iter().filter(|&&x| !x).count() as u32;
+            let left = excluded.iter().filter(|&&x| !x).count() as u32;
+            enc.encode(index, 1, left, out)?;
+        }
+        self.update(sym, found);
+        Ok(())
+    }
+
+    fn decode<R: Read>(&mut self, dec: &mut ArithmeticDecoder, input: &mut BitReader<R>) -> io::Result<u8> {
+        let mut excluded = [false; 256];
+        for k in self.orders() {
+            let cands = match self.candidates(k, &excluded) {
+                Some(cands) => cands,
+                None => continue,
+            };
+            let target = dec.target(cands.total)?;
+            let mut low = 0;
+            for &(s, w) in &cands.symbols {
+                if target < low + w {
+                    dec.consume(low, w, cands.total, input)?;
+                    self.update(s, Some(k));
+                    return Ok(s);
+                }
+                low += w;
+            }
+            dec.consume(low, cands.escape, cands.total, input)?;
+            for &(s, _) in &cands.symbols {
+                excluded[s as usize] = true;
+            }
+        }
+        // a corrupt stream can escape from every byte, leaving none here
+        let left = excluded.iter().filter(|&&x| !x).count() as u32;
+        let index = dec.target(left)?;
+        dec.consume(index, 1, left, input)?;
+        let sym = (0..=255u8).filter(|&b| !excluded[b as usize]).nth(index as usize).ok_or(ArithError::Corrupt)?;
+        self.update(sym, None);
+        Ok(sym)
+    }
+
+    // Count `sym` in the context that predicted it and every longer one
+    fn update(&mut self, sym: u8, found: Option<u8>) {
+        let from = found.unwrap_or(0);
+        for k in self.orders().filter(|&k| k >= from) {
+            let key = self.key(k);
+            self.contexts.entry(key).or_default().add(sym);
+        }
+        self.history = self.history << 8 | sym as u64;
+        self.seen += 1;
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Ppm, PpmError};
+    use crate::arithmetic;
+    use crate::models::Order1;
+
+    const TEXT: &str = "It was the best of times, it was the worst of times, it was the age of wisdom, \
+                        it was the age of foolishness, it was the epoch of belief, it was the epoch of \
+                        incredulity, it was the season of Light, it was the season of Darkness, it was \
+                        the spring of hope, it was the winter of despair.";
+
+    #[test]
+    fn test_round_trip() {
+        let samples: Vec<Vec<u8>> = vec![Vec::new(), b"a".to_vec(), b"abracadabra".to_vec(),
+                                         (0..=255u8).rev().collect(), vec![0; 5000],
+                                         (0..20_000u32).map(|i| (i * 7919 % 256) as u8).collect(),
+                                         TEXT.as_bytes().to_vec()];
+        for data in &samples {
+            for order in [0, 1, 3, 8] {
+                let packed = Ppm::new(order).compress(data);
+                assert!(Ppm::decompress(&packed, data.len()).unwrap() == *data);
+            }
+        }
+        assert_eq!(Ppm::decompress(&[9, 0, 0], 1), Err(PpmError::BadOrder(9)));
+        assert_eq!(Ppm::decompress(&[], 1), Err(PpmError::Truncated));
+
+        // once order 0 has seen every byte, set bits can escape from all of them
+        let all: Vec<u8> = (0..=255).collect();
+        let mut packed = Ppm::new(0).compress(&all);
+        packed.truncate(packed.len() - 3);
+        packed.extend([0xff; 8]);
+        assert_eq!(Ppm::decompress(&packed, 300), Err(PpmError::Corrupt));
+        assert_eq!(Ppm::level(6).order(), super::DEFAULT_ORDER);
+    }
+
+    #[test]
+    fn test_ratio() {
+        // longer contexts pay off on text
+        let text = TEXT.repeat(8);
+        let order1 = arithmetic::encode(text.as_bytes(), Order1::default()).len();
+        let ppm = Ppm::default().compress(text.as_bytes()).len();
+        assert!(ppm * 2 < order1);
+        assert!(Ppm::new(2).compress(text.as_bytes()).len() > ppm);
+    }
+
+}
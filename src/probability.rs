@@ -0,0 +1,257 @@
+// Symbol counts quantised to integer frequencies that sum to exactly
+// 2^precision, the form rANS, FSE and arithmetic coders all work from.
+// Encoder and decoder must arrive at the very same table, so this is the
+// one place counts get normalised, and it's deterministic: no floats and
+// no dependence on hashing.
+//
+// Each symbol first gets the floor of its exact share. Symbols that were
+// seen but round down to nothing are raised to 1, since a zero frequency
+// can't be coded at all. Any shortfall goes, one unit each, to the
+// symbols with the largest remainders (largest remainder method); any
+// excess from the raised symbols is taken back from wherever a unit less
+// costs the fewest bits over the counted data. A table that already sums
+// to 2^precision comes through unchanged.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::error::Error;
+use std::fmt;
+
+pub const MAX_PRECISION: u32 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbError {
+    // No symbol has a nonzero count
+    Empty,
+    // More symbols were seen than 2^precision has room for
+    TooManySymbols { symbols: usize, precision: u32 },
+    // Precision over MAX_PRECISION
+    BadPrecision(u32),
+}
+
+impl fmt::Display for ProbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProbError::Empty => write!(f, "no symbol has a nonzero count"),
+            ProbError::TooManySymbols { symbols, precision } => {
+                write!(f, "{} symbols don't fit in a table of 2^{}", symbols, precision)
+            }
+            ProbError::BadPrecision(p) => write!(f, "precision {} is over the maximum of {}", p, MAX_PRECISION),
+        }
+    }
+}
+
+impl Error for ProbError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbTable {
+    precision: u32,
+    freqs: Vec<u32>,
+    // cum[i] is the sum of freqs[..i]; one longer than freqs
+    cum: Vec<u32>,
+}
+
+impl ProbTable {
+
+    // Symbols are the indices into `counts`; a zero count stays zero
+    pub fn from_counts(counts: &[u64], precision: u32) -> Result<Self, ProbError> {
+        if precision > MAX_PRECISION {
+            return Err(ProbError::BadPrecision(precision));
+        }
+        let scale = 1u64 << precision;
+        let total = counts.iter().fold(0u128, |acc, &c| acc + c as u128);
+        let used = counts.iter().filter(|&&c| c > 0).count();
+        if total == 0 {
+            return Err(ProbError::Empty);
+        }
+        if used as u64 > scale {
+            return Err(ProbError::TooManySymbols { symbols: used, precision });
+        }
+
+        let mut freqs = vec![0u32; counts.len()];
+        let mut rems = vec![0u128; counts.len()];
+        for (i, &c) in counts.iter().enumerate() {
+            if c == 0 {
+                continue;
+            }
+            let exact = c as u128 * scale as u128;
+            let q = (exact / total) as u32;
+            if q == 0 {
+                freqs[i] = 1;
+            } else {
+                freqs[i] = q;
+                rems[i] = exact % total;
+            }
+        }
+
+        let sum: u64 = freqs.iter().map(|&q| q as u64).sum();
+        if sum < scale {
+            // Largest remainders first; ties to the larger count, then the
+            // lower index
+            let mut order: Vec<usize> = (0..counts.len()).filter(|&i| rems[i] > 0).collect();
+            order.sort_by(|&a, &b| rems[b].cmp(&rems[a]).then(counts[b].cmp(&counts[a])).then(a.cmp(&b)));
+            for &i in order.iter().take((scale - sum) as usize) {
+                freqs[i] += 1;
+            }
+        } else if sum > scale {
+            let mut heap: BinaryHeap<Trim> = (0..counts.len())
+                .filter(|&i| freqs[i] > 1)
+                .map(|i| Trim::new(i, counts[i], freqs[i]))
+                .collect();
+            for _ in 0..sum - scale {
+                // there's always one: `used` units can't exceed the scale
+                let trim = heap.pop().expect("room to trim");
+                freqs[trim.index] -= 1;
+                if freqs[trim.index] > 1 {
+                    heap.push(Trim::new(trim.index, counts[trim.index], freqs[trim.index]));
+                }
+            }
+        }
+
+        let mut cum = Vec::with_capacity(freqs.len() + 1);
+        cum.push(0);
+        for &q in &freqs {
+            cum.push(cum.last().unwrap() + q);
+        }
+        Ok(ProbTable { precision, freqs, cum })
+    }
+
+    pub fn precision(&self) -> u32 {
+        self.precision
+    }
+
+    // What the frequencies sum to: 2^precision
+    pub fn total(&self) -> u32 {
+        1 << self.precision
+    }
+
+    // Number of symbols, used or not
+    pub fn len(&self) -> usize {
+        self.freqs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.freqs.is_empty()
+    }
+
+    // 0 for a symbol outside the table
+    pub fn freq(&self, sym: usize) -> u32 {
+        self.freqs.get(sym).cloned().unwrap_or(0)
+    }
+
+    // Start of the symbol's range of slots. Panics unless sym <= len()
+    pub fn cum(&self, sym: usize) -> u32 {
+        self.cum[sym]
+    }
+
+    pub fn freqs(&self) -> &[u32] {
+        &self.freqs
+    }
+
+    pub fn probability(&self, sym: usize) -> f64 {
+        self.freq(sym) as f64 / self.total() as f64
+    }
+
+    // The symbol whose range holds `slot`. Panics unless slot < total()
+    pub fn symbol_at(&self, slot: u32) -> usize {
+        assert!(slot < self.total(), "slot out of range");
+        // last cum[i] <= slot; zero-frequency symbols share their start with
+        // the next one, and the search lands past them
+        self.cum.partition_point(|&c| c <= slot) - 1
+    }
+
+}
+
+// A candidate for giving up one unit. That costs count * log2(q / (q - 1))
+// bits over the counted data, close enough to count / (q - 1/2), which
+// compares exactly in integers. The cheapest comes off the heap first, ties
+// going to the lower index.
+struct Trim {
+    index: usize,
+    count: u64,
+    q: u32,
+}
+
+impl Trim {
+
+    fn new(index: usize, count: u64, q: u32) -> Self {
+        Trim { index, count, q }
+    }
+
+}
+
+impl PartialEq for Trim {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Trim {}
+
+impl PartialOrd for Trim {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Trim {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let cost = self.count as u128 * (2 * other.q as u128 - 1);
+        let other_cost = other.count as u128 * (2 * self.q as u128 - 1);
+        other_cost.cmp(&cost).then(other.index.cmp(&self.index))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{ProbError, ProbTable};
+
+    fn check(table: &ProbTable, counts: &[u64]) {
+        assert_eq!(table.freqs().iter().map(|&q| q as u64).sum::<u64>(), table.total() as u64);
+        for (i, &c) in counts.iter().enumerate() {
+            assert_eq!(table.freq(i) == 0, c == 0);
+            for slot in table.cum(i)..table.cum(i + 1) {
+                assert_eq!(table.symbol_at(slot), i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        // 4/3 each; the one spare unit goes to the first
+        assert_eq!(ProbTable::from_counts(&[1, 1, 1], 2).unwrap().freqs(), [2, 1, 1]);
+        // remainders .0, .2, .8; the last rounds down to 0 and is raised
+        assert_eq!(ProbTable::from_counts(&[5, 3, 2], 2).unwrap().freqs(), [2, 1, 1]);
+        // raised symbols push the sum over; the common symbol pays
+        assert_eq!(ProbTable::from_counts(&[1000, 1, 0, 1, 1], 2).unwrap().freqs(), [1, 1, 0, 1, 1]);
+        assert_eq!(ProbTable::from_counts(&[100, 100, 1, 1, 1, 1], 3).unwrap().freqs(), [2, 2, 1, 1, 1, 1]);
+
+        // a normalised table is a fixed point, which is what lets a decoder
+        // rebuild the encoder's table from the stored frequencies
+        let mut seed = 7u64;
+        for precision in [8, 12, 14] {
+            for _ in 0..20 {
+                let counts: Vec<u64> = (0..200).map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    let x = seed >> 40;
+                    if x.is_multiple_of(5) { 0 } else { x % 1000 * (x % 7 + 1).pow(4) }
+                }).collect();
+                let table = ProbTable::from_counts(&counts, precision).unwrap();
+                check(&table, &counts);
+                let again: Vec<u64> = table.freqs().iter().map(|&q| q as u64).collect();
+                assert_eq!(ProbTable::from_counts(&again, precision).unwrap(), table);
+            }
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(ProbTable::from_counts(&[0, 0], 12).err(), Some(ProbError::Empty));
+        assert_eq!(ProbTable::from_counts(&[1; 5], 2).err(), Some(ProbError::TooManySymbols { symbols: 5, precision: 2 }));
+        assert_eq!(ProbTable::from_counts(&[1], 25).err(), Some(ProbError::BadPrecision(25)));
+        let one = ProbTable::from_counts(&[0, 9], 0).unwrap();
+        assert_eq!((one.freqs(), one.symbol_at(0)), (&[0, 1][..], 1));
+    }
+
+}
@@ -0,0 +1,187 @@
+// Built-in byte frequency tables for common kinds of data. A code built
+// from one of them is the same on every machine, so short messages can be
+// sent without a code table as long as both ends agree on the profile:
+//
+//   let code = ByteHuffman::for_profile(Profile::Json);
+//
+// ProfileBuilder gathers the counts for a new profile from a corpus and
+// writes them out in the form tables.rs uses.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::histogram;
+
+mod tables;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Profile {
+    EnglishText,
+    Json,
+    Base64,
+    SourceCode,
+}
+
+impl Profile {
+
+    pub const ALL: [Profile; 4] = [Profile::EnglishText, Profile::Json, Profile::Base64, Profile::SourceCode];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::EnglishText => "english-text",
+            Profile::Json => "json",
+            Profile::Base64 => "base64",
+            Profile::SourceCode => "source-code",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Profile> {
+        Profile::ALL.iter().cloned().find(|p| p.name() == name)
+    }
+
+    // Every byte has a nonzero frequency
+    pub fn frequencies(self) -> HashMap<u8, u64> {
+        let table = match self {
+            Profile::EnglishText => &tables::ENGLISH_TEXT,
+            Profile::Json => &tables::JSON,
+            Profile::Base64 => &tables::BASE64,
+            Profile::SourceCode => &tables::SOURCE_CODE,
+        };
+        table.iter().enumerate().map(|(b, &f)| (b as u8, f as u64)).collect()
+    }
+
+}
+
+// Byte counts over a corpus
+#[derive(Clone)]
+pub struct ProfileBuilder {
+    counts: [u64; 256],
+}
+
+impl ProfileBuilder {
+
+    pub fn new() -> Self {
+        ProfileBuilder { counts: [0; 256] }
+    }
+
+    pub fn add(&mut self, data: &[u8]) {
+        histogram::add_bytes(&mut self.counts, data);
+    }
+
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.add(&fs::read(path)?);
+        Ok(())
+    }
+
+    // Every file under `dir`, recursively; symbolic links aren't followed
+    pub fn add_dir<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let kind = entry.file_type()?;
+            if kind.is_dir() {
+                self.add_dir(entry.path())?;
+            } else if kind.is_file() {
+                self.add_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn counts(&self) -> &[u64; 256] {
+        &self.counts
+    }
+
+    // Counts scaled to sum to about 2^16, every byte at least 1, as the
+    // built-in profiles are
+    pub fn scaled(&self) -> [u16; 256] {
+        let total: u64 = self.counts.iter().sum::<u64>().max(1);
+        let mut scaled = [0u16; 256];
+        for (s, &c) in scaled.iter_mut().zip(self.counts.iter()) {
+            let v = (c as u128 * (1 << 16) as u128 + total as u128 / 2) / total as u128;
+            *s = v.clamp(1, u16::MAX as u128) as u16;
+        }
+        scaled
+    }
+
+    pub fn frequencies(&self) -> HashMap<u8, u64> {
+        self.scaled().iter().enumerate().map(|(b, &f)| (b as u8, f as u64)).collect()
+    }
+
+    // The scaled table as a Rust constant named `name`
+    pub fn to_rust(&self, name: &str) -> String {
+        let mut out = format!("pub(crate) const {}: [u16; 256] = [\n", name);
+        for row in self.scaled().chunks(16) {
+            let values: Vec<String> = row.iter().map(|v| v.to_string()).collect();
+            writeln!(out, "    {},", values.join(", ")).unwrap();
+        }
+        out.push_str("];\n");
+        out
+    }
+
+}
+
+impl Default for ProfileBuilder {
+    fn default() -> Self {
+        ProfileBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Profile, ProfileBuilder};
+    use crate::huffman::ByteHuffman;
+
+    #[test]
+    fn test_profiles() {
+        let samples = [
+            (Profile::EnglishText, &b"It is a truth universally acknowledged, that a single man in \
+                                      possession of a good fortune, must be in want of a wife."[..]),
+            (Profile::Json, b"{\"id\": 1234, \"name\": \"entrust\", \"tags\": [\"huffman\", \"rans\"]}"),
+            (Profile::Base64, b"SGVsbG8sIHdvcmxkISBUaGlzIGlzIGJhc2U2NCBlbmNvZGVkIHRleHQu"),
+            (Profile::SourceCode, b"fn main() {\n    let x = vec![1, 2, 3];\n    println!(\"{:?}\", x);\n}\n"),
+        ];
+        for &(profile, text) in &samples {
+            assert_eq!(Profile::from_name(profile.name()), Some(profile));
+            let code = ByteHuffman::for_profile(profile);
+            let (packed, nbits) = code.encode_bytes_packed(text);
+            assert_eq!(code.decode_bytes_packed(&packed, nbits), text);
+            // no table needed, and the matching profile does best
+            for other in Profile::ALL.iter().filter(|&&p| p != profile) {
+                assert!(nbits <= ByteHuffman::for_profile(*other).encode_bytes_packed(text).1,
+                        "{} vs {}", profile.name(), other.name());
+            }
+            assert!(nbits < text.len() * 8 * 4 / 5, "{}", profile.name());
+        }
+
+        // any byte at all can be encoded
+        let data: Vec<u8> = (0..=255).collect();
+        let code = ByteHuffman::for_profile(Profile::EnglishText);
+        let (packed, nbits) = code.encode_bytes_packed(&data);
+        assert_eq!(code.decode_bytes_packed(&packed, nbits), data);
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut builder = ProfileBuilder::new();
+        builder.add(b"aaab");
+        assert_eq!(builder.counts()[b'a' as usize], 3);
+        let scaled = builder.scaled();
+        assert_eq!(scaled[b'a' as usize], 49152);
+        assert_eq!(scaled[b'b' as usize], 16384);
+        assert_eq!(scaled[0], 1);
+
+        let source = builder.to_rust("AAAB");
+        assert!(source.starts_with("pub(crate) const AAAB: [u16; 256] = [\n    1, 1,"));
+        assert_eq!(source.lines().count(), 18);
+
+        builder.add_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/src/profiles")).unwrap();
+        assert!(builder.counts()[b'{' as usize] > 0);
+        assert!(builder.add_dir("/nonexistent/entrust/corpus").is_err());
+    }
+
+}
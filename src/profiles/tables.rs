@@ -0,0 +1,83 @@
+// Generated by ProfileBuilder::to_rust. Byte frequencies scaled to sum to
+// about 2^16, every byte at least 1 so any input can be encoded.
+//
+// ENGLISH_TEXT: the license texts in /usr/share/common-licenses
+// JSON: AWS service model files from botocore and boto3
+// BASE64: 3 MB of random bytes, MIME-encoded with 76-column lines
+// SOURCE_CODE: Rust crate sources, the Python standard library and C headers
+
+pub(crate) const ENGLISH_TEXT: [u16; 256] = [
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 6, 1270, 1, 5, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    11403, 1, 167, 1, 1, 1, 1, 34, 96, 120, 78, 1, 579, 133, 457, 32,
+    41, 72, 48, 27, 13, 13, 15, 9, 8, 15, 26, 32, 8, 7, 8, 1,
+    1, 251, 56, 232, 166, 281, 121, 122, 96, 307, 4, 6, 337, 99, 209, 204,
+    174, 5, 205, 253, 307, 110, 47, 76, 13, 137, 5, 2, 1, 2, 1, 38,
+    6, 3207, 761, 1856, 1634, 5724, 1191, 695, 1940, 3965, 35, 247, 1502, 1102, 3310, 4236,
+    1028, 50, 3467, 2881, 4425, 1404, 514, 603, 122, 1026, 11, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+];
+
+pub(crate) const JSON: [u16; 256] = [
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2225, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    23198, 1, 6052, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1212, 123, 123, 9,
+    123, 63, 62, 38, 32, 32, 37, 23, 26, 32, 1895, 1, 1, 1, 1, 1,
+    3, 197, 38, 134, 156, 64, 38, 84, 7, 448, 9, 44, 90, 75, 208, 43,
+    125, 7, 148, 159, 202, 36, 124, 11, 3, 1, 2, 329, 1, 329, 1, 186,
+    1, 1974, 233, 1053, 742, 3953, 328, 459, 289, 1829, 23, 335, 618, 713, 1639, 1585,
+    959, 132, 2035, 1816, 2910, 1149, 99, 102, 153, 323, 7, 732, 1, 732, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+];
+
+pub(crate) const BASE64: [u16; 256] = [
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 851, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1013, 1, 1, 1, 1011,
+    1012, 1007, 1008, 1015, 1018, 1006, 1005, 1010, 1017, 1009, 1, 1, 1, 1, 1, 1,
+    1, 1016, 1006, 1010, 1011, 1004, 1007, 1008, 1009, 1015, 1013, 1007, 1015, 1010, 1010, 1016,
+    1013, 1013, 1014, 1022, 1009, 1005, 1013, 1011, 1017, 1007, 1010, 1, 1, 1, 1, 1,
+    1, 1011, 1012, 1008, 1008, 1014, 1016, 1011, 1008, 1006, 1017, 1013, 1011, 1009, 1005, 1006,
+    1009, 1009, 1014, 1012, 1007, 1007, 1015, 1004, 1014, 1012, 1008, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+];
+
+pub(crate) const SOURCE_CODE: [u16; 256] = [
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 115, 1647, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    13531, 84, 386, 191, 15, 13, 74, 199, 677, 677, 266, 20, 674, 201, 465, 658,
+    289, 256, 395, 320, 149, 84, 139, 68, 121, 65, 1478, 382, 70, 422, 156, 15,
+    6, 423, 141, 468, 308, 706, 212, 184, 128, 477, 34, 77, 285, 230, 388, 413,
+    411, 21, 543, 576, 618, 206, 92, 117, 76, 157, 18, 137, 30, 136, 2, 1710,
+    102, 1898, 689, 1415, 1154, 3685, 1046, 499, 590, 2063, 42, 218, 1339, 811, 2245, 2020,
+    1234, 58, 2146, 2331, 2950, 1424, 256, 433, 227, 492, 116, 164, 20, 164, 1, 1,
+    3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    3, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    3, 3, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    7, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+];
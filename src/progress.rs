@@ -0,0 +1,172 @@
+// Progress reports from long operations, for drawing progress bars.
+//
+// The file functions in container.rs (compress_file_with_progress,
+// decompress_file_with_progress) and the stream adapters in stream.rs
+// (HuffmanEncoder::on_progress, HuffmanDecoder::on_progress) call back with
+// a Progress as they go: once per 64KB or so for files, once per read or
+// write for streams, and a last time with Stage::Done once everything has
+// been written.
+//
+// Compressing a file without blocks takes two passes over it, one to count
+// its bytes and one to encode them, so `consumed` starts again from zero at
+// the Encoding stage. `produced` only ever grows.
+//
+// The same places check for cancellation: the *_cancellable file functions
+// take an AtomicBool, and the stream adapters one set with cancel_on. Once
+// it's set, the next check fails with a Cancelled error; a cancelled file
+// function leaves nothing at its output path.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    // Counting the input's bytes before building the code
+    Scanning,
+    Encoding,
+    Decoding,
+    // Everything is written; the counts are final
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub stage: Stage,
+    // Input bytes read so far in this stage
+    pub consumed: u64,
+    // Input size, if known (not for streams)
+    pub total: Option<u64>,
+    // Output bytes written so far
+    pub produced: u64,
+}
+
+impl Progress {
+
+    // How far through its input this stage is, from 0 to 1; None if the
+    // input size isn't known
+    pub fn fraction(&self) -> Option<f64> {
+        match self.total {
+            _ if self.stage == Stage::Done => Some(1.0),
+            Some(0) => Some(1.0),
+            Some(total) => Some((self.consumed as f64 / total as f64).min(1.0)),
+            None => None,
+        }
+    }
+
+}
+
+// The error from an operation that was cancelled. The file functions and
+// stream adapters return it inside an io::Error (see is_cancelled).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "operation cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+// Of kind Other: Interrupted would have write_all and friends retry
+impl From<Cancelled> for io::Error {
+    fn from(e: Cancelled) -> Self {
+        io::Error::other(e)
+    }
+}
+
+pub fn is_cancelled(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<Cancelled>())
+}
+
+// A progress callback and a cancellation flag, for the file functions
+pub(crate) struct Monitor<'a> {
+    progress: &'a mut dyn FnMut(Progress),
+    cancel: Option<&'a AtomicBool>,
+}
+
+impl<'a> Monitor<'a> {
+
+    pub(crate) fn new(progress: &'a mut dyn FnMut(Progress), cancel: Option<&'a AtomicBool>) -> Self {
+        Monitor { progress, cancel }
+    }
+
+    // Pass on `progress`, unless the flag is set. Done always goes through:
+    // by then there's nothing left to cancel.
+    pub(crate) fn report(&mut self, progress: Progress) -> io::Result<()> {
+        if progress.stage != Stage::Done && self.cancel.is_some_and(|c| c.load(Ordering::Relaxed)) {
+            return Err(Cancelled.into());
+        }
+        (self.progress)(progress);
+        Ok(())
+    }
+
+}
+
+// A reader or writer that counts the bytes going through it
+#[derive(Debug)]
+pub(crate) struct Counter<T> {
+    inner: T,
+    count: u64,
+}
+
+impl<T> Counter<T> {
+
+    pub(crate) fn new(inner: T) -> Self {
+        Counter { inner, count: 0 }
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.count
+    }
+
+}
+
+impl<R: Read> Read for Counter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Counter<W> {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+}
+
+// Seeking doesn't change the count: it's bytes moved, not a position
+impl<T: Seek> Seek for Counter<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Progress, Stage};
+
+    #[test]
+    fn test_fraction() {
+        let progress = Progress { stage: Stage::Encoding, consumed: 25, total: Some(100), produced: 10 };
+        assert_eq!(progress.fraction(), Some(0.25));
+        assert_eq!(Progress { total: None, ..progress }.fraction(), None);
+        assert_eq!(Progress { total: Some(0), ..progress }.fraction(), Some(1.0));
+        // a file that grew since its size was taken
+        assert_eq!(Progress { consumed: 150, ..progress }.fraction(), Some(1.0));
+        assert_eq!(Progress { stage: Stage::Done, total: None, ..progress }.fraction(), Some(1.0));
+    }
+
+}
@@ -0,0 +1,222 @@
+// Python bindings, behind the `python` feature. `maturin develop` builds
+// and installs the module (see pyproject.toml), then:
+//
+//   import entrust
+//   code = entrust.HuffmanCode(corpus)
+//   packed, nbits = code.encode(data)
+//   code.report(data).efficiency
+//   entrust.compress(data, ["bwt", "mtf", "rle", "huffman"])
+//
+// Byte symbols only; strings should be encoded to bytes first. Errors from
+// the crate are raised as ValueError, or EOFError for truncated input.
+
+use std::collections::HashMap;
+
+use pyo3::exceptions::{PyEOFError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::analysis;
+use crate::error::Error;
+use crate::huffman::ByteHuffman;
+use crate::pipeline::{self, Pipeline};
+use crate::stats;
+
+fn py_error<E: Into<Error>>(e: E) -> PyErr {
+    match e.into() {
+        Error::TruncatedStream => PyEOFError::new_err("compressed data is truncated"),
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+#[pyclass(name = "HuffmanCode", module = "entrust")]
+#[derive(Clone)]
+pub struct PyHuffmanCode {
+    code: ByteHuffman,
+}
+
+#[pymethods]
+impl PyHuffmanCode {
+
+    // A code for the byte distribution of `data`
+    #[new]
+    fn new(data: &[u8]) -> PyResult<Self> {
+        Ok(PyHuffmanCode { code: ByteHuffman::try_new_bytes(data).map_err(py_error)? })
+    }
+
+    #[staticmethod]
+    fn from_frequencies(frequencies: HashMap<u8, u64>) -> PyResult<Self> {
+        Ok(PyHuffmanCode { code: ByteHuffman::try_from_frequencies(&frequencies).map_err(py_error)? })
+    }
+
+    // (packed bytes, number of bits used)
+    fn encode<'py>(&self, py: Python<'py>, data: &[u8]) -> PyResult<(Bound<'py, PyBytes>, usize)> {
+        let (packed, nbits) = self.code.try_encode_bytes_packed(data).map_err(py_error)?;
+        Ok((PyBytes::new(py, &packed), nbits))
+    }
+
+    fn decode<'py>(&self, py: Python<'py>, packed: &[u8], nbits: usize) -> PyResult<Bound<'py, PyBytes>> {
+        let data = self.code.try_decode_bytes_packed(packed, nbits).map_err(py_error)?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    // byte -> codeword as a string of '0' and '1'
+    fn codewords(&self) -> HashMap<u8, String> {
+        self.code.codewords().iter().map(|(&b, c)| (b, c.to_bit_string())).collect()
+    }
+
+    fn code_lengths(&self) -> HashMap<u8, usize> {
+        self.code.code_lengths().collect()
+    }
+
+    fn frequencies(&self) -> HashMap<u8, u64> {
+        self.code.frequencies().clone()
+    }
+
+    fn report(&self, data: &[u8]) -> PyResult<CompressionReport> {
+        let report = stats::CompressionReport::for_bytes(&self.code, data).map_err(py_error)?;
+        Ok(CompressionReport {
+            symbols: report.symbols,
+            input_bits: report.input_bits,
+            output_bits: report.output_bits,
+            ratio: report.ratio,
+            average_length: report.average_length,
+            entropy: report.entropy,
+            efficiency: report.efficiency,
+        })
+    }
+
+    fn to_dot(&self) -> String {
+        self.code.to_dot()
+    }
+
+    fn __len__(&self) -> usize {
+        self.code.codewords().len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<HuffmanCode over {} symbols>", self.code.codewords().len())
+    }
+
+}
+
+// stats::CompressionReport without the per-symbol lengths, which
+// HuffmanCode.code_lengths() already gives
+#[pyclass(get_all, frozen, module = "entrust")]
+#[derive(Clone)]
+pub struct CompressionReport {
+    symbols: u64,
+    input_bits: u64,
+    output_bits: u64,
+    ratio: f64,
+    average_length: f64,
+    entropy: f64,
+    efficiency: f64,
+}
+
+#[pymethods]
+impl CompressionReport {
+
+    fn __repr__(&self) -> String {
+        format!("<CompressionReport ratio={:.4} average_length={:.4} entropy={:.4}>",
+                self.ratio, self.average_length, self.entropy)
+    }
+
+}
+
+fn stages(names: &[String]) -> PyResult<Pipeline> {
+    let mut pipeline = Pipeline::new();
+    if names.len() >= u8::MAX as usize {
+        return Err(PyValueError::new_err("too many pipeline stages"));
+    }
+    for name in names {
+        pipeline = match name.as_str() {
+            "bwt" => pipeline.then(pipeline::Bwt),
+            "mtf" => pipeline.then(pipeline::Mtf),
+            "rle" => pipeline.then(pipeline::Rle),
+            "lz77" => pipeline.then(pipeline::Lz77),
+            "huffman" => pipeline.then(pipeline::Huffman),
+            "rans" => pipeline.then(pipeline::Rans),
+            _ => return Err(PyValueError::new_err(format!("unknown pipeline stage {:?}", name))),
+        };
+    }
+    Ok(pipeline)
+}
+
+// Run `data` through the named stages; the output records them, so
+// decompress() needs nothing else
+#[pyfunction]
+#[pyo3(signature = (data, stages=vec!["huffman".to_string()]))]
+fn compress<'py>(py: Python<'py>, data: &[u8], stages: Vec<String>) -> PyResult<Bound<'py, PyBytes>> {
+    let pipeline = self::stages(&stages)?;
+    Ok(PyBytes::new(py, &pipeline.compress(data)))
+}
+
+#[pyfunction]
+fn decompress<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    Ok(PyBytes::new(py, &Pipeline::decompress(data).map_err(py_error)?))
+}
+
+// Empirical entropy of the bytes of `data`, in bits per byte
+#[pyfunction]
+fn entropy(data: &[u8]) -> f64 {
+    analysis::entropy(data)
+}
+
+#[pymodule]
+fn entrust(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyHuffmanCode>()?;
+    m.add_class::<CompressionReport>()?;
+    m.add_function(wrap_pyfunction!(compress, m)?)?;
+    m.add_function(wrap_pyfunction!(decompress, m)?)?;
+    m.add_function(wrap_pyfunction!(entropy, m)?)?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test {
+
+    use pyo3::exceptions::{PyEOFError, PyValueError};
+    use pyo3::types::PyBytesMethods;
+    use pyo3::Python;
+
+    use super::{compress, decompress, entropy, PyHuffmanCode};
+
+    #[test]
+    fn test_bindings() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let data = b"a man a plan a canal panama".repeat(20);
+            let code = PyHuffmanCode::new(&data).unwrap();
+            let (packed, nbits) = code.encode(py, &data).unwrap();
+            assert_eq!(code.decode(py, packed.as_bytes(), nbits).unwrap().as_bytes(), &data[..]);
+            assert_eq!(code.code_lengths().len(), code.__len__());
+            assert!(code.report(&data).unwrap().efficiency > 0.9);
+
+            let stages = ["bwt", "mtf", "rle", "huffman"].iter().map(|s| s.to_string()).collect();
+            let packed = compress(py, &data, stages).unwrap();
+            assert_eq!(decompress(py, packed.as_bytes()).unwrap().as_bytes(), &data[..]);
+            assert_eq!(entropy(b"abab"), 1.0);
+        });
+    }
+
+    #[test]
+    fn test_errors() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            assert!(PyHuffmanCode::new(b"").err().unwrap().is_instance_of::<PyValueError>(py));
+            let code = PyHuffmanCode::new(b"ab").unwrap();
+            assert!(code.encode(py, b"abc").unwrap_err().is_instance_of::<PyValueError>(py));
+            assert!(compress(py, b"x", vec!["zip".to_string()]).unwrap_err().is_instance_of::<PyValueError>(py));
+
+            // truncated input raises EOFError, corrupt input ValueError
+            let packed = compress(py, b"hello", vec!["huffman".to_string()]).unwrap();
+            let packed = packed.as_bytes();
+            let err = decompress(py, &packed[..1]).unwrap_err();
+            assert!(err.is_instance_of::<PyEOFError>(py));
+            assert_eq!(err.value(py).to_string(), "compressed data is truncated");
+            assert!(decompress(py, &packed[..packed.len() - 1]).unwrap_err().is_instance_of::<PyValueError>(py));
+        });
+    }
+
+}
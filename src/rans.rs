@@ -0,0 +1,231 @@
+// Static rANS (Duda's range variant of asymmetric numeral systems), in the
+// byte-oriented form popularised by Fabian Giesen's rans_byte. Symbol
+// frequencies are scaled to sum to 2^SCALE_BITS; each symbol then costs
+// close to its information content, as with arithmetic coding, but the
+// per-symbol work is a multiply and a few shifts.
+//
+// LANES independent states are interleaved over one byte stream, so the
+// dependency chains of consecutive symbols overlap. Symbols are encoded
+// back to front and the output reversed, so the decoder runs forwards:
+// the final states (LANES u32s, big-endian), then the renormalisation
+// bytes in the order the decoder consumes them.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::histogram;
+use crate::huffman::{BuildError, EncodeError};
+use crate::probability::ProbTable;
+use crate::scratch::{self, Scratch};
+
+// Quantised frequencies sum to 2^SCALE_BITS
+pub const SCALE_BITS: u32 = 14;
+const SCALE: u32 = 1 << SCALE_BITS;
+// States stay in [RANS_L, RANS_L << 8)
+const RANS_L: u32 = 1 << 23;
+const LANES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RansError {
+    // The stream ran out before every symbol was decoded
+    Truncated,
+    // The stream doesn't come back to the encoder's initial state, so it
+    // was damaged or encoded with a different table
+    Corrupt,
+}
+
+impl fmt::Display for RansError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RansError::Truncated => write!(f, "rANS stream is truncated"),
+            RansError::Corrupt => write!(f, "rANS stream is corrupt"),
+        }
+    }
+}
+
+impl Error for RansError {}
+
+#[derive(Clone)]
+pub struct Rans {
+    // Quantised frequency of each byte and the running total before it
+    freqs: [u32; 256],
+    cum: [u32; 257],
+    // Symbol owning each of the SCALE slots
+    slots: Arc<[u8]>,
+}
+
+impl Rans {
+
+    pub fn new(data: &[u8]) -> Self {
+        Rans::try_new(data).expect("can't build an rANS table for empty input")
+    }
+
+    pub fn try_new(data: &[u8]) -> Result<Self, BuildError> {
+        Rans::from_frequencies(&histogram::byte_frequencies(data))
+    }
+
+    // Takes the same frequency map a ByteHuffman is built from. Bytes with
+    // a zero count can't be encoded.
+    pub fn from_frequencies(freq: &HashMap<u8, u64>) -> Result<Self, BuildError> {
+        let mut counts = [0u64; 256];
+        for (&b, &f) in freq {
+            counts[b as usize] = f;
+        }
+        let freqs = normalize(&counts, SCALE_BITS).ok_or(BuildError::EmptyAlphabet)?;
+
+        let mut cum = [0u32; 257];
+        for i in 0..256 {
+            cum[i + 1] = cum[i] + freqs[i];
+        }
+        let mut slots = vec![0u8; SCALE as usize];
+        for b in 0..256 {
+            slots[cum[b] as usize..cum[b + 1] as usize].iter_mut().for_each(|s| *s = b as u8);
+        }
+        Ok(Rans { freqs, cum, slots: slots.into() })
+    }
+
+    // Scaled frequency of `b` (out of 2^SCALE_BITS)
+    pub fn frequency(&self, b: u8) -> u32 {
+        self.freqs[b as usize]
+    }
+
+    pub fn encode(&self, data: &[u8]) -> Result<Vec<u8>, EncodeError<u8>> {
+        scratch::with_scratch(|s| self.encode_with_scratch(data, s))
+    }
+
+    // encode(), staging the reversed output in `scratch`; only the result
+    // is allocated
+    pub fn encode_with_scratch(&self, data: &[u8], scratch: &mut Scratch) -> Result<Vec<u8>, EncodeError<u8>> {
+        if let Some(pos) = data.iter().position(|&b| self.freqs[b as usize] == 0) {
+            return Err(EncodeError { symbol: data[pos], pos });
+        }
+
+        let out = &mut scratch.bytes;
+        out.clear();
+        let mut states = [RANS_L; LANES];
+        for (i, &b) in data.iter().enumerate().rev() {
+            let x = &mut states[i % LANES];
+            let f = self.freqs[b as usize];
+            let x_max = ((RANS_L >> SCALE_BITS) << 8) * f;
+            while *x >= x_max {
+                out.push(*x as u8);
+                *x >>= 8;
+            }
+            *x = ((*x / f) << SCALE_BITS) + (*x % f) + self.cum[b as usize];
+        }
+        for x in states.iter().rev() {
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+        Ok(out.iter().rev().cloned().collect())
+    }
+
+    // `len` is the number of symbols encoded, which the stream doesn't record
+    pub fn decode(&self, bytes: &[u8], len: usize) -> Result<Vec<u8>, RansError> {
+        // don't trust `len` with a huge up-front allocation
+        let mut out = Vec::with_capacity(len.min(1 << 20));
+        self.decode_each(bytes, len, |b| out.push(b))?;
+        Ok(out)
+    }
+
+    // decode() of out.len() symbols, into `out`
+    pub fn decode_into(&self, bytes: &[u8], out: &mut [u8]) -> Result<(), RansError> {
+        let len = out.len();
+        let mut slots = out.iter_mut();
+        self.decode_each(bytes, len, |b| *slots.next().unwrap() = b)
+    }
+
+    fn decode_each<F: FnMut(u8)>(&self, bytes: &[u8], len: usize, mut emit: F) -> Result<(), RansError> {
+        if bytes.len() < 4 * LANES {
+            return Err(RansError::Truncated);
+        }
+        let mut states = [0u32; LANES];
+        for (x, chunk) in states.iter_mut().zip(bytes.chunks(4)) {
+            *x = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        let mut input = bytes[4 * LANES..].iter();
+
+        for i in 0..len {
+            let x = &mut states[i % LANES];
+            let slot = *x & (SCALE - 1);
+            let b = self.slots[slot as usize];
+            *x = self.freqs[b as usize] * (*x >> SCALE_BITS) + slot - self.cum[b as usize];
+            while *x < RANS_L {
+                *x = (*x << 8) | *input.next().ok_or(RansError::Truncated)? as u32;
+            }
+            emit(b);
+        }
+        if input.next().is_some() || states.iter().any(|&x| x != RANS_L) {
+            return Err(RansError::Corrupt);
+        }
+        Ok(())
+    }
+
+}
+
+// Scale counts to sum to 2^scale_bits (see ProbTable), keeping every used
+// symbol at one or more. None if no symbol is used or there are more than
+// 2^scale_bits.
+pub(crate) fn normalize(counts: &[u64; 256], scale_bits: u32) -> Option<[u32; 256]> {
+    let table = ProbTable::from_counts(counts, scale_bits).ok()?;
+    let mut freqs = [0u32; 256];
+    freqs.copy_from_slice(table.freqs());
+    Some(freqs)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Rans, RansError, SCALE_BITS};
+    use crate::huffman::{BuildError, ByteHuffman, EncodeError};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_round_trip() {
+        let samples: Vec<Vec<u8>> = vec![b"a".to_vec(), b"ab".to_vec(), b"abracadabra".to_vec(),
+                                         (0..=255u8).collect(),
+                                         b"the quick brown fox jumped over the lazy dog".repeat(300)];
+        for data in samples {
+            let rans = Rans::new(&data);
+            let encoded = rans.encode(&data).unwrap();
+            assert_eq!(rans.decode(&encoded, data.len()).unwrap(), data);
+        }
+        let rans = Rans::new(b"xyz");
+        assert_eq!(rans.decode(&rans.encode(b"").unwrap(), 0).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_quantization() {
+        // every used byte keeps a slot, however rare
+        let mut freq: HashMap<u8, u64> = (0..=255u8).map(|b| (b, 1)).collect();
+        freq.insert(b'e', 1_000_000_000);
+        let rans = Rans::from_frequencies(&freq).unwrap();
+        let total: u32 = (0..=255u8).map(|b| rans.frequency(b)).sum();
+        assert_eq!(total, 1 << SCALE_BITS);
+        assert!((0..=255u8).all(|b| rans.frequency(b) >= 1));
+
+        assert_eq!(Rans::from_frequencies(&HashMap::new()).err(), Some(BuildError::EmptyAlphabet));
+        assert_eq!(Rans::new(b"ab").encode(b"abc").err(), Some(EncodeError { symbol: b'c', pos: 2 }));
+    }
+
+    #[test]
+    fn test_beats_huffman_on_skewed_input() {
+        // p(a) = 0.95: Huffman can't go below a bit per symbol
+        let data: Vec<u8> = (0..20_000).map(|i| if i % 20 == 0 { b'b' } else { b'a' }).collect();
+        let encoded = Rans::new(&data).encode(&data).unwrap();
+        let (_, huffman_bits) = ByteHuffman::new_bytes(&data).encode_bytes_packed(&data);
+        assert!(encoded.len() * 8 < huffman_bits / 3);
+    }
+
+    #[test]
+    fn test_corrupt_streams() {
+        let data = b"fifty liquors yeah good".repeat(10);
+        let rans = Rans::new(&data);
+        let encoded = rans.encode(&data).unwrap();
+        assert_eq!(rans.decode(&encoded[..10], data.len()), Err(RansError::Truncated));
+        assert_eq!(rans.decode(&encoded[..encoded.len() - 1], data.len()), Err(RansError::Truncated));
+        assert_eq!(rans.decode(&encoded, data.len() - 1), Err(RansError::Corrupt));
+    }
+
+}
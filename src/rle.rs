@@ -0,0 +1,224 @@
+// Run-length encoding, on its own or ahead of an entropy coder (BWT output
+// in particular is full of runs). Two byte-level formats:
+//
+// - count-prefixed: every run is a (count, byte) pair, count 1-255. Simple,
+//   but doubles the size of input without runs.
+// - escaped: runs of MIN_ESCAPED_RUN or more, and every occurrence of the
+//   escape byte, become (escape, count, byte); everything else is copied
+//   through. Pick an escape byte that is rare in the input.
+//
+// Bit-level RLE turns a packed bit sequence into the lengths of its
+// alternating runs, starting with a (possibly empty) run of zeros.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::bitio;
+use crate::limits::{DecodeOptions, LimitExceeded};
+
+// Shorter runs are cheaper sent as literals than as escape, count, byte
+pub const MIN_ESCAPED_RUN: usize = 4;
+const MAX_RUN: usize = u8::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RleError {
+    // The input ends partway through a run
+    Truncated,
+    // A run with a count of zero
+    ZeroRun,
+    // More output than DecodeOptions::max_output_bytes
+    LimitExceeded(LimitExceeded),
+}
+
+impl fmt::Display for RleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RleError::Truncated => write!(f, "run-length data is truncated"),
+            RleError::ZeroRun => write!(f, "run of length zero"),
+            RleError::LimitExceeded(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for RleError {}
+
+impl From<LimitExceeded> for RleError {
+    fn from(e: LimitExceeded) -> Self {
+        RleError::LimitExceeded(e)
+    }
+}
+
+// Maximal runs of equal bytes, each at most MAX_RUN long
+fn runs(data: &[u8]) -> impl Iterator<Item=(u8, usize)> + '_ {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let &b = data.get(pos)?;
+        let len = data[pos..].iter().take(MAX_RUN).take_while(|&&x| x == b).count();
+        pos += len;
+        Some((b, len))
+    })
+}
+
+pub fn encode_counted(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (b, len) in runs(data) {
+        out.push(len as u8);
+        out.push(b);
+    }
+    out
+}
+
+pub fn decode_counted(data: &[u8]) -> Result<Vec<u8>, RleError> {
+    decode_counted_with(data, &DecodeOptions::new())
+}
+
+// decode_counted, stopping with LimitExceeded before the output passes
+// max_output_bytes; two bytes can stand for 255
+pub fn decode_counted_with(data: &[u8], limits: &DecodeOptions) -> Result<Vec<u8>, RleError> {
+    let mut out = Vec::new();
+    for pair in data.chunks(2) {
+        if pair.len() < 2 {
+            return Err(RleError::Truncated);
+        }
+        if pair[0] == 0 {
+            return Err(RleError::ZeroRun);
+        }
+        limits.check_output(out.len() as u64 + pair[0] as u64)?;
+        out.extend(std::iter::repeat_n(pair[1], pair[0] as usize));
+    }
+    Ok(out)
+}
+
+pub fn encode_escaped(data: &[u8], escape: u8) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (b, len) in runs(data) {
+        if len >= MIN_ESCAPED_RUN || b == escape {
+            out.extend_from_slice(&[escape, len as u8, b]);
+        } else {
+            out.extend(std::iter::repeat_n(b, len));
+        }
+    }
+    out
+}
+
+pub fn decode_escaped(data: &[u8], escape: u8) -> Result<Vec<u8>, RleError> {
+    decode_escaped_with(data, escape, &DecodeOptions::new())
+}
+
+// decode_escaped, stopping with LimitExceeded before the output passes
+// max_output_bytes
+pub fn decode_escaped_with(data: &[u8], escape: u8, limits: &DecodeOptions) -> Result<Vec<u8>, RleError> {
+    let mut out = Vec::new();
+    let mut input = data.iter();
+    while let Some(&b) = input.next() {
+        if b != escape {
+            limits.check_output(out.len() as u64 + 1)?;
+            out.push(b);
+            continue;
+        }
+        let (&len, &b) = input.next().zip(input.next()).ok_or(RleError::Truncated)?;
+        if len == 0 {
+            return Err(RleError::ZeroRun);
+        }
+        limits.check_output(out.len() as u64 + len as u64)?;
+        out.extend(std::iter::repeat_n(b, len as usize));
+    }
+    Ok(out)
+}
+
+// Lengths of the alternating runs in the first `nbits` bits of an
+// MSB-first packed buffer, zeros first. Only the first run can be empty.
+pub fn encode_bit_runs(bytes: &[u8], nbits: usize) -> Vec<u64> {
+    let mut runs = vec![0u64];
+    let mut current = false;
+    for bit in bitio::unpack(bytes, nbits) {
+        if bit != current {
+            runs.push(0);
+            current = bit;
+        }
+        *runs.last_mut().unwrap() += 1;
+    }
+    if runs == [0] {
+        runs.clear();
+    }
+    runs
+}
+
+// Inverse of encode_bit_runs: the packed bits and how many there are
+pub fn decode_bit_runs(runs: &[u64]) -> (Vec<u8>, usize) {
+    bitio::pack(|w| {
+        for (i, &len) in runs.iter().enumerate() {
+            for _ in 0..len {
+                w.write_bit(i % 2 == 1)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{decode_bit_runs, decode_counted, decode_counted_with, decode_escaped, decode_escaped_with,
+                encode_bit_runs, encode_counted, encode_escaped, RleError};
+    use crate::limits::{DecodeOptions, Limit, LimitExceeded};
+
+    fn samples() -> Vec<Vec<u8>> {
+        vec![b"".to_vec(), b"a".to_vec(), b"abc".to_vec(), b"aaaabbbcccccccd".to_vec(),
+             vec![7; 1000], (0..=255u8).collect(), b"\x00\x00\x00\x00\x00xy\x00".to_vec()]
+    }
+
+    #[test]
+    fn test_counted() {
+        assert_eq!(encode_counted(b"aaabcc"), vec![3, b'a', 1, b'b', 2, b'c']);
+        assert_eq!(encode_counted(&[9; 300]), vec![255, 9, 45, 9]);
+        for data in samples() {
+            assert_eq!(decode_counted(&encode_counted(&data)).unwrap(), data);
+        }
+        assert_eq!(decode_counted(&[3, b'a', 1]), Err(RleError::Truncated));
+        assert_eq!(decode_counted(&[0, b'a']), Err(RleError::ZeroRun));
+    }
+
+    #[test]
+    fn test_escaped() {
+        assert_eq!(encode_escaped(b"abbbbbc!d", b'!'), b"a!\x05bc!\x01!d".to_vec());
+        for data in samples() {
+            for &escape in &[0u8, b'a', 0xff] {
+                assert_eq!(decode_escaped(&encode_escaped(&data, escape), escape).unwrap(), data);
+            }
+        }
+        // input without long runs or escapes passes through unchanged
+        assert_eq!(encode_escaped(b"abcabc", 0), b"abcabc".to_vec());
+        assert_eq!(decode_escaped(b"ab\x00\x05", 0), Err(RleError::Truncated));
+        assert_eq!(decode_escaped(b"\x00\x00b", 0), Err(RleError::ZeroRun));
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = DecodeOptions::new().max_output_bytes(600);
+        let over = Err(RleError::LimitExceeded(LimitExceeded { limit: Limit::OutputBytes, max: 600 }));
+        let counted = encode_counted(&[1; 601]);
+        assert_eq!(decode_counted_with(&counted, &limits), over);
+        assert_eq!(decode_counted_with(&counted[..4], &limits).unwrap(), [1; 510]);
+        let escaped = encode_escaped(&[1; 601], 0);
+        assert_eq!(decode_escaped_with(&escaped, 0, &limits), over);
+        assert_eq!(decode_escaped_with(b"abc", 0, &limits.max_output_bytes(2)).unwrap_err(),
+                   RleError::LimitExceeded(LimitExceeded { limit: Limit::OutputBytes, max: 2 }));
+    }
+
+    #[test]
+    fn test_bit_runs() {
+        // 1110 0000 01
+        let runs = encode_bit_runs(&[0b1110_0000, 0b0100_0000], 10);
+        assert_eq!(runs, vec![0, 3, 6, 1]);
+        assert_eq!(decode_bit_runs(&runs), (vec![0b1110_0000, 0b0100_0000], 10));
+        assert_eq!(encode_bit_runs(&[], 0), Vec::<u64>::new());
+        assert_eq!(decode_bit_runs(&[]), (vec![], 0));
+
+        for data in samples() {
+            let runs = encode_bit_runs(&data, data.len() * 8);
+            assert_eq!(decode_bit_runs(&runs), (data.clone(), data.len() * 8));
+        }
+    }
+
+}
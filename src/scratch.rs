@@ -0,0 +1,196 @@
+// Reusable buffers for the encoders' temporaries, so a service encoding
+// many small messages doesn't allocate and free them for every one.
+//
+// A Scratch is the buffers themselves. Pass one to the encode_with_scratch
+// methods (Rans, Fse, and Coder for every coder) to reuse it across calls;
+// the plain encode() methods use one kept per thread, through with_scratch.
+// A ScratchPool hands Scratches out to threads that don't live long enough
+// for a thread-local to pay off, and takes them back when they're dropped.
+//
+// Buffers keep their capacity between uses, up to MAX_RETAINED bytes each;
+// anything bigger is let go, so one huge message doesn't pin its memory.
+
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+// Capacity a buffer may keep once it's done with, in bytes
+pub const MAX_RETAINED: usize = 1 << 20;
+
+#[derive(Debug, Default)]
+pub struct Scratch {
+    // staging for output built back to front (rANS)
+    pub(crate) bytes: Vec<u8>,
+    // (bits, count) fields to be written in reverse (FSE)
+    pub(crate) fields: Vec<(u32, u32)>,
+}
+
+impl Scratch {
+
+    pub fn new() -> Self {
+        Scratch::default()
+    }
+
+    // Bytes held between uses
+    pub fn capacity(&self) -> usize {
+        self.bytes.capacity() + self.fields.capacity() * std::mem::size_of::<(u32, u32)>()
+    }
+
+    // Empty the buffers, dropping any that grew past MAX_RETAINED
+    pub fn trim(&mut self) {
+        self.bytes.clear();
+        self.fields.clear();
+        if self.bytes.capacity() > MAX_RETAINED {
+            self.bytes = Vec::new();
+        }
+        if self.fields.capacity() * std::mem::size_of::<(u32, u32)>() > MAX_RETAINED {
+            self.fields = Vec::new();
+        }
+    }
+
+}
+
+thread_local! {
+    static SCRATCH: RefCell<Scratch> = RefCell::new(Scratch::new());
+}
+
+// Run `f` with this thread's Scratch. A nested call (an encoder inside
+// another's callback) gets a fresh one rather than the one in use.
+pub fn with_scratch<T, F: FnOnce(&mut Scratch) -> T>(f: F) -> T {
+    SCRATCH.with(|cell| match cell.try_borrow_mut() {
+        Ok(mut scratch) => {
+            let ret = f(&mut scratch);
+            scratch.trim();
+            ret
+        }
+        Err(_) => f(&mut Scratch::new()),
+    })
+}
+
+// Scratches shared between threads. At most `limit` idle ones are kept;
+// get() makes a new one when none are idle.
+#[derive(Debug)]
+pub struct ScratchPool {
+    idle: Mutex<Vec<Scratch>>,
+    limit: usize,
+}
+
+impl ScratchPool {
+
+    pub fn new(limit: usize) -> Self {
+        ScratchPool { idle: Mutex::new(Vec::new()), limit }
+    }
+
+    pub fn get(&self) -> PooledScratch<'_> {
+        let scratch = self.lock().pop().unwrap_or_default();
+        PooledScratch { pool: self, scratch: Some(scratch) }
+    }
+
+    // Scratches waiting to be handed out
+    pub fn idle(&self) -> usize {
+        self.lock().len()
+    }
+
+    fn put(&self, mut scratch: Scratch) {
+        scratch.trim();
+        let mut idle = self.lock();
+        if idle.len() < self.limit {
+            idle.push(scratch);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Scratch>> {
+        // a thread that panicked holding the lock left the list intact
+        self.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+}
+
+impl Default for ScratchPool {
+    // One per core is enough to never make a new Scratch in steady state
+    fn default() -> Self {
+        ScratchPool::new(std::thread::available_parallelism().map_or(4, |n| n.get()))
+    }
+}
+
+// A Scratch on loan from a pool, returned to it on drop
+#[derive(Debug)]
+pub struct PooledScratch<'a> {
+    pool: &'a ScratchPool,
+    scratch: Option<Scratch>,
+}
+
+impl Deref for PooledScratch<'_> {
+    type Target = Scratch;
+
+    fn deref(&self) -> &Scratch {
+        self.scratch.as_ref().expect("only taken on drop")
+    }
+}
+
+impl DerefMut for PooledScratch<'_> {
+    fn deref_mut(&mut self) -> &mut Scratch {
+        self.scratch.as_mut().expect("only taken on drop")
+    }
+}
+
+impl Drop for PooledScratch<'_> {
+    fn drop(&mut self) {
+        if let Some(scratch) = self.scratch.take() {
+            self.pool.put(scratch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{with_scratch, Scratch, ScratchPool, MAX_RETAINED};
+    use crate::coder::CodecId;
+    use crate::fse::Fse;
+    use crate::rans::Rans;
+
+    #[test]
+    fn test_pool() {
+        let pool = ScratchPool::new(2);
+        let (mut a, b, c) = (pool.get(), pool.get(), pool.get());
+        a.bytes.reserve(1000);
+        drop(c);
+        drop(a);
+        drop(b); // one more than the pool keeps
+        assert_eq!(pool.idle(), 2);
+        // the buffers come back empty, but keep their capacity
+        let reused = pool.get();
+        assert!(reused.bytes.is_empty() && reused.capacity() >= 1000);
+
+        let mut big = Scratch::new();
+        big.bytes.reserve(MAX_RETAINED + 1);
+        big.fields.reserve(10);
+        big.trim();
+        assert_eq!(big.bytes.capacity(), 0);
+        assert!(big.fields.capacity() >= 10);
+
+        // nesting falls back to a fresh Scratch instead of panicking
+        assert_eq!(with_scratch(|_| with_scratch(|inner| inner.capacity())), 0);
+    }
+
+    #[test]
+    fn test_encode_with_scratch() {
+        let data: Vec<u8> = (0..5000u32).map(|i| (i * i % 251 % 17) as u8).collect();
+        let pool = ScratchPool::default();
+        let rans = Rans::new(&data);
+        let fse = Fse::new(&data);
+        for message in data.chunks(700) {
+            let mut scratch = pool.get();
+            assert_eq!(rans.encode_with_scratch(message, &mut scratch).unwrap(), rans.encode(message).unwrap());
+            assert_eq!(fse.encode_with_scratch(message, &mut scratch).unwrap(), fse.encode(message).unwrap());
+        }
+        for id in CodecId::ALL.iter() {
+            let coder = id.train(&data).unwrap();
+            let bits = coder.encode_with_scratch(&data, &mut pool.get()).unwrap();
+            assert_eq!(bits, coder.encode(&data).unwrap());
+            assert!(coder.decode(&bits).unwrap() == data);
+        }
+    }
+
+}
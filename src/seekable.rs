@@ -0,0 +1,465 @@
+// Random access into seekable containers (CompressOptions::seekable). The
+// seek table at the end of one says where each block's frame starts and
+// where its bytes start in the original data, so SeekableDecoder can read
+// any range by decoding only the blocks it overlaps:
+//
+//   let mut reader = SeekableDecoder::new(File::open("big.entr")?)?;
+//   reader.seek(SeekFrom::Start(1 << 30))?;
+//   reader.read_exact(&mut buf)?;
+//
+// Each block is checked against its own checksum as it's decoded; the
+// container's overall checksum isn't, as that would mean reading it all.
+//
+// append() adds blocks to the end of a seekable container in place: the
+// new frames go where the checksum and seek table were, followed by a
+// new checksum and the seek table with the new blocks added, and the
+// lengths in the header are patched. The new frames are written in the
+// container's own version of the format. Nothing before the old checksum is
+// rewritten, but an append cut short leaves the container unreadable.
+
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::codes::varint;
+use crate::container::{self, CompressOptions, ContainerError, Format, Prelude, SEEK_MAGIC};
+use crate::limits::{DecodeOptions, Limit};
+
+// A seekable container's seek table, as read back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeekTable {
+    // (frame offset, data offset) per block
+    entries: Vec<(u64, u64)>,
+    // where the last frame ends, and the length of the original data
+    payload_end: u64,
+    len: u64,
+}
+
+impl SeekTable {
+
+    // Reads the container's header and seek table, leaving `input` at an
+    // unspecified position
+    pub fn read<R: Read + Seek>(input: &mut R) -> io::Result<SeekTable> {
+        Ok(read_table(input)?.1)
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Length of the original data
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Where block `i`'s frame sits in the container. Panics if there's no
+    // such block.
+    pub fn frame_range(&self, i: usize) -> Range<u64> {
+        let end = self.entries.get(i + 1).map_or(self.payload_end, |&(frame, _)| frame);
+        self.entries[i].0..end
+    }
+
+    // Which bytes of the original data block `i` holds. Panics if there's
+    // no such block.
+    pub fn data_range(&self, i: usize) -> Range<u64> {
+        let end = self.entries.get(i + 1).map_or(self.len, |&(_, data)| data);
+        self.entries[i].1..end
+    }
+
+    // The block holding byte `pos` of the original data, if it's in range
+    pub fn block_at(&self, pos: u64) -> Option<usize> {
+        if pos >= self.len {
+            return None;
+        }
+        match self.entries.binary_search_by(|&(_, data)| data.cmp(&pos)) {
+            Ok(i) => Some(i),
+            Err(i) => Some(i - 1),
+        }
+    }
+
+}
+
+fn read_table<R: Read + Seek>(input: &mut R) -> io::Result<(Format, SeekTable)> {
+    input.seek(SeekFrom::Start(0))?;
+    let Prelude { format, len, payload_len, .. } = container::read_prelude(input)?;
+    if !format.seekable {
+        return Err(ContainerError::NotSeekable.into());
+    }
+    let payload_start = input.stream_position()?;
+    let payload_end = payload_start.checked_add(payload_len).ok_or(ContainerError::BadSeekTable)?;
+
+    // the table runs from the end of the checksum to the end of the container
+    let end = input.seek(SeekFrom::End(0))?;
+    if end < 8 {
+        return Err(ContainerError::Truncated.into());
+    }
+    input.seek(SeekFrom::Start(end - 8))?;
+    // the entries' size in bytes, or before version 3 their number
+    let size = u32::from_be_bytes(container::read_array(input)?) as u64;
+    let size = if format.varints { size } else { 16 * size };
+    let magic = container::read_array::<4>(input)?;
+    let table_start = end.checked_sub(size + 8);
+    if magic != SEEK_MAGIC || table_start != payload_end.checked_add(format.check.size() as u64) {
+        return Err(ContainerError::BadSeekTable.into());
+    }
+    let table_start = table_start.unwrap();
+    input.seek(SeekFrom::Start(table_start))?;
+    let bytes = container::read_exact_vec(input, size)?;
+    let entries = if format.varints {
+        let mut rest = &bytes[..];
+        let mut entries = Vec::new();
+        while !rest.is_empty() {
+            let frame = varint::read_u64(&mut rest).map_err(|_| ContainerError::BadSeekTable)?;
+            let data = varint::read_u64(&mut rest).map_err(|_| ContainerError::BadSeekTable)?;
+            entries.push((frame, data));
+        }
+        entries
+    } else {
+        bytes.chunks(16).map(|e| {
+            let (frame, data) = e.split_at(8);
+            (u64::from_be_bytes(frame.try_into().unwrap()), u64::from_be_bytes(data.try_into().unwrap()))
+        }).collect()
+    };
+
+    // the frames tile the payload and the blocks the data, in order
+    let starts_ok = entries.first().map_or(len == 0 && payload_len == 0, |&(frame, data)| {
+        frame == payload_start && data == 0
+    });
+    let ordered = entries.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1);
+    let ends_ok = entries.last().is_none_or(|&(frame, data)| frame < payload_end && data < len);
+    if !(starts_ok && ordered && ends_ok) {
+        return Err(ContainerError::BadSeekTable.into());
+    }
+    Ok((format, SeekTable { entries, payload_end, len }))
+}
+
+// Compresses `data` onto the end of the seekable container in `file`,
+// which is left at an unspecified position. The codec and checksum in
+// `options` must be the container's; its level and block size apply to
+// the new blocks only.
+pub fn append<F: Read + Write + Seek>(file: &mut F, data: &[u8], options: &CompressOptions) -> io::Result<()> {
+    let (format, table) = read_table(file)?;
+    if options.codec() != format.codec || options.get_checksum() != format.check {
+        return Err(ContainerError::OptionsMismatch.into());
+    }
+    if data.is_empty() {
+        return Ok(());
+    }
+    let check = format.check;
+    let size = options.seekable(true).get_block_size().unwrap();
+
+    // the checksum goes over every block's checksum, old and new
+    let mut digest = check.digest();
+    let mut block_checksum = vec![0u8; check.size()];
+    for i in 0..table.block_count() {
+        file.seek(SeekFrom::Start(table.frame_range(i).end - check.size() as u64))?;
+        file.read_exact(&mut block_checksum)?;
+        digest.update(&block_checksum);
+    }
+
+    file.seek(SeekFrom::Start(table.payload_end))?;
+    let mut out = BufWriter::new(&mut *file);
+    let mut entries = table.entries.clone();
+    let (mut frame_offset, mut data_offset) = (table.payload_end, table.len);
+    for block in data.chunks(size) {
+        let frame = container::encode_frame(block, options, format.varints);
+        digest.update(&frame[frame.len() - check.size()..]);
+        out.write_all(&frame)?;
+        entries.push((frame_offset, data_offset));
+        frame_offset += frame.len() as u64;
+        data_offset += block.len() as u64;
+    }
+    let mut trailer = Vec::new();
+    container::put_checksum(&mut trailer, check, digest.finish());
+    trailer.extend(container::seek_table(&entries, format.varints));
+    out.write_all(&trailer)?;
+    out.flush()?;
+    drop(out);
+
+    // the original length sits after magic, version, codec and check bytes,
+    // the payload length just ahead of the first frame
+    let payload_start = table.entries.first().map_or(table.payload_end, |&(frame, _)| frame);
+    file.seek(SeekFrom::Start(7))?;
+    file.write_all(&data_offset.to_be_bytes())?;
+    file.seek(SeekFrom::Start(payload_start - 8))?;
+    file.write_all(&(frame_offset - payload_start).to_be_bytes())?;
+    file.flush()
+}
+
+// append() to the file at `path`, or if there's no file there (or it's
+// empty), a new seekable container holding `data`
+pub fn append_file<P: AsRef<Path>>(path: P, data: &[u8], options: &CompressOptions) -> io::Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+    if file.metadata()?.len() == 0 {
+        return file.write_all(&container::compress_with(data, &options.seekable(true)));
+    }
+    append(&mut file, data, options)?;
+    file.sync_data()
+}
+
+// Reads a seekable container's original data from any position, decoding
+// one block at a time and keeping the last one decoded
+pub struct SeekableDecoder<R> {
+    inner: R,
+    format: Format,
+    table: SeekTable,
+    pos: u64,
+    block: Option<(usize, Vec<u8>)>,
+    limits: DecodeOptions,
+}
+
+impl<R: Read + Seek> SeekableDecoder<R> {
+
+    // Fails with ContainerError::NotSeekable (inside an io::Error) for a
+    // container written without a seek table
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let (format, table) = read_table(&mut inner)?;
+        Ok(SeekableDecoder { inner, format, table, pos: 0, block: None, limits: DecodeOptions::new() })
+    }
+
+    // Reads fail with LimitExceeded (inside an io::Error) if the original
+    // data is longer than max_output_bytes, or a block's code table goes
+    // past the table limits
+    pub fn limits(mut self, limits: DecodeOptions) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn seek_table(&self) -> &SeekTable {
+        &self.table
+    }
+
+    // Length of the original data
+    pub fn len(&self) -> u64 {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    // Bytes `range` of the original data, cut short at its end
+    pub fn read_range(&mut self, range: Range<u64>) -> io::Result<Vec<u8>> {
+        let end = range.end.min(self.len());
+        let mut out = Vec::with_capacity(end.saturating_sub(range.start).min(1 << 20) as usize);
+        self.seek(SeekFrom::Start(range.start))?;
+        (&mut *self).take(end.saturating_sub(range.start)).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn load(&mut self, i: usize) -> io::Result<&[u8]> {
+        if self.block.as_ref().is_none_or(|&(cached, _)| cached != i) {
+            let frame = self.table.frame_range(i);
+            self.inner.seek(SeekFrom::Start(frame.start))?;
+            let bytes = container::read_exact_vec(&mut self.inner, frame.end - frame.start)?;
+            // a frame claiming more bytes than its entry is stopped before
+            // it's decoded
+            let range = self.table.data_range(i);
+            let limits = self.limits.max_output_bytes(range.end - range.start);
+            let data = container::decode_frame(self.format, &bytes, &limits).map_err(|e| match e {
+                ContainerError::LimitExceeded(e) if e.limit == Limit::OutputBytes => ContainerError::BadSeekTable,
+                e => e,
+            })?;
+            if data.len() as u64 != range.end - range.start {
+                return Err(ContainerError::BadSeekTable.into());
+            }
+            self.block = Some((i, data));
+        }
+        Ok(&self.block.as_ref().unwrap().1)
+    }
+
+}
+
+impl<R: Read + Seek> Read for SeekableDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let i = match self.table.block_at(self.pos) {
+            Some(i) => i,
+            None => return Ok(0),
+        };
+        self.limits.check_output(self.len()).map_err(ContainerError::from)?;
+        let at = (self.pos - self.table.data_range(i).start) as usize;
+        let block = self.load(i)?;
+        let n = buf.len().min(block.len() - at);
+        buf[..n].copy_from_slice(&block[at..at + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+// Positions are in the original data. Seeking past the end is allowed;
+// reads there return nothing.
+impl<R: Read + Seek> Seek for SeekableDecoder<R> {
+    fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match from {
+            SeekFrom::Start(pos) => {
+                self.pos = pos;
+                return Ok(pos);
+            }
+            SeekFrom::End(delta) => (self.len(), delta),
+            SeekFrom::Current(delta) => (self.pos, delta),
+        };
+        let pos = match delta.cmp(&0) {
+            Ordering::Less => base.checked_sub(delta.unsigned_abs()),
+            _ => base.checked_add(delta as u64),
+        };
+        self.pos = pos.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position"))?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{append, append_file, SeekTable, SeekableDecoder};
+    use crate::checksum::ChecksumKind;
+    use crate::container::{self, Codec, CompressOptions, ContainerError};
+    use crate::limits::{DecodeOptions, Limit, LimitExceeded};
+    use std::fs;
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+    use std::convert::TryInto;
+
+    fn sample() -> Vec<u8> {
+        (0..50_000u32).map(|i| (i * 7 % 13 + i / 1000) as u8).collect()
+    }
+
+    #[test]
+    fn test_seek_table() {
+        let data = sample();
+        for &codec in [Codec::Huffman, Codec::BwtPipeline, Codec::Lzss].iter() {
+            let options = CompressOptions::new(codec).block_size(4096).seekable(true);
+            let packed = container::compress_with(&data, &options);
+            assert_eq!(packed[5], codec.id() | container::BLOCKED | container::SEEKABLE);
+            assert_eq!(container::decompress_from_slice(&packed).unwrap(), data);
+
+            let table = SeekTable::read(&mut Cursor::new(&packed)).unwrap();
+            assert_eq!((table.block_count(), table.len()), (13, 50_000));
+            assert_eq!(table.data_range(12), 49_152..50_000);
+            assert_eq!(table.frame_range(0).start, 27);
+            assert_eq!((table.block_at(4095), table.block_at(4096), table.block_at(50_000)), (Some(0), Some(1), None));
+        }
+
+        // seekable without a block size still gets blocks
+        let options = CompressOptions::new(Codec::Huffman).seekable(true);
+        assert_eq!(options.get_block_size(), Some(container::SEEKABLE_BLOCK_SIZE));
+        let packed = container::compress_with(b"", &options);
+        assert!(SeekTable::read(&mut Cursor::new(&packed)).unwrap().is_empty());
+
+        let plain = container::compress_with(&data, &CompressOptions::new(Codec::Huffman).block_size(4096));
+        let err = SeekTable::read(&mut Cursor::new(&plain)).unwrap_err();
+        assert_eq!(err.get_ref().unwrap().downcast_ref::<ContainerError>(), Some(&ContainerError::NotSeekable));
+    }
+
+    #[test]
+    fn test_random_access() {
+        let data = sample();
+        let options = CompressOptions::new(Codec::Lzss).block_size(5000).checksum(ChecksumKind::XxHash64).seekable(true);
+        let packed = container::compress_with(&data, &options);
+        let mut reader = SeekableDecoder::new(Cursor::new(&packed)).unwrap();
+        assert_eq!(reader.len(), 50_000);
+        for &(start, end) in [(0, 10), (4990, 5010), (12_345, 31_000), (49_999, 50_000), (49_000, 60_000), (70_000, 80_000)].iter() {
+            assert_eq!(reader.read_range(start..end).unwrap(), data[(start as usize).min(50_000)..(end as usize).min(50_000)]);
+        }
+
+        reader.seek(SeekFrom::End(-3)).unwrap();
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &data[49_997..]);
+        assert_eq!(reader.seek(SeekFrom::Current(-10)).unwrap(), 49_990);
+        assert!(reader.seek(SeekFrom::Current(-50_000)).is_err());
+
+        // a damaged block is caught when it's read, and only then
+        let mut corrupt = packed.clone();
+        let frame = reader.seek_table().frame_range(3);
+        corrupt[frame.end as usize - 1] ^= 1;
+        let mut reader = SeekableDecoder::new(Cursor::new(&corrupt)).unwrap();
+        assert_eq!(reader.read_range(0..15_000).unwrap(), &data[..15_000]);
+        assert!(reader.read_range(15_000..15_001).is_err());
+        assert_eq!(container::decompress_from_slice(&corrupt), Err(ContainerError::ChecksumMismatch));
+
+        // the first entry of the seek table, which has to be the first frame
+        let mut bad = packed.clone();
+        let size = u32::from_be_bytes(bad[bad.len() - 8..bad.len() - 4].try_into().unwrap()) as usize;
+        let at = bad.len() - 8 - size;
+        bad[at] ^= 1;
+        assert!(SeekableDecoder::new(Cursor::new(&bad)).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let data = sample();
+        let options = CompressOptions::new(Codec::Huffman).block_size(5000).seekable(true);
+        let packed = container::compress_with(&data, &options);
+        let limits = DecodeOptions::new().max_output_bytes(50_000);
+        let mut reader = SeekableDecoder::new(Cursor::new(&packed)).unwrap().limits(limits);
+        assert_eq!(reader.read_range(0..50_000).unwrap(), data);
+        let mut reader = SeekableDecoder::new(Cursor::new(&packed)).unwrap().limits(limits.max_output_bytes(49_999));
+        let err = reader.read_range(0..10).unwrap_err();
+        assert_eq!(err.get_ref().unwrap().downcast_ref::<ContainerError>(),
+                   Some(&ContainerError::LimitExceeded(LimitExceeded { limit: Limit::OutputBytes, max: 49_999 })));
+
+        // a seek table giving the first block 1000 bytes, whose frame says 5000
+        let table = SeekTable::read(&mut Cursor::new(&packed)).unwrap();
+        let mut entries = table.entries.clone();
+        entries.iter_mut().skip(1).for_each(|e| e.1 -= 4000);
+        let mut short = packed[..table.payload_end as usize + 4].to_vec();
+        short.extend(container::seek_table(&entries, true));
+        short[7..15].copy_from_slice(&46_000u64.to_be_bytes());
+        let err = SeekableDecoder::new(Cursor::new(&short)).unwrap().read_range(0..10).unwrap_err();
+        assert_eq!(err.get_ref().unwrap().downcast_ref::<ContainerError>(), Some(&ContainerError::BadSeekTable));
+    }
+
+    #[test]
+    fn test_append() {
+        let data = sample();
+        let options = CompressOptions::new(Codec::Lzss).block_size(5000).seekable(true);
+        let mut packed = Cursor::new(container::compress_with(&data[..20_000], &options));
+        append(&mut packed, &data[20_000..40_000], &options).unwrap();
+        append(&mut packed, b"", &options).unwrap();
+        append(&mut packed, &data[40_000..], &options).unwrap();
+        // on block boundaries, the same as compressing it all at once
+        let packed = packed.into_inner();
+        assert!(packed == container::compress_with(&data, &options));
+
+        let mut packed = Cursor::new(container::compress_with(b"", &options));
+        for chunk in data.chunks(7777) {
+            append(&mut packed, chunk, &options.block_size(3000)).unwrap();
+        }
+        let packed = packed.into_inner();
+        assert_eq!(container::decompress_from_slice(&packed).unwrap(), data);
+        let mut reader = SeekableDecoder::new(Cursor::new(&packed)).unwrap();
+        assert_eq!(reader.seek_table().block_count(), 6 * 3 + 2);
+        assert_eq!(reader.read_range(7000..8000).unwrap(), &data[7000..8000]);
+
+        let err = append(&mut Cursor::new(packed.clone()), b"x", &CompressOptions::new(Codec::Huffman)).unwrap_err();
+        assert_eq!(err.get_ref().unwrap().downcast_ref::<ContainerError>(), Some(&ContainerError::OptionsMismatch));
+        let plain = container::compress_with(b"abc", &CompressOptions::new(Codec::Lzss));
+        assert!(append(&mut Cursor::new(plain), b"x", &options).is_err());
+    }
+
+    #[test]
+    fn test_append_file() {
+        let path = std::env::temp_dir().join(format!("entrust-{}-append", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let options = CompressOptions::new(Codec::Huffman).checksum(ChecksumKind::Crc32c).block_size(1000);
+        let mut log = Vec::new();
+        for i in 0..20 {
+            let line = format!("{} GET /index.html 200\n", i).repeat(i * 10);
+            append_file(&path, line.as_bytes(), &options).unwrap();
+            log.extend_from_slice(line.as_bytes());
+        }
+        assert_eq!(container::decompress_from_slice(&fs::read(&path).unwrap()).unwrap(), log);
+        let _ = fs::remove_file(&path);
+    }
+
+}
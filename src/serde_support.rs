@@ -0,0 +1,134 @@
+// serde for HuffmanCode and FrequencyTable, behind the `serde` feature. A
+// code is stored as its codewords (as '0'/'1' strings) and the frequencies
+// it was built from, both keyed by symbol and sorted so the output is
+// stable:
+//
+//   {"frequencies": {"a": 1, "b": 2}, "codewords": {"a": "10", "b": "0"}}
+//
+// Deserializing checks the codewords form a prefix code, the same as
+// HuffmanCode::from_table does. A FrequencyTable is just the frequencies
+// part, {"a": 1, "b": 2}; zero counts are dropped on the way back in.
+// Enums and plain structs elsewhere in the crate (Codec, ChecksumKind,
+// CompressOptions, container::Header) derive their impls where they're
+// defined.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+use crate::bitio::Bits;
+use crate::frequency::FrequencyTable;
+use crate::huffman::{HuffmanCode, Symbol};
+
+#[derive(serde::Serialize)]
+struct TableRef<'a, S: Ord> {
+    frequencies: BTreeMap<&'a S, u64>,
+    codewords: BTreeMap<&'a S, String>,
+}
+
+#[derive(serde::Deserialize)]
+struct Table<S: Symbol> {
+    #[serde(default = "HashMap::new")]
+    frequencies: HashMap<S, u64>,
+    codewords: HashMap<S, String>,
+}
+
+impl<S: Symbol + Serialize> Serialize for HuffmanCode<S> {
+    fn serialize<Z: Serializer>(&self, serializer: Z) -> Result<Z::Ok, Z::Error> {
+        TableRef {
+            frequencies: self.frequencies().iter().map(|(sym, &f)| (sym, f)).collect(),
+            codewords: self.codewords().iter().map(|(sym, c)| (sym, c.to_bit_string())).collect(),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de, S: Symbol + Deserialize<'de>> Deserialize<'de> for HuffmanCode<S> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let table = Table::<S>::deserialize(deserializer)?;
+        if table.codewords.is_empty() {
+            return Err(de::Error::custom("code has no codewords"));
+        }
+        let codewords = table.codewords.into_iter()
+            .map(|(sym, c)| Bits::from_bit_string(&c).map(|c| (sym, c)))
+            .collect::<Option<HashMap<S, Bits>>>()
+            .ok_or_else(|| de::Error::custom("codewords must be strings of '0' and '1'"))?;
+        HuffmanCode::try_from_codewords(table.frequencies, codewords).map_err(de::Error::custom)
+    }
+}
+
+impl<S: Symbol + Serialize> Serialize for FrequencyTable<S> {
+    fn serialize<Z: Serializer>(&self, serializer: Z) -> Result<Z::Ok, Z::Error> {
+        self.iter().collect::<BTreeMap<&S, u64>>().serialize(serializer)
+    }
+}
+
+impl<'de, S: Symbol + Deserialize<'de>> Deserialize<'de> for FrequencyTable<S> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(FrequencyTable::from(HashMap::<S, u64>::deserialize(deserializer)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use crate::checksum::ChecksumKind;
+    use crate::container::{self, Codec, CompressOptions, Header};
+    use crate::frequency::FrequencyTable;
+    use crate::huffman::{ByteHuffman, CharHuffman, HuffmanCode};
+
+    #[test]
+    fn test_code_json() {
+        let code = HuffmanCode::new("abbccc");
+        let json = serde_json::to_string(&code).unwrap();
+        assert_eq!(json, r#"{"frequencies":{"a":1,"b":2,"c":3},"codewords":{"a":"10","b":"11","c":"0"}}"#);
+        let restored: CharHuffman = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.frequencies(), code.frequencies());
+        assert!(restored.code_lengths().eq(code.code_lengths()));
+        assert_eq!(restored.decode_string(&code.encode_string("cabcab")), "cabcab");
+
+        // byte codes key on numbers; frequencies may be left out
+        let bytes: ByteHuffman = serde_json::from_str(r#"{"codewords":{"0":"0","255":"1"}}"#).unwrap();
+        assert_eq!(bytes.encode_bytes(&[255, 0]), "10");
+        assert!(bytes.frequencies().is_empty());
+
+        for bad in [r#"{"codewords":{}}"#, r#"{"codewords":{"a":"0","b":"01"}}"#,
+                    r#"{"codewords":{"a":"0","b":"1x"}}"#, r#"{"frequencies":{"a":1}}"#].iter() {
+            assert!(serde_json::from_str::<CharHuffman>(bad).is_err(), "{}", bad);
+        }
+
+        let table: FrequencyTable<u8> = b"abbccc".iter().cloned().collect();
+        let json = serde_json::to_string(&table).unwrap();
+        assert_eq!(json, r#"{"97":1,"98":2,"99":3}"#);
+        assert_eq!(serde_json::from_str::<FrequencyTable<u8>>(&json).unwrap(), table);
+        let zeros: FrequencyTable<char> = serde_json::from_str(r#"{"a":0,"b":4}"#).unwrap();
+        assert_eq!((zeros.len(), zeros.total()), (1, 4));
+    }
+
+    #[test]
+    fn test_metadata_json() {
+        let options = CompressOptions::new(Codec::Huffman).block_size(4096).checksum(ChecksumKind::XxHash64);
+        let json = serde_json::to_string(&options).unwrap();
+        assert_eq!(json, r#"{"codec":"Huffman","block_size":4096,"checksum":"XxHash64","level":6,"seekable":false,"ecc":0}"#);
+        assert_eq!(serde_json::from_str::<CompressOptions>(&json).unwrap(), options);
+        // written before there were levels
+        let old = r#"{"codec":"Huffman","block_size":4096,"checksum":"XxHash64"}"#;
+        assert_eq!(serde_json::from_str::<CompressOptions>(old).unwrap(), options);
+        // what the builders would refuse is refused here too
+        let err = serde_json::from_str::<CompressOptions>(&json.replace("4096", "0")).unwrap_err();
+        assert!(err.to_string().contains("block size must be in 1..=u32::MAX"));
+        let err = serde_json::from_str::<CompressOptions>(&json.replace(r#""level":6"#, r#""level":42"#)).unwrap_err();
+        assert!(err.to_string().contains("level must be in 1..=9"));
+        let err = serde_json::from_str::<CompressOptions>(&json.replace(r#""ecc":0"#, r#""ecc":255"#)).unwrap_err();
+        assert!(err.to_string().contains("parity must be in 0..=254"));
+
+        let packed = container::compress_with(b"hello hello", &options);
+        let header = container::read_header(&packed).unwrap();
+        assert_eq!(header, Header { version: container::FORMAT_VERSION, codec: Codec::Huffman, blocked: true,
+                                    seekable: false,
+                                    checksum: ChecksumKind::XxHash64, len: 11, ecc: 0 });
+        let json = serde_json::to_string(&header).unwrap();
+        assert_eq!(serde_json::from_str::<Header>(&json).unwrap(), header);
+    }
+
+}
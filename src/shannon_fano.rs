@@ -0,0 +1,163 @@
+// Shannon-Fano coding, the top-down predecessor of Huffman's algorithm:
+// sort the symbols by decreasing frequency, split the list where the two
+// halves' totals are closest, prefix one half with 0 and the other with 1,
+// and recurse. Codes come out close to, but not always as good as,
+// Huffman codes for the same frequencies, which makes this mostly useful
+// for comparison.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::bitio::{BitReader, BitWriter, Bits};
+use crate::huffman::{BuildError, HuffmanCode, PrefixCode, Symbol};
+
+#[derive(Clone)]
+pub struct ShannonFano<S: Symbol = char> {
+    // Holds the codewords and the tree to decode them with
+    code: HuffmanCode<S>,
+}
+
+impl<S: Symbol> ShannonFano<S> {
+
+    // Panics on an empty sequence; see try_from_symbols
+    pub fn from_symbols<I: IntoIterator<Item=S>>(symbols: I) -> Self {
+        ShannonFano::try_from_symbols(symbols).expect("can't build a Shannon-Fano code for empty input")
+    }
+
+    pub fn try_from_symbols<I: IntoIterator<Item=S>>(symbols: I) -> Result<Self, BuildError> {
+        let mut freqs = HashMap::new();
+        for sym in symbols {
+            *freqs.entry(sym).or_insert(0) += 1;
+        }
+        ShannonFano::try_from_frequencies(&freqs)
+    }
+
+    // Panics on an empty map; see try_from_frequencies
+    pub fn from_frequencies(freq: &HashMap<S, u64>) -> Self {
+        ShannonFano::try_from_frequencies(freq).expect("can't build a Shannon-Fano code without symbols")
+    }
+
+    pub fn try_from_frequencies(freq: &HashMap<S, u64>) -> Result<Self, BuildError> {
+        if freq.is_empty() {
+            return Err(BuildError::EmptyAlphabet);
+        }
+        // most frequent first, ties in symbol order
+        let mut sorted: Vec<(&S, u64)> = freq.iter().map(|(sym, &f)| (sym, f)).collect();
+        sorted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+        let mut code = HashMap::new();
+        if sorted.len() == 1 {
+            // a lone symbol still needs a one-bit codeword
+            code.insert(sorted[0].0.clone(), Bits::new(vec![0], 1));
+        } else {
+            split(&sorted, Bits::default(), &mut code);
+        }
+        Ok(ShannonFano { code: HuffmanCode::from_codewords(freq.clone(), code) })
+    }
+
+    pub fn frequencies(&self) -> &HashMap<S, u64> {
+        self.code.frequencies()
+    }
+
+    pub fn encode_symbols_packed(&self, symbols: &[S]) -> (Vec<u8>, usize) {
+        self.code.encode_symbols_packed(symbols)
+    }
+
+    pub fn decode_symbols_packed(&self, bytes: &[u8], nbits: usize) -> Vec<S> {
+        self.code.decode_symbols_packed(bytes, nbits)
+    }
+
+    pub fn write_symbol<W: Write>(&self, sym: &S, out: &mut BitWriter<W>) -> io::Result<()> {
+        self.code.write_symbol(sym, out)
+    }
+
+    pub fn read_symbol<R: Read>(&self, input: &mut BitReader<R>) -> io::Result<S> {
+        self.code.read_symbol(input)
+    }
+
+}
+
+impl<S: Symbol> PrefixCode<S> for ShannonFano<S> {
+
+    fn codeword(&self, sym: &S) -> Option<&Bits> {
+        self.code.codeword(sym)
+    }
+
+    fn decode_bits(&self, bits: &Bits) -> Vec<S> {
+        self.code.decode_bits(bits)
+    }
+
+}
+
+fn split<S: Symbol>(symbols: &[(&S, u64)], prefix: Bits, code: &mut HashMap<S, Bits>) {
+    if let [(sym, _)] = symbols {
+        code.insert((*sym).clone(), prefix);
+        return;
+    }
+    // first point where the head's total reaches half, or the one before
+    // it if that is closer
+    let total: u64 = symbols.iter().map(|&(_, f)| f).sum();
+    let mut head = 0;
+    let mut at = 0;
+    while at < symbols.len() - 1 && 2 * (head + symbols[at].1) <= total {
+        head += symbols[at].1;
+        at += 1;
+    }
+    let closer = |at: usize| total.abs_diff(2 * (head + symbols[at].1)) < total.abs_diff(2 * head);
+    if at == 0 || (at < symbols.len() - 1 && closer(at)) {
+        at += 1;
+    }
+    let mut head = prefix.clone();
+    head.push(false);
+    split(&symbols[..at], head, code);
+    let mut tail = prefix;
+    tail.push(true);
+    split(&symbols[at..], tail, code);
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::ShannonFano;
+    use crate::huffman::{CharHuffman, PrefixCode};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_classic_example() {
+        // A 15, B 7, C 6, D 6, E 5: Shannon-Fano splits {A, B} / {C, D, E}
+        // and spends 89 bits where Huffman spends 87
+        let freq: HashMap<char, u64> = vec![('A', 15), ('B', 7), ('C', 6), ('D', 6), ('E', 5)]
+            .into_iter().collect();
+        let sf = ShannonFano::from_frequencies(&freq);
+        let expected = [('A', "00"), ('B', "01"), ('C', "10"), ('D', "110"), ('E', "111")];
+        for (ch, codeword) in expected.iter() {
+            assert_eq!(sf.codeword(ch).map(|c| c.to_bit_string()), Some(codeword.to_string()));
+        }
+        let huffman = CharHuffman::from_frequencies(&freq);
+        assert_eq!(sf.average_length(&freq), 89.0 / 39.0);
+        assert_eq!(huffman.average_length(&freq), 87.0 / 39.0);
+    }
+
+    // Works with either code
+    fn round_trip<C: PrefixCode<char>>(code: &C, s: &str) -> String {
+        let chars: Vec<char> = s.chars().collect();
+        code.decode_symbols(&code.encode_symbols(&chars)).into_iter().collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for s in &["a", "ab", "abracadabra", "the quick brown fox jumped over the lazy dog"] {
+            let sf = ShannonFano::from_symbols(s.chars());
+            let huffman = CharHuffman::new(s);
+            assert_eq!(round_trip(&sf, s), *s);
+            assert_eq!(round_trip(&huffman, s), *s);
+            assert!(huffman.average_length(huffman.frequencies()) <= sf.average_length(sf.frequencies()));
+
+            let chars: Vec<char> = s.chars().collect();
+            let (packed, nbits) = sf.encode_symbols_packed(&chars);
+            assert_eq!(sf.decode_symbols_packed(&packed, nbits), chars);
+        }
+        assert!(ShannonFano::<char>::try_from_frequencies(&HashMap::new()).is_err());
+    }
+
+}
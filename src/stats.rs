@@ -0,0 +1,166 @@
+// How well a code does on a particular input, compared with the input's
+// raw size and with the Shannon bound for its symbol distribution.
+
+use std::collections::HashMap;
+
+use crate::analysis::entropy_from_counts;
+use crate::huffman::{EncodeError, HuffmanCode, Symbol};
+
+#[derive(Debug, Clone)]
+pub struct CompressionReport<S: Symbol> {
+    pub symbols: u64,       // number of input symbols
+    pub input_bits: u64,
+    pub output_bits: u64,
+    pub ratio: f64,         // output_bits / input_bits
+    pub code_lengths: HashMap<S, usize>,
+    pub average_length: f64, // output bits per input symbol
+    pub entropy: f64,        // empirical entropy of the input, bits per symbol
+    pub efficiency: f64,     // entropy / average_length, at most 1
+}
+
+impl<S: Symbol> CompressionReport<S> {
+
+    // `input_bits_per_symbol` is what each symbol costs uncoded, e.g. 8
+    // for bytes or 16 for u16 tokens.
+    pub fn new(code: &HuffmanCode<S>, data: &[S], input_bits_per_symbol: u64)
+        -> Result<Self, EncodeError<S>> {
+        let input_bits = data.len() as u64 * input_bits_per_symbol;
+        CompressionReport::build(code, data, input_bits)
+    }
+
+    fn build(code: &HuffmanCode<S>, data: &[S], input_bits: u64) -> Result<Self, EncodeError<S>> {
+        let code_lengths: HashMap<S, usize> = code.codewords().iter()
+            .map(|(sym, c)| (sym.clone(), c.len()))
+            .collect();
+
+        let mut counts: HashMap<&S, u64> = HashMap::new();
+        let mut output_bits = 0u64;
+        for (pos, sym) in data.iter().enumerate() {
+            let len = code_lengths.get(sym).ok_or_else(|| EncodeError { symbol: sym.clone(), pos })?;
+            output_bits += *len as u64;
+            *counts.entry(sym).or_insert(0) += 1;
+        }
+
+        let n = data.len() as u64;
+        let entropy = entropy_from_counts(counts.values().cloned(), n);
+        let average_length = if n == 0 { 0.0 } else { output_bits as f64 / n as f64 };
+        Ok(CompressionReport {
+            symbols: n,
+            input_bits,
+            output_bits,
+            ratio: if input_bits == 0 { 0.0 } else { output_bits as f64 / input_bits as f64 },
+            code_lengths,
+            average_length,
+            entropy,
+            efficiency: if average_length == 0.0 { 1.0 } else { entropy / average_length },
+        })
+    }
+
+}
+
+impl CompressionReport<u8> {
+    pub fn for_bytes(code: &HuffmanCode<u8>, data: &[u8]) -> Result<Self, EncodeError<u8>> {
+        CompressionReport::new(code, data, 8)
+    }
+}
+
+impl CompressionReport<char> {
+    // Input size is the string's UTF-8 length
+    pub fn for_str(code: &HuffmanCode<char>, s: &str) -> Result<Self, EncodeError<char>> {
+        let chars: Vec<char> = s.chars().collect();
+        CompressionReport::build(code, &chars, s.len() as u64 * 8)
+    }
+}
+
+// What HuffmanCode::verify finds out about a code, measured against a
+// frequency table
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeAudit<S: Symbol> {
+    pub prefix_free: bool,
+    pub kraft_sum: f64,       // sum of 2^-len over the codewords, at most 1 for a prefix code
+    pub complete: bool,       // Kraft sum exactly 1: no codeword could be shortened
+    pub missing: Vec<S>,      // symbols in the table without a codeword, in order
+    pub expected_length: f64, // bits per symbol under the table, over symbols with codewords
+    pub optimal_length: f64,  // the same for a Huffman code built from the table
+    pub optimal: bool,        // expected_length matches, and nothing is missing
+}
+
+impl<S: Symbol> CodeAudit<S> {
+
+    // Safe to use on data distributed like the table
+    pub fn is_ok(&self) -> bool {
+        self.prefix_free && self.missing.is_empty() && self.optimal
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::CompressionReport;
+    use crate::bitio::Bits;
+    use crate::frequency::FrequencyTable;
+    use crate::huffman::{ByteHuffman, CharHuffman, EncodeError};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_report() {
+        // a: 1/2, b: 1/4, c: 1/4 -- Huffman meets the entropy exactly
+        let data = b"aaaabbcc";
+        let code = ByteHuffman::new_bytes(data);
+        let report = CompressionReport::for_bytes(&code, data).unwrap();
+        assert_eq!(report.symbols, 8);
+        assert_eq!(report.input_bits, 64);
+        assert_eq!(report.output_bits, 12);
+        assert_eq!(report.ratio, 12.0 / 64.0);
+        assert_eq!(report.code_lengths[&b'a'], 1);
+        assert_eq!(report.average_length, 1.5);
+        assert!((report.entropy - 1.5).abs() < 1e-12);
+        assert!((report.efficiency - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_str_report() {
+        let s = "the quick brown fox jumped over the lazy dog";
+        let code = CharHuffman::new(s);
+        let report = CompressionReport::for_str(&code, s).unwrap();
+        assert_eq!(report.output_bits as usize, code.encode_string(s).len());
+        assert_eq!(report.input_bits as usize, 8 * s.len());
+        // Huffman is within one bit of the entropy
+        assert!(report.entropy <= report.average_length);
+        assert!(report.average_length < report.entropy + 1.0);
+        assert!(report.efficiency <= 1.0);
+
+        assert_eq!(CompressionReport::for_str(&code, "thE").err(), Some(EncodeError { symbol: 'E', pos: 2 }));
+    }
+
+    #[test]
+    fn test_audit() {
+        let table: FrequencyTable<u8> = b"aaaabbcc".iter().cloned().collect();
+        let audit = ByteHuffman::new_bytes(b"aaaabbcc").verify(&table);
+        assert!(audit.is_ok() && audit.complete);
+        assert_eq!((audit.kraft_sum, audit.expected_length, audit.optimal_length), (1.0, 1.5, 1.5));
+
+        // a valid but lopsided table: 'a' gets the longest codeword
+        let bits = |s: &str| Bits::from_bit_string(s).unwrap();
+        let codewords: HashMap<u8, Bits> = vec![(b'a', bits("11")), (b'b', bits("0")), (b'c', bits("10"))]
+            .into_iter().collect();
+        let audit = ByteHuffman::from_codewords(HashMap::new(), codewords).verify(&table);
+        assert!(audit.prefix_free && audit.complete && !audit.optimal);
+        assert_eq!((audit.expected_length, audit.optimal_length), (1.75, 1.5));
+
+        // incomplete, and 'c' can't be coded at all
+        let codewords: HashMap<u8, Bits> = vec![(b'a', bits("0")), (b'b', bits("10"))].into_iter().collect();
+        let audit = ByteHuffman::from_codewords(HashMap::new(), codewords).verify(&table);
+        assert!(!audit.complete && !audit.is_ok());
+        assert_eq!((audit.kraft_sum, audit.missing), (0.75, vec![b'c']));
+        assert!(ByteHuffman::new_bytes(b"ab").verify(&FrequencyTable::new()).optimal);
+
+        // far more free codewords than a u64 counts, 2^-100 short of complete
+        let codewords: HashMap<u8, Bits> = vec![(b'a', bits("0")), (b'b', bits(&"1".repeat(100)))]
+            .into_iter().collect();
+        let audit = ByteHuffman::from_codewords(HashMap::new(), codewords).verify(&table);
+        assert!(audit.prefix_free && !audit.complete);
+    }
+
+}
@@ -0,0 +1,751 @@
+// Streaming compression with a fixed ByteHuffman code, in the style of
+// flate2's write/read adapters.
+//
+// Stream layout: the packed codewords (MSB-first, zero-padded to a whole
+// byte), followed by a single trailer byte giving the number of padding
+// bits (0-7) in the last data byte. An empty input is just the trailer.
+//
+// The coding itself is StreamingCoder, a push/pull state machine that
+// never touches a reader or writer: input goes in with feed() and
+// finish(), output comes out of drain(). The io adapters here, the tokio
+// ones in async_stream.rs and anything driving it over FFI are loops
+// around those three calls.
+//
+// The io adapters take an optional progress callback (on_progress), called
+// after every write or read that moved data and once more when the stream
+// ends, and an optional cancellation flag (cancel_on), checked before every
+// write or read; see progress.rs.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::bitio::{BitReader, BitWriter};
+use crate::huffman::ByteHuffman;
+use crate::limits::{DecodeOptions, LimitExceeded};
+use crate::progress::{Cancelled, Progress, Stage};
+
+// How much a StreamingCoder buffers before feed() stops taking input
+pub(crate) const CHUNK: usize = 8 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamError {
+    // The input byte at `pos` (counting from the start of the stream) has
+    // no codeword
+    UnknownSymbol { pos: usize },
+    // feed() after finish()
+    Finished,
+    // The stream ends before its trailer, or partway through a codeword
+    Truncated,
+    BadTrailer,
+    // Bits that don't match any codeword
+    BadCodeword,
+    // More output than the decoder's DecodeOptions::max_output_bytes
+    LimitExceeded(LimitExceeded),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreamError::UnknownSymbol { pos } => write!(f, "no codeword for the byte at position {}", pos),
+            StreamError::Finished => write!(f, "input after finish"),
+            StreamError::Truncated => write!(f, "stream is truncated"),
+            StreamError::BadTrailer => write!(f, "invalid stream trailer"),
+            StreamError::BadCodeword => write!(f, "bits don't match any codeword"),
+            StreamError::LimitExceeded(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for StreamError {}
+
+// For the adapters; the StreamError is kept as the inner error
+impl From<StreamError> for io::Error {
+    fn from(e: StreamError) -> Self {
+        let kind = match e {
+            StreamError::UnknownSymbol { .. } => io::ErrorKind::InvalidInput,
+            StreamError::Finished => io::ErrorKind::Other,
+            StreamError::Truncated => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+// What drain() did. Every variant carries the number of bytes written to
+// the output buffer, which may be 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    // The buffer is full and there may be more output: drain again
+    More(usize),
+    // Everything the input so far allows is out: feed() more, or finish()
+    NeedsInput(usize),
+    // The end of the stream; later calls return Done(0)
+    Done(usize),
+}
+
+impl Status {
+
+    pub fn written(&self) -> usize {
+        match *self {
+            Status::More(n) | Status::NeedsInput(n) | Status::Done(n) => n,
+        }
+    }
+
+}
+
+enum State {
+    // Finished bytes not yet drained
+    Encode { bits: BitWriter<Vec<u8>> },
+    // Compressed bytes not yet fully decoded; the first `bitpos` bits of
+    // buf[0] have been. Until finish() the last two bytes are held back,
+    // since they may turn out to be the padded final byte and the trailer.
+    Decode { buf: Vec<u8>, bitpos: usize },
+}
+
+pub struct StreamingCoder {
+    code: ByteHuffman,
+    state: State,
+    // Bytes fed so far
+    pos: usize,
+    finished: bool,
+}
+
+impl StreamingCoder {
+
+    pub fn encoder(code: ByteHuffman) -> Self {
+        StreamingCoder::with_state(code, State::Encode { bits: BitWriter::new(Vec::new()) })
+    }
+
+    pub fn decoder(code: ByteHuffman) -> Self {
+        StreamingCoder::with_state(code, State::Decode { buf: Vec::new(), bitpos: 0 })
+    }
+
+    fn with_state(code: ByteHuffman, state: State) -> Self {
+        StreamingCoder { code, state, pos: 0, finished: false }
+    }
+
+    pub fn code(&self) -> &ByteHuffman {
+        &self.code
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // Take as much of `input` as there is room for and return how much
+    // that was. An encoder stops once it holds CHUNK bytes of output; a
+    // decoder takes all of `input` as long as it holds under CHUNK
+    // compressed bytes, which is always true after drain() returned
+    // NeedsInput. 0 means drain first.
+    //
+    // An encoder fails with UnknownSymbol on a byte without a codeword if
+    // it is the first of `input`, otherwise it stops short of it.
+    pub fn feed(&mut self, input: &[u8]) -> Result<usize, StreamError> {
+        if self.finished {
+            return Err(StreamError::Finished);
+        }
+        let n = match self.state {
+            State::Encode { ref mut bits } => {
+                let mut n = 0;
+                while n < input.len() && bits.get_ref().len() < CHUNK {
+                    if self.code.write_symbol(&input[n], bits).is_err() {
+                        if n == 0 {
+                            return Err(StreamError::UnknownSymbol { pos: self.pos });
+                        }
+                        break;
+                    }
+                    n += 1;
+                }
+                n
+            }
+            State::Decode { ref mut buf, .. } => {
+                if buf.len() >= CHUNK {
+                    return Ok(0);
+                }
+                buf.extend_from_slice(input);
+                input.len()
+            }
+        };
+        self.pos += n;
+        Ok(n)
+    }
+
+    // The input is complete. An encoder pads out the last byte and adds
+    // the trailer; a decoder checks that the last byte fed was a trailer.
+    pub fn finish(&mut self) -> Result<(), StreamError> {
+        if self.finished {
+            return Ok(());
+        }
+        match self.state {
+            State::Encode { ref mut bits } => {
+                let pad = ((8 - bits.bits_written() % 8) % 8) as u8;
+                // writing to a Vec can't fail
+                bits.align().unwrap();
+                bits.get_mut().push(pad);
+            }
+            State::Decode { ref buf, .. } => match buf.last() {
+                None => return Err(StreamError::Truncated),
+                Some(&pad) if pad > 7 || (buf.len() - 1) * 8 < pad as usize => return Err(StreamError::BadTrailer),
+                Some(_) => {}
+            },
+        }
+        self.finished = true;
+        Ok(())
+    }
+
+    // Write as much output as is ready into `out`
+    pub fn drain(&mut self, out: &mut [u8]) -> Result<Status, StreamError> {
+        let (n, more) = match self.state {
+            State::Encode { ref mut bits } => {
+                let pending = bits.get_mut();
+                let n = pending.len().min(out.len());
+                out[..n].copy_from_slice(&pending[..n]);
+                pending.drain(..n);
+                (n, !pending.is_empty())
+            }
+            State::Decode { ref mut buf, ref mut bitpos } => {
+                decode_buffered(&self.code, buf, bitpos, self.finished, out)?
+            }
+        };
+        Ok(match (more, self.finished) {
+            (true, _) => Status::More(n),
+            (false, false) => Status::NeedsInput(n),
+            (false, true) => Status::Done(n),
+        })
+    }
+
+}
+
+// Decode as many whole symbols from `buf` as fit in `out`, and say whether
+// there may be more. A codeword cut off by the end of the buffer is left
+// for the next call.
+fn decode_buffered(code: &ByteHuffman, buf: &mut Vec<u8>, bitpos: &mut usize, eof: bool,
+                   out: &mut [u8]) -> Result<(usize, bool), StreamError> {
+    if buf.is_empty() || out.is_empty() {
+        return Ok((0, false));
+    }
+    let (data_end, limit) = if eof {
+        let pad = buf[buf.len() - 1] as usize;
+        (buf.len() - 1, (buf.len() - 1) * 8 - pad)
+    } else {
+        let end = buf.len().saturating_sub(2);
+        (end, end * 8)
+    };
+    if *bitpos >= limit {
+        return Ok((0, false));
+    }
+
+    let mut reader = BitReader::new(&buf[..data_end]);
+    // skipping bits already in the buffer can't fail
+    reader.read_bits(*bitpos as u32).unwrap();
+    let mut pos = *bitpos;
+    let mut n = 0;
+    while n < out.len() {
+        let b = match code.read_symbol(&mut reader) {
+            Ok(b) => b,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(_) => return Err(StreamError::BadCodeword),
+        };
+        if reader.bits_read() as usize > limit {
+            break; // ran into the padding
+        }
+        out[n] = b;
+        n += 1;
+        pos = reader.bits_read() as usize;
+    }
+
+    buf.drain(..pos / 8);
+    *bitpos = pos % 8;
+    // Whatever is left short of the padding isn't a whole codeword; that
+    // is an error once everything before it is out
+    if n == 0 && eof {
+        return Err(StreamError::Truncated);
+    }
+    Ok((n, n == out.len() || (eof && pos < limit)))
+}
+
+// An adapter's byte counts, and its progress callback and cancellation
+// flag if it has them
+#[derive(Default)]
+struct Reporter {
+    callback: Option<Box<dyn FnMut(Progress) + Send>>,
+    cancel: Option<Arc<AtomicBool>>,
+    consumed: u64,
+    produced: u64,
+    done: bool,
+}
+
+impl Reporter {
+
+    fn check(&self) -> Result<(), Cancelled> {
+        match self.cancel {
+            Some(ref cancel) if cancel.load(Ordering::Relaxed) => Err(Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    fn report(&mut self, stage: Stage) {
+        if self.done {
+            return;
+        }
+        self.done = stage == Stage::Done;
+        if let Some(callback) = self.callback.as_mut() {
+            callback(Progress { stage, consumed: self.consumed, total: None, produced: self.produced });
+        }
+    }
+
+}
+
+pub struct HuffmanEncoder<W: Write> {
+    // Only None after finish() has handed the writer back
+    inner: Option<W>,
+    // Completed bytes go to `inner` after every write
+    coder: StreamingCoder,
+    progress: Reporter,
+}
+
+impl<W: Write> HuffmanEncoder<W> {
+
+    pub fn new(inner: W, code: ByteHuffman) -> Self {
+        HuffmanEncoder { inner: Some(inner), coder: StreamingCoder::encoder(code), progress: Reporter::default() }
+    }
+
+    // Call `f` with Stage::Encoding after every write, and Stage::Done once
+    // the stream is finished
+    pub fn on_progress<F: FnMut(Progress) + Send + 'static>(mut self, f: F) -> Self {
+        self.progress.callback = Some(Box::new(f));
+        self
+    }
+
+    // Once `cancel` is set, writes and finishing fail with
+    // progress::Cancelled, and dropping the encoder leaves the stream
+    // without its end
+    pub fn cancel_on(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.progress.cancel = Some(cancel);
+        self
+    }
+
+    pub fn code(&self) -> &ByteHuffman {
+        self.coder.code()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    // Write out the final partial byte and the trailer; later writes are
+    // an error. Dropping the encoder does this too, ignoring errors.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if self.coder.is_finished() {
+            return Ok(());
+        }
+        self.progress.check()?;
+        self.coder.finish()?;
+        self.dump()?;
+        self.get_mut().flush()?;
+        self.progress.report(Stage::Done);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    fn dump(&mut self) -> io::Result<()> {
+        let mut chunk = [0u8; CHUNK];
+        loop {
+            let n = self.coder.drain(&mut chunk)?.written();
+            if n == 0 {
+                return Ok(());
+            }
+            self.inner.as_mut().unwrap().write_all(&chunk[..n])?;
+            self.progress.produced += n as u64;
+        }
+    }
+
+}
+
+impl<W: Write> Write for HuffmanEncoder<W> {
+
+    // Bytes without a codeword fail with InvalidInput (after any bytes
+    // before them in `buf` have been accepted).
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.progress.check()?;
+        let n = self.coder.feed(buf)?;
+        self.dump()?;
+        self.progress.consumed += n as u64;
+        self.progress.report(Stage::Encoding);
+        Ok(n)
+    }
+
+    // Pushes out every complete byte; bits of a partial byte stay buffered
+    // until more data arrives or the stream is finished.
+    fn flush(&mut self) -> io::Result<()> {
+        self.dump()?;
+        self.get_mut().flush()
+    }
+
+}
+
+impl<W: Write> Drop for HuffmanEncoder<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+pub struct HuffmanDecoder<R: Read> {
+    inner: R,
+    coder: StreamingCoder,
+    progress: Reporter,
+    limits: DecodeOptions,
+}
+
+impl<R: Read> HuffmanDecoder<R> {
+
+    pub fn new(inner: R, code: ByteHuffman) -> Self {
+        HuffmanDecoder { inner, coder: StreamingCoder::decoder(code), progress: Reporter::default(),
+                         limits: DecodeOptions::new() }
+    }
+
+    // Fail reads with StreamError::LimitExceeded once the output would
+    // pass limits.max_output_bytes; the other limits are for the table,
+    // see HuffmanCode::from_table_with
+    pub fn limits(mut self, limits: DecodeOptions) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // Call `f` with Stage::Decoding after every read that returns data, and
+    // Stage::Done at the end of the stream
+    pub fn on_progress<F: FnMut(Progress) + Send + 'static>(mut self, f: F) -> Self {
+        self.progress.callback = Some(Box::new(f));
+        self
+    }
+
+    // Once `cancel` is set, reads fail with progress::Cancelled
+    pub fn cancel_on(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.progress.cancel = Some(cancel);
+        self
+    }
+
+    pub fn code(&self) -> &ByteHuffman {
+        self.coder.code()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    // Compressed bytes already pulled from the reader are lost
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+}
+
+impl<R: Read> Read for HuffmanDecoder<R> {
+
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        self.progress.check()?;
+        if out.is_empty() {
+            return Ok(0);
+        }
+        // one byte more than the limit leaves room for is enough to tell
+        let room = usize::try_from(self.limits.get_max_output_bytes() - self.progress.produced).unwrap_or(usize::MAX);
+        let len = out.len().min(room.saturating_add(1));
+        let out = &mut out[..len];
+        let mut chunk = [0u8; CHUNK];
+        loop {
+            match self.coder.drain(out)? {
+                Status::NeedsInput(0) => {}
+                status => {
+                    let n = status.written();
+                    self.limits.check_output(self.progress.produced + n as u64).map_err(StreamError::LimitExceeded)?;
+                    self.progress.produced += n as u64;
+                    self.progress.report(if n == 0 { Stage::Done } else { Stage::Decoding });
+                    return Ok(n);
+                }
+            }
+            match self.inner.read(&mut chunk) {
+                Ok(0) => self.coder.finish()?,
+                Ok(n) => {
+                    self.coder.feed(&chunk[..n])?;
+                    self.progress.consumed += n as u64;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{HuffmanDecoder, HuffmanEncoder, Status, StreamError, StreamingCoder, CHUNK};
+    use crate::huffman::ByteHuffman;
+    use crate::limits::{DecodeOptions, Limit, LimitExceeded};
+    use crate::progress::{self, Progress, Stage};
+    use std::io::{self, Read, Write};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    // Hands out its data one byte per read() call
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || out.is_empty() {
+                return Ok(0);
+            }
+            out[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    fn compress(data: &[u8], code: &ByteHuffman) -> Vec<u8> {
+        let mut encoder = HuffmanEncoder::new(Vec::new(), code.clone());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_encoder() {
+        let data = b"the quick brown fox jumped over the lazy dog".repeat(50);
+        let code = ByteHuffman::new_bytes(&data);
+        let mut encoder = HuffmanEncoder::new(Vec::new(), code.clone());
+        // feed it in awkwardly sized pieces
+        for chunk in data.chunks(7) {
+            encoder.write_all(chunk).unwrap();
+        }
+        let out = encoder.finish().unwrap();
+
+        let (packed, nbits) = code.encode_bytes_packed(&data);
+        assert_eq!(out[..out.len() - 1], packed[..]);
+        assert_eq!(*out.last().unwrap() as usize, packed.len() * 8 - nbits);
+        assert!(out.len() < data.len() * 5 / 8);
+    }
+
+    #[test]
+    fn test_encoder_errors() {
+        let code = ByteHuffman::new_bytes(b"abc");
+        let mut encoder = HuffmanEncoder::new(Vec::new(), code);
+        assert_eq!(encoder.write(b"abxc").unwrap(), 2);
+        assert!(encoder.write(b"xc").is_err());
+        encoder.try_finish().unwrap();
+        assert!(encoder.write(b"a").is_err());
+
+        // dropping finishes the stream
+        let mut out = Vec::new();
+        HuffmanEncoder::new(&mut out, ByteHuffman::new_bytes(b"ab")).write_all(b"a").unwrap();
+        assert_eq!(out.len(), 2);
+    }
+
+    #[test]
+    fn test_decoder() {
+        let samples: Vec<Vec<u8>> = vec![b"the quick brown fox jumped over the lazy dog".repeat(500),
+                                         b"ab".to_vec(), b"".to_vec(), (0..=255u8).collect()];
+        for data in samples {
+            let code = ByteHuffman::new_bytes(&[&data[..], b"ab"].concat());
+            let compressed = compress(&data, &code);
+
+            let mut decoded = Vec::new();
+            HuffmanDecoder::new(&compressed[..], code.clone()).read_to_end(&mut decoded).unwrap();
+            assert_eq!(data, decoded);
+
+            // codewords split across every possible read boundary
+            let mut decoded = Vec::new();
+            HuffmanDecoder::new(Trickle(&compressed), code.clone()).read_to_end(&mut decoded).unwrap();
+            assert_eq!(data, decoded);
+
+            // tiny output buffers
+            let mut decoder = HuffmanDecoder::new(&compressed[..], code);
+            let mut decoded = Vec::new();
+            let mut byte = [0u8];
+            while decoder.read(&mut byte).unwrap() == 1 {
+                decoded.push(byte[0]);
+            }
+            assert_eq!(data, decoded);
+        }
+    }
+
+    #[test]
+    fn test_decoder_errors() {
+        let data = b"fifty liquors yeah good".repeat(3);
+        let code = ByteHuffman::new_bytes(&data);
+        let compressed = compress(&data, &code);
+
+        let mut sink = Vec::new();
+        let err = HuffmanDecoder::new(&b""[..], code.clone()).read_to_end(&mut sink).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let err = HuffmanDecoder::new(&[5u8][..], code.clone()).read_to_end(&mut sink).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut bad_trailer = compressed.clone();
+        *bad_trailer.last_mut().unwrap() = 9;
+        let err = HuffmanDecoder::new(&bad_trailer[..], code.clone()).read_to_end(&mut sink).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // drop the last data byte, keeping the trailer
+        let mut truncated = compressed[..compressed.len() - 2].to_vec();
+        truncated.push(0);
+        let mut decoded = Vec::new();
+        let err = HuffmanDecoder::new(&truncated[..], code).read_to_end(&mut decoded).unwrap_err();
+        assert_eq!(err.get_ref().unwrap().downcast_ref::<StreamError>(), Some(&StreamError::Truncated));
+        // everything up to the lost byte still comes out
+        assert_eq!(decoded, &data[..67]);
+    }
+
+    #[test]
+    fn test_progress() {
+        let data = b"green ideas sleep furiously ".repeat(100);
+        let code = ByteHuffman::new_bytes(&data);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let mut encoder = HuffmanEncoder::new(Vec::new(), code.clone()).on_progress(move |p| log.lock().unwrap().push(p));
+        for chunk in data.chunks(1000) {
+            encoder.write_all(chunk).unwrap();
+        }
+        let compressed = encoder.finish().unwrap();
+        let reports = std::mem::take(&mut *seen.lock().unwrap());
+        assert_eq!(reports.len(), 4);
+        assert_eq!((reports[0].stage, reports[0].consumed, reports[0].total), (Stage::Encoding, 1000, None));
+        let done = Progress { stage: Stage::Done, consumed: data.len() as u64, total: None,
+                              produced: compressed.len() as u64 };
+        assert_eq!(reports[3], done);
+
+        let log = seen.clone();
+        let mut decoder = HuffmanDecoder::new(&compressed[..], code).on_progress(move |p| log.lock().unwrap().push(p));
+        let mut decoded = Vec::new();
+        decoder.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+        // reading past the end doesn't report again
+        assert_eq!(decoder.read(&mut [0u8; 4]).unwrap(), 0);
+        let reports = seen.lock().unwrap();
+        assert!(reports[..reports.len() - 1].iter().all(|p| p.stage == Stage::Decoding));
+        let done = Progress { stage: Stage::Done, consumed: compressed.len() as u64, total: None,
+                              produced: data.len() as u64 };
+        assert_eq!(reports.last(), Some(&done));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let data = b"colorless green ideas".repeat(100);
+        let code = ByteHuffman::new_bytes(&data);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut out = Vec::new();
+        let mut encoder = HuffmanEncoder::new(&mut out, code.clone()).cancel_on(cancel.clone());
+        encoder.write_all(&data[..1000]).unwrap();
+        cancel.store(true, Ordering::Relaxed);
+        let err = encoder.write_all(&data[1000..]).unwrap_err();
+        assert!(progress::is_cancelled(&err));
+        assert!(progress::is_cancelled(&encoder.try_finish().unwrap_err()));
+        drop(encoder);
+        // no trailer: the stream doesn't decode as if it were complete
+        let mut decoded = Vec::new();
+        let _ = HuffmanDecoder::new(&out[..], code.clone()).read_to_end(&mut decoded);
+        assert!(decoded.len() <= 1000);
+
+        let compressed = compress(&data, &code);
+        let mut decoder = HuffmanDecoder::new(&compressed[..], code).cancel_on(cancel.clone());
+        assert!(progress::is_cancelled(&decoder.read(&mut [0u8; 16]).unwrap_err()));
+        cancel.store(false, Ordering::Relaxed);
+        let mut decoded = Vec::new();
+        decoder.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn test_limits() {
+        let data = b"twelve bytes".repeat(100);
+        let code = ByteHuffman::new_bytes(&data);
+        let compressed = compress(&data, &code);
+        let mut decoded = Vec::new();
+        let mut decoder = HuffmanDecoder::new(&compressed[..], code.clone())
+            .limits(DecodeOptions::new().max_output_bytes(1000));
+        let err = decoder.read_to_end(&mut decoded).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let over = LimitExceeded { limit: Limit::OutputBytes, max: 1000 };
+        assert_eq!(err.into_inner().unwrap().downcast_ref::<StreamError>(), Some(&StreamError::LimitExceeded(over)));
+        assert!(decoded.len() <= 1000);
+
+        // exactly at the limit is fine
+        let mut decoded = Vec::new();
+        HuffmanDecoder::new(Trickle(&compressed), code).limits(DecodeOptions::new().max_output_bytes(1200))
+            .read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    // Drain until there's nothing more to come for now; returns that status
+    fn drain_all(coder: &mut StreamingCoder, out: &mut [u8], into: &mut Vec<u8>) -> Status {
+        loop {
+            let status = coder.drain(out).unwrap();
+            into.extend_from_slice(&out[..status.written()]);
+            if status.written() == 0 {
+                return status;
+            }
+        }
+    }
+
+    #[test]
+    fn test_streaming_coder() {
+        let data = b"so much depends upon a red wheel barrow".repeat(2000);
+        let code = ByteHuffman::new_bytes(&data);
+
+        // push everything at once: the encoder takes only what it has room for
+        let mut encoder = StreamingCoder::encoder(code.clone());
+        let mut input = &data[..];
+        let mut compressed = Vec::new();
+        let mut out = [0u8; 100];
+        while !input.is_empty() {
+            let n = encoder.feed(input).unwrap();
+            assert!(n < data.len());
+            input = &input[n..];
+            assert_eq!(drain_all(&mut encoder, &mut out, &mut compressed), Status::NeedsInput(0));
+        }
+        encoder.finish().unwrap();
+        assert_eq!(drain_all(&mut encoder, &mut out, &mut compressed), Status::Done(0));
+        assert!(compressed == compress(&data, &code));
+        assert_eq!(encoder.feed(b"s"), Err(StreamError::Finished));
+
+        // and back, a few bytes at a time each way
+        let mut decoder = StreamingCoder::decoder(code.clone());
+        let mut decoded = Vec::new();
+        let mut out = [0u8; 3];
+        for piece in compressed.chunks(5) {
+            assert_eq!(decoder.feed(piece).unwrap(), piece.len());
+            assert_eq!(drain_all(&mut decoder, &mut out, &mut decoded), Status::NeedsInput(0));
+        }
+        decoder.finish().unwrap();
+        assert_eq!(drain_all(&mut decoder, &mut out, &mut decoded), Status::Done(0));
+        assert!(decoded == data);
+
+        // a decoder holding CHUNK bytes waits to be drained
+        let mut decoder = StreamingCoder::decoder(code.clone());
+        assert_eq!(decoder.feed(&compressed[..CHUNK]).unwrap(), CHUNK);
+        assert_eq!(decoder.feed(&compressed[CHUNK..]).unwrap(), 0);
+
+        let mut encoder = StreamingCoder::encoder(code);
+        assert_eq!(encoder.feed(b"so!"), Ok(2));
+        assert_eq!(encoder.feed(b"!"), Err(StreamError::UnknownSymbol { pos: 2 }));
+    }
+
+}
@@ -0,0 +1,307 @@
+// Table-driven Huffman decoding. Rather than walking the tree one bit at a
+// time, the decoder peeks at the next `bits` bits of input and looks them
+// up in a table whose entries say which symbol those bits start with and
+// how long its codeword is. Codewords longer than the table width go
+// through second-level (and, for very deep codes, further) tables indexed
+// by the bits that follow, as in zlib's inflate.
+//
+// The primary table also records, for each index, every whole codeword its
+// bits hold (up to MAX_RUN of them), so one lookup can emit several short
+// symbols: with a 10-bit table, English text's 3 and 4 bit codes come out
+// two or three at a time. The primary table is made the full `bits` wide
+// for them even when every codeword is shorter, but runs are only built
+// for primary tables up to MAX_RUN_BITS wide, where they fit in cache.
+
+use std::collections::HashMap;
+
+use crate::huffman::{HuffmanCode, Symbol};
+
+pub const DEFAULT_BITS: u8 = 10;
+// Widest lookup any table level does: peek() serves up to 32 bits
+const MAX_BITS: u8 = 24;
+// Most symbols one lookup emits, and the widest table that gets runs
+pub const MAX_RUN: usize = 4;
+pub const MAX_RUN_BITS: u8 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Entry {
+    Invalid,                           // no codeword starts with these bits
+    Symbol { index: u32, len: u8 },    // symbols[index], codeword ends `len` bits into this level
+    Link { offset: u32, bits: u8 },    // continue in the table at `offset`, `bits` wide
+}
+
+// The codewords a primary index holds whole: their symbols are
+// run_symbols[start..start + count]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Run {
+    start: u32,
+    len: u8, // bits they take together
+    count: u8,
+}
+
+#[derive(Clone)]
+pub struct TableDecoder<S: Symbol> {
+    symbols: Vec<S>,
+    // All table levels back to back; the primary table comes first
+    entries: Vec<Entry>,
+    // One per primary entry; empty if the table is too wide or runs are off
+    runs: Vec<Run>,
+    run_symbols: Vec<S>,
+    bits: u8,
+}
+
+impl<S: Symbol> TableDecoder<S> {
+
+    pub fn new(code: &HuffmanCode<S>) -> Self {
+        TableDecoder::with_bits(code, DEFAULT_BITS)
+    }
+
+    // `bits` is the width of each table level, clamped to 1..=24. Wider
+    // tables resolve more codewords in one lookup but cost 2^bits entries.
+    pub fn with_bits(code: &HuffmanCode<S>, bits: u8) -> Self {
+        let bits = bits.clamp(1, MAX_BITS);
+        let mut sorted: Vec<(&S, String)> = code.codewords().iter().map(|(sym, c)| (sym, c.to_bit_string())).collect();
+        sorted.sort();
+
+        let mut decoder = TableDecoder {
+            symbols: sorted.iter().map(|(sym, _)| (*sym).clone()).collect(),
+            entries: Vec::new(),
+            runs: Vec::new(),
+            run_symbols: Vec::new(),
+            bits,
+        };
+        let codes: Vec<(&str, u32)> = sorted.iter().enumerate()
+            .map(|(i, (_, c))| (c.as_str(), i as u32))
+            .collect();
+        // room in the primary table for runs even if every codeword is short
+        decoder.build(&codes, bits.min(MAX_RUN_BITS));
+        decoder.build_runs();
+        decoder
+    }
+
+    // Whether a lookup may emit more than one symbol; on by default for
+    // tables up to MAX_RUN_BITS wide. Off, every symbol takes a lookup.
+    pub fn multi_symbol(mut self, on: bool) -> Self {
+        if on {
+            self.build_runs();
+        } else {
+            self.runs = Vec::new();
+            self.run_symbols = Vec::new();
+        }
+        self
+    }
+
+    fn build_runs(&mut self) {
+        let (primary, width) = self.primary();
+        if width > MAX_RUN_BITS || !self.runs.is_empty() {
+            return;
+        }
+        let mask = primary - 1;
+        let mut runs = Vec::with_capacity(primary);
+        let mut run_symbols = Vec::new();
+        for i in 0..primary {
+            let mut run = Run { start: run_symbols.len() as u32, ..Run::default() };
+            // past the index's own bits the shifted value reads zeros, so
+            // only codewords ending within them are certain
+            while (run.count as usize) < MAX_RUN {
+                match self.entries[(i << run.len) & mask] {
+                    Entry::Symbol { index, len } if run.len + len <= width => {
+                        run_symbols.push(self.symbols[index as usize].clone());
+                        run.count += 1;
+                        run.len += len;
+                    }
+                    _ => break,
+                }
+            }
+            runs.push(run);
+        }
+        self.runs = runs;
+        self.run_symbols = run_symbols;
+    }
+
+    // Size and width of the primary table
+    fn primary(&self) -> (usize, u8) {
+        let primary = self.entries.len().min(1 << self.bits);
+        (primary, primary.trailing_zeros() as u8)
+    }
+
+    // Lay out a table for `codes` (codeword suffixes still to be matched),
+    // at least `min_width` bits wide, and return its offset.
+    fn build(&mut self, codes: &[(&str, u32)], min_width: u8) -> u32 {
+        let longest = codes.iter().map(|(c, _)| c.len()).max().unwrap_or(0);
+        let width = (longest as u8).clamp(min_width.max(1), self.bits);
+        let offset = self.entries.len();
+        self.entries.resize(offset + (1 << width), Entry::Invalid);
+
+        let mut longer: HashMap<usize, Vec<(&str, u32)>> = HashMap::new();
+        for &(codeword, index) in codes {
+            if codeword.len() <= width as usize {
+                // every index starting with the codeword decodes to it
+                let prefix = bits_value(codeword) << (width as usize - codeword.len());
+                let span = 1 << (width as usize - codeword.len());
+                for entry in &mut self.entries[offset + prefix..offset + prefix + span] {
+                    *entry = Entry::Symbol { index, len: codeword.len() as u8 };
+                }
+            } else {
+                let (head, tail) = codeword.split_at(width as usize);
+                longer.entry(bits_value(head)).or_default().push((tail, index));
+            }
+        }
+
+        let mut prefixes: Vec<usize> = longer.keys().cloned().collect();
+        prefixes.sort();
+        for prefix in prefixes {
+            let group = &longer[&prefix];
+            let sub = self.build(group, 1);
+            let sub_bits = (group.iter().map(|(c, _)| c.len()).max().unwrap() as u8).clamp(1, self.bits);
+            self.entries[offset + prefix] = Entry::Link { offset: sub, bits: sub_bits };
+        }
+        offset as u32
+    }
+
+    // Decode the first `nbits` bits of an MSB-first packed buffer, as
+    // produced by HuffmanCode::encode_symbols_packed. Decoding stops early
+    // at bits that don't form a codeword.
+    pub fn decode_packed(&self, bytes: &[u8], nbits: usize) -> Vec<S> {
+        let nbits = nbits.min(bytes.len() * 8);
+        let (_, primary_bits) = self.primary();
+        let mut ret = Vec::new();
+        let mut pos = 0;
+
+        'symbols: while pos < nbits {
+            let first = peek(bytes, pos, primary_bits);
+            if let Some(run) = self.runs.get(first) {
+                // near the end, the bits may stop partway through the run
+                if run.count > 0 && pos + run.len as usize <= nbits {
+                    let start = run.start as usize;
+                    ret.extend_from_slice(&self.run_symbols[start..start + run.count as usize]);
+                    pos += run.len as usize;
+                    continue;
+                }
+            }
+            let mut offset = 0usize;
+            let mut width = primary_bits;
+            let mut consumed = 0;
+            loop {
+                let index = if consumed == 0 { first } else { peek(bytes, pos + consumed, width) };
+                match self.entries[offset + index] {
+                    Entry::Symbol { index, len } => {
+                        consumed += len as usize;
+                        if pos + consumed > nbits {
+                            break 'symbols; // codeword runs into the padding
+                        }
+                        ret.push(self.symbols[index as usize].clone());
+                        pos += consumed;
+                        break;
+                    }
+                    Entry::Link { offset: next, bits } => {
+                        consumed += width as usize;
+                        offset = next as usize;
+                        width = bits;
+                    }
+                    Entry::Invalid => break 'symbols,
+                }
+            }
+        }
+        ret
+    }
+
+}
+
+fn bits_value(codeword: &str) -> usize {
+    codeword.bytes().fold(0, |v, b| (v << 1) | (b == b'1') as usize)
+}
+
+// The `width` bits starting at bit `pos` of an MSB-first buffer, reading
+// zeros past its end
+#[inline]
+fn peek(bytes: &[u8], pos: usize, width: u8) -> usize {
+    let start = pos / 8;
+    let mut window = [0u8; 8];
+    match bytes.get(start..start + 8) {
+        Some(chunk) => window.copy_from_slice(chunk),
+        None => {
+            let end = bytes.len().min(start + 8);
+            if start < end {
+                window[..end - start].copy_from_slice(&bytes[start..end]);
+            }
+        }
+    }
+    let v = u64::from_be_bytes(window) << (pos % 8);
+    (v >> (64 - width as u32)) as usize
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{TableDecoder, MAX_RUN_BITS};
+    use crate::huffman::{ByteHuffman, HuffmanCode};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_matches_tree_decoder() {
+        let text = b"the quick brown fox jumped over the lazy dog, again and again".repeat(30);
+        let code = ByteHuffman::new_bytes(&text);
+        let (packed, nbits) = code.encode_bytes_packed(&text);
+        for &bits in [1u8, 3, 8, 10, 12].iter() {
+            let decoder = TableDecoder::with_bits(&code, bits);
+            assert_eq!(decoder.decode_packed(&packed, nbits), text);
+            assert_eq!(decoder.multi_symbol(false).decode_packed(&packed, nbits), text);
+        }
+    }
+
+    #[test]
+    fn test_runs() {
+        // a=0 b=10 c=110 d=1110 ... down to 7 bits; in a 7-bit table, index
+        // 0100110 holds a b a c
+        let freqs: HashMap<char, u64> = "abcdefgh".chars().zip([64, 32, 16, 8, 4, 2, 1, 1]).collect();
+        let code = HuffmanCode::from_frequencies(&freqs);
+        let decoder = TableDecoder::with_bits(&code, 7);
+        let run = decoder.runs[0b0100110];
+        assert_eq!((run.count, run.len), (4, 7));
+        // after the a, six ones are only the start of a codeword
+        assert_eq!((decoder.runs[0b0111111].count, decoder.runs[0b0111111].len), (1, 1));
+
+        // runs that would read past the end give way to single symbols
+        let s: Vec<char> = "abacddabhhaa".chars().collect();
+        let (packed, nbits) = code.encode_symbols_packed(&s);
+        let wide = TableDecoder::with_bits(&code, 10);
+        for cut in 0..=nbits {
+            let expected = code.decode_symbols_packed(&packed, cut);
+            assert_eq!(decoder.decode_packed(&packed, cut), expected);
+            assert_eq!(wide.decode_packed(&packed, cut), expected);
+        }
+    }
+
+    #[test]
+    fn test_deep_codes() {
+        // Fibonacci weights: a 29-deep tree that needs several table levels
+        let mut freqs = HashMap::new();
+        let (mut a, mut b) = (1u64, 1u64);
+        for ch in (0..30).map(|i| std::char::from_u32('A' as u32 + i).unwrap()) {
+            freqs.insert(ch, a);
+            let next = a + b;
+            a = b;
+            b = next;
+        }
+        let code = HuffmanCode::from_frequencies(&freqs);
+        let s: Vec<char> = "ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^ZZZA".chars().collect();
+        let (packed, nbits) = code.encode_symbols_packed(&s);
+        for &bits in [4u8, 8, 10].iter() {
+            assert_eq!(TableDecoder::with_bits(&code, bits).decode_packed(&packed, nbits), s);
+        }
+        // too wide a table for runs
+        assert!(TableDecoder::with_bits(&code, MAX_RUN_BITS + 1).runs.is_empty());
+    }
+
+    #[test]
+    fn test_stops_at_padding() {
+        let code = ByteHuffman::new_bytes(b"aab");
+        let (packed, nbits) = code.encode_bytes_packed(b"ab");
+        let decoder = TableDecoder::new(&code);
+        assert_eq!(decoder.decode_packed(&packed, nbits), b"ab");
+        assert_eq!(decoder.decode_packed(&packed, nbits - 1), b"a");
+        assert_eq!(decoder.decode_packed(&packed, 0), b"");
+    }
+
+}
@@ -0,0 +1,152 @@
+// proptest strategies for frequency tables, alphabets, messages and raw
+// bitstreams, behind the `proptest-support` feature, so code built on this
+// crate can property-test its own use of it. The tests below run the
+// crate's codes through them: round trips, prefix-freeness, and complete
+// codes (Kraft sum exactly one).
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use proptest::collection::{self, SizeRange};
+use proptest::prelude::*;
+use proptest::sample;
+
+use crate::huffman::Symbol;
+
+// Largest count the strategies produce; even thousands of symbols at this
+// count can't overflow a u64 total
+pub const MAX_COUNT: u64 = 1 << 32;
+
+// A count for one symbol: mostly small (so ties are common), sometimes
+// anywhere up to MAX_COUNT, sometimes a power of two (so a table can be
+// skewed enough to make a deep tree)
+pub fn count() -> impl Strategy<Value=u64> {
+    prop_oneof![
+        2 => 1..=16u64,
+        1 => 1..=MAX_COUNT,
+        1 => (0..=32u32).prop_map(|e| 1u64 << e),
+    ]
+}
+
+// Nonzero counts for a number of distinct symbols in `size`. `symbol` must
+// be able to produce that many distinct values, or generation gives up.
+pub fn frequencies<S, T>(symbol: T, size: impl Into<SizeRange>) -> impl Strategy<Value=HashMap<S, u64>>
+    where S: Hash + Eq + Debug, T: Strategy<Value=S> {
+    collection::hash_map(symbol, count(), size)
+}
+
+// Counts over bytes, from a single byte up to all 256
+pub fn byte_frequencies() -> impl Strategy<Value=HashMap<u8, u64>> {
+    (1..=256usize).prop_flat_map(|n| {
+        (sample::subsequence((0..=255u8).collect::<Vec<u8>>(), n), collection::vec(count(), n))
+    }).prop_map(|(bytes, counts)| bytes.into_iter().zip(counts).collect())
+}
+
+// Distinct symbols, in order
+pub fn alphabet<S, T>(symbol: T, size: impl Into<SizeRange>) -> impl Strategy<Value=Vec<S>>
+    where S: Symbol + Debug, T: Strategy<Value=S> {
+    collection::hash_set(symbol, size).prop_map(|set: HashSet<S>| {
+        let mut symbols: Vec<S> = set.into_iter().collect();
+        symbols.sort();
+        symbols
+    })
+}
+
+// A message using only symbols from `alphabet`, which must not be empty
+pub fn message<S: Clone + Debug + 'static>(alphabet: Vec<S>, len: impl Into<SizeRange>) -> impl Strategy<Value=Vec<S>> {
+    collection::vec(sample::select(alphabet), len)
+}
+
+// A frequency table and a message over its symbols, for round trips
+pub fn table_and_message<S, T>(symbol: T, size: impl Into<SizeRange>, len: impl Into<SizeRange> + Clone + 'static)
+    -> impl Strategy<Value=(HashMap<S, u64>, Vec<S>)>
+    where S: Symbol + Debug + 'static, T: Strategy<Value=S> {
+    frequencies(symbol, size).prop_flat_map(move |freqs| {
+        let mut symbols: Vec<S> = freqs.keys().cloned().collect();
+        symbols.sort();
+        (Just(freqs), message(symbols, len.clone()))
+    })
+}
+
+// Up to `max_bytes` random bytes and a bit count within them, as the
+// packed decoders take
+pub fn bitstream(max_bytes: usize) -> impl Strategy<Value=(Vec<u8>, usize)> {
+    collection::vec(any::<u8>(), 0..=max_bytes).prop_flat_map(|bytes| {
+        let nbits = bytes.len() * 8;
+        (Just(bytes), 0..=nbits)
+    })
+}
+
+// Whether codewords of these lengths use up the whole code space (Kraft
+// sum exactly one), as any Huffman code over two or more symbols does.
+// Lengths must be 1..=127.
+pub fn is_complete<I: IntoIterator<Item=usize>>(lengths: I) -> bool {
+    let sum: u128 = lengths.into_iter()
+        .map(|len| {
+            assert!((1..=127).contains(&len), "codeword length out of range");
+            1u128 << (127 - len)
+        })
+        .fold(0u128, |acc, x| acc.saturating_add(x));
+    sum == 1 << 127
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{bitstream, byte_frequencies, frequencies, is_complete, table_and_message};
+    use crate::huffman::{ByteHuffman, HuffmanCode, PrefixCode};
+    use crate::shannon_fano::ShannonFano;
+    use proptest::prelude::*;
+
+    proptest! {
+
+        #[test]
+        fn round_trip((freqs, data) in table_and_message(any::<u16>(), 1..200, 0..500)) {
+            let code = HuffmanCode::from_frequencies(&freqs);
+            let (packed, nbits) = code.encode_symbols_packed(&data);
+            prop_assert_eq!(code.try_decode_symbols_packed(&packed, nbits).unwrap(), data.clone());
+            let table = HuffmanCode::<u16>::from_table(&code.serialize_table()).unwrap();
+            prop_assert_eq!(table.try_decode_symbols_packed(&packed, nbits).unwrap(), data);
+        }
+
+        #[test]
+        fn prefix_free(freqs in byte_frequencies()) {
+            let code = ByteHuffman::from_frequencies(&freqs);
+            let mut codewords: Vec<String> = freqs.keys()
+                .map(|b| code.code_for(b).unwrap().to_bit_string())
+                .collect();
+            // sorted, a codeword would be directly followed by one it prefixes
+            codewords.sort();
+            for pair in codewords.windows(2) {
+                prop_assert!(!pair[1].starts_with(&pair[0]));
+            }
+        }
+
+        #[test]
+        fn complete_and_optimal(freqs in frequencies(any::<char>(), 2..100)) {
+            let code = HuffmanCode::from_frequencies(&freqs);
+            prop_assert!(is_complete(code.code_lengths().map(|(_, len)| len)));
+            let bits = |lengths: Vec<(char, usize)>| -> u128 {
+                lengths.iter().map(|(ch, len)| freqs[ch] as u128 * *len as u128).sum()
+            };
+            let huffman = bits(code.code_lengths().collect());
+            let limited = HuffmanCode::with_max_length(&freqs, 16).unwrap();
+            prop_assert!(limited.code_lengths().all(|(_, len)| len <= 16));
+            prop_assert!(huffman <= bits(limited.code_lengths().collect()));
+            let sf = ShannonFano::from_frequencies(&freqs);
+            let sf_lengths = freqs.keys().map(|ch| (*ch, sf.codeword(ch).unwrap().len())).collect();
+            prop_assert!(huffman <= bits(sf_lengths));
+        }
+
+        #[test]
+        fn random_bits((bytes, nbits) in bitstream(64), freqs in byte_frequencies()) {
+            // anything may come out, but nothing panics
+            let code = ByteHuffman::from_frequencies(&freqs);
+            let _ = code.try_decode_bytes_packed(&bytes, nbits);
+            let _ = code.decode_bytes_packed(&bytes, nbits);
+        }
+
+    }
+
+}
@@ -0,0 +1,196 @@
+// Huffman codes for text, over a choice of alphabet. Which one codes a
+// corpus best varies: raw UTF-8 bytes keep the table small for mostly-ASCII
+// text, codepoints suit scripts with many multi-byte characters, and
+// grapheme clusters (behind the `graphemes` feature) keep combining marks
+// and emoji sequences together as single symbols.
+
+use std::collections::HashMap;
+
+#[cfg(feature = "graphemes")]
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::error::{Error, Result};
+use crate::huffman::{ByteHuffman, CharHuffman, HuffmanCode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlphabetMode {
+    Bytes,      // UTF-8 code units
+    Codepoints, // chars, as CharHuffman
+    #[cfg(feature = "graphemes")]
+    Graphemes,  // extended grapheme clusters (UAX #29)
+}
+
+impl AlphabetMode {
+
+    #[cfg(not(feature = "graphemes"))]
+    pub const ALL: [AlphabetMode; 2] = [AlphabetMode::Bytes, AlphabetMode::Codepoints];
+    #[cfg(feature = "graphemes")]
+    pub const ALL: [AlphabetMode; 3] = [AlphabetMode::Bytes, AlphabetMode::Codepoints, AlphabetMode::Graphemes];
+
+    // Number of symbols `s` splits into
+    pub fn count(self, s: &str) -> usize {
+        match self {
+            AlphabetMode::Bytes => s.len(),
+            AlphabetMode::Codepoints => s.chars().count(),
+            #[cfg(feature = "graphemes")]
+            AlphabetMode::Graphemes => s.graphemes(true).count(),
+        }
+    }
+
+}
+
+#[derive(Clone)]
+enum TextCode {
+    Bytes(ByteHuffman),
+    Codepoints(CharHuffman),
+    #[cfg(feature = "graphemes")]
+    Graphemes(HuffmanCode<String>),
+}
+
+#[derive(Clone)]
+pub struct TextHuffman {
+    code: TextCode,
+}
+
+impl TextHuffman {
+
+    // Panics on an empty string; see try_new
+    pub fn new(s: &str, mode: AlphabetMode) -> Self {
+        TextHuffman::try_new(s, mode).expect("can't build a Huffman code for an empty string")
+    }
+
+    pub fn try_new(s: &str, mode: AlphabetMode) -> Result<Self> {
+        let code = match mode {
+            AlphabetMode::Bytes => TextCode::Bytes(HuffmanCode::try_new_bytes(s.as_bytes())?),
+            AlphabetMode::Codepoints => TextCode::Codepoints(HuffmanCode::try_new(s)?),
+            #[cfg(feature = "graphemes")]
+            AlphabetMode::Graphemes => {
+                TextCode::Graphemes(HuffmanCode::try_from_symbols(s.graphemes(true).map(String::from))?)
+            }
+        };
+        Ok(TextHuffman { code })
+    }
+
+    // Try every mode on `s` and keep the one with the fewest coded bits.
+    // Only the payload is compared; a bigger alphabet also costs more to
+    // transmit, which matters for short texts.
+    pub fn best_for(s: &str) -> Result<Self> {
+        let mut best: Option<(usize, TextHuffman)> = None;
+        for &mode in AlphabetMode::ALL.iter() {
+            let code = TextHuffman::try_new(s, mode)?;
+            let nbits = code.encode(s)?.1;
+            if best.as_ref().is_none_or(|(bits, _)| nbits < *bits) {
+                best = Some((nbits, code));
+            }
+        }
+        Ok(best.unwrap().1) // ALL isn't empty and `s` wasn't (try_new checked)
+    }
+
+    pub fn mode(&self) -> AlphabetMode {
+        match self.code {
+            TextCode::Bytes(_) => AlphabetMode::Bytes,
+            TextCode::Codepoints(_) => AlphabetMode::Codepoints,
+            #[cfg(feature = "graphemes")]
+            TextCode::Graphemes(_) => AlphabetMode::Graphemes,
+        }
+    }
+
+    // Number of symbols with a codeword
+    pub fn alphabet_size(&self) -> usize {
+        match &self.code {
+            TextCode::Bytes(code) => code.alphabet().len(),
+            TextCode::Codepoints(code) => code.alphabet().len(),
+            #[cfg(feature = "graphemes")]
+            TextCode::Graphemes(code) => code.alphabet().len(),
+        }
+    }
+
+    // Codeword length of every symbol, each symbol as the text it stands
+    // for. A byte that isn't a whole UTF-8 character is shown as \xNN.
+    pub fn code_lengths(&self) -> HashMap<String, usize> {
+        match &self.code {
+            TextCode::Bytes(code) => code.code_lengths()
+                .map(|(b, len)| (if b.is_ascii() { (b as char).to_string() } else { format!("\\x{:02x}", b) }, len))
+                .collect(),
+            TextCode::Codepoints(code) => code.code_lengths().map(|(ch, len)| (ch.to_string(), len)).collect(),
+            #[cfg(feature = "graphemes")]
+            TextCode::Graphemes(code) => code.code_lengths().collect(),
+        }
+    }
+
+    // Packed codewords and the number of bits used
+    pub fn encode(&self, s: &str) -> Result<(Vec<u8>, usize)> {
+        Ok(match &self.code {
+            TextCode::Bytes(code) => code.try_encode_bytes_packed(s.as_bytes())?,
+            TextCode::Codepoints(code) => code.try_encode_packed(s)?,
+            #[cfg(feature = "graphemes")]
+            TextCode::Graphemes(code) => {
+                let symbols: Vec<String> = s.graphemes(true).map(String::from).collect();
+                code.try_encode_symbols_packed(&symbols)?
+            }
+        })
+    }
+
+    pub fn decode(&self, bytes: &[u8], nbits: usize) -> Result<String> {
+        Ok(match &self.code {
+            TextCode::Bytes(code) => {
+                let data = code.try_decode_bytes_packed(bytes, nbits)?;
+                String::from_utf8(data).map_err(|e| Error::InvalidData(Box::new(e)))?
+            }
+            TextCode::Codepoints(code) => code.try_decode_packed(bytes, nbits)?,
+            #[cfg(feature = "graphemes")]
+            TextCode::Graphemes(code) => code.try_decode_symbols_packed(bytes, nbits)?.concat(),
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{AlphabetMode, TextHuffman};
+    use crate::error::Error;
+
+    const SAMPLE: &str = "Ça va? Ça va très bien, merci. «Déjà vu» — naïve café, crème brûlée.";
+
+    #[test]
+    fn test_modes() {
+        for &mode in AlphabetMode::ALL.iter() {
+            let code = TextHuffman::new(SAMPLE, mode);
+            assert_eq!(code.mode(), mode);
+            let (packed, nbits) = code.encode(SAMPLE).unwrap();
+            assert_eq!(code.decode(&packed, nbits).unwrap(), SAMPLE);
+            assert_eq!(code.code_lengths().len(), code.alphabet_size());
+        }
+
+        let bytes = TextHuffman::new(SAMPLE, AlphabetMode::Bytes);
+        let chars = TextHuffman::new(SAMPLE, AlphabetMode::Codepoints);
+        assert_eq!(AlphabetMode::Bytes.count("é"), 2);
+        assert_eq!(AlphabetMode::Codepoints.count("é"), 1);
+
+        // Cyrillic is all two-byte characters: codepoints win
+        let russian = "съешь же ещё этих мягких французских булок, да выпей чаю".repeat(3);
+        assert_eq!(TextHuffman::best_for(&russian).unwrap().mode(), AlphabetMode::Codepoints);
+
+        assert!(matches!(TextHuffman::try_new("", AlphabetMode::Bytes), Err(Error::EmptyAlphabet)));
+        assert!(matches!(chars.encode("日本"), Err(Error::UnknownSymbol { pos: 0 })));
+        // a byte code can decode to something that isn't UTF-8
+        let (packed, nbits) = bytes.encode("é").unwrap();
+        assert!(matches!(bytes.decode(&packed, nbits - bytes.code_lengths()["\\xa9"]),
+                         Err(Error::InvalidData(_))));
+    }
+
+    #[cfg(feature = "graphemes")]
+    #[test]
+    fn test_graphemes() {
+        // e + combining acute, and a family emoji joined with ZWJs
+        let s = "cafe\u{301} 👨\u{200d}👩\u{200d}👧 cafe\u{301} 👨\u{200d}👩\u{200d}👧";
+        assert_eq!(AlphabetMode::Graphemes.count(s), 13);
+        let code = TextHuffman::new(s, AlphabetMode::Graphemes);
+        assert!(code.code_lengths().contains_key("e\u{301}"));
+        let (packed, nbits) = code.encode(s).unwrap();
+        assert_eq!(code.decode(&packed, nbits).unwrap(), s);
+        assert!(code.encode("e").is_err());
+    }
+
+}
@@ -0,0 +1,231 @@
+// Word-level compression of text. The input is split into words (runs of
+// letters, digits and underscores), runs of whitespace, and single
+// punctuation characters, and each distinct token becomes one symbol of a
+// Huffman code. On natural-language text the per-token cost is well under
+// what coding the same words letter by letter would take, at the price of
+// a dictionary in the header.
+//
+// Layout (big-endian):
+//   count u32, then `count` dictionary entries in token order:
+//     token length u32, token (UTF-8), codeword length u8
+//   nbits u64, then the packed codewords
+// Codewords are assigned canonically from the lengths, token index i
+// standing for the i-th entry.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::canonical;
+use crate::huffman::{DecodeError, HuffmanCode, TableError};
+
+// Longest codeword the encoder assigns; the lengths fit in a byte either way
+const MAX_CODEWORD: u8 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    Word,
+    Space,
+    Punct,
+}
+
+impl TokenKind {
+
+    pub fn of(ch: char) -> TokenKind {
+        if ch.is_alphanumeric() || ch == '_' {
+            TokenKind::Word
+        } else if ch.is_whitespace() {
+            TokenKind::Space
+        } else {
+            TokenKind::Punct
+        }
+    }
+
+}
+
+// Tokens in order, concatenating back to `s`
+pub fn tokenize(s: &str) -> impl Iterator<Item=(TokenKind, &str)> + '_ {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let kind = TokenKind::of(first);
+        let len = match kind {
+            TokenKind::Punct => first.len_utf8(),
+            _ => rest.find(|ch| TokenKind::of(ch) != kind).unwrap_or(rest.len()),
+        };
+        let (token, tail) = rest.split_at(len);
+        rest = tail;
+        Some((kind, token))
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenError {
+    Truncated,
+    // A dictionary entry that isn't UTF-8, or repeats an earlier one
+    InvalidToken,
+    // Codeword lengths that don't make a prefix code
+    Table(TableError),
+    Corrupt(DecodeError),
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenError::Truncated => write!(f, "token stream is truncated"),
+            TokenError::InvalidToken => write!(f, "invalid dictionary token"),
+            TokenError::Table(e) => write!(f, "invalid token code: {}", e),
+            TokenError::Corrupt(e) => write!(f, "corrupt token stream: {}", e),
+        }
+    }
+}
+
+impl Error for TokenError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TokenError::Table(e) => Some(e),
+            TokenError::Corrupt(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+pub fn compress(s: &str) -> Vec<u8> {
+    let mut freqs: HashMap<&str, u64> = HashMap::new();
+    for (_, token) in tokenize(s) {
+        *freqs.entry(token).or_insert(0) += 1;
+    }
+    let mut dictionary: Vec<&str> = freqs.keys().cloned().collect();
+    dictionary.sort();
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&(dictionary.len() as u32).to_be_bytes());
+    if dictionary.is_empty() {
+        out.extend_from_slice(&0u64.to_be_bytes());
+        return out;
+    }
+
+    let index: HashMap<&str, u32> = dictionary.iter().enumerate().map(|(i, t)| (*t, i as u32)).collect();
+    let index_freqs: HashMap<u32, u64> = freqs.iter().map(|(t, f)| (index[t], *f)).collect();
+    let code = HuffmanCode::with_max_length(&index_freqs, MAX_CODEWORD)
+        .expect("a nonempty dictionary has a code within 32 bits");
+    // at most MAX_CODEWORD bits, so each fits its byte
+    let lengths: HashMap<u32, u8> = code.code_lengths().map(|(i, len)| (i, len as u8)).collect();
+    for (i, token) in dictionary.iter().enumerate() {
+        out.extend_from_slice(&(token.len() as u32).to_be_bytes());
+        out.extend_from_slice(token.as_bytes());
+        out.push(lengths[&(i as u32)]);
+    }
+
+    let symbols: Vec<u32> = tokenize(s).map(|(_, token)| index[token]).collect();
+    let (packed, nbits) = code.encode_symbols_packed(&symbols);
+    out.extend_from_slice(&(nbits as u64).to_be_bytes());
+    out.extend(packed);
+    out
+}
+
+pub fn decompress(bytes: &[u8]) -> Result<String, TokenError> {
+    let mut rest = bytes;
+    let count = take_u32(&mut rest)? as usize;
+    // don't trust `count` with a huge up-front allocation
+    let mut dictionary: Vec<&str> = Vec::with_capacity(count.min(1 << 20));
+    let mut lengths = Vec::with_capacity(count.min(1 << 20));
+    for i in 0..count {
+        let len = take_u32(&mut rest)? as usize;
+        let token = std::str::from_utf8(take(&mut rest, len)?).map_err(|_| TokenError::InvalidToken)?;
+        if token.is_empty() || dictionary.last().is_some_and(|&prev| prev >= token) {
+            return Err(TokenError::InvalidToken); // entries are sorted, so this catches repeats
+        }
+        dictionary.push(token);
+        lengths.push((i as u32, take(&mut rest, 1)?[0]));
+    }
+    let nbits = take_u64(&mut rest)?;
+    if nbits > rest.len() as u64 * 8 {
+        return Err(TokenError::Truncated);
+    }
+    if count == 0 {
+        return Ok(String::new());
+    }
+
+    if lengths.iter().any(|&(_, len)| len == 0) {
+        return Err(TokenError::Table(TableError::Oversubscribed));
+    }
+    let codewords = canonical::canonical_codewords(&lengths).ok_or(TokenError::Table(TableError::Oversubscribed))?;
+    let code = HuffmanCode::try_from_codewords(HashMap::new(), codewords.into_iter().collect())
+        .map_err(TokenError::Table)?;
+    let symbols = code.try_decode_symbols_packed(rest, nbits as usize).map_err(TokenError::Corrupt)?;
+    Ok(symbols.into_iter().map(|i| dictionary[i as usize]).collect())
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], TokenError> {
+    if bytes.len() < n {
+        return Err(TokenError::Truncated);
+    }
+    let (head, tail) = bytes.split_at(n);
+    *bytes = tail;
+    Ok(head)
+}
+
+fn take_u32(bytes: &mut &[u8]) -> Result<u32, TokenError> {
+    let b = take(bytes, 4)?;
+    Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn take_u64(bytes: &mut &[u8]) -> Result<u64, TokenError> {
+    let b = take(bytes, 8)?;
+    let mut arr = [0u8; 8];
+    arr.copy_from_slice(b);
+    Ok(u64::from_be_bytes(arr))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{compress, decompress, tokenize, TokenError, TokenKind};
+    use crate::huffman::HuffmanCode;
+
+    const TEXT: &str = "It was the best of times, it was the worst of times, it was the age of wisdom, \
+                        it was the age of foolishness, it was the epoch of belief, it was the epoch of \
+                        incredulity, it was the season of Light, it was the season of Darkness, it was \
+                        the spring of hope, it was the winter of despair.";
+
+    #[test]
+    fn test_tokenize() {
+        let tokens: Vec<(TokenKind, &str)> = tokenize("don't  panic_now!\n42").collect();
+        assert_eq!(tokens, vec![(TokenKind::Word, "don"), (TokenKind::Punct, "'"), (TokenKind::Word, "t"),
+                                (TokenKind::Space, "  "), (TokenKind::Word, "panic_now"), (TokenKind::Punct, "!"),
+                                (TokenKind::Space, "\n"), (TokenKind::Word, "42")]);
+        assert_eq!(tokenize("«héllo»").map(|(_, t)| t).collect::<Vec<_>>(), vec!["«", "héllo", "»"]);
+        assert_eq!(tokenize("").count(), 0);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for s in [TEXT, "", "a", "  ", "ünïcödé wörds, ünïcödé wörds"].iter() {
+            assert_eq!(decompress(&compress(s)).unwrap(), *s);
+        }
+
+        // dictionary and all, well under half of a character code's payload
+        let text = TEXT.repeat(20);
+        let packed = compress(&text);
+        let char_bits = HuffmanCode::new(&text).encode_packed(&text).1;
+        assert!(packed.len() * 8 < char_bits / 2);
+    }
+
+    #[test]
+    fn test_bad_input() {
+        let packed = compress(TEXT);
+        for cut in [0, 3, 10, packed.len() - 1].iter() {
+            assert!(decompress(&packed[..*cut]).is_err());
+        }
+        assert_eq!(decompress(&packed[..packed.len() - 1]), Err(TokenError::Truncated));
+        // the first entry is " ", the smallest token: length, byte 8, codeword length
+        let mut bad = packed.clone();
+        bad[8] = 0xff;
+        assert_eq!(decompress(&bad), Err(TokenError::InvalidToken));
+        let mut bad = packed;
+        bad[9] = 0;
+        assert!(matches!(decompress(&bad), Err(TokenError::Table(_))));
+    }
+
+}
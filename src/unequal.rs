@@ -0,0 +1,293 @@
+// Prefix codes for channels whose letters cost different amounts to send:
+// Morse code's dash takes three times as long as a dot, some storage media
+// write one symbol faster than another, and so on. The goal is the least
+// expected cost per symbol rather than the fewest letters.
+//
+// The exact problem (Karp, 1961) needs an expensive dynamic programme, so
+// this builds two cheap candidates and keeps the better one:
+//
+// - a Varn code: start from the empty word and keep splitting the cheapest
+//   leaf into one child per letter until there are enough leaves, then
+//   give the most frequent symbols the cheapest leaves. Optimal when the
+//   symbols are equally likely.
+// - a Huffman tree over the same number of letters, relabelled so that at
+//   every node heavier subtrees hang off cheaper letters. Optimal when the
+//   letters cost the same, and good when the distribution is skewed.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::huffman::{BuildError, DecodeError, EncodeError, Symbol};
+use crate::nary::NaryHuffman;
+
+struct TrieNode<S> {
+    children: Vec<Option<usize>>,
+    sym: Option<S>,
+}
+
+pub struct UnequalCostCode<S: Symbol = char> {
+    costs: Vec<f64>,
+    freqs: HashMap<S, u64>,
+    code: HashMap<S, Vec<u8>>,
+    // Decoding trie; node 0 is the root
+    nodes: Vec<TrieNode<S>>,
+}
+
+impl<S: Symbol> UnequalCostCode<S> {
+
+    // Panics on an empty map; see try_from_frequencies
+    pub fn from_frequencies(freq: &HashMap<S, u64>, costs: &[f64]) -> Self {
+        UnequalCostCode::try_from_frequencies(freq, costs).expect("can't build a code without symbols")
+    }
+
+    // `costs[i]` is the cost of sending letter i. Panics unless there are
+    // 2 to 256 letters, each with a positive, finite cost.
+    pub fn try_from_frequencies(freq: &HashMap<S, u64>, costs: &[f64]) -> Result<Self, BuildError> {
+        assert!((2..=256).contains(&costs.len()), "need 2 to 256 letters");
+        assert!(costs.iter().all(|&c| c > 0.0 && c.is_finite()), "letter costs must be positive and finite");
+        if freq.is_empty() {
+            return Err(BuildError::EmptyAlphabet);
+        }
+
+        let varn = varn_code(freq, costs);
+        let huffman = relabelled_huffman(freq, costs)?;
+        let code = if total_cost(freq, &huffman, costs) < total_cost(freq, &varn, costs) { huffman } else { varn };
+
+        let mut nodes = vec![TrieNode { children: vec![None; costs.len()], sym: None }];
+        for (sym, codeword) in &code {
+            let mut node = 0;
+            for &letter in codeword {
+                node = match nodes[node].children[letter as usize] {
+                    Some(next) => next,
+                    None => {
+                        nodes.push(TrieNode { children: vec![None; costs.len()], sym: None });
+                        nodes[node].children[letter as usize] = Some(nodes.len() - 1);
+                        nodes.len() - 1
+                    }
+                };
+            }
+            nodes[node].sym = Some(sym.clone());
+        }
+        Ok(UnequalCostCode { costs: costs.to_vec(), freqs: freq.clone(), code, nodes })
+    }
+
+    pub fn from_symbols<I: IntoIterator<Item=S>>(symbols: I, costs: &[f64]) -> Self {
+        UnequalCostCode::try_from_symbols(symbols, costs).expect("can't build a code for empty input")
+    }
+
+    pub fn try_from_symbols<I: IntoIterator<Item=S>>(symbols: I, costs: &[f64]) -> Result<Self, BuildError> {
+        let mut freqs = HashMap::new();
+        for sym in symbols {
+            *freqs.entry(sym).or_insert(0) += 1;
+        }
+        UnequalCostCode::try_from_frequencies(&freqs, costs)
+    }
+
+    pub fn letter_costs(&self) -> &[f64] {
+        &self.costs
+    }
+
+    // The codeword for `sym` as letter indices
+    pub fn codeword(&self, sym: &S) -> Option<&[u8]> {
+        self.code.get(sym).map(|c| c.as_slice())
+    }
+
+    pub fn cost_of(&self, sym: &S) -> Option<f64> {
+        self.code.get(sym).map(|c| word_cost(c, &self.costs))
+    }
+
+    // Expected cost per symbol under the frequencies the code was built from
+    pub fn expected_cost(&self) -> f64 {
+        let total: u64 = self.freqs.values().sum();
+        if total == 0 {
+            return 0.0;
+        }
+        total_cost(&self.freqs, &self.code, &self.costs) / total as f64
+    }
+
+    // Panics on a symbol without a codeword; see try_encode
+    pub fn encode(&self, symbols: &[S]) -> Vec<u8> {
+        self.try_encode(symbols)
+            .unwrap_or_else(|e| panic!("no codeword for the symbol at position {}", e.pos))
+    }
+
+    pub fn try_encode(&self, symbols: &[S]) -> Result<Vec<u8>, EncodeError<S>> {
+        let mut out = Vec::new();
+        for (pos, sym) in symbols.iter().enumerate() {
+            out.extend_from_slice(self.code.get(sym).ok_or_else(|| EncodeError { symbol: sym.clone(), pos })?);
+        }
+        Ok(out)
+    }
+
+    // Positions in errors count letters
+    pub fn decode(&self, letters: &[u8]) -> Result<Vec<S>, DecodeError> {
+        let mut ret = Vec::new();
+        let mut node = 0;
+        let mut start = 0;
+        for (pos, &letter) in letters.iter().enumerate() {
+            node = self.nodes[node].children.get(letter as usize).cloned().flatten()
+                .ok_or(DecodeError::InvalidBitstream { pos: start })?;
+            if let Some(sym) = &self.nodes[node].sym {
+                ret.push(sym.clone());
+                node = 0;
+                start = pos + 1;
+            }
+        }
+        if node != 0 {
+            return Err(DecodeError::TruncatedCodeword { pos: start });
+        }
+        Ok(ret)
+    }
+
+}
+
+fn word_cost(word: &[u8], costs: &[f64]) -> f64 {
+    word.iter().map(|&l| costs[l as usize]).sum()
+}
+
+fn total_cost<S: Symbol>(freq: &HashMap<S, u64>, code: &HashMap<S, Vec<u8>>, costs: &[f64]) -> f64 {
+    freq.iter().map(|(sym, &f)| f as f64 * word_cost(&code[sym], costs)).sum()
+}
+
+// Letter indices from cheapest to dearest, ties by index
+fn letters_by_cost(costs: &[f64]) -> Vec<u8> {
+    let mut letters: Vec<u8> = (0..costs.len()).map(|l| l as u8).collect();
+    letters.sort_by(|&a, &b| costs[a as usize].total_cmp(&costs[b as usize]).then(a.cmp(&b)));
+    letters
+}
+
+// Symbols from most to least frequent, ties in symbol order
+fn by_frequency<S: Symbol>(freq: &HashMap<S, u64>) -> Vec<&S> {
+    let mut symbols: Vec<(&S, &u64)> = freq.iter().collect();
+    symbols.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    symbols.into_iter().map(|(sym, _)| sym).collect()
+}
+
+// Min-heap entry for Varn's construction; ties go to the smaller word so
+// the result doesn't depend on heap internals
+struct Leaf {
+    cost: f64,
+    word: Vec<u8>,
+}
+
+impl PartialEq for Leaf {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Leaf {}
+
+impl PartialOrd for Leaf {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Leaf {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost).then_with(|| other.word.cmp(&self.word))
+    }
+}
+
+fn varn_code<S: Symbol>(freq: &HashMap<S, u64>, costs: &[f64]) -> HashMap<S, Vec<u8>> {
+    let letters = letters_by_cost(costs);
+    let n = freq.len().max(2); // a lone symbol still gets a one-letter word
+    let mut leaves = BinaryHeap::new();
+    leaves.push(Leaf { cost: 0.0, word: Vec::new() });
+    while leaves.len() < n {
+        // splitting one leaf into k adds k - 1; don't overshoot
+        let k = letters.len().min(n - leaves.len() + 1);
+        let parent = leaves.pop().unwrap();
+        for &l in &letters[..k] {
+            let mut word = parent.word.clone();
+            word.push(l);
+            leaves.push(Leaf { cost: parent.cost + costs[l as usize], word });
+        }
+    }
+    let cheapest = std::iter::from_fn(|| leaves.pop());
+    by_frequency(freq).into_iter().cloned().zip(cheapest.map(|leaf| leaf.word)).collect()
+}
+
+fn relabelled_huffman<S: Symbol>(freq: &HashMap<S, u64>, costs: &[f64]) -> Result<HashMap<S, Vec<u8>>, BuildError> {
+    let tree = NaryHuffman::try_from_frequencies(freq, costs.len())?;
+    let letters = letters_by_cost(costs);
+
+    // Weight of every prefix of every codeword, then at each node rank the
+    // children by weight and hand out letters in order of cost
+    let mut weights: HashMap<Vec<u8>, u64> = HashMap::new();
+    for (sym, &f) in freq {
+        let word = tree.codeword(sym).unwrap();
+        for len in 1..=word.len() {
+            *weights.entry(word[..len].to_vec()).or_insert(0) += f;
+        }
+    }
+    let mut relabel: HashMap<Vec<u8>, u8> = HashMap::new(); // full prefix -> new last letter
+    let mut parents: HashMap<&[u8], Vec<(&Vec<u8>, u64)>> = HashMap::new();
+    for (prefix, &w) in &weights {
+        parents.entry(&prefix[..prefix.len() - 1]).or_default().push((prefix, w));
+    }
+    for children in parents.values_mut() {
+        children.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        for (i, (prefix, _)) in children.iter().enumerate() {
+            relabel.insert((*prefix).clone(), letters[i]);
+        }
+    }
+
+    Ok(freq.keys().map(|sym| {
+        let word = tree.codeword(sym).unwrap();
+        (sym.clone(), (1..=word.len()).map(|len| relabel[&word[..len]]).collect())
+    }).collect())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::UnequalCostCode;
+    use crate::huffman::{DecodeError, HuffmanCode};
+    use std::collections::HashMap;
+
+    // dot and dash, counting the gap after each
+    const MORSE: [f64; 2] = [2.0, 4.0];
+
+    #[test]
+    fn test_varn() {
+        // equally likely symbols, letters costing 1 and 2: split the cheapest
+        // leaf until there are four
+        let freqs: HashMap<char, u64> = "abcd".chars().map(|ch| (ch, 1)).collect();
+        let code = UnequalCostCode::from_frequencies(&freqs, &[1.0, 2.0]);
+        assert_eq!(code.expected_cost(), 3.0);
+        let mut costs: Vec<f64> = "abcd".chars().map(|ch| code.cost_of(&ch).unwrap()).collect();
+        costs.sort_by(f64::total_cmp);
+        assert_eq!(costs, vec![2.0, 3.0, 3.0, 4.0]);
+
+        let lone = UnequalCostCode::from_symbols("zzz".chars(), &[5.0, 1.0, 3.0]);
+        assert_eq!(lone.codeword(&'z'), Some(&[1u8][..]));
+    }
+
+    #[test]
+    fn test_costs() {
+        let text = "the quick brown fox jumps over the lazy dog and then some more english text to code";
+        let chars: Vec<char> = text.chars().collect();
+        let code = UnequalCostCode::from_symbols(text.chars(), &MORSE);
+        let letters = code.encode(&chars);
+        assert_eq!(code.decode(&letters).unwrap(), chars);
+        assert_eq!(code.decode(&letters[..letters.len() - 1]).unwrap_err(),
+                   DecodeError::TruncatedCodeword { pos: letters.len() - code.codeword(&'e').unwrap().len() });
+        assert_eq!(code.decode(&[7]), Err(DecodeError::InvalidBitstream { pos: 0 }));
+
+        // cheaper than sending the plain Huffman code's bits as dots and dashes
+        let huffman = HuffmanCode::new(text);
+        let plain: f64 = chars.iter()
+            .map(|ch| huffman.encode_string(&ch.to_string()).chars().map(|b| MORSE[(b == '1') as usize]).sum::<f64>())
+            .sum::<f64>() / chars.len() as f64;
+        assert!(code.expected_cost() < plain);
+
+        // equal costs: as good as Huffman
+        let equal = UnequalCostCode::from_symbols(text.chars(), &[1.0, 1.0]);
+        let average = huffman.code_lengths().map(|(ch, len)| huffman.frequencies()[&ch] * len as u64).sum::<u64>()
+            as f64 / chars.len() as f64;
+        assert!((equal.expected_cost() - average).abs() < 1e-9);
+    }
+
+}
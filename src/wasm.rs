@@ -0,0 +1,88 @@
+// JavaScript bindings, behind the `wasm` feature. Byte arrays cross the
+// boundary as Uint8Array:
+//
+//   import { compress, compressWith, decompress } from "entrust";
+//   const packed = compressWith(bytes, 65536, "xxhash64");
+//   const bytes2 = decompress(packed);
+//
+// Nothing exported here panics. Bad options and corrupt input come back to
+// JavaScript as a thrown Error carrying the message of entrust::Error.
+
+use wasm_bindgen::prelude::*;
+
+use crate::checksum::ChecksumKind;
+use crate::container::{self, Codec, CompressOptions};
+use crate::error::{Error, Result};
+
+// Default options: Huffman, one block, CRC-32
+#[wasm_bindgen]
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    container::compress_to_vec(data, Codec::Huffman)
+}
+
+// `blockSize` as for CompressOptions::block_size (omit for one block);
+// `checksum` is "none", "crc16", "crc32", "crc32c", "adler32" or
+// "xxhash64" (omit for crc32)
+#[wasm_bindgen(js_name = compressWith)]
+pub fn compress_with(data: &[u8], block_size: Option<u32>, checksum: Option<String>)
+    -> std::result::Result<Vec<u8>, JsError> {
+    let options = options(block_size, checksum.as_deref()).map_err(js_error)?;
+    Ok(container::compress_with(data, &options))
+}
+
+#[wasm_bindgen]
+pub fn decompress(bytes: &[u8]) -> std::result::Result<Vec<u8>, JsError> {
+    container::decompress_from_slice(bytes).map_err(|e| js_error(e.into()))
+}
+
+// Length of the original data, read from the header alone
+#[wasm_bindgen(js_name = decompressedLength)]
+pub fn decompressed_length(bytes: &[u8]) -> std::result::Result<f64, JsError> {
+    let header = container::read_header(bytes).map_err(|e| js_error(e.into()))?;
+    Ok(header.len as f64)
+}
+
+// Checked here rather than left to the builder, which panics
+fn options(block_size: Option<u32>, checksum: Option<&str>) -> Result<CompressOptions> {
+    let mut options = CompressOptions::new(Codec::Huffman);
+    match block_size {
+        Some(0) => return Err(Error::InvalidParameter("block size must be positive")),
+        Some(size) => options = options.block_size(size as usize),
+        None => {}
+    }
+    if let Some(name) = checksum {
+        let kind = ChecksumKind::from_name(name).ok_or(Error::InvalidParameter("unknown checksum name"))?;
+        options = options.checksum(kind);
+    }
+    Ok(options)
+}
+
+fn js_error(e: Error) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+
+    // JsError only works on wasm32, so the tests stick to the paths that
+    // don't fail.
+
+    use super::{compress, compress_with, decompress, options};
+    use crate::checksum::ChecksumKind;
+    use crate::error::Error;
+
+    #[test]
+    fn test_bindings() {
+        let data = b"a man a plan a canal panama".repeat(20);
+        assert_eq!(decompress(&compress(&data)).unwrap(), data);
+        let packed = compress_with(&data, Some(100), Some("xxhash64".to_string())).unwrap();
+        assert_eq!(decompress(&packed).unwrap(), data);
+        assert_eq!(super::decompressed_length(&packed).unwrap(), data.len() as f64);
+
+        let opts = options(Some(100), Some("crc32c")).unwrap();
+        assert_eq!((opts.get_block_size(), opts.get_checksum()), (Some(100), ChecksumKind::Crc32c));
+        assert!(matches!(options(Some(0), None), Err(Error::InvalidParameter(_))));
+        assert!(matches!(options(None, Some("md5")), Err(Error::InvalidParameter(_))));
+    }
+
+}
@@ -0,0 +1,159 @@
+// zlib streams (RFC 1950), as in PNG image data and HTTP's `deflate`
+// content-encoding: a two-byte header, a raw DEFLATE stream from the
+// deflate module and the Adler-32 of the data, big-endian.
+//
+// The header is CMF (CM = 8 for deflate in the low nibble, CINFO = log2 of
+// the window size minus 8 in the high one) and FLG, whose low five bits
+// make CMF << 8 | FLG a multiple of 31. Bit 5 of FLG (FDICT) announces a
+// preset dictionary, which isn't supported; the top two (FLEVEL) are only
+// informational. What's written is 78 9c: a 32K window, default level.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::checksum::adler32;
+use crate::deflate::{deflate, inflate_prefix_with, DeflateError};
+use crate::limits::DecodeOptions;
+
+const CM_DEFLATE: u8 = 8;
+// Largest window CINFO allows, 2^(7 + 8) bytes
+const MAX_CINFO: u8 = 7;
+const FDICT: u8 = 0x20;
+const FLEVEL_DEFAULT: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZlibError {
+    Truncated,
+    HeaderCheck,           // CMF << 8 | FLG isn't a multiple of 31
+    UnsupportedMethod(u8), // CM other than deflate
+    InvalidWindow(u8),     // CINFO above 7
+    PresetDictionary,
+    Deflate(DeflateError),
+    ChecksumMismatch,
+    TrailingData,
+}
+
+impl fmt::Display for ZlibError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ZlibError::Truncated => write!(f, "zlib stream is truncated"),
+            ZlibError::HeaderCheck => write!(f, "zlib header check failed"),
+            ZlibError::UnsupportedMethod(cm) => write!(f, "unsupported zlib compression method {}", cm),
+            ZlibError::InvalidWindow(cinfo) => write!(f, "invalid zlib window size {}", cinfo),
+            ZlibError::PresetDictionary => write!(f, "zlib preset dictionaries are not supported"),
+            ZlibError::Deflate(e) => write!(f, "{}", e),
+            ZlibError::ChecksumMismatch => write!(f, "zlib Adler-32 mismatch"),
+            ZlibError::TrailingData => write!(f, "trailing data after zlib stream"),
+        }
+    }
+}
+
+impl Error for ZlibError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ZlibError::Deflate(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DeflateError> for ZlibError {
+    fn from(e: DeflateError) -> Self {
+        match e {
+            DeflateError::Truncated => ZlibError::Truncated,
+            e => ZlibError::Deflate(e),
+        }
+    }
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let cmf = MAX_CINFO << 4 | CM_DEFLATE;
+    let flg = FLEVEL_DEFAULT << 6;
+    let check = (31 - u16::from_be_bytes([cmf, flg]) % 31) % 31;
+    let mut out = vec![cmf, flg | check as u8];
+    out.extend(deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// The whole of `bytes` has to be one zlib stream
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>, ZlibError> {
+    decompress_with(bytes, &DecodeOptions::new())
+}
+
+// decompress, with the output held to `limits` (deflate::inflate_with)
+pub fn decompress_with(bytes: &[u8], limits: &DecodeOptions) -> Result<Vec<u8>, ZlibError> {
+    let (data, used) = decompress_prefix_with(bytes, limits)?;
+    if used != bytes.len() {
+        return Err(ZlibError::TrailingData);
+    }
+    Ok(data)
+}
+
+// Decompress the zlib stream at the start of `bytes`, returning the data and
+// the number of bytes the stream took
+pub fn decompress_prefix(bytes: &[u8]) -> Result<(Vec<u8>, usize), ZlibError> {
+    decompress_prefix_with(bytes, &DecodeOptions::new())
+}
+
+pub fn decompress_prefix_with(bytes: &[u8], limits: &DecodeOptions) -> Result<(Vec<u8>, usize), ZlibError> {
+    if bytes.len() < 2 {
+        return Err(ZlibError::Truncated);
+    }
+    let (cmf, flg) = (bytes[0], bytes[1]);
+    if u16::from_be_bytes([cmf, flg]) % 31 != 0 {
+        return Err(ZlibError::HeaderCheck);
+    }
+    if cmf & 0x0f != CM_DEFLATE {
+        return Err(ZlibError::UnsupportedMethod(cmf & 0x0f));
+    }
+    if cmf >> 4 > MAX_CINFO {
+        return Err(ZlibError::InvalidWindow(cmf >> 4));
+    }
+    if flg & FDICT != 0 {
+        return Err(ZlibError::PresetDictionary);
+    }
+
+    let (data, used) = inflate_prefix_with(&bytes[2..], limits)?;
+    let end = 2 + used + 4;
+    let trailer = bytes.get(2 + used..end).ok_or(ZlibError::Truncated)?;
+    if u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) != adler32(&data) {
+        return Err(ZlibError::ChecksumMismatch);
+    }
+    Ok((data, end))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{compress, decompress, decompress_prefix, ZlibError};
+
+    #[test]
+    fn test_zlib_stream() {
+        // zlib.compress(b"hello, zlib\n") from Python
+        let stream = [0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0xa8, 0xca, 0xc9, 0x4c,
+                      0xe2, 0x02, 0x00, 0x1c, 0xce, 0x04, 0x1c];
+        assert_eq!(decompress(&stream).unwrap(), b"hello, zlib\n");
+
+        let mut bad = stream;
+        bad[stream.len() - 1] ^= 1;
+        assert_eq!(decompress(&bad), Err(ZlibError::ChecksumMismatch));
+        assert_eq!(decompress(&stream[..stream.len() - 2]), Err(ZlibError::Truncated));
+        assert_eq!(decompress(&[0x78, 0x9d]), Err(ZlibError::HeaderCheck));
+        assert_eq!(decompress(&[0x78, 0xbb, 0, 0, 0, 1]), Err(ZlibError::PresetDictionary));
+        assert_eq!(decompress(&[&stream[..], &[0]].concat()), Err(ZlibError::TrailingData));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"PNG image data is a zlib stream; so is HTTP deflate. ".repeat(40);
+        let stream = compress(&data);
+        assert_eq!(&stream[..2], &[0x78, 0x9c]);
+        assert_eq!(decompress(&stream).unwrap(), data);
+        assert_eq!(decompress(&compress(b"")).unwrap(), b"");
+
+        let followed = [&stream[..], b"IEND"].concat();
+        assert_eq!(decompress_prefix(&followed).unwrap(), (data, stream.len()));
+    }
+
+}